@@ -67,7 +67,17 @@ impl SigningAlgorithm {
 /// Hash algorithms exposed in the `DKIM record`,
 /// used to describe the content of the "p=" tag in the record.
 #[allow(clippy::module_name_repetitions)]
-#[derive(Debug, PartialEq, Eq, Copy, Clone, strum::EnumString, strum::Display)]
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    Copy,
+    Clone,
+    strum::EnumString,
+    strum::Display,
+    serde_with::SerializeDisplay,
+    serde_with::DeserializeFromStr,
+)]
 #[strum(serialize_all = "lowercase")]
 pub enum HashAlgorithm {
     /// The SHA-1 hash function should be considered cryptographically broken and unsuitable
@@ -54,6 +54,14 @@ impl TryFrom<&Record> for InnerPublicKey {
 }
 
 impl InnerPublicKey {
+    /// Size of the key in bits, if it is an RSA key.
+    pub(super) fn rsa_key_size(&self) -> Option<usize> {
+        match self {
+            Self::Rsa(rsa) => Some(rsa::traits::PublicKeyParts::size(rsa) * 8),
+            Self::Ed25519(_) => None,
+        }
+    }
+
     pub(super) fn verify(
         &self,
         hashed: &[u8],
@@ -10,7 +10,7 @@
  */
 
 use super::{
-    private_key::PrivateKey, signature::QueryMethod, BackendError, Canonicalization, Mail,
+    private_key::PrivateKey, signature::QueryMethod, BackendError, Canonicalization, Header, Mail,
     Signature, SigningAlgorithm, RSA_MINIMUM_ACCEPTABLE_KEY_SIZE,
 };
 use base64::{engine::general_purpose::STANDARD, Engine};
@@ -94,3 +94,118 @@ pub fn sign(
 
     Ok(signature)
 }
+
+/// Selector and private key signing the messages of a domain.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DomainKey {
+    /// The selector used to retrieve the public key.
+    pub selector: String,
+    /// The private key producing the signature.
+    pub private_key: crate::TlsPrivateKey,
+    /// The headers to sign (`h=` tag of the signature), the default of the signer if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers_field: Option<Vec<String>>,
+    /// The canonicalization of the headers and the body (`c=` tag of the signature),
+    /// the default of the signer if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonicalization: Option<Canonicalization>,
+}
+
+/// Keys signing the messages, chosen by the domain of the `From` header.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SigningKeys {
+    /// The keys of each signing domain. A `From` domain is signed with the key of
+    /// the domain itself, or of its closest parent domain.
+    #[serde(default)]
+    pub domains: std::collections::HashMap<String, DomainKey>,
+    /// A domain of `domains` (usually the organizational domain) adding a second
+    /// signature to all the messages signed with another domain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub double_signing: Option<String>,
+}
+
+impl SigningKeys {
+    /// Get the key of the signing domain `sdid` if its selector is `selector`.
+    #[must_use]
+    pub fn get(&self, sdid: &str, selector: &str) -> Option<&DomainKey> {
+        self.domains
+            .get(&sdid.to_lowercase())
+            .filter(|key| key.selector == selector)
+    }
+
+    /// Get the signing domains and their key for a `From` domain, the first one
+    /// being the key of the `From` domain.
+    #[must_use]
+    pub fn select(&self, from_domain: &str) -> Vec<(&str, &DomainKey)> {
+        let mut domain = from_domain.trim_end_matches('.').to_lowercase();
+        let mut selected = loop {
+            if let Some((sdid, key)) = self.domains.get_key_value(&domain) {
+                break vec![(sdid.as_str(), key)];
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent.to_string(),
+                None => break vec![],
+            }
+        };
+
+        if let Some((sdid, key)) = self
+            .double_signing
+            .as_ref()
+            .and_then(|domain| self.domains.get_key_value(domain))
+        {
+            if selected.iter().all(|(selected, _)| *selected != sdid) {
+                selected.push((sdid.as_str(), key));
+            }
+        }
+
+        selected
+    }
+}
+
+/// Sign the message with each key selected by [`SigningKeys::select`] for `from_domain`,
+/// the domain of its `From` header. No signature is produced if no key matches.
+/// The domains of `signed` (already signing the message) are skipped.
+///
+/// The headers and the canonicalization configured for a key are used, `headers_field`
+/// and `canonicalization` otherwise.
+///
+/// # Errors
+///
+/// * see [`SigningError`]
+pub fn sign_for_from_domain(
+    message: &impl Mail,
+    from_domain: &str,
+    keys: &SigningKeys,
+    signed: &[String],
+    canonicalization: Canonicalization,
+    headers_field: &[String],
+) -> Result<Vec<Signature>, SigningError> {
+    keys.select(from_domain)
+        .into_iter()
+        .filter(|(sdid, _)| {
+            let skipped = signed
+                .iter()
+                .any(|domain| domain.eq_ignore_ascii_case(sdid));
+            if skipped {
+                tracing::debug!(sdid, "The message is already signed for the domain");
+            }
+            !skipped
+        })
+        .map(|(sdid, key)| {
+            sign(
+                message,
+                key.private_key.private_key(),
+                sdid.to_string(),
+                key.selector.clone(),
+                key.canonicalization.unwrap_or(canonicalization),
+                key.headers_field
+                    .clone()
+                    .unwrap_or_else(|| headers_field.to_vec()),
+                #[cfg(test)]
+                None,
+            )
+        })
+        .collect()
+}
@@ -72,7 +72,13 @@ fn verify_file() {
         .collect::<Result<Vec<_>, <PublicKey as std::str::FromStr>::Err>>()
         .unwrap();
 
-    dkim::verify(&signature, &DkimMail { mail: &mail }, keys.first().unwrap()).unwrap();
+    dkim::verify(
+        &signature,
+        &DkimMail { mail: &mail },
+        keys.first().unwrap(),
+        &dkim::VerifyPolicy::default(),
+    )
+    .unwrap();
 }
 
 #[test]
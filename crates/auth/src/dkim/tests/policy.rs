@@ -0,0 +1,152 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::dkim::{
+    signature::QueryMethod, verify, Canonicalization, HashAlgorithm, PublicKey, Signature,
+    SigningAlgorithm, VerifierError, VerifyPolicy,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+struct TestHeader(&'static str, &'static str);
+
+impl crate::dkim::Header for TestHeader {
+    fn field_name(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn get(&self) -> String {
+        format!("{}: {}\r\n", self.0, self.1)
+    }
+}
+
+struct TestMail;
+
+impl crate::dkim::Mail for TestMail {
+    type H = TestHeader;
+
+    fn get_body(&self) -> String {
+        "Hello world!\r\n".to_string()
+    }
+
+    fn get_headers(&self) -> Vec<Self::H> {
+        vec![
+            TestHeader("From", "john.doe@example.com"),
+            TestHeader("Subject", "policy"),
+        ]
+    }
+}
+
+/// Sign the message without the key size check of [`crate::dkim::sign`].
+fn sign_rsa(key: &rsa::RsaPrivateKey, signing_algorithm: SigningAlgorithm) -> Signature {
+    let message = TestMail;
+    let canonicalization = "relaxed/relaxed".parse::<Canonicalization>().unwrap();
+
+    let mut signature = Signature {
+        version: 1,
+        signing_algorithm,
+        sdid: "example.com".to_string(),
+        selector: "policy".to_string(),
+        canonicalization,
+        query_method: vec![QueryMethod::default()],
+        auid: String::default(),
+        signature_timestamp: None,
+        expire_time: None,
+        body_length: None,
+        headers_field: vec!["From".to_string(), "Subject".to_string()],
+        copy_header_fields: None,
+        body_hash: STANDARD.encode(
+            signing_algorithm
+                .get_preferred_hash_algo()
+                .hash(canonicalization.canonicalize_body(&crate::dkim::Mail::get_body(&message))),
+        ),
+        signature: String::default(),
+        raw: String::default(),
+    };
+    signature.raw = signature.to_string();
+
+    let padding = match signing_algorithm {
+        #[cfg(feature = "historic")]
+        SigningAlgorithm::RsaSha1 => rsa::Pkcs1v15Sign::new::<sha1::Sha1>(),
+        SigningAlgorithm::RsaSha256 => rsa::Pkcs1v15Sign::new::<sha2::Sha256>(),
+        SigningAlgorithm::Ed25519Sha256 => unimplemented!(),
+    };
+    signature.signature = STANDARD.encode(
+        key.sign(padding, &signature.get_header_hash(&message))
+            .unwrap(),
+    );
+    signature.raw.push_str(&signature.signature);
+
+    signature
+}
+
+fn public_key(key: &rsa::RsaPrivateKey, hash_algorithms: &str) -> PublicKey {
+    let der = rsa::pkcs8::EncodePublicKey::to_public_key_der(&key.to_public_key()).unwrap();
+    format!(
+        "v=DKIM1; h={hash_algorithms}; k=rsa; p={}",
+        STANDARD.encode(der.as_bytes())
+    )
+    .parse()
+    .unwrap()
+}
+
+fn lenient() -> VerifyPolicy {
+    VerifyPolicy {
+        min_rsa_key_size: 512,
+        allowed_hash_algorithms: vec![
+            #[cfg(feature = "historic")]
+            HashAlgorithm::Sha1,
+            HashAlgorithm::Sha256,
+        ],
+    }
+}
+
+#[test]
+fn default_policy() {
+    assert_eq!(
+        VerifyPolicy::default(),
+        VerifyPolicy {
+            min_rsa_key_size: 1024,
+            allowed_hash_algorithms: vec![HashAlgorithm::Sha256],
+        }
+    );
+}
+
+#[test]
+fn rsa_512_bits() {
+    let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 512).unwrap();
+    let signature = sign_rsa(&key, SigningAlgorithm::RsaSha256);
+    let public_key = public_key(&key, "sha256");
+
+    assert!(matches!(
+        verify(&signature, &TestMail, &public_key, &VerifyPolicy::default()),
+        Err(VerifierError::KeyTooSmall {
+            size: 512,
+            minimum: 1024
+        })
+    ));
+    verify(&signature, &TestMail, &public_key, &lenient()).unwrap();
+}
+
+#[test]
+#[cfg(feature = "historic")]
+fn rsa_sha1() {
+    let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let signature = sign_rsa(&key, SigningAlgorithm::RsaSha1);
+    let public_key = public_key(&key, "sha1:sha256");
+
+    assert!(matches!(
+        verify(&signature, &TestMail, &public_key, &VerifyPolicy::default()),
+        Err(VerifierError::HashAlgorithmNotAllowed {
+            hash_algorithm: HashAlgorithm::Sha1
+        })
+    ));
+    verify(&signature, &TestMail, &public_key, &lenient()).unwrap();
+}
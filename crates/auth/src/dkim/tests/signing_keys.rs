@@ -0,0 +1,310 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::dkim::{
+    get_from_domain, sign_for_from_domain, verify, DomainKey, PublicKey, SigningKeys, VerifyPolicy,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+struct TestHeader(&'static str, String);
+
+impl crate::dkim::Header for TestHeader {
+    fn field_name(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn get(&self) -> String {
+        format!("{}: {}\r\n", self.0, self.1)
+    }
+}
+
+struct TestMail {
+    from: String,
+}
+
+impl crate::dkim::Mail for TestMail {
+    type H = TestHeader;
+
+    fn get_body(&self) -> String {
+        "Hello world!\r\n".to_string()
+    }
+
+    fn get_headers(&self) -> Vec<Self::H> {
+        vec![
+            TestHeader("From", self.from.clone()),
+            TestHeader("Subject", "signing keys".to_string()),
+        ]
+    }
+}
+
+fn key_pair(selector: &str) -> (DomainKey, PublicKey) {
+    let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+
+    let der = rsa::pkcs8::EncodePublicKey::to_public_key_der(&key.to_public_key()).unwrap();
+    let public_key = format!("v=DKIM1; k=rsa; p={}", STANDARD.encode(der.as_bytes()))
+        .parse()
+        .unwrap();
+
+    let pem = rsa::pkcs8::EncodePrivateKey::to_pkcs8_pem(&key, rsa::pkcs8::LineEnding::LF)
+        .unwrap()
+        .to_string();
+
+    (
+        DomainKey {
+            selector: selector.to_string(),
+            private_key: pem.parse().unwrap(),
+            headers_field: None,
+            canonicalization: None,
+        },
+        public_key,
+    )
+}
+
+/// The domain of the `From` address, as parsed by the caller.
+fn from_domain(message: &TestMail) -> &str {
+    message
+        .from
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim_end_matches('>'))
+        .unwrap()
+}
+
+fn headers_field() -> Vec<String> {
+    vec!["From".to_string(), "Subject".to_string()]
+}
+
+#[test]
+fn select_by_from_domain() {
+    let (brand_a, brand_a_public) = key_pair("brand-a");
+    let (brand_b, brand_b_public) = key_pair("brand-b");
+    let keys = SigningKeys {
+        domains: [
+            ("brand-a.com".to_string(), brand_a),
+            ("brand-b.com".to_string(), brand_b),
+        ]
+        .into_iter()
+        .collect(),
+        double_signing: None,
+    };
+
+    for (from, sdid, selector, public_key) in [
+        (
+            "john.doe@brand-a.com",
+            "brand-a.com",
+            "brand-a",
+            &brand_a_public,
+        ),
+        (
+            "Jane Doe <jane.doe@news.brand-b.com>",
+            "brand-b.com",
+            "brand-b",
+            &brand_b_public,
+        ),
+    ] {
+        let message = TestMail {
+            from: from.to_string(),
+        };
+        let signatures = sign_for_from_domain(
+            &message,
+            from_domain(&message),
+            &keys,
+            &[],
+            "relaxed/relaxed".parse().unwrap(),
+            &headers_field(),
+        )
+        .unwrap();
+
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(signatures[0].sdid, sdid);
+        assert_eq!(signatures[0].selector, selector);
+        verify(
+            &signatures[0],
+            &message,
+            public_key,
+            &VerifyPolicy::default(),
+        )
+        .unwrap();
+    }
+
+    let unknown = TestMail {
+        from: "john.doe@example.com".to_string(),
+    };
+    assert!(sign_for_from_domain(
+        &unknown,
+        from_domain(&unknown),
+        &keys,
+        &[],
+        "relaxed/relaxed".parse().unwrap(),
+        &headers_field()
+    )
+    .unwrap()
+    .is_empty());
+}
+
+#[test]
+fn double_signing() {
+    let (brand_a, brand_a_public) = key_pair("brand-a");
+    let (organization, organization_public) = key_pair("org");
+    let keys = SigningKeys {
+        domains: [
+            ("brand-a.com".to_string(), brand_a),
+            ("example.com".to_string(), organization),
+        ]
+        .into_iter()
+        .collect(),
+        double_signing: Some("example.com".to_string()),
+    };
+
+    let message = TestMail {
+        from: "john.doe@brand-a.com".to_string(),
+    };
+    let signatures = sign_for_from_domain(
+        &message,
+        from_domain(&message),
+        &keys,
+        &[],
+        "relaxed/relaxed".parse().unwrap(),
+        &headers_field(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        signatures
+            .iter()
+            .map(|i| (i.sdid.as_str(), i.selector.as_str()))
+            .collect::<Vec<_>>(),
+        [("brand-a.com", "brand-a"), ("example.com", "org")]
+    );
+    verify(
+        &signatures[0],
+        &message,
+        &brand_a_public,
+        &VerifyPolicy::default(),
+    )
+    .unwrap();
+    verify(
+        &signatures[1],
+        &message,
+        &organization_public,
+        &VerifyPolicy::default(),
+    )
+    .unwrap();
+
+    // the organizational domain signs only once its own messages
+    let message = TestMail {
+        from: "john.doe@example.com".to_string(),
+    };
+    assert_eq!(
+        keys.select("example.com")
+            .into_iter()
+            .map(|(sdid, _)| sdid)
+            .collect::<Vec<_>>(),
+        ["example.com"]
+    );
+    assert_eq!(
+        sign_for_from_domain(
+            &message,
+            from_domain(&message),
+            &keys,
+            &[],
+            "relaxed/relaxed".parse().unwrap(),
+            &headers_field()
+        )
+        .unwrap()
+        .len(),
+        1
+    );
+}
+
+#[test]
+fn settings_of_the_key() {
+    let (brand_a, brand_a_public) = key_pair("brand-a");
+    let (organization, organization_public) = key_pair("org");
+    let keys = SigningKeys {
+        domains: [
+            (
+                "brand-a.com".to_string(),
+                DomainKey {
+                    headers_field: Some(vec!["From".to_string()]),
+                    canonicalization: Some("simple/simple".parse().unwrap()),
+                    ..brand_a
+                },
+            ),
+            ("example.com".to_string(), organization),
+        ]
+        .into_iter()
+        .collect(),
+        double_signing: Some("example.com".to_string()),
+    };
+
+    let message = TestMail {
+        from: "john.doe@brand-a.com".to_string(),
+    };
+    let signatures = sign_for_from_domain(
+        &message,
+        from_domain(&message),
+        &keys,
+        &[],
+        "relaxed/relaxed".parse().unwrap(),
+        &headers_field(),
+    )
+    .unwrap();
+
+    assert_eq!(signatures[0].headers_field, ["From"]);
+    assert_eq!(signatures[0].canonicalization.to_string(), "simple/simple");
+    assert_eq!(signatures[1].headers_field, headers_field());
+    assert_eq!(
+        signatures[1].canonicalization.to_string(),
+        "relaxed/relaxed"
+    );
+    for (signature, public_key) in signatures
+        .iter()
+        .zip([&brand_a_public, &organization_public])
+    {
+        verify(signature, &message, public_key, &VerifyPolicy::default()).unwrap();
+    }
+}
+
+#[test]
+fn already_signed() {
+    let (brand_a, _) = key_pair("brand-a");
+    let (organization, _) = key_pair("org");
+    let keys = SigningKeys {
+        domains: [
+            ("brand-a.com".to_string(), brand_a),
+            ("example.com".to_string(), organization),
+        ]
+        .into_iter()
+        .collect(),
+        double_signing: Some("example.com".to_string()),
+    };
+
+    let message = TestMail {
+        from: "john.doe@brand-a.com".to_string(),
+    };
+    let signatures = sign_for_from_domain(
+        &message,
+        from_domain(&message),
+        &keys,
+        &["Brand-A.com".to_string()],
+        "relaxed/relaxed".parse().unwrap(),
+        &headers_field(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        signatures
+            .iter()
+            .map(|i| i.sdid.as_str())
+            .collect::<Vec<_>>(),
+        ["example.com"]
+    );
+}
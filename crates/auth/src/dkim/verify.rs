@@ -9,9 +9,63 @@
  *
  */
 
-use super::{BackendError, HashAlgorithm, Mail, PublicKey, Signature, SigningAlgorithm};
+use super::{
+    BackendError, HashAlgorithm, Mail, PublicKey, Signature, SigningAlgorithm,
+    RSA_MINIMUM_ACCEPTABLE_KEY_SIZE,
+};
 use base64::{engine::general_purpose::STANDARD, Engine};
 
+/// Requirements on the keys and algorithms of the signatures accepted by [`verify`].
+///
+/// The default policy follows the RFC 8301: RSA keys of at least 1024 bits, and no `sha1`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerifyPolicy {
+    /// Minimum size of the RSA public keys, in bits.
+    #[serde(default = "VerifyPolicy::default_min_rsa_key_size")]
+    pub min_rsa_key_size: usize,
+    /// Hash algorithms accepted in the signatures.
+    #[serde(default = "VerifyPolicy::default_allowed_hash_algorithms")]
+    pub allowed_hash_algorithms: Vec<HashAlgorithm>,
+}
+
+impl Default for VerifyPolicy {
+    fn default() -> Self {
+        Self {
+            min_rsa_key_size: Self::default_min_rsa_key_size(),
+            allowed_hash_algorithms: Self::default_allowed_hash_algorithms(),
+        }
+    }
+}
+
+impl VerifyPolicy {
+    const fn default_min_rsa_key_size() -> usize {
+        RSA_MINIMUM_ACCEPTABLE_KEY_SIZE
+    }
+
+    fn default_allowed_hash_algorithms() -> Vec<HashAlgorithm> {
+        vec![HashAlgorithm::Sha256]
+    }
+
+    fn check(&self, signature: &Signature, public_key: &PublicKey) -> Result<(), VerifierError> {
+        let hash_algorithm = *signature.signing_algorithm.get_preferred_hash_algo();
+        if !self.allowed_hash_algorithms.contains(&hash_algorithm) {
+            return Err(VerifierError::HashAlgorithmNotAllowed { hash_algorithm });
+        }
+
+        if let Some(size) = public_key.inner.rsa_key_size() {
+            if size < self.min_rsa_key_size {
+                return Err(VerifierError::KeyTooSmall {
+                    size,
+                    minimum: self.min_rsa_key_size,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
 /// Errors that can occur when verifying a DKIM signature
 #[must_use]
 #[derive(Debug, thiserror::Error)]
@@ -57,6 +111,20 @@ pub enum VerifierError {
         /// The hash expected
         expected: String,
     },
+    /// The hash algorithm of the signature is not allowed by the [`VerifyPolicy`]
+    #[error("the hash algorithm `{hash_algorithm}` is not allowed by the policy")]
+    HashAlgorithmNotAllowed {
+        /// The hash algorithm of the signature
+        hash_algorithm: HashAlgorithm,
+    },
+    /// The public key is smaller than the minimum of the [`VerifyPolicy`]
+    #[error("the public key size ({size} bits) is below the policy minimum ({minimum} bits)")]
+    KeyTooSmall {
+        /// The size of the public key, in bits
+        size: usize,
+        /// The minimum size required by the policy, in bits
+        minimum: usize,
+    },
     /// A base64 error occurred
     #[error("base64 error: {0}")]
     Base64Error(#[from] base64::DecodeError),
@@ -65,7 +133,7 @@ pub enum VerifierError {
     BackendError(#[from] BackendError),
 }
 
-/// Verify **ONE** DKIM signature.
+/// Verify **ONE** DKIM signature, rejecting the keys and algorithms not accepted by the `policy`.
 ///
 /// # Errors
 ///
@@ -74,6 +142,7 @@ pub fn verify(
     signature: &Signature,
     message: &impl Mail,
     public_key: &PublicKey,
+    policy: &VerifyPolicy,
 ) -> Result<(), VerifierError> {
     if !signature
         .signing_algorithm
@@ -85,6 +154,8 @@ pub fn verify(
         });
     }
 
+    policy.check(signature, public_key)?;
+
     let body = signature
         .canonicalization
         .canonicalize_body(&message.get_body());
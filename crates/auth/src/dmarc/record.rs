@@ -69,6 +69,9 @@ enum FailureReportOption {
 #[derive(
     Debug,
     Clone,
+    Copy,
+    PartialEq,
+    Eq,
     strum::EnumString,
     strum::Display,
     serde_with::SerializeDisplay,
@@ -122,6 +125,12 @@ pub struct Record {
 }
 
 impl Record {
+    /// Percentage of the failing messages to which the policy is applied (`pct` tag).
+    #[must_use]
+    pub const fn percentage(&self) -> u8 {
+        self.percentage
+    }
+
     ///
     #[must_use]
     pub fn dkim_is_aligned(&self, rfc5322_from: &str, dkim_domain: &str) -> bool {
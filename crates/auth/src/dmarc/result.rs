@@ -9,7 +9,7 @@
  *
  */
 
-use super::Record;
+use super::{ReceiverPolicy, Record};
 use vsmtp_protocol::Domain;
 
 #[derive(
@@ -47,4 +47,89 @@ pub struct Result {
     pub rfc5322_from_domain: Domain,
     // NOTE: wrapped in an Option if the query failed
     pub record: Option<Record>,
+    /// Disposition applied to the message, once the policy has been evaluated.
+    #[serde(default)]
+    pub disposition: Option<ReceiverPolicy>,
+}
+
+impl Result {
+    /// Get the policy published by the domain owner, which is the subdomain policy (`sp` tag)
+    /// if the RFC5322.From domain is a subdomain of the domain of the record.
+    #[must_use]
+    pub fn policy(&self) -> ReceiverPolicy {
+        let is_subdomain = self.domain != self.rfc5322_from_domain
+            && self.domain.zone_of(&self.rfc5322_from_domain);
+
+        self.record.as_ref().map_or(ReceiverPolicy::None, |record| {
+            record
+                .receiver_policy_subdomain
+                .filter(|_| is_subdomain)
+                .unwrap_or(record.receiver_policy)
+        })
+    }
+
+    /// Get the disposition of the message, see <https://www.rfc-editor.org/rfc/rfc7489#section-6.6.4>.
+    ///
+    /// The policy is only applied to the messages failing the DMARC check. `sample` is a number
+    /// in the `0..100` range drawn for the message: if it is not below the percentage (`pct` tag)
+    /// of the record, the next lower policy is applied instead.
+    #[must_use]
+    pub fn disposition(&self, sample: u8) -> ReceiverPolicy {
+        let Some(record) = self.record.as_ref().filter(|_| self.value == Value::Fail) else {
+            return ReceiverPolicy::None;
+        };
+
+        match self.policy() {
+            policy if sample < record.percentage() => policy,
+            ReceiverPolicy::Reject => ReceiverPolicy::Quarantine,
+            ReceiverPolicy::Quarantine | ReceiverPolicy::None => ReceiverPolicy::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReceiverPolicy, Result, Value};
+
+    fn result(value: Value, record: &str) -> Result {
+        Result {
+            value,
+            domain: "example.com".parse().unwrap(),
+            rfc5322_from_domain: "example.com".parse().unwrap(),
+            record: Some(record.parse().unwrap()),
+            disposition: None,
+        }
+    }
+
+    #[test]
+    fn pass() {
+        let result = result(Value::Pass, "v=DMARC1; p=reject");
+        assert_eq!(result.disposition(0), ReceiverPolicy::None);
+    }
+
+    #[test]
+    fn fail_with_reject() {
+        let result = result(Value::Fail, "v=DMARC1; p=reject");
+        assert_eq!(result.disposition(0), ReceiverPolicy::Reject);
+        assert_eq!(result.disposition(99), ReceiverPolicy::Reject);
+    }
+
+    #[test]
+    fn fail_with_quarantine() {
+        let result = result(Value::Fail, "v=DMARC1; p=quarantine");
+        assert_eq!(result.disposition(0), ReceiverPolicy::Quarantine);
+        assert_eq!(result.disposition(99), ReceiverPolicy::Quarantine);
+    }
+
+    #[test]
+    fn percentage_sampling() {
+        let reject = result(Value::Fail, "v=DMARC1; p=reject; pct=20");
+        assert_eq!(reject.disposition(0), ReceiverPolicy::Reject);
+        assert_eq!(reject.disposition(19), ReceiverPolicy::Reject);
+        assert_eq!(reject.disposition(20), ReceiverPolicy::Quarantine);
+        assert_eq!(reject.disposition(99), ReceiverPolicy::Quarantine);
+
+        let quarantine = result(Value::Fail, "v=DMARC1; p=quarantine; pct=0");
+        assert_eq!(quarantine.disposition(0), ReceiverPolicy::None);
+    }
 }
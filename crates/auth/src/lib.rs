@@ -77,6 +77,8 @@ pub mod dkim {
     #[cfg(test)]
     mod tests {
         mod hash_header;
+        mod policy;
+        mod signing_keys;
         // mod sign_verify;
         mod parse {
             mod public_key;
@@ -93,9 +95,9 @@ pub mod dkim {
     pub use private_key::PrivateKey;
     pub use public_key::PublicKey;
     pub use result::{DkimVerificationResult, Value};
-    pub use sign::{sign, SigningError};
+    pub use sign::{sign, sign_for_from_domain, DomainKey, SigningError, SigningKeys};
     pub use signature::Signature;
-    pub use verify::{verify, VerifierError};
+    pub use verify::{verify, VerifierError, VerifyPolicy};
 
     /// Errors that can occur when verifying or signing a DKIM signature
     #[derive(Debug, thiserror::Error)]
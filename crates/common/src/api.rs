@@ -9,188 +9,238 @@
  *
  */
 
-use crate::broker::{Exchange, Queue};
+use crate::broker::{Acker, BackendError, Consumer, Exchange, Message, Queue, QueueBackend};
 use lapin::protocol::{AMQPErrorKind, AMQPSoftError};
 
-// TODO: Can be merged into a broker crate, with the rest of lapin abstraction.
-// NOTE: must put those in a trait, to have a small abstraction layer
-pub async fn write_to_working(channel: &lapin::Channel, payload: Vec<u8>) {
-    let confirm = channel
-        .basic_publish(
-            "",
-            Queue::ToWorking.as_ref(),
-            lapin::options::BasicPublishOptions {
-                mandatory: true,
-                ..Default::default()
-            },
-            &payload,
-            lapin::BasicProperties::default()
-                .with_content_type(lapin::types::ShortString::from("application/json")),
-        )
-        .await
-        .unwrap();
-
-    assert_eq!(
-        confirm.await.unwrap(),
-        lapin::publisher_confirm::Confirmation::Ack(None)
-    );
-}
+#[async_trait::async_trait]
+impl QueueBackend for lapin::Channel {
+    async fn write_to_working(&self, payload: Vec<u8>) {
+        let confirm = self
+            .basic_publish(
+                "",
+                Queue::ToWorking.as_ref(),
+                lapin::options::BasicPublishOptions {
+                    mandatory: true,
+                    ..Default::default()
+                },
+                &payload,
+                lapin::BasicProperties::default()
+                    .with_content_type(lapin::types::ShortString::from("application/json")),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            confirm.await.unwrap(),
+            lapin::publisher_confirm::Confirmation::Ack(None)
+        );
+    }
 
-pub async fn write_to_quarantine(channel: &lapin::Channel, quarantine: &str, payload: Vec<u8>) {
-    let quarantine_name = format!("rule.{quarantine}");
+    async fn write_to_delivery(&self, routing_key: &str, payload: Vec<u8>) -> bool {
+        let confirm = self
+            .basic_publish(
+                Exchange::Delivery.as_ref(),
+                routing_key,
+                lapin::options::BasicPublishOptions {
+                    mandatory: true,
+                    ..Default::default()
+                },
+                &payload,
+                lapin::BasicProperties::default()
+                    .with_content_type(lapin::types::ShortString::from("application/json")),
+            )
+            .await
+            .unwrap();
+
+        match confirm.await.unwrap() {
+            lapin::publisher_confirm::Confirmation::Ack(None) => true,
+            lapin::publisher_confirm::Confirmation::Ack(Some(message)) => {
+                if let Some(error) = message.error() {
+                    match error.kind() {
+                        AMQPErrorKind::Soft(AMQPSoftError::NOROUTE) => false,
+                        AMQPErrorKind::Soft(e) => todo!("error not handled {e:?}"),
+                        AMQPErrorKind::Hard(e) => todo!("error not handled {e:?}"),
+                    }
+                } else {
+                    todo!("message was returned, but no error was provided");
+                }
+            }
+            otherwise => todo!("{otherwise:?}"),
+        }
+    }
 
-    let confirm = channel
-        .basic_publish(
-            Exchange::Quarantine.as_ref(),
-            &quarantine_name,
-            lapin::options::BasicPublishOptions {
-                mandatory: true,
-                ..Default::default()
-            },
-            &payload,
-            lapin::BasicProperties::default()
-                .with_content_type(lapin::types::ShortString::from("application/json")),
-        )
-        .await
-        .unwrap();
-
-    assert_eq!(
-        confirm.await.unwrap(),
-        lapin::publisher_confirm::Confirmation::Ack(None)
-    );
-}
+    async fn write_to_deferred(
+        &self,
+        routing_key: &str,
+        delay: std::time::Duration,
+        payload: Vec<u8>,
+    ) {
+        let properties = lapin::BasicProperties::default()
+            .with_content_type(lapin::types::ShortString::from("application/json"))
+            .with_headers(
+                std::iter::once((
+                    "x-delay".into(),
+                    lapin::types::LongString::from(delay.as_millis().to_string()).into(),
+                ))
+                .collect::<std::collections::BTreeMap<lapin::types::ShortString, lapin::types::AMQPValue>>()
+                .into(),
+            );
+
+        let confirm = self
+            .basic_publish(
+                Exchange::DelayedDeferred.as_ref(),
+                routing_key,
+                lapin::options::BasicPublishOptions::default(),
+                &payload,
+                properties,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            confirm.await.unwrap(),
+            lapin::publisher_confirm::Confirmation::Ack(None)
+        );
+    }
 
-pub async fn write_to_deferred(
-    channel: &lapin::Channel,
-    routing_key: &str,
-    delay: std::time::Duration,
-    payload: Vec<u8>,
-) {
-    let properties = lapin::BasicProperties::default()
-        .with_content_type(lapin::types::ShortString::from("application/json"))
-        .with_headers(
-            std::iter::once((
-                "x-delay".into(),
-                lapin::types::LongString::from(delay.as_millis().to_string()).into(),
-            ))
-            .collect::<std::collections::BTreeMap<lapin::types::ShortString, lapin::types::AMQPValue>>()
-            .into(),
+    async fn write_to_report_dsn(&self, payload: Vec<u8>) {
+        let confirm = self
+            .basic_publish(
+                "",
+                Queue::DSN.as_ref(),
+                lapin::options::BasicPublishOptions {
+                    mandatory: true,
+                    ..Default::default()
+                },
+                &payload,
+                lapin::BasicProperties::default()
+                    .with_content_type(lapin::types::ShortString::from("application/json")),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            confirm.await.unwrap(),
+            lapin::publisher_confirm::Confirmation::Ack(None)
         );
+    }
 
-    let confirm = channel
-        .basic_publish(
-            Exchange::DelayedDeferred.as_ref(),
-            routing_key,
-            lapin::options::BasicPublishOptions::default(),
-            &payload,
-            properties,
-        )
-        .await
-        .unwrap();
-
-    assert_eq!(
-        confirm.await.unwrap(),
-        lapin::publisher_confirm::Confirmation::Ack(None)
-    );
-}
+    async fn write_to_quarantine(&self, quarantine: &str, payload: Vec<u8>) {
+        let quarantine_name = format!("rule.{quarantine}");
+
+        let confirm = self
+            .basic_publish(
+                Exchange::Quarantine.as_ref(),
+                &quarantine_name,
+                lapin::options::BasicPublishOptions {
+                    mandatory: true,
+                    ..Default::default()
+                },
+                &payload,
+                lapin::BasicProperties::default()
+                    .with_content_type(lapin::types::ShortString::from("application/json")),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            confirm.await.unwrap(),
+            lapin::publisher_confirm::Confirmation::Ack(None)
+        );
+    }
 
-pub async fn write_to_report_dsn(channel: &lapin::Channel, payload: Vec<u8>) {
-    let confirm = channel
-        .basic_publish(
-            "",
-            Queue::DSN.as_ref(),
-            lapin::options::BasicPublishOptions {
-                mandatory: true,
-                ..Default::default()
-            },
-            &payload,
-            lapin::BasicProperties::default()
-                .with_content_type(lapin::types::ShortString::from("application/json")),
-        )
-        .await
-        .unwrap();
-
-    assert_eq!(
-        confirm.await.unwrap(),
-        lapin::publisher_confirm::Confirmation::Ack(None)
-    );
-}
+    async fn write_to_dead(&self, payload: Vec<u8>) {
+        let confirm = self
+            .basic_publish(
+                Exchange::Quarantine.as_ref(),
+                "dead",
+                lapin::options::BasicPublishOptions {
+                    mandatory: true,
+                    ..Default::default()
+                },
+                &payload,
+                lapin::BasicProperties::default()
+                    .with_content_type(lapin::types::ShortString::from("application/json")),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            confirm.await.unwrap(),
+            lapin::publisher_confirm::Confirmation::Ack(None)
+        );
+    }
 
-pub async fn write_to_dead(channel: &lapin::Channel, payload: Vec<u8>) {
-    let confirm = channel
-        .basic_publish(
-            Exchange::Quarantine.as_ref(),
-            "dead",
-            lapin::options::BasicPublishOptions {
-                mandatory: true,
-                ..Default::default()
-            },
-            &payload,
-            lapin::BasicProperties::default()
-                .with_content_type(lapin::types::ShortString::from("application/json")),
-        )
-        .await
-        .unwrap();
-
-    assert_eq!(
-        confirm.await.unwrap(),
-        lapin::publisher_confirm::Confirmation::Ack(None)
-    );
-}
+    async fn write_to_no_route(&self, payload: Vec<u8>) {
+        let confirm = self
+            .basic_publish(
+                Exchange::Quarantine.as_ref(),
+                Queue::NoRoute.as_ref(),
+                lapin::options::BasicPublishOptions {
+                    mandatory: true,
+                    ..Default::default()
+                },
+                &payload,
+                lapin::BasicProperties::default()
+                    .with_content_type(lapin::types::ShortString::from("application/json")),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            confirm.await.unwrap(),
+            lapin::publisher_confirm::Confirmation::Ack(None)
+        );
+    }
+
+    async fn set_prefetch(&self, prefetch_count: u16) -> Result<(), BackendError> {
+        Ok(self
+            .basic_qos(prefetch_count, lapin::options::BasicQosOptions::default())
+            .await?)
+    }
 
-pub async fn write_to_no_route(channel: &lapin::Channel, payload: Vec<u8>) {
-    let confirm = channel
-        .basic_publish(
-            Exchange::Quarantine.as_ref(),
-            Queue::NoRoute.as_ref(),
-            lapin::options::BasicPublishOptions {
-                mandatory: true,
-                ..Default::default()
+    async fn consume(&self, queue: &str) -> Result<Consumer, BackendError> {
+        let consumer = self
+            .basic_consume(
+                queue,
+                "",
+                lapin::options::BasicConsumeOptions::default(),
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
+
+        Ok(Box::pin(tokio_stream::StreamExt::map(
+            consumer,
+            |delivery| {
+                delivery
+                    .map(|delivery| {
+                        Message::new(delivery.data, delivery.acker)
+                            .with_redelivered(delivery.redelivered)
+                    })
+                    .map_err(Into::into)
             },
-            &payload,
-            lapin::BasicProperties::default()
-                .with_content_type(lapin::types::ShortString::from("application/json")),
-        )
-        .await
-        .unwrap();
-
-    assert_eq!(
-        confirm.await.unwrap(),
-        lapin::publisher_confirm::Confirmation::Ack(None)
-    );
+        )))
+    }
+
+    async fn queue_depth(&self, queue: &str) -> Result<u32, BackendError> {
+        let queue = self
+            .queue_declare(
+                queue,
+                lapin::options::QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
+
+        Ok(queue.message_count())
+    }
 }
 
-pub async fn write_to_delivery(channel: &lapin::Channel, routing_key: &str, payload: Vec<u8>) {
-    let confirm = channel
-        .basic_publish(
-            Exchange::Delivery.as_ref(),
-            routing_key,
-            lapin::options::BasicPublishOptions {
-                mandatory: true,
-                ..Default::default()
-            },
-            &payload,
-            lapin::BasicProperties::default()
-                .with_content_type(lapin::types::ShortString::from("application/json")),
-        )
-        .await
-        .unwrap();
-
-    match confirm.await.unwrap() {
-        lapin::publisher_confirm::Confirmation::Ack(None) => {}
-        lapin::publisher_confirm::Confirmation::Ack(Some(message)) => {
-            if let Some(error) = message.error() {
-                match error.kind() {
-                    AMQPErrorKind::Soft(AMQPSoftError::NOROUTE) => {
-                        write_to_no_route(channel, payload).await;
-                    }
-                    AMQPErrorKind::Soft(e) => todo!("error not handled {e:?}"),
-                    AMQPErrorKind::Hard(e) => todo!("error not handled {e:?}"),
-                }
-            } else {
-                todo!("message was returned, but no error was provided");
-            }
-        }
-        otherwise => todo!("{otherwise:?}"),
+#[async_trait::async_trait]
+impl Acker for lapin::acker::Acker {
+    async fn ack(&self) -> Result<(), BackendError> {
+        Ok(lapin::acker::Acker::ack(self, lapin::options::BasicAckOptions::default()).await?)
     }
 }
@@ -9,6 +9,9 @@
  *
  */
 
+#[cfg(any(test, feature = "testing"))]
+pub mod in_memory;
+
 /// The "delivery" and "deferred" queues exists, but are not **unique**
 /// they are created on demand, and are named after the systems route.
 #[derive(strum::AsRefStr)]
@@ -27,4 +30,210 @@ pub enum Exchange {
     Delivery,
     DelayedDeferred,
     Quarantine,
+    /// Fanout of the [`Backlog`] published by the delivery services.
+    Backlog,
+}
+
+/// Number of messages deferred by the delivery service bound to `routing_key`,
+/// published regularly to the [`Exchange::Backlog`] exchange.
+///
+/// The deferred messages are held by the [`Exchange::DelayedDeferred`] exchange until
+/// their delay is elapsed, they are not counted in the depth of any queue.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Backlog {
+    pub routing_key: String,
+    pub deferred: u32,
+}
+
+/// Delivery class of a message. The high priority messages are published to their own
+/// `delivery-{routing_key}.priority` queue, so they are not held behind a backlog of bulk mail.
+/// The delayed ones are routed to the same queue once their delay has expired.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum Priority {
+    /// Transactional mail, `urgent` being the RFC 2156 `Priority` header value.
+    #[strum(serialize = "high", serialize = "urgent")]
+    High,
+    #[default]
+    Normal,
+}
+
+impl Priority {
+    /// The routing key of the deliveries of this class for the route `routing_key`.
+    #[must_use]
+    pub fn routing_key(self, routing_key: &str) -> String {
+        match self {
+            Self::High => format!("{routing_key}.priority"),
+            Self::Normal => routing_key.to_string(),
+        }
+    }
+}
+
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Acknowledge a consumed [`Message`] to its backend.
+#[async_trait::async_trait]
+pub trait Acker: Send + Sync {
+    async fn ack(&self) -> Result<(), BackendError>;
+}
+
+/// A payload consumed from a queue, which must be acknowledged once handled.
+pub struct Message {
+    pub data: Vec<u8>,
+    /// The message has already been delivered to a consumer which did not acknowledge it.
+    pub redelivered: bool,
+    acker: Box<dyn Acker>,
+}
+
+impl Message {
+    pub fn new(data: Vec<u8>, acker: impl Acker + 'static) -> Self {
+        Self {
+            data,
+            redelivered: false,
+            acker: Box::new(acker),
+        }
+    }
+
+    /// Flag the message as [`Message::redelivered`] by the backend.
+    #[must_use]
+    pub const fn with_redelivered(mut self, redelivered: bool) -> Self {
+        self.redelivered = redelivered;
+        self
+    }
+
+    /// Remove the message from its queue.
+    ///
+    /// # Errors
+    ///
+    /// * the backend failed to acknowledge the message
+    pub async fn ack(&self) -> Result<(), BackendError> {
+        self.acker.ack().await
+    }
+}
+
+pub type Consumer =
+    std::pin::Pin<Box<dyn tokio_stream::Stream<Item = Result<Message, BackendError>> + Send>>;
+
+/// The message-queue operations used to move a message between the services.
+///
+/// The queues and exchanges are expected to be declared by the backend, the messages
+/// published with `routing_key` to the [`Exchange::Delivery`] and [`Exchange::DelayedDeferred`]
+/// exchanges are consumed from the `delivery-{routing_key}` and `deferred-{routing_key}` queues.
+///
+/// The AMQP implementation over a [`lapin::Channel`] is the default backend.
+#[async_trait::async_trait]
+pub trait QueueBackend: Send + Sync {
+    /// Send a received message to the working service.
+    async fn write_to_working(&self, payload: Vec<u8>);
+
+    /// Send a processed message to the delivery service bound to `routing_key`.
+    ///
+    /// Returns `false` if no delivery service is bound to `routing_key`,
+    /// the message is then not published.
+    async fn write_to_delivery(&self, routing_key: &str, payload: Vec<u8>) -> bool;
+
+    /// Send a message back to the delivery service bound to `routing_key` once `delay` is elapsed.
+    ///
+    /// The route is not checked, the delayed exchange does not support the `mandatory` flag.
+    async fn write_to_deferred(
+        &self,
+        routing_key: &str,
+        delay: std::time::Duration,
+        payload: Vec<u8>,
+    );
+
+    /// Request a delivery status notification for the message.
+    async fn write_to_report_dsn(&self, payload: Vec<u8>);
+
+    /// Put the message in the `quarantine` queue.
+    async fn write_to_quarantine(&self, quarantine: &str, payload: Vec<u8>);
+
+    /// Put the message in the [`Queue::Dead`] queue.
+    async fn write_to_dead(&self, payload: Vec<u8>);
+
+    /// Put a message without delivery service in the [`Queue::NoRoute`] queue.
+    async fn write_to_no_route(&self, payload: Vec<u8>);
+
+    /// Limit the number of unacknowledged messages delivered to the consumers.
+    async fn set_prefetch(&self, prefetch_count: u16) -> Result<(), BackendError>;
+
+    /// Consume the messages of `queue`.
+    async fn consume(&self, queue: &str) -> Result<Consumer, BackendError>;
+
+    /// Number of messages ready in `queue`.
+    ///
+    /// The messages delayed by the [`Exchange::DelayedDeferred`] exchange are not counted,
+    /// see [`Backlog`].
+    async fn queue_depth(&self, queue: &str) -> Result<u32, BackendError>;
+}
+
+/// Apply the `prefetch_count` to the backend, then consume all the `queues`.
+///
+/// # Errors
+///
+/// * the prefetch count cannot be set
+/// * one of the queues cannot be consumed
+pub async fn subscribe(
+    backend: &dyn QueueBackend,
+    prefetch_count: u16,
+    queues: impl IntoIterator<Item = String> + Send,
+) -> Result<tokio_stream::StreamMap<String, Consumer>, BackendError> {
+    backend.set_prefetch(prefetch_count).await?;
+
+    let mut consumers = tokio_stream::StreamMap::new();
+    for queue in queues {
+        tracing::debug!("Starting consumer for {}", queue);
+        let consumer = backend.consume(&queue).await?;
+        consumers.insert(queue, consumer);
+    }
+
+    Ok(consumers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{in_memory::InMemory, subscribe, Priority};
+
+    #[tokio::test]
+    async fn prefetch_is_applied_before_consuming() {
+        let config: vsmtp_config::Broker =
+            serde_json::from_str(r#"{ "uri": "amqp://localhost", "prefetch_count": 16 }"#).unwrap();
+        let backend = InMemory::default();
+
+        let consumers = subscribe(
+            &backend,
+            config.prefetch_count,
+            ["deferred-basic".to_string(), "delivery-basic".to_string()],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(consumers.len(), 2);
+        assert_eq!(
+            backend.consumed(),
+            [
+                ("deferred-basic".to_string(), Some(16)),
+                ("delivery-basic".to_string(), Some(16))
+            ]
+        );
+    }
+
+    #[test]
+    fn default_prefetch() {
+        let config: vsmtp_config::Broker =
+            serde_json::from_str(r#"{ "uri": "amqp://localhost" }"#).unwrap();
+        assert_eq!(config.prefetch_count, 1);
+        assert_eq!(vsmtp_config::Broker::default().prefetch_count, 1);
+    }
+
+    #[test]
+    fn priority() {
+        assert_eq!("high".parse::<Priority>().unwrap(), Priority::High);
+        assert_eq!("Urgent".parse::<Priority>().unwrap(), Priority::High);
+        assert_eq!("normal".parse::<Priority>().unwrap(), Priority::Normal);
+        assert!("non-urgent".parse::<Priority>().is_err());
+
+        assert_eq!(Priority::High.routing_key("basic"), "basic.priority");
+        assert_eq!(Priority::Normal.routing_key("basic"), "basic");
+    }
 }
@@ -0,0 +1,255 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::{Acker, BackendError, Consumer, Exchange, Message, Priority, Queue, QueueBackend};
+
+type Queues = std::sync::Arc<
+    std::sync::Mutex<std::collections::HashMap<String, std::collections::VecDeque<Vec<u8>>>>,
+>;
+
+/// A [`QueueBackend`] holding its queues in memory, to test the services without a broker.
+///
+/// The queues are named as the ones declared for the AMQP backend: `to-working`,
+/// `delivery-{routing_key}`, `deferred-{routing_key}`, `dsn`, `rule.{quarantine}`,
+/// `dead` and `no-route`. The delayed high priority messages go to their
+/// `delivery-{routing_key}` queue.
+///
+/// A consumer ends once its queue is empty. The messages consumed and not acknowledged
+/// go back to their queue when dropped, as they do when the AMQP channel is closed.
+#[derive(Default)]
+pub struct InMemory {
+    queues: Queues,
+    /// The delay of the messages published to the deferred exchange, with their routing key.
+    delays: std::sync::Mutex<Vec<(String, std::time::Duration)>>,
+    /// The routing keys without delivery service bound to them.
+    unbound: std::collections::HashSet<String>,
+    prefetch: std::sync::Mutex<Option<u16>>,
+    /// The queues consumed, with the prefetch count applied at that time.
+    consumed: std::sync::Mutex<Vec<(String, Option<u16>)>>,
+    /// The broker closes the channel of a depth read on an unknown queue.
+    closed: std::sync::atomic::AtomicBool,
+}
+
+impl InMemory {
+    /// Remove the delivery services bound to the `routing_keys`, the messages
+    /// sent to them are not published. The delayed messages are still published,
+    /// as with the AMQP backend.
+    #[must_use]
+    pub fn without_service(mut self, routing_keys: &[&str]) -> Self {
+        self.unbound
+            .extend(routing_keys.iter().map(ToString::to_string));
+        self
+    }
+
+    /// Publish `payload` directly to `queue`.
+    pub fn push(&self, queue: &str, payload: Vec<u8>) {
+        self.queues
+            .lock()
+            .expect("queues poisoned")
+            .entry(queue.to_string())
+            .or_default()
+            .push_back(payload);
+    }
+
+    /// The messages waiting in `queue`.
+    #[must_use]
+    pub fn messages(&self, queue: &str) -> Vec<Vec<u8>> {
+        self.queues
+            .lock()
+            .expect("queues poisoned")
+            .get(queue)
+            .map(|messages| messages.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The name of the queues with messages waiting, in alphabetical order.
+    #[must_use]
+    pub fn queues(&self) -> Vec<String> {
+        let mut queues = self
+            .queues
+            .lock()
+            .expect("queues poisoned")
+            .iter()
+            .filter(|(_, messages)| !messages.is_empty())
+            .map(|(queue, _)| queue.clone())
+            .collect::<Vec<_>>();
+        queues.sort();
+        queues
+    }
+
+    /// No message is waiting in any queue.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.queues
+            .lock()
+            .expect("queues poisoned")
+            .values()
+            .all(std::collections::VecDeque::is_empty)
+    }
+
+    /// The delays of the messages published to the deferred exchange, with their routing key.
+    #[must_use]
+    pub fn delays(&self) -> Vec<(String, std::time::Duration)> {
+        self.delays.lock().expect("delays poisoned").clone()
+    }
+
+    /// The queues consumed, with the prefetch count applied at that time.
+    #[must_use]
+    pub fn consumed(&self) -> Vec<(String, Option<u16>)> {
+        self.consumed.lock().expect("consumed poisoned").clone()
+    }
+
+    fn assert_open(&self) {
+        assert!(
+            !self.closed.load(std::sync::atomic::Ordering::SeqCst),
+            "read on a closed channel"
+        );
+    }
+}
+
+/// Send the message back to its queue if dropped without being acknowledged.
+struct Ack {
+    queues: Queues,
+    queue: String,
+    payload: Vec<u8>,
+    acked: std::sync::atomic::AtomicBool,
+}
+
+#[async_trait::async_trait]
+impl Acker for Ack {
+    async fn ack(&self) -> Result<(), BackendError> {
+        self.acked.store(true, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl Drop for Ack {
+    fn drop(&mut self) {
+        if !self.acked.load(std::sync::atomic::Ordering::SeqCst) {
+            if let Ok(mut queues) = self.queues.lock() {
+                queues
+                    .entry(std::mem::take(&mut self.queue))
+                    .or_default()
+                    .push_back(std::mem::take(&mut self.payload));
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl QueueBackend for InMemory {
+    async fn write_to_working(&self, payload: Vec<u8>) {
+        self.push(Queue::ToWorking.as_ref(), payload);
+    }
+
+    async fn write_to_delivery(&self, routing_key: &str, payload: Vec<u8>) -> bool {
+        if self.unbound.contains(routing_key) {
+            return false;
+        }
+        self.push(
+            &format!("{}-{routing_key}", Exchange::Delivery.as_ref()),
+            payload,
+        );
+        true
+    }
+
+    async fn write_to_deferred(
+        &self,
+        routing_key: &str,
+        delay: std::time::Duration,
+        payload: Vec<u8>,
+    ) {
+        self.delays
+            .lock()
+            .expect("delays poisoned")
+            .push((routing_key.to_string(), delay));
+        if routing_key.ends_with(&Priority::High.routing_key("")) {
+            self.push(
+                &format!("{}-{routing_key}", Exchange::Delivery.as_ref()),
+                payload,
+            );
+        } else {
+            self.push(&format!("deferred-{routing_key}"), payload);
+        }
+    }
+
+    async fn write_to_report_dsn(&self, payload: Vec<u8>) {
+        self.push(Queue::DSN.as_ref(), payload);
+    }
+
+    async fn write_to_quarantine(&self, quarantine: &str, payload: Vec<u8>) {
+        self.push(&format!("rule.{quarantine}"), payload);
+    }
+
+    async fn write_to_dead(&self, payload: Vec<u8>) {
+        self.push(Queue::Dead.as_ref(), payload);
+    }
+
+    async fn write_to_no_route(&self, payload: Vec<u8>) {
+        self.push(Queue::NoRoute.as_ref(), payload);
+    }
+
+    async fn set_prefetch(&self, prefetch_count: u16) -> Result<(), BackendError> {
+        self.assert_open();
+        *self.prefetch.lock().expect("prefetch poisoned") = Some(prefetch_count);
+        Ok(())
+    }
+
+    async fn consume(&self, queue: &str) -> Result<Consumer, BackendError> {
+        self.assert_open();
+        self.consumed.lock().expect("consumed poisoned").push((
+            queue.to_string(),
+            *self.prefetch.lock().expect("prefetch poisoned"),
+        ));
+
+        let messages = self
+            .queues
+            .lock()
+            .expect("queues poisoned")
+            .get_mut(queue)
+            .map(std::mem::take)
+            .unwrap_or_default();
+
+        // built ahead, so that the messages not read by the consumer go back to the queue.
+        let messages = messages
+            .into_iter()
+            .map(|payload| {
+                Ok(Message::new(
+                    payload.clone(),
+                    Ack {
+                        queues: self.queues.clone(),
+                        queue: queue.to_string(),
+                        payload,
+                        acked: std::sync::atomic::AtomicBool::new(false),
+                    },
+                ))
+            })
+            .collect::<Vec<Result<_, BackendError>>>();
+
+        Ok(Box::pin(tokio_stream::iter(messages)))
+    }
+
+    async fn queue_depth(&self, queue: &str) -> Result<u32, BackendError> {
+        self.assert_open();
+        let depth = self
+            .queues
+            .lock()
+            .expect("queues poisoned")
+            .get(queue)
+            .map(std::collections::VecDeque::len);
+
+        let Some(depth) = depth else {
+            self.closed.store(true, std::sync::atomic::Ordering::SeqCst);
+            return Err(format!("NOT_FOUND - no queue '{queue}'").into());
+        };
+        Ok(u32::try_from(depth).expect("queue depth fits in u32"))
+    }
+}
@@ -0,0 +1,368 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::{timeouts::Timeouts, Mailbox};
+use vsmtp_protocol::{ClientName, Reader, Reply, Writer};
+
+/// Verdict of the verification of a recipient by its mail exchanger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The recipient has been accepted.
+    Accepted,
+    /// The recipient has been rejected with a permanent error.
+    Rejected(Reply),
+    /// The recipient could not be verified (temporary error, unreachable server, ...).
+    Unknown(String),
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Accepted => "accepted",
+            Self::Rejected(_) => "rejected",
+            Self::Unknown(_) => "unknown",
+        })
+    }
+}
+
+/// Verification of the recipients by their mail exchanger (callout).
+///
+/// A session is opened to the mail exchanger, `MAIL FROM:<>` and `RCPT TO:<recipient>` are
+/// issued, then the session is closed without sending any message.
+/// The accepted and rejected recipients are cached for `ttl`.
+pub struct Callout {
+    client_name: ClientName,
+    timeouts: Timeouts,
+    ttl: std::time::Duration,
+    cache: std::sync::Mutex<std::collections::HashMap<String, (std::time::Instant, Verdict)>>,
+}
+
+impl Callout {
+    /// Create a callout verifier, introducing itself with `client_name` in the `EHLO` command.
+    #[must_use]
+    pub fn new(client_name: ClientName, timeouts: Timeouts, ttl: std::time::Duration) -> Self {
+        Self {
+            client_name,
+            timeouts,
+            ttl,
+            cache: std::sync::Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+
+    /// Get the verdict of a recipient from the cache, if it has not expired.
+    ///
+    /// # Panics
+    ///
+    /// * the cache lock is poisoned.
+    #[must_use]
+    pub fn cached(&self, rcpt: &Mailbox) -> Option<Verdict> {
+        let mut cache = self.cache.lock().expect("callout cache poisoned");
+        let key = rcpt.to_string().to_lowercase();
+
+        match cache.get(&key) {
+            Some((inserted, verdict)) if inserted.elapsed() < self.ttl => Some(verdict.clone()),
+            Some(_) => {
+                cache.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Verify that `rcpt` is accepted by the mail exchanger listening on `ip_addr`.
+    /// The cached verdicts are returned without opening a session.
+    ///
+    /// # Panics
+    ///
+    /// * the cache lock is poisoned.
+    pub async fn verify(&self, ip_addr: std::net::SocketAddr, rcpt: &Mailbox) -> Verdict {
+        if let Some(verdict) = self.cached(rcpt) {
+            tracing::debug!(%rcpt, %verdict, "callout verdict found in cache");
+            return verdict;
+        }
+
+        let verdict = self.probe(ip_addr, rcpt).await;
+        tracing::debug!(%rcpt, %ip_addr, ?verdict, "callout done");
+
+        if !matches!(verdict, Verdict::Unknown(_)) {
+            self.cache.lock().expect("callout cache poisoned").insert(
+                rcpt.to_string().to_lowercase(),
+                (std::time::Instant::now(), verdict.clone()),
+            );
+        }
+
+        verdict
+    }
+
+    async fn probe(&self, ip_addr: std::net::SocketAddr, rcpt: &Mailbox) -> Verdict {
+        let socket = match tokio::time::timeout(
+            self.timeouts.connect,
+            tokio::net::TcpStream::connect(ip_addr),
+        )
+        .await
+        {
+            Ok(Ok(socket)) => socket,
+            Ok(Err(error)) => return Verdict::Unknown(error.to_string()),
+            Err(elapsed) => {
+                return Verdict::Unknown(format!("connection timeout reached: {elapsed}"))
+            }
+        };
+        let (read, write) = socket.into_split();
+        let mut reader = Reader::new(read, false);
+        let mut writer = Writer::new(write);
+
+        let replies = reader.as_reply_stream();
+        tokio::pin!(replies);
+
+        let verdict =
+            match tokio::time::timeout(self.timeouts.greeting, next_reply(&mut replies)).await {
+                Ok(Ok(reply)) if reply.code().value() == 220 => {
+                    self.envelope(&mut replies, &mut writer, rcpt).await
+                }
+                Ok(Ok(reply)) => {
+                    return Verdict::Unknown(format!(
+                        "expect 220 on greetings, got {}",
+                        reply.to_string().trim_end()
+                    ))
+                }
+                Ok(Err(error)) => return Verdict::Unknown(error),
+                Err(elapsed) => {
+                    return Verdict::Unknown(format!("greetings timeout reached: {elapsed}"))
+                }
+            };
+
+        // NOTE: the session is closed in any case.
+        let _ = self.command(&mut replies, &mut writer, "QUIT\r\n").await;
+        verdict.unwrap_or_else(Verdict::Unknown)
+    }
+
+    /// Send the envelope of a transaction without the message.
+    async fn envelope<S, W>(
+        &self,
+        replies: &mut S,
+        writer: &mut Writer<W>,
+        rcpt: &Mailbox,
+    ) -> Result<Verdict, String>
+    where
+        S: tokio_stream::Stream<Item = Result<Reply, vsmtp_protocol::Error>> + Unpin + Send,
+        W: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        let ehlo = self
+            .command(replies, writer, &format!("EHLO {}\r\n", self.client_name))
+            .await?;
+        if ehlo.code().value() / 100 != 2 {
+            return Err(format!(
+                "expect 250 on ehlo, got {}",
+                ehlo.to_string().trim_end()
+            ));
+        }
+
+        let mail_from = self.command(replies, writer, "MAIL FROM:<>\r\n").await?;
+        if mail_from.code().value() / 100 != 2 {
+            return Err(format!(
+                "the null reverse path has been refused: {}",
+                mail_from.to_string().trim_end()
+            ));
+        }
+
+        let rcpt_to = self
+            .command(replies, writer, &format!("RCPT TO:<{rcpt}>\r\n"))
+            .await?;
+        Ok(match rcpt_to.code().value() / 100 {
+            2 => Verdict::Accepted,
+            5 => Verdict::Rejected(rcpt_to),
+            _ => Verdict::Unknown(rcpt_to.to_string().trim_end().to_string()),
+        })
+    }
+
+    /// Send `command` and receive its reply, within the command timeout.
+    async fn command<S, W>(
+        &self,
+        replies: &mut S,
+        writer: &mut Writer<W>,
+        command: &str,
+    ) -> Result<Reply, String>
+    where
+        S: tokio_stream::Stream<Item = Result<Reply, vsmtp_protocol::Error>> + Unpin + Send,
+        W: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        tokio::time::timeout(self.timeouts.command, async {
+            writer
+                .write_all(command)
+                .await
+                .map_err(|error| error.to_string())?;
+            next_reply(replies).await
+        })
+        .await
+        .map_err(|elapsed| format!("command timeout reached: {elapsed}"))?
+    }
+}
+
+async fn next_reply<S>(replies: &mut S) -> Result<Reply, String>
+where
+    S: tokio_stream::Stream<Item = Result<Reply, vsmtp_protocol::Error>> + Unpin + Send,
+{
+    match tokio_stream::StreamExt::try_next(replies).await {
+        Ok(Some(reply)) => Ok(reply),
+        Ok(None) => Err("unexpected eof".to_string()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Callout, Verdict};
+    use crate::timeouts::Timeouts;
+    use crate::Mailbox;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+    use vsmtp_protocol::ClientName;
+
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+    fn callout(ttl: std::time::Duration) -> Callout {
+        Callout::new(
+            ClientName::Domain("probe.example.com".parse().unwrap()),
+            Timeouts {
+                connect: TIMEOUT,
+                greeting: TIMEOUT,
+                command: TIMEOUT,
+                data: TIMEOUT,
+            },
+            ttl,
+        )
+    }
+
+    /// Accept sessions, answer `rcpt` to `RCPT TO` and record the commands received.
+    async fn reply_at_rcpt(
+        rcpt: &'static str,
+    ) -> (
+        std::net::SocketAddr,
+        std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let commands = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+        let received = commands.clone();
+        let server = tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (read, mut write) = socket.into_split();
+                let mut lines = tokio::io::BufReader::new(read).lines();
+
+                write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    received.lock().unwrap().push(line.clone());
+                    let reply = match line.split_whitespace().next() {
+                        Some("EHLO") => "250 localhost\r\n",
+                        Some("MAIL") => "250 Ok\r\n",
+                        Some("RCPT") => rcpt,
+                        Some("QUIT") => "221 Bye\r\n",
+                        _ => "500 Unexpected command\r\n",
+                    };
+                    write.write_all(reply.as_bytes()).await.unwrap();
+                }
+            }
+        });
+
+        (addr, commands, server)
+    }
+
+    fn jenny() -> Mailbox {
+        Mailbox("jenny@example.com".parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn accepted() {
+        let (addr, commands, server) = reply_at_rcpt("250 Ok\r\n").await;
+
+        let verdict = callout(std::time::Duration::from_secs(60))
+            .verify(addr, &jenny())
+            .await;
+        assert_eq!(verdict, Verdict::Accepted);
+        assert_eq!(
+            *commands.lock().unwrap(),
+            vec![
+                "EHLO probe.example.com",
+                "MAIL FROM:<>",
+                "RCPT TO:<jenny@example.com>",
+                "QUIT"
+            ]
+        );
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn rejected() {
+        let (addr, _, server) = reply_at_rcpt("550 5.1.1 No such user\r\n").await;
+
+        let verdict = callout(std::time::Duration::from_secs(60))
+            .verify(addr, &jenny())
+            .await;
+        assert_eq!(
+            verdict,
+            Verdict::Rejected("550 5.1.1 No such user\r\n".parse().unwrap())
+        );
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn temporary_failure_is_not_cached() {
+        let (addr, commands, server) = reply_at_rcpt("450 4.2.0 Try again later\r\n").await;
+        let callout = callout(std::time::Duration::from_secs(60));
+
+        for _ in 0..2 {
+            let verdict = callout.verify(addr, &jenny()).await;
+            assert!(matches!(verdict, Verdict::Unknown(_)));
+        }
+        assert_eq!(callout.cached(&jenny()), None);
+        assert_eq!(
+            commands
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|command| command.starts_with("RCPT"))
+                .count(),
+            2
+        );
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn cached() {
+        let (addr, commands, server) = reply_at_rcpt("550 5.1.1 No such user\r\n").await;
+        let callout = callout(std::time::Duration::from_millis(300));
+
+        for _ in 0..3 {
+            let verdict = callout.verify(addr, &jenny()).await;
+            assert_eq!(verdict.to_string(), "rejected");
+        }
+        assert_eq!(commands.lock().unwrap().len(), 4);
+        assert_eq!(
+            callout.cached(&Mailbox("JENNY@example.com".parse().unwrap())),
+            Some(Verdict::Rejected(
+                "550 5.1.1 No such user\r\n".parse().unwrap()
+            ))
+        );
+
+        // the verdict expires after the ttl.
+        tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+        assert_eq!(callout.cached(&jenny()), None);
+        callout.verify(addr, &jenny()).await;
+        assert_eq!(commands.lock().unwrap().len(), 8);
+
+        server.abort();
+    }
+}
@@ -42,10 +42,111 @@ impl<T: serde::Serialize> Ctx<T> {
     }
 }
 
+/// Key of the number of commands issued by the client in [`Ctx::internal`].
+const COMMAND_COUNT: &str = "command_count";
+/// Keys of [`Ctx::internal`] only relevant to the session, removed from the context
+/// of the messages sent to the other services.
+const SESSION_KEYS: &[&str] = &[COMMAND_COUNT, MAIL_FROM_PARAMETERS, RCPT_TO_PARAMETERS];
+/// Key of the raw parameters of the last `MAIL FROM` command in [`Ctx::internal`].
+const MAIL_FROM_PARAMETERS: &str = "mail_from_parameters";
+/// Key of the raw parameters of the last `RCPT TO` command in [`Ctx::internal`].
+const RCPT_TO_PARAMETERS: &str = "rcpt_to_parameters";
+/// Key of the values of the `DKIM-Signature` headers added by this server in [`Ctx::internal`].
+const DKIM_SIGNATURES: &str = "dkim_signatures";
+
+impl<T> Ctx<T> {
+    /// Count a command issued by the client during the session.
+    pub fn count_command(&mut self) {
+        let count = self.command_count().saturating_add(1);
+        self.internal
+            .insert(COMMAND_COUNT.to_string(), rhai::Dynamic::from_int(count));
+    }
+
+    /// Number of commands issued by the client so far in the session.
+    #[must_use]
+    pub fn command_count(&self) -> rhai::INT {
+        self.internal
+            .get(COMMAND_COUNT)
+            .and_then(|count| count.as_int().ok())
+            .unwrap_or_default()
+    }
+
+    /// Record the parameters of the `MAIL FROM` command as received,
+    /// the ones of the previous transaction are forgotten.
+    pub fn set_mail_from_parameters(&mut self, parameters: String) {
+        self.internal.remove(RCPT_TO_PARAMETERS);
+        self.internal
+            .insert(MAIL_FROM_PARAMETERS.to_string(), parameters.into());
+    }
+
+    /// Record the parameters of the `RCPT TO` command as received.
+    pub fn set_rcpt_to_parameters(&mut self, parameters: String) {
+        self.internal
+            .insert(RCPT_TO_PARAMETERS.to_string(), parameters.into());
+    }
+
+    /// Parameters of the last `MAIL FROM` command, `None` if it has not been received.
+    #[must_use]
+    pub fn mail_from_parameters(&self) -> Option<String> {
+        self.internal
+            .get(MAIL_FROM_PARAMETERS)
+            .and_then(|parameters| parameters.clone().into_string().ok())
+    }
+
+    /// Parameters of the last `RCPT TO` command, `None` if it has not been received.
+    #[must_use]
+    pub fn rcpt_to_parameters(&self) -> Option<String> {
+        self.internal
+            .get(RCPT_TO_PARAMETERS)
+            .and_then(|parameters| parameters.clone().into_string().ok())
+    }
+
+    /// Record the value of a `DKIM-Signature` header added to the message by this server.
+    pub fn add_dkim_signature(&mut self, signature: String) {
+        let mut signatures = self.dkim_signatures();
+        signatures.push(signature);
+        self.internal.insert(
+            DKIM_SIGNATURES.to_string(),
+            signatures
+                .into_iter()
+                .map(rhai::Dynamic::from)
+                .collect::<rhai::Array>()
+                .into(),
+        );
+    }
+
+    /// Values of the `DKIM-Signature` headers added to the message by this server.
+    #[must_use]
+    pub fn dkim_signatures(&self) -> Vec<String> {
+        self.internal
+            .get(DKIM_SIGNATURES)
+            .and_then(|signatures| signatures.clone().into_array().ok())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|signature| signature.into_string().ok())
+            .collect()
+    }
+}
+
 impl Ctx<StatefulCtxReceived> {
     pub fn produce_new(&self) -> Self {
         let mut new_instance = self.clone();
         new_instance.metadata.reset();
+        new_instance.internal.remove(DKIM_SIGNATURES);
         new_instance
     }
+
+    /// Take the context of the message received, the state of the session
+    /// being kept for the next transaction, see [`Ctx::produce_new`].
+    ///
+    /// The data only relevant to the session are not sent with the message.
+    #[must_use]
+    pub fn take_message(&mut self) -> Self {
+        let next = self.produce_new();
+        let mut message = std::mem::replace(self, next);
+        for key in SESSION_KEYS {
+            message.internal.remove(*key);
+        }
+        message
+    }
 }
@@ -24,10 +24,17 @@ pub struct CtxDelivery {
     pub routing_key: DeliveryRoute,
     pub mail_from: MailFromProps,
     pub rcpt_to: Vec<Recipient>,
+    /// Name of the transport of the recipients, holding the policy of the delivery.
+    #[serde(default)]
+    pub transport: Option<String>,
     #[dummy(faker = "MailFaker")]
     pub mail: std::sync::Arc<std::sync::RwLock<Mail>>,
     pub last_deliveries: Vec<DeliveryAttempt>,
     pub attempt: Vec<DeliveryAttempt>,
+    /// The message has been deferred with a copy in the deferred store of its delivery service,
+    /// the copies consumed without a matching entry in the store are obsolete.
+    #[serde(default)]
+    pub persisted: bool,
 }
 
 impl CtxDelivery {
@@ -42,9 +49,11 @@ impl CtxDelivery {
             routing_key: route,
             mail_from,
             rcpt_to,
+            transport: None,
             mail,
             last_deliveries: vec![],
             attempt: vec![],
+            persisted: false,
         }
     }
 
@@ -54,22 +63,43 @@ impl CtxDelivery {
         std::time::Duration::from_secs((self.attempt.len() * 10).try_into().unwrap())
     }
 
-    pub fn get_undelivered_rcpt(&self) -> impl Iterator<Item = &Recipient> {
-        fn recipient_attempt_is_successful(attempt: &DeliveryAttempt, rcpt: &Recipient) -> bool {
+    /// Get the longest delay requested by the remote servers during the last deliveries.
+    #[must_use]
+    pub fn get_retry_hint(&self) -> Option<std::time::Duration> {
+        self.last_deliveries
+            .iter()
+            .filter_map(DeliveryAttempt::get_retry_hint)
+            .max()
+    }
+
+    /// Get the time at which the first delivery attempt of the message ended,
+    /// `None` before the first attempt.
+    #[must_use]
+    pub fn get_first_attempt(&self) -> Option<time::OffsetDateTime> {
+        self.attempt
+            .iter()
+            .filter_map(DeliveryAttempt::get_timestamp)
+            .min()
+    }
+
+    /// A recipient is delivered once one of the attempts targeting it succeeded.
+    #[must_use]
+    pub fn is_rcpt_delivered(&self, rcpt: &Recipient) -> bool {
+        self.attempt.iter().rev().any(|attempt| {
             attempt
                 .get_rcpt_index(rcpt)
                 .is_some_and(|rcpt_idx| attempt.get_action(rcpt_idx).is_successful())
-        }
-
-        self.rcpt_to.iter().filter(|rcpt| {
-            !self
-                .attempt
-                .iter()
-                .rev()
-                .any(|attempt| recipient_attempt_is_successful(attempt, rcpt))
         })
     }
 
+    /// Get the recipients which have not been delivered yet, and must be targeted
+    /// by the next delivery attempt.
+    pub fn get_undelivered_rcpt(&self) -> impl Iterator<Item = &Recipient> {
+        self.rcpt_to
+            .iter()
+            .filter(|rcpt| !self.is_rcpt_delivered(rcpt))
+    }
+
     #[must_use]
     pub fn get_last_delivery_attempt_of_rcpt(
         &self,
@@ -27,6 +27,18 @@ pub enum LocalInformation {
     WriteZero,
     /// scenario: could not allocate memory
     OutOfMemory,
+    /// scenario: the delivery command exited with a temporary failure (`EX_TEMPFAIL`)
+    CommandTemporaryFailure {
+        exit_code: i32,
+    },
+    /// scenario: the delivery command exited with an error, or was killed by a signal
+    CommandFailure {
+        exit_code: Option<i32>,
+    },
+    /// scenario: the message already went through this server too many times
+    RoutingLoop {
+        hops: usize,
+    },
     /// error not related to mail storage system, but might happen (really unlikely)
     OtherError(String),
     Success,
@@ -39,11 +51,16 @@ impl LocalInformation {
             | Self::NotFound
             | Self::PermissionDenied
             | Self::AlreadyExists
-            | Self::BrokenPipe => Action::Failed {
+            | Self::BrokenPipe
+            | Self::CommandFailure { .. }
+            | Self::RoutingLoop { .. } => Action::Failed {
                 diagnostic_code: None,
             },
             Self::Success => Action::Delivered,
-            Self::TimedOut | Self::WriteZero | Self::OutOfMemory => Action::Delayed {
+            Self::TimedOut
+            | Self::WriteZero
+            | Self::OutOfMemory
+            | Self::CommandTemporaryFailure { .. } => Action::Delayed {
                 diagnostic_code: None,
                 will_retry_until: None,
             },
@@ -60,7 +77,11 @@ impl From<&LocalInformation> for Status {
             | LocalInformation::BrokenPipe => Self("5.0.0".to_string()),
             LocalInformation::TimedOut => Self("4.4.7".to_string()),
             LocalInformation::WriteZero => Self("4.3.1".to_string()),
-            LocalInformation::OutOfMemory => Self("4.3.0".to_string()),
+            LocalInformation::OutOfMemory | LocalInformation::CommandTemporaryFailure { .. } => {
+                Self("4.3.0".to_string())
+            }
+            LocalInformation::CommandFailure { .. } => Self("5.3.0".to_string()),
+            LocalInformation::RoutingLoop { .. } => Self("5.4.6".to_string()),
             LocalInformation::OtherError(_) => Self("5.3.0".to_string()),
             LocalInformation::Success => Self("2.0.0".to_owned()),
         }
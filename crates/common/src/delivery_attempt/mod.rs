@@ -9,16 +9,18 @@
  *
  */
 
-use crate::{Mailbox, Recipient};
+use crate::{tls::TlsProps, Mailbox, Recipient};
 
 mod local_information;
 mod remote_information;
+mod report;
 
 pub use local_information::LocalInformation;
 pub use remote_information::{
     EitherEhloOrError, EitherGreetingsOrError, EitherRemoteServerOrError, RemoteInformation,
     RemoteMailExchange, RemoteServer,
 };
+pub use report::{AttemptReport, RecipientReport, RemoteReport, TlsReport};
 
 pub struct Status(pub String);
 
@@ -65,6 +67,25 @@ pub struct DeliveryAttempt {
     #[dummy(faker = "ShouldNotifyFaker")]
     should_notify: ShouldNotify,
     inner: DeliveryType,
+    // the delay requested by the remote server before the next attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_hint: Option<std::time::Duration>,
+    // the time at which the attempt ended
+    #[serde(
+        default,
+        with = "time::serde::iso8601::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[dummy(expr = "None")]
+    timestamp: Option<time::OffsetDateTime>,
+    // the TLS session established with the remote server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[dummy(expr = "None")]
+    tls: Option<TlsProps>,
+    // the reason why an opportunistic TLS upgrade fell back to cleartext
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[dummy(expr = "None")]
+    tls_downgrade: Option<String>,
 }
 
 impl DeliveryAttempt {
@@ -78,6 +99,10 @@ impl DeliveryAttempt {
             recipients: rcpt_to,
             inner: DeliveryType::RemoteSmtp(Box::new(remote_info)),
             should_notify,
+            retry_hint: None,
+            timestamp: Some(time::OffsetDateTime::now_utc()),
+            tls: None,
+            tls_downgrade: None,
         }
     }
 
@@ -91,9 +116,66 @@ impl DeliveryAttempt {
             recipients: vec![rcpt_to],
             inner: DeliveryType::Local(local_info),
             should_notify,
+            retry_hint: None,
+            timestamp: Some(time::OffsetDateTime::now_utc()),
+            tls: None,
+            tls_downgrade: None,
         }
     }
 
+    /// Parse the retry hint of the remote replies, to override the default backoff
+    /// of the next attempt.
+    #[must_use]
+    pub fn with_retry_hint(mut self) -> Self {
+        self.retry_hint = match &self.inner {
+            DeliveryType::Local(_) => None,
+            DeliveryType::RemoteSmtp(remote_information) => remote_information.get_retry_hint(),
+        };
+        self
+    }
+
+    /// Record the TLS session established with the remote server.
+    #[must_use]
+    pub fn with_tls(mut self, tls: TlsProps) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Record that the connection fell back to cleartext after a failed TLS upgrade.
+    #[must_use]
+    pub fn with_tls_downgrade(mut self, reason: String) -> Self {
+        self.tls_downgrade = Some(reason);
+        self
+    }
+
+    #[must_use]
+    pub fn get_tls_downgrade(&self) -> Option<&str> {
+        self.tls_downgrade.as_deref()
+    }
+
+    /// Get the error of the TLS handshake, if the attempt stopped on it.
+    #[must_use]
+    pub fn get_tls_upgrade_error(&self) -> Option<&str> {
+        match &self.inner {
+            DeliveryType::RemoteSmtp(remote) => match remote.as_ref() {
+                RemoteInformation::SmtpTlsUpgrade { error, .. } => Some(error),
+                _ => None,
+            },
+            DeliveryType::Local(_) => None,
+        }
+    }
+
+    /// Get the time at which the attempt ended.
+    #[must_use]
+    pub const fn get_timestamp(&self) -> Option<time::OffsetDateTime> {
+        self.timestamp
+    }
+
+    #[must_use]
+    pub const fn get_retry_hint(&self) -> Option<std::time::Duration> {
+        self.retry_hint
+    }
+
     #[must_use]
     pub const fn should_notify_on(&self, on: ShouldNotify) -> bool {
         self.should_notify.contains(on)
@@ -80,6 +80,17 @@ pub enum RemoteInformation {
         error: String,
         io: Option<vsmtp_protocol::Error>,
     },
+    /// Information received after a failed authentication.
+    SmtpAuth {
+        mx: Option<RemoteMailExchange>,
+        target: RemoteServer,
+        greeting: Reply,
+        ehlo: response::Ehlo,
+        error: String,
+        // the reply of the server, if the credentials have been sent.
+        reply: Option<Reply>,
+        io: Option<vsmtp_protocol::Error>,
+    },
     /// Information received after a MAIL FROM command.
     SmtpMailFrom {
         mx: Option<RemoteMailExchange>,
@@ -128,8 +139,10 @@ pub enum RemoteInformation {
 }
 
 impl RemoteInformation {
-    pub fn get_status(&self, rcpt_idx: usize) -> Option<Status> {
-        let reply = match self {
+    /// Get the reply which determined the outcome of the attempt for a recipient, if any.
+    #[must_use]
+    pub fn get_reply(&self, rcpt_idx: usize) -> Option<&Reply> {
+        match self {
             Self::SmtpGreetings {
                 greeting: Err((_, reply)),
                 ..
@@ -138,36 +151,40 @@ impl RemoteInformation {
                 ehlo: Err((_, reply)),
                 ..
             }
+            | Self::SmtpAuth {
+                reply: Some(reply), ..
+            }
             | Self::SmtpMailFrom {
                 mail_from: reply, ..
-            } => reply.code(),
+            } => Some(reply),
 
-            Self::SmtpRcptTo { rcpt_to, .. } => match rcpt_to.get(rcpt_idx) {
-                Some(rcpt_to) => rcpt_to.code(),
-                None => return None,
-            },
+            Self::SmtpRcptTo { rcpt_to, .. } => rcpt_to.get(rcpt_idx),
 
             Self::DnsMxIpLookup { .. }
             | Self::DnsMxLookup { .. }
-            | Self::SmtpTlsUpgrade { .. }
-            | Self::SmtpData { .. } => {
-                return None;
+            | Self::TcpConnection { .. }
+            | Self::SmtpGreetings {
+                greeting: Ok(_), ..
             }
+            | Self::SmtpEhlo { ehlo: Ok(_), .. }
+            | Self::SmtpTlsUpgrade { .. }
+            | Self::SmtpAuth { reply: None, .. }
+            | Self::SmtpData { .. } => None,
+
             Self::SmtpDataEnd {
                 rcpt_to, data_end, ..
-            } => match rcpt_to.get(rcpt_idx) {
-                Some(rcpt_to) => {
-                    if rcpt_to.code().value() / 100 == 2 {
-                        rcpt_to.code()
-                    } else {
-                        data_end.code()
-                    }
+            } => rcpt_to.get(rcpt_idx).map(|rcpt_to| {
+                if rcpt_to.code().value() / 100 == 2 {
+                    data_end
+                } else {
+                    rcpt_to
                 }
-                None => return None,
-            },
+            }),
+        }
+    }
 
-            _ => todo!("{self:?}"),
-        };
+    pub fn get_status(&self, rcpt_idx: usize) -> Option<Status> {
+        let reply = self.get_reply(rcpt_idx)?.code();
 
         Some(Status(
             reply
@@ -177,6 +194,67 @@ impl RemoteInformation {
         ))
     }
 
+    /// Get the mail exchanger targeted by the attempt, if it has been resolved.
+    #[must_use]
+    pub const fn get_mx(&self) -> Option<&RemoteMailExchange> {
+        match self {
+            Self::DnsMxLookup { .. } => None,
+            Self::DnsMxIpLookup { mx, .. } => Some(mx),
+            Self::TcpConnection { mx, .. }
+            | Self::SmtpGreetings { mx, .. }
+            | Self::SmtpEhlo { mx, .. }
+            | Self::SmtpTlsUpgrade { mx, .. }
+            | Self::SmtpAuth { mx, .. }
+            | Self::SmtpMailFrom { mx, .. }
+            | Self::SmtpRcptTo { mx, .. }
+            | Self::SmtpData { mx, .. }
+            | Self::SmtpDataEnd { mx, .. } => mx.as_ref(),
+        }
+    }
+
+    /// Get the server targeted by the attempt, if a connection has been tried.
+    #[must_use]
+    pub const fn get_target(&self) -> Option<&RemoteServer> {
+        match self {
+            Self::DnsMxLookup { .. } | Self::DnsMxIpLookup { .. } => None,
+            Self::TcpConnection {
+                target: Ok(target) | Err((_, target)),
+                ..
+            }
+            | Self::SmtpGreetings { target, .. }
+            | Self::SmtpEhlo { target, .. }
+            | Self::SmtpTlsUpgrade { target, .. }
+            | Self::SmtpAuth { target, .. }
+            | Self::SmtpMailFrom { target, .. }
+            | Self::SmtpRcptTo { target, .. }
+            | Self::SmtpData { target, .. }
+            | Self::SmtpDataEnd { target, .. } => Some(target),
+        }
+    }
+
+    /// Get the error which interrupted the attempt, if any.
+    #[must_use]
+    pub fn get_error(&self) -> Option<String> {
+        match self {
+            Self::DnsMxLookup { error } | Self::DnsMxIpLookup { error, .. } => {
+                Some(format!("{error:?}"))
+            }
+            Self::TcpConnection {
+                target: Err((error, _)),
+                ..
+            }
+            | Self::SmtpTlsUpgrade { error, .. }
+            | Self::SmtpAuth { error, .. } => Some(error.clone()),
+            Self::TcpConnection { io, .. }
+            | Self::SmtpGreetings { io, .. }
+            | Self::SmtpEhlo { io, .. }
+            | Self::SmtpMailFrom { io, .. }
+            | Self::SmtpRcptTo { io, .. }
+            | Self::SmtpData { io, .. }
+            | Self::SmtpDataEnd { io, .. } => io.as_ref().map(ToString::to_string),
+        }
+    }
+
     pub(super) fn get_action(&self, rcpt_idx: usize) -> Action {
         match self {
             Self::DnsMxLookup { .. }
@@ -185,6 +263,7 @@ impl RemoteInformation {
             | Self::SmtpGreetings { .. }
             | Self::SmtpEhlo { .. }
             | Self::SmtpTlsUpgrade { .. }
+            | Self::SmtpAuth { .. }
             | Self::SmtpMailFrom { .. }
             | Self::SmtpRcptTo { .. }
             | Self::SmtpData { .. } => Action::Delayed {
@@ -223,6 +302,66 @@ impl RemoteInformation {
         }
     }
 
+    /// Get the delay the remote server asked to wait before the next attempt, if any.
+    ///
+    /// The hint is parsed from the text of the transient (4xx) replies, for instance
+    /// `451 4.7.1 Greylisted, retry after 300 seconds` or `421 Retry-After: 120`.
+    /// If several replies carry a hint, the longest one is returned.
+    #[must_use]
+    pub fn get_retry_hint(&self) -> Option<std::time::Duration> {
+        let replies: Vec<&Reply> = match self {
+            Self::DnsMxLookup { .. }
+            | Self::DnsMxIpLookup { .. }
+            | Self::TcpConnection { .. }
+            | Self::SmtpTlsUpgrade { .. }
+            | Self::SmtpAuth { reply: None, .. }
+            | Self::SmtpGreetings {
+                greeting: Ok(_), ..
+            }
+            | Self::SmtpEhlo { ehlo: Ok(_), .. } => vec![],
+            Self::SmtpGreetings {
+                greeting: Err((_, reply)),
+                ..
+            }
+            | Self::SmtpEhlo {
+                ehlo: Err((_, reply)),
+                ..
+            }
+            | Self::SmtpAuth {
+                reply: Some(reply), ..
+            } => vec![reply],
+            Self::SmtpMailFrom { mail_from, .. } => vec![mail_from],
+            Self::SmtpRcptTo {
+                mail_from, rcpt_to, ..
+            } => std::iter::once(mail_from).chain(rcpt_to).collect(),
+            Self::SmtpData {
+                mail_from,
+                rcpt_to,
+                data,
+                ..
+            } => std::iter::once(mail_from)
+                .chain(rcpt_to)
+                .chain(std::iter::once(data))
+                .collect(),
+            Self::SmtpDataEnd {
+                mail_from,
+                rcpt_to,
+                data,
+                data_end,
+                ..
+            } => std::iter::once(mail_from)
+                .chain(rcpt_to)
+                .chain([data, data_end])
+                .collect(),
+        };
+
+        replies
+            .into_iter()
+            .filter(|reply| reply.code().value() / 100 == 4)
+            .filter_map(parse_retry_hint)
+            .max()
+    }
+
     pub fn save_greetings(&mut self, greeting: EitherGreetingsOrError) {
         match self {
             Self::TcpConnection {
@@ -257,6 +396,12 @@ impl RemoteInformation {
                     io: None,
                 };
             }
+            // the EHLO command is issued again after the TLS upgrade.
+            Self::SmtpEhlo {
+                ehlo: previous @ EitherEhloOrError::Ok(_),
+                io: None,
+                ..
+            } => *previous = ehlo,
             _ => todo!("{self:?}"),
         }
     }
@@ -276,6 +421,7 @@ impl RemoteInformation {
                 ..
             }
             | Self::SmtpTlsUpgrade { ehlo, .. }
+            | Self::SmtpAuth { ehlo, .. }
             | Self::SmtpMailFrom { ehlo, .. }
             | Self::SmtpRcptTo { ehlo, .. }
             | Self::SmtpData { ehlo, .. }
@@ -288,6 +434,12 @@ impl RemoteInformation {
         self.get_ehlo().is_some_and(|r| r.contains(extension))
     }
 
+    /// Get the arguments of an extension advertised by the remote server, if any.
+    #[must_use]
+    pub fn get_extension_arguments(&self, extension: Extension) -> Option<&str> {
+        self.get_ehlo().and_then(|r| r.arguments(extension))
+    }
+
     #[allow(clippy::needless_pass_by_value)]
     pub fn save_tls_upgrade_error(&mut self, error: std::io::Error) {
         match &self {
@@ -311,12 +463,61 @@ impl RemoteInformation {
         }
     }
 
+    /// Save the failure of the authentication, `reply` being the last reply
+    /// of the server if the credentials have been sent.
+    pub fn save_auth_error(&mut self, error: String, reply: Option<Reply>) {
+        match self {
+            Self::SmtpEhlo {
+                mx,
+                target,
+                greeting,
+                ehlo: EitherEhloOrError::Ok(ehlo),
+                io: None,
+            } => {
+                *self = Self::SmtpAuth {
+                    mx: mx.clone(),
+                    target: target.clone(),
+                    greeting: greeting.clone(),
+                    ehlo: ehlo.clone(),
+                    error,
+                    reply,
+                    io: None,
+                }
+            }
+            // only the last failure is kept.
+            Self::SmtpAuth {
+                error: previous_error,
+                reply: previous_reply,
+                io: None,
+                ..
+            } => {
+                *previous_error = error;
+                *previous_reply = reply;
+            }
+            // the credentials are only sent after a successful EHLO.
+            Self::DnsMxLookup { .. }
+            | Self::DnsMxIpLookup { .. }
+            | Self::TcpConnection { .. }
+            | Self::SmtpGreetings { .. }
+            | Self::SmtpEhlo { .. }
+            | Self::SmtpTlsUpgrade { .. }
+            | Self::SmtpAuth { .. }
+            | Self::SmtpMailFrom { .. }
+            | Self::SmtpRcptTo { .. }
+            | Self::SmtpData { .. }
+            | Self::SmtpDataEnd { .. } => {
+                tracing::warn!(state = ?self, %error, "Authentication failure out of sequence, ignored");
+            }
+        }
+    }
+
     pub fn save_io_error(&mut self, error: vsmtp_protocol::Error) {
         match self {
             Self::TcpConnection { io, .. }
             | Self::SmtpGreetings { io, .. }
             | Self::SmtpEhlo { io, .. }
             | Self::SmtpTlsUpgrade { io, .. }
+            | Self::SmtpAuth { io, .. }
             | Self::SmtpMailFrom { io, .. }
             | Self::SmtpRcptTo { io, .. }
             | Self::SmtpData { io, .. }
@@ -482,6 +683,15 @@ impl RemoteInformation {
                 ehlo,
                 error: _,
                 io: _,
+            }
+            | Self::SmtpAuth {
+                mx,
+                target,
+                greeting,
+                ehlo,
+                error: _,
+                reply: _,
+                io: _,
             } => {
                 let pre_transaction_value = Self::SmtpEhlo {
                     mx: mx.clone(),
@@ -533,3 +743,45 @@ impl RemoteInformation {
         }
     }
 }
+
+/// Parse a retry hint such as `retry after 300 seconds`, `try again in 5 minutes`
+/// or `Retry-After: 120` from the text of a reply. A value without unit is in seconds.
+fn parse_retry_hint(reply: &Reply) -> Option<std::time::Duration> {
+    const PATTERNS: [&str; 5] = [
+        "retry-after:",
+        "retry after",
+        "retry in",
+        "try again after",
+        "try again in",
+    ];
+
+    let text = reply
+        .lines()
+        .map(|line| line.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    PATTERNS.iter().find_map(|pattern| {
+        let (_, hint) = text.split_once(pattern)?;
+        let hint = hint.trim_start();
+
+        let digits = hint
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(hint.len());
+        let value = hint[..digits].parse::<u64>().ok()?;
+
+        let unit = hint[digits..]
+            .trim_start()
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .next()
+            .unwrap_or_default();
+        let seconds = match unit {
+            "" | "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hour" | "hours" => 3600,
+            _ => return None,
+        };
+
+        Some(std::time::Duration::from_secs(value.checked_mul(seconds)?))
+    })
+}
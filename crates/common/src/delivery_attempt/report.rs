@@ -0,0 +1,302 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::{Action, DeliveryAttempt, DeliveryType, LocalInformation, Status};
+
+/// Outcome of a delivery attempt, exported to external logging or analytics systems.
+///
+/// Unlike the serialization of [`DeliveryAttempt`] used to move the messages between
+/// the services, this schema is flat and stable:
+///
+/// ```json
+/// {
+///   "timestamp": "2023-11-02T10:21:13Z",
+///   "type": "remote",
+///   "remote": { "mx": "mx.example.com", "mx_priority": 10, "ip_addr": "192.0.2.1:25" },
+///   "tls": { "protocol_version": "TLSv1_3", "cipher_suite": "TLS_AES_256_GCM_SHA384" },
+///   "tls_downgrade": null,
+///   "error": null,
+///   "recipients": [
+///     {
+///       "address": "jenny@example.com",
+///       "action": "delivered",
+///       "status": "2.0.0",
+///       "reply": "250 2.0.0 Ok: queued"
+///     }
+///   ]
+/// }
+/// ```
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+pub struct AttemptReport {
+    /// The time at which the attempt ended, rfc3339 formatted.
+    pub timestamp: Option<String>,
+    /// `remote` for an SMTP delivery, `local` for a mailbox or a command.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The remote server, for the `remote` attempts.
+    pub remote: Option<RemoteReport>,
+    /// The TLS session established with the remote server, if any.
+    pub tls: Option<TlsReport>,
+    /// Why the connection fell back to cleartext, for an opportunistic TLS upgrade.
+    pub tls_downgrade: Option<String>,
+    /// The error which interrupted the attempt, if any.
+    pub error: Option<String>,
+    /// The outcome for each recipient targeted by the attempt.
+    pub recipients: Vec<RecipientReport>,
+}
+
+/// The remote server of an [`AttemptReport`].
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+pub struct RemoteReport {
+    /// The mail exchanger, not set for the direct connections.
+    pub mx: Option<String>,
+    /// The priority of the mail exchanger.
+    pub mx_priority: Option<u16>,
+    /// The address of the server, not set if the lookup failed.
+    pub ip_addr: Option<String>,
+}
+
+/// The TLS session of an [`AttemptReport`].
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+pub struct TlsReport {
+    pub protocol_version: String,
+    pub cipher_suite: String,
+}
+
+/// The outcome of an [`AttemptReport`] for a recipient.
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+pub struct RecipientReport {
+    pub address: String,
+    /// `failed`, `delayed`, `delivered`, `relayed` or `expanded`, see rfc3464.
+    pub action: &'static str,
+    /// The enhanced status code.
+    pub status: Option<String>,
+    /// The reply of the remote server, on a single line.
+    pub reply: Option<String>,
+}
+
+impl Action {
+    const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Failed { .. } => "failed",
+            Self::Delayed { .. } => "delayed",
+            Self::Delivered => "delivered",
+            Self::Relayed => "relayed",
+            Self::Expanded => "expanded",
+        }
+    }
+}
+
+impl DeliveryAttempt {
+    /// Build the [`AttemptReport`] of the attempt.
+    #[must_use]
+    pub fn to_report(&self) -> AttemptReport {
+        let recipients = self
+            .recipients
+            .iter()
+            .enumerate()
+            .map(|(idx, rcpt)| {
+                let (status, reply) = match &self.inner {
+                    DeliveryType::Local(local) => (Some(Status::from(local)), None),
+                    DeliveryType::RemoteSmtp(remote) => (
+                        remote.get_status(idx),
+                        remote.get_reply(idx).map(|reply| {
+                            std::iter::once(reply.code().value().to_string())
+                                .chain(reply.code().details().map(ToString::to_string))
+                                .chain(reply.lines().map(|line| line.trim().to_string()))
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                        }),
+                    ),
+                };
+
+                RecipientReport {
+                    address: rcpt.to_string(),
+                    action: self.get_action(idx).as_str(),
+                    status: status.map(|Status(status)| status),
+                    reply,
+                }
+            })
+            .collect();
+
+        let (kind, remote, error) = match &self.inner {
+            DeliveryType::Local(LocalInformation::Success) => ("local", None, None),
+            DeliveryType::Local(local) => ("local", None, Some(format!("{local:?}"))),
+            DeliveryType::RemoteSmtp(remote) => (
+                "remote",
+                Some(RemoteReport {
+                    mx: remote.get_mx().map(|mx| mx.mx.to_string()),
+                    mx_priority: remote.get_mx().map(|mx| mx.mx_priority),
+                    ip_addr: remote.get_target().map(|target| target.ip_addr.to_string()),
+                }),
+                remote.get_error(),
+            ),
+        };
+
+        AttemptReport {
+            timestamp: self.timestamp.and_then(|timestamp| {
+                timestamp
+                    .format(&time::format_description::well_known::Rfc3339)
+                    .ok()
+            }),
+            kind,
+            remote,
+            tls: self.tls.as_ref().map(|tls| TlsReport {
+                protocol_version: tls.protocol_version.to_string(),
+                cipher_suite: tls.cipher_suite.to_string(),
+            }),
+            tls_downgrade: self.tls_downgrade.clone(),
+            error,
+            recipients,
+        }
+    }
+
+    /// Export the attempt as JSON, following the schema of [`AttemptReport`].
+    #[must_use]
+    pub fn to_report_json(&self) -> serde_json::Value {
+        serde_json::to_value(self.to_report()).expect("the report is serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        delivery_attempt::{
+            DeliveryAttempt, EitherEhloOrError, EitherGreetingsOrError, RemoteInformation,
+            RemoteMailExchange, RemoteServer, ShouldNotify,
+        },
+        tls::TlsProps,
+        Mailbox,
+    };
+    use vsmtp_protocol::Reply;
+
+    fn reply(reply: &str) -> Reply {
+        format!("{reply}\r\n").parse().unwrap()
+    }
+
+    fn attempt(rcpt_to: &[(&str, &str)], data_end: &str) -> DeliveryAttempt {
+        let mut remote = RemoteInformation::TcpConnection {
+            mx: Some(RemoteMailExchange {
+                mx: "mx.example.com".parse().unwrap(),
+                mx_priority: 10,
+            }),
+            target: Ok(RemoteServer {
+                ip_addr: "192.0.2.1:25".parse().unwrap(),
+            }),
+            io: None,
+        };
+        remote.save_greetings(EitherGreetingsOrError::Ok(reply(
+            "220 mx.example.com ESMTP",
+        )));
+        remote.save_ehlo(EitherEhloOrError::Ok(
+            reply("250 mx.example.com").try_into().unwrap(),
+        ));
+        remote.save_mail_from(reply("250 2.1.0 Ok"));
+        for (_, rcpt_reply) in rcpt_to {
+            remote.save_rcpt_to(reply(rcpt_reply));
+        }
+        remote.save_data(reply("354 End data with <CR><LF>.<CR><LF>"));
+        remote.save_data_end(reply(data_end));
+
+        let mut attempt = DeliveryAttempt::new_remote(
+            rcpt_to
+                .iter()
+                .map(|(rcpt, _)| Mailbox(rcpt.parse().unwrap()))
+                .collect(),
+            remote,
+            ShouldNotify::Failure | ShouldNotify::Delay,
+        );
+        attempt.timestamp = Some(time::macros::datetime!(2023-11-02 10:21:13 UTC));
+        attempt
+    }
+
+    #[test]
+    fn remote_success() {
+        let attempt = attempt(
+            &[("jenny@example.com", "250 2.1.5 Ok")],
+            "250 2.0.0 Ok: queued as 4F2A",
+        )
+        .with_tls(TlsProps {
+            protocol_version: "TLSv1.3".parse().unwrap(),
+            cipher_suite: "TLS_AES_256_GCM_SHA384".parse().unwrap(),
+            peer_certificates: None,
+            alpn_protocol: None,
+        });
+
+        assert_eq!(
+            attempt.to_report_json(),
+            serde_json::json!({
+                "timestamp": "2023-11-02T10:21:13Z",
+                "type": "remote",
+                "remote": {
+                    "mx": "mx.example.com",
+                    "mx_priority": 10,
+                    "ip_addr": "192.0.2.1:25",
+                },
+                "tls": {
+                    "protocol_version": "TLSv1_3",
+                    "cipher_suite": "TLS_AES_256_GCM_SHA384",
+                },
+                "tls_downgrade": null,
+                "error": null,
+                "recipients": [
+                    {
+                        "address": "jenny@example.com",
+                        "action": "delivered",
+                        "status": "2.0.0",
+                        "reply": "250 2.0.0 Ok: queued as 4F2A",
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn remote_failure() {
+        let attempt = attempt(
+            &[
+                ("jenny@example.com", "250 2.1.5 Ok"),
+                ("unknown@example.com", "550 5.1.1 No such user"),
+            ],
+            "250 2.0.0 Ok: queued as 4F2A",
+        );
+
+        assert_eq!(
+            attempt.to_report_json(),
+            serde_json::json!({
+                "timestamp": "2023-11-02T10:21:13Z",
+                "type": "remote",
+                "remote": {
+                    "mx": "mx.example.com",
+                    "mx_priority": 10,
+                    "ip_addr": "192.0.2.1:25",
+                },
+                "tls": null,
+                "tls_downgrade": null,
+                "error": null,
+                "recipients": [
+                    {
+                        "address": "jenny@example.com",
+                        "action": "delivered",
+                        "status": "2.0.0",
+                        "reply": "250 2.0.0 Ok: queued as 4F2A",
+                    },
+                    {
+                        "address": "unknown@example.com",
+                        "action": "failed",
+                        "status": "5.1.1",
+                        "reply": "550 5.1.1 No such user",
+                    },
+                ],
+            })
+        );
+    }
+}
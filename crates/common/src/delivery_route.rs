@@ -25,6 +25,8 @@ pub enum DeliveryRoute {
     Maildir,
     // mbox delivery (POP3)
     Mbox,
+    // delivery through a local command (procmail-style)
+    Pipe,
     // delivery to a predefined service over SMTP
     Forward {
         // TODO: must be one word, should not contain dots
@@ -107,7 +109,7 @@ impl std::str::FromStr for DeliveryRoute {
                 service: service.to_string(),
             })
         } else {
-            [Self::Basic, Self::Maildir, Self::Mbox]
+            [Self::Basic, Self::Maildir, Self::Mbox, Self::Pipe]
                 .into_iter()
                 .find_map(|i| (s == Into::<&'static str>::into(i.clone())).then_some(i))
                 .ok_or(DeliveryRouteParseError)
@@ -0,0 +1,89 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+/// Values by recipient domain pattern.
+///
+/// * `example.com` matches the domain only,
+/// * `*.example.com` matches the subdomains of `example.com`, the longest pattern winning,
+/// * `*` matches every domain not matched by another pattern.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub struct DomainMap<T>(std::collections::HashMap<String, T>);
+
+impl<T> Default for DomainMap<T> {
+    fn default() -> Self {
+        Self(std::collections::HashMap::new())
+    }
+}
+
+impl<T> FromIterator<(String, T)> for DomainMap<T> {
+    fn from_iter<I: IntoIterator<Item = (String, T)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<T> DomainMap<T> {
+    /// Add a value for a domain pattern, replacing the previous one.
+    pub fn insert(&mut self, pattern: String, value: T) -> Option<T> {
+        self.0.insert(pattern, value)
+    }
+
+    /// Iterate over the values of all the patterns.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.0.values()
+    }
+
+    /// Get the value of the pattern matching `domain`, if any.
+    #[must_use]
+    pub fn get(&self, domain: &str) -> Option<&T> {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+
+        if let Some(value) = self.0.get(&domain) {
+            return Some(value);
+        }
+
+        self.0
+            .iter()
+            .filter_map(|(pattern, value)| {
+                let suffix = pattern.strip_prefix('*')?;
+                (suffix.is_empty() || (suffix.starts_with('.') && domain.ends_with(suffix)))
+                    .then_some((suffix.len(), value))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DomainMap;
+
+    #[test]
+    fn patterns() {
+        let mut map = [
+            ("example.com", "exact"),
+            ("*.example.com", "subdomain"),
+            ("*.eu.example.com", "eu"),
+        ]
+        .into_iter()
+        .map(|(pattern, value)| (pattern.to_string(), value))
+        .collect::<DomainMap<_>>();
+
+        assert_eq!(map.get("Example.com."), Some(&"exact"));
+        assert_eq!(map.get("mail.example.com"), Some(&"subdomain"));
+        assert_eq!(map.get("paris.eu.example.com"), Some(&"eu"));
+        assert_eq!(map.get("notexample.com"), None);
+
+        map.insert("*".to_string(), "default");
+        assert_eq!(map.get("example.org"), Some(&"default"));
+        assert_eq!(map.get("example.com"), Some(&"exact"));
+    }
+}
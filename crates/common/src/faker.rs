@@ -59,6 +59,7 @@ impl fake::Dummy<fake::Faker> for Recipient {
                 Some(OriginalRecipient {
                     addr_type: "rfc822".to_string(),
                     mailbox: MailboxFaker { domain: None }.fake_with_rng(rng),
+                    xtext: None,
                 })
             } else {
                 None
@@ -135,6 +136,12 @@ impl fake::Dummy<RcptToFaker> for std::collections::HashMap<DeliveryRoute, Vec<R
                 (fake::Faker, 1..10).fake_with_rng::<Vec<Recipient>, _>(rng),
             );
         }
+        if rng.gen_bool(0.5) {
+            map.insert(
+                DeliveryRoute::Pipe,
+                (fake::Faker, 1..10).fake_with_rng::<Vec<Recipient>, _>(rng),
+            );
+        }
         if rng.gen_bool(0.5) {
             let domain_count: usize = (1..15).fake_with_rng(rng);
             for _ in 0..domain_count {
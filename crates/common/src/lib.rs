@@ -9,19 +9,23 @@
  *
  */
 
-pub mod ctx;
 pub mod api;
 pub mod broker;
+pub mod callout;
+pub mod ctx;
 pub mod ctx_delivery;
 pub mod ctx_received;
 pub mod delivery_attempt;
 pub mod delivery_route;
 pub mod dns_resolver;
+pub mod domain_map;
 pub mod extensions;
 pub mod faker;
 pub mod libc;
 pub mod response;
 pub mod stateful_ctx_received;
+pub mod telemetry;
+pub mod timeouts;
 pub mod tls;
 
 pub use hickory_resolver;
@@ -29,7 +33,7 @@ pub use time;
 pub use uuid;
 
 use crate::faker::MailboxFaker;
-use vsmtp_protocol::{Address, Domain, NotifyOn, OriginalRecipient};
+use vsmtp_protocol::{Address, Canonicalization, Domain, NotifyOn, OriginalRecipient};
 
 pub async fn init_logs(
     conn: &lapin::Connection,
@@ -43,8 +47,35 @@ pub async fn init_logs(
 
     let (layer, dispatcher) = tracing_amqp::layer(conn, service_name).await;
 
+    let otlp = match &config.otlp {
+        Some(otlp) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(&otlp.endpoint),
+                )
+                .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+                    opentelemetry_sdk::Resource::new([opentelemetry::KeyValue::new(
+                        "service.name",
+                        service_name.to_owned(),
+                    )]),
+                ))
+                .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+            Some(
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(filter.clone()),
+            )
+        }
+        None => None,
+    };
+
     tracing_subscriber::registry()
         .with(layer.with_filter(filter))
+        .with(otlp)
         .try_init()
         .unwrap();
     tokio::spawn(dispatcher);
@@ -76,6 +107,12 @@ impl Mailbox {
     pub fn domain(&self) -> Domain {
         self.0.domain()
     }
+
+    /// Get the canonical form of the mailbox, see [`Address::canonicalize`].
+    #[must_use]
+    pub fn canonicalize(&self, options: &Canonicalization) -> Self {
+        Self(self.0.canonicalize(options))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
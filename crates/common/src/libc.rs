@@ -59,3 +59,19 @@ pub fn getpwuid(uid: libc::uid_t) -> std::io::Result<std::path::PathBuf> {
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
         .into())
 }
+
+/// Place an exclusive advisory lock on an open file, blocking until it is available.
+/// The lock is released when the file is closed.
+///
+/// # Errors
+///
+/// * see `flock(2)` ERRORS
+#[inline]
+pub fn flock_exclusive(file: &std::fs::File) -> std::io::Result<()> {
+    #[allow(unsafe_code)]
+    // SAFETY: ffi call
+    match unsafe { libc::flock(std::os::fd::AsRawFd::as_raw_fd(file), libc::LOCK_EX) } {
+        0i32 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
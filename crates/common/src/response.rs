@@ -32,6 +32,15 @@ impl Ehlo {
     pub fn contains(&self, extension: Extension) -> bool {
         self.extensions.iter().any(|(e, _)| *e == extension)
     }
+
+    /// Get the arguments of an extension, if advertised.
+    #[must_use]
+    pub fn arguments(&self, extension: Extension) -> Option<&str> {
+        self.extensions
+            .iter()
+            .find(|(e, _)| *e == extension)
+            .map(|(_, args)| args.trim())
+    }
 }
 
 impl TryFrom<Reply> for Ehlo {
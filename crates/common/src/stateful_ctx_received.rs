@@ -19,7 +19,10 @@ use crate::{
 use fake::faker::time::fr_fr::DateTimeBetween;
 use vsmtp_auth::{dkim::DkimVerificationResult, dmarc, iprev::IpRevResult, spf};
 use vsmtp_mail_parser::Mail;
-use vsmtp_protocol::{rustls, ClientName, Domain, DsnReturn, NotifyOn, Stage};
+use vsmtp_protocol::{
+    rustls, ClientName, ConnectionKind, Domain, DsnReturn, MimeBodyType, NotifyOn,
+    OriginalRecipient, Stage,
+};
 
 macro_rules! exactly {
     ($i:expr) => {
@@ -188,6 +191,7 @@ impl StatefulCtxReceived {
         reverse_path: Option<Mailbox>,
         envelop_id: Option<String>,
         ret: Option<DsnReturn>,
+        mime_body_type: Option<MimeBodyType>,
     ) -> Result<&mut Self, StateError> {
         match self {
             Self::Helo { connect, helo } => {
@@ -200,6 +204,7 @@ impl StatefulCtxReceived {
                         message_uuid: uuid::Uuid::new_v4(),
                         envelop_id,
                         ret,
+                        mime_body_type,
                         spf_mail_from_identity: None,
                     },
                 };
@@ -420,11 +425,47 @@ impl StatefulCtxReceived {
             _ => Err(StateError::new(exactly!(Stage::Finished), self.get_stage())),
         }
     }
+
+    /// Convert the context into a rhai map, with one entry per section received
+    /// so far (`connect`, `helo`, `mail_from`, `rcpt_to` and `complete`).
+    ///
+    /// The message body and the SASL credentials are not included.
+    ///
+    /// # Errors
+    ///
+    /// * the context cannot be converted
+    pub fn to_object(&self) -> Result<rhai::Map, Box<rhai::EvalAltResult>> {
+        // the variant name is the only key of the serialized enum
+        let mut sections = match serde_json::to_value(self).map_err(|e| e.to_string())? {
+            serde_json::Value::Object(variant) => variant.into_iter().next(),
+            _ => None,
+        }
+        .and_then(|(_, sections)| match sections {
+            serde_json::Value::Object(sections) => Some(sections),
+            _ => None,
+        })
+        .ok_or("context is not serialized as a struct variant")?;
+
+        sections.remove("mail");
+        if let Some(serde_json::Value::Object(sasl)) = sections
+            .get_mut("connect")
+            .and_then(|connect| connect.get_mut("sasl"))
+        {
+            sasl.remove("credentials");
+        }
+
+        rhai::serde::to_dynamic(sections)?
+            .try_cast::<rhai::Map>()
+            .ok_or_else(|| "context is not convertible to a map".into())
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, fake::Dummy)]
 pub struct SaslAuthProps {
     pub cancel_count: usize,
+    /// Number of AUTH exchanges of the session which failed the verification.
+    #[serde(default)]
+    pub failure_count: usize,
     pub is_authenticated: bool,
     pub mechanism: vsmtp_protocol::auth::Mechanism,
     #[dummy(faker = "CredentialsFaker")]
@@ -441,6 +482,10 @@ pub struct ConnectProps {
     #[dummy(faker = "IpFaker")]
     pub server_addr: std::net::SocketAddr,
     pub server_name: Domain,
+    /// The kind of the listener which accepted the connection.
+    #[serde(default)]
+    #[dummy(expr = "ConnectionKind::Relay")]
+    pub kind: ConnectionKind,
     pub sasl: Option<SaslAuthProps>,
     pub iprev: Option<IpRevResult>,
     /// This field is `Some` when the client and server
@@ -467,7 +512,6 @@ pub struct MailFromProps {
     // TODO:
     // * rfc 4954 : "AUTH="
     // * rfc 1870 : "SIZE="
-    // * rfc 6152 : "BODY="
     // * rfc 6531 : "SMTPUTF8"
     // * rfc 3885 : "MTRK"
     // * rfc 4865 : "FUTURERELEASE"
@@ -484,6 +528,9 @@ pub struct MailFromProps {
     pub envelop_id: Option<String>,
     pub spf_mail_from_identity: Option<std::sync::Arc<spf::Result>>,
     pub ret: Option<DsnReturn>,
+    /// rfc 6152 : the `BODY=` parameter of the `MAIL FROM` command.
+    #[serde(default)]
+    pub mime_body_type: Option<MimeBodyType>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, fake::Dummy)]
@@ -498,6 +545,15 @@ impl RcptToProps {
         self.recipient.values().flatten()
     }
 
+    /// Get an iterator over the recipients with their delivery route.
+    pub fn recipient_values_with_route(
+        &self,
+    ) -> impl Iterator<Item = (&DeliveryRoute, &Recipient)> {
+        self.recipient
+            .iter()
+            .flat_map(|(route, recipients)| recipients.iter().map(move |rcpt| (route, rcpt)))
+    }
+
     /// Get a mutable iterator over the recipients without the delivery route.
     pub fn recipient_values_mut(&mut self) -> impl Iterator<Item = &mut Recipient> {
         self.recipient.values_mut().flatten()
@@ -529,6 +585,56 @@ impl RcptToProps {
         });
     }
 
+    /// Replace the recipient `alias` by its `members`, on the same delivery route and with
+    /// the same notification settings. The members already in the list are skipped.
+    ///
+    /// The original recipient (`ORCPT`) of the alias is preserved, or set to the alias address
+    /// if the client did not send one, so that the DSN report the address used by the sender.
+    pub fn expand_recipient(
+        &mut self,
+        alias: &Mailbox,
+        members: impl IntoIterator<Item = Mailbox>,
+    ) {
+        let Some((route, idx)) = self.recipient.iter().find_map(|(route, recipients)| {
+            recipients
+                .iter()
+                .position(|r| r.forward_path == *alias)
+                .map(|idx| (route.clone(), idx))
+        }) else {
+            return;
+        };
+
+        let expanded = {
+            let alias = &self.recipient[&route][idx];
+            let original_forward_path = alias.original_forward_path.clone().or_else(|| {
+                Some(OriginalRecipient {
+                    addr_type: "rfc822".to_string(),
+                    mailbox: alias.forward_path.0.clone(),
+                    xtext: None,
+                })
+            });
+
+            let mut expanded = Vec::<Recipient>::new();
+            for member in members {
+                if self.recipient_values().any(|r| r.forward_path == member)
+                    || expanded.iter().any(|r| r.forward_path == member)
+                {
+                    continue;
+                }
+                expanded.push(Recipient {
+                    forward_path: member,
+                    original_forward_path: original_forward_path.clone(),
+                    notify_on: alias.notify_on.clone(),
+                });
+            }
+            expanded
+        };
+
+        if let Some(recipients) = self.recipient.get_mut(&route) {
+            recipients.splice(idx..=idx, expanded);
+        }
+    }
+
     /// Replace a recipient address by another. The notification settings stay unchanged.
     pub fn rewrite_recipient(&mut self, old_addr: &Mailbox, new_addr: Mailbox) {
         if let Some(r) = self
@@ -545,3 +651,62 @@ pub struct CompleteProps {
     pub dkim: Option<std::sync::Arc<Vec<DkimVerificationResult>>>,
     pub dmarc: Option<std::sync::Arc<dmarc::Result>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::StatefulCtxReceived;
+    use crate::ctx_received::CtxReceived;
+
+    fn keys(object: &rhai::Map, section: &str) -> Vec<String> {
+        let mut keys = object[section]
+            .read_lock::<rhai::Map>()
+            .unwrap()
+            .keys()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn to_object() {
+        let received = CtxReceived::fake();
+        let rcpt_to = StatefulCtxReceived::RcptTo {
+            connect: received.connect.clone(),
+            helo: received.helo.clone(),
+            mail_from: received.mail_from.clone(),
+            rcpt_to: received.rcpt_to.clone(),
+        }
+        .to_object()
+        .unwrap();
+
+        let mut sections = rcpt_to.keys().map(ToString::to_string).collect::<Vec<_>>();
+        sections.sort();
+        assert_eq!(sections, ["connect", "helo", "mail_from", "rcpt_to"]);
+
+        for key in [
+            "client_addr",
+            "connect_timestamp",
+            "connect_uuid",
+            "server_name",
+        ] {
+            assert!(keys(&rcpt_to, "connect").contains(&key.to_string()));
+        }
+        assert_eq!(
+            keys(&rcpt_to, "helo"),
+            ["client_name", "spf_helo_identity", "using_deprecated"]
+        );
+        for key in ["mail_timestamp", "message_uuid", "reverse_path"] {
+            assert!(keys(&rcpt_to, "mail_from").contains(&key.to_string()));
+        }
+        assert_eq!(keys(&rcpt_to, "rcpt_to"), ["recipient"]);
+        assert_eq!(
+            rcpt_to["mail_from"].read_lock::<rhai::Map>().unwrap()["message_uuid"].to_string(),
+            received.mail_from.message_uuid.to_string()
+        );
+
+        let complete = StatefulCtxReceived::Complete(received).to_object().unwrap();
+        assert!(complete.contains_key("complete"));
+        assert!(!complete.contains_key("mail"));
+    }
+}
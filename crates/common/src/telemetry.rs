@@ -0,0 +1,138 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::ctx::Ctx;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Key of the trace context in [`Ctx::internal`], in the W3C `traceparent` format.
+///
+/// See <https://www.w3.org/TR/trace-context/#traceparent-header>
+const TRACEPARENT: &str = "traceparent";
+
+impl<T> Ctx<T> {
+    /// The span context of the last service which exported the span of this message, if any.
+    #[must_use]
+    pub fn trace_parent(&self) -> Option<String> {
+        self.internal
+            .get(TRACEPARENT)
+            .and_then(|value| value.clone().into_string().ok())
+    }
+
+    pub fn set_trace_parent(&mut self, trace_parent: String) {
+        self.internal
+            .insert(TRACEPARENT.to_string(), trace_parent.into());
+    }
+}
+
+/// Create the span handling the message `uuid` in a service.
+///
+/// The span is attached to the span context carried by `ctx`, which is then replaced by
+/// the context of the new span, so that the next service continues the same trace.
+/// If the spans of this service are not exported, the context is forwarded unchanged
+/// so that the trace is not linked to a span which does not exist.
+pub fn message_span<T>(ctx: &mut Ctx<T>, uuid: &uuid::Uuid) -> tracing::Span {
+    let span = tracing::info_span!("message", %uuid);
+    let propagator = TraceContextPropagator::new();
+
+    if let Some(trace_parent) = ctx.trace_parent() {
+        span.set_parent(propagator.extract(&std::collections::HashMap::from([(
+            TRACEPARENT.to_string(),
+            trace_parent,
+        )])));
+    }
+
+    let mut carrier = std::collections::HashMap::new();
+    propagator.inject_context(&span.context(), &mut carrier);
+    if let Some(trace_parent) = carrier.remove(TRACEPARENT) {
+        ctx.set_trace_parent(trace_parent);
+    }
+
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::message_span;
+    use crate::{ctx::Ctx, ctx_received::CtxReceived, stateful_ctx_received::StatefulCtxReceived};
+    use opentelemetry::trace::{TraceContextExt, TracerProvider};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn ctx() -> (Ctx<StatefulCtxReceived>, uuid::Uuid) {
+        let received = CtxReceived::fake();
+        let uuid = received.mail_from.message_uuid;
+        (
+            Ctx {
+                variables: std::collections::HashMap::default(),
+                internal: std::collections::HashMap::default(),
+                metadata: StatefulCtxReceived::Complete(received),
+            },
+            uuid,
+        )
+    }
+
+    fn exported<R>(f: impl FnOnce() -> R) -> R {
+        let tracer = opentelemetry_sdk::trace::TracerProvider::builder()
+            .build()
+            .tracer("test");
+        tracing::subscriber::with_default(
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer)),
+            f,
+        )
+    }
+
+    #[test]
+    fn receiver_to_working() {
+        let (mut ctx, uuid) = ctx();
+        assert_eq!(ctx.trace_parent(), None);
+
+        // receiver
+        let receiver = exported(|| {
+            let span = message_span(&mut ctx, &uuid);
+            span.context().span().span_context().clone()
+        });
+        let sent = ctx.trace_parent().unwrap();
+        assert_eq!(
+            sent,
+            format!("00-{}-{}-01", receiver.trace_id(), receiver.span_id())
+        );
+
+        // working
+        let mut ctx = Ctx::<StatefulCtxReceived>::from_json(&ctx.to_json().unwrap()).unwrap();
+        assert_eq!(ctx.trace_parent().as_ref(), Some(&sent));
+
+        let working = exported(|| {
+            let span = message_span(&mut ctx, &uuid);
+            span.context().span().span_context().clone()
+        });
+        assert_eq!(working.trace_id(), receiver.trace_id());
+        assert_ne!(working.span_id(), receiver.span_id());
+        assert_eq!(
+            ctx.trace_parent().unwrap(),
+            format!("00-{}-{}-01", working.trace_id(), working.span_id())
+        );
+    }
+
+    #[test]
+    fn forwarded_if_not_exported() {
+        let (mut ctx, uuid) = ctx();
+
+        let _span = message_span(&mut ctx, &uuid);
+        assert_eq!(ctx.trace_parent(), None);
+
+        let sent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string();
+        ctx.set_trace_parent(sent.clone());
+        let _span = message_span(&mut ctx, &uuid);
+        assert_eq!(ctx.trace_parent(), Some(sent));
+    }
+}
@@ -0,0 +1,58 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+/// Maximum durations of the steps of an outgoing SMTP transaction.
+///
+/// Once one of them is reached, the attempt is aborted and the recipients are delayed.
+/// The defaults follow the recommendations of RFC 5321 section 4.5.3.2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Timeouts {
+    /// Maximum duration to establish the TCP connection.
+    #[serde(default = "default_connect", with = "humantime_serde")]
+    pub connect: std::time::Duration,
+    /// Maximum duration to wait for the greetings of the remote server.
+    #[serde(default = "default_greeting", with = "humantime_serde")]
+    pub greeting: std::time::Duration,
+    /// Maximum duration to send a command and receive its reply.
+    #[serde(default = "default_command", with = "humantime_serde")]
+    pub command: std::time::Duration,
+    /// Maximum duration to send the message and receive the reply of the final dot.
+    #[serde(default = "default_data", with = "humantime_serde")]
+    pub data: std::time::Duration,
+}
+
+const fn default_connect() -> std::time::Duration {
+    std::time::Duration::from_secs(30)
+}
+
+const fn default_greeting() -> std::time::Duration {
+    std::time::Duration::from_secs(5 * 60)
+}
+
+const fn default_command() -> std::time::Duration {
+    std::time::Duration::from_secs(5 * 60)
+}
+
+const fn default_data() -> std::time::Duration {
+    std::time::Duration::from_secs(10 * 60)
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: default_connect(),
+            greeting: default_greeting(),
+            command: default_command(),
+            data: default_data(),
+        }
+    }
+}
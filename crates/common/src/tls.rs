@@ -10,14 +10,18 @@
  */
 
 mod cert_resolver;
+mod certificate;
 mod cipher_suite;
+mod client_auth;
 pub mod error;
 mod logger;
 pub mod protocol_version;
 pub mod secret;
 
 pub use cert_resolver::CertResolver;
+pub use certificate::CertificateIdentity;
 pub use cipher_suite::CipherSuite;
+pub use client_auth::ClientAuth;
 pub use protocol_version::ProtocolVersion;
 
 use self::secret::Secret;
@@ -38,6 +42,9 @@ pub struct TlsProps {
     /// Cipher suite used for the transaction.
     pub cipher_suite: CipherSuite,
     /// Certificate chain used by the peer to authenticate.
+    ///
+    /// On the receiver, only sent by the clients if [`ClientAuth`] is configured,
+    /// and verified against its authorities during the handshake.
     #[serde(
         serialize_with = "serde_with::As::<Option<Vec<serde_with::base64::Base64>>>::serialize",
         deserialize_with = "TlsProps::deserialize"
@@ -69,6 +76,31 @@ impl TlsProps {
             })
             .transpose()
     }
+
+    /// Does the session use at least the `min_version` of TLS, and one of the `allowed_ciphers`?
+    /// Any cipher suite is allowed if `allowed_ciphers` is empty.
+    #[must_use]
+    pub fn meets_policy(
+        &self,
+        min_version: &ProtocolVersion,
+        allowed_ciphers: &[CipherSuite],
+    ) -> bool {
+        self.protocol_version.0.get_u16() >= min_version.0.get_u16()
+            && (allowed_ciphers.is_empty() || allowed_ciphers.contains(&self.cipher_suite))
+    }
+
+    /// Identity of the peer read from the first certificate of its chain,
+    /// `None` if the peer did not send a certificate.
+    ///
+    /// # Errors
+    ///
+    /// * The certificate cannot be parsed.
+    pub fn peer_identity(&self) -> Option<Result<CertificateIdentity, Error>> {
+        self.peer_certificates
+            .as_ref()
+            .and_then(|certificates| certificates.first())
+            .map(CertificateIdentity::try_from)
+    }
 }
 
 impl<T> fake::Dummy<T> for TlsProps {
@@ -85,6 +117,7 @@ pub fn get_rustls_config(
     hostname: &str,
     root: Option<&Secret>,
     r#virtual: &std::collections::BTreeMap<Domain, Secret>,
+    client_auth: Option<&ClientAuth>,
 ) -> Result<rustls::ServerConfig, Error> {
     let protocol_version = match (
         protocol_version
@@ -115,11 +148,16 @@ pub fn get_rustls_config(
         cert_resolver.add(&domain.to_string(), secret.to_rustls()?)?;
     }
 
+    let client_cert_verifier = client_auth.map_or_else(
+        || Ok(rustls::server::NoClientAuth::boxed()),
+        ClientAuth::verifier,
+    )?;
+
     let mut tls_config = rustls::ServerConfig::builder()
         .with_cipher_suites(&to_supported_cipher_suite(cipher_suite))
         .with_kx_groups(&rustls::ALL_KX_GROUPS)
         .with_protocol_versions(protocol_version)?
-        .with_client_cert_verifier(rustls::server::NoClientAuth::boxed())
+        .with_client_cert_verifier(client_cert_verifier)
         .with_cert_resolver(std::sync::Arc::new(cert_resolver::CertResolver {
             sni_resolver: cert_resolver,
             hostname: hostname.to_string(),
@@ -143,3 +181,42 @@ fn to_supported_cipher_suite(cipher_suite: &[CipherSuite]) -> Vec<rustls::Suppor
         .copied()
         .collect::<Vec<_>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CipherSuite, ProtocolVersion, TlsProps};
+
+    fn session(protocol_version: &str, cipher_suite: &str) -> TlsProps {
+        TlsProps {
+            protocol_version: protocol_version.parse().unwrap(),
+            cipher_suite: cipher_suite.parse().unwrap(),
+            peer_certificates: None,
+            alpn_protocol: None,
+        }
+    }
+
+    #[test]
+    fn tls13_minimum_policy() {
+        let tls13 = "TLSv1.3".parse::<ProtocolVersion>().unwrap();
+
+        assert!(session("TLSv1.3", "TLS_AES_256_GCM_SHA384").meets_policy(&tls13, &[]));
+        assert!(!session("TLSv1.2", "ECDHE_RSA_WITH_AES_256_GCM_SHA384").meets_policy(&tls13, &[]));
+        assert!(session("TLSv1.3", "TLS_AES_256_GCM_SHA384")
+            .meets_policy(&"TLSv1.2".parse().unwrap(), &[]));
+    }
+
+    #[test]
+    fn allowed_ciphers() {
+        let tls12 = "TLSv1.2".parse::<ProtocolVersion>().unwrap();
+        let allowed = [
+            "TLS_AES_256_GCM_SHA384",
+            "ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        ]
+        .map(|cipher| cipher.parse::<CipherSuite>().unwrap());
+
+        assert!(
+            session("TLSv1.2", "ECDHE_RSA_WITH_AES_256_GCM_SHA384").meets_policy(&tls12, &allowed)
+        );
+        assert!(!session("TLSv1.3", "TLS_CHACHA20_POLY1305_SHA256").meets_policy(&tls12, &allowed));
+    }
+}
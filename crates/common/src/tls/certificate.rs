@@ -0,0 +1,69 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::error::Error;
+use vsmtp_protocol::rustls;
+use x509_parser::extensions::GeneralName;
+
+/// Identity of a peer, read from its X.509 certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateIdentity {
+    /// Distinguished name of the subject, ex: `O=Example, CN=john.doe`.
+    pub subject: String,
+    /// Common name (CN) of the subject, if any.
+    pub common_name: Option<String>,
+    /// Distinguished name of the issuer.
+    pub issuer: String,
+    /// Email addresses of the subject alternative name extension.
+    pub emails: Vec<String>,
+    /// Domain names of the subject alternative name extension.
+    pub dns_names: Vec<String>,
+}
+
+impl TryFrom<&rustls::Certificate> for CertificateIdentity {
+    type Error = Error;
+
+    fn try_from(certificate: &rustls::Certificate) -> Result<Self, Self::Error> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(&certificate.0)
+            .map_err(|error| Error::ParseCertificate(error.to_string()))?;
+
+        let common_name = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .map(|common_name| common_name.as_str().map(str::to_string))
+            .transpose()
+            .map_err(|error| Error::ParseCertificate(error.to_string()))?;
+
+        let mut emails = vec![];
+        let mut dns_names = vec![];
+        if let Some(san) = certificate
+            .subject_alternative_name()
+            .map_err(|error| Error::ParseCertificate(error.to_string()))?
+        {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::RFC822Name(email) => emails.push((*email).to_string()),
+                    GeneralName::DNSName(domain) => dns_names.push((*domain).to_string()),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self {
+            subject: certificate.subject().to_string(),
+            common_name,
+            issuer: certificate.issuer().to_string(),
+            emails,
+            dns_names,
+        })
+    }
+}
@@ -0,0 +1,50 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::error::Error;
+use vsmtp_auth::TlsCertificate;
+use vsmtp_protocol::rustls;
+
+/// Authentication of the clients with their certificate during the TLS handshake.
+///
+/// A client sending a certificate which is not issued by one of the `authorities`
+/// fails the handshake, so that only verified certificates reach the rules.
+#[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAuth {
+    /// Certificates of the authorities issuing the client certificates.
+    pub authorities: TlsCertificate,
+    /// Fail the handshake of the clients not sending a certificate.
+    #[serde(default)]
+    pub required: bool,
+}
+
+impl ClientAuth {
+    /// Build the verifier of the client certificates.
+    ///
+    /// # Errors
+    ///
+    /// * An authority is not a valid trust anchor.
+    pub fn verifier(
+        &self,
+    ) -> Result<std::sync::Arc<dyn rustls::server::ClientCertVerifier>, Error> {
+        let mut roots = rustls::RootCertStore::empty();
+        for authority in self.authorities.certs() {
+            roots.add(authority)?;
+        }
+
+        Ok(if self.required {
+            rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed()
+        } else {
+            rustls::server::AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+        })
+    }
+}
@@ -39,4 +39,6 @@ pub enum Error {
     UnsupportedPrivateKey(String),
     #[error("private key path is valid but the private key is empty")]
     EmptyPrivateKey,
+    #[error("Failed to parse certificate: {0}")]
+    ParseCertificate(String),
 }
@@ -10,18 +10,45 @@
  */
 use vsmtp_auth::TlsCertificate;
 
-#[derive(Default, Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct Broker {
     // FIXME: Which default should be used ?
     /// AMQP endpoint.
     pub uri: Box<str>,
     pub extra_root_ca: Option<std::sync::Arc<TlsCertificate>>,
+    /// Number of messages a consumer can hold without acknowledging them,
+    /// i.e. the number of messages processed concurrently by a service.
+    #[serde(default = "default_prefetch_count")]
+    pub prefetch_count: u16,
+    /// Prefetch count of the consumers of the high priority delivery queues.
+    #[serde(default = "default_prefetch_count")]
+    pub priority_prefetch_count: u16,
+}
+
+const fn default_prefetch_count() -> u16 {
+    1
+}
+
+impl Default for Broker {
+    fn default() -> Self {
+        Self {
+            uri: Box::default(),
+            extra_root_ca: None,
+            prefetch_count: default_prefetch_count(),
+            priority_prefetch_count: default_prefetch_count(),
+        }
+    }
 }
 
 impl Broker {
     pub async fn connect(&self) -> Result<lapin::Connection, lapin::Error> {
-        let Self { uri, extra_root_ca } = self;
+        let Self {
+            uri,
+            extra_root_ca,
+            prefetch_count: _,
+            priority_prefetch_count: _,
+        } = self;
 
         lapin::Connection::connect_with_config(
             uri,
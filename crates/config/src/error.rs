@@ -19,6 +19,10 @@ pub enum ConfigError {
     Deserialize(#[from] serde_path_to_error::Error<serde_json::Error>),
     #[error("failed to open script for configuration at `{0}`: {1}")]
     FileOpen(std::path::PathBuf, std::io::Error),
+    #[error("module `{0}` imported by the configuration does not exist")]
+    ModuleNotFound(std::path::PathBuf),
+    #[error("failed to load module `{0}` imported by the configuration: `{1}`")]
+    Module(std::path::PathBuf, Box<rhai::EvalAltResult>),
     #[error("failed to compile a rhai script for configuration: `{0}`")]
     Compilation(#[from] rhai::ParseError),
     #[error("failed to execute a rhai script for configuration: `{0}`")]
@@ -21,6 +21,60 @@ pub use semver;
 /// Result type for configuration operations.
 pub type ConfigResult<T> = Result<T, error::ConfigError>;
 
+/// Resolve the modules imported by a configuration script from the configuration
+/// directory, including the modules imported by other modules.
+struct ConfigModuleResolver(rhai::module_resolvers::FileModuleResolver);
+
+impl rhai::ModuleResolver for ConfigModuleResolver {
+    fn resolve(
+        &self,
+        engine: &rhai::Engine,
+        _source: Option<&str>,
+        path: &str,
+        pos: rhai::Position,
+    ) -> Result<rhai::Shared<rhai::Module>, Box<rhai::EvalAltResult>> {
+        self.0.resolve(engine, None, path, pos)
+    }
+
+    fn resolve_ast(
+        &self,
+        engine: &rhai::Engine,
+        _source: Option<&str>,
+        path: &str,
+        pos: rhai::Position,
+    ) -> Option<Result<rhai::AST, Box<rhai::EvalAltResult>>> {
+        self.0.resolve_ast(engine, None, path, pos)
+    }
+}
+
+/// Report the file of the module which could not be imported by the configuration.
+fn module_error(
+    error: Box<rhai::EvalAltResult>,
+    resolve_path: &std::path::Path,
+) -> error::ConfigError {
+    let module_path = |module: &str| resolve_path.join(format!("{module}.rhai"));
+
+    match *error {
+        rhai::EvalAltResult::ErrorModuleNotFound(module, _) => {
+            error::ConfigError::ModuleNotFound(module_path(&module))
+        }
+        // the error is reported for the innermost module.
+        rhai::EvalAltResult::ErrorInModule(_, error, _)
+            if matches!(
+                *error,
+                rhai::EvalAltResult::ErrorInModule(..)
+                    | rhai::EvalAltResult::ErrorModuleNotFound(..)
+            ) =>
+        {
+            module_error(error, resolve_path)
+        }
+        rhai::EvalAltResult::ErrorInModule(module, error, _) => {
+            error::ConfigError::Module(module_path(&module), error)
+        }
+        otherwise => Box::new(otherwise).into(),
+    }
+}
+
 /// Getters for base configuration structures.
 pub trait Config: Default + serde::Serialize + serde::de::DeserializeOwned + Sized {
     /// Create a default configuration with the path of the script passed
@@ -67,12 +121,12 @@ pub trait Config: Default + serde::Serialize + serde::de::DeserializeOwned + Siz
         let mut engine = rhai::Engine::new();
 
         if let Some(resolve_path) = resolve_path.as_ref() {
-            engine.set_module_resolver(
+            engine.set_module_resolver(ConfigModuleResolver(
                 rhai::module_resolvers::FileModuleResolver::new_with_path_and_extension(
                     resolve_path,
                     "rhai",
                 ),
-            );
+            ));
         }
 
         for (name, module) in [
@@ -84,7 +138,12 @@ pub trait Config: Default + serde::Serialize + serde::de::DeserializeOwned + Siz
             engine.register_static_module(name, module);
         }
 
-        let ast = engine.compile_into_self_contained(&rhai::Scope::new(), script)?;
+        let ast = engine
+            .compile_into_self_contained(&rhai::Scope::new(), script)
+            .map_err(|error| match resolve_path {
+                Some(resolve_path) => module_error(error, resolve_path),
+                None => error.into(),
+            })?;
 
         let mut cfg = Self::default();
         cfg.with_path(path);
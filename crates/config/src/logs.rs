@@ -24,6 +24,24 @@ pub struct Logs {
     #[serde(default)]
     #[serde_as(as = "serde_with::Map<serde_with::Same, serde_with::DisplayFromStr>")]
     pub levels: Vec<(String, TracingLevelFilter)>,
+    /// Export the spans to an OpenTelemetry collector, disabled if `None`.
+    #[serde(default)]
+    pub otlp: Option<Otlp>,
+}
+
+/// OpenTelemetry export of the spans, using the OTLP protocol over gRPC.
+#[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Otlp {
+    /// Address of the collector.
+    #[serde(default = "Otlp::default_endpoint")]
+    pub endpoint: String,
+}
+
+impl Otlp {
+    fn default_endpoint() -> String {
+        "http://localhost:4317".to_string()
+    }
 }
 
 impl Logs {
@@ -37,6 +55,7 @@ impl Default for Logs {
         Self {
             default_level: Self::default_log_level(),
             levels: Vec::default(),
+            otlp: None,
         }
     }
 }
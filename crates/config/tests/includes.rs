@@ -0,0 +1,77 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_config::{broker, logs, semver, Config, ConfigError};
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct MyConfig {
+    pub name: String,
+    pub server: Server,
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Server {
+    pub port: u16,
+    pub max_clients: usize,
+}
+
+impl Config for MyConfig {
+    fn api_version(&self) -> &semver::VersionReq {
+        unimplemented!()
+    }
+
+    fn broker(&self) -> &broker::Broker {
+        unimplemented!()
+    }
+
+    fn logs(&self) -> &logs::Logs {
+        unimplemented!()
+    }
+
+    fn path(&self) -> &std::path::Path {
+        unimplemented!()
+    }
+}
+
+fn script(name: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from_iter([env!("CARGO_MANIFEST_DIR"), "tests/scripts/includes", name])
+}
+
+#[test]
+fn imported_settings() {
+    let config = MyConfig::from_rhai_file(&script("config.rhai")).unwrap();
+
+    assert_eq!(config.name, "mx.example.com");
+    assert_eq!(config.server.port, 25);
+    assert_eq!(config.server.max_clients, 8);
+}
+
+#[test]
+fn missing_import() {
+    match MyConfig::from_rhai_file(&script("missing.rhai")) {
+        Err(ConfigError::ModuleNotFound(path)) => {
+            assert_eq!(path, script("settings/missing.rhai"));
+        }
+        Err(otherwise) => panic!("unexpected error: {otherwise}"),
+        Ok(_) => panic!("the configuration should not load"),
+    }
+}
+
+#[test]
+fn error_in_import() {
+    match MyConfig::from_rhai_file(&script("broken.rhai")) {
+        Err(ConfigError::Module(path, _)) => {
+            assert_eq!(path, script("settings/broken.rhai"));
+        }
+        Err(otherwise) => panic!("unexpected error: {otherwise}"),
+        Ok(_) => panic!("the configuration should not load"),
+    }
+}
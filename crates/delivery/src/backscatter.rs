@@ -0,0 +1,244 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_auth::spf;
+use vsmtp_common::{
+    dns_resolver::DnsResolver, hickory_resolver, stateful_ctx_received::MailFromProps,
+};
+
+/// Lookup of the records proving that a sender domain exists.
+#[async_trait::async_trait]
+pub trait SenderLookup: Send + Sync {
+    /// Does the domain have a MX, A or AAAA record?
+    ///
+    /// The lookup errors which are not a proof of the absence of the domain
+    /// (timeout, SERVFAIL, ...) must be considered as existing.
+    async fn domain_exists(&self, domain: &str) -> bool;
+}
+
+#[async_trait::async_trait]
+impl SenderLookup for DnsResolver {
+    async fn domain_exists(&self, domain: &str) -> bool {
+        let is_not_found = |error: &hickory_resolver::error::ResolveError| {
+            matches!(
+                error.kind(),
+                hickory_resolver::error::ResolveErrorKind::NoRecordsFound { .. }
+            )
+        };
+
+        match self.resolver.mx_lookup(domain).await {
+            Ok(_) => true,
+            Err(error) if is_not_found(&error) => match self.resolver.lookup_ip(domain).await {
+                Ok(_) => true,
+                Err(error) => !is_not_found(&error),
+            },
+            Err(_) => true,
+        }
+    }
+}
+
+/// Why the sender of a message looks forged.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Forged {
+    #[error("the sender domain '{0}' does not exist")]
+    UnknownDomain(String),
+    #[error("the reverse path is not aligned with a SPF pass")]
+    SpfNotAligned,
+}
+
+/// Suppression of the DSN sent to forged senders (backscatter).
+///
+/// The domain of the reverse path must have a MX, A or AAAA record, and optionally
+/// the SPF of the reverse path must pass for the same domain.
+/// The domain lookups are cached for `ttl`, so a burst of bounces
+/// to the same domain produces a single lookup.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BounceGuard {
+    /// Require a SPF `pass` for the domain of the reverse path.
+    /// The DSN are suppressed if SPF has not been evaluated during the transaction.
+    #[serde(default)]
+    spf_alignment: bool,
+    #[serde(default = "BounceGuard::default_ttl", with = "humantime_serde")]
+    ttl: std::time::Duration,
+    #[serde(skip)]
+    cache: std::sync::Mutex<std::collections::HashMap<String, (std::time::Instant, bool)>>,
+}
+
+impl Default for BounceGuard {
+    fn default() -> Self {
+        Self::new(false, Self::default_ttl())
+    }
+}
+
+impl BounceGuard {
+    const fn default_ttl() -> std::time::Duration {
+        std::time::Duration::from_secs(60 * 10)
+    }
+
+    #[must_use]
+    pub fn new(spf_alignment: bool, ttl: std::time::Duration) -> Self {
+        Self {
+            spf_alignment,
+            ttl,
+            cache: std::sync::Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+
+    async fn domain_exists(&self, lookup: &dyn SenderLookup, domain: &str) -> bool {
+        let key = domain.to_lowercase();
+        let cached = self
+            .cache
+            .lock()
+            .expect("bounce guard cache poisoned")
+            .get(&key)
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, exists)| *exists);
+        if let Some(exists) = cached {
+            return exists;
+        }
+
+        let exists = lookup.domain_exists(domain).await;
+        self.cache
+            .lock()
+            .expect("bounce guard cache poisoned")
+            .insert(key, (std::time::Instant::now(), exists));
+        exists
+    }
+
+    /// Check the sender of a message before emitting a DSN.
+    /// A message with a null reverse path is not checked.
+    ///
+    /// # Errors
+    ///
+    /// * the sender looks forged, and the DSN must be suppressed.
+    ///
+    /// # Panics
+    ///
+    /// * the cache lock is poisoned.
+    pub async fn check(
+        &self,
+        lookup: &dyn SenderLookup,
+        mail_from: &MailFromProps,
+    ) -> Result<(), Forged> {
+        let Some(reverse_path) = &mail_from.reverse_path else {
+            return Ok(());
+        };
+        let domain = reverse_path.domain().to_string();
+
+        if !self.domain_exists(lookup, &domain).await {
+            return Err(Forged::UnknownDomain(domain));
+        }
+
+        if self.spf_alignment
+            && !mail_from
+                .spf_mail_from_identity
+                .as_deref()
+                .is_some_and(|spf| {
+                    spf.value == spf::Value::Pass
+                        && spf
+                            .domain
+                            .as_deref()
+                            .is_some_and(|spf_domain| spf_domain.eq_ignore_ascii_case(&domain))
+                })
+        {
+            return Err(Forged::SpfNotAligned);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BounceGuard, Forged, SenderLookup};
+    use vsmtp_auth::spf;
+    use vsmtp_common::{stateful_ctx_received::MailFromProps, uuid, Mailbox};
+
+    /// Count the lookups, only `example.com` exists.
+    #[derive(Default)]
+    struct Lookup(std::sync::atomic::AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl SenderLookup for Lookup {
+        async fn domain_exists(&self, domain: &str) -> bool {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            domain == "example.com"
+        }
+    }
+
+    fn mail_from(reverse_path: &str, spf: Option<spf::Value>) -> MailFromProps {
+        MailFromProps {
+            reverse_path: Some(Mailbox(reverse_path.parse().unwrap())),
+            mail_timestamp: vsmtp_common::time::OffsetDateTime::now_utc(),
+            message_uuid: uuid::Uuid::new_v4(),
+            envelop_id: None,
+            spf_mail_from_identity: spf.map(|value| {
+                std::sync::Arc::new(spf::Result {
+                    value,
+                    domain: Some("example.com".to_string()),
+                })
+            }),
+            ret: None,
+            mime_body_type: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_domain() {
+        let guard = BounceGuard::default();
+        let lookup = Lookup::default();
+
+        assert_eq!(
+            guard
+                .check(&lookup, &mail_from("john.doe@forged.example", None))
+                .await,
+            Err(Forged::UnknownDomain("forged.example".to_string()))
+        );
+        assert_eq!(
+            guard
+                .check(&lookup, &mail_from("john.doe@example.com", None))
+                .await,
+            Ok(())
+        );
+
+        // the verdicts are cached.
+        guard
+            .check(&lookup, &mail_from("jane.doe@forged.example", None))
+            .await
+            .unwrap_err();
+        assert_eq!(lookup.0.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn spf_alignment() {
+        let guard = BounceGuard::new(true, BounceGuard::default_ttl());
+        let lookup = Lookup::default();
+
+        assert_eq!(
+            guard
+                .check(
+                    &lookup,
+                    &mail_from("john.doe@example.com", Some(spf::Value::Pass))
+                )
+                .await,
+            Ok(())
+        );
+        for spf in [None, Some(spf::Value::SoftFail)] {
+            assert_eq!(
+                guard
+                    .check(&lookup, &mail_from("john.doe@example.com", spf))
+                    .await,
+                Err(Forged::SpfNotAligned)
+            );
+        }
+    }
+}
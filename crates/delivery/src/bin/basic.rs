@@ -19,15 +19,23 @@ use vsmtp_common::{
     Recipient,
 };
 use vsmtp_config::Config;
-use vsmtp_delivery::{delivery_main, send, DeliverySystem, Tls};
+use vsmtp_delivery::{
+    delivery_main, send, BounceGuard, DeliverySystem, DeliveryWindow, HeaderPrivacy, LoopDetection,
+    RetryHint, Route, SenderLookup, Smarthost, SmarthostMap, StuckAlarm, Timeouts, Tls, Transport,
+    Transports,
+};
 use vsmtp_protocol::{ClientName, Domain};
 
 /// The [`Basic`] implementation of the delivery system.
 ///
 /// It has been designed to be as simple as possible:
 /// * group the recipients by domain (meaning it support multiple domains per message)
-/// * for each domain, lookup the MX records and take the MX with the higher priority
+/// * for each domain, relay to its smarthost if any, otherwise lookup the MX records
+///   and take the MX with the higher priority
 /// * make only one attempt to send the message to that MX
+///
+/// The policy of the delivery (TLS, retry, concurrency, source IP) is the one of
+/// the transport mapped to the recipients by the working service, if any.
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(deny_unknown_fields)]
 struct Basic {
@@ -37,6 +45,31 @@ struct Basic {
     dns: DnsResolver,
     tls: Tls,
     #[serde(default)]
+    timeouts: Timeouts,
+    #[serde(default)]
+    deferred_store: Option<std::path::PathBuf>,
+    /// Warn when the messages of a domain stay deferred for too long.
+    #[serde(default)]
+    stuck_alarm: Option<StuckAlarm>,
+    /// Use the retry hint of the remote 4xx replies as the delay before the next attempt.
+    #[serde(default)]
+    retry_hint: Option<RetryHint>,
+    /// Suppress the DSN of the messages whose sender looks forged (backscatter).
+    #[serde(default)]
+    bounce_guard: Option<BounceGuard>,
+    /// Header fields removed from the messages before they are sent.
+    #[serde(default)]
+    header_privacy: Option<HeaderPrivacy>,
+    /// Refuse to relay the messages looping through this server.
+    #[serde(default)]
+    loop_detection: Option<LoopDetection>,
+    /// Relays used instead of the MX for some recipient domains.
+    #[serde(default)]
+    smarthosts: SmarthostMap,
+    /// Delivery policies, by transport name.
+    #[serde(default)]
+    transports: Transports,
+    #[serde(default)]
     broker: vsmtp_config::Broker,
     #[serde(default)]
     logs: vsmtp_config::Logs,
@@ -50,9 +83,66 @@ fn get_notification_supported() -> ShouldNotify {
 }
 
 impl Basic {
+    #[tracing::instrument(
+        skip(self, smarthost, transport, mail_from, rcpt_to, mail),
+        fields(rcpt_count = rcpt_to.len(), smarthost = %smarthost.host, port = smarthost.port)
+        ret,
+        level = "debug"
+    )]
+    async fn send_to_smarthost(
+        &self,
+        domain: Domain,
+        smarthost: &Smarthost,
+        transport: Option<&Transport>,
+        mail_from: MailFromProps,
+        rcpt_to: Vec<&Recipient>,
+        mail: &[u8],
+    ) -> DeliveryAttempt {
+        let ip = match smarthost.host.parse::<std::net::IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => match self.dns.resolver.lookup_ip(smarthost.host.as_str()).await {
+                // NOTE: a successful lookup has at least one IP.
+                Ok(records) => records.iter().next().unwrap(),
+                Err(e) => {
+                    return DeliveryAttempt::new_remote(
+                        rcpt_to
+                            .into_iter()
+                            .map(|i| i.forward_path.clone())
+                            .collect::<Vec<_>>(),
+                        RemoteInformation::DnsMxIpLookup {
+                            mx: RemoteMailExchange {
+                                mx: smarthost.host.parse().unwrap_or(domain),
+                                mx_priority: 0,
+                            },
+                            error: e.into(),
+                        },
+                        get_notification_supported(),
+                    );
+                }
+            },
+        };
+
+        send(
+            std::net::SocketAddr::new(ip, smarthost.port),
+            smarthost.host.parse().unwrap_or(domain),
+            ClientName::Domain(hostname::get().unwrap().to_string_lossy().parse().unwrap()),
+            mail_from,
+            rcpt_to.into_iter().cloned().collect::<Vec<_>>(),
+            None,
+            mail,
+            smarthost.tls.clone(),
+            smarthost.auth.as_ref(),
+            transport.and_then(|transport| transport.source_ip),
+            self.timeouts,
+            self.extra_root_ca.clone(),
+            self.retry_hint.is_some(),
+        )
+        .await
+    }
+
     // TODO: null mx record (with optional fallback on A/AAAA record)
     #[tracing::instrument(
-        skip(self, mail_from, rcpt_to, mail),
+        skip(self, transport, mail_from, rcpt_to, mail),
         fields(rcpt_count = rcpt_to.len())
         ret,
         level = "debug"
@@ -60,6 +150,7 @@ impl Basic {
     async fn send_to_one_domain(
         &self,
         domain: Domain,
+        transport: Option<&Transport>,
         mail_from: MailFromProps,
         rcpt_to: Vec<&Recipient>,
         mail: &[u8],
@@ -132,8 +223,14 @@ impl Basic {
                 mx_priority: mx.preference(),
             }),
             mail,
-            self.tls.clone(),
+            transport
+                .map_or(&self.tls, |transport| &transport.tls)
+                .clone(),
+            None,
+            transport.and_then(|transport| transport.source_ip),
+            self.timeouts,
             self.extra_root_ca.clone(),
+            self.retry_hint.is_some(),
         )
         .await
     }
@@ -149,6 +246,40 @@ impl DeliverySystem for Basic {
         DeliveryRoute::Basic
     }
 
+    fn deferred_store(&self) -> Option<&std::path::Path> {
+        self.deferred_store.as_deref()
+    }
+
+    fn stuck_alarm(&self) -> Option<StuckAlarm> {
+        self.stuck_alarm
+    }
+
+    fn retry_hint(&self) -> Option<&RetryHint> {
+        self.retry_hint.as_ref()
+    }
+
+    fn bounce_guard(&self) -> Option<(&BounceGuard, &dyn SenderLookup)> {
+        self.bounce_guard
+            .as_ref()
+            .map(|guard| (guard, &self.dns as &dyn SenderLookup))
+    }
+
+    fn header_privacy(&self) -> Option<&HeaderPrivacy> {
+        self.header_privacy.as_ref()
+    }
+
+    fn loop_detection(&self) -> Option<&LoopDetection> {
+        self.loop_detection.as_ref()
+    }
+
+    fn retry_delay(&self, ctx: &CtxDelivery) -> Option<std::time::Duration> {
+        self.transports.retry_delay(ctx)
+    }
+
+    fn delivery_window(&self, ctx: &CtxDelivery, domain: &str) -> Option<&DeliveryWindow> {
+        self.transports.delivery_window(ctx, domain)
+    }
+
     async fn deliver(self: std::sync::Arc<Self>, ctx: &CtxDelivery) -> Vec<DeliveryAttempt> {
         let rcpt_to = ctx.get_undelivered_rcpt();
 
@@ -163,9 +294,30 @@ impl DeliverySystem for Basic {
         }
 
         let mail = ctx.mail.read().unwrap().to_string();
+        let transport = self.transports.get(ctx);
 
         let deliveries = rcpt_by_domain.into_iter().map(|(domain, rcpt_to)| {
-            self.send_to_one_domain(domain, ctx.mail_from.clone(), rcpt_to, mail.as_bytes())
+            let this = self.clone();
+            let mail_from = ctx.mail_from.clone();
+            let mail = mail.as_bytes();
+            async move {
+                let _slot = match transport {
+                    Some(transport) => Some(transport.acquire().await),
+                    None => None,
+                };
+                match this.smarthosts.route(&domain.to_string()) {
+                    Route::Smarthost(smarthost) => {
+                        this.send_to_smarthost(
+                            domain, smarthost, transport, mail_from, rcpt_to, mail,
+                        )
+                        .await
+                    }
+                    Route::Mx => {
+                        this.send_to_one_domain(domain, transport, mail_from, rcpt_to, mail)
+                            .await
+                    }
+                }
+            }
         });
 
         futures_util::future::join_all(deliveries).await
@@ -182,6 +334,15 @@ impl Default for Basic {
             logs: vsmtp_config::Logs::default(),
             path: std::path::PathBuf::default(),
             tls: Tls::default(),
+            timeouts: Timeouts::default(),
+            deferred_store: None,
+            stuck_alarm: None,
+            retry_hint: None,
+            bounce_guard: None,
+            header_privacy: None,
+            loop_detection: None,
+            smarthosts: SmarthostMap::default(),
+            transports: Transports::default(),
             extra_root_ca: None,
         }
     }
@@ -18,7 +18,10 @@ use vsmtp_common::{
     dns_resolver::DnsResolver,
 };
 use vsmtp_config::Config;
-use vsmtp_delivery::{delivery_main, send, DeliverySystem, Tls};
+use vsmtp_delivery::{
+    delivery_main, send, BounceGuard, DeliverySystem, HeaderPrivacy, LoopDetection, RetryHint,
+    SenderLookup, StuckAlarm, Timeouts, Tls,
+};
 use vsmtp_protocol::ClientName;
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -30,6 +33,25 @@ struct Forward {
     service: String,
     target: url::Url,
     tls: Tls,
+    #[serde(default)]
+    timeouts: Timeouts,
+    #[serde(default)]
+    deferred_store: Option<std::path::PathBuf>,
+    /// Warn when the messages of a domain stay deferred for too long.
+    #[serde(default)]
+    stuck_alarm: Option<StuckAlarm>,
+    /// Use the retry hint of the remote 4xx replies as the delay before the next attempt.
+    #[serde(default)]
+    retry_hint: Option<RetryHint>,
+    /// Suppress the DSN of the messages whose sender looks forged (backscatter).
+    #[serde(default)]
+    bounce_guard: Option<BounceGuard>,
+    /// Header fields removed from the messages before they are sent.
+    #[serde(default)]
+    header_privacy: Option<HeaderPrivacy>,
+    /// Refuse to relay the messages looping through this server.
+    #[serde(default)]
+    loop_detection: Option<LoopDetection>,
     dns: DnsResolver,
     #[serde(default)]
     broker: vsmtp_config::Broker,
@@ -52,19 +74,35 @@ impl DeliverySystem for Forward {
         }
     }
 
-    async fn deliver(
-        self: Arc<Self>,
-        CtxDelivery {
-            uuid: _,
-            routing_key: _,
-            mail_from,
-            rcpt_to,
-            mail,
-            last_deliveries: _,
-            attempt: _,
-        }: &CtxDelivery,
-    ) -> Vec<DeliveryAttempt> {
-        let message_str = mail.read().unwrap().to_string();
+    fn deferred_store(&self) -> Option<&std::path::Path> {
+        self.deferred_store.as_deref()
+    }
+
+    fn stuck_alarm(&self) -> Option<StuckAlarm> {
+        self.stuck_alarm
+    }
+
+    fn retry_hint(&self) -> Option<&RetryHint> {
+        self.retry_hint.as_ref()
+    }
+
+    fn bounce_guard(&self) -> Option<(&BounceGuard, &dyn SenderLookup)> {
+        self.bounce_guard
+            .as_ref()
+            .map(|guard| (guard, &self.dns as &dyn SenderLookup))
+    }
+
+    fn header_privacy(&self) -> Option<&HeaderPrivacy> {
+        self.header_privacy.as_ref()
+    }
+
+    fn loop_detection(&self) -> Option<&LoopDetection> {
+        self.loop_detection.as_ref()
+    }
+
+    async fn deliver(self: Arc<Self>, ctx: &CtxDelivery) -> Vec<DeliveryAttempt> {
+        let message_str = ctx.mail.read().unwrap().to_string();
+        let rcpt_to = ctx.get_undelivered_rcpt().cloned().collect::<Vec<_>>();
 
         assert!(self.target.scheme() == "smtp");
 
@@ -107,12 +145,16 @@ impl DeliverySystem for Forward {
                 std::net::SocketAddr::new(target_ip, self.target.port().unwrap_or(25)),
                 sni.into(),
                 ClientName::Domain(hostname::get().unwrap().to_string_lossy().parse().unwrap()),
-                mail_from.clone(),
-                rcpt_to.clone(),
+                ctx.mail_from.clone(),
+                rcpt_to,
                 None,
                 message_str.as_bytes(),
                 self.tls.clone(),
+                None,
+                None,
+                self.timeouts,
                 self.extra_root_ca.clone(),
+                self.retry_hint.is_some(),
             )
             .await,
         ]
@@ -131,6 +173,13 @@ impl Default for Forward {
             logs: vsmtp_config::Logs::default(),
             path: std::path::PathBuf::default(),
             tls: Tls::default(),
+            timeouts: Timeouts::default(),
+            deferred_store: None,
+            stuck_alarm: None,
+            retry_hint: None,
+            bounce_guard: None,
+            header_privacy: None,
+            loop_detection: None,
             extra_root_ca: None,
         }
     }
@@ -9,56 +9,8 @@
  *
  */
 
-use std::sync::Arc;
-use vsmtp_common::{
-    ctx_delivery::CtxDelivery, delivery_attempt::DeliveryAttempt, delivery_route::DeliveryRoute,
-};
 use vsmtp_config::Config;
-use vsmtp_delivery::{delivery_main, DeliverySystem};
-
-#[derive(Default, serde::Serialize, serde::Deserialize)]
-#[serde(deny_unknown_fields)]
-struct Mbox {
-    #[serde(skip, default = "default_mbox_hostname")]
-    name: String,
-}
-
-fn default_mbox_hostname() -> String {
-    "mbox".to_string()
-}
-
-#[async_trait::async_trait]
-impl DeliverySystem for Mbox {
-    fn name(&self) -> &str {
-        &self.name
-    }
-
-    fn routing_key(&self) -> DeliveryRoute {
-        DeliveryRoute::Mbox
-    }
-
-    async fn deliver(self: Arc<Self>, _: &CtxDelivery) -> Vec<DeliveryAttempt> {
-        unimplemented!()
-    }
-}
-
-impl Config for Mbox {
-    fn api_version(&self) -> &vsmtp_config::semver::VersionReq {
-        todo!()
-    }
-
-    fn broker(&self) -> &vsmtp_config::broker::Broker {
-        todo!()
-    }
-
-    fn logs(&self) -> &vsmtp_config::logs::Logs {
-        todo!()
-    }
-
-    fn path(&self) -> &std::path::Path {
-        todo!()
-    }
-}
+use vsmtp_delivery::{delivery_main, MboxDelivery};
 
 #[derive(clap::Parser)]
 #[command(author, version, about)]
@@ -72,7 +24,7 @@ struct Args {
 async fn main() {
     let Args { config } = <Args as clap::Parser>::parse();
 
-    let system = match Mbox::from_rhai_file(&config) {
+    let system = match MboxDelivery::from_rhai_file(&config) {
         Ok(cfg) => std::sync::Arc::new(cfg),
         Err(error) => {
             eprintln!("Failed to initialize mbox delivery configuration: {error}");
@@ -0,0 +1,38 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_config::Config;
+use vsmtp_delivery::{report_main, ReportService};
+
+#[derive(clap::Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Path to the rhai configuration file.
+    #[arg(short, long, default_value_t = String::from("/etc/vsmtp/report/conf.d/config.rhai"))]
+    pub config: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let Args { config } = <Args as clap::Parser>::parse();
+
+    let service = match ReportService::from_rhai_file(&config) {
+        Ok(cfg) => cfg,
+        Err(error) => {
+            eprintln!("Failed to initialize report configuration: {error}");
+            return;
+        }
+    };
+
+    if let Err(error) = report_main(service).await {
+        tracing::error!("Failed to run the report service: {error}");
+    }
+}
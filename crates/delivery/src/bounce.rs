@@ -0,0 +1,654 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_common::{ctx_delivery::CtxDelivery, delivery_attempt::Action, uuid};
+use vsmtp_mail_parser::Mail;
+
+/// Class of a delivery status notification, selecting its template.
+/// The classes are ordered from the most to the least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BounceClass {
+    /// The message could not be delivered, and will not be retried.
+    Failure,
+    /// The delivery failed temporarily, and will be retried.
+    Delay,
+    /// The message has been delivered.
+    Success,
+}
+
+impl BounceClass {
+    const ALL: [Self; 3] = [Self::Failure, Self::Delay, Self::Success];
+
+    /// Get the class of the notification reporting `action`.
+    #[must_use]
+    pub const fn of(action: &Action) -> Self {
+        match action {
+            Action::Failed { .. } => Self::Failure,
+            Action::Delayed { .. } => Self::Delay,
+            Action::Delivered | Action::Relayed | Action::Expanded => Self::Success,
+        }
+    }
+
+    /// Name of the template file, without the `.txt` extension.
+    const fn file_stem(self) -> &'static str {
+        match self {
+            Self::Failure => "failure",
+            Self::Delay => "delay",
+            Self::Success => "success",
+        }
+    }
+
+    /// Value of the `Action` field of the delivery status.
+    /// <https://www.rfc-editor.org/rfc/rfc3464#section-2.3.3>
+    const fn action(self) -> &'static str {
+        match self {
+            Self::Failure => "failed",
+            Self::Delay => "delayed",
+            Self::Success => "delivered",
+        }
+    }
+
+    const fn builtin(self) -> &'static str {
+        match self {
+            Self::Failure => {
+                "Subject: Undelivered Mail Returned to Sender\n\
+                \n\
+                This is the mail system at {reporting_mta}.\n\
+                \n\
+                Your message \"{original_subject}\" could not be delivered to {recipient}.\n\
+                The delivery will not be retried.\n\
+                \n\
+                {status} {diagnostic_code}\n"
+            }
+            Self::Delay => {
+                "Subject: Delayed Mail (still being retried)\n\
+                \n\
+                This is the mail system at {reporting_mta}.\n\
+                \n\
+                Your message \"{original_subject}\" could not be delivered to {recipient} yet.\n\
+                The delivery will be retried, no action is required on your part.\n\
+                \n\
+                {status} {diagnostic_code}\n"
+            }
+            Self::Success => {
+                "Subject: Successful Mail Delivery Report\n\
+                \n\
+                This is the mail system at {reporting_mta}.\n\
+                \n\
+                Your message \"{original_subject}\" has been delivered to {recipient}.\n"
+            }
+        }
+    }
+}
+
+/// Human-readable part of a notification, with an optional `Subject:` first line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    subject: String,
+    body: String,
+}
+
+impl Template {
+    fn parse(content: &str, class: BounceClass) -> Self {
+        let content = content.replace("\r\n", "\n");
+        match content
+            .strip_prefix("Subject:")
+            .and_then(|content| content.split_once('\n'))
+        {
+            Some((subject, body)) => Self {
+                subject: subject.trim().to_string(),
+                body: body.trim_start_matches('\n').to_string(),
+            },
+            None => Self {
+                subject: Self::parse(class.builtin(), class).subject,
+                body: content,
+            },
+        }
+    }
+}
+
+/// A delivery status notification to render, for one recipient.
+pub struct Bounce<'a> {
+    pub class: BounceClass,
+    /// Name of the server emitting the notification.
+    pub reporting_mta: &'a str,
+    /// Recipient of the notification, the reverse path of the original message.
+    pub sender: &'a str,
+    /// Recipient of the original message the notification is about.
+    pub recipient: &'a str,
+    /// Enhanced status code of the delivery, for example `5.1.1`.
+    pub status: &'a str,
+    /// Reply of the remote server or error of the local delivery.
+    pub diagnostic_code: &'a str,
+    /// The headers of the original message are returned with the notification.
+    pub original: &'a Mail,
+}
+
+impl Bounce<'_> {
+    fn original_subject(&self) -> String {
+        self.original
+            .get_header("Subject")
+            .map_or_else(String::new, |subject| subject.body.trim().to_string())
+    }
+
+    /// Substitute the placeholders of `template`. The values are flattened on a single line,
+    /// so they cannot inject headers or MIME boundaries.
+    fn substitute(&self, template: &str) -> String {
+        let line = |value: &str| value.replace(['\r', '\n'], " ");
+
+        template
+            .replace("{recipient}", &line(self.recipient))
+            .replace("{diagnostic_code}", &line(self.diagnostic_code))
+            .replace("{original_subject}", &line(&self.original_subject()))
+            .replace("{status}", &line(self.status))
+            .replace("{reporting_mta}", &line(self.reporting_mta))
+    }
+
+    /// Per-recipient fields of the delivery status.
+    /// <https://www.rfc-editor.org/rfc/rfc3464#section-2.3>
+    ///
+    /// The `Diagnostic-Code` field is omitted if there is no diagnostic,
+    /// as for the successful deliveries.
+    fn recipient_status(&self) -> String {
+        let line = |value: &str| value.replace(['\r', '\n'], " ");
+
+        let mut status = format!(
+            "Final-Recipient: rfc822; {}\r\n\
+            Action: {}\r\n\
+            Status: {}\r\n",
+            line(self.recipient),
+            self.class.action(),
+            line(self.status),
+        );
+        if !self.diagnostic_code.is_empty() {
+            status.push_str(&format!(
+                "Diagnostic-Code: smtp; {}\r\n",
+                line(self.diagnostic_code)
+            ));
+        }
+        status
+    }
+}
+
+/// Encode a header value containing non-ascii characters.
+/// <https://www.rfc-editor.org/rfc/rfc2047#section-2>
+fn encode_word(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!(
+            "=?utf-8?b?{}?=",
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, value)
+        )
+    }
+}
+
+/// Templates of the human-readable part of the delivery status notifications,
+/// by language and by [`BounceClass`].
+///
+/// They are loaded from a directory with a sub-directory per language, usually
+/// `bounce/` in the configuration directory of the service:
+///
+/// ```text
+/// bounce/
+/// ├── en/
+/// │   ├── failure.txt
+/// │   └── delay.txt
+/// └── fr/
+///     └── failure.txt
+/// ```
+///
+/// A template may start with a `Subject:` line followed by an empty line, and contains
+/// the placeholders `{recipient}`, `{diagnostic_code}`, `{original_subject}`, `{status}`
+/// and `{reporting_mta}`. The missing templates fall back to the default language,
+/// then to the built-in english templates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BounceTemplates {
+    default_language: String,
+    templates: std::collections::HashMap<(String, BounceClass), Template>,
+}
+
+impl Default for BounceTemplates {
+    fn default() -> Self {
+        Self {
+            default_language: "en".to_string(),
+            templates: std::collections::HashMap::new(),
+        }
+    }
+}
+
+impl BounceTemplates {
+    /// Load the templates of every language in `dir`.
+    ///
+    /// # Errors
+    ///
+    /// * `dir` or a template cannot be read.
+    pub fn load(
+        dir: &impl AsRef<std::path::Path>,
+        default_language: impl Into<String>,
+    ) -> std::io::Result<Self> {
+        let mut templates = std::collections::HashMap::new();
+
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let language = entry.file_name().to_string_lossy().to_lowercase();
+
+            for class in BounceClass::ALL {
+                let path = entry.path().join(format!("{}.txt", class.file_stem()));
+                match std::fs::read_to_string(&path) {
+                    Ok(content) => {
+                        templates
+                            .insert((language.clone(), class), Template::parse(&content, class));
+                    }
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+
+        Ok(Self {
+            default_language: default_language.into().to_lowercase(),
+            templates,
+        })
+    }
+
+    fn template(
+        &self,
+        class: BounceClass,
+        language: Option<&str>,
+    ) -> std::borrow::Cow<'_, Template> {
+        language
+            .map(str::to_lowercase)
+            .into_iter()
+            .chain(std::iter::once(self.default_language.clone()))
+            .find_map(|language| self.templates.get(&(language, class)))
+            .map_or_else(
+                || std::borrow::Cow::Owned(Template::parse(class.builtin(), class)),
+                std::borrow::Cow::Borrowed,
+            )
+    }
+
+    /// Render the subject and the human-readable text of a notification.
+    #[must_use]
+    pub fn render_text(&self, bounce: &Bounce<'_>, language: Option<&str>) -> (String, String) {
+        let template = self.template(bounce.class, language);
+
+        (
+            bounce.substitute(&template.subject),
+            bounce.substitute(&template.body),
+        )
+    }
+
+    /// Render a `multipart/report` notification, with the human-readable text,
+    /// the delivery status and the headers of the original message.
+    /// <https://www.rfc-editor.org/rfc/rfc3464>
+    #[must_use]
+    pub fn render(&self, bounce: &Bounce<'_>, language: Option<&str>) -> String {
+        self.render_all(std::slice::from_ref(bounce), language)
+    }
+
+    /// Render a single notification about all the recipients of `bounces`, which are about
+    /// the same original message: the texts of the recipients are concatenated, the subject
+    /// is the one of the most severe class, and the delivery status has a block per recipient.
+    ///
+    /// # Panics
+    ///
+    /// * `bounces` is empty
+    fn render_all(&self, bounces: &[Bounce<'_>], language: Option<&str>) -> String {
+        let first = bounces.first().expect("at least one recipient to report");
+        let most_severe = bounces
+            .iter()
+            .min_by_key(|bounce| bounce.class)
+            .unwrap_or(first);
+
+        let (subject, _) = self.render_text(most_severe, language);
+        let text = bounces
+            .iter()
+            .map(|bounce| self.render_text(bounce, language).1)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .lines()
+            .flat_map(|line| [line, "\r\n"])
+            .collect::<String>();
+
+        let line = |value: &str| value.replace(['\r', '\n'], " ");
+        let delivery_status = std::iter::once(format!(
+            "Reporting-MTA: dns; {}\r\n",
+            line(first.reporting_mta)
+        ))
+        .chain(
+            bounces
+                .iter()
+                .map(|bounce| format!("\r\n{}", bounce.recipient_status())),
+        )
+        .collect::<String>();
+
+        let boundary = uuid::Uuid::new_v4().to_string();
+        let date = time::OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc2822)
+            .expect("the current date is formattable");
+
+        [
+            format!("From: MAILER-DAEMON@{}\r\n", first.reporting_mta),
+            format!("To: <{}>\r\n", first.sender),
+            format!("Subject: {}\r\n", encode_word(&subject)),
+            format!("Date: {date}\r\n"),
+            "Auto-Submitted: auto-replied\r\n".to_string(),
+            "MIME-Version: 1.0\r\n".to_string(),
+            format!(
+                "Content-Type: multipart/report; report-type=delivery-status; boundary=\"{boundary}\"\r\n"
+            ),
+            "\r\n".to_string(),
+            "This is a MIME-encapsulated message.\r\n".to_string(),
+            "\r\n".to_string(),
+            format!("--{boundary}\r\n"),
+            "Content-Type: text/plain; charset=utf-8\r\n".to_string(),
+            "Content-Transfer-Encoding: 8bit\r\n".to_string(),
+            "\r\n".to_string(),
+            text,
+            format!("--{boundary}\r\n"),
+            "Content-Type: message/delivery-status\r\n".to_string(),
+            "\r\n".to_string(),
+            delivery_status,
+            format!("--{boundary}\r\n"),
+            "Content-Type: text/rfc822-headers\r\n".to_string(),
+            "\r\n".to_string(),
+            first.original.headers.to_string(),
+            format!("--{boundary}--\r\n"),
+        ]
+        .concat()
+    }
+
+    /// Render the notification of a report request emitted by a delivery cycle,
+    /// with a per-recipient block for each recipient of the request, from the last
+    /// delivery attempts of the message.
+    ///
+    /// Nothing is rendered for a message with a null reverse path,
+    /// or without attempt for the recipients of the request.
+    #[must_use]
+    pub fn render_report(
+        &self,
+        report: &CtxDelivery,
+        reporting_mta: &str,
+        language: Option<&str>,
+    ) -> Option<String> {
+        let sender = report.mail_from.reverse_path.as_ref()?.to_string();
+        let original = report.mail.read().unwrap();
+
+        let recipients = report
+            .rcpt_to
+            .iter()
+            .filter_map(|rcpt| {
+                let (attempt, idx) =
+                    report.last_deliveries.iter().rev().find_map(|attempt| {
+                        attempt.get_rcpt_index(rcpt).map(|idx| (attempt, idx))
+                    })?;
+
+                let action = attempt.get_action(idx);
+                let diagnostic_code = match &action {
+                    Action::Failed { diagnostic_code }
+                    | Action::Delayed {
+                        diagnostic_code, ..
+                    } => diagnostic_code.clone().unwrap_or_default(),
+                    Action::Delivered | Action::Relayed | Action::Expanded => String::new(),
+                };
+
+                Some((
+                    BounceClass::of(&action),
+                    rcpt.forward_path.to_string(),
+                    attempt.get_status(idx).0,
+                    diagnostic_code,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let bounces = recipients
+            .iter()
+            .map(|(class, recipient, status, diagnostic_code)| Bounce {
+                class: *class,
+                reporting_mta,
+                sender: &sender,
+                recipient,
+                status,
+                diagnostic_code,
+                original: &original,
+            })
+            .collect::<Vec<_>>();
+
+        (!bounces.is_empty()).then(|| self.render_all(&bounces, language))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bounce, BounceClass, BounceTemplates};
+    use vsmtp_common::uuid;
+    use vsmtp_mail_parser::{mail::body::ParsedBody, mime::Part, Mail};
+
+    fn original() -> Mail {
+        Mail::try_from(
+            [
+                "From: john.doe@example.com\r\n",
+                "Date: Thu, 02 Nov 2023 10:21:13 +0000\r\n",
+                "Subject: Quarterly report\r\n",
+                "\r\n",
+                "Hello world!\r\n",
+            ]
+            .concat()
+            .as_str(),
+        )
+        .unwrap()
+    }
+
+    fn bounce<'a>(class: BounceClass, original: &'a Mail) -> Bounce<'a> {
+        Bounce {
+            class,
+            reporting_mta: "mx.example.com",
+            sender: "john.doe@example.com",
+            recipient: "jenny@example.net",
+            status: "5.1.1",
+            diagnostic_code: "550 5.1.1 mailbox unavailable",
+            original,
+        }
+    }
+
+    fn templates() -> BounceTemplates {
+        let dir = std::env::temp_dir().join(format!("vsmtp-bounce-{}", uuid::Uuid::new_v4()));
+        let write = |path: &str, content: &str| {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+
+        write(
+            "en/failure.txt",
+            "Subject: Returned: {original_subject}\n\nNo luck with {recipient}: {diagnostic_code}\n",
+        );
+        write(
+            "en/delay.txt",
+            "Subject: Delayed: {original_subject}\n\nStill trying {recipient} ({status}).\n",
+        );
+        write(
+            "fr/failure.txt",
+            "Subject: Non distribué : {original_subject}\n\nÉchec pour {recipient} : {diagnostic_code}\n",
+        );
+
+        let templates = BounceTemplates::load(&dir, "EN").unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        templates
+    }
+
+    /// Get the content type and the content of the parts of a `multipart/report`.
+    fn parts(rendered: &str) -> Vec<(String, String)> {
+        let mut mail = Mail::try_from(rendered).unwrap();
+        let ParsedBody::Mime(mime) = mail.parse_body().unwrap() else {
+            panic!("the notification should be a MIME message");
+        };
+        assert!(mime.content_type().starts_with("multipart/report"));
+        let Part::Multipart(multipart) = &mime.part else {
+            panic!("the notification should be a multipart");
+        };
+
+        multipart
+            .parts
+            .iter()
+            .map(|part| (part.content_type(), part.raw_part()))
+            .collect()
+    }
+
+    #[test]
+    fn failure() {
+        let original = original();
+        let templates = templates();
+        let bounce = bounce(BounceClass::Failure, &original);
+
+        let (subject, text) = templates.render_text(&bounce, None);
+        assert_eq!(subject, "Returned: Quarterly report");
+        assert_eq!(
+            text,
+            "No luck with jenny@example.net: 550 5.1.1 mailbox unavailable\n"
+        );
+
+        let (subject, text) = templates.render_text(&bounce, Some("fr"));
+        assert_eq!(subject, "Non distribué : Quarterly report");
+        assert_eq!(
+            text,
+            "Échec pour jenny@example.net : 550 5.1.1 mailbox unavailable\n"
+        );
+
+        let rendered = templates.render(&bounce, Some("fr"));
+        assert!(rendered.contains(&format!(
+            "Subject: =?utf-8?b?{}?=\r\n",
+            base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                "Non distribué : Quarterly report"
+            )
+        )));
+
+        let parts = parts(&rendered);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].0, "text/plain");
+        assert_eq!(
+            parts[0].1,
+            "Échec pour jenny@example.net : 550 5.1.1 mailbox unavailable\r\n"
+        );
+        assert_eq!(parts[1].0, "message/delivery-status");
+        assert!(parts[1]
+            .1
+            .contains("Final-Recipient: rfc822; jenny@example.net\r\n"));
+        assert!(parts[1].1.contains("Action: failed\r\n"));
+        assert!(parts[1].1.contains("Status: 5.1.1\r\n"));
+        assert_eq!(parts[2].0, "text/rfc822-headers");
+        assert!(parts[2].1.contains("Subject: Quarterly report\r\n"));
+        assert!(!parts[2].1.contains("Hello world!"));
+    }
+
+    #[test]
+    fn delay() {
+        let original = original();
+        let templates = templates();
+        let bounce = Bounce {
+            status: "4.4.7",
+            diagnostic_code: "timeout",
+            ..bounce(BounceClass::Delay, &original)
+        };
+
+        let rendered = templates.render(&bounce, Some("de"));
+        assert!(rendered.contains("Subject: Delayed: Quarterly report\r\n"));
+        assert!(rendered.contains("To: <john.doe@example.com>\r\n"));
+
+        let parts = parts(&rendered);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].0, "text/plain");
+        assert_eq!(parts[0].1, "Still trying jenny@example.net (4.4.7).\r\n");
+        assert!(parts[1].1.contains("Action: delayed\r\n"));
+        assert!(parts[1].1.contains("Diagnostic-Code: smtp; timeout\r\n"));
+    }
+
+    #[test]
+    fn builtin() {
+        let original = original();
+        let templates = BounceTemplates::default();
+        let bounce = bounce(BounceClass::Success, &original);
+
+        let (subject, text) = templates.render_text(&bounce, Some("fr"));
+        assert_eq!(subject, "Successful Mail Delivery Report");
+        assert!(text.contains("\"Quarterly report\" has been delivered to jenny@example.net"));
+        assert_eq!(parts(&templates.render(&bounce, None)).len(), 3);
+    }
+
+    #[test]
+    fn single_report_for_all_recipients() {
+        let original = original();
+        let templates = templates();
+        let bounces = [
+            Bounce {
+                recipient: "jenny@example.net",
+                status: "4.4.7",
+                diagnostic_code: "timeout",
+                ..bounce(BounceClass::Delay, &original)
+            },
+            Bounce {
+                recipient: "jane@example.org",
+                ..bounce(BounceClass::Failure, &original)
+            },
+        ];
+
+        let rendered = templates.render_all(&bounces, None);
+        // the subject of the most severe class.
+        assert!(rendered.contains("Subject: Returned: Quarterly report\r\n"));
+
+        let parts = parts(&rendered);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(
+            parts[0].1,
+            concat!(
+                "Still trying jenny@example.net (4.4.7).\r\n",
+                "\r\n",
+                "No luck with jane@example.org: 550 5.1.1 mailbox unavailable\r\n",
+            )
+        );
+        assert_eq!(
+            parts[1].1,
+            concat!(
+                "Reporting-MTA: dns; mx.example.com\r\n",
+                "\r\n",
+                "Final-Recipient: rfc822; jenny@example.net\r\n",
+                "Action: delayed\r\n",
+                "Status: 4.4.7\r\n",
+                "Diagnostic-Code: smtp; timeout\r\n",
+                "\r\n",
+                "Final-Recipient: rfc822; jane@example.org\r\n",
+                "Action: failed\r\n",
+                "Status: 5.1.1\r\n",
+                "Diagnostic-Code: smtp; 550 5.1.1 mailbox unavailable\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn values_on_a_single_line() {
+        let original = original();
+        let templates = templates();
+        let bounce = Bounce {
+            diagnostic_code: "550 rejected\r\n--boundary\r\nX-Injected: yes",
+            ..bounce(BounceClass::Failure, &original)
+        };
+
+        let rendered = templates.render(&bounce, None);
+        assert!(!rendered.contains("\r\nX-Injected"));
+        assert!(!rendered.contains("\r\n--boundary"));
+        assert_eq!(parts(&rendered).len(), 3);
+    }
+}
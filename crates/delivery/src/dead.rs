@@ -0,0 +1,230 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use tokio_stream::StreamExt;
+use vsmtp_common::{
+    broker::{BackendError, Message, Queue, QueueBackend},
+    ctx::Ctx,
+    ctx_delivery::CtxDelivery,
+    uuid,
+};
+
+/// A message of the [`Queue::Dead`] queue, as listed to the admin.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DeadMessage {
+    pub uuid: uuid::Uuid,
+    pub routing_key: String,
+    /// Number of delivery attempts made before the message was put in the dead queue.
+    pub attempts: usize,
+    /// The recipients which have not been delivered.
+    pub undelivered: Vec<String>,
+}
+
+impl DeadMessage {
+    fn new(ctx: &CtxDelivery) -> Self {
+        Self {
+            uuid: ctx.uuid,
+            routing_key: ctx.routing_key.to_string(),
+            attempts: ctx.attempt.len(),
+            undelivered: ctx
+                .get_undelivered_rcpt()
+                .map(|rcpt| rcpt.forward_path.0.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Prepare a dead message for a new delivery cycle: the recipients already delivered
+/// are removed, so they do not receive the message twice, and the attempts are reset.
+pub fn reset_attempts(ctx: &mut CtxDelivery) {
+    let delivered = ctx
+        .rcpt_to
+        .iter()
+        .map(|rcpt| ctx.is_rcpt_delivered(rcpt))
+        .collect::<Vec<_>>();
+    let mut delivered = delivered.into_iter();
+    ctx.rcpt_to
+        .retain(|_| !delivered.next().unwrap_or_default());
+    ctx.attempt.clear();
+}
+
+/// Read all the messages of the dead queue.
+///
+/// The messages are not acknowledged by this function, the ones not acknowledged by
+/// the caller go back to the queue when the channel of the backend is closed.
+async fn read_all(
+    backend: &dyn QueueBackend,
+) -> Result<Vec<(Message, Ctx<CtxDelivery>)>, BackendError> {
+    let depth = backend.queue_depth(Queue::Dead.as_ref()).await?;
+    // all the messages are held unacknowledged, the prefetch must not limit them.
+    backend.set_prefetch(0).await?;
+
+    let mut consumer = backend
+        .consume(Queue::Dead.as_ref())
+        .await?
+        .take(depth as usize);
+
+    let mut messages = vec![];
+    while let Some(message) = consumer.next().await {
+        let message = message?;
+        match Ctx::<CtxDelivery>::from_json(&message.data) {
+            Ok(ctx) => messages.push((message, ctx)),
+            Err(error) => tracing::warn!(%error, "Skipping an invalid dead message"),
+        }
+    }
+
+    Ok(messages)
+}
+
+/// List the messages of the dead queue, leaving them in the queue.
+///
+/// # Errors
+///
+/// * the dead queue cannot be consumed
+pub async fn inspect_dead(backend: &dyn QueueBackend) -> Result<Vec<DeadMessage>, BackendError> {
+    Ok(read_all(backend)
+        .await?
+        .iter()
+        .map(|(_, ctx)| DeadMessage::new(&ctx.metadata))
+        .collect())
+}
+
+/// Send the messages of the dead queue accepted by `select` back to the delivery exchange,
+/// with their attempts reset (see [`reset_attempts`]). The other messages are left in the queue.
+///
+/// Return the messages requeued.
+///
+/// # Errors
+///
+/// * the dead queue cannot be consumed
+/// * a requeued message cannot be acknowledged
+pub async fn requeue_dead(
+    backend: &dyn QueueBackend,
+    select: impl Fn(&DeadMessage) -> bool,
+) -> Result<Vec<DeadMessage>, BackendError> {
+    let mut requeued = vec![];
+    for (message, mut ctx) in read_all(backend).await? {
+        let dead = DeadMessage::new(&ctx.metadata);
+        if !select(&dead) {
+            continue;
+        }
+        reset_attempts(&mut ctx.metadata);
+
+        let payload = ctx.to_json().expect("context is serializable");
+        if !backend.write_to_delivery(&dead.routing_key, payload).await {
+            tracing::warn!(
+                uuid = %dead.uuid,
+                routing_key = %dead.routing_key,
+                "No delivery service bound to the route, the message stays in the dead queue"
+            );
+            continue;
+        }
+
+        message.ack().await?;
+        tracing::info!(uuid = %dead.uuid, "Dead message requeued");
+        requeued.push(dead);
+    }
+
+    Ok(requeued)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inspect_dead, requeue_dead};
+    use crate::testing::recipient;
+    use vsmtp_common::{
+        broker::{in_memory::InMemory, Queue},
+        ctx::Ctx,
+        ctx_delivery::CtxDelivery,
+        delivery_attempt::{DeliveryAttempt, LocalInformation, ShouldNotify},
+        delivery_route::DeliveryRoute,
+    };
+
+    /// A message which failed for `a@localhost` too many times, and was delivered to `b@localhost`.
+    fn dead() -> Ctx<CtxDelivery> {
+        let mut metadata = CtxDelivery::fake();
+        metadata.routing_key = DeliveryRoute::Maildir;
+        metadata.rcpt_to = vec![recipient("a@localhost"), recipient("b@localhost")];
+        metadata.last_deliveries = vec![];
+        metadata.attempt = std::iter::repeat_with(|| {
+            DeliveryAttempt::new_local(
+                recipient("a@localhost").forward_path,
+                LocalInformation::TimedOut,
+                ShouldNotify::all(),
+            )
+        })
+        .take(10)
+        .chain(std::iter::once(DeliveryAttempt::new_local(
+            recipient("b@localhost").forward_path,
+            LocalInformation::Success,
+            ShouldNotify::all(),
+        )))
+        .collect();
+
+        Ctx {
+            variables: std::collections::HashMap::default(),
+            internal: std::collections::HashMap::default(),
+            metadata,
+        }
+    }
+
+    #[tokio::test]
+    async fn inspect() {
+        let ctx = dead();
+        let backend = InMemory::default();
+        backend.push(Queue::Dead.as_ref(), ctx.to_json().unwrap());
+        backend.push(Queue::Dead.as_ref(), b"not a context".to_vec());
+
+        let messages = inspect_dead(&backend).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].uuid, ctx.metadata.uuid);
+        assert_eq!(messages[0].routing_key, "maildir");
+        assert_eq!(messages[0].attempts, 11);
+        assert_eq!(messages[0].undelivered, ["a@localhost"]);
+
+        assert_eq!(backend.messages(Queue::Dead.as_ref()).len(), 2);
+        assert!(backend.messages("delivery-maildir").is_empty());
+    }
+
+    #[tokio::test]
+    async fn requeue_with_reset_attempts() {
+        let (selected, kept) = (dead(), dead());
+        let backend = InMemory::default();
+        backend.push(Queue::Dead.as_ref(), kept.to_json().unwrap());
+        backend.push(Queue::Dead.as_ref(), selected.to_json().unwrap());
+
+        let requeued = requeue_dead(&backend, |message| message.uuid == selected.metadata.uuid)
+            .await
+            .unwrap();
+        assert_eq!(requeued.len(), 1);
+        assert_eq!(requeued[0].uuid, selected.metadata.uuid);
+        assert_eq!(requeued[0].attempts, 11);
+
+        // only the requeued message leaves the dead queue.
+        let remaining = backend.messages(Queue::Dead.as_ref());
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(
+            Ctx::<CtxDelivery>::from_json(&remaining[0])
+                .unwrap()
+                .metadata
+                .uuid,
+            kept.metadata.uuid
+        );
+
+        let delivery = backend.messages("delivery-maildir");
+        assert_eq!(delivery.len(), 1);
+
+        let ctx = Ctx::<CtxDelivery>::from_json(&delivery[0]).unwrap();
+        assert_eq!(ctx.metadata.uuid, selected.metadata.uuid);
+        assert!(ctx.metadata.attempt.is_empty());
+        assert_eq!(ctx.metadata.rcpt_to, [recipient("a@localhost")]);
+    }
+}
@@ -0,0 +1,708 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use tokio_stream::StreamExt;
+use vsmtp_common::{
+    broker::{Backlog, Exchange, QueueBackend},
+    ctx::Ctx,
+    ctx_delivery::CtxDelivery,
+    uuid,
+};
+
+/// Delay between two publications of the [`Backlog`] of a delivery service.
+const BACKLOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Number of messages currently deferred by a delivery system, per destination domain.
+///
+/// The messages are tracked from the moment they are deferred, until they are delivered
+/// or put in the dead queue. Their age is counted from their first delivery attempt,
+/// recorded in the message. The messages deferred before the service started are
+/// restored from the [`DeferredStore`], see [`DeferredGauge::restore`].
+pub struct DeferredGauge {
+    routing_key: String,
+    tracked: std::sync::Mutex<Tracked>,
+}
+
+#[derive(Default)]
+struct Tracked {
+    messages: std::collections::HashMap<uuid::Uuid, Deferred>,
+    /// Number of messages deferred for each destination domain.
+    per_domain: std::collections::HashMap<String, usize>,
+}
+
+/// A message tracked by the [`DeferredGauge`].
+struct Deferred {
+    /// When the first delivery attempt of the message ended.
+    since: time::OffsetDateTime,
+    domains: std::collections::HashSet<String>,
+}
+
+/// Raise an alarm when a message stays deferred for too long.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StuckAlarm {
+    /// Age of the oldest deferred message of a domain above which the alarm is raised.
+    #[serde(with = "humantime_serde")]
+    pub threshold: std::time::Duration,
+    /// Delay between two scans of the deferred messages.
+    #[serde(default = "default_interval", with = "humantime_serde")]
+    pub interval: std::time::Duration,
+}
+
+const fn default_interval() -> std::time::Duration {
+    std::time::Duration::from_secs(60)
+}
+
+/// A destination domain whose oldest deferred message exceeds the [`StuckAlarm`] threshold.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StuckDomain {
+    pub domain: String,
+    /// Age of the oldest deferred message of the domain.
+    pub oldest: std::time::Duration,
+    /// Number of messages deferred for the domain.
+    pub deferred: usize,
+}
+
+/// Answer of an admin query on the deferred messages.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DeferredSnapshot {
+    pub routing_key: String,
+    pub per_domain: std::collections::BTreeMap<String, usize>,
+}
+
+impl DeferredGauge {
+    #[must_use]
+    pub fn new(routing_key: String) -> Self {
+        Self {
+            routing_key,
+            tracked: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Track the messages of the [`DeferredStore`], deferred before the service started.
+    pub fn restore(&self, entries: &[DeferredEntry]) {
+        let now = time::OffsetDateTime::now_utc();
+        for entry in entries {
+            match Ctx::<CtxDelivery>::from_json(entry.payload.as_bytes()) {
+                Ok(ctx) => self.set(
+                    entry.uuid,
+                    ctx.metadata.get_first_attempt().unwrap_or(now),
+                    ctx.metadata
+                        .get_undelivered_rcpt()
+                        .map(|rcpt| rcpt.forward_path.0.domain().to_string())
+                        .collect(),
+                ),
+                Err(error) => {
+                    tracing::warn!(uuid = %entry.uuid, %error, "Invalid deferred entry payload");
+                }
+            }
+        }
+    }
+
+    /// Set the destination domains for which the message is deferred, `since` being
+    /// the end of its first delivery attempt. An empty set means the message has left
+    /// the deferred flow.
+    pub fn set(
+        &self,
+        message: uuid::Uuid,
+        since: time::OffsetDateTime,
+        domains: std::collections::HashSet<String>,
+    ) {
+        let mut tracked = self.tracked.lock().unwrap();
+        let Tracked {
+            messages,
+            per_domain,
+        } = &mut *tracked;
+
+        let previous = if domains.is_empty() {
+            messages.remove(&message)
+        } else {
+            messages.insert(
+                message,
+                Deferred {
+                    since,
+                    domains: domains.clone(),
+                },
+            )
+        }
+        .map(|previous| previous.domains)
+        .unwrap_or_default();
+
+        for domain in previous.difference(&domains) {
+            if let Some(count) = per_domain.get_mut(domain) {
+                *count = count.saturating_sub(1);
+            }
+        }
+        for domain in domains.difference(&previous) {
+            *per_domain.entry(domain.clone()).or_default() += 1;
+        }
+
+        for domain in previous.symmetric_difference(&domains) {
+            let deferred = per_domain.get(domain).copied().unwrap_or_default();
+            if deferred == 0 {
+                per_domain.remove(domain);
+            }
+            tracing::info!(
+                target: "metrics",
+                routing_key = %self.routing_key,
+                %domain,
+                deferred,
+                "Deferred messages per domain"
+            );
+        }
+    }
+
+    /// Get the number of messages deferred for each destination domain.
+    #[must_use]
+    pub fn snapshot(&self) -> DeferredSnapshot {
+        DeferredSnapshot {
+            routing_key: self.routing_key.clone(),
+            per_domain: self
+                .tracked
+                .lock()
+                .unwrap()
+                .per_domain
+                .iter()
+                .map(|(domain, deferred)| (domain.clone(), *deferred))
+                .collect(),
+        }
+    }
+
+    /// Get the destination domains whose oldest deferred message is older than `threshold`
+    /// at `now`, and emit a warning for each of them.
+    #[must_use]
+    pub fn stuck(
+        &self,
+        threshold: std::time::Duration,
+        now: time::OffsetDateTime,
+    ) -> Vec<StuckDomain> {
+        let mut per_domain =
+            std::collections::BTreeMap::<&str, (std::time::Duration, usize)>::new();
+        let tracked = self.tracked.lock().unwrap();
+        for message in tracked.messages.values() {
+            let age = std::time::Duration::try_from(now - message.since).unwrap_or_default();
+            for domain in &message.domains {
+                let (oldest, deferred) = per_domain.entry(domain).or_default();
+                *oldest = (*oldest).max(age);
+                *deferred += 1;
+            }
+        }
+
+        per_domain
+            .into_iter()
+            .filter(|(_, (oldest, _))| *oldest > threshold)
+            .map(|(domain, (oldest, deferred))| {
+                tracing::warn!(
+                    target: "metrics",
+                    routing_key = %self.routing_key,
+                    %domain,
+                    deferred,
+                    oldest = %humantime::format_duration(oldest),
+                    "Messages stuck in the deferred queue"
+                );
+                StuckDomain {
+                    domain: domain.to_string(),
+                    oldest,
+                    deferred,
+                }
+            })
+            .collect()
+    }
+
+    /// Scan the deferred messages every `alarm.interval`, see [`Self::stuck`].
+    pub(crate) async fn watch_stuck(self: std::sync::Arc<Self>, alarm: StuckAlarm) {
+        let mut interval = tokio::time::interval(alarm.interval);
+        loop {
+            interval.tick().await;
+            let _ = self.stuck(alarm.threshold, time::OffsetDateTime::now_utc());
+        }
+    }
+
+    /// Publish the number of deferred messages to the [`Exchange::Backlog`] exchange
+    /// every few seconds, read by the receivers to defer the recipients of a lagging service.
+    pub(crate) async fn publish_backlog(
+        self: std::sync::Arc<Self>,
+        channel: lapin::Channel,
+    ) -> lapin::Result<()> {
+        channel
+            .exchange_declare(
+                Exchange::Backlog.as_ref(),
+                lapin::ExchangeKind::Fanout,
+                lapin::options::ExchangeDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
+
+        let mut interval = tokio::time::interval(BACKLOG_INTERVAL);
+        loop {
+            interval.tick().await;
+            let deferred = self.tracked.lock().unwrap().messages.len();
+            let backlog = Backlog {
+                routing_key: self.routing_key.clone(),
+                deferred: u32::try_from(deferred).unwrap_or(u32::MAX),
+            };
+
+            channel
+                .basic_publish(
+                    Exchange::Backlog.as_ref(),
+                    "",
+                    lapin::options::BasicPublishOptions::default(),
+                    &serde_json::to_vec(&backlog).expect("backlog is serializable"),
+                    lapin::BasicProperties::default()
+                        .with_content_type(lapin::types::ShortString::from("application/json")),
+                )
+                .await?;
+        }
+    }
+
+    /// Name of the queue receiving the admin queries.
+    #[must_use]
+    pub fn admin_queue(&self) -> String {
+        format!("admin-deferred-{}", self.routing_key)
+    }
+
+    /// Answer the admin queries with a [`DeferredSnapshot`] serialized in JSON,
+    /// published to the `reply_to` queue of the query.
+    pub(crate) async fn serve_admin_queries(
+        self: std::sync::Arc<Self>,
+        channel: lapin::Channel,
+    ) -> lapin::Result<()> {
+        let queue = self.admin_queue();
+        channel
+            .queue_declare(
+                &queue,
+                lapin::options::QueueDeclareOptions {
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
+
+        let mut consumer = channel
+            .basic_consume(
+                &queue,
+                "",
+                lapin::options::BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
+
+        while let Some(query) = consumer.next().await {
+            let query = query?;
+            let Some(reply_to) = query.properties.reply_to() else {
+                tracing::warn!("Admin query without a 'reply_to' queue, ignoring it");
+                continue;
+            };
+
+            let mut properties = lapin::BasicProperties::default();
+            if let Some(correlation_id) = query.properties.correlation_id() {
+                properties = properties.with_correlation_id(correlation_id.clone());
+            }
+
+            channel
+                .basic_publish(
+                    "",
+                    reply_to.as_str(),
+                    lapin::options::BasicPublishOptions::default(),
+                    &serde_json::to_vec(&self.snapshot()).expect("snapshot is serializable"),
+                    properties,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A deferred message waiting for its next delivery attempt.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DeferredEntry {
+    pub uuid: uuid::Uuid,
+    pub routing_key: String,
+    pub next_retry: std::time::SystemTime,
+    /// The message context, serialized in JSON.
+    pub payload: String,
+}
+
+/// Durable copy of the deferred messages, stored as one JSON file per message
+/// in a directory.
+///
+/// The delay of the deferred messages is held by the `x-delayed-message` exchange,
+/// which can be lost on a broker restart. The entries of the store are re-published
+/// with their remaining delay when the delivery service starts.
+///
+/// If the broker did not lose the message, the re-armed copy is a duplicate:
+/// only the first copy consumed claims the entry, see [`DeferredStore::claim`].
+pub struct DeferredStore {
+    path: std::path::PathBuf,
+}
+
+impl DeferredStore {
+    #[must_use]
+    pub fn new(path: std::path::PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn entry_path(&self, uuid: &uuid::Uuid) -> std::path::PathBuf {
+        self.path.join(format!("{uuid}.json"))
+    }
+
+    /// Save (or replace) the entry of a message.
+    ///
+    /// # Errors
+    ///
+    /// * the directory cannot be created
+    /// * the entry cannot be written
+    pub fn save(&self, entry: &DeferredEntry) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.path)?;
+
+        // written then renamed, to never read a partial entry
+        let tmp = self.path.join(format!(".{}.json.tmp", entry.uuid));
+        std::fs::write(
+            &tmp,
+            serde_json::to_vec(entry).expect("entry is serializable"),
+        )?;
+        std::fs::rename(tmp, self.entry_path(&entry.uuid))
+    }
+
+    /// Remove the entry of a message, if any.
+    ///
+    /// # Errors
+    ///
+    /// * the entry cannot be removed
+    pub fn remove(&self, uuid: &uuid::Uuid) -> std::io::Result<()> {
+        match std::fs::remove_file(self.entry_path(uuid)) {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            otherwise => otherwise,
+        }
+    }
+
+    /// Claim the entry of a message consumed from the deferred queue, removing it.
+    ///
+    /// Return `false` if the store holds no entry with this `payload`, the copy is then
+    /// obsolete: another copy has been handled already, or the message has been deferred again.
+    ///
+    /// # Errors
+    ///
+    /// * the entry cannot be read or removed
+    pub fn claim(&self, uuid: &uuid::Uuid, payload: &[u8]) -> std::io::Result<bool> {
+        let path = self.entry_path(uuid);
+        let entry = match std::fs::read(&path) {
+            Ok(entry) => serde_json::from_slice::<DeferredEntry>(&entry)?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+        if entry.payload.as_bytes() != payload {
+            return Ok(false);
+        }
+
+        // the copies are consumed concurrently, only one of them removes the entry.
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Read all the entries of the store, invalid entries are skipped.
+    ///
+    /// # Errors
+    ///
+    /// * the directory cannot be read
+    pub fn load(&self) -> std::io::Result<Vec<DeferredEntry>> {
+        let dir = match std::fs::read_dir(&self.path) {
+            Ok(dir) => dir,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error),
+        };
+
+        let mut entries = vec![];
+        for file in dir {
+            let path = file?.path();
+            if path.extension().and_then(std::ffi::OsStr::to_str) != Some("json") {
+                continue;
+            }
+            match serde_json::from_slice(&std::fs::read(&path)?) {
+                Ok(entry) => entries.push(entry),
+                Err(error) => tracing::warn!(?path, %error, "Skipping invalid deferred entry"),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Publish again all the entries of the store, with the delay remaining at `now`.
+    ///
+    /// Return the entries re-armed.
+    ///
+    /// # Errors
+    ///
+    /// * the store cannot be read
+    pub async fn rearm(
+        &self,
+        backend: &dyn QueueBackend,
+        now: std::time::SystemTime,
+    ) -> std::io::Result<Vec<DeferredEntry>> {
+        let entries = self.load()?;
+        for entry in &entries {
+            let delay = entry
+                .next_retry
+                .duration_since(now)
+                .unwrap_or(std::time::Duration::ZERO);
+
+            tracing::debug!(
+                uuid = %entry.uuid,
+                "Re-arming deferred message, will retry after {}",
+                humantime::format_duration(delay)
+            );
+            backend
+                .write_to_deferred(
+                    &entry.routing_key,
+                    delay,
+                    entry.payload.clone().into_bytes(),
+                )
+                .await;
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeferredEntry, DeferredGauge, DeferredStore, StuckDomain};
+    use crate::testing::recipient;
+    use vsmtp_common::{
+        broker::in_memory::InMemory,
+        ctx::Ctx,
+        ctx_delivery::CtxDelivery,
+        delivery_attempt::{DeliveryAttempt, LocalInformation, ShouldNotify},
+        uuid,
+    };
+
+    fn domains(domains: &[&str]) -> std::collections::HashSet<String> {
+        domains.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn deferring_then_delivering() {
+        let gauge = DeferredGauge::new("basic".to_string());
+        let (first, second) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let since = time::OffsetDateTime::now_utc();
+
+        gauge.set(first, since, domains(&["example.com", "example.org"]));
+        gauge.set(second, since, domains(&["example.com"]));
+        assert_eq!(
+            gauge.snapshot().per_domain,
+            [
+                ("example.com".to_string(), 2),
+                ("example.org".to_string(), 1)
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        // delivered to "example.org", but deferred again for "example.com"
+        gauge.set(first, since, domains(&["example.com"]));
+        assert_eq!(
+            gauge.snapshot().per_domain,
+            [("example.com".to_string(), 2)].into_iter().collect()
+        );
+
+        gauge.set(first, since, domains(&[]));
+        gauge.set(second, since, domains(&[]));
+        assert!(gauge.snapshot().per_domain.is_empty());
+        assert_eq!(gauge.snapshot().routing_key, "basic");
+    }
+
+    #[test]
+    fn stuck_messages_raise_an_alarm() {
+        let gauge = DeferredGauge::new("basic".to_string());
+        let threshold = std::time::Duration::from_secs(3600);
+        let (stuck, delivered) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let first_attempt = time::OffsetDateTime::now_utc();
+
+        gauge.set(stuck, first_attempt, domains(&["example.com"]));
+        gauge.set(delivered, first_attempt, domains(&["example.org"]));
+        assert!(gauge.stuck(threshold, first_attempt).is_empty());
+
+        // deferred again, the age is counted from the first attempt
+        gauge.set(stuck, first_attempt, domains(&["example.com"]));
+        gauge.set(delivered, first_attempt, domains(&[]));
+
+        let later = first_attempt + threshold + std::time::Duration::from_secs(1);
+        let alarms = gauge.stuck(threshold, later);
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].domain, "example.com");
+        assert_eq!(alarms[0].deferred, 1);
+        assert!(alarms[0].oldest > threshold);
+
+        gauge.set(stuck, first_attempt, domains(&[]));
+        assert_eq!(gauge.stuck(threshold, later), Vec::<StuckDomain>::new());
+    }
+
+    #[test]
+    fn age_survives_a_restart() {
+        let threshold = std::time::Duration::from_secs(3600);
+        let now = time::OffsetDateTime::now_utc();
+
+        // a message first attempted by the previous process two hours ago.
+        let gauge = DeferredGauge::new("basic".to_string());
+        gauge.set(
+            uuid::Uuid::new_v4(),
+            now - 2 * threshold,
+            domains(&["example.com"]),
+        );
+
+        let alarms = gauge.stuck(threshold, now);
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].oldest, 2 * threshold);
+    }
+
+    #[test]
+    fn restore_from_the_store() {
+        let mut metadata = CtxDelivery::fake();
+        metadata.rcpt_to = vec![recipient("a@example.com"), recipient("b@example.org")];
+        metadata.last_deliveries = vec![];
+        metadata.attempt = vec![DeliveryAttempt::new_local(
+            recipient("a@example.com").forward_path,
+            LocalInformation::Success,
+            ShouldNotify::all(),
+        )];
+        let ctx = Ctx {
+            variables: std::collections::HashMap::default(),
+            internal: std::collections::HashMap::default(),
+            metadata,
+        };
+
+        let entry = |payload: String| DeferredEntry {
+            uuid: uuid::Uuid::new_v4(),
+            routing_key: "basic".to_string(),
+            next_retry: std::time::SystemTime::now(),
+            payload,
+        };
+
+        let gauge = DeferredGauge::new("basic".to_string());
+        gauge.restore(&[
+            entry(String::from_utf8(ctx.to_json().unwrap()).unwrap()),
+            entry("not a context".to_string()),
+        ]);
+
+        // only the undelivered recipients are deferred.
+        assert_eq!(
+            gauge.snapshot().per_domain,
+            [("example.org".to_string(), 1)].into_iter().collect()
+        );
+    }
+
+    #[tokio::test]
+    async fn rearm_after_restart() {
+        let path = std::env::temp_dir().join(format!("vsmtp-deferred-{}", uuid::Uuid::new_v4()));
+        let now = std::time::SystemTime::now();
+        let (pending, overdue, delivered) = (
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+        );
+
+        {
+            let store = DeferredStore::new(path.clone());
+            for (uuid, next_retry) in [
+                (pending, now + std::time::Duration::from_secs(300)),
+                (overdue, now - std::time::Duration::from_secs(60)),
+                (delivered, now + std::time::Duration::from_secs(60)),
+            ] {
+                store
+                    .save(&DeferredEntry {
+                        uuid,
+                        routing_key: "basic".to_string(),
+                        next_retry,
+                        payload: format!("{{\"uuid\":\"{uuid}\"}}"),
+                    })
+                    .unwrap();
+            }
+            store.remove(&delivered).unwrap();
+            store.remove(&delivered).unwrap();
+        }
+
+        // the service restarts 100 seconds later
+        let store = DeferredStore::new(path.clone());
+        let backend = InMemory::default();
+        let rearmed = store
+            .rearm(&backend, now + std::time::Duration::from_secs(100))
+            .await
+            .unwrap();
+        assert_eq!(rearmed.len(), 2);
+
+        let mut deferred = backend
+            .delays()
+            .into_iter()
+            .zip(backend.messages("deferred-basic"))
+            .map(|((routing_key, delay), payload)| (routing_key, delay, payload))
+            .collect::<Vec<_>>();
+        deferred.sort_by_key(|(_, delay, _)| *delay);
+        assert_eq!(
+            deferred,
+            vec![
+                (
+                    "basic".to_string(),
+                    std::time::Duration::ZERO,
+                    format!("{{\"uuid\":\"{overdue}\"}}").into_bytes()
+                ),
+                (
+                    "basic".to_string(),
+                    std::time::Duration::from_secs(200),
+                    format!("{{\"uuid\":\"{pending}\"}}").into_bytes()
+                ),
+            ]
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn only_one_copy_is_claimed() {
+        let path = std::env::temp_dir().join(format!("vsmtp-deferred-{}", uuid::Uuid::new_v4()));
+        let store = DeferredStore::new(path.clone());
+        let uuid = uuid::Uuid::new_v4();
+        let entry = |payload: &str| DeferredEntry {
+            uuid,
+            routing_key: "basic".to_string(),
+            next_retry: std::time::SystemTime::now(),
+            payload: payload.to_string(),
+        };
+
+        store.save(&entry("first")).unwrap();
+        // the original copy and the re-armed one are consumed
+        assert!(store.claim(&uuid, b"first").unwrap());
+        assert!(!store.claim(&uuid, b"first").unwrap());
+
+        // deferred again, the other copy of the previous deferral is obsolete
+        store.save(&entry("second")).unwrap();
+        assert!(!store.claim(&uuid, b"first").unwrap());
+        assert!(store.claim(&uuid, b"second").unwrap());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn load_missing_store() {
+        let path = std::env::temp_dir().join(format!("vsmtp-deferred-{}", uuid::Uuid::new_v4()));
+        assert!(DeferredStore::new(path).load().unwrap().is_empty());
+    }
+}
@@ -22,22 +22,57 @@ pub use send::send;
 
 use std::sync::Arc;
 use tokio_stream::StreamExt;
+use tracing::Instrument;
 use vsmtp_common::{
-    api::{write_to_dead, write_to_deferred, write_to_report_dsn},
-    broker::{Exchange, Queue},
+    broker::{subscribe, Exchange, Priority, Queue, QueueBackend},
     ctx::Ctx,
     ctx_delivery::CtxDelivery,
-    delivery_attempt::{Action, DeliveryAttempt, ShouldNotify},
+    delivery_attempt::{Action, DeliveryAttempt, LocalInformation, ShouldNotify},
     delivery_route::DeliveryRoute,
+    telemetry::message_span,
     Recipient,
 };
 use vsmtp_config::Config;
+use vsmtp_mail_parser::mail::headers::Header;
 use vsmtp_protocol::NotifyOn;
 
+mod backscatter;
+pub use backscatter::{BounceGuard, Forged, SenderLookup};
+mod bounce;
+pub use bounce::{Bounce, BounceClass, BounceTemplates};
+mod dead;
+pub use dead::{inspect_dead, requeue_dead, reset_attempts, DeadMessage};
+mod deferred;
+pub use deferred::{
+    DeferredEntry, DeferredGauge, DeferredSnapshot, DeferredStore, StuckAlarm, StuckDomain,
+};
 mod frequency;
 pub use frequency::Frequency;
+mod loops;
+pub use loops::LoopDetection;
+mod maildir;
+pub use maildir::{MaildirDelivery, UserLookup};
+mod mbox;
+pub use mbox::MboxDelivery;
+mod pipe;
+pub use pipe::PipeDelivery;
+mod privacy;
+pub use privacy::HeaderPrivacy;
+mod report;
+pub use report::{report_main, ReportService, Templates};
+mod retry;
+pub use retry::RetryHint;
+mod smarthost;
+pub use smarthost::{Credentials, Route, Smarthost, SmarthostMap};
+#[cfg(test)]
+mod testing;
+pub use vsmtp_common::timeouts::Timeouts;
 mod tls;
 pub use tls::{Requirement, Tls};
+mod transport;
+pub use transport::{Transport, Transports};
+mod window;
+pub use window::{DeliveryWindow, ParseErrorDeliveryWindow};
 
 pub enum DeliveryOutcome {
     Success,
@@ -45,51 +80,78 @@ pub enum DeliveryOutcome {
     Dead,
 }
 
-/// Quick check to determine if the delivery method should produce a DSN,
+/// Aggregate the recipients to report in a single DSN for a delivery cycle,
 /// based on the delivery attempts, the notification supported by the delivery method and the
 /// notification parameters requested for each recipients.
 ///
-/// Return true if only one recipient should produce a DSN.
-#[allow(clippy::cognitive_complexity)] // tracing
-fn should_produce_dsn(attempts: &[DeliveryAttempt], recipients: &[Recipient]) -> bool {
-    for attempt in attempts {
-        for (idx, rcpt) in attempt.recipients().enumerate() {
-            let Some(rcpt) = recipients.iter().find(|i| i.forward_path.0 == rcpt.0) else {
-                continue;
+/// Each recipient is reported once, with the action of its last attempt of the cycle, and
+/// a delay is not reported again if it has already been during a `previous` cycle.
+fn reportable_recipients(
+    attempts: &[DeliveryAttempt],
+    previous: &[DeliveryAttempt],
+    recipients: &[Recipient],
+) -> Vec<Recipient> {
+    let is_reported_delay = |attempt: &DeliveryAttempt, idx: usize| {
+        matches!(attempt.get_action(idx), Action::Delayed { .. })
+            && attempt.should_notify_on(ShouldNotify::Delay)
+    };
+
+    recipients
+        .iter()
+        .filter(|rcpt| {
+            let NotifyOn::Some {
+                success,
+                failure,
+                delay,
+            } = rcpt.notify_on
+            else {
+                return false;
             };
 
-            match rcpt.notify_on {
-                NotifyOn::Never => continue,
-                NotifyOn::Some {
-                    success,
-                    failure,
-                    delay,
-                } => match attempt.get_action(idx) {
-                    Action::Failed { .. }
-                        if failure && attempt.should_notify_on(ShouldNotify::Failure) =>
-                    {
-                        return true
-                    }
-                    Action::Delayed { .. }
-                        if delay && attempt.should_notify_on(ShouldNotify::Delay) =>
-                    {
-                        return true
-                    }
-                    Action::Delivered
-                        if success && attempt.should_notify_on(ShouldNotify::Success) =>
-                    {
-                        return true
-                    }
-                    // TODO:
-                    Action::Relayed => todo!(),
-                    Action::Expanded => todo!(),
-                    _ => continue,
-                },
+            let Some((attempt, idx)) = attempts
+                .iter()
+                .rev()
+                .find_map(|attempt| attempt.get_rcpt_index(rcpt).map(|idx| (attempt, idx)))
+            else {
+                return false;
+            };
+
+            match attempt.get_action(idx) {
+                Action::Failed { .. } => failure && attempt.should_notify_on(ShouldNotify::Failure),
+                Action::Delayed { .. } => {
+                    delay
+                        && attempt.should_notify_on(ShouldNotify::Delay)
+                        && !previous.iter().any(|attempt| {
+                            attempt
+                                .get_rcpt_index(rcpt)
+                                .is_some_and(|idx| is_reported_delay(attempt, idx))
+                        })
+                }
+                Action::Delivered => success && attempt.should_notify_on(ShouldNotify::Success),
+                // TODO:
+                Action::Relayed => todo!(),
+                Action::Expanded => todo!(),
             }
-        }
-    }
+        })
+        .cloned()
+        .collect()
+}
+
+/// Content of a message on final delivery (maildir, mbox, pipe): any existing `Return-Path`
+/// header is removed and a new one matching the envelope sender is prepended.
+/// see <https://www.rfc-editor.org/rfc/rfc5321#section-4.4>
+fn final_delivery_content(ctx: &CtxDelivery) -> String {
+    let mut mail = ctx.mail.read().unwrap().clone();
+    while mail.remove_header("Return-Path") {}
 
-    false
+    let reverse_path = ctx
+        .mail_from
+        .reverse_path
+        .as_ref()
+        .map_or_else(String::new, ToString::to_string);
+    mail.prepend_headers([Header::new("Return-Path", format!("<{reverse_path}>"))]);
+
+    mail.to_string()
 }
 
 #[async_trait::async_trait]
@@ -104,22 +166,155 @@ pub trait DeliverySystem: Send + Sync {
         std::time::Duration::ZERO
     }
 
+    /// Directory where the deferred messages are persisted, to be re-armed
+    /// when the service restarts. Nothing is persisted if `None`.
+    fn deferred_store(&self) -> Option<&std::path::Path> {
+        None
+    }
+
+    /// Alarm raised when messages stay deferred for too long. No alarm if `None`.
+    fn stuck_alarm(&self) -> Option<StuckAlarm> {
+        None
+    }
+
+    /// Guard checking the sender before a DSN is emitted, to avoid backscatter.
+    /// No DSN is suppressed if `None`.
+    fn bounce_guard(&self) -> Option<(&BounceGuard, &dyn SenderLookup)> {
+        None
+    }
+
+    /// Header fields removed from the message before it is delivered, for the
+    /// services sending it out of the system. Nothing is removed if `None`.
+    fn header_privacy(&self) -> Option<&HeaderPrivacy> {
+        None
+    }
+
+    /// Detection of the messages looping through this server, which are not relayed.
+    /// No message is checked if `None`.
+    fn loop_detection(&self) -> Option<&LoopDetection> {
+        None
+    }
+
+    /// Bounds of the delay requested by the remote servers in their 4xx replies.
+    /// The hints are ignored if `None`.
+    fn retry_hint(&self) -> Option<&RetryHint> {
+        None
+    }
+
+    /// Delay before the next attempt of a delivery which failed temporarily.
+    /// The default backoff is used if `None`.
+    fn retry_delay(&self, _ctx: &CtxDelivery) -> Option<std::time::Duration> {
+        None
+    }
+
+    /// Window during which the recipients of `domain` are delivered.
+    /// Delivered at any time if `None`.
+    fn delivery_window(&self, _ctx: &CtxDelivery, _domain: &str) -> Option<&DeliveryWindow> {
+        None
+    }
+
     #[tracing::instrument(skip_all, fields(
         uuid = ?ctx.metadata.uuid.to_string()[0..8],
         retry = ctx.metadata.attempt.len()),
     )]
-    async fn do_delivery(self: Arc<Self>, channel: &lapin::Channel, mut ctx: Ctx<CtxDelivery>) {
-        let attempts = self.deliver(&ctx.metadata).await;
-        ctx.metadata.last_deliveries = attempts;
+    async fn do_delivery(
+        self: Arc<Self>,
+        backend: &dyn QueueBackend,
+        deferred: &DeferredGauge,
+        store: Option<&DeferredStore>,
+        mut ctx: Ctx<CtxDelivery>,
+    ) {
+        let hops = self
+            .loop_detection()
+            .and_then(|detection| detection.check(&ctx.metadata.mail.read().unwrap()));
+
+        // the recipients outside of the window of their domain, with the delay before it opens.
+        let now = time::OffsetDateTime::now_utc();
+        let held = ctx
+            .metadata
+            .get_undelivered_rcpt()
+            .filter_map(|rcpt| {
+                self.delivery_window(&ctx.metadata, &rcpt.forward_path.0.domain().to_string())
+                    .and_then(|window| window.delay(now))
+                    .map(|delay| (rcpt.clone(), delay))
+            })
+            .collect::<Vec<_>>();
 
-        let should_produce_dsn =
-            should_produce_dsn(&ctx.metadata.last_deliveries, &ctx.metadata.rcpt_to);
-        if should_produce_dsn {
-            tracing::debug!("Message should produce DSN, emitting a report request");
-            write_to_report_dsn(channel, ctx.to_json().unwrap()).await;
+        let attempts = if let Some(hops) = hops {
+            tracing::warn!(
+                hops,
+                "554 5.4.6 Routing loop detected, the message is not relayed"
+            );
+            ctx.metadata
+                .get_undelivered_rcpt()
+                .map(|rcpt| {
+                    DeliveryAttempt::new_local(
+                        rcpt.forward_path.clone(),
+                        LocalInformation::RoutingLoop { hops },
+                        ShouldNotify::Failure,
+                    )
+                })
+                .collect()
         } else {
+            if let Some(privacy) = self.header_privacy() {
+                let stripped = privacy.strip(&mut ctx.metadata.mail.write().unwrap());
+                tracing::trace!(stripped, "Internal header fields removed");
+            }
+
+            if held.is_empty() {
+                Arc::clone(&self).deliver(&ctx.metadata).await
+            } else {
+                tracing::debug!(
+                    count = held.len(),
+                    "Recipients outside of their delivery window, holding them"
+                );
+                // only the recipients within their window are delivered during this cycle.
+                let rcpt_to = ctx.metadata.rcpt_to.clone();
+                ctx.metadata
+                    .rcpt_to
+                    .retain(|rcpt| !held.iter().any(|(held, _)| held == rcpt));
+                let attempts = if ctx.metadata.get_undelivered_rcpt().next().is_some() {
+                    Arc::clone(&self).deliver(&ctx.metadata).await
+                } else {
+                    vec![]
+                };
+                ctx.metadata.rcpt_to = rcpt_to;
+                attempts
+            }
+        };
+        ctx.metadata.last_deliveries = attempts;
+
+        let reportable = reportable_recipients(
+            &ctx.metadata.last_deliveries,
+            &ctx.metadata.attempt,
+            &ctx.metadata.rcpt_to,
+        );
+        let forged = match (reportable.is_empty(), self.bounce_guard()) {
+            (false, Some((guard, lookup))) => {
+                guard.check(lookup, &ctx.metadata.mail_from).await.err()
+            }
+            _ => None,
+        };
+
+        if reportable.is_empty() {
             tracing::debug!("Message should not produce DSN");
+        } else if let Some(forged) = forged {
+            tracing::warn!(%forged, "The sender looks forged, suppressing the DSN");
+        } else {
+            tracing::debug!(
+                count = reportable.len(),
+                "Message should produce DSN, emitting a report request"
+            );
+            // the report request only carries the recipients to report.
+            let rcpt_to = std::mem::replace(&mut ctx.metadata.rcpt_to, reportable);
+            backend.write_to_report_dsn(ctx.to_json().unwrap()).await;
+            ctx.metadata.rcpt_to = rcpt_to;
         }
+        let retry_hint = ctx
+            .metadata
+            .get_retry_hint()
+            .zip(self.retry_hint())
+            .map(|(hint, bounds)| bounds.delay(hint));
         let last_deliveries = std::mem::take(&mut ctx.metadata.last_deliveries);
         ctx.metadata.attempt.extend(last_deliveries);
 
@@ -127,34 +322,88 @@ pub trait DeliverySystem: Send + Sync {
         // one domain will produce one attempt, meaning mails with multiple domains will inevitably reach this threshold
         let status = if ctx.metadata.is_fully_delivered() {
             DeliveryOutcome::Success
-        } else if ctx.metadata.attempt.len() > 10 {
+        } else if hops.is_some() || ctx.metadata.attempt.len() > 10 {
             DeliveryOutcome::Dead
         } else {
             DeliveryOutcome::Delayed
         };
 
+        // the held recipients are not late, they are not tracked as deferred.
+        deferred.set(
+            ctx.metadata.uuid,
+            ctx.metadata.get_first_attempt().unwrap_or(now),
+            if matches!(status, DeliveryOutcome::Delayed) {
+                ctx.metadata
+                    .get_undelivered_rcpt()
+                    .filter(|rcpt| !held.iter().any(|(held, _)| held == *rcpt))
+                    .map(|rcpt| rcpt.forward_path.0.domain().to_string())
+                    .collect()
+            } else {
+                std::collections::HashSet::new()
+            },
+        );
+
+        if let Some(store) = store {
+            if let Err(error) = store.remove(&ctx.metadata.uuid) {
+                tracing::warn!(%error, "Failed to remove the deferred entry");
+            }
+        }
+
         match status {
             DeliveryOutcome::Success => {
                 tracing::debug!("Message has been sent successfully, dropping it");
             }
             DeliveryOutcome::Delayed => {
-                let delay = ctx.metadata.get_delayed_duration();
+                let retry = || {
+                    retry_hint
+                        .or_else(|| self.retry_delay(&ctx.metadata))
+                        .unwrap_or_else(|| ctx.metadata.get_delayed_duration())
+                };
+                // the held recipients are retried when their window opens, the others as usual.
+                let opening = held.iter().map(|(_, delay)| *delay).min();
+                let retrying = ctx
+                    .metadata
+                    .get_undelivered_rcpt()
+                    .any(|rcpt| !held.iter().any(|(held, _)| held == rcpt));
+                let delay = match opening {
+                    Some(opening) if retrying => retry().min(opening),
+                    Some(opening) => opening,
+                    None => retry(),
+                };
 
                 tracing::debug!(
                     "Message delivery failed, will retry after {}",
                     humantime::format_duration(delay)
                 );
 
-                let payload = ctx.to_json().unwrap();
+                ctx.metadata.persisted = store.is_some();
+                let mut payload = ctx.to_json().unwrap();
                 let routing_key = ctx.metadata.routing_key.to_string();
 
-                write_to_deferred(channel, &routing_key, delay, payload).await;
+                if let Some(store) = store {
+                    let entry = DeferredEntry {
+                        uuid: ctx.metadata.uuid,
+                        routing_key: routing_key.clone(),
+                        next_retry: std::time::SystemTime::now() + delay,
+                        payload: String::from_utf8(payload.clone()).expect("json is utf8"),
+                    };
+                    if let Err(error) = store.save(&entry) {
+                        tracing::warn!(%error, "Failed to persist the deferred entry");
+                        // without an entry, the copy must not be taken as obsolete.
+                        ctx.metadata.persisted = false;
+                        payload = ctx.to_json().unwrap();
+                    }
+                }
+
+                backend
+                    .write_to_deferred(&routing_key, delay, payload)
+                    .await;
             }
             DeliveryOutcome::Dead => {
                 tracing::debug!("Message delivery failed too many times, putting it in dead queue");
 
                 let payload = ctx.to_json().unwrap();
-                write_to_dead(channel, payload).await;
+                backend.write_to_dead(payload).await;
             }
         }
     }
@@ -164,7 +413,7 @@ pub trait DeliverySystem: Send + Sync {
 async fn init(
     channel: &lapin::Channel,
     system: &impl DeliverySystem,
-) -> lapin::Result<tokio_stream::StreamMap<String, lapin::Consumer>> {
+) -> lapin::Result<(Vec<String>, String)> {
     channel
         .exchange_declare(
             Exchange::DelayedDeferred.as_ref(),
@@ -236,7 +485,7 @@ async fn init(
         )
         .await?;
 
-    let mut consumers = vec![];
+    let mut queues = vec![];
     let routing_key = system.routing_key().to_string();
     let q_suffix = routing_key.to_string();
     {
@@ -263,17 +512,7 @@ async fn init(
             )
             .await?;
 
-        let consumer = channel
-            .basic_consume(
-                &deferred_q,
-                "",
-                lapin::options::BasicConsumeOptions::default(),
-                lapin::types::FieldTable::default(),
-            )
-            .await?;
-
-        tracing::debug!("Starting consumer for {}", deferred_q);
-        consumers.push((deferred_q, consumer));
+        queues.push(deferred_q);
     }
 
     {
@@ -299,48 +538,119 @@ async fn init(
             )
             .await?;
 
-        let consumer = channel
-            .basic_consume(
-                &delivery_q,
-                "",
-                lapin::options::BasicConsumeOptions::default(),
+        queues.push(delivery_q);
+    }
+
+    let priority_q = {
+        let priority_key = Priority::High.routing_key(&routing_key);
+        let priority_q = format!("{}-{priority_key}", Exchange::Delivery.as_ref());
+        channel
+            .queue_declare(
+                &priority_q,
+                lapin::options::QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
                 lapin::types::FieldTable::default(),
             )
             .await?;
 
-        tracing::debug!("Starting consumer for {}", delivery_q);
-        consumers.push((delivery_q, consumer));
-    }
+        channel
+            .queue_bind(
+                &priority_q,
+                Exchange::Delivery.as_ref(),
+                &priority_key,
+                lapin::options::QueueBindOptions::default(),
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
 
-    Ok(tokio_stream::StreamMap::from_iter(consumers))
+        // the delayed high priority messages are delivered from the same queue.
+        channel
+            .queue_bind(
+                &priority_q,
+                Exchange::DelayedDeferred.as_ref(),
+                &priority_key,
+                lapin::options::QueueBindOptions::default(),
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
+
+        priority_q
+    };
+
+    Ok((queues, priority_q))
 }
 
 pub async fn start_delivery(
     system: std::sync::Arc<impl DeliverySystem + 'static>,
     conn: &lapin::Connection,
+    prefetch_count: u16,
+    priority_prefetch_count: u16,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let channel = conn.create_channel().await?;
     channel
         .confirm_select(lapin::options::ConfirmSelectOptions::default())
         .await?;
-    channel
-        .basic_qos(1, lapin::options::BasicQosOptions::default())
-        .await?;
 
-    let consumer = init(&channel, system.as_ref()).await?;
-    let consumer = tokio_stream::StreamExt::throttle(consumer, system.get_throttle());
+    let (queues, priority_q) = init(&channel, system.as_ref()).await?;
+
+    let backend: Arc<dyn QueueBackend> = Arc::new(channel);
+    let mut consumers = subscribe(backend.as_ref(), prefetch_count, queues).await?;
+    // the prefetch count applies to the consumers started afterward.
+    backend.set_prefetch(priority_prefetch_count).await?;
+    let priority = backend.consume(&priority_q).await?;
+    consumers.insert(priority_q, priority);
+
+    let store = system
+        .deferred_store()
+        .map(|path| Arc::new(DeferredStore::new(path.to_path_buf())));
+    let deferred = Arc::new(DeferredGauge::new(system.routing_key().to_string()));
+    if let Some(store) = &store {
+        let rearmed = store
+            .rearm(backend.as_ref(), std::time::SystemTime::now())
+            .await?;
+        tracing::info!("{} deferred message(s) have been re-armed", rearmed.len());
+        deferred.restore(&rearmed);
+    }
+
+    {
+        let deferred = deferred.clone();
+        let channel = conn.create_channel().await?;
+        tokio::spawn(async move {
+            if let Err(error) = deferred.serve_admin_queries(channel).await {
+                tracing::error!("Failed to serve admin queries: {error}");
+            }
+        });
+    }
+    {
+        let deferred = deferred.clone();
+        let channel = conn.create_channel().await?;
+        tokio::spawn(async move {
+            if let Err(error) = deferred.publish_backlog(channel).await {
+                tracing::error!("Failed to publish the backlog: {error}");
+            }
+        });
+    }
+
+    if let Some(alarm) = system.stuck_alarm() {
+        tokio::spawn(deferred.clone().watch_stuck(alarm));
+    }
+
+    let consumer = tokio_stream::StreamExt::throttle(consumers, system.get_throttle());
 
     tokio::pin!(consumer);
     tracing::info!("Delivery service has been started");
 
     while let Some((_, item)) = consumer.next().await {
         let system = system.clone();
-        let channel = channel.clone();
+        let backend = backend.clone();
+        let deferred = deferred.clone();
+        let store = store.clone();
 
         tokio::spawn(async move {
             let item = item.unwrap();
-            let lapin::message::Delivery { data, .. } = &item;
-            let ctx = match Ctx::<CtxDelivery>::from_json(data) {
+            let mut ctx = match Ctx::<CtxDelivery>::from_json(&item.data) {
                 Err(e) => {
                     tracing::debug!("handle invaliding payload {}", e);
                     return;
@@ -352,11 +662,29 @@ pub async fn start_delivery(
                 Ok(ctx) => ctx,
             };
 
-            item.ack(lapin::options::BasicAckOptions::default())
-                .await
-                .expect("ack");
+            item.ack().await.expect("ack");
 
-            system.clone().do_delivery(&channel, ctx).await;
+            if ctx.metadata.persisted {
+                if let Some(store) = &store {
+                    match store.claim(&ctx.metadata.uuid, &item.data) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            tracing::debug!("Obsolete copy of a deferred message, dropping it");
+                            return;
+                        }
+                        Err(error) => tracing::warn!(%error, "Failed to claim the deferred entry"),
+                    }
+                }
+                ctx.metadata.persisted = false;
+            }
+
+            let uuid = ctx.metadata.mail_from.message_uuid;
+            let span = message_span(&mut ctx, &uuid);
+            system
+                .clone()
+                .do_delivery(backend.as_ref(), &deferred, store.as_deref(), ctx)
+                .instrument(span)
+                .await;
         });
     }
 
@@ -368,5 +696,486 @@ pub async fn delivery_main(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let conn = system.broker().connect().await?;
     vsmtp_common::init_logs(&conn, system.logs(), system.name()).await?;
-    start_delivery(system, &conn).await
+    let prefetch_count = system.broker().prefetch_count;
+    let priority_prefetch_count = system.broker().priority_prefetch_count;
+    start_delivery(system, &conn, prefetch_count, priority_prefetch_count).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BounceGuard, BounceTemplates, DeferredGauge, DeliverySystem, DeliveryWindow, HeaderPrivacy,
+        LoopDetection, SenderLookup,
+    };
+    use std::sync::Arc;
+    use vsmtp_common::{
+        broker::in_memory::InMemory,
+        ctx::Ctx,
+        ctx_delivery::CtxDelivery,
+        delivery_attempt::{DeliveryAttempt, LocalInformation, ShouldNotify},
+        delivery_route::DeliveryRoute,
+        domain_map::DomainMap,
+        Mailbox, Recipient,
+    };
+    use vsmtp_protocol::NotifyOn;
+
+    /// Return the attempts of the next delivery cycle.
+    struct Scripted(
+        std::sync::Mutex<Vec<DeliveryAttempt>>,
+        Option<(BounceGuard, Exists)>,
+    );
+
+    /// Does the sender domain exist?
+    struct Exists(bool);
+
+    #[async_trait::async_trait]
+    impl SenderLookup for Exists {
+        async fn domain_exists(&self, _: &str) -> bool {
+            self.0
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl DeliverySystem for Scripted {
+        fn name(&self) -> &str {
+            "scripted"
+        }
+
+        async fn deliver(self: Arc<Self>, _: &CtxDelivery) -> Vec<DeliveryAttempt> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+
+        fn routing_key(&self) -> DeliveryRoute {
+            DeliveryRoute::Maildir
+        }
+
+        fn bounce_guard(&self) -> Option<(&BounceGuard, &dyn SenderLookup)> {
+            self.1
+                .as_ref()
+                .map(|(guard, lookup)| (guard, lookup as &dyn SenderLookup))
+        }
+    }
+
+    /// Deliver to all the recipients, recording the messages as sent.
+    struct Capture {
+        privacy: Option<HeaderPrivacy>,
+        loops: Option<LoopDetection>,
+        windows: DomainMap<DeliveryWindow>,
+        sent: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl DeliverySystem for Capture {
+        fn name(&self) -> &str {
+            "capture"
+        }
+
+        async fn deliver(self: Arc<Self>, ctx: &CtxDelivery) -> Vec<DeliveryAttempt> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push(ctx.mail.read().unwrap().to_string());
+            ctx.rcpt_to
+                .iter()
+                .map(|rcpt| {
+                    DeliveryAttempt::new_local(
+                        rcpt.forward_path.clone(),
+                        LocalInformation::Success,
+                        ShouldNotify::all(),
+                    )
+                })
+                .collect()
+        }
+
+        fn routing_key(&self) -> DeliveryRoute {
+            DeliveryRoute::Basic
+        }
+
+        fn header_privacy(&self) -> Option<&HeaderPrivacy> {
+            self.privacy.as_ref()
+        }
+
+        fn loop_detection(&self) -> Option<&LoopDetection> {
+            self.loops.as_ref()
+        }
+
+        fn delivery_window(&self, _: &CtxDelivery, domain: &str) -> Option<&DeliveryWindow> {
+            self.windows.get(domain)
+        }
+    }
+
+    fn mailbox(addr: &str) -> Mailbox {
+        Mailbox(addr.parse().unwrap())
+    }
+
+    fn recipient(addr: &str) -> Recipient {
+        Recipient {
+            forward_path: mailbox(addr),
+            original_forward_path: None,
+            notify_on: NotifyOn::Some {
+                success: false,
+                failure: true,
+                delay: true,
+            },
+        }
+    }
+
+    fn attempt(addr: &str, info: LocalInformation) -> DeliveryAttempt {
+        DeliveryAttempt::new_local(mailbox(addr), info, ShouldNotify::all())
+    }
+
+    fn ctx() -> Ctx<CtxDelivery> {
+        let mut metadata = CtxDelivery::fake();
+        metadata.routing_key = DeliveryRoute::Maildir;
+        metadata.rcpt_to = vec![
+            recipient("a@localhost"),
+            recipient("b@localhost"),
+            recipient("c@localhost"),
+            recipient("d@localhost"),
+        ];
+        metadata.last_deliveries = vec![];
+        metadata.attempt = vec![];
+
+        Ctx {
+            variables: std::collections::HashMap::default(),
+            internal: std::collections::HashMap::default(),
+            metadata,
+        }
+    }
+
+    async fn run_cycle(backend: &InMemory, attempts: Vec<DeliveryAttempt>, ctx: Ctx<CtxDelivery>) {
+        run_guarded_cycle(backend, attempts, ctx, None).await;
+    }
+
+    async fn run_guarded_cycle(
+        backend: &InMemory,
+        attempts: Vec<DeliveryAttempt>,
+        ctx: Ctx<CtxDelivery>,
+        guard: Option<(BounceGuard, Exists)>,
+    ) {
+        Arc::new(Scripted(std::sync::Mutex::new(attempts), guard))
+            .do_delivery(
+                backend,
+                &DeferredGauge::new("maildir".to_string()),
+                None,
+                ctx,
+            )
+            .await;
+    }
+
+    fn reported(payload: &[u8]) -> Vec<Recipient> {
+        Ctx::<CtxDelivery>::from_json(payload)
+            .unwrap()
+            .metadata
+            .rcpt_to
+    }
+
+    #[tokio::test]
+    async fn single_combined_report() {
+        let backend = InMemory::default();
+        run_cycle(
+            &backend,
+            vec![
+                attempt("a@localhost", LocalInformation::NotFound),
+                attempt("b@localhost", LocalInformation::PermissionDenied),
+                attempt("c@localhost", LocalInformation::TimedOut),
+                attempt("d@localhost", LocalInformation::Success),
+            ],
+            ctx(),
+        )
+        .await;
+
+        let reports = backend.messages("dsn");
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reported(&reports[0]),
+            vec![
+                recipient("a@localhost"),
+                recipient("b@localhost"),
+                recipient("c@localhost"),
+            ]
+        );
+
+        // the deferred message still targets all the recipients.
+        let deferred = backend.messages("deferred-maildir");
+        assert_eq!(deferred.len(), 1);
+        assert_eq!(reported(&deferred[0]).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn delay_reported_once() {
+        let backend = InMemory::default();
+        run_cycle(
+            &backend,
+            vec![attempt("c@localhost", LocalInformation::TimedOut)],
+            ctx(),
+        )
+        .await;
+        assert_eq!(backend.messages("dsn").len(), 1);
+
+        let retry =
+            Ctx::<CtxDelivery>::from_json(&backend.messages("deferred-maildir")[0]).unwrap();
+        run_cycle(
+            &backend,
+            vec![
+                attempt("c@localhost", LocalInformation::TimedOut),
+                attempt("a@localhost", LocalInformation::NotFound),
+            ],
+            retry,
+        )
+        .await;
+
+        let reports = backend.messages("dsn");
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reported(&reports[1]), vec![recipient("a@localhost")]);
+    }
+
+    #[tokio::test]
+    async fn success_notification() {
+        let mut ctx = ctx();
+        ctx.metadata.mail_from.reverse_path = Some(mailbox("john.doe@example.com"));
+        ctx.metadata.mail = Arc::new(std::sync::RwLock::new(
+            vsmtp_mail_parser::Mail::try_from(concat!(
+                "From: john.doe@example.com\r\n",
+                "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+                "Subject: Quarterly report\r\n",
+                "\r\n",
+                "Hello world!\r\n",
+            ))
+            .unwrap(),
+        ));
+        ctx.metadata.rcpt_to[3].notify_on = NotifyOn::Some {
+            success: true,
+            failure: true,
+            delay: false,
+        };
+
+        let backend = InMemory::default();
+        run_cycle(
+            &backend,
+            vec![
+                attempt("c@localhost", LocalInformation::Success),
+                attempt("d@localhost", LocalInformation::Success),
+            ],
+            ctx,
+        )
+        .await;
+
+        // only the recipient requesting it is notified of the success.
+        let reports = backend.messages("dsn");
+        assert_eq!(reports.len(), 1);
+        let report = Ctx::<CtxDelivery>::from_json(&reports[0]).unwrap();
+
+        let dsn =
+            BounceTemplates::default().render_report(&report.metadata, "mx.example.com", None);
+        let dsn = dsn.unwrap();
+        assert!(dsn.contains("To: <john.doe@example.com>\r\n"));
+        assert!(dsn.contains("Subject: Successful Mail Delivery Report\r\n"));
+        assert!(dsn.contains("\"Quarterly report\" has been delivered to d@localhost.\r\n"));
+        assert!(dsn.contains("Final-Recipient: rfc822; d@localhost\r\n"));
+        assert!(dsn.contains("Action: delivered\r\nStatus: 2.0.0\r\n"));
+        assert!(!dsn.contains("Diagnostic-Code"));
+        assert!(dsn.contains("Subject: Quarterly report\r\n"));
+    }
+
+    #[tokio::test]
+    async fn report_to_forged_sender() {
+        let mut ctx = ctx();
+        ctx.metadata.mail_from.reverse_path = Some(mailbox("john.doe@forged.example"));
+
+        let backend = InMemory::default();
+        run_guarded_cycle(
+            &backend,
+            vec![attempt("a@localhost", LocalInformation::NotFound)],
+            ctx,
+            Some((BounceGuard::default(), Exists(false))),
+        )
+        .await;
+
+        assert!(backend.messages("dsn").is_empty());
+    }
+
+    #[tokio::test]
+    async fn report_to_valid_sender() {
+        let mut ctx = ctx();
+        ctx.metadata.mail_from.reverse_path = Some(mailbox("john.doe@example.com"));
+
+        let backend = InMemory::default();
+        run_guarded_cycle(
+            &backend,
+            vec![attempt("a@localhost", LocalInformation::NotFound)],
+            ctx,
+            Some((BounceGuard::default(), Exists(true))),
+        )
+        .await;
+
+        let reports = backend.messages("dsn");
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reported(&reports[0]), vec![recipient("a@localhost")]);
+    }
+
+    #[tokio::test]
+    async fn internal_headers_are_not_sent_out() {
+        let message = concat!(
+            "From: john.doe@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "X-Spam-Score: 7.5\r\n",
+            "X-Vsmtp-Quarantine: spam\r\n",
+            "Subject: Hello\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+        );
+        let deliver = |privacy| async move {
+            let mut ctx = ctx();
+            ctx.metadata.mail = Arc::new(std::sync::RwLock::new(
+                vsmtp_mail_parser::Mail::try_from(message).unwrap(),
+            ));
+            let system = Arc::new(Capture {
+                privacy,
+                loops: None,
+                windows: DomainMap::default(),
+                sent: std::sync::Mutex::default(),
+            });
+            system
+                .clone()
+                .do_delivery(
+                    &InMemory::default(),
+                    &DeferredGauge::new("basic".to_string()),
+                    None,
+                    ctx,
+                )
+                .await;
+            let mut sent = system.sent.lock().unwrap().clone();
+            assert_eq!(sent.len(), 1);
+            sent.remove(0)
+        };
+
+        let outbound = deliver(Some(HeaderPrivacy::new(["X-Spam-Score", "X-Vsmtp-*"]))).await;
+        assert!(!outbound.contains("X-Spam-Score"));
+        assert!(!outbound.contains("X-Vsmtp-Quarantine"));
+        assert!(outbound.contains("Subject: Hello\r\n"));
+        assert!(outbound.ends_with("\r\n\r\nHello world!\r\n"));
+
+        // the local deliveries keep the internal header fields.
+        let local = deliver(None).await;
+        assert!(local.contains("X-Spam-Score: 7.5\r\n"));
+        assert!(local.contains("X-Vsmtp-Quarantine: spam\r\n"));
+    }
+
+    #[tokio::test]
+    async fn routing_loop_is_not_relayed() {
+        let message = concat!(
+            "Received: from relay.example.net by MX.example.com; Tue, 1 Jan 2030 00:00:03 +0000\r\n",
+            "Received: from mx.example.com by relay.example.net; Tue, 1 Jan 2030 00:00:02 +0000\r\n",
+            "Received: from relay.example.net by mx.example.com; Tue, 1 Jan 2030 00:00:01 +0000\r\n",
+            "Received: from client.example.org by mx.example.com; Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "From: john.doe@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+        );
+        let deliver = |max_hops| async move {
+            let backend = InMemory::default();
+            let mut ctx = ctx();
+            ctx.metadata.mail = Arc::new(std::sync::RwLock::new(
+                vsmtp_mail_parser::Mail::try_from(message).unwrap(),
+            ));
+            let system = Arc::new(Capture {
+                privacy: None,
+                loops: Some(LoopDetection::new(["mx.example.com"], max_hops)),
+                windows: DomainMap::default(),
+                sent: std::sync::Mutex::default(),
+            });
+            system
+                .clone()
+                .do_delivery(
+                    &backend,
+                    &DeferredGauge::new("basic".to_string()),
+                    None,
+                    ctx,
+                )
+                .await;
+            let sent = system.sent.lock().unwrap().len();
+            (backend, sent)
+        };
+
+        // the message went through `mx.example.com` three times.
+        let (backend, sent) = deliver(2).await;
+        assert_eq!(sent, 0);
+        let reports = backend.messages("dsn");
+        assert_eq!(reports.len(), 1);
+        let report = Ctx::<CtxDelivery>::from_json(&reports[0]).unwrap();
+        assert_eq!(report.metadata.rcpt_to.len(), 4);
+        assert_eq!(report.metadata.last_deliveries[0].get_status(0).0, "5.4.6");
+        // a looping message is not retried.
+        assert!(backend.messages("deferred-maildir").is_empty());
+        assert_eq!(backend.messages("dead").len(), 1);
+
+        let (backend, sent) = deliver(3).await;
+        assert_eq!(sent, 1);
+        assert!(backend.messages("dsn").is_empty());
+    }
+
+    /// A window of an hour, opening `offset` from now.
+    fn window(offset: time::Duration) -> DeliveryWindow {
+        let start = time::OffsetDateTime::now_utc().time() + offset;
+        let end = start + time::Duration::HOUR;
+        format!(
+            "{:02}:{:02}-{:02}:{:02}",
+            start.hour(),
+            start.minute(),
+            end.hour(),
+            end.minute()
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn delivery_window() {
+        let gauge = DeferredGauge::new("basic".to_string());
+        let deliver = |window| {
+            let gauge = &gauge;
+            async move {
+                let backend = InMemory::default();
+                let mut ctx = ctx();
+                ctx.metadata.rcpt_to =
+                    vec![recipient("a@partner.example"), recipient("b@localhost")];
+                Arc::new(Capture {
+                    privacy: None,
+                    loops: None,
+                    windows: std::iter::once(("partner.example".to_string(), window)).collect(),
+                    sent: std::sync::Mutex::default(),
+                })
+                .do_delivery(&backend, gauge, None, ctx)
+                .await;
+                backend
+            }
+        };
+
+        // outside of the window, the other recipients are delivered and
+        // the message is deferred until the window opens.
+        let backend = deliver(window(time::Duration::HOUR)).await;
+        let deferred = backend.messages("deferred-maildir");
+        assert_eq!(deferred.len(), 1);
+        let retry = Ctx::<CtxDelivery>::from_json(&deferred[0]).unwrap();
+        assert_eq!(
+            retry.metadata.get_undelivered_rcpt().collect::<Vec<_>>(),
+            [&recipient("a@partner.example")]
+        );
+        let delay = backend.delays()[0].1;
+        assert!(
+            delay > std::time::Duration::from_secs(58 * 60)
+                && delay <= std::time::Duration::from_secs(60 * 60),
+            "{delay:?}"
+        );
+        // holding a recipient is not a delay to report, nor a stuck delivery.
+        assert!(backend.messages("dsn").is_empty());
+        assert!(gauge.snapshot().per_domain.is_empty());
+
+        // within the window, all the recipients are delivered.
+        let backend = deliver(window(-time::Duration::minutes(30))).await;
+        assert!(backend.messages("deferred-maildir").is_empty());
+        assert!(backend.messages("dead").is_empty());
+    }
 }
@@ -0,0 +1,57 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_mail_parser::Mail;
+
+/// Detection of the routing loops going through this server, from the `by` clause
+/// of the `Received` header fields of the message.
+///
+/// A looping message is not relayed, its recipients fail with a `5.4.6` status.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoopDetection {
+    /// Names of this server, as written in the `Received` header fields. Case insensitive.
+    hostnames: Vec<String>,
+    /// Number of times a message can go through this server.
+    #[serde(default = "LoopDetection::default_max_hops")]
+    max_hops: usize,
+}
+
+impl LoopDetection {
+    #[must_use]
+    pub fn new(hostnames: impl IntoIterator<Item = impl Into<String>>, max_hops: usize) -> Self {
+        Self {
+            hostnames: hostnames.into_iter().map(Into::into).collect(),
+            max_hops,
+        }
+    }
+
+    const fn default_max_hops() -> usize {
+        3
+    }
+
+    /// Number of times `mail` went through this server, `None` if it is not looping.
+    #[must_use]
+    pub fn check(&self, mail: &Mail) -> Option<usize> {
+        let hops = mail
+            .received()
+            .into_iter()
+            .filter_map(|received| received.by)
+            .filter(|by| {
+                self.hostnames
+                    .iter()
+                    .any(|hostname| hostname.eq_ignore_ascii_case(by.trim_end_matches('.')))
+            })
+            .count();
+
+        (hops > self.max_hops).then_some(hops)
+    }
+}
@@ -0,0 +1,415 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::{final_delivery_content, DeliverySystem};
+use std::sync::Arc;
+use vsmtp_common::delivery_attempt::{DeliveryAttempt, LocalInformation, ShouldNotify};
+use vsmtp_common::libc::{chown, getpwuid};
+use vsmtp_common::{ctx_delivery::CtxDelivery, delivery_route::DeliveryRoute};
+use vsmtp_config::Config;
+use vsmtp_protocol::Address;
+
+/// How the system user owning the mailbox is found from the recipient address.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserLookup {
+    #[default]
+    LocalPart,
+    FullAddress,
+}
+
+/// Delivery of the messages in the Maildir of local users.
+/// see <https://cr.yp.to/proto/maildir.html>
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct MaildirDelivery {
+    #[serde(skip, default = "default_maildir_hostname")]
+    name: String,
+    api_version: vsmtp_config::semver::VersionReq,
+    #[serde(default, with = "option_group")]
+    group_local: Option<uzers::Group>,
+    #[serde(default)]
+    user_lookup: UserLookup,
+    /// Maildir location of a recipient (by full address), used instead
+    /// of the `~/Maildir` folder of the system user.
+    #[serde(default)]
+    mailboxes: std::collections::HashMap<String, std::path::PathBuf>,
+    #[serde(default)]
+    broker: vsmtp_config::Broker,
+    #[serde(default)]
+    logs: vsmtp_config::Logs,
+    #[serde(skip)]
+    path: std::path::PathBuf,
+}
+
+fn default_maildir_hostname() -> String {
+    "maildir".to_string()
+}
+
+mod option_group {
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<uzers::Group>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match <Option<String> as serde::Deserialize>::deserialize(d)? {
+            Some(group_local) => Ok(Some(uzers::get_group_by_name(&group_local).ok_or_else(
+                || serde::de::Error::custom(format!("Group '{group_local}' does not exist.")),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn serialize<S>(this: &Option<uzers::Group>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match this {
+            Some(group) => serializer.serialize_some(&group.name().to_string_lossy()),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// Generate a file name unique to this delivery, following the Maildir convention:
+/// `<seconds>.M<microseconds>P<pid>Q<deliveries>.<hostname>`.
+fn unique_filename() -> String {
+    static DELIVERIES: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    // '/' and ':' are not allowed in the hostname part of the name.
+    let hostname = hostname::get().map_or_else(
+        |_| "localhost".to_string(),
+        |hostname| {
+            hostname
+                .to_string_lossy()
+                .replace('/', "\\057")
+                .replace(':', "\\072")
+        },
+    );
+
+    format!(
+        "{}.M{}P{}Q{}.{hostname}",
+        now.as_secs(),
+        now.subsec_micros(),
+        std::process::id(),
+        DELIVERIES.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    )
+}
+
+impl MaildirDelivery {
+    #[tracing::instrument(name = "create-maildir", fields(folder = ?path.display()))]
+    fn create_and_chown(
+        path: &std::path::PathBuf,
+        user: Option<&uzers::User>,
+        group_local: &Option<uzers::Group>,
+    ) -> std::io::Result<()> {
+        if path.exists() {
+            tracing::info!("Folder already exists.");
+        } else {
+            tracing::debug!("Creating folder.");
+
+            std::fs::create_dir_all(path)?;
+
+            tracing::trace!(
+                user = user.map_or(u32::MAX, uzers::User::uid),
+                group = group_local.as_ref().map_or(u32::MAX, uzers::Group::gid),
+                "Setting permissions.",
+            );
+
+            chown(
+                path,
+                user.map(uzers::User::uid),
+                group_local.as_ref().map(uzers::Group::gid),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the message in the `tmp` folder of the Maildir, then move it to `new`,
+    /// so that a mail reader never sees a partially written message.
+    ///
+    /// Return the path of the message delivered.
+    fn write(
+        &self,
+        maildir: &std::path::PathBuf,
+        addr: &Address,
+        user: Option<&uzers::User>,
+        content: &[u8],
+    ) -> std::io::Result<std::path::PathBuf> {
+        Self::create_and_chown(maildir, user, &self.group_local)?;
+        for dir in ["new", "tmp", "cur"] {
+            Self::create_and_chown(&maildir.join(dir), user, &self.group_local)?;
+        }
+
+        let filename = unique_filename();
+        let file_in_maildir_tmp = maildir.join("tmp").join(&filename);
+        let file_in_maildir_inbox = maildir.join("new").join(&filename);
+
+        if let Err(error) = self.write_and_move(
+            &file_in_maildir_tmp,
+            &file_in_maildir_inbox,
+            addr,
+            user,
+            content,
+        ) {
+            let _ = std::fs::remove_file(&file_in_maildir_tmp);
+            return Err(error);
+        }
+
+        Ok(file_in_maildir_inbox)
+    }
+
+    fn write_and_move(
+        &self,
+        file_in_maildir_tmp: &std::path::Path,
+        file_in_maildir_inbox: &std::path::Path,
+        addr: &Address,
+        user: Option<&uzers::User>,
+        content: &[u8],
+    ) -> std::io::Result<()> {
+        let email = std::fs::OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(file_in_maildir_tmp)?;
+
+        let mut email_buf = std::io::BufWriter::new(email);
+        std::io::Write::write_all(
+            &mut email_buf,
+            format!("Delivered-To: {addr}\r\n").as_bytes(),
+        )?;
+        std::io::Write::write_all(&mut email_buf, content)?;
+        email_buf
+            .into_inner()
+            .map_err(std::io::IntoInnerError::into_error)?
+            .sync_all()?;
+
+        chown(
+            file_in_maildir_tmp,
+            user.map(uzers::User::uid),
+            self.group_local.as_ref().map(uzers::Group::gid),
+        )?;
+
+        std::fs::rename(file_in_maildir_tmp, file_in_maildir_inbox)
+    }
+
+    /// Get the Maildir of the recipient, and the system user owning it if any.
+    fn lookup(&self, addr: &Address) -> Option<(std::path::PathBuf, Option<uzers::User>)> {
+        let user = uzers::get_user_by_name(match self.user_lookup {
+            UserLookup::LocalPart => addr.local_part(),
+            UserLookup::FullAddress => addr.full(),
+        });
+
+        match (self.mailboxes.get(addr.full()), user) {
+            (Some(maildir), user) => Some((maildir.clone(), user)),
+            (None, Some(user)) => match getpwuid(user.uid()) {
+                Ok(home) => Some((home.join("Maildir"), Some(user))),
+                Err(error) => {
+                    tracing::error!(uid = user.uid(), "Cannot get home directory: {error}");
+                    None
+                }
+            },
+            (None, None) => None,
+        }
+    }
+}
+
+fn get_notification_supported() -> ShouldNotify {
+    ShouldNotify::Success | ShouldNotify::Failure | ShouldNotify::Delay
+}
+
+#[async_trait::async_trait]
+impl DeliverySystem for MaildirDelivery {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn routing_key(&self) -> DeliveryRoute {
+        DeliveryRoute::Maildir
+    }
+
+    async fn deliver(self: Arc<Self>, ctx: &CtxDelivery) -> Vec<DeliveryAttempt> {
+        let content = final_delivery_content(ctx);
+        let mut attempt = vec![];
+
+        for i in ctx.get_undelivered_rcpt() {
+            let addr = &i.forward_path.0;
+
+            match self.lookup(addr).map(|(maildir, user)| {
+                self.write(&maildir, addr, user.as_ref(), content.as_bytes())
+            }) {
+                None => {
+                    tracing::error!(%addr, "Mailbox does not exist, cannot process delivery");
+                    attempt.push(DeliveryAttempt::new_local(
+                        i.forward_path.clone(),
+                        LocalInformation::NotFound,
+                        get_notification_supported(),
+                    ));
+                }
+                Some(Err(e)) => {
+                    tracing::error!(%addr, "Error while writing maildir: {}", e);
+                    attempt.push(DeliveryAttempt::new_local(
+                        i.forward_path.clone(),
+                        e.into(),
+                        get_notification_supported(),
+                    ));
+                }
+                Some(Ok(path)) => {
+                    tracing::info!(%addr, path = ?path.display(), "Message written to maildir successfully");
+                    attempt.push(DeliveryAttempt::new_local(
+                        i.forward_path.clone(),
+                        LocalInformation::Success,
+                        get_notification_supported(),
+                    ));
+                }
+            };
+        }
+
+        attempt
+    }
+}
+
+impl Config for MaildirDelivery {
+    fn api_version(&self) -> &vsmtp_config::semver::VersionReq {
+        &self.api_version
+    }
+
+    fn broker(&self) -> &vsmtp_config::Broker {
+        &self.broker
+    }
+
+    fn logs(&self) -> &vsmtp_config::logs::Logs {
+        &self.logs
+    }
+
+    fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeliverySystem, MaildirDelivery};
+    use crate::testing::delivery;
+    use vsmtp_common::{delivery_route::DeliveryRoute, uuid};
+
+    const MESSAGE: &str = concat!(
+        "From: john.doe@example.com\r\n",
+        "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+        "Subject: maildir\r\n",
+        "\r\n",
+        "Hello world!\r\n",
+    );
+
+    fn temp_maildir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("vsmtp-maildir-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn assert_maildir_filename(filename: &str) {
+        let mut parts = filename.splitn(3, '.');
+        let (seconds, unique, hostname) = (
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+        );
+
+        assert!(seconds.parse::<u64>().is_ok(), "{filename}");
+        let (micros, unique) = unique.strip_prefix('M').unwrap().split_once('P').unwrap();
+        let (pid, deliveries) = unique.split_once('Q').unwrap();
+        assert!(micros.parse::<u32>().is_ok(), "{filename}");
+        assert_eq!(pid.parse::<u32>().unwrap(), std::process::id());
+        assert!(deliveries.parse::<u64>().is_ok(), "{filename}");
+        assert!(!hostname.is_empty() && !hostname.contains(['/', ':']));
+    }
+
+    #[test]
+    fn write_atomically_in_new() {
+        let maildir = temp_maildir();
+        let system = MaildirDelivery::default();
+
+        let path = system
+            .write(
+                &maildir,
+                &"jenny@example.com".parse().unwrap(),
+                None,
+                MESSAGE.as_bytes(),
+            )
+            .unwrap();
+
+        assert_eq!(path.parent().unwrap(), maildir.join("new"));
+        assert_maildir_filename(path.file_name().unwrap().to_str().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("Delivered-To: jenny@example.com\r\n{MESSAGE}")
+        );
+        assert!(std::fs::read_dir(maildir.join("tmp"))
+            .unwrap()
+            .next()
+            .is_none());
+        assert!(maildir.join("cur").is_dir());
+
+        std::fs::remove_dir_all(maildir).unwrap();
+    }
+
+    /// Deliver `message` to a configured mailbox, and return the content written.
+    async fn deliver(message: &str) -> String {
+        let maildir = temp_maildir();
+        let system = std::sync::Arc::new(MaildirDelivery {
+            mailboxes: [("jenny@example.com".to_string(), maildir.clone())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        });
+
+        let attempts = system
+            .deliver(&delivery(
+                DeliveryRoute::Maildir,
+                &["jenny@example.com"],
+                message,
+            ))
+            .await;
+        assert_eq!(attempts.len(), 1);
+        assert!(attempts[0].get_action(0).is_successful());
+
+        let delivered = std::fs::read_dir(maildir.join("new"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(delivered.len(), 1);
+        assert_maildir_filename(delivered[0].file_name().unwrap().to_str().unwrap());
+        let content = std::fs::read_to_string(&delivered[0]).unwrap();
+
+        std::fs::remove_dir_all(maildir).unwrap();
+        content
+    }
+
+    #[tokio::test]
+    async fn deliver_to_configured_mailbox() {
+        assert!(deliver(MESSAGE)
+            .await
+            .starts_with("Delivered-To: jenny@example.com\r\n"));
+    }
+
+    #[tokio::test]
+    async fn return_path_replaced() {
+        let content = deliver(&format!(
+            "Return-Path: <forged@example.org>\r\nReturn-Path: <other@example.org>\r\n{MESSAGE}"
+        ))
+        .await;
+
+        assert_eq!(
+            content,
+            format!("Delivered-To: jenny@example.com\r\nReturn-Path: <john.doe@example.com>\r\n{MESSAGE}")
+        );
+    }
+}
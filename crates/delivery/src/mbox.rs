@@ -0,0 +1,295 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::{final_delivery_content, DeliverySystem, UserLookup};
+use std::sync::Arc;
+use vsmtp_common::delivery_attempt::{DeliveryAttempt, LocalInformation, ShouldNotify};
+use vsmtp_common::libc::{chown, flock_exclusive};
+use vsmtp_common::{ctx_delivery::CtxDelivery, delivery_route::DeliveryRoute, Mailbox};
+use vsmtp_config::Config;
+use vsmtp_protocol::Address;
+
+/// Delivery of the messages by appending them to the mbox file of local users.
+/// see <https://datatracker.ietf.org/doc/html/rfc4155>
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MboxDelivery {
+    #[serde(skip, default = "default_mbox_hostname")]
+    name: String,
+    api_version: vsmtp_config::semver::VersionReq,
+    #[serde(default)]
+    user_lookup: UserLookup,
+    /// Folder containing the mbox of the users, named after them.
+    #[serde(default = "default_spool")]
+    spool: std::path::PathBuf,
+    /// mbox file of a recipient (by full address), used instead
+    /// of the file of the system user in the spool.
+    #[serde(default)]
+    mailboxes: std::collections::HashMap<String, std::path::PathBuf>,
+    #[serde(default)]
+    broker: vsmtp_config::Broker,
+    #[serde(default)]
+    logs: vsmtp_config::Logs,
+    #[serde(skip)]
+    path: std::path::PathBuf,
+}
+
+fn default_mbox_hostname() -> String {
+    "mbox".to_string()
+}
+
+fn default_spool() -> std::path::PathBuf {
+    "/var/mail".into()
+}
+
+/// Escape the lines of the message which could be mistaken for a message separator,
+/// by prefixing any line matching `^>*From ` with a '>'. ("mboxrd" format)
+///
+/// Line endings are converted to LF, and the message always ends with a LF.
+fn escape_from_lines(content: &str) -> String {
+    let mut escaped = String::with_capacity(content.len());
+
+    for line in content.lines() {
+        if line.trim_start_matches('>').starts_with("From ") {
+            escaped.push('>');
+        }
+        escaped.push_str(line);
+        escaped.push('\n');
+    }
+
+    escaped
+}
+
+/// Format the message as an entry of a mbox file: the "From " separator line
+/// with the envelope sender and the delivery date, the escaped message and an empty line.
+fn format_mbox_entry(
+    reverse_path: Option<&Mailbox>,
+    addr: &Address,
+    content: &str,
+    timestamp: time::OffsetDateTime,
+) -> String {
+    let date = timestamp
+        .format(time::macros::format_description!(
+            "[weekday repr:short] [month repr:short] [day padding:space] [hour]:[minute]:[second] [year]"
+        ))
+        .expect("valid format description");
+
+    format!(
+        "From {} {date}\n{}\n",
+        reverse_path.map_or("MAILER-DAEMON", |mailbox| mailbox.0.full()),
+        escape_from_lines(&format!("Delivered-To: {addr}\n{content}")),
+    )
+}
+
+impl MboxDelivery {
+    /// Append the entry at the end of the mbox, holding an exclusive lock on the file
+    /// so that concurrent deliveries do not interleave.
+    fn append(
+        mbox: &std::path::Path,
+        user: Option<&uzers::User>,
+        entry: &str,
+    ) -> std::io::Result<()> {
+        let exists = mbox.exists();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(mbox)?;
+
+        flock_exclusive(&file)?;
+        if !exists {
+            chown(mbox, user.map(uzers::User::uid), None)?;
+        }
+
+        let length = file.metadata()?.len();
+        let written =
+            std::io::Write::write_all(&mut &file, entry.as_bytes()).and_then(|()| file.sync_all());
+
+        // Do not leave a partial message which would corrupt the mbox.
+        if let Err(error) = written {
+            let _ = file.set_len(length);
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Get the mbox of the recipient, and the system user owning it if any.
+    fn lookup(&self, addr: &Address) -> Option<(std::path::PathBuf, Option<uzers::User>)> {
+        let name = match self.user_lookup {
+            UserLookup::LocalPart => addr.local_part(),
+            UserLookup::FullAddress => addr.full(),
+        };
+        let user = uzers::get_user_by_name(name);
+
+        match (self.mailboxes.get(addr.full()), user) {
+            (Some(mbox), user) => Some((mbox.clone(), user)),
+            (None, Some(user)) => Some((self.spool.join(name), Some(user))),
+            (None, None) => None,
+        }
+    }
+}
+
+fn get_notification_supported() -> ShouldNotify {
+    ShouldNotify::Success | ShouldNotify::Failure | ShouldNotify::Delay
+}
+
+#[async_trait::async_trait]
+impl DeliverySystem for MboxDelivery {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn routing_key(&self) -> DeliveryRoute {
+        DeliveryRoute::Mbox
+    }
+
+    async fn deliver(self: Arc<Self>, ctx: &CtxDelivery) -> Vec<DeliveryAttempt> {
+        let content = final_delivery_content(ctx);
+        let timestamp = time::OffsetDateTime::now_utc();
+        let mut attempt = vec![];
+
+        for i in ctx.get_undelivered_rcpt() {
+            let addr = &i.forward_path.0;
+
+            match self.lookup(addr).map(|(mbox, user)| {
+                let entry = format_mbox_entry(
+                    ctx.mail_from.reverse_path.as_ref(),
+                    addr,
+                    &content,
+                    timestamp,
+                );
+                Self::append(&mbox, user.as_ref(), &entry)
+            }) {
+                None => {
+                    tracing::error!(%addr, "Mailbox does not exist, cannot process delivery");
+                    attempt.push(DeliveryAttempt::new_local(
+                        i.forward_path.clone(),
+                        LocalInformation::NotFound,
+                        get_notification_supported(),
+                    ));
+                }
+                Some(Err(e)) => {
+                    tracing::error!(%addr, "Error while writing mbox: {}", e);
+                    attempt.push(DeliveryAttempt::new_local(
+                        i.forward_path.clone(),
+                        e.into(),
+                        get_notification_supported(),
+                    ));
+                }
+                Some(Ok(())) => {
+                    tracing::info!(%addr, "Message appended to mbox successfully");
+                    attempt.push(DeliveryAttempt::new_local(
+                        i.forward_path.clone(),
+                        LocalInformation::Success,
+                        get_notification_supported(),
+                    ));
+                }
+            };
+        }
+
+        attempt
+    }
+}
+
+impl Config for MboxDelivery {
+    fn api_version(&self) -> &vsmtp_config::semver::VersionReq {
+        &self.api_version
+    }
+
+    fn broker(&self) -> &vsmtp_config::Broker {
+        &self.broker
+    }
+
+    fn logs(&self) -> &vsmtp_config::logs::Logs {
+        &self.logs
+    }
+
+    fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_from_lines, format_mbox_entry, MboxDelivery};
+    use vsmtp_common::{uuid, Mailbox};
+
+    #[test]
+    fn from_munging() {
+        assert_eq!(
+            escape_from_lines(
+                "Subject: mbox\r\n\r\nFrom the start\r\n>From a quote\r\nFromage\r\n from\r\n"
+            ),
+            "Subject: mbox\n\n>From the start\n>>From a quote\nFromage\n from\n"
+        );
+    }
+
+    #[test]
+    fn entry_format() {
+        let entry = format_mbox_entry(
+            Some(&Mailbox("john.doe@example.com".parse().unwrap())),
+            &"jenny@example.com".parse().unwrap(),
+            "Subject: mbox\r\n\r\nFrom here\r\n",
+            time::macros::datetime!(2023-11-06 9:05:03 UTC),
+        );
+
+        assert_eq!(
+            entry,
+            [
+                "From john.doe@example.com Mon Nov  6 09:05:03 2023\n",
+                "Delivered-To: jenny@example.com\n",
+                "Subject: mbox\n",
+                "\n",
+                ">From here\n",
+                "\n",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn concurrent_appends_do_not_interleave() {
+        let mbox = std::env::temp_dir().join(format!("vsmtp-mbox-{}", uuid::Uuid::new_v4()));
+        let addr = "jenny@example.com".parse().unwrap();
+        let senders = ["a", "b", "c", "d", "e", "f", "g", "h"];
+
+        std::thread::scope(|scope| {
+            for sender in senders {
+                let (mbox, addr) = (&mbox, &addr);
+                scope.spawn(move || {
+                    let content =
+                        format!("Subject: {sender}\r\n\r\n{}\r\n", sender.repeat(100_000));
+                    for _ in 0..4 {
+                        let entry = format_mbox_entry(
+                            None,
+                            addr,
+                            &content,
+                            time::OffsetDateTime::now_utc(),
+                        );
+                        MboxDelivery::append(mbox, None, &entry).unwrap();
+                    }
+                });
+            }
+        });
+
+        let content = std::fs::read_to_string(&mbox).unwrap();
+        let entries = content.split("\n\nFrom MAILER-DAEMON ").collect::<Vec<_>>();
+        assert_eq!(entries.len(), senders.len() * 4);
+
+        for entry in entries {
+            let (_, message) = entry.split_once("\nSubject: ").unwrap();
+            let (sender, body) = message.split_once("\n\n").unwrap();
+            assert_eq!(body.trim_end(), sender.repeat(100_000));
+        }
+
+        std::fs::remove_file(mbox).unwrap();
+    }
+}
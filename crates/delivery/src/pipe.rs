@@ -0,0 +1,342 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::{final_delivery_content, DeliverySystem};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use vsmtp_common::delivery_attempt::{DeliveryAttempt, LocalInformation, ShouldNotify};
+use vsmtp_common::{ctx_delivery::CtxDelivery, delivery_route::DeliveryRoute, Mailbox};
+use vsmtp_config::Config;
+use vsmtp_protocol::Address;
+
+/// Exit code of a command reporting a temporary failure, see `sysexits.h`.
+const EX_TEMPFAIL: i32 = 75;
+
+/// Delivery of the messages by piping them to the standard input of a command,
+/// run once per recipient. (procmail-style)
+///
+/// The envelope is given to the command with the `SENDER` and `RECIPIENT`
+/// environment variables.
+/// The exit code 0 means the message is delivered, 75 (`EX_TEMPFAIL`) that the
+/// delivery must be retried later, and any other code that the delivery failed.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipeDelivery {
+    #[serde(skip, default = "default_pipe_hostname")]
+    name: String,
+    api_version: vsmtp_config::semver::VersionReq,
+    /// Command to run and its arguments.
+    args: Vec<String>,
+    /// Maximum duration of the command, which is killed once reached.
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    timeout: std::time::Duration,
+    #[serde(default)]
+    broker: vsmtp_config::Broker,
+    #[serde(default)]
+    logs: vsmtp_config::Logs,
+    #[serde(skip)]
+    path: std::path::PathBuf,
+}
+
+fn default_pipe_hostname() -> String {
+    "pipe".to_string()
+}
+
+const fn default_timeout() -> std::time::Duration {
+    std::time::Duration::from_secs(60)
+}
+
+fn exit_status_to_information(status: std::process::ExitStatus) -> LocalInformation {
+    match status.code() {
+        Some(0) => LocalInformation::Success,
+        Some(EX_TEMPFAIL) => LocalInformation::CommandTemporaryFailure {
+            exit_code: EX_TEMPFAIL,
+        },
+        exit_code => LocalInformation::CommandFailure { exit_code },
+    }
+}
+
+impl PipeDelivery {
+    /// Run the command for one recipient, writing the message on its standard input.
+    async fn run(
+        &self,
+        reverse_path: Option<&Mailbox>,
+        addr: &Address,
+        content: &[u8],
+    ) -> LocalInformation {
+        let Some((program, args)) = self.args.split_first() else {
+            return LocalInformation::OtherError("no command to run".to_string());
+        };
+
+        let mut command = tokio::process::Command::new(program);
+        command
+            .args(args)
+            .env(
+                "SENDER",
+                reverse_path.map_or("", |mailbox| mailbox.0.full()),
+            )
+            .env("RECIPIENT", addr.full())
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true);
+        tracing::trace!(?command, "Running command.");
+
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(error) => return error.into(),
+        };
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+
+        let outcome = tokio::time::timeout(self.timeout, async {
+            match stdin.write_all(content).await {
+                // The command is not required to read the whole message.
+                Err(error) if error.kind() != std::io::ErrorKind::BrokenPipe => {
+                    return Err(error);
+                }
+                _ => drop(stdin),
+            }
+            child.wait().await
+        })
+        .await;
+
+        match outcome {
+            Ok(Ok(status)) => exit_status_to_information(status),
+            Ok(Err(error)) => error.into(),
+            Err(_elapsed) => {
+                tracing::warn!(timeout = ?self.timeout, "Command timed out, killing it.");
+                if let Err(error) = child.kill().await {
+                    tracing::error!("Failed to kill the command: {error}");
+                }
+                LocalInformation::TimedOut
+            }
+        }
+    }
+}
+
+fn get_notification_supported() -> ShouldNotify {
+    ShouldNotify::Success | ShouldNotify::Failure | ShouldNotify::Delay
+}
+
+#[async_trait::async_trait]
+impl DeliverySystem for PipeDelivery {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn routing_key(&self) -> DeliveryRoute {
+        DeliveryRoute::Pipe
+    }
+
+    async fn deliver(self: Arc<Self>, ctx: &CtxDelivery) -> Vec<DeliveryAttempt> {
+        let content = final_delivery_content(ctx);
+        let mut attempt = vec![];
+
+        for i in ctx.get_undelivered_rcpt() {
+            let addr = &i.forward_path.0;
+            let information = self
+                .run(
+                    ctx.mail_from.reverse_path.as_ref(),
+                    addr,
+                    content.as_bytes(),
+                )
+                .await;
+
+            tracing::info!(%addr, ?information, "Command run for the recipient");
+            attempt.push(DeliveryAttempt::new_local(
+                i.forward_path.clone(),
+                information,
+                get_notification_supported(),
+            ));
+        }
+
+        attempt
+    }
+}
+
+impl Config for PipeDelivery {
+    fn api_version(&self) -> &vsmtp_config::semver::VersionReq {
+        &self.api_version
+    }
+
+    fn broker(&self) -> &vsmtp_config::Broker {
+        &self.broker
+    }
+
+    fn logs(&self) -> &vsmtp_config::logs::Logs {
+        &self.logs
+    }
+
+    fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PipeDelivery;
+    use crate::{
+        testing::{delivery, recipient},
+        DeliverySystem,
+    };
+    use vsmtp_common::{
+        delivery_attempt::{Action, DeliveryAttempt, LocalInformation, ShouldNotify},
+        delivery_route::DeliveryRoute,
+        uuid, Mailbox,
+    };
+
+    const MESSAGE: &str = concat!(
+        "From: john.doe@example.com\r\n",
+        "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+        "Subject: pipe\r\n",
+        "\r\n",
+        "Hello world!\r\n",
+    );
+
+    fn pipe(args: &[&str], timeout: std::time::Duration) -> PipeDelivery {
+        PipeDelivery {
+            name: super::default_pipe_hostname(),
+            api_version: vsmtp_config::semver::VersionReq::default(),
+            args: args.iter().map(ToString::to_string).collect(),
+            timeout,
+            broker: vsmtp_config::Broker::default(),
+            logs: vsmtp_config::Logs::default(),
+            path: std::path::PathBuf::default(),
+        }
+    }
+
+    async fn run(system: &PipeDelivery) -> LocalInformation {
+        system
+            .run(
+                Some(&Mailbox("john.doe@example.com".parse().unwrap())),
+                &"jenny@example.com".parse().unwrap(),
+                MESSAGE.as_bytes(),
+            )
+            .await
+    }
+
+    fn action(information: LocalInformation) -> Action {
+        DeliveryAttempt::new_local(
+            Mailbox("jenny@example.com".parse().unwrap()),
+            information,
+            ShouldNotify::empty(),
+        )
+        .get_action(0)
+    }
+
+    #[tokio::test]
+    async fn success() {
+        let output = std::env::temp_dir().join(format!("vsmtp-pipe-{}", uuid::Uuid::new_v4()));
+        let system = pipe(
+            &[
+                "sh",
+                "-c",
+                "{ echo \"$SENDER $RECIPIENT\"; cat; } > \"$0\"",
+                output.to_str().unwrap(),
+            ],
+            std::time::Duration::from_secs(10),
+        );
+
+        let information = run(&system).await;
+        assert!(matches!(information, LocalInformation::Success));
+        assert!(matches!(action(information), Action::Delivered));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            format!("john.doe@example.com jenny@example.com\n{MESSAGE}")
+        );
+
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn temporary_failure() {
+        let system = pipe(
+            &["sh", "-c", "cat > /dev/null; exit 75"],
+            std::time::Duration::from_secs(10),
+        );
+
+        let information = run(&system).await;
+        assert!(matches!(
+            information,
+            LocalInformation::CommandTemporaryFailure { exit_code: 75 }
+        ));
+        assert!(matches!(action(information), Action::Delayed { .. }));
+    }
+
+    #[tokio::test]
+    async fn permanent_failure() {
+        let system = pipe(&["sh", "-c", "exit 1"], std::time::Duration::from_secs(10));
+
+        let information = run(&system).await;
+        assert!(matches!(
+            information,
+            LocalInformation::CommandFailure { exit_code: Some(1) }
+        ));
+        assert!(matches!(action(information), Action::Failed { .. }));
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let system = pipe(&["sleep", "30"], std::time::Duration::from_millis(100));
+
+        let start = std::time::Instant::now();
+        let information = run(&system).await;
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        assert!(matches!(information, LocalInformation::TimedOut));
+        assert!(matches!(action(information), Action::Delayed { .. }));
+    }
+
+    #[tokio::test]
+    async fn retry_only_targets_pending_recipients() {
+        let output = std::env::temp_dir().join(format!("vsmtp-pipe-{}", uuid::Uuid::new_v4()));
+        let system = std::sync::Arc::new(pipe(
+            &[
+                "sh",
+                "-c",
+                "cat > /dev/null; echo \"$RECIPIENT\" >> \"$0\"; \
+                 [ \"$RECIPIENT\" = b@example.com ] && exit 75; exit 0",
+                output.to_str().unwrap(),
+            ],
+            std::time::Duration::from_secs(10),
+        ));
+
+        let mut ctx = delivery(
+            DeliveryRoute::Pipe,
+            &["a@example.com", "b@example.com"],
+            MESSAGE,
+        );
+
+        let attempts = system.clone().deliver(&ctx).await;
+        assert_eq!(attempts.len(), 2);
+        ctx.attempt.extend(attempts);
+
+        assert!(ctx.is_rcpt_delivered(&recipient("a@example.com")));
+        assert!(!ctx.is_rcpt_delivered(&recipient("b@example.com")));
+        assert!(!ctx.is_fully_delivered());
+
+        let attempts = system.deliver(&ctx).await;
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(
+            attempts[0].recipients().collect::<Vec<_>>(),
+            [&Mailbox("b@example.com".parse().unwrap())]
+        );
+        assert!(matches!(attempts[0].get_action(0), Action::Delayed { .. }));
+        ctx.attempt.extend(attempts);
+
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "a@example.com\nb@example.com\nb@example.com\n"
+        );
+        assert!(!ctx.is_fully_delivered());
+
+        std::fs::remove_file(output).unwrap();
+    }
+}
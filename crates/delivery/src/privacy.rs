@@ -0,0 +1,75 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_mail_parser::Mail;
+
+/// Header fields added during the processing of the message (quarantine reasons,
+/// scores, ...) which must not leak to the external recipients.
+///
+/// The fields are removed by the delivery services sending the messages out of the
+/// system, the local delivery services keep them.
+#[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderPrivacy {
+    /// Name of the header fields to remove, case insensitive.
+    /// A trailing `*` matches any suffix, ex: `X-Vsmtp-*`.
+    strip: Vec<String>,
+}
+
+impl HeaderPrivacy {
+    #[must_use]
+    pub fn new(strip: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            strip: strip.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Does the header field `name` match one of the patterns?
+    #[must_use]
+    pub fn is_internal(&self, name: &str) -> bool {
+        self.strip.iter().any(|pattern| {
+            pattern.strip_suffix('*').map_or_else(
+                || pattern.eq_ignore_ascii_case(name),
+                |prefix| {
+                    name.get(..prefix.len())
+                        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+                },
+            )
+        })
+    }
+
+    /// Remove the internal header fields of `mail`, returning the number of fields removed.
+    pub fn strip(&self, mail: &mut Mail) -> usize {
+        let count = mail.headers.len();
+        mail.headers
+            .retain(|header| !self.is_internal(&header.name));
+        count - mail.headers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderPrivacy;
+
+    #[test]
+    fn patterns() {
+        let privacy = HeaderPrivacy::new(["X-Spam-Score", "x-vsmtp-*"]);
+
+        assert!(privacy.is_internal("X-Spam-Score"));
+        assert!(privacy.is_internal("x-spam-score"));
+        assert!(!privacy.is_internal("X-Spam-Score-Details"));
+        assert!(privacy.is_internal("X-VSMTP-Quarantine"));
+        assert!(privacy.is_internal("x-vsmtp-"));
+        assert!(!privacy.is_internal("X-Vsmtp"));
+        assert!(!privacy.is_internal("Subject"));
+        assert!(!HeaderPrivacy::default().is_internal("X-Spam-Score"));
+    }
+}
@@ -0,0 +1,382 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::BounceTemplates;
+use tokio_stream::StreamExt;
+use vsmtp_common::{
+    broker::{subscribe, Queue, QueueBackend},
+    ctx::Ctx,
+    ctx_delivery::CtxDelivery,
+    delivery_route::DeliveryRoute,
+    stateful_ctx_received::MailFromProps,
+    uuid, Recipient,
+};
+use vsmtp_config::Config;
+use vsmtp_mail_parser::Mail;
+use vsmtp_protocol::{MimeBodyType, NotifyOn};
+
+/// Templates of the notifications, see [`BounceTemplates`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Templates {
+    /// Directory of the templates, by language. The built-in english templates are used if `None`.
+    #[serde(default)]
+    pub path: Option<std::path::PathBuf>,
+    /// Language used when the original message has no `Content-Language` header,
+    /// or no template in its language.
+    #[serde(default = "default_language")]
+    pub default_language: String,
+}
+
+fn default_language() -> String {
+    "en".to_string()
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            path: None,
+            default_language: default_language(),
+        }
+    }
+}
+
+/// Service emitting the delivery status notifications requested by the delivery services
+/// in the [`Queue::DSN`] queue. The notifications are rendered with the [`BounceTemplates`],
+/// and sent to the reverse path of the original message with a null reverse path.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReportService {
+    api_version: vsmtp_config::semver::VersionReq,
+    /// Name of the server emitting the notifications, in their `From` and `Reporting-MTA` fields.
+    #[serde(default = "default_reporting_mta")]
+    reporting_mta: String,
+    /// Delivery service of the notifications.
+    #[serde(default = "default_routing_key")]
+    routing_key: DeliveryRoute,
+    #[serde(default)]
+    templates: Templates,
+    #[serde(default)]
+    broker: vsmtp_config::Broker,
+    #[serde(default)]
+    logs: vsmtp_config::Logs,
+    #[serde(skip)]
+    path: std::path::PathBuf,
+}
+
+fn default_reporting_mta() -> String {
+    hostname::get().map_or_else(
+        |_| "localhost".to_string(),
+        |hostname| hostname.to_string_lossy().to_string(),
+    )
+}
+
+const fn default_routing_key() -> DeliveryRoute {
+    DeliveryRoute::Basic
+}
+
+impl Default for ReportService {
+    fn default() -> Self {
+        Self {
+            api_version: vsmtp_config::semver::VersionReq::default(),
+            reporting_mta: default_reporting_mta(),
+            routing_key: default_routing_key(),
+            templates: Templates::default(),
+            broker: vsmtp_config::Broker::default(),
+            logs: vsmtp_config::Logs::default(),
+            path: std::path::PathBuf::default(),
+        }
+    }
+}
+
+/// Primary language tag of the original message, ex: `fr` for `Content-Language: fr-CA`.
+fn language(original: &Mail) -> Option<String> {
+    let header = original.get_header("Content-Language")?;
+    let tag = header.body.split(',').next()?.trim();
+    let primary = tag.split('-').next()?;
+    (!primary.is_empty()).then(|| primary.to_string())
+}
+
+impl ReportService {
+    /// Load the templates of the notifications from the configured directory.
+    ///
+    /// # Errors
+    ///
+    /// * the directory or a template cannot be read
+    pub fn load_templates(&self) -> std::io::Result<BounceTemplates> {
+        match &self.templates.path {
+            Some(path) => BounceTemplates::load(path, self.templates.default_language.clone()),
+            None => Ok(BounceTemplates::default()),
+        }
+    }
+
+    /// Build the notification of a report request, addressed to the reverse path
+    /// of the original message, in the language of the original message.
+    #[must_use]
+    pub fn notification(
+        &self,
+        templates: &BounceTemplates,
+        report: &CtxDelivery,
+    ) -> Option<CtxDelivery> {
+        let sender = report.mail_from.reverse_path.as_ref()?;
+        let language = language(&report.mail.read().unwrap());
+
+        templates
+            .render_report(report, &self.reporting_mta, language.as_deref())
+            .and_then(|rendered| match Mail::try_from(rendered.as_str()) {
+                Ok(mail) => Some(mail),
+                Err(error) => {
+                    tracing::error!(%error, "Failed to parse the rendered notification");
+                    None
+                }
+            })
+            .map(|mail| {
+                CtxDelivery::new(
+                    self.routing_key.clone(),
+                    MailFromProps {
+                        reverse_path: None,
+                        mail_timestamp: time::OffsetDateTime::now_utc(),
+                        message_uuid: uuid::Uuid::new_v4(),
+                        envelop_id: None,
+                        spf_mail_from_identity: None,
+                        ret: None,
+                        // the human-readable part is sent in 8bit.
+                        mime_body_type: Some(MimeBodyType::EightBitMime),
+                    },
+                    vec![Recipient {
+                        forward_path: sender.clone(),
+                        original_forward_path: None,
+                        notify_on: NotifyOn::Never,
+                    }],
+                    std::sync::Arc::new(std::sync::RwLock::new(mail)),
+                )
+            })
+    }
+
+    /// Send the notification of the report request `payload` to the delivery service.
+    /// A notification without delivery service is put in the [`Queue::NoRoute`] queue.
+    pub async fn handle(
+        &self,
+        backend: &dyn QueueBackend,
+        templates: &BounceTemplates,
+        payload: &[u8],
+    ) {
+        let report = match Ctx::<CtxDelivery>::from_json(payload) {
+            Ok(report) => report,
+            Err(error) => {
+                tracing::warn!(%error, "Invalid report request, ignoring it");
+                return;
+            }
+        };
+
+        let routing_key = self.routing_key.to_string();
+        if let Some(notification) = self.notification(templates, &report.metadata) {
+            tracing::info!(
+                uuid = %report.metadata.uuid,
+                notification = %notification.uuid,
+                "Sending the delivery status notification"
+            );
+            let payload = Ctx {
+                variables: std::collections::HashMap::new(),
+                internal: std::collections::HashMap::new(),
+                metadata: notification,
+            }
+            .to_json()
+            .unwrap();
+
+            if !backend
+                .write_to_delivery(&routing_key, payload.clone())
+                .await
+            {
+                tracing::warn!(routing_key, "No delivery service for the notification");
+                backend.write_to_no_route(payload).await;
+            }
+        }
+    }
+}
+
+impl Config for ReportService {
+    fn with_path(&mut self, path: &impl AsRef<std::path::Path>) {
+        self.path = path.as_ref().into();
+    }
+
+    fn api_version(&self) -> &vsmtp_config::semver::VersionReq {
+        &self.api_version
+    }
+
+    fn broker(&self) -> &vsmtp_config::Broker {
+        &self.broker
+    }
+
+    fn logs(&self) -> &vsmtp_config::logs::Logs {
+        &self.logs
+    }
+
+    fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+/// Consume the report requests of the [`Queue::DSN`] queue, see [`ReportService::handle`].
+///
+/// # Errors
+///
+/// * the broker cannot be reached
+/// * the templates cannot be loaded
+pub async fn report_main(service: ReportService) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = service.broker().connect().await?;
+    vsmtp_common::init_logs(&conn, service.logs(), "report").await?;
+    let templates = service.load_templates()?;
+
+    let channel = conn.create_channel().await?;
+    channel
+        .confirm_select(lapin::options::ConfirmSelectOptions::default())
+        .await?;
+    channel
+        .queue_declare(
+            Queue::DSN.as_ref(),
+            lapin::options::QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            lapin::types::FieldTable::default(),
+        )
+        .await?;
+
+    let backend: &dyn QueueBackend = &channel;
+    let mut consumer = subscribe(
+        backend,
+        service.broker().prefetch_count,
+        [Queue::DSN.as_ref().to_string()],
+    )
+    .await?;
+    tracing::info!("Report service has been started");
+
+    while let Some((_, message)) = consumer.next().await {
+        let message = message?;
+        service.handle(backend, &templates, &message.data).await;
+        message.ack().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReportService;
+    use crate::{testing, BounceTemplates};
+    use vsmtp_common::{
+        broker::in_memory::InMemory,
+        ctx::Ctx,
+        ctx_delivery::CtxDelivery,
+        delivery_attempt::{DeliveryAttempt, LocalInformation, ShouldNotify},
+        delivery_route::DeliveryRoute,
+        uuid, Mailbox,
+    };
+    use vsmtp_protocol::NotifyOn;
+
+    fn mailbox(addr: &str) -> Mailbox {
+        Mailbox(addr.parse().unwrap())
+    }
+
+    fn report(reverse_path: Option<&str>) -> Ctx<CtxDelivery> {
+        let mut report = testing::delivery(
+            DeliveryRoute::Maildir,
+            &["jenny@example.net"],
+            concat!(
+                "From: john.doe@example.com\r\n",
+                "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+                "Subject: Quarterly report\r\n",
+                "Content-Language: fr-CA\r\n",
+                "\r\n",
+                "Hello world!\r\n",
+            ),
+        );
+        report.mail_from.reverse_path = reverse_path.map(mailbox);
+        report.rcpt_to[0].notify_on = NotifyOn::Some {
+            success: false,
+            failure: true,
+            delay: false,
+        };
+        report.last_deliveries = vec![DeliveryAttempt::new_local(
+            mailbox("jenny@example.net"),
+            LocalInformation::NotFound,
+            ShouldNotify::all(),
+        )];
+
+        Ctx {
+            variables: std::collections::HashMap::new(),
+            internal: std::collections::HashMap::new(),
+            metadata: report,
+        }
+    }
+
+    #[tokio::test]
+    async fn notification_to_the_sender() {
+        let dir = std::env::temp_dir().join(format!("vsmtp-report-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("fr")).unwrap();
+        std::fs::write(
+            dir.join("fr/failure.txt"),
+            "Subject: Non distribué\n\nÉchec pour {recipient}\n",
+        )
+        .unwrap();
+
+        let service: ReportService = serde_json::from_value(serde_json::json!({
+            "api_version": "*",
+            "reporting_mta": "mx.example.com",
+            "templates": { "path": dir },
+        }))
+        .unwrap();
+        let templates = service.load_templates().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let backend = InMemory::default();
+        service
+            .handle(
+                &backend,
+                &templates,
+                &report(Some("john.doe@example.com")).to_json().unwrap(),
+            )
+            .await;
+
+        let delivery = backend.messages("delivery-basic");
+        assert_eq!(delivery.len(), 1);
+
+        let notification = Ctx::<CtxDelivery>::from_json(&delivery[0]).unwrap();
+        assert_eq!(notification.metadata.routing_key, DeliveryRoute::Basic);
+        assert_eq!(notification.metadata.mail_from.reverse_path, None);
+        assert_eq!(notification.metadata.rcpt_to.len(), 1);
+        assert_eq!(
+            notification.metadata.rcpt_to[0].forward_path,
+            mailbox("john.doe@example.com")
+        );
+        assert_eq!(notification.metadata.rcpt_to[0].notify_on, NotifyOn::Never);
+
+        let mail = notification.metadata.mail.read().unwrap().to_string();
+        assert!(mail.contains("From: MAILER-DAEMON@mx.example.com\r\n"));
+        assert!(mail.contains("Échec pour jenny@example.net\r\n"));
+        assert!(mail.contains("Reporting-MTA: dns; mx.example.com\r\n"));
+    }
+
+    #[tokio::test]
+    async fn no_notification_to_null_sender() {
+        let backend = InMemory::default();
+        ReportService::default()
+            .handle(
+                &backend,
+                &BounceTemplates::default(),
+                &report(None).to_json().unwrap(),
+            )
+            .await;
+
+        assert!(backend.messages("delivery-basic").is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+/// Use the retry hint of the remote 4xx replies (ex: `retry after 300 seconds`)
+/// as the delay before the next attempt, instead of the default backoff.
+///
+/// The hint is clamped between `min` and `max`, so that a remote server cannot
+/// make the service retry in a loop or hold a message forever.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryHint {
+    #[serde(default = "RetryHint::default_min", with = "humantime_serde")]
+    pub min: std::time::Duration,
+    #[serde(default = "RetryHint::default_max", with = "humantime_serde")]
+    pub max: std::time::Duration,
+}
+
+impl Default for RetryHint {
+    fn default() -> Self {
+        Self {
+            min: Self::default_min(),
+            max: Self::default_max(),
+        }
+    }
+}
+
+impl RetryHint {
+    const fn default_min() -> std::time::Duration {
+        std::time::Duration::from_secs(60)
+    }
+
+    const fn default_max() -> std::time::Duration {
+        std::time::Duration::from_secs(6 * 60 * 60)
+    }
+
+    /// The delay before the next attempt for the `hint` of the remote server.
+    #[must_use]
+    pub fn delay(&self, hint: std::time::Duration) -> std::time::Duration {
+        hint.max(self.min).min(self.max.max(self.min))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryHint;
+
+    const fn secs(secs: u64) -> std::time::Duration {
+        std::time::Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn clamped() {
+        let retry_hint = RetryHint {
+            min: secs(60),
+            max: secs(3600),
+        };
+
+        assert_eq!(retry_hint.delay(secs(0)), secs(60));
+        assert_eq!(retry_hint.delay(secs(300)), secs(300));
+        assert_eq!(retry_hint.delay(secs(86_400)), secs(3600));
+    }
+
+    #[test]
+    fn deserialize() {
+        assert_eq!(
+            serde_json::from_str::<RetryHint>(r#"{ "max": "1h" }"#).unwrap(),
+            RetryHint {
+                min: secs(60),
+                max: secs(3600),
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<RetryHint>("{}").unwrap(),
+            RetryHint::default()
+        );
+    }
+}
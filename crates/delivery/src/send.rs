@@ -10,7 +10,7 @@
  */
 
 use crate::smtp::{Sender, SenderHandler, UpgradeTls};
-use crate::{Requirement, Tls};
+use crate::{Credentials, Requirement, Timeouts, Tls};
 use vsmtp_auth::TlsCertificate;
 use vsmtp_common::delivery_attempt::{
     EitherEhloOrError, EitherGreetingsOrError, EitherRemoteServerOrError,
@@ -22,6 +22,7 @@ use vsmtp_common::{
     extensions::Extension,
     response,
     stateful_ctx_received::MailFromProps,
+    tls::{CipherSuite, ProtocolVersion, TlsProps},
     Recipient,
 };
 use vsmtp_protocol::{rustls, tokio_rustls, ClientName, Domain, Reader, Reply, Writer};
@@ -35,7 +36,10 @@ struct BasicSender {
     remote_output: RemoteInformation,
     tls: Tls,
     tls_connector: tokio_rustls::TlsConnector,
+    // the TLS session established with the remote server
+    tls_session: Option<TlsProps>,
     should_notify: ShouldNotify,
+    retry_hint: bool,
 }
 
 #[async_trait::async_trait]
@@ -62,6 +66,20 @@ impl SenderHandler for BasicSender {
         true
     }
 
+    fn on_tls_upgrade(&mut self, connection: &rustls::ClientConnection) {
+        if let (Some(protocol_version), Some(cipher_suite)) = (
+            connection.protocol_version(),
+            connection.negotiated_cipher_suite(),
+        ) {
+            self.tls_session = Some(TlsProps {
+                protocol_version: ProtocolVersion(protocol_version),
+                cipher_suite: CipherSuite(cipher_suite.suite()),
+                peer_certificates: connection.peer_certificates().map(<[_]>::to_vec),
+                alpn_protocol: connection.alpn_protocol().map(<[_]>::to_vec),
+            });
+        }
+    }
+
     fn on_tls_upgrade_error(&mut self, error: std::io::Error) -> Self::Result {
         self.remote_output.save_tls_upgrade_error(error);
         self.take_result()
@@ -116,6 +134,12 @@ impl SenderHandler for BasicSender {
 
     async fn on_ehlo(&mut self, reply: Reply) -> Result<UpgradeTls, ()> {
         match response::Ehlo::try_from(reply.clone()) {
+            // the extensions advertised once the connection is encrypted.
+            Ok(response) if self.tls_session.is_some() => {
+                self.remote_output
+                    .save_ehlo(EitherEhloOrError::Ok(response));
+                Ok(UpgradeTls::No)
+            }
             Ok(response) => match (self.tls.starttls, response.contains(Extension::StartTls)) {
                 (Requirement::Required, false) => {
                     self.remote_output.save_ehlo(EitherEhloOrError::Err((
@@ -126,12 +150,16 @@ impl SenderHandler for BasicSender {
                     )));
                     Err(())
                 }
-                (Requirement::Required | Requirement::Optional, true) => {
+                (
+                    Requirement::Required | Requirement::Optional | Requirement::Opportunistic,
+                    true,
+                ) => {
                     self.remote_output
                         .save_ehlo(EitherEhloOrError::Ok(response));
                     Ok(UpgradeTls::Yes)
                 }
-                (Requirement::Optional, false) | (Requirement::Disabled, _) => {
+                (Requirement::Optional | Requirement::Opportunistic, false)
+                | (Requirement::Disabled, _) => {
                     self.remote_output
                         .save_ehlo(EitherEhloOrError::Ok(response));
                     Ok(UpgradeTls::No)
@@ -149,6 +177,26 @@ impl SenderHandler for BasicSender {
         self.remote_output.has_extension(extension)
     }
 
+    fn get_extension_arguments(&self, extension: Extension) -> Option<String> {
+        self.remote_output
+            .get_extension_arguments(extension)
+            .map(str::to_string)
+    }
+
+    async fn on_auth(&mut self, reply: Reply) -> Result<(), ()> {
+        if reply.code().value() == 235 {
+            Ok(())
+        } else {
+            self.remote_output
+                .save_auth_error("authentication failed".to_string(), Some(reply));
+            Err(())
+        }
+    }
+
+    fn on_auth_error(&mut self, error: String) {
+        self.remote_output.save_auth_error(error, None);
+    }
+
     async fn on_mail_from(&mut self, reply: Reply) -> Result<(), ()> {
         self.remote_output.save_mail_from(reply);
         Ok(())
@@ -170,14 +218,24 @@ impl SenderHandler for BasicSender {
     }
 
     fn take_result(&mut self) -> DeliveryAttempt {
-        DeliveryAttempt::new_remote(
+        let attempt = DeliveryAttempt::new_remote(
             self.rcpt_to
                 .iter()
                 .map(|r| r.forward_path.clone())
                 .collect(),
             self.remote_output.finalize(),
             self.should_notify,
-        )
+        );
+        let attempt = match self.tls_session.take() {
+            Some(tls) => attempt.with_tls(tls),
+            None => attempt,
+        };
+
+        if self.retry_hint {
+            attempt.with_retry_hint()
+        } else {
+            attempt
+        }
     }
 }
 
@@ -191,7 +249,74 @@ pub async fn send(
     mx: Option<RemoteMailExchange>,
     message: &[u8],
     tls: Tls,
+    auth: Option<&Credentials>,
+    source_ip: Option<std::net::IpAddr>,
+    timeouts: Timeouts,
+    extra_root_ca: Option<std::sync::Arc<TlsCertificate>>,
+    retry_hint: bool,
+) -> DeliveryAttempt {
+    let attempt = transaction(
+        ip_addr,
+        server_name.clone(),
+        client_name.clone(),
+        from.clone(),
+        to.clone(),
+        mx.clone(),
+        message,
+        tls.clone(),
+        auth,
+        source_ip,
+        timeouts,
+        extra_root_ca.clone(),
+        retry_hint,
+    )
+    .await;
+
+    if !matches!(tls.starttls, Requirement::Opportunistic) {
+        return attempt;
+    }
+    let Some(reason) = attempt.get_tls_upgrade_error().map(str::to_string) else {
+        return attempt;
+    };
+
+    // the handshake consumed the connection, the transaction is restarted in cleartext.
+    tracing::warn!(%ip_addr, %reason, "TLS handshake failed, falling back to cleartext");
+    transaction(
+        ip_addr,
+        server_name,
+        client_name,
+        from,
+        to,
+        mx,
+        message,
+        Tls {
+            starttls: Requirement::Disabled,
+        },
+        auth,
+        source_ip,
+        timeouts,
+        extra_root_ca,
+        retry_hint,
+    )
+    .await
+    .with_tls_downgrade(reason)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn transaction(
+    ip_addr: std::net::SocketAddr,
+    server_name: Domain,
+    client_name: ClientName,
+    from: MailFromProps,
+    to: Vec<Recipient>,
+    mx: Option<RemoteMailExchange>,
+    message: &[u8],
+    tls: Tls,
+    auth: Option<&Credentials>,
+    source_ip: Option<std::net::IpAddr>,
+    timeouts: Timeouts,
     extra_root_ca: Option<std::sync::Arc<TlsCertificate>>,
+    retry_hint: bool,
 ) -> DeliveryAttempt {
     let should_notify = ShouldNotify::Failure | ShouldNotify::Delay;
 
@@ -201,13 +326,8 @@ pub async fn send(
         io: None,
     };
 
-    let connect_timeout = std::time::Duration::from_secs(1);
-    let socket = match tokio::time::timeout(
-        connect_timeout,
-        tokio::net::TcpStream::connect(ip_addr),
-    )
-    .await
-    {
+    let connect_timeout = timeouts.connect;
+    let socket = match tokio::time::timeout(connect_timeout, connect(ip_addr, source_ip)).await {
         Ok(socket) => match socket {
             Ok(socket) => socket,
             Err(error) => {
@@ -248,48 +368,607 @@ pub async fn send(
         })),
         tls,
         should_notify,
-        tls_connector: {
-            let mut root_store = rustls::RootCertStore::empty();
-
-            root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
-                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                    ta.subject,
-                    ta.spki,
-                    ta.name_constraints,
-                )
-            }));
-
-            if let Some(extra_root_ca) = extra_root_ca {
-                for i in extra_root_ca.certs() {
-                    root_store.add(i).unwrap();
-                }
-            }
-
-            // NOTE: We could let the user customize the tls parameters here.
-            let config = rustls::ClientConfig::builder()
-                .with_safe_defaults()
-                .with_root_certificates(root_store)
-                .with_no_client_auth();
-
-            tokio_rustls::TlsConnector::from(std::sync::Arc::new(config))
-        },
+        retry_hint,
+        tls_connector: tls_connector(extra_root_ca.as_deref()),
+        tls_session: None,
     };
 
     let mut sender = Sender::new(
         Reader::new(Box::new(read), true),
         Writer::new(Box::new(write)),
         handler,
+        timeouts,
     );
 
     let Ok(pre_transaction) = sender.pre_transaction().await else {
         return sender.handler().take_result();
     };
-    if matches!(pre_transaction, UpgradeTls::Yes) {
-        match sender.upgrade_tls().await {
-            Ok(mut secured_sender) => secured_sender.send().await,
-            Err(info) => info,
+    let mut sender = if matches!(pre_transaction, UpgradeTls::Yes) {
+        let mut secured_sender = match sender.upgrade_tls().await {
+            Ok(secured_sender) => secured_sender,
+            Err(info) => return info,
+        };
+        // the knowledge obtained from the server is discarded after the handshake (RFC 3207).
+        if secured_sender.ehlo().await.is_err() {
+            return secured_sender.handler().take_result();
+        }
+        secured_sender
+    } else {
+        sender
+    };
+
+    if let Some(credentials) = auth {
+        let handler = sender.handler();
+        if handler.tls_session.is_none() && !credentials.allow_cleartext {
+            handler.on_auth_error(
+                "refusing to send the credentials over a cleartext connection".to_string(),
+            );
+            return handler.take_result();
         }
+        if sender.authenticate(credentials).await.is_err() {
+            return sender.handler().take_result();
+        }
+    }
+
+    sender.send().await
+}
+
+/// Open a connection to `ip_addr`, from `source_ip` if set.
+async fn connect(
+    ip_addr: std::net::SocketAddr,
+    source_ip: Option<std::net::IpAddr>,
+) -> std::io::Result<tokio::net::TcpStream> {
+    let Some(source_ip) = source_ip else {
+        return tokio::net::TcpStream::connect(ip_addr).await;
+    };
+
+    let socket = if ip_addr.is_ipv4() {
+        tokio::net::TcpSocket::new_v4()?
     } else {
-        sender.send().await
+        tokio::net::TcpSocket::new_v6()?
+    };
+    socket.bind(std::net::SocketAddr::new(source_ip, 0))?;
+    socket.connect(ip_addr).await
+}
+
+/// Build the TLS connector used to upgrade the connection to the remote server,
+/// trusting the webpki roots and the extra root certificates.
+fn tls_connector(extra_root_ca: Option<&TlsCertificate>) -> tokio_rustls::TlsConnector {
+    let mut root_store = rustls::RootCertStore::empty();
+
+    root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+
+    if let Some(extra_root_ca) = extra_root_ca {
+        for i in extra_root_ca.certs() {
+            root_store.add(i).unwrap();
+        }
+    }
+
+    // NOTE: We could let the user customize the tls parameters here.
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+
+    tokio_rustls::TlsConnector::from(std::sync::Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::send;
+    use crate::{testing, Credentials, Requirement, Timeouts, Tls};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+    use vsmtp_common::delivery_attempt::{Action, DeliveryAttempt};
+    use vsmtp_protocol::ClientName;
+
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+    fn timeouts() -> Timeouts {
+        Timeouts {
+            connect: TIMEOUT,
+            greeting: TIMEOUT,
+            command: TIMEOUT,
+            data: TIMEOUT,
+        }
+    }
+
+    async fn attempt(addr: std::net::SocketAddr, retry_hint: bool) -> DeliveryAttempt {
+        send(
+            addr,
+            "localhost".parse().unwrap(),
+            ClientName::Domain("client.example.com".parse().unwrap()),
+            testing::mail_from(),
+            vec![testing::recipient("jenny@example.com")],
+            None,
+            b"Subject: timeout\r\n\r\nHello world!\r\n",
+            Tls {
+                starttls: Requirement::Disabled,
+            },
+            None,
+            None,
+            timeouts(),
+            None,
+            retry_hint,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn stall_at_greetings() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            drop(socket);
+        });
+
+        let start = std::time::Instant::now();
+        let attempt = attempt(addr, false).await;
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(matches!(attempt.get_action(0), Action::Delayed { .. }));
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn stall_at_data() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (read, mut write) = socket.into_split();
+            let mut lines = tokio::io::BufReader::new(read).lines();
+
+            write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply = match line.split_whitespace().next() {
+                    Some("EHLO") => "250 localhost\r\n",
+                    Some("MAIL" | "RCPT") => "250 Ok\r\n",
+                    Some("DATA") => "354 Start mail input\r\n",
+                    // the message is read, but the final dot is never acknowledged.
+                    _ => continue,
+                };
+                write.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let start = std::time::Instant::now();
+        let attempt = attempt(addr, false).await;
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(matches!(attempt.get_action(0), Action::Delayed { .. }));
+
+        server.abort();
+    }
+
+    /// Accept one transaction and answer `data_end` to the end of the message.
+    async fn reply_at_data_end(
+        data_end: &'static str,
+    ) -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (read, mut write) = socket.into_split();
+            let mut lines = tokio::io::BufReader::new(read).lines();
+
+            write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply = match line.split_whitespace().next() {
+                    Some("EHLO") => "250 localhost\r\n",
+                    Some("MAIL" | "RCPT") => "250 Ok\r\n",
+                    Some("DATA") => "354 Start mail input\r\n",
+                    Some("QUIT") => "221 Bye\r\n",
+                    _ if line == "." => data_end,
+                    _ => continue,
+                };
+                write.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        (addr, server)
+    }
+
+    #[tokio::test]
+    async fn retry_hint() {
+        let (addr, server) =
+            reply_at_data_end("451 4.7.1 Greylisted, please retry after 5 minutes\r\n").await;
+
+        let attempt = attempt(addr, true).await;
+        assert!(matches!(attempt.get_action(0), Action::Delayed { .. }));
+        assert_eq!(
+            attempt.get_retry_hint(),
+            Some(std::time::Duration::from_secs(300))
+        );
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn retry_hint_disabled() {
+        let (addr, server) =
+            reply_at_data_end("451 4.7.1 Greylisted, please retry after 5 minutes\r\n").await;
+
+        let attempt = attempt(addr, false).await;
+        assert!(matches!(attempt.get_action(0), Action::Delayed { .. }));
+        assert_eq!(attempt.get_retry_hint(), None);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn no_retry_hint() {
+        let (addr, server) = reply_at_data_end("451 4.3.0 Local error in processing\r\n").await;
+
+        let attempt = attempt(addr, true).await;
+        assert!(matches!(attempt.get_action(0), Action::Delayed { .. }));
+        assert_eq!(attempt.get_retry_hint(), None);
+
+        server.abort();
+    }
+
+    /// How the server handles the STARTTLS extension.
+    enum StartTls {
+        NotAdvertised,
+        /// Accept the command, but answer the handshake with garbage.
+        Broken,
+        Accept(vsmtp_protocol::tokio_rustls::TlsAcceptor),
+    }
+
+    fn tls_acceptor() -> vsmtp_protocol::tokio_rustls::TlsAcceptor {
+        use vsmtp_common::tls::{secret::Secret, CipherSuite, ProtocolVersion};
+        use vsmtp_protocol::rustls;
+
+        let config = vsmtp_common::tls::get_rustls_config(
+            &[ProtocolVersion(rustls::ProtocolVersion::TLSv1_3)],
+            &rustls::ALL_CIPHER_SUITES
+                .iter()
+                .map(|i| CipherSuite(i.suite()))
+                .collect::<Vec<_>>(),
+            false,
+            "mx.example.com",
+            Some(&Secret {
+                certificate: include_str!("../tests/certs/mx.example.com.crt")
+                    .parse()
+                    .unwrap(),
+                private_key: include_str!("../tests/certs/mx.example.com.key")
+                    .parse()
+                    .unwrap(),
+            }),
+            &std::collections::BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+
+        std::sync::Arc::new(config).into()
+    }
+
+    /// Serve a transaction, and return the stream if the client asked for STARTTLS.
+    /// No greetings are sent on an upgraded stream.
+    async fn serve<S>(stream: S, advertise_starttls: bool, greet: bool) -> Option<S>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let mut stream = tokio::io::BufReader::new(stream);
+        let mut line = String::new();
+
+        if greet {
+            stream
+                .get_mut()
+                .write_all(b"220 mx.example.com ESMTP\r\n")
+                .await
+                .unwrap();
+        }
+        loop {
+            line.clear();
+            // the client may close the TLS stream without a close_notify.
+            if matches!(stream.read_line(&mut line).await, Ok(0) | Err(_)) {
+                return None;
+            }
+
+            let reply = match line.split_whitespace().next() {
+                Some("EHLO") if advertise_starttls => "250-mx.example.com\r\n250 STARTTLS\r\n",
+                Some("EHLO") => "250 mx.example.com\r\n",
+                Some("STARTTLS") => {
+                    stream.get_mut().write_all(b"220 Ready\r\n").await.unwrap();
+                    return Some(stream.into_inner());
+                }
+                Some("MAIL" | "RCPT") => "250 Ok\r\n",
+                Some("DATA") => "354 Start mail input\r\n",
+                Some("QUIT") => "221 Bye\r\n",
+                _ if line.trim_end() == "." => "250 Ok\r\n",
+                _ => continue,
+            };
+            stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+        }
+    }
+
+    async fn opportunistic_attempt(starttls: StartTls) -> DeliveryAttempt {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let advertise_starttls = !matches!(starttls, StartTls::NotAdvertised);
+            let Some(mut socket) = serve(socket, advertise_starttls, true).await else {
+                return;
+            };
+
+            match starttls {
+                StartTls::NotAdvertised => unreachable!(),
+                StartTls::Broken => {
+                    socket.write_all(b"not a TLS record\r\n").await.unwrap();
+                    drop(socket);
+
+                    let (socket, _) = listener.accept().await.unwrap();
+                    assert!(serve(socket, true, true).await.is_none());
+                }
+                StartTls::Accept(acceptor) => {
+                    let socket = acceptor.accept(socket).await.unwrap();
+                    assert!(serve(socket, false, false).await.is_none());
+                }
+            }
+        });
+
+        let attempt = send(
+            addr,
+            "mx.example.com".parse().unwrap(),
+            ClientName::Domain("client.example.com".parse().unwrap()),
+            testing::mail_from(),
+            vec![testing::recipient("jenny@example.com")],
+            None,
+            b"Subject: opportunistic\r\n\r\nHello world!\r\n",
+            Tls {
+                starttls: Requirement::Opportunistic,
+            },
+            None,
+            None,
+            timeouts(),
+            Some(std::sync::Arc::new(
+                include_str!("../tests/certs/rootCA.crt").parse().unwrap(),
+            )),
+            false,
+        )
+        .await;
+
+        server.await.unwrap();
+        attempt
+    }
+
+    #[tokio::test]
+    async fn opportunistic_starttls() {
+        let attempt = opportunistic_attempt(StartTls::Accept(tls_acceptor())).await;
+
+        assert!(matches!(attempt.get_action(0), Action::Delivered));
+        assert_eq!(attempt.get_tls_downgrade(), None);
+        assert_eq!(
+            attempt.to_report().tls.map(|tls| tls.protocol_version),
+            Some("TLSv1_3".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn opportunistic_handshake_failure() {
+        let attempt = opportunistic_attempt(StartTls::Broken).await;
+
+        assert!(matches!(attempt.get_action(0), Action::Delivered));
+        assert!(attempt.get_tls_downgrade().is_some());
+        assert_eq!(attempt.to_report().tls, None);
+    }
+
+    #[tokio::test]
+    async fn opportunistic_without_starttls() {
+        let attempt = opportunistic_attempt(StartTls::NotAdvertised).await;
+
+        assert!(matches!(attempt.get_action(0), Action::Delivered));
+        assert_eq!(attempt.get_tls_downgrade(), None);
+        assert_eq!(attempt.to_report().tls, None);
+    }
+
+    fn credentials(password: &str, allow_cleartext: bool) -> Credentials {
+        Credentials {
+            username: "vsmtp".to_string(),
+            password: password.to_string(),
+            allow_cleartext,
+        }
+    }
+
+    /// Serve a transaction requiring the authentication of `vsmtp:secret`,
+    /// recording the commands received, and return the stream if the client asked for STARTTLS.
+    async fn serve_auth<S>(
+        stream: S,
+        greet: bool,
+        extensions: &[&str],
+        commands: &mut Vec<String>,
+    ) -> Option<S>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let mut stream = tokio::io::BufReader::new(stream);
+        let mut line = String::new();
+        let mut login = vec![];
+        let mut authenticated = false;
+
+        if greet {
+            stream
+                .get_mut()
+                .write_all(b"220 mx.example.com ESMTP\r\n")
+                .await
+                .unwrap();
+        }
+        loop {
+            line.clear();
+            if matches!(stream.read_line(&mut line).await, Ok(0) | Err(_)) {
+                return None;
+            }
+            let command = line.trim_end().to_string();
+            commands.push(command.clone());
+
+            let reply = match command.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["EHLO", ..] => std::iter::once("mx.example.com")
+                    .chain(extensions.iter().copied())
+                    .enumerate()
+                    .map(|(i, line)| {
+                        let separator = if i == extensions.len() { ' ' } else { '-' };
+                        format!("250{separator}{line}\r\n")
+                    })
+                    .collect::<String>(),
+                ["STARTTLS"] => {
+                    stream.get_mut().write_all(b"220 Ready\r\n").await.unwrap();
+                    return Some(stream.into_inner());
+                }
+                ["AUTH", "PLAIN", response] => {
+                    authenticated = *response == STANDARD.encode("\0vsmtp\0secret");
+                    if authenticated {
+                        "235 2.7.0 Authentication successful\r\n".to_string()
+                    } else {
+                        "535 5.7.8 Authentication credentials invalid\r\n".to_string()
+                    }
+                }
+                ["AUTH", "LOGIN"] => {
+                    login.push(command.clone());
+                    "334 VXNlcm5hbWU6\r\n".to_string()
+                }
+                [response] if !login.is_empty() => {
+                    login.push((*response).to_string());
+                    if login.len() == 2 {
+                        "334 UGFzc3dvcmQ6\r\n".to_string()
+                    } else {
+                        authenticated = login[1] == STANDARD.encode("vsmtp")
+                            && login[2] == STANDARD.encode("secret");
+                        login.clear();
+                        if authenticated {
+                            "235 2.7.0 Authentication successful\r\n".to_string()
+                        } else {
+                            "535 5.7.8 Authentication credentials invalid\r\n".to_string()
+                        }
+                    }
+                }
+                ["MAIL", ..] if !authenticated => {
+                    "530 5.7.0 Authentication required\r\n".to_string()
+                }
+                ["MAIL" | "RCPT", ..] => "250 Ok\r\n".to_string(),
+                ["DATA"] => "354 Start mail input\r\n".to_string(),
+                ["QUIT"] => "221 Bye\r\n".to_string(),
+                ["."] => "250 Ok\r\n".to_string(),
+                _ => continue,
+            };
+            stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+        }
+    }
+
+    /// Send a message to a server requiring the authentication, and return the attempt
+    /// with the commands received by the server.
+    async fn auth_attempt(
+        extensions: &'static [&'static str],
+        starttls: Requirement,
+        credentials: &Credentials,
+    ) -> (DeliveryAttempt, Vec<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut commands = vec![];
+            let (socket, _) = listener.accept().await.unwrap();
+            if let Some(socket) = serve_auth(socket, true, extensions, &mut commands).await {
+                let socket = tls_acceptor().accept(socket).await.unwrap();
+                serve_auth(socket, false, &["AUTH PLAIN LOGIN"], &mut commands).await;
+            }
+            commands
+        });
+
+        let attempt = send(
+            addr,
+            "mx.example.com".parse().unwrap(),
+            ClientName::Domain("client.example.com".parse().unwrap()),
+            testing::mail_from(),
+            vec![testing::recipient("jenny@example.com")],
+            None,
+            b"Subject: auth\r\n\r\nHello world!\r\n",
+            Tls { starttls },
+            Some(credentials),
+            None,
+            timeouts(),
+            Some(std::sync::Arc::new(
+                include_str!("../tests/certs/rootCA.crt").parse().unwrap(),
+            )),
+            false,
+        )
+        .await;
+
+        (attempt, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn auth_plain() {
+        let (attempt, commands) = auth_attempt(
+            &["AUTH PLAIN LOGIN"],
+            Requirement::Disabled,
+            &credentials("secret", true),
+        )
+        .await;
+
+        assert!(matches!(attempt.get_action(0), Action::Delivered));
+        assert!(commands.iter().any(|c| c.starts_with("AUTH PLAIN ")));
+    }
+
+    #[tokio::test]
+    async fn auth_login() {
+        let (attempt, commands) = auth_attempt(
+            &["AUTH=LOGIN"],
+            Requirement::Disabled,
+            &credentials("secret", true),
+        )
+        .await;
+
+        assert!(matches!(attempt.get_action(0), Action::Delivered));
+        assert!(commands.iter().any(|c| c == "AUTH LOGIN"));
+    }
+
+    #[tokio::test]
+    async fn auth_over_tls() {
+        // the mechanisms are advertised once the connection is encrypted.
+        let (attempt, commands) = auth_attempt(
+            &["STARTTLS"],
+            Requirement::Required,
+            &credentials("secret", false),
+        )
+        .await;
+
+        assert!(matches!(attempt.get_action(0), Action::Delivered));
+        assert!(attempt.to_report().tls.is_some());
+        assert_eq!(commands.iter().filter(|c| c.starts_with("EHLO")).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn auth_invalid_credentials() {
+        let (attempt, commands) = auth_attempt(
+            &["AUTH PLAIN"],
+            Requirement::Disabled,
+            &credentials("wrong", true),
+        )
+        .await;
+
+        assert!(matches!(attempt.get_action(0), Action::Delayed { .. }));
+        assert!(!commands.iter().any(|c| c.starts_with("MAIL")));
+    }
+
+    #[tokio::test]
+    async fn auth_refused_over_cleartext() {
+        let (attempt, commands) = auth_attempt(
+            &["AUTH PLAIN LOGIN"],
+            Requirement::Optional,
+            &credentials("secret", false),
+        )
+        .await;
+
+        assert!(matches!(attempt.get_action(0), Action::Delayed { .. }));
+        assert!(!commands.iter().any(|c| c.starts_with("AUTH")));
+        assert!(!commands.iter().any(|c| c.starts_with("MAIL")));
     }
 }
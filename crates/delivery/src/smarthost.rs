@@ -0,0 +1,198 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::Tls;
+use vsmtp_common::domain_map::DomainMap;
+
+/// Credentials used to authenticate to a smarthost.
+///
+/// The password is masked when the credentials are printed or serialized.
+#[derive(Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    /// Send the credentials even if the connection is not encrypted.
+    #[serde(default)]
+    pub allow_cleartext: bool,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .field("allow_cleartext", &self.allow_cleartext)
+            .finish()
+    }
+}
+
+impl serde::Serialize for Credentials {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("Credentials", 3)?;
+        s.serialize_field("username", &self.username)?;
+        s.serialize_field("password", "***")?;
+        s.serialize_field("allow_cleartext", &self.allow_cleartext)?;
+        s.end()
+    }
+}
+
+/// A relay receiving the messages of some domains, instead of their MX.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Smarthost {
+    /// Host name or IP of the relay.
+    pub host: String,
+    #[serde(default = "Smarthost::default_port")]
+    pub port: u16,
+    /// Credentials to authenticate with, if the relay requires it.
+    #[serde(default)]
+    pub auth: Option<Credentials>,
+    /// STARTTLS requirement for the relay, required by default.
+    #[serde(default)]
+    pub tls: Tls,
+}
+
+impl Smarthost {
+    const fn default_port() -> u16 {
+        25
+    }
+}
+
+/// Where the messages of a recipient domain are sent.
+#[derive(Debug)]
+pub enum Route<'a> {
+    /// Relay the messages to a smarthost.
+    Smarthost(&'a Smarthost),
+    /// Lookup the MX records of the domain.
+    Mx,
+}
+
+/// Smarthosts by recipient domain pattern.
+#[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub struct SmarthostMap(DomainMap<Smarthost>);
+
+impl SmarthostMap {
+    /// Get the route of the messages to `domain`.
+    #[must_use]
+    pub fn route(&self, domain: &str) -> Route<'_> {
+        self.0.get(domain).map_or(Route::Mx, Route::Smarthost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Credentials, Route, SmarthostMap};
+
+    fn map() -> SmarthostMap {
+        serde_json::from_value(serde_json::json!({
+            "example.com": {
+                "host": "relay.example.com",
+                "port": 587,
+                "auth": { "username": "vsmtp", "password": "secret" },
+            },
+            "*.example.net": { "host": "192.0.2.1", "tls": { "starttls": "optional" } },
+            "*.eu.example.net": { "host": "eu.relay.example.net" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn matched_domain() {
+        let map = map();
+        let Route::Smarthost(smarthost) = map.route("Example.com.") else {
+            panic!("example.com should be relayed");
+        };
+
+        assert_eq!(smarthost.host, "relay.example.com");
+        assert_eq!(smarthost.port, 587);
+        assert_eq!(
+            smarthost.auth,
+            Some(Credentials {
+                username: "vsmtp".to_string(),
+                password: "secret".to_string(),
+                allow_cleartext: false,
+            })
+        );
+    }
+
+    #[test]
+    fn wildcard() {
+        let map = map();
+
+        let Route::Smarthost(smarthost) = map.route("mail.example.net") else {
+            panic!("subdomains of example.net should be relayed");
+        };
+        assert_eq!(smarthost.host, "192.0.2.1");
+        assert_eq!(smarthost.port, 25);
+        assert!(smarthost.auth.is_none());
+
+        let Route::Smarthost(smarthost) = map.route("paris.eu.example.net") else {
+            panic!("subdomains of eu.example.net should be relayed");
+        };
+        assert_eq!(smarthost.host, "eu.relay.example.net");
+
+        // the wildcard does not match the domain itself.
+        assert!(matches!(map.route("example.net"), Route::Mx));
+    }
+
+    #[test]
+    fn unmatched_domain() {
+        let map = map();
+
+        assert!(matches!(map.route("example.org"), Route::Mx));
+        assert!(matches!(map.route("sub.example.com"), Route::Mx));
+        assert!(matches!(map.route("notexample.net"), Route::Mx));
+    }
+
+    #[test]
+    fn catch_all() {
+        let mut map = map();
+        map.0.insert(
+            "*".to_string(),
+            serde_json::from_value(serde_json::json!({ "host": "default.relay" })).unwrap(),
+        );
+
+        let Route::Smarthost(smarthost) = map.route("example.org") else {
+            panic!("the catch all should relay example.org");
+        };
+        assert_eq!(smarthost.host, "default.relay");
+
+        let Route::Smarthost(smarthost) = map.route("example.com") else {
+            panic!("example.com should be relayed");
+        };
+        assert_eq!(smarthost.host, "relay.example.com");
+    }
+
+    #[test]
+    fn password_masked() {
+        let map = map();
+        let Route::Smarthost(smarthost) = map.route("example.com") else {
+            panic!("example.com should be relayed");
+        };
+
+        assert!(!format!("{map:?}").contains("secret"));
+        assert_eq!(
+            serde_json::to_value(smarthost.auth.as_ref().unwrap()).unwrap(),
+            serde_json::json!({
+                "username": "vsmtp",
+                "password": "***",
+                "allow_cleartext": false,
+            })
+        );
+    }
+}
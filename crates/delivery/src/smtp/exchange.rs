@@ -11,24 +11,29 @@
 
 use super::SenderHandler;
 use crate::smtp::handler::UpgradeTls;
-use vsmtp_common::{stateful_ctx_received::MailFromProps, Recipient};
+use crate::{Credentials, Timeouts};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use vsmtp_common::{extensions::Extension, stateful_ctx_received::MailFromProps, Recipient};
 use vsmtp_protocol::{DsnReturn, NotifyOn, Reader, Reply, Verb, Writer};
 
 pub struct Sender<H: SenderHandler> {
     reader: Reader<Box<dyn tokio::io::AsyncRead + Unpin + Send + Sync>>,
     writer: Writer<Box<dyn tokio::io::AsyncWrite + Unpin + Send + Sync>>,
     handler: H,
+    timeouts: Timeouts,
 }
 impl<H: SenderHandler + Sync + Send> Sender<H> {
     pub fn new(
         reader: Reader<Box<dyn tokio::io::AsyncRead + Unpin + Send + Sync>>,
         writer: Writer<Box<dyn tokio::io::AsyncWrite + Unpin + Send + Sync>>,
         handler: H,
+        timeouts: Timeouts,
     ) -> Self {
         Self {
             reader,
             writer,
             handler,
+            timeouts,
         }
     }
 
@@ -37,15 +42,20 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
     }
 
     pub async fn quit(&mut self) -> Result<(), ()> {
-        if let Err(e) = self.writer.write_all(Verb::Quit.as_ref()).await {
-            self.handler.on_io_error(e.into());
+        if let Err(e) = with_timeout(
+            self.timeouts.command,
+            self.writer.write_all(Verb::Quit.as_ref()),
+        )
+        .await
+        {
+            self.handler.on_io_error(e);
             return Err(());
         }
 
         let replies = self.reader.as_reply_stream();
         tokio::pin!(replies);
 
-        match Self::next_reply(&mut replies).await {
+        match Self::next_reply(&mut replies, self.timeouts.command).await {
             Ok(reply) => self.handler.on_quit(reply).await,
             Err(e) => {
                 self.handler.on_io_error(e);
@@ -55,15 +65,20 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
     }
 
     pub async fn noop(&mut self) -> Result<(), ()> {
-        if let Err(e) = self.writer.write_all(Verb::Noop.as_ref()).await {
-            self.handler.on_io_error(e.into());
+        if let Err(e) = with_timeout(
+            self.timeouts.command,
+            self.writer.write_all(Verb::Noop.as_ref()),
+        )
+        .await
+        {
+            self.handler.on_io_error(e);
             return Err(());
         }
 
         let replies = self.reader.as_reply_stream();
         tokio::pin!(replies);
 
-        match Self::next_reply(&mut replies).await {
+        match Self::next_reply(&mut replies, self.timeouts.command).await {
             Ok(reply) => self.handler.on_noop(reply).await,
             Err(e) => {
                 self.handler.on_io_error(e);
@@ -73,12 +88,12 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
     }
 
     pub async fn pre_transaction(&mut self) -> Result<UpgradeTls, ()> {
-        let replies = self.reader.as_reply_stream();
-        tokio::pin!(replies);
-
         self.handler.on_connect().await?;
         if self.handler.has_just_connected() {
-            let greetings = match Self::next_reply(&mut replies).await {
+            let replies = self.reader.as_reply_stream();
+            tokio::pin!(replies);
+
+            let greetings = match Self::next_reply(&mut replies, self.timeouts.greeting).await {
                 Ok(reply) => reply,
                 Err(e) => {
                     self.handler.on_io_error(e);
@@ -88,19 +103,29 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
             self.handler.on_greetings(greetings).await?;
         }
 
+        self.ehlo().await
+    }
+
+    /// Send the EHLO command, also used to refresh the extensions of the server
+    /// after the TLS upgrade.
+    pub async fn ehlo(&mut self) -> Result<UpgradeTls, ()> {
+        let replies = self.reader.as_reply_stream();
+        tokio::pin!(replies);
+
         let client_name = self.handler.get_client_name();
 
         // TODO: handle unsupported EHLO (fallback on HELO)
-        if let Err(e) = self
-            .writer
-            .write_all(&format!("EHLO {client_name}\r\n"))
-            .await
+        if let Err(e) = with_timeout(
+            self.timeouts.command,
+            self.writer.write_all(&format!("EHLO {client_name}\r\n")),
+        )
+        .await
         {
-            self.handler.on_io_error(e.into());
+            self.handler.on_io_error(e);
             return Err(());
         }
 
-        let ehlo_reply = match Self::next_reply(&mut replies).await {
+        let ehlo_reply = match Self::next_reply(&mut replies, self.timeouts.command).await {
             Ok(reply) => reply,
             Err(e) => {
                 self.handler.on_io_error(e);
@@ -110,12 +135,85 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         self.handler.on_ehlo(ehlo_reply).await
     }
 
+    /// Authenticate to the remote server, with the PLAIN mechanism if advertised,
+    /// LOGIN otherwise.
+    pub async fn authenticate(&mut self, credentials: &Credentials) -> Result<(), ()> {
+        let Some(mechanisms) = self.handler.get_extension_arguments(Extension::Auth) else {
+            self.handler
+                .on_auth_error("the server did not advertise the AUTH extension".to_string());
+            return Err(());
+        };
+        let mechanisms = mechanisms
+            .split(|c: char| c.is_whitespace() || c == '=')
+            .map(str::to_ascii_uppercase)
+            .collect::<Vec<_>>();
+
+        let Credentials {
+            username, password, ..
+        } = credentials;
+        let (mut command, responses) = if mechanisms.iter().any(|m| m == "PLAIN") {
+            (
+                format!(
+                    "AUTH PLAIN {}\r\n",
+                    STANDARD.encode(format!("\0{username}\0{password}"))
+                ),
+                vec![],
+            )
+        } else if mechanisms.iter().any(|m| m == "LOGIN") {
+            (
+                "AUTH LOGIN\r\n".to_string(),
+                vec![
+                    format!("{}\r\n", STANDARD.encode(username)),
+                    format!("{}\r\n", STANDARD.encode(password)),
+                ],
+            )
+        } else {
+            self.handler.on_auth_error(format!(
+                "no supported mechanism advertised by the server: {}",
+                mechanisms.join(" ")
+            ));
+            return Err(());
+        };
+
+        let Self {
+            reader,
+            writer,
+            handler,
+            timeouts,
+        } = self;
+
+        let replies = reader.as_reply_stream();
+        tokio::pin!(replies);
+
+        let mut responses = responses.into_iter();
+        loop {
+            if let Err(e) = with_timeout(timeouts.command, writer.write_all(&command)).await {
+                handler.on_io_error(e);
+                return Err(());
+            }
+
+            let reply = match Self::next_reply(&mut replies, timeouts.command).await {
+                Ok(reply) => reply,
+                Err(e) => {
+                    handler.on_io_error(e);
+                    return Err(());
+                }
+            };
+
+            match (reply.code().value(), responses.next()) {
+                (334, Some(response)) => command = response,
+                _ => return handler.on_auth(reply).await,
+            }
+        }
+    }
+
     #[tracing::instrument(skip_all, ret)]
     pub async fn send(&mut self) -> H::Result {
         let Self {
             reader,
             writer,
             handler,
+            timeouts,
         } = self;
 
         let replies = reader.as_reply_stream();
@@ -125,9 +223,15 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         // DSN on success or delayed.
         {
             let envelope = if handler.has_pipelining() {
-                Self::send_envelop_pipelining(handler, &mut replies, writer).await
+                Self::send_envelop_pipelining(handler, &mut replies, writer, timeouts.command).await
             } else {
-                Self::send_envelop_without_pipelining(handler, &mut replies, writer).await
+                Self::send_envelop_without_pipelining(
+                    handler,
+                    &mut replies,
+                    writer,
+                    timeouts.command,
+                )
+                .await
             };
             if envelope == Err(()) {
                 return handler.take_result();
@@ -135,12 +239,13 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         }
 
         // TODO: handle CHUNKING ?
-        if let Err(e) = writer.write_all(Verb::Data.as_ref()).await {
-            self.handler.on_io_error(e.into());
-            return self.handler.take_result();
+        if let Err(e) = with_timeout(timeouts.command, writer.write_all(Verb::Data.as_ref())).await
+        {
+            handler.on_io_error(e);
+            return handler.take_result();
         }
 
-        let data_start_reply = match Self::next_reply(&mut replies).await {
+        let data_start_reply = match Self::next_reply(&mut replies, timeouts.command).await {
             Ok(reply) => reply,
             Err(e) => {
                 handler.on_io_error(e);
@@ -152,17 +257,18 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
             return handler.take_result();
         };
 
-        if let Err(e) = writer.write_all_bytes(&handler.get_message()).await {
-            self.handler.on_io_error(e.into());
-            return self.handler.take_result();
-        }
-
-        if let Err(e) = writer.write_all(".\r\n").await {
-            self.handler.on_io_error(e.into());
-            return self.handler.take_result();
+        let message = handler.get_message();
+        if let Err(e) = with_timeout(timeouts.data, async {
+            writer.write_all_bytes(&message).await?;
+            writer.write_all(".\r\n").await
+        })
+        .await
+        {
+            handler.on_io_error(e);
+            return handler.take_result();
         }
 
-        let data_end_reply = match Self::next_reply(&mut replies).await {
+        let data_end_reply = match Self::next_reply(&mut replies, timeouts.data).await {
             Ok(reply) => reply,
             Err(e) => {
                 handler.on_io_error(e);
@@ -179,10 +285,13 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
             mut reader,
             mut writer,
             mut handler,
+            timeouts,
         } = self;
 
-        if let Err(e) = writer.write_all(Verb::StartTls.as_ref()).await {
-            handler.on_io_error(e.into());
+        if let Err(e) =
+            with_timeout(timeouts.command, writer.write_all(Verb::StartTls.as_ref())).await
+        {
+            handler.on_io_error(e);
             return Err(handler.take_result());
         }
 
@@ -190,7 +299,7 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
             let replies = reader.as_reply_stream();
             tokio::pin!(replies);
 
-            match Self::next_reply(&mut replies).await {
+            match Self::next_reply(&mut replies, timeouts.command).await {
                 Ok(reply) => reply,
                 Err(e) => {
                     handler.on_io_error(e);
@@ -224,13 +333,21 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
             peer_addr = ?tcp_stream.peer_addr(),
             "connecting to the remote server"
         );
-        let tls_stream = match handler.get_tls_connector().connect(sni, tcp_stream).await {
+        let tls_stream = match tokio::time::timeout(
+            timeouts.command,
+            handler.get_tls_connector().connect(sni, tcp_stream),
+        )
+        .await
+        .unwrap_or_else(|elapsed| Err(elapsed.into()))
+        {
             Ok(s) => s,
             Err(e) => {
                 return Err(handler.on_tls_upgrade_error(e));
             }
         };
 
+        handler.on_tls_upgrade(tls_stream.get_ref().1);
+
         let (reader, writer) = tokio::io::split(tls_stream);
 
         let (reader, writer) = (
@@ -244,14 +361,18 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
             reader,
             writer,
             handler,
+            timeouts,
         })
     }
 
-    async fn next_reply<S>(reply_stream: &mut S) -> Result<Reply, vsmtp_protocol::Error>
+    async fn next_reply<S>(
+        reply_stream: &mut S,
+        timeout: std::time::Duration,
+    ) -> Result<Reply, vsmtp_protocol::Error>
     where
         S: tokio_stream::Stream<Item = Result<Reply, vsmtp_protocol::Error>> + Unpin + Send,
     {
-        match tokio_stream::StreamExt::try_next(reply_stream).await {
+        match with_timeout(timeout, tokio_stream::StreamExt::try_next(reply_stream)).await {
             Ok(Some(reply)) => Ok(reply),
             Ok(None) => Err(vsmtp_protocol::Error::from(std::io::Error::new(
                 std::io::ErrorKind::UnexpectedEof,
@@ -308,10 +429,7 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
                     " {} NOTIFY={}",
                     original_forward_path
                         .as_ref()
-                        .map_or_else(String::new, |orcpt| format!(
-                            "ORCPT={};{}",
-                            orcpt.addr_type, orcpt.mailbox
-                        )),
+                        .map_or_else(String::new, |orcpt| format!("ORCPT={orcpt}")),
                     match notify_on {
                         NotifyOn::Some {
                             success,
@@ -335,6 +453,7 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         handler: &mut H,
         replies: &mut S,
         sink: &mut Writer<W>,
+        timeout: std::time::Duration,
     ) -> Result<(), ()>
     where
         H: SenderHandler + Sync + Send,
@@ -354,12 +473,12 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         ]
         .concat();
 
-        if let Err(e) = sink.write_all(&cmd).await {
-            handler.on_io_error(e.into());
+        if let Err(e) = with_timeout(timeout, sink.write_all(&cmd)).await {
+            handler.on_io_error(e);
             return Err(());
         }
 
-        let mail_from_reply = match Self::next_reply(replies).await {
+        let mail_from_reply = match Self::next_reply(replies, timeout).await {
             Ok(reply) => reply,
             Err(e) => {
                 handler.on_io_error(e);
@@ -371,7 +490,7 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
 
         let mut at_least_one_rcpt_is_valid = false;
         for i in 0..rcpt.len() {
-            let rcpt_reply = match Self::next_reply(replies).await {
+            let rcpt_reply = match Self::next_reply(replies, timeout).await {
                 Ok(reply) => reply,
                 Err(e) => {
                     handler.on_io_error(e);
@@ -396,6 +515,7 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         handler: &mut H,
         replies: &mut S,
         sink: &mut Writer<W>,
+        timeout: std::time::Duration,
     ) -> Result<(), ()>
     where
         H: SenderHandler + Sync + Send,
@@ -405,15 +525,17 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         let has_dsn = handler.has_dsn();
 
         let from = handler.get_mail_from();
-        if let Err(e) = sink
-            .write_all(&Self::build_mail_from_to_command(&from, has_dsn))
-            .await
+        if let Err(e) = with_timeout(
+            timeout,
+            sink.write_all(&Self::build_mail_from_to_command(&from, has_dsn)),
+        )
+        .await
         {
-            handler.on_io_error(e.into());
+            handler.on_io_error(e);
             return Err(());
         }
 
-        let mail_from_reply = match Self::next_reply(replies).await {
+        let mail_from_reply = match Self::next_reply(replies, timeout).await {
             Ok(reply) => reply,
             Err(e) => {
                 handler.on_io_error(e);
@@ -427,12 +549,12 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         let mut at_least_one_rcpt_is_valid = false;
         for i in rcpt {
             let command = Self::build_rcpt_to_command(&i, has_dsn);
-            if let Err(e) = sink.write_all(&command).await {
-                handler.on_io_error(e.into());
+            if let Err(e) = with_timeout(timeout, sink.write_all(&command)).await {
+                handler.on_io_error(e);
                 return Err(());
             }
 
-            let rcpt_reply = match Self::next_reply(replies).await {
+            let rcpt_reply = match Self::next_reply(replies, timeout).await {
                 Ok(reply) => reply,
                 Err(e) => {
                     handler.on_io_error(e);
@@ -450,3 +572,17 @@ impl<H: SenderHandler + Sync + Send> Sender<H> {
         }
     }
 }
+
+/// Run `future`, failing with [`std::io::ErrorKind::TimedOut`] if it does not complete in `duration`.
+async fn with_timeout<T, E>(
+    duration: std::time::Duration,
+    future: impl std::future::Future<Output = Result<T, E>> + Send,
+) -> Result<T, vsmtp_protocol::Error>
+where
+    E: Into<vsmtp_protocol::Error>,
+{
+    match tokio::time::timeout(duration, future).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(elapsed) => Err(std::io::Error::from(elapsed).into()),
+    }
+}
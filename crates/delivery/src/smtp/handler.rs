@@ -40,6 +40,11 @@ pub trait SenderHandler {
         self.has_extension(Extension::DeliveryStatusNotification)
     }
 
+    /// Get the arguments of an extension advertised by the remote server, if any.
+    fn get_extension_arguments(&self, _extension: Extension) -> Option<String> {
+        None
+    }
+
     async fn on_noop(&self, reply: Reply) -> Result<(), ()>;
     async fn on_quit(&self, reply: Reply) -> Result<(), ()>;
     async fn on_connect(&mut self) -> Result<(), ()>;
@@ -50,6 +55,21 @@ pub trait SenderHandler {
     async fn on_data_start(&mut self, reply: Reply) -> Result<(), ()>;
     async fn on_data_end(&mut self, reply: Reply) -> Result<(), ()>;
 
+    /// Called with the last reply of the AUTH exchange.
+    async fn on_auth(&mut self, reply: Reply) -> Result<(), ()> {
+        if reply.code().value() == 235 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Called when the authentication cannot be performed.
+    fn on_auth_error(&mut self, _error: String) {}
+
+    /// Called once the TLS session is established with the remote server.
+    fn on_tls_upgrade(&mut self, _connection: &rustls::ClientConnection) {}
+
     fn on_tls_upgrade_error(&mut self, error: std::io::Error) -> Self::Result;
     fn on_io_error(&mut self, error: vsmtp_protocol::Error);
 
@@ -0,0 +1,51 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+//! Fixtures shared by the tests of the delivery services.
+
+use vsmtp_common::{
+    ctx_delivery::CtxDelivery, delivery_route::DeliveryRoute, stateful_ctx_received::MailFromProps,
+    Mailbox, Recipient,
+};
+use vsmtp_mail_parser::Mail;
+use vsmtp_protocol::NotifyOn;
+
+/// The envelope of a message sent by `john.doe@example.com`, without DSN nor `BODY` parameter.
+pub fn mail_from() -> MailFromProps {
+    MailFromProps {
+        reverse_path: Some(Mailbox("john.doe@example.com".parse().unwrap())),
+        mail_timestamp: vsmtp_common::time::OffsetDateTime::now_utc(),
+        message_uuid: vsmtp_common::uuid::Uuid::new_v4(),
+        envelop_id: None,
+        spf_mail_from_identity: None,
+        ret: None,
+        mime_body_type: None,
+    }
+}
+
+/// The recipient `addr`, without notification.
+pub fn recipient(addr: &str) -> Recipient {
+    Recipient {
+        forward_path: Mailbox(addr.parse().unwrap()),
+        original_forward_path: None,
+        notify_on: NotifyOn::Never,
+    }
+}
+
+/// The first delivery of `message`, from [`mail_from`] to the `rcpt_to` of `route`.
+pub fn delivery(route: DeliveryRoute, rcpt_to: &[&str], message: &str) -> CtxDelivery {
+    CtxDelivery::new(
+        route,
+        mail_from(),
+        rcpt_to.iter().map(|addr| recipient(addr)).collect(),
+        std::sync::Arc::new(std::sync::RwLock::new(Mail::try_from(message).unwrap())),
+    )
+}
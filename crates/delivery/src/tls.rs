@@ -15,6 +15,9 @@ pub enum Requirement {
     #[default]
     Required,
     Optional,
+    /// Upgrade the connection if the server advertises STARTTLS, but fall back
+    /// to cleartext if the handshake fails, recording the downgrade.
+    Opportunistic,
     Disabled,
 }
 
@@ -0,0 +1,207 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::{DeliveryWindow, Frequency, Tls};
+use vsmtp_common::{ctx_delivery::CtxDelivery, domain_map::DomainMap};
+
+/// Policy applied to the deliveries of the recipients mapped to a transport
+/// by the working service.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transport {
+    /// STARTTLS requirement of the connections, required by default.
+    #[serde(default)]
+    pub tls: Tls,
+    /// Frequency of the attempts, for example `1/30m`. The default backoff is used if not set.
+    #[serde(default)]
+    pub retry: Option<Frequency>,
+    /// Maximum number of connections opened at once by the deliveries of the transport,
+    /// all the messages included.
+    #[serde(default = "Transport::default_concurrency")]
+    pub concurrency: usize,
+    /// Local address of the connections to the remote servers.
+    #[serde(default)]
+    pub source_ip: Option<std::net::IpAddr>,
+    /// Windows during which the recipients are delivered, by domain pattern, for example
+    /// `{ "partner.com": "22:00-06:00" }`. The recipients outside of their window are
+    /// deferred until it opens, the other domains are delivered at any time.
+    #[serde(default)]
+    pub windows: DomainMap<DeliveryWindow>,
+    /// Connection slots shared by the deliveries, created on the first one.
+    #[serde(skip)]
+    slots: std::sync::Mutex<Option<std::sync::Arc<tokio::sync::Semaphore>>>,
+}
+
+impl Transport {
+    const fn default_concurrency() -> usize {
+        10
+    }
+
+    /// Wait for a connection slot of the transport, released when the permit is dropped.
+    ///
+    /// # Panics
+    ///
+    /// * the slots lock is poisoned.
+    pub async fn acquire(&self) -> tokio::sync::OwnedSemaphorePermit {
+        let slots = self
+            .slots
+            .lock()
+            .expect("transport slots poisoned")
+            .get_or_insert_with(|| {
+                std::sync::Arc::new(tokio::sync::Semaphore::new(self.concurrency.max(1)))
+            })
+            .clone();
+
+        slots
+            .acquire_owned()
+            .await
+            .expect("the transport slots are never closed")
+    }
+}
+
+/// Transports by name.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub struct Transports(std::collections::HashMap<String, Transport>);
+
+impl Transports {
+    /// Get the transport of the recipients of a delivery, if any.
+    #[must_use]
+    pub fn get(&self, ctx: &CtxDelivery) -> Option<&Transport> {
+        let name = ctx.transport.as_ref()?;
+        let transport = self.0.get(name);
+        if transport.is_none() {
+            tracing::warn!(transport = %name, "Unknown transport, using the default policy");
+        }
+        transport
+    }
+
+    /// Get the delay before the next attempt of a delivery, if its transport has a retry frequency.
+    #[must_use]
+    pub fn retry_delay(&self, ctx: &CtxDelivery) -> Option<std::time::Duration> {
+        self.get(ctx)
+            .and_then(|transport| transport.retry.as_ref())
+            .map(|retry| *retry.as_ref())
+    }
+
+    /// Get the window during which the recipients of `domain` are delivered, if any.
+    #[must_use]
+    pub fn delivery_window(&self, ctx: &CtxDelivery, domain: &str) -> Option<&DeliveryWindow> {
+        self.get(ctx)
+            .and_then(|transport| transport.windows.get(domain))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transports;
+    use crate::{testing, Requirement};
+    use vsmtp_common::{ctx_delivery::CtxDelivery, delivery_route::DeliveryRoute};
+
+    fn transports() -> Transports {
+        serde_json::from_value(serde_json::json!({
+            "partners": {
+                "tls": { "starttls": "required" },
+                "retry": "1/10m",
+                "concurrency": 2,
+                "source_ip": "192.0.2.10",
+                "windows": { "*.partner.com": "22:00-06:00" },
+            },
+            "bulk": {
+                "tls": { "starttls": "opportunistic" },
+                "retry": "1/4h",
+            },
+        }))
+        .unwrap()
+    }
+
+    fn delivery(transport: Option<&str>) -> CtxDelivery {
+        let mut ctx = testing::delivery(
+            DeliveryRoute::Basic,
+            &[],
+            concat!(
+                "From: john.doe@example.com\r\n",
+                "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+                "Subject: transport\r\n",
+                "\r\n",
+                "Hello world!\r\n",
+            ),
+        );
+        ctx.transport = transport.map(str::to_string);
+        ctx
+    }
+
+    #[test]
+    fn policy_by_transport() {
+        let transports = transports();
+
+        let partners = delivery(Some("partners"));
+        let transport = transports.get(&partners).unwrap();
+        assert!(matches!(transport.tls.starttls, Requirement::Required));
+        assert_eq!(transport.concurrency, 2);
+        assert_eq!(transport.source_ip, Some("192.0.2.10".parse().unwrap()));
+        assert_eq!(
+            transports.retry_delay(&partners),
+            Some(std::time::Duration::from_secs(10 * 60))
+        );
+        assert_eq!(
+            transports.delivery_window(&partners, "mx.partner.com"),
+            Some(&"22:00-06:00".parse().unwrap())
+        );
+        assert_eq!(transports.delivery_window(&partners, "example.com"), None);
+
+        let bulk = delivery(Some("bulk"));
+        let transport = transports.get(&bulk).unwrap();
+        assert!(matches!(transport.tls.starttls, Requirement::Opportunistic));
+        assert_eq!(transport.concurrency, 10);
+        assert_eq!(transport.source_ip, None);
+        assert_eq!(
+            transports.retry_delay(&bulk),
+            Some(std::time::Duration::from_secs(4 * 60 * 60))
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrency_shared_by_the_messages() {
+        let transports = transports();
+        let transport = transports.get(&delivery(Some("partners"))).unwrap();
+
+        let first = transport.acquire().await;
+        // another message of the same transport.
+        let _second = transports
+            .get(&delivery(Some("partners")))
+            .unwrap()
+            .acquire()
+            .await;
+
+        let third = transport.acquire();
+        tokio::pin!(third);
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), &mut third)
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        tokio::time::timeout(std::time::Duration::from_millis(50), third)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn without_transport() {
+        let transports = transports();
+
+        assert!(transports.get(&delivery(None)).is_none());
+        assert!(transports.get(&delivery(Some("unknown"))).is_none());
+        assert_eq!(transports.retry_delay(&delivery(None)), None);
+    }
+}
@@ -0,0 +1,193 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+/// Time of the day during which the recipients of a domain are delivered, for example
+/// `22:00-06:00`. The window ends on the next day if its end is before its start.
+///
+/// The times are in UTC, unless followed by the offset of the timezone of the domain,
+/// for example `09:00-17:00 +02:00`. The offset is fixed, it does not follow the daylight saving time.
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde_with::SerializeDisplay, serde_with::DeserializeFromStr,
+)]
+pub struct DeliveryWindow {
+    start: time::Time,
+    end: time::Time,
+    offset: time::UtcOffset,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseErrorDeliveryWindow {
+    #[error("expected format: <HH:MM>-<HH:MM> [<+|-><HH:MM>]")]
+    Format,
+    #[error("invalid number {0}")]
+    Number(#[from] std::num::ParseIntError),
+    #[error("invalid time {0}")]
+    Time(#[from] time::error::ComponentRange),
+    #[error("the window is empty")]
+    Empty,
+}
+
+impl DeliveryWindow {
+    /// Delay before the window opens, `None` if `now` is within the window.
+    #[must_use]
+    pub fn delay(&self, now: time::OffsetDateTime) -> Option<std::time::Duration> {
+        let now = now.to_offset(self.offset).time();
+        let within = if self.start < self.end {
+            self.start <= now && now < self.end
+        } else {
+            self.start <= now || now < self.end
+        };
+        if within {
+            return None;
+        }
+
+        let mut delay = self.start - now;
+        if delay.is_negative() {
+            delay += time::Duration::DAY;
+        }
+        Some(std::time::Duration::try_from(delay).expect("delay is positive"))
+    }
+}
+
+fn parse_time(s: &str) -> Result<time::Time, ParseErrorDeliveryWindow> {
+    let (hour, minute) = s
+        .trim()
+        .split_once(':')
+        .ok_or(ParseErrorDeliveryWindow::Format)?;
+    Ok(time::Time::from_hms(hour.parse()?, minute.parse()?, 0)?)
+}
+
+fn parse_offset(s: &str) -> Result<time::UtcOffset, ParseErrorDeliveryWindow> {
+    let (sign, offset) = if let Some(offset) = s.strip_prefix('+') {
+        (1, offset)
+    } else if let Some(offset) = s.strip_prefix('-') {
+        (-1, offset)
+    } else {
+        return Err(ParseErrorDeliveryWindow::Format);
+    };
+    let offset = parse_time(offset)?;
+    Ok(time::UtcOffset::from_hms(
+        sign * i8::try_from(offset.hour()).expect("hour is below 24"),
+        sign * i8::try_from(offset.minute()).expect("minute is below 60"),
+        0,
+    )?)
+}
+
+impl std::str::FromStr for DeliveryWindow {
+    type Err = ParseErrorDeliveryWindow;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (window, offset) = match s.trim().split_once(char::is_whitespace) {
+            Some((window, offset)) => (window, parse_offset(offset.trim())?),
+            None => (s, time::UtcOffset::UTC),
+        };
+        let (start, end) = window.split_once('-').ok_or(Self::Err::Format)?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            return Err(Self::Err::Empty);
+        }
+
+        Ok(Self { start, end, offset })
+    }
+}
+
+impl std::fmt::Display for DeliveryWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start.hour(),
+            self.start.minute(),
+            self.end.hour(),
+            self.end.minute()
+        )?;
+        if !self.offset.is_utc() {
+            let sign = if self.offset.is_negative() { '-' } else { '+' };
+            write!(
+                f,
+                " {sign}{:02}:{:02}",
+                self.offset.whole_hours().unsigned_abs(),
+                self.offset.minutes_past_hour().unsigned_abs()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryWindow;
+
+    fn at(hour: u8, minute: u8) -> time::OffsetDateTime {
+        time::OffsetDateTime::UNIX_EPOCH
+            .replace_time(time::Time::from_hms(hour, minute, 0).unwrap())
+    }
+
+    const fn minutes(minutes: u64) -> Option<std::time::Duration> {
+        Some(std::time::Duration::from_secs(minutes * 60))
+    }
+
+    #[test]
+    fn parse() {
+        let window = "9:00-17:30".parse::<DeliveryWindow>().unwrap();
+        assert_eq!(window.to_string(), "09:00-17:30");
+
+        assert!("09:00".parse::<DeliveryWindow>().is_err());
+        assert!("09:00-25:00".parse::<DeliveryWindow>().is_err());
+        assert!("09:00-09:00".parse::<DeliveryWindow>().is_err());
+
+        let window = "9:00-17:30 +2:00".parse::<DeliveryWindow>().unwrap();
+        assert_eq!(window.to_string(), "09:00-17:30 +02:00");
+        let window = "09:00-17:30 -03:30".parse::<DeliveryWindow>().unwrap();
+        assert_eq!(window.to_string(), "09:00-17:30 -03:30");
+        assert_eq!(
+            "09:00-17:30 +00:00"
+                .parse::<DeliveryWindow>()
+                .unwrap()
+                .to_string(),
+            "09:00-17:30"
+        );
+
+        assert!("09:00-17:30 02:00".parse::<DeliveryWindow>().is_err());
+        assert!("09:00-17:30 +25:00".parse::<DeliveryWindow>().is_err());
+    }
+
+    #[test]
+    fn timezone() {
+        // 09:00-17:30 in UTC+02:00 is 07:00-15:30 in UTC.
+        let window = "09:00-17:30 +02:00".parse::<DeliveryWindow>().unwrap();
+
+        assert_eq!(window.delay(at(7, 0)), None);
+        assert_eq!(window.delay(at(15, 29)), None);
+        assert_eq!(window.delay(at(6, 15)), minutes(45));
+        assert_eq!(window.delay(at(15, 30)), minutes(15 * 60 + 30));
+    }
+
+    #[test]
+    fn same_day() {
+        let window = "09:00-17:30".parse::<DeliveryWindow>().unwrap();
+
+        assert_eq!(window.delay(at(9, 0)), None);
+        assert_eq!(window.delay(at(17, 29)), None);
+        assert_eq!(window.delay(at(8, 15)), minutes(45));
+        assert_eq!(window.delay(at(17, 30)), minutes(15 * 60 + 30));
+    }
+
+    #[test]
+    fn overnight() {
+        let window = "22:00-06:00".parse::<DeliveryWindow>().unwrap();
+
+        assert_eq!(window.delay(at(23, 0)), None);
+        assert_eq!(window.delay(at(5, 59)), None);
+        assert_eq!(window.delay(at(6, 0)), minutes(16 * 60));
+        assert_eq!(window.delay(at(21, 30)), minutes(30));
+    }
+}
@@ -92,6 +92,46 @@ pub enum LogInstanceType {
     Journald,
 }
 
+/// Sampling of the events of a target.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SamplingRule {
+    /// Dispatch only 1 event out of `one_in`.
+    #[serde(default)]
+    pub one_in: Option<u64>,
+    /// Maximum number of events dispatched per second.
+    #[serde(default)]
+    pub max_per_second: Option<u64>,
+}
+
+/// Sampling of the events per target, to avoid flooding the sinks.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sampling {
+    /// Rules to apply, by target. A rule also applies to the events
+    /// whose target starts with the key, the longest key being used.
+    #[serde(default)]
+    pub targets: std::collections::HashMap<String, SamplingRule>,
+    /// Interval at which the number of dropped events is reported.
+    #[serde(default = "Sampling::default_summary_interval", with = "humantime_serde")]
+    pub summary_interval: std::time::Duration,
+}
+
+impl Sampling {
+    const fn default_summary_interval() -> std::time::Duration {
+        std::time::Duration::from_secs(60)
+    }
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            targets: std::collections::HashMap::default(),
+            summary_interval: Self::default_summary_interval(),
+        }
+    }
+}
+
 /// Configuration of a logger
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct LogInstance {
@@ -120,6 +160,9 @@ pub struct LogDispatcherConfig {
     /// all loggers used by the log dispatcher.
     #[serde(default, with = "loggers")]
     pub loggers: std::collections::HashMap<String, Vec<LogInstanceType>>,
+    /// sampling of the events before being dispatched to the loggers.
+    #[serde(default)]
+    pub sampling: Sampling,
 }
 
 mod loggers {
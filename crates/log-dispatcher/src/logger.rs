@@ -282,7 +282,7 @@ impl Logger for Journald {
         if let Some(socket) = &mut self.socket {
             if let Some(msg) = vsmtp_log_dispatcher::get_message(event) {
                 let msg = Self::format_event(event, &msg);
-                if let Err(err) = socket.send_to(msg.as_bytes(), Self::SYSTEMD_SOCKET) {
+                if let Err(err) = socket.send_to(&msg, Self::SYSTEMD_SOCKET) {
                     tracing::warn!("Cannot send message to journald: {err}");
                 }
             }
@@ -293,25 +293,85 @@ impl Logger for Journald {
 impl Journald {
     const SYSTEMD_SOCKET: &'static str = "/run/systemd/journal/socket";
 
-    /// Format an event to be syslog compliant
+    /// Convert the name of an event field to a journald field name,
+    /// `None` if nothing valid remains.
+    ///
+    /// Journald only accepts uppercase letters, digits and underscores,
+    /// and the name cannot start with a digit or an underscore.
+    ///
+    /// # Arguments:
+    /// * `name` name of the event field
+    fn field_name(name: &str) -> Option<String> {
+        let name = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .skip_while(|c| !c.is_ascii_uppercase())
+            .take(64)
+            .collect::<String>();
+
+        (!name.is_empty()).then_some(name)
+    }
+
+    /// Append a `NAME=value` pair using the journald native protocol.
+    ///
+    /// # Arguments:
+    /// * `buffer` the datagram being built
+    /// * `name` a valid journald field name
+    /// * `value` the value of the field
+    fn push_field(buffer: &mut Vec<u8>, name: &str, value: &str) {
+        buffer.extend_from_slice(name.as_bytes());
+        // values with a newline must be length prefixed (see https://systemd.io/JOURNAL_NATIVE_PROTOCOL/)
+        if value.contains('\n') {
+            buffer.push(b'\n');
+            buffer.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buffer.push(b'=');
+        }
+        buffer.extend_from_slice(value.as_bytes());
+        buffer.push(b'\n');
+    }
+
+    /// Format an event using the journald native protocol, each field of the
+    /// event being sent as a journald field.
     ///
     /// # Arguments:
     /// * `event` the event to format
     /// * `message` the message to send
-    fn format_event(event: &Event, msg: &String) -> String {
-        // TODO: handle '\n' logging (see https://systemd.io/JOURNAL_NATIVE_PROTOCOL/)
-        let mut msg = format!(
-            "MESSAGE={}\nPRIORITY={}\n",
-            msg,
-            formatter::level_to_syslog_level(&event.level),
+    fn format_event(event: &Event, msg: &str) -> Vec<u8> {
+        let mut buffer = vec![];
+        Self::push_field(&mut buffer, "MESSAGE", msg);
+        Self::push_field(
+            &mut buffer,
+            "PRIORITY",
+            &formatter::level_to_syslog_level(&event.level).to_string(),
         );
         if let Some(file) = event.file {
-            msg.push_str(format!("CODE_FILE={file}\n").as_str());
+            Self::push_field(&mut buffer, "CODE_FILE", file);
         }
         if let Some(line) = event.line {
-            msg.push_str(format!("CODE_LINE={line}\n").as_str());
+            Self::push_field(&mut buffer, "CODE_LINE", &line.to_string());
         }
-        msg
+        Self::push_field(&mut buffer, "TARGET", event.target);
+
+        for (name, value) in &event.fields {
+            if name == "message" {
+                continue;
+            }
+            let Some(name) = Self::field_name(name) else {
+                continue;
+            };
+            match value {
+                serde_json::Value::String(value) => Self::push_field(&mut buffer, &name, value),
+                otherwise => Self::push_field(&mut buffer, &name, &otherwise.to_string()),
+            }
+        }
+        buffer
     }
 
     /// Instantiate a new journald logger
@@ -326,3 +386,89 @@ impl Journald {
         Self { socket }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(level: tracing::Level, fields: serde_json::Value) -> Event<'static> {
+        Event {
+            timestamp: std::time::SystemTime::now(),
+            name: "event",
+            target: "vsmtp_receiver::smtp",
+            service: "receiver".to_string(),
+            level,
+            module_path: None,
+            file: Some("src/session.rs"),
+            line: Some(42),
+            kind: 1,
+            topic: "receiver".to_string(),
+            hostname: None,
+            fields: fields.as_object().unwrap().clone(),
+            spans: vec![],
+        }
+    }
+
+    #[test]
+    fn journald_fields() {
+        let event = event(
+            tracing::Level::WARN,
+            serde_json::json!({
+                "message": "Recipient rejected",
+                "rcpt": "jenny@example.net",
+                "code": 554,
+                "enhanced.code": "5.7.1",
+                "_private": true,
+            }),
+        );
+
+        let datagram = String::from_utf8(Journald::format_event(&event, "Recipient rejected"))
+            .unwrap();
+        let fields = datagram.lines().collect::<Vec<_>>();
+
+        assert_eq!(fields.iter().filter(|f| f.starts_with("MESSAGE=")).count(), 1);
+        for expected in [
+            "MESSAGE=Recipient rejected",
+            "PRIORITY=4",
+            "CODE_FILE=src/session.rs",
+            "CODE_LINE=42",
+            "TARGET=vsmtp_receiver::smtp",
+            "RCPT=jenny@example.net",
+            "CODE=554",
+            "ENHANCED_CODE=5.7.1",
+            "PRIVATE=true",
+        ] {
+            assert!(fields.contains(&expected), "{expected} not in {fields:?}");
+        }
+    }
+
+    #[test]
+    fn journald_priority() {
+        for (level, priority) in [
+            (tracing::Level::ERROR, "PRIORITY=3"),
+            (tracing::Level::WARN, "PRIORITY=4"),
+            (tracing::Level::INFO, "PRIORITY=6"),
+            (tracing::Level::DEBUG, "PRIORITY=7"),
+            (tracing::Level::TRACE, "PRIORITY=7"),
+        ] {
+            let event = event(level, serde_json::json!({ "message": "hello" }));
+            let datagram = String::from_utf8(Journald::format_event(&event, "hello")).unwrap();
+
+            assert!(datagram.lines().any(|f| f == priority));
+        }
+    }
+
+    #[test]
+    fn journald_multiline_value() {
+        let event = event(
+            tracing::Level::INFO,
+            serde_json::json!({ "message": "hello", "body": "a\nb" }),
+        );
+        let datagram = Journald::format_event(&event, "hello");
+
+        let mut expected = b"BODY\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert!(datagram.windows(expected.len()).any(|w| w == expected));
+    }
+}
@@ -23,6 +23,7 @@ use tracing_subscriber::{
 mod config;
 mod formatter;
 mod logger;
+mod sampler;
 
 const FORMATTERS_RFC3164: formatter::Rfc3164 = formatter::Rfc3164;
 const FORMATTERS_RFC5424: formatter::Rfc5424 = formatter::Rfc5424;
@@ -97,6 +98,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut consumers = StreamMap::new();
     let mut loggers = HashMap::<String, Vec<Box<dyn Logger>>>::new();
+    let mut samplers = HashMap::<String, sampler::Sampler>::new();
 
     for (topic, sinks) in config.loggers {
         let sinks = sinks
@@ -104,6 +106,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .map(instantiate_logger)
             .collect::<Vec<_>>();
         loggers.insert(topic.clone(), sinks);
+        samplers.insert(
+            topic.clone(),
+            sampler::Sampler::new(&config.sampling, std::time::Instant::now()),
+        );
 
         let channel = conn.create_channel().await?;
         channel
@@ -165,8 +171,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .ack(lapin::options::BasicAckOptions::default())
                     .await?;
 
-                for logger in loggers.get_mut(&topic).expect("topic exists") {
-                    logger.log(&event);
+                let now = std::time::Instant::now();
+                let sampler = samplers.get_mut(&topic).expect("topic exists");
+                let loggers = loggers.get_mut(&topic).expect("topic exists");
+
+                if sampler.sample(event.target, now) {
+                    for logger in loggers.iter_mut() {
+                        logger.log(&event);
+                    }
+                }
+
+                // the summary is checked when an event is received on the topic,
+                // the dropped events can only be reported at that point.
+                for summary in sampler.summary(now).into_iter().flatten() {
+                    let event = summary.to_event(&topic);
+                    for logger in loggers.iter_mut() {
+                        logger.log(&event);
+                    }
                 }
             }
             Err(e) => {
@@ -0,0 +1,302 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::config::{Sampling, SamplingRule};
+use tracing_amqp::Event;
+
+/// Counters of a sampled target.
+struct TargetState {
+    /// Number of events received since the last summary.
+    seen: u64,
+    /// Number of events dropped since the last summary.
+    dropped: u64,
+    /// Number of events received, used for the `one_in` ratio.
+    total: u64,
+    /// Start of the current one second window.
+    window_start: std::time::Instant,
+    /// Number of events dispatched in the current window.
+    window_count: u64,
+}
+
+/// Number of events received and dropped for a target during a summary interval.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Summary {
+    /// Target (or prefix) of the sampling rule.
+    pub target: String,
+    /// Number of events received.
+    pub seen: u64,
+    /// Number of events which were not dispatched.
+    pub dropped: u64,
+}
+
+impl Summary {
+    /// Create the event dispatched to the loggers for this summary.
+    ///
+    /// # Arguments:
+    /// * `topic` topic on which the events were received
+    pub fn to_event(&self, topic: &str) -> Event<'static> {
+        let mut fields = serde_json::Map::new();
+        fields.insert("message".to_string(), "Log sampling summary".into());
+        fields.insert("sampled_target".to_string(), self.target.clone().into());
+        fields.insert("seen".to_string(), self.seen.into());
+        fields.insert("dropped".to_string(), self.dropped.into());
+
+        Event {
+            timestamp: std::time::SystemTime::now(),
+            name: "sampling summary",
+            target: "vsmtp_log_dispatcher::sampler",
+            service: "log-dispatcher".to_string(),
+            level: tracing::Level::INFO,
+            module_path: None,
+            file: None,
+            line: None,
+            kind: 1,
+            topic: topic.to_string(),
+            hostname: None,
+            fields,
+            spans: vec![],
+        }
+    }
+}
+
+/// Drop the events of the targets producing too many logs.
+pub struct Sampler {
+    /// Sampling rules, by target.
+    rules: std::collections::HashMap<String, SamplingRule>,
+    /// Interval at which the summaries are produced.
+    summary_interval: std::time::Duration,
+    /// Last time a summary was produced.
+    last_summary: std::time::Instant,
+    /// Counters, by rule.
+    states: std::collections::HashMap<String, TargetState>,
+}
+
+impl Sampler {
+    /// Instantiate a new sampler
+    ///
+    /// # Arguments:
+    /// * `config` sampling configuration
+    /// * `now` start of the first summary interval
+    pub fn new(config: &Sampling, now: std::time::Instant) -> Self {
+        Self {
+            rules: config.targets.clone(),
+            summary_interval: config.summary_interval,
+            last_summary: now,
+            states: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Get the rule applying to a target, the longest matching prefix winning.
+    fn rule(&self, target: &str) -> Option<(&String, &SamplingRule)> {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+    }
+
+    /// Should the event of `target` be dispatched to the loggers ?
+    ///
+    /// # Arguments:
+    /// * `target` target of the event
+    /// * `now` time at which the event is handled
+    pub fn sample(&mut self, target: &str, now: std::time::Instant) -> bool {
+        let Some((prefix, rule)) = self.rule(target) else {
+            return true;
+        };
+
+        let state = self
+            .states
+            .entry(prefix.clone())
+            .or_insert_with(|| TargetState {
+                seen: 0,
+                dropped: 0,
+                total: 0,
+                window_start: now,
+                window_count: 0,
+            });
+
+        state.seen += 1;
+        state.total += 1;
+
+        let mut keep = rule
+            .one_in
+            .map_or(true, |one_in| one_in <= 1 || (state.total - 1) % one_in == 0);
+
+        if keep {
+            if let Some(max_per_second) = rule.max_per_second {
+                if now.duration_since(state.window_start) >= std::time::Duration::from_secs(1) {
+                    state.window_start = now;
+                    state.window_count = 0;
+                }
+                keep = state.window_count < max_per_second;
+            }
+        }
+
+        if keep {
+            state.window_count += 1;
+        } else {
+            state.dropped += 1;
+        }
+        keep
+    }
+
+    /// Produce the summaries of the sampled targets if the summary interval has elapsed,
+    /// resetting the counters.
+    ///
+    /// # Arguments:
+    /// * `now` current time
+    pub fn summary(&mut self, now: std::time::Instant) -> Option<Vec<Summary>> {
+        if now.duration_since(self.last_summary) < self.summary_interval {
+            return None;
+        }
+        self.last_summary = now;
+
+        let mut summaries = self
+            .states
+            .iter_mut()
+            .filter(|(_, state)| state.seen != 0)
+            .map(|(target, state)| {
+                let summary = Summary {
+                    target: target.clone(),
+                    seen: state.seen,
+                    dropped: state.dropped,
+                };
+                state.seen = 0;
+                state.dropped = 0;
+                summary
+            })
+            .collect::<Vec<_>>();
+        summaries.sort_by(|a, b| a.target.cmp(&b.target));
+
+        Some(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampler(rules: &[(&str, SamplingRule)]) -> (Sampler, std::time::Instant) {
+        let now = std::time::Instant::now();
+        let config = Sampling {
+            targets: rules
+                .iter()
+                .map(|(target, rule)| ((*target).to_string(), rule.clone()))
+                .collect(),
+            summary_interval: std::time::Duration::from_secs(60),
+        };
+        (Sampler::new(&config, now), now)
+    }
+
+    #[test]
+    fn one_in() {
+        let (mut sampler, now) = sampler(&[(
+            "vsmtp_receiver",
+            SamplingRule {
+                one_in: Some(10),
+                max_per_second: None,
+            },
+        )]);
+
+        let kept = (0..100)
+            .filter(|_| sampler.sample("vsmtp_receiver::smtp::session", now))
+            .count();
+        assert_eq!(kept, 10);
+
+        // targets without rule are not sampled
+        assert!((0..100).all(|_| sampler.sample("vsmtp_working", now)));
+    }
+
+    #[test]
+    fn max_per_second() {
+        let (mut sampler, now) = sampler(&[(
+            "vsmtp_receiver",
+            SamplingRule {
+                one_in: None,
+                max_per_second: Some(5),
+            },
+        )]);
+
+        let kept = (0..20)
+            .filter(|_| sampler.sample("vsmtp_receiver", now))
+            .count();
+        assert_eq!(kept, 5);
+
+        let later = now + std::time::Duration::from_secs(1);
+        let kept = (0..20)
+            .filter(|_| sampler.sample("vsmtp_receiver", later))
+            .count();
+        assert_eq!(kept, 5);
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let (mut sampler, now) = sampler(&[
+            (
+                "vsmtp_receiver",
+                SamplingRule {
+                    one_in: Some(2),
+                    max_per_second: None,
+                },
+            ),
+            (
+                "vsmtp_receiver::smtp",
+                SamplingRule {
+                    one_in: Some(4),
+                    max_per_second: None,
+                },
+            ),
+        ]);
+
+        let kept = (0..8)
+            .filter(|_| sampler.sample("vsmtp_receiver::smtp::session", now))
+            .count();
+        assert_eq!(kept, 2);
+    }
+
+    #[test]
+    fn summary() {
+        let (mut sampler, now) = sampler(&[(
+            "vsmtp_receiver",
+            SamplingRule {
+                one_in: Some(4),
+                max_per_second: None,
+            },
+        )]);
+
+        for _ in 0..100 {
+            sampler.sample("vsmtp_receiver", now);
+        }
+        assert_eq!(sampler.summary(now), None);
+
+        let later = now + std::time::Duration::from_secs(60);
+        let summaries = sampler.summary(later).unwrap();
+        assert_eq!(
+            summaries,
+            vec![Summary {
+                target: "vsmtp_receiver".to_string(),
+                seen: 100,
+                dropped: 75,
+            }]
+        );
+
+        let event = summaries[0].to_event("receiver");
+        assert_eq!(event.fields["sampled_target"], "vsmtp_receiver");
+        assert_eq!(event.fields["seen"], 100);
+        assert_eq!(event.fields["dropped"], 75);
+
+        // counters are reset after a summary
+        let summaries = sampler
+            .summary(later + std::time::Duration::from_secs(60))
+            .unwrap();
+        assert!(summaries.is_empty());
+    }
+}
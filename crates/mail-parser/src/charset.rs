@@ -0,0 +1,59 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+/// Get the encoding of a charset label (ex: `iso-8859-1`, `utf8`), `None` if it is unknown.
+/// <https://encoding.spec.whatwg.org/#names-and-labels>
+pub fn encoding(charset: &str) -> Option<&'static encoding_rs::Encoding> {
+    encoding_rs::Encoding::for_label(charset.trim().as_bytes())
+}
+
+/// Decode the `bytes` of a text encoded in `charset`, `None` if the
+/// charset is unknown or the bytes are not valid for it.
+pub fn decode(charset: &str, bytes: &[u8]) -> Option<String> {
+    encoding(charset)?
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(std::borrow::Cow::into_owned)
+}
+
+/// Decode the `bytes` of a text encoded in `charset`, replacing the invalid sequences.
+/// A text without charset, or with an unknown one, is read as UTF-8.
+pub fn decode_lossy(charset: Option<&str>, bytes: &[u8]) -> String {
+    charset
+        .and_then(encoding)
+        .unwrap_or(encoding_rs::UTF_8)
+        .decode_without_bom_handling(bytes)
+        .0
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, decode_lossy};
+
+    #[test]
+    fn strict() {
+        assert_eq!(decode("UTF-8", "café".as_bytes()).as_deref(), Some("café"));
+        assert_eq!(decode("latin1", b"caf\xE9").as_deref(), Some("café"));
+        assert_eq!(
+            decode("koi8-r", b"\xD4\xC5\xD3\xD4").as_deref(),
+            Some("тест")
+        );
+        assert_eq!(decode("utf-8", b"caf\xE9"), None);
+        assert_eq!(decode("x-unknown", b"cafe"), None);
+    }
+
+    #[test]
+    fn lossy() {
+        assert_eq!(decode_lossy(Some("iso-8859-1"), b"caf\xE9"), "café");
+        assert_eq!(decode_lossy(None, b"caf\xE9"), "caf\u{FFFD}");
+        assert_eq!(decode_lossy(Some("x-unknown"), "café".as_bytes()), "café");
+    }
+}
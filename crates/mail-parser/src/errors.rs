@@ -24,6 +24,25 @@ pub enum ParserError {
         /// Actual size.
         got: usize,
     },
+    /// The header section of the message is longer than expected.
+    #[error("header section is not supposed to be longer than {expected} bytes but got {got}")]
+    HeaderTooLong {
+        /// Maximum size expected.
+        expected: usize,
+        /// Actual size.
+        got: usize,
+    },
+    /// A line of the message is longer than expected.
+    #[error("line is not supposed to be longer than {expected} bytes but got {got}")]
+    LineTooLong {
+        /// Maximum length expected.
+        expected: usize,
+        /// Actual length.
+        got: usize,
+    },
+    /// The message has more MIME parts than expected, nested parts included.
+    #[error("message is not supposed to have more than {0} MIME parts")]
+    TooManyParts(usize),
     ///
     #[error("parsing email failed: {0}")]
     InvalidMail(String),
@@ -24,6 +24,8 @@
 /// average size of a mail
 pub const MAIL_SIZE: usize = 1_000_000; // 1MB
 
+/// Conversion of the texts from their charset.
+mod charset;
 /// Errors raised by the parser.
 pub mod errors;
 /// Rust representation of an email.
@@ -18,10 +18,20 @@ use self::{
     headers::{Header, Headers},
 };
 
+/// Parsing of the address header fields.
+pub mod addresses;
 /// Body definition of an email.
 pub mod body;
+/// Encoded words of the unstructured header fields (RFC 2047).
+pub mod encoded_words;
+/// Fingerprint of an email, used to detect duplicates.
+mod fingerprint;
 /// Headers definition of an email.
 pub mod headers;
+/// Parsing of the `Received` trace header fields.
+pub mod received;
+/// Extraction of the URLs of an email.
+mod urls;
 
 pub const FROM_HEADER: &str = "From";
 pub const TO_HEADER: &str = "To";
@@ -70,12 +80,16 @@ impl Mail {
     /// # Args
     ///
     /// * `stream` - The stream to parse the email from.
+    /// * `max_parts` - The maximum number of MIME parts of the email, nested parts included,
+    ///   see [`crate::parsing::bytes::Parser::with_max_parts`].
     ///
     /// # Errors
     ///
     /// * The input is not compliant
+    /// * The email has more than `max_parts` MIME parts.
     pub async fn parse_stream<'a>(
         mut stream: impl tokio_stream::Stream<Item = Result<Vec<u8>, ParserError>> + Unpin + Send + 'a,
+        max_parts: Option<usize>,
     ) -> Result<Self, ParserError> {
         let mut buffer = Vec::new();
 
@@ -83,7 +97,18 @@ impl Mail {
             buffer.push(i);
         }
 
-        crate::parsing::bytes::Parser::default().parse_headers(buffer)
+        let Some(max_parts) = max_parts else {
+            return crate::parsing::bytes::Parser::default().parse_headers(buffer);
+        };
+
+        let mut parser = crate::parsing::bytes::Parser::with_max_parts(max_parts);
+        let mail = parser.parse_headers(buffer)?;
+        // the body is parsed on demand, a malformed body is only reported then.
+        if let Err(error @ ParserError::TooManyParts(_)) = parser.check_body(&mail) {
+            return Err(error);
+        }
+
+        Ok(mail)
     }
 
     /// Get a mutable reference on the body.
@@ -286,6 +311,114 @@ impl Mail {
     pub fn parse_body(&mut self) -> Result<&mut ParsedBody, ParserError> {
         self.body_mut()
     }
+
+    /// Get the text of the body, decoded and converted to UTF-8. For MIME messages,
+    /// see [`mime::Mime::body_text`].
+    ///
+    /// # Errors
+    ///
+    /// Failed to parse or decode the body.
+    pub fn body_text(&mut self) -> Result<String, ParserError> {
+        if matches!(self.body, Body::Empty) {
+            return Ok(String::new());
+        }
+
+        match self.parse_body()? {
+            ParsedBody::Text(content) => Ok(content.concat()),
+            ParsedBody::Mime(mime) => mime.body_text(),
+            ParsedBody::Empty => Ok(String::new()),
+        }
+    }
+
+    /// Append a footer to the body of the email. For MIME messages, see
+    /// [`mime::Mime::append_footer`], otherwise the text footer is appended,
+    /// derived from the html footer if empty.
+    ///
+    /// # Errors
+    ///
+    /// Failed to parse the body.
+    pub fn append_footer(&mut self, text_plain: &str, text_html: &str) -> Result<(), ParserError> {
+        if matches!(self.body, Body::Empty) {
+            self.body = Body::Parsed(ParsedBody::Empty);
+        }
+
+        let body = self.parse_body()?;
+        if matches!(body, ParsedBody::Empty) {
+            *body = ParsedBody::Text(vec![]);
+        }
+
+        let mime_headers = match body {
+            ParsedBody::Mime(mime) => {
+                mime.append_footer(text_plain, text_html)?;
+                // the top-level mime headers are written from the header section.
+                mime.headers
+                    .iter()
+                    .map(|header| {
+                        let raw = header.to_string();
+                        (
+                            header.name.clone(),
+                            raw[header.name.len() + 1..].to_string(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            }
+            ParsedBody::Text(content) => {
+                let text_plain = if text_plain.is_empty() {
+                    mime::html_to_text(text_html)
+                } else {
+                    text_plain.to_string()
+                };
+                if text_plain.is_empty() {
+                    return Ok(());
+                }
+
+                if let Some(last) = content
+                    .last_mut()
+                    .filter(|last| !last.is_empty() && !last.ends_with('\n'))
+                {
+                    last.push_str("\r\n");
+                }
+                content.extend(
+                    mime::lines(text_plain.as_bytes())
+                        .into_iter()
+                        .map(|line| format!("{}\r\n", String::from_utf8_lossy(line))),
+                );
+
+                // a body without mime headers is in US-ASCII.
+                // https://datatracker.ietf.org/doc/html/rfc2045#section-5.2
+                if text_plain.is_ascii() {
+                    return Ok(());
+                }
+                [
+                    (mime::MIME_VERSION_HEADER, "1.0"),
+                    (mime::CONTENT_TYPE_HEADER, "text/plain; charset=utf-8"),
+                    (mime::CONTENT_TRANSFER_ENCODING_HEADER, "8bit"),
+                ]
+                .into_iter()
+                .map(|(name, body)| (name.to_string(), format!(" {body}\r\n")))
+                .collect()
+            }
+            ParsedBody::Empty => return Ok(()),
+        };
+
+        for (name, body) in mime_headers.into_iter().filter(|(name, _)| {
+            name.eq_ignore_ascii_case(mime::CONTENT_TYPE_HEADER)
+                || name.eq_ignore_ascii_case(mime::CONTENT_TRANSFER_ENCODING_HEADER)
+                || name.eq_ignore_ascii_case(mime::MIME_VERSION_HEADER)
+        }) {
+            match self
+                .headers
+                .0
+                .iter_mut()
+                .find(|header| header.name.eq_ignore_ascii_case(&name))
+            {
+                Some(header) => header.body = body,
+                None => self.headers.push(Header::new_unchecked(name, body)),
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for Mail {
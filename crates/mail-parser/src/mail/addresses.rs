@@ -0,0 +1,239 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Mail;
+
+/// A mailbox of an address header field (`From`, `Sender`, `Reply-To`, `To`, ...).
+/// <https://www.rfc-editor.org/rfc/rfc5322#section-3.4>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    /// The display name, unquoted. Encoded words are not decoded.
+    pub display_name: Option<String>,
+    /// The `local-part@domain` address.
+    pub address: String,
+}
+
+impl Address {
+    /// The domain of the address.
+    #[must_use]
+    pub fn domain(&self) -> &str {
+        self.address
+            .rsplit_once('@')
+            .map_or("", |(_, domain)| domain)
+    }
+}
+
+impl Mail {
+    /// Parse the mailboxes of all the header fields named `name`, in order of appearance.
+    ///
+    /// The members of the groups are flattened, and the malformed mailboxes are skipped.
+    #[must_use]
+    pub fn header_addresses(&self, name: &str) -> Vec<Address> {
+        self.get_headers(name)
+            .flat_map(|header| parse_address_list(&header.body))
+            .collect()
+    }
+}
+
+/// Parse an `address-list`, ignoring the comments and the group names.
+fn parse_address_list(value: &str) -> Vec<Address> {
+    let mut addresses = vec![];
+    // the text outside of the angle brackets, the quotes are kept.
+    let mut phrase = String::new();
+    let mut angle: Option<String> = None;
+    let mut in_angle = false;
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        let buffer = if in_angle {
+            angle.get_or_insert_with(String::new)
+        } else {
+            &mut phrase
+        };
+
+        match c {
+            '(' => {
+                skip_comment(&mut chars);
+                buffer.push(' ');
+            }
+            '"' => {
+                buffer.push('"');
+                while let Some(c) = chars.next() {
+                    buffer.push(c);
+                    match c {
+                        '\\' => buffer.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '\r' | '\n' => {}
+            '<' if !in_angle => {
+                in_angle = true;
+                angle = Some(String::new());
+            }
+            '>' if in_angle => in_angle = false,
+            // the display name of a group.
+            ':' if !in_angle => {
+                phrase.clear();
+                angle = None;
+            }
+            ',' | ';' if !in_angle => {
+                addresses.extend(to_address(&phrase, angle.as_deref()));
+                phrase.clear();
+                angle = None;
+            }
+            _ => buffer.push(c),
+        }
+    }
+    addresses.extend(to_address(&phrase, angle.as_deref()));
+
+    addresses
+}
+
+/// Skip a (possibly nested) comment, the opening parenthesis being consumed.
+fn skip_comment(chars: &mut std::str::Chars<'_>) {
+    let mut depth = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' => depth += 1,
+            ')' if depth == 1 => return,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+}
+
+fn to_address(phrase: &str, angle: Option<&str>) -> Option<Address> {
+    let (display_name, address) = match angle {
+        Some(angle) => {
+            // obsolete source route: `<@relay.example.com:john@example.com>`
+            let address = match angle.trim_start().strip_prefix('@') {
+                Some(route) => route.split_once(':').map_or(angle, |(_, address)| address),
+                None => angle,
+            };
+            (
+                Some(unquote(phrase)).filter(|name| !name.is_empty()),
+                address,
+            )
+        }
+        None => (None, phrase),
+    };
+
+    let address = address.trim();
+    let (local_part, domain) = address.rsplit_once('@')?;
+    if local_part.is_empty() || domain.is_empty() || domain.contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some(Address {
+        display_name,
+        address: address.to_string(),
+    })
+}
+
+/// Remove the quotes and the escapes of a phrase, and collapse its whitespaces.
+fn unquote(phrase: &str) -> String {
+    let mut output = String::with_capacity(phrase.len());
+    let mut chars = phrase.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => output.extend(chars.next()),
+            '"' => {}
+            _ => output.push(c),
+        }
+    }
+
+    output.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_address_list, Address};
+    use crate::Mail;
+
+    fn address(display_name: Option<&str>, address: &str) -> Address {
+        Address {
+            display_name: display_name.map(ToString::to_string),
+            address: address.to_string(),
+        }
+    }
+
+    #[test]
+    fn display_names() {
+        pretty_assertions::assert_eq!(
+            parse_address_list(concat!(
+                "\"Doe, John\" <john.doe@example.com>, ",
+                "Jenny (the boss) \"\\\"J\\\"\" <jenny@example.com>,",
+                "\"quoted local\"@example.org (comment, with a comma)\r\n",
+            )),
+            vec![
+                address(Some("Doe, John"), "john.doe@example.com"),
+                address(Some("Jenny \"J\""), "jenny@example.com"),
+                address(None, "\"quoted local\"@example.org"),
+            ]
+        );
+    }
+
+    #[test]
+    fn groups() {
+        pretty_assertions::assert_eq!(
+            parse_address_list(concat!(
+                "undisclosed-recipients:;, ",
+                "Team: alice@example.com, \"Bob\" <bob@example.org>;, ",
+                "<@relay.example.net:carol@example.net>",
+            )),
+            vec![
+                address(None, "alice@example.com"),
+                address(Some("Bob"), "bob@example.org"),
+                address(None, "carol@example.net"),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        pretty_assertions::assert_eq!(
+            parse_address_list("John Doe, <>, jenny@, @example.com, john@example.com"),
+            vec![address(None, "john@example.com")]
+        );
+    }
+
+    #[test]
+    fn folded_headers() {
+        let mail = Mail::try_from(concat!(
+            "From: \"John Doe\"\r\n",
+            " <john.doe@example.com>\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "Reply-To: a@example.com,\r\n",
+            "\tb@example.org\r\n",
+            "Reply-To: c@example.net\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+        ))
+        .unwrap();
+
+        pretty_assertions::assert_eq!(
+            mail.header_addresses("from"),
+            vec![address(Some("John Doe"), "john.doe@example.com")]
+        );
+        pretty_assertions::assert_eq!(
+            mail.header_addresses("Reply-To")
+                .iter()
+                .map(Address::domain)
+                .collect::<Vec<_>>(),
+            vec!["example.com", "example.org", "example.net"]
+        );
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::{headers::Header, Mail};
+
+const SUBJECT_HEADER: &str = "Subject";
+
+/// Maximum length of the text of an encoded word, so that `=?utf-8?B?<text>?=`
+/// fits in the 75 characters allowed by the RFC.
+const MAX_ENCODED_TEXT: usize = 75 - "=?utf-8?B??=".len();
+
+/// Decode the encoded words of an unstructured header value (like `Subject`),
+/// and unfold it. <https://www.rfc-editor.org/rfc/rfc2047>
+///
+/// The words with an unknown charset or a malformed encoding are kept as is.
+#[must_use]
+pub fn decode(value: &str) -> String {
+    decode_words(value).0
+}
+
+/// See [`decode`], also telling if all the encoded words have been decoded.
+fn decode_words(value: &str) -> (String, bool) {
+    let value = value.replace("\r\n", "");
+    let mut decoded = String::new();
+    let mut space = String::new();
+    let mut previous_is_encoded = false;
+    let mut complete = true;
+
+    for word in value.trim().split_inclusive(char::is_whitespace) {
+        let trimmed = word.trim_end();
+        if !trimmed.is_empty() {
+            let text = decode_word(trimmed);
+            complete &= text.is_some() || !is_encoded_word(trimmed);
+            // the whitespace between two encoded words is not displayed.
+            if !(previous_is_encoded && text.is_some()) {
+                decoded.push_str(&space);
+            }
+            previous_is_encoded = text.is_some();
+            decoded.push_str(text.as_deref().unwrap_or(trimmed));
+            space.clear();
+        }
+        space.push_str(&word[trimmed.len()..]);
+    }
+
+    (decoded, complete)
+}
+
+fn is_encoded_word(word: &str) -> bool {
+    word.starts_with("=?") && word.ends_with("?=")
+}
+
+/// Decode a single `=?charset?encoding?text?=` word.
+fn decode_word(word: &str) -> Option<String> {
+    let inner = word.strip_prefix("=?")?.strip_suffix("?=")?;
+    let mut parts = inner.splitn(3, '?');
+    let (charset, encoding, text) = (parts.next()?, parts.next()?, parts.next()?);
+    // the language of RFC 2231, ex: `utf-8*fr`
+    let charset = charset
+        .split_once('*')
+        .map_or(charset, |(charset, _)| charset);
+
+    let bytes = match encoding {
+        "B" | "b" => {
+            base64::Engine::decode(&base64::engine::general_purpose::STANDARD, text).ok()?
+        }
+        "Q" | "q" => decode_q(text)?,
+        _ => return None,
+    };
+
+    crate::charset::decode(charset, &bytes)
+}
+
+/// Decode the "Q" encoding, a variant of quoted-printable where `_` is a space.
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut input = text.bytes();
+
+    while let Some(byte) = input.next() {
+        match byte {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                let hex = [input.next()?, input.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            otherwise => bytes.push(otherwise),
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Encode an unstructured header value if it contains non-ASCII characters,
+/// as a sequence of `utf-8` base64 encoded words folded on multiple lines.
+/// <https://www.rfc-editor.org/rfc/rfc2047>
+#[must_use]
+pub fn encode(value: &str) -> String {
+    if value.is_ascii() && !value.contains("=?") && !value.contains(['\r', '\n']) {
+        return value.to_string();
+    }
+
+    // 3 bytes are encoded in 4 characters, a character is never split between two words.
+    let max_bytes = MAX_ENCODED_TEXT / 4 * 3;
+    let mut chunks = vec![String::new()];
+    for c in value.chars() {
+        let chunk = chunks.last_mut().expect("never empty");
+        if chunk.len() + c.len_utf8() > max_bytes {
+            chunks.push(c.to_string());
+        } else {
+            chunk.push(c);
+        }
+    }
+
+    chunks
+        .into_iter()
+        .map(|chunk| {
+            format!(
+                "=?utf-8?B?{}?=",
+                base64::Engine::encode(&base64::engine::general_purpose::STANDARD, chunk)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+
+impl Mail {
+    /// Get the value of the `Subject` header, with its encoded words decoded.
+    #[must_use]
+    pub fn subject(&self) -> Option<String> {
+        self.get_header(SUBJECT_HEADER)
+            .map(|header| decode(&header.body))
+    }
+
+    /// Set the value of the `Subject` header, encoded if it is not plain ASCII.
+    pub fn set_subject(&mut self, subject: &str) {
+        let header = Header::new(SUBJECT_HEADER, encode(subject));
+
+        if let Some(existing) = self
+            .headers
+            .iter_mut()
+            .find(|header| header.name.eq_ignore_ascii_case(SUBJECT_HEADER))
+        {
+            existing.body = header.body;
+        } else {
+            self.headers.push(header);
+        }
+    }
+
+    /// Insert `prefix` at the start of the `Subject` header, or add it if it is missing.
+    /// Nothing is done if the subject already starts with the prefix.
+    ///
+    /// A subject with encoded words which cannot be decoded is prefixed as is,
+    /// instead of being decoded and encoded again.
+    pub fn prefix_subject(&mut self, prefix: &str) {
+        let Some(existing) = self
+            .headers
+            .iter_mut()
+            .find(|header| header.name.eq_ignore_ascii_case(SUBJECT_HEADER))
+        else {
+            self.set_subject(prefix);
+            return;
+        };
+
+        let (subject, complete) = decode_words(&existing.body);
+        if subject.starts_with(prefix) {
+            return;
+        }
+
+        if complete {
+            existing.body = format!(" {}\r\n", encode(&format!("{prefix}{subject}")));
+        } else {
+            let text = prefix.trim_end();
+            let encoded = encode(text);
+            // an encoded word must be separated from the next word.
+            let space = match &prefix[text.len()..] {
+                "" if encoded != text => " ",
+                space => space,
+            };
+            existing.body = format!(" {encoded}{space}{}", existing.body.trim_start());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Mail};
+
+    #[test]
+    fn decoding() {
+        for (value, expected) in [
+            (" Hello  world\r\n", "Hello  world"),
+            ("=?utf-8?B?Q2Fmw6k=?=", "Café"),
+            ("=?ISO-8859-1?Q?caf=E9_cr=E8me?=", "café crème"),
+            ("Re: =?utf-8?q?caf=C3=A9?= au lait", "Re: café au lait"),
+            ("=?utf-8?B?Q2Fm?=\r\n =?utf-8?B?w6k=?=", "Café"),
+            ("=?koi8-r?B?1MXT1A==?= ok", "тест ok"),
+            ("=?x-unknown?B?1MXT1A==?= ok", "=?x-unknown?B?1MXT1A==?= ok"),
+            ("=?utf-8?Q?caf=E?=", "=?utf-8?Q?caf=E?="),
+        ] {
+            assert_eq!(decode(value), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn ascii_is_not_encoded() {
+        assert_eq!(encode("[SPAM] Hello"), "[SPAM] Hello");
+        assert_eq!(
+            encode("=?not an encoded word?="),
+            "=?utf-8?B?PT9ub3QgYW4gZW5jb2RlZCB3b3JkPz0=?="
+        );
+    }
+
+    #[test]
+    fn long_value_is_folded() {
+        let subject = "Réunion de l'équipe — ordre du jour, comptes rendus et questions diverses";
+        let encoded = encode(subject);
+
+        assert!(encoded.is_ascii());
+        assert!(encoded.split("\r\n ").count() > 1);
+        assert!(encoded.split("\r\n ").all(|word| word.len() <= 75));
+        assert_eq!(decode(&encoded), subject);
+    }
+
+    #[test]
+    fn set_subject() {
+        let mut mail = Mail::try_from(concat!(
+            "From: john@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "Subject: =?utf-8?B?Q2Fmw6k=?=\r\n",
+            "\r\n",
+            "Hello\r\n",
+        ))
+        .unwrap();
+        assert_eq!(mail.subject().as_deref(), Some("Café"));
+
+        mail.set_subject(&format!("[SPAM] {}", mail.subject().unwrap()));
+        let mail = Mail::try_from(mail.to_string().as_str()).unwrap();
+        assert_eq!(mail.subject().as_deref(), Some("[SPAM] Café"));
+        assert!(mail.get_header("Subject").unwrap().body.is_ascii());
+
+        let mut mail = Mail::try_from("From: john@example.com\r\n").unwrap();
+        assert_eq!(mail.subject(), None);
+        mail.set_subject("Hello");
+        assert_eq!(
+            mail.to_string(),
+            "From: john@example.com\r\nSubject: Hello\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn prefix_subject() {
+        let mail = |subject: &str| {
+            Mail::try_from(
+                format!("From: john@example.com\r\nSubject: {subject}\r\n\r\nHello\r\n").as_str(),
+            )
+            .unwrap()
+        };
+
+        let mut decoded = mail("=?koi8-r?B?1MXT1A==?=");
+        decoded.prefix_subject("[SPAM] ");
+        decoded.prefix_subject("[SPAM] ");
+        assert_eq!(decoded.subject().as_deref(), Some("[SPAM] тест"));
+
+        // the raw value is kept when it cannot be decoded.
+        let mut unknown = mail("=?x-unknown?B?1MXT1A==?=");
+        unknown.prefix_subject("[SPAM] ");
+        unknown.prefix_subject("[SPAM] ");
+        assert_eq!(
+            unknown.get_header("Subject").unwrap().body,
+            " [SPAM] =?x-unknown?B?1MXT1A==?=\r\n"
+        );
+
+        unknown.prefix_subject("[Réf] ");
+        assert_eq!(
+            unknown.subject().as_deref(),
+            Some("[Réf] [SPAM] =?x-unknown?B?1MXT1A==?=")
+        );
+
+        let mut missing = Mail::try_from("From: john@example.com\r\n").unwrap();
+        missing.prefix_subject("[SPAM] ");
+        assert_eq!(missing.subject().as_deref(), Some("[SPAM]"));
+    }
+}
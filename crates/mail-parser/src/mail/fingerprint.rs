@@ -0,0 +1,105 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Mail;
+
+/// Headers identifying a message, trace headers (like `Received`) are excluded
+/// because they change each time the message is relayed.
+const FINGERPRINT_HEADERS: [&str; 6] = ["from", "to", "cc", "subject", "date", "message-id"];
+
+impl Mail {
+    /// Compute a stable fingerprint of the email, as an hexadecimal SHA-256 digest.
+    ///
+    /// The identifying headers (`From`, `To`, `Cc`, `Subject`, `Date` and `Message-ID`)
+    /// are unfolded and their whitespaces are collapsed, and the trailing whitespaces
+    /// of the lines and the trailing empty lines of the body are removed, so that
+    /// a message relayed again produces the same fingerprint.
+    #[must_use]
+    pub fn fingerprint(&self) -> String {
+        let mut digest = <sha2::Sha256 as sha2::Digest>::new();
+
+        for name in FINGERPRINT_HEADERS {
+            for header in self.get_headers(name) {
+                let value = header.body.split_whitespace().collect::<Vec<_>>().join(" ");
+                sha2::Digest::update(&mut digest, format!("{name}:{value}\n"));
+            }
+        }
+        sha2::Digest::update(&mut digest, "\n");
+
+        let body = self.body.to_string();
+        let lines = body.lines().map(str::trim_end).collect::<Vec<_>>();
+        let end = lines
+            .iter()
+            .rposition(|line| !line.is_empty())
+            .map_or(0, |last| last + 1);
+        for line in &lines[..end] {
+            sha2::Digest::update(&mut digest, format!("{line}\n"));
+        }
+
+        sha2::Digest::finalize(digest)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Mail;
+
+    #[test]
+    fn identical_messages() {
+        let first = Mail::try_from(concat!(
+            "Received: from mx1.example.com\r\n",
+            "From: john.doe@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "Subject: hello\r\n",
+            "Message-ID: <1@example.com>\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+        ))
+        .unwrap();
+        let second = Mail::try_from(concat!(
+            "Received: from mx2.example.com\r\n",
+            "Received: from mx1.example.com\r\n",
+            "From:  john.doe@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "Subject: hello\r\n",
+            "Message-ID: <1@example.com>\r\n",
+            "\r\n",
+            "Hello world!  \r\n",
+            "\r\n",
+        ))
+        .unwrap();
+
+        assert_eq!(first.fingerprint(), second.fingerprint());
+        assert_eq!(first.fingerprint().len(), 64);
+    }
+
+    #[test]
+    fn different_messages() {
+        let mail = |subject: &str, body: &str| {
+            Mail::try_from(
+                format!(
+                    "From: john.doe@example.com\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\nSubject: {subject}\r\n\r\n{body}\r\n"
+                )
+                .as_str(),
+            )
+            .unwrap()
+        };
+        let first = mail("hello", "Hello world!");
+        let second = mail("hello", "Hello you!");
+        let third = mail("hi", "Hello world!");
+
+        assert_ne!(first.fingerprint(), second.fingerprint());
+        assert_ne!(first.fingerprint(), third.fingerprint());
+    }
+}
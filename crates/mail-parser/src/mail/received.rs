@@ -0,0 +1,148 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Mail;
+
+const RECEIVED_HEADER: &str = "Received";
+
+/// Clauses of a `Received` trace header field, the comments are ignored.
+/// <https://www.rfc-editor.org/rfc/rfc5321#section-4.4>
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Received {
+    /// Name given by the client (`from` clause).
+    pub from: Option<String>,
+    /// Name of the server which received the message (`by` clause).
+    pub by: Option<String>,
+    /// Protocol used (`with` clause), e.g. `ESMTPS`.
+    pub with: Option<String>,
+    /// Identifier of the message on the server (`id` clause).
+    pub id: Option<String>,
+    /// Recipient of the message (`for` clause).
+    pub r#for: Option<String>,
+    /// Date of the reception, after the `;`.
+    pub date: Option<String>,
+}
+
+impl From<&str> for Received {
+    fn from(value: &str) -> Self {
+        let value = strip_comments(&value.replace("\r\n", ""));
+        let (clauses, date) = value
+            .rsplit_once(';')
+            .map_or((value.as_str(), None), |(clauses, date)| {
+                (clauses, Some(date.trim().to_string()))
+            });
+
+        let mut received = Self {
+            date: date.filter(|date| !date.is_empty()),
+            ..Self::default()
+        };
+
+        let mut words = clauses.split_whitespace();
+        while let Some(keyword) = words.next() {
+            let clause = match keyword.to_ascii_lowercase().as_str() {
+                "from" => &mut received.from,
+                "by" => &mut received.by,
+                "with" => &mut received.with,
+                "id" => &mut received.id,
+                "for" => &mut received.r#for,
+                _ => continue,
+            };
+            if clause.is_none() {
+                *clause = words.next().map(str::to_string);
+            }
+        }
+
+        received
+    }
+}
+
+/// Remove the (possibly nested) comments between parentheses.
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0_usize;
+    value
+        .chars()
+        .filter(|c| match c {
+            '(' => {
+                depth += 1;
+                false
+            }
+            ')' => {
+                depth = depth.saturating_sub(1);
+                false
+            }
+            _ => depth == 0,
+        })
+        .collect()
+}
+
+impl Mail {
+    /// Parse the `Received` header fields, the most recent first.
+    #[must_use]
+    pub fn received(&self) -> Vec<Received> {
+        self.get_headers(RECEIVED_HEADER)
+            .map(|header| Received::from(header.body.as_str()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mail, Received};
+
+    #[test]
+    fn clauses() {
+        let received = concat!(
+            " from client.example.com (client.example.com [192.0.2.1])\r\n",
+            "\tby mx.example.net (vSMTP) with ESMTPS id 1a2b3c\r\n",
+            "\tfor <jenny@example.net>; Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+        );
+        pretty_assertions::assert_eq!(
+            Received::from(received),
+            Received {
+                from: Some("client.example.com".to_string()),
+                by: Some("mx.example.net".to_string()),
+                with: Some("ESMTPS".to_string()),
+                id: Some("1a2b3c".to_string()),
+                r#for: Some("<jenny@example.net>".to_string()),
+                date: Some("Tue, 1 Jan 2030 00:00:00 +0000".to_string()),
+            }
+        );
+
+        pretty_assertions::assert_eq!(
+            Received::from(" by localhost (Postfix, from userid 0)"),
+            Received {
+                by: Some("localhost".to_string()),
+                ..Received::default()
+            }
+        );
+    }
+
+    #[test]
+    fn headers_in_order() {
+        let mail = Mail::try_from(concat!(
+            "Received: from mx1.example.com by mx2.example.com; Tue, 1 Jan 2030 00:00:01 +0000\r\n",
+            "Received: from client.example.com by mx1.example.com; Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "From: john@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "\r\n",
+            "Hello\r\n",
+        ))
+        .unwrap();
+
+        pretty_assertions::assert_eq!(
+            mail.received()
+                .into_iter()
+                .map(|received| received.by.unwrap())
+                .collect::<Vec<_>>(),
+            ["mx2.example.com", "mx1.example.com"]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Mail;
+use crate::ParserError;
+
+/// Schemes of the URLs extracted from the body.
+const URL_SCHEMES: [&str; 3] = ["http://", "https://", "ftp://"];
+
+impl Mail {
+    /// Extract the URLs of the text and html parts of the body, see [`Mail::body_text`].
+    ///
+    /// Both the bare URLs and the values of the `href` attributes are extracted,
+    /// without duplicates and in order of appearance.
+    ///
+    /// # Errors
+    ///
+    /// Failed to parse or decode the body.
+    pub fn urls(&mut self) -> Result<Vec<String>, ParserError> {
+        self.body_text().map(|text| extract_urls(&text))
+    }
+}
+
+/// Extract the `http`, `https` and `ftp` URLs of a text.
+fn extract_urls(text: &str) -> Vec<String> {
+    let text = text.replace("&amp;", "&");
+    // NOTE: the offsets are the same in both strings, as only ascii characters are lowered.
+    let lower = text.to_ascii_lowercase();
+    let mut urls = Vec::<String>::new();
+    let mut position = 0;
+
+    while position < text.len() {
+        let rest = &lower[position..];
+        let found = if rest.starts_with("href") {
+            href_value(&text[position + "href".len()..]).map(|(offset, value)| {
+                (
+                    position + "href".len() + offset,
+                    Some(value).filter(|value| has_url_scheme(value)),
+                )
+            })
+        } else if has_url_scheme(rest)
+            && !text[..position]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric)
+        {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "\"'<>()[]{}`".contains(c))
+                .unwrap_or(rest.len());
+            let url =
+                text[position..position + end].trim_end_matches(['.', ',', ';', ':', '!', '?']);
+
+            Some((position + end, Some(url).filter(|url| has_url_scheme(url))))
+        } else {
+            None
+        };
+
+        match found {
+            Some((end, url)) => {
+                if let Some(url) = url.filter(|url| !urls.iter().any(|known| known == url)) {
+                    urls.push(url.to_string());
+                }
+                position = end;
+            }
+            None => {
+                position += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    }
+
+    urls
+}
+
+/// Parse the value of an attribute following its name, quoted or not.
+/// Return the offset of the end of the value and the value.
+fn href_value(text: &str) -> Option<(usize, &str)> {
+    let value = text.trim_start().strip_prefix('=')?.trim_start();
+    let start = text.len() - value.len();
+
+    let (value, end) = match value.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let value = &value[1..];
+            let length = value.find(quote)?;
+            (&value[..length], start + length + 2)
+        }
+        _ => {
+            let length = value
+                .find(|c: char| c.is_whitespace() || c == '>')
+                .unwrap_or(value.len());
+            (&value[..length], start + length)
+        }
+    };
+
+    Some((end, value.trim()))
+}
+
+fn has_url_scheme(value: &str) -> bool {
+    URL_SCHEMES.iter().any(|scheme| {
+        value
+            .get(..scheme.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+            && value.len() > scheme.len()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Mail;
+
+    #[test]
+    fn html_anchors() {
+        let mut mail = Mail::try_from(concat!(
+            "From: john.doe@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: text/html; charset=utf-8\r\n",
+            "\r\n",
+            "<p>Please <a href=\"https://login.example.com/verify?id=1&amp;t=2\">verify</a>\r\n",
+            "your account, or <A HREF='http://example.org/help'>ask for help</A>.</p>\r\n",
+            "<a href=\"mailto:support@example.com\">contact</a> <a href=#top>top</a>\r\n",
+            "<a href=https://login.example.com/verify?id=1&t=2>again</a>\r\n",
+            "<p>Visit https://example.org/help too</p>\r\n",
+        ))
+        .unwrap();
+
+        pretty_assertions::assert_eq!(
+            mail.urls().unwrap(),
+            vec![
+                "https://login.example.com/verify?id=1&t=2",
+                "http://example.org/help",
+                "https://example.org/help",
+            ]
+        );
+    }
+
+    #[test]
+    fn bare_links() {
+        let mut mail = Mail::try_from(concat!(
+            "From: john.doe@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "\r\n",
+            "Your parcel is waiting: https://track.example.com/parcel/42.\r\n",
+            "Track it at https://track.example.com/parcel/42, or (ftp://files.example.com/label.pdf)\r\n",
+            "Not a link: xhttps://example.com nor https:// alone.\r\n",
+        ))
+        .unwrap();
+
+        pretty_assertions::assert_eq!(
+            mail.urls().unwrap(),
+            vec![
+                "https://track.example.com/parcel/42",
+                "ftp://files.example.com/label.pdf",
+            ]
+        );
+    }
+}
@@ -9,6 +9,7 @@
  *
  */
 
+use crate::charset;
 use crate::mime::parts::MultipartDisplayable;
 use crate::ParserError;
 use crate::ParserResult;
@@ -17,6 +18,10 @@ use crate::ParserResult;
 pub mod headers;
 pub use headers::Header;
 
+/// Content-Disposition parameters.
+pub mod disposition;
+pub use disposition::ContentDisposition;
+
 /// Mime parts definition.
 pub mod parts;
 pub use parts::Multipart;
@@ -25,6 +30,7 @@ pub use parts::Part;
 pub const CONTENT_TYPE_HEADER: &str = "Content-Type";
 pub const CONTENT_DISPOSITION_HEADER: &str = "Content-Disposition";
 pub const MIME_VERSION_HEADER: &str = "MIME-Version";
+pub const CONTENT_TRANSFER_ENCODING_HEADER: &str = "Content-Transfer-Encoding";
 
 /// <https://www.rfc-editor.org/rfc/rfc2045>
 #[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
@@ -83,6 +89,507 @@ impl Mime {
     }
 }
 
+impl Mime {
+    fn header(&self, name: &str) -> Option<&Header> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Get the mime type of the current part, in lower case (e.g. `text/plain`).
+    ///
+    /// Without a Content-Type header field, embedded emails are `message/rfc822`
+    /// and any other part is `text/plain`.
+    #[must_use]
+    pub fn content_type(&self) -> String {
+        match (self.header(CONTENT_TYPE_HEADER), &self.part) {
+            (Some(content_type), _) => content_type.body().to_ascii_lowercase(),
+            (None, Part::Embedded(_)) => "message/rfc822".to_string(),
+            (None, _) => "text/plain".to_string(),
+        }
+    }
+
+    /// Get the disposition of the current part (e.g. `inline` or `attachment`),
+    /// if a Content-Disposition header field is present.
+    #[must_use]
+    pub fn disposition(&self) -> Option<&str> {
+        self.header(CONTENT_DISPOSITION_HEADER).map(Header::body)
+    }
+
+    /// Get the parameters of the Content-Disposition header field, if present.
+    #[must_use]
+    pub fn content_disposition(&self) -> Option<ContentDisposition> {
+        self.header(CONTENT_DISPOSITION_HEADER)
+            .map(ContentDisposition::from)
+    }
+
+    /// Get the file name of the current part, from the `filename` parameter of
+    /// the Content-Disposition header field or the `name` parameter of the Content-Type.
+    /// The RFC 2231 continuations and encodings are decoded.
+    #[must_use]
+    pub fn filename(&self) -> Option<String> {
+        self.content_disposition()
+            .and_then(|disposition| disposition.filename)
+            .or_else(|| {
+                self.header(CONTENT_TYPE_HEADER)
+                    .and_then(|header| header.param("name"))
+            })
+    }
+
+    /// Get the content of the current part, decoded following its Content-Transfer-Encoding.
+    /// Multipart sections and embedded emails are returned as is.
+    ///
+    /// # Errors
+    ///
+    /// * the content is not valid base64.
+    pub fn decoded_body(&self) -> ParserResult<Vec<u8>> {
+        let content = match &self.part {
+            Part::Text(content) | Part::Html(content) | Part::Binary(content) => content.join(""),
+            Part::Multipart(_) | Part::Embedded(_) => return Ok(self.raw_part().into_bytes()),
+        };
+
+        match self.transfer_encoding().as_deref() {
+            Some("base64") => {
+                let content = content
+                    .bytes()
+                    .filter(|c| !c.is_ascii_whitespace())
+                    .collect::<Vec<_>>();
+
+                base64::Engine::decode(&base64::engine::general_purpose::STANDARD, content)
+                    .map_err(|e| ParserError::InvalidMail(format!("Invalid base64 content: {e}")))
+            }
+            Some("quoted-printable") => Ok(decode_quoted_printable(content.as_bytes())),
+            _ => Ok(content.into_bytes()),
+        }
+    }
+
+    /// Replace the content of the current part, encoding it following its
+    /// Content-Transfer-Encoding. Unless the content is encoded in base64, its
+    /// line breaks are converted to CRLF and a line break terminates it.
+    ///
+    /// # Errors
+    ///
+    /// * the part is a multipart section or an embedded email.
+    pub fn set_body(&mut self, body: &[u8]) -> ParserResult<()> {
+        let encoded = match self.transfer_encoding().as_deref() {
+            Some("base64") => encode_base64(body),
+            Some("quoted-printable") => encode_quoted_printable(body),
+            _ => lines(body)
+                .into_iter()
+                .map(|line| format!("{}\r\n", String::from_utf8_lossy(line)))
+                .collect(),
+        };
+
+        match self.part {
+            Part::Text(ref mut content)
+            | Part::Html(ref mut content)
+            | Part::Binary(ref mut content) => {
+                *content = encoded;
+                Ok(())
+            }
+            Part::Multipart(_) | Part::Embedded(_) => Err(ParserError::InvalidMail(format!(
+                "cannot replace the body of a {} part",
+                self.content_type()
+            ))),
+        }
+    }
+
+    /// Get a mutable reference on the `index`-th part that is not a multipart section,
+    /// counting depth first. A part that is not a multipart section is its own first part.
+    #[must_use]
+    pub fn leaf_mut(&mut self, mut index: usize) -> Option<&mut Self> {
+        self.find_leaf_mut(&mut index)
+    }
+
+    fn find_leaf_mut(&mut self, index: &mut usize) -> Option<&mut Self> {
+        if !matches!(self.part, Part::Multipart(_)) {
+            if *index == 0 {
+                return Some(self);
+            }
+            *index -= 1;
+            return None;
+        }
+
+        match &mut self.part {
+            Part::Multipart(multipart) => multipart
+                .parts
+                .iter_mut()
+                .find_map(|part| part.find_leaf_mut(index)),
+            _ => None,
+        }
+    }
+
+    /// Get the number of parts that are not multipart sections.
+    #[must_use]
+    pub fn leaf_count(&self) -> usize {
+        match &self.part {
+            Part::Multipart(multipart) => multipart.parts.iter().map(Self::leaf_count).sum(),
+            _ => 1,
+        }
+    }
+
+    /// Remove the `index`-th part that is not a multipart section, counting depth first.
+    /// The multipart sections left empty by the removal are removed too.
+    ///
+    /// # Errors
+    ///
+    /// * the index is out of bounds.
+    /// * the part is the last one of the message.
+    pub fn remove_leaf(&mut self, index: usize) -> ParserResult<Self> {
+        let count = self.leaf_count();
+        if index >= count {
+            return Err(ParserError::InvalidMail(format!(
+                "mime part {index} does not exist, the message has {count} parts"
+            )));
+        }
+        if count == 1 {
+            return Err(ParserError::InvalidMail(
+                "cannot remove the last mime part of the message".to_string(),
+            ));
+        }
+
+        let mut remaining = index;
+        match &mut self.part {
+            Part::Multipart(multipart) => remove_leaf_from(multipart, &mut remaining),
+            _ => None,
+        }
+        .ok_or_else(|| ParserError::InvalidMail(format!("mime part {index} does not exist")))
+    }
+
+    /// Append a footer to the text and html parts that are not attachments.
+    /// The html footer is inserted before the closing `</body>` tag, if any.
+    ///
+    /// An empty footer is derived from the other one, so that all the versions
+    /// of a `multipart/alternative` section get the footer.
+    ///
+    /// The footer is encoded in the charset of the part, the part being converted
+    /// to UTF-8 if its charset cannot represent the footer.
+    ///
+    /// # Errors
+    ///
+    /// * the content of a part cannot be decoded.
+    /// * the charset of a part is unknown.
+    pub fn append_footer(&mut self, text_plain: &str, text_html: &str) -> ParserResult<()> {
+        let (text_plain, text_html) = match (text_plain.is_empty(), text_html.is_empty()) {
+            (true, true) => return Ok(()),
+            (false, true) => (text_plain.to_string(), text_to_html(text_plain)),
+            (true, false) => (html_to_text(text_html), text_html.to_string()),
+            (false, false) => (text_plain.to_string(), text_html.to_string()),
+        };
+
+        for index in 0..self.leaf_count() {
+            let Some(part) = self.leaf_mut(index) else {
+                continue;
+            };
+
+            match part.part {
+                _ if part.is_attachment() => {}
+                Part::Text(_) => part.insert_footer(&text_plain, false)?,
+                Part::Html(_) => part.insert_footer(&text_html, true)?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Append `footer` to the body of the part, or insert it before its closing `</body>`
+    /// tag if `html`.
+    fn insert_footer(&mut self, footer: &str, html: bool) -> ParserResult<()> {
+        let charset = self
+            .header(CONTENT_TYPE_HEADER)
+            .and_then(|header| header.arg("charset"))
+            .map(|charset| charset.value().to_string());
+        // without a charset, or in US-ASCII, only an ASCII footer can be appended as is.
+        // https://datatracker.ietf.org/doc/html/rfc2045#section-5.2
+        let encoding =
+            match charset.as_deref() {
+                None => None,
+                Some(charset) if charset.eq_ignore_ascii_case("us-ascii") => None,
+                Some(charset) => Some(charset::encoding(charset).ok_or_else(|| {
+                    ParserError::InvalidMail(format!("unknown charset '{charset}'"))
+                })?),
+            };
+
+        let encoded_footer = match encoding {
+            _ if footer.is_ascii()
+                && encoding.map_or(true, encoding_rs::Encoding::is_ascii_compatible) =>
+            {
+                Some(footer.as_bytes().to_vec())
+            }
+            None => None,
+            Some(encoding) => {
+                let (encoded, output_encoding, unmappable) = encoding.encode(footer);
+                (output_encoding == encoding && !unmappable).then(|| encoded.into_owned())
+            }
+        };
+
+        let mut body = self.decoded_body()?;
+        let footer = match encoded_footer {
+            Some(footer) => footer,
+            None => {
+                let encoding = encoding.unwrap_or(encoding_rs::UTF_8);
+                let (decoded, malformed) = encoding.decode_without_bom_handling(&body);
+                if malformed {
+                    return Err(ParserError::InvalidMail(format!(
+                        "the content of the part is not valid {}",
+                        encoding.name()
+                    )));
+                }
+                body = decoded.into_owned().into_bytes();
+                self.set_content_type_arg("charset", "utf-8");
+                footer.as_bytes().to_vec()
+            }
+        };
+
+        let end_of_html = body
+            .windows(b"</body".len())
+            .rposition(|window| window.eq_ignore_ascii_case(b"</body"));
+        match end_of_html {
+            Some(end) if html => {
+                let tail = body.split_off(end);
+                body.extend(footer);
+                body.extend(tail);
+            }
+            _ => {
+                if !body.is_empty() && !body.ends_with(b"\n") {
+                    body.extend_from_slice(b"\r\n");
+                }
+                body.extend(footer);
+            }
+        }
+
+        // the content of the parts not encoded in base64 or quoted-printable is stored
+        // as UTF-8, and 8bit content cannot be sent without the 8bit encoding.
+        let quoted_printable = match self.transfer_encoding().as_deref() {
+            Some("base64" | "quoted-printable") => false,
+            Some("8bit" | "binary") => std::str::from_utf8(&body).is_err(),
+            _ => !body.is_ascii(),
+        };
+        if quoted_printable {
+            self.set_header(CONTENT_TRANSFER_ENCODING_HEADER, "quoted-printable");
+        }
+
+        self.set_body(&body)
+    }
+
+    /// Set the parameter `name` of the Content-Type header, adding the header if missing.
+    fn set_content_type_arg(&mut self, name: &str, value: &str) {
+        if self.header(CONTENT_TYPE_HEADER).is_none() {
+            let content_type = self.content_type();
+            self.set_header(CONTENT_TYPE_HEADER, &content_type);
+        }
+
+        if let Some(header) = self
+            .headers
+            .iter_mut()
+            .find(|header| header.name.eq_ignore_ascii_case(CONTENT_TYPE_HEADER))
+        {
+            header.set_arg(name, value);
+        }
+    }
+
+    /// Replace the body of the header `name`, its parameters included, adding it if missing.
+    fn set_header(&mut self, name: &str, body: &str) {
+        let header = Header::new_unchecked(name, format!(" {body}\r\n"), vec![]);
+        match self
+            .headers
+            .iter_mut()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+        {
+            Some(previous) => *previous = header,
+            None => self.headers.push(header),
+        }
+    }
+
+    /// Get the text of the text and html parts that are not attachments, decoded
+    /// following their Content-Transfer-Encoding and converted from their charset
+    /// to UTF-8. The text of the parts are concatenated, separated by a line break.
+    ///
+    /// # Errors
+    ///
+    /// * the content of a part cannot be decoded.
+    pub fn body_text(&self) -> ParserResult<String> {
+        let mut text = String::new();
+        self.push_text(&mut text)?;
+        Ok(text)
+    }
+
+    fn push_text(&self, text: &mut String) -> ParserResult<()> {
+        match &self.part {
+            Part::Multipart(multipart) => {
+                for part in &multipart.parts {
+                    part.push_text(text)?;
+                }
+            }
+            Part::Text(_) | Part::Html(_) if !self.is_attachment() => {
+                let charset = self
+                    .header(CONTENT_TYPE_HEADER)
+                    .and_then(|header| header.arg("charset"))
+                    .map(headers::Arg::value);
+
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push_str("\r\n");
+                }
+                text.push_str(&charset::decode_lossy(charset, &self.decoded_body()?));
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn transfer_encoding(&self) -> Option<String> {
+        self.header(CONTENT_TRANSFER_ENCODING_HEADER)
+            .map(|header| header.body().to_ascii_lowercase())
+    }
+}
+
+fn remove_leaf_from(multipart: &mut Multipart, index: &mut usize) -> Option<Mime> {
+    for i in 0..multipart.parts.len() {
+        match multipart.parts[i].part {
+            Part::Multipart(ref mut inner) => {
+                if let Some(removed) = remove_leaf_from(inner, index) {
+                    if inner.parts.is_empty() {
+                        multipart.parts.remove(i);
+                    }
+                    return Some(removed);
+                }
+            }
+            _ if *index == 0 => return Some(multipart.parts.remove(i)),
+            _ => *index -= 1,
+        }
+    }
+
+    None
+}
+
+/// Split a content on its line breaks (LF or CRLF), a trailing line break does not
+/// produce an empty line.
+pub(crate) fn lines(content: &[u8]) -> Vec<&[u8]> {
+    if content.is_empty() {
+        return vec![];
+    }
+
+    content
+        .strip_suffix(b"\n")
+        .unwrap_or(content)
+        .split(|c| *c == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .collect()
+}
+
+/// Encode a content in base64, in lines of 76 characters.
+///
+/// see <https://datatracker.ietf.org/doc/html/rfc2045#section-6.8>
+fn encode_base64(content: &[u8]) -> Vec<String> {
+    base64::Engine::encode(&base64::engine::general_purpose::STANDARD, content)
+        .as_bytes()
+        .chunks(76)
+        .map(|line| format!("{}\r\n", String::from_utf8_lossy(line)))
+        .collect()
+}
+
+/// Encode a content in quoted-printable, keeping its line breaks and adding
+/// soft line breaks to keep the lines under 76 characters.
+///
+/// see <https://datatracker.ietf.org/doc/html/rfc2045#section-6.7>
+fn encode_quoted_printable(content: &[u8]) -> Vec<String> {
+    let mut encoded = vec![];
+
+    for line in lines(content) {
+        let mut current = String::new();
+
+        for (i, c) in line.iter().enumerate() {
+            let token = match c {
+                b' ' | b'\t' if i + 1 != line.len() => char::from(*c).to_string(),
+                33..=60 | 62..=126 => char::from(*c).to_string(),
+                _ => format!("={c:02X}"),
+            };
+
+            if current.len() + token.len() > 75 {
+                current.push_str("=\r\n");
+                encoded.push(std::mem::take(&mut current));
+            }
+            current.push_str(&token);
+        }
+
+        current.push_str("\r\n");
+        encoded.push(current);
+    }
+
+    encoded
+}
+
+/// Decode a quoted-printable content, removing the soft line breaks.
+/// Invalid escape sequences are kept as is.
+///
+/// see <https://datatracker.ietf.org/doc/html/rfc2045#section-6.7>
+fn decode_quoted_printable(content: &[u8]) -> Vec<u8> {
+    fn hex(c: u8) -> Option<u8> {
+        char::from(c)
+            .to_digit(16)
+            .and_then(|d| u8::try_from(d).ok())
+    }
+
+    let mut decoded = Vec::with_capacity(content.len());
+    let mut rest = content;
+
+    while let [c, tail @ ..] = rest {
+        rest = match (c, tail) {
+            (b'=', [b'\r', b'\n', tail @ ..] | [b'\n', tail @ ..]) => tail,
+            (b'=', [high, low, tail @ ..]) if hex(*high).is_some() && hex(*low).is_some() => {
+                decoded.push(hex(*high).unwrap_or_default() << 4 | hex(*low).unwrap_or_default());
+                tail
+            }
+            _ => {
+                decoded.push(*c);
+                tail
+            }
+        };
+    }
+
+    decoded
+}
+
+/// The html version of a text footer, for the html parts.
+fn text_to_html(text: &str) -> String {
+    let escaped = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!(
+        "<p>{}</p>",
+        escaped.trim_end().lines().collect::<Vec<_>>().join("<br>")
+    )
+}
+
+/// The text version of an html footer, for the text parts: the tags are removed,
+/// the paragraphs and line breaks being kept as line breaks.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_ascii_lowercase();
+        if tag.starts_with("br") || tag == "/p" || tag == "/div" {
+            text.push_str("\r\n");
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
 /// Cut the mime type of the current section and return the type and subtype.
 /// if no Content-Type header is found, will check the parent for a default
 /// Content-Type header value.
@@ -302,4 +809,273 @@ mod tests {
         );
         pretty_assertions::assert_eq!(input.body(), "application/foobar".to_string());
     }
+
+    #[test]
+    fn decoded_body() {
+        let part = |encoding: &str, content: &[&str]| Mime {
+            headers: vec![
+                Header::new_unchecked(
+                    CONTENT_TYPE_HEADER.to_string(),
+                    " application/octet-stream".to_string(),
+                    vec![Arg::from_str(" name=\"data.bin\"").unwrap()],
+                ),
+                Header::new_unchecked(
+                    CONTENT_TRANSFER_ENCODING_HEADER.to_string(),
+                    format!(" {encoding}"),
+                    Vec::default(),
+                ),
+            ],
+            part: Part::Binary(content.iter().map(ToString::to_string).collect()),
+        };
+
+        let base64 = part("base64", &["aGVsbG8g\r\n", "d29ybGQ=\r\n"]);
+        pretty_assertions::assert_eq!(base64.decoded_body().unwrap(), b"hello world");
+        pretty_assertions::assert_eq!(base64.content_type(), "application/octet-stream");
+        pretty_assertions::assert_eq!(base64.filename().as_deref(), Some("data.bin"));
+        pretty_assertions::assert_eq!(base64.disposition(), None);
+
+        let quoted_printable = part("Quoted-Printable", &["caf=C3=A9 au =\r\n", "lait=\r\n"]);
+        pretty_assertions::assert_eq!(
+            quoted_printable.decoded_body().unwrap(),
+            "café au lait".as_bytes()
+        );
+
+        assert!(part("base64", &["not base64 !\r\n"])
+            .decoded_body()
+            .is_err());
+    }
+
+    #[test]
+    fn body_text_encoded() {
+        let part = |content_type: &str, encoding: &str, content: &[&str]| Mime {
+            headers: vec![
+                Header::new_unchecked(
+                    CONTENT_TYPE_HEADER.to_string(),
+                    format!(" {content_type}"),
+                    vec![],
+                ),
+                Header::new_unchecked(
+                    CONTENT_TRANSFER_ENCODING_HEADER.to_string(),
+                    format!(" {encoding}"),
+                    Vec::default(),
+                ),
+            ],
+            part: Part::Text(content.iter().map(ToString::to_string).collect()),
+        };
+
+        let base64 = part(
+            "text/plain",
+            "base64",
+            &["Y2xpY2sgaGVyZSB0byB2ZXJp\r\n", "Znk=\r\n"],
+        );
+        pretty_assertions::assert_eq!(base64.body_text().unwrap(), "click here to verify");
+
+        let quoted_printable = part(
+            "text/plain",
+            "quoted-printable",
+            &["caf=C3=A9 au =\r\n", "lait\r\n"],
+        );
+        pretty_assertions::assert_eq!(quoted_printable.body_text().unwrap(), "café au lait\r\n");
+
+        let mut latin1 = part("text/plain", "quoted-printable", &["caf=E9\r\n"]);
+        latin1.headers[0] = Header::new_unchecked(
+            CONTENT_TYPE_HEADER.to_string(),
+            " text/plain".to_string(),
+            vec![Arg::from_str(" charset=\"ISO-8859-1\"").unwrap()],
+        );
+        pretty_assertions::assert_eq!(latin1.body_text().unwrap(), "café\r\n");
+    }
+
+    #[test]
+    fn body_text_alternative() {
+        let mut mail = crate::Mail::try_from(
+            [
+                "From: john <john@example.com>\r\n",
+                "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Content-Type: multipart/alternative; boundary=\"alt\"\r\n",
+                "\r\n",
+                "--alt\r\n",
+                "Content-Type: text/plain; charset=utf-8\r\n",
+                "Content-Transfer-Encoding: quoted-printable\r\n",
+                "\r\n",
+                "Verify your acc=\r\n",
+                "ount\r\n",
+                "--alt\r\n",
+                "Content-Type: text/html; charset=utf-8\r\n",
+                "Content-Transfer-Encoding: base64\r\n",
+                "\r\n",
+                "PHA+VmVyaWZ5IHlvdXIgYWNjb3VudDwvcD4=\r\n",
+                "--alt--\r\n",
+            ]
+            .concat()
+            .as_str(),
+        )
+        .unwrap();
+
+        pretty_assertions::assert_eq!(
+            mail.body_text().unwrap(),
+            "Verify your account\r\n<p>Verify your account</p>"
+        );
+    }
+
+    #[test]
+    fn set_body() {
+        let mut part = Mime {
+            headers: vec![Header::new_unchecked(
+                CONTENT_TRANSFER_ENCODING_HEADER.to_string(),
+                " quoted-printable".to_string(),
+                Vec::default(),
+            )],
+            part: Part::Text(vec![]),
+        };
+
+        let long_line = "caf\u{e9} ".repeat(20);
+        part.set_body(format!("{long_line}\nsecond line\t\n").as_bytes())
+            .unwrap();
+
+        let Part::Text(content) = &part.part else {
+            panic!("the part should stay a text part")
+        };
+        assert!(content.iter().all(|line| line.len() <= 78));
+        pretty_assertions::assert_eq!(content.last().unwrap(), "second line=09\r\n");
+        pretty_assertions::assert_eq!(
+            part.decoded_body().unwrap(),
+            format!("{long_line}\r\nsecond line\t\r\n").as_bytes()
+        );
+
+        part.headers[0] = Header::new_unchecked(
+            CONTENT_TRANSFER_ENCODING_HEADER.to_string(),
+            " base64".to_string(),
+            Vec::default(),
+        );
+        part.set_body(&[0, 159, 146, 150]).unwrap();
+        pretty_assertions::assert_eq!(part.decoded_body().unwrap(), [0, 159, 146, 150]);
+    }
+
+    #[test]
+    fn append_footer_charset() {
+        let part = |charset: &str, encoding: &str, content: &[&str]| Mime {
+            headers: vec![
+                Header::new_unchecked(
+                    CONTENT_TYPE_HEADER.to_string(),
+                    " text/plain".to_string(),
+                    vec![Arg::from_str(&format!(" charset={charset}")).unwrap()],
+                ),
+                Header::new_unchecked(
+                    CONTENT_TRANSFER_ENCODING_HEADER.to_string(),
+                    format!(" {encoding}"),
+                    Vec::default(),
+                ),
+            ],
+            part: Part::Text(content.iter().map(ToString::to_string).collect()),
+        };
+
+        // the footer is encoded in the charset of the part.
+        let mut latin1 = part("\"ISO-8859-1\"", "quoted-printable", &["caf=E9\r\n"]);
+        latin1.append_footer("Envoy\u{e9}", "").unwrap();
+        pretty_assertions::assert_eq!(latin1.decoded_body().unwrap(), b"caf\xE9\r\nEnvoy\xE9\r\n");
+        pretty_assertions::assert_eq!(latin1.body_text().unwrap(), "caf\u{e9}\r\nEnvoy\u{e9}\r\n");
+
+        // the part is converted to UTF-8 if its charset cannot represent the footer.
+        let mut koi8 = part("koi8-r", "base64", &["0NLJ18XUDQo=\r\n"]);
+        koi8.append_footer("caf\u{e9}", "").unwrap();
+        pretty_assertions::assert_eq!(
+            koi8.header(CONTENT_TYPE_HEADER)
+                .unwrap()
+                .param("charset")
+                .unwrap(),
+            "utf-8"
+        );
+        pretty_assertions::assert_eq!(
+            koi8.decoded_body().unwrap(),
+            "\u{43f}\u{440}\u{438}\u{432}\u{435}\u{442}\r\ncaf\u{e9}\r\n".as_bytes()
+        );
+
+        let mut ascii = part("us-ascii", "7bit", &["hello\r\n"]);
+        ascii.append_footer("caf\u{e9}", "").unwrap();
+        pretty_assertions::assert_eq!(ascii.transfer_encoding().unwrap(), "quoted-printable");
+        pretty_assertions::assert_eq!(ascii.body_text().unwrap(), "hello\r\ncaf\u{e9}\r\n");
+
+        let mut unknown = part("x-unknown", "7bit", &["hello\r\n"]);
+        assert!(unknown.append_footer("footer", "").is_err());
+    }
+
+    #[test]
+    fn append_footer_alternative() {
+        let mut mail = crate::Mail::try_from(
+            [
+                "From: john <john@example.com>\r\n",
+                "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Content-Type: multipart/alternative; boundary=\"alt\"\r\n",
+                "\r\n",
+                "--alt\r\n",
+                "Content-Type: text/plain; charset=utf-8\r\n",
+                "Content-Transfer-Encoding: quoted-printable\r\n",
+                "\r\n",
+                "Verify your acc=\r\n",
+                "ount\r\n",
+                "--alt\r\n",
+                "Content-Type: text/html; charset=utf-8\r\n",
+                "Content-Transfer-Encoding: base64\r\n",
+                "\r\n",
+                "PHA+VmVyaWZ5IHlvdXIgYWNjb3VudDwvcD4=\r\n",
+                "--alt--\r\n",
+            ]
+            .concat()
+            .as_str(),
+        )
+        .unwrap();
+
+        // the html footer also reaches the text version.
+        mail.append_footer("", "<p>Sent by <b>vSMTP</b> &amp; co</p>")
+            .unwrap();
+
+        pretty_assertions::assert_eq!(
+            mail.body_text().unwrap(),
+            [
+                "Verify your account\r\nSent by vSMTP & co\r\n",
+                "<p>Verify your account</p>\r\n<p>Sent by <b>vSMTP</b> &amp; co</p>",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn append_footer_top_level() {
+        let mut mail = crate::Mail::try_from(
+            [
+                "From: john <john@example.com>\r\n",
+                "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Content-Type: text/plain\r\n",
+                "Subject: hello\r\n",
+                "\r\n",
+                "hello\r\n",
+            ]
+            .concat()
+            .as_str(),
+        )
+        .unwrap();
+
+        mail.append_footer("caf\u{e9}", "").unwrap();
+
+        // the top-level mime headers are updated in place.
+        pretty_assertions::assert_eq!(
+            mail.to_string(),
+            [
+                "From: john <john@example.com>\r\n",
+                "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+                "MIME-Version: 1.0\r\n",
+                "Content-Type: text/plain; charset=utf-8\r\n",
+                "Subject: hello\r\n",
+                "Content-Transfer-Encoding: quoted-printable\r\n",
+                "\r\n",
+                "hello\r\n",
+                "caf=C3=A9\r\n",
+            ]
+            .concat()
+        );
+    }
 }
@@ -0,0 +1,112 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Header;
+
+/// Parameters of a Content-Disposition header field.
+/// <https://www.rfc-editor.org/rfc/rfc2183>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDisposition {
+    /// Disposition type, in lower case (e.g. `inline` or `attachment`).
+    pub kind: String,
+    /// Suggested name of the file, decoded.
+    pub filename: Option<String>,
+    /// Date of creation of the file, as written in the header (RFC 5322 date-time).
+    pub creation_date: Option<String>,
+    /// Date of the last modification of the file, as written in the header.
+    pub modification_date: Option<String>,
+    /// Date of the last read of the file, as written in the header.
+    pub read_date: Option<String>,
+    /// Approximate size of the file in bytes, `None` if absent or invalid.
+    pub size: Option<u64>,
+}
+
+impl From<&Header> for ContentDisposition {
+    fn from(header: &Header) -> Self {
+        Self {
+            kind: header.body().to_ascii_lowercase(),
+            filename: header.param("filename"),
+            creation_date: header.param("creation-date"),
+            modification_date: header.param("modification-date"),
+            read_date: header.param("read-date"),
+            size: header
+                .param("size")
+                .and_then(|size| size.trim().parse().ok()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentDisposition;
+    use crate::parsing::bytes::get_mime_header;
+
+    fn parse(value: &str) -> ContentDisposition {
+        ContentDisposition::from(&get_mime_header("Content-Disposition", value))
+    }
+
+    #[test]
+    fn parameters() {
+        pretty_assertions::assert_eq!(
+            parse(concat!(
+                " Attachment; filename=\"customers.txt\";\r\n",
+                " creation-date=\"Sat, 05 Aug 2017 19:35:36 GMT\"; size=1024\r\n"
+            )),
+            ContentDisposition {
+                kind: "attachment".to_string(),
+                filename: Some("customers.txt".to_string()),
+                creation_date: Some("Sat, 05 Aug 2017 19:35:36 GMT".to_string()),
+                modification_date: None,
+                read_date: None,
+                size: Some(1024),
+            }
+        );
+        pretty_assertions::assert_eq!(parse(" inline\r\n").filename, None);
+    }
+
+    #[test]
+    fn continuations() {
+        let disposition = parse(concat!(
+            " attachment;\r\n",
+            " filename*1=\" of the meeting.pdf\";\r\n",
+            " filename*0=\"minutes\"\r\n"
+        ));
+        pretty_assertions::assert_eq!(
+            disposition.filename.as_deref(),
+            Some("minutes of the meeting.pdf")
+        );
+
+        // the sections after a missing one are ignored.
+        let disposition = parse(" attachment; filename*0=\"a\"; filename*2=\"c\"\r\n");
+        pretty_assertions::assert_eq!(disposition.filename.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn extended_values() {
+        let disposition = parse(" attachment; filename*=utf-8''caf%C3%A9%20cr%C3%A8me.txt\r\n");
+        pretty_assertions::assert_eq!(disposition.filename.as_deref(), Some("café crème.txt"));
+
+        let disposition = parse(concat!(
+            " attachment;\r\n",
+            " filename*0*=iso-8859-1'fr'r%E9union;\r\n",
+            " filename*1=\" d'equipe\";\r\n",
+            " filename*2*=%20%E9t%E9.odt\r\n",
+        ));
+        pretty_assertions::assert_eq!(
+            disposition.filename.as_deref(),
+            Some("réunion d'equipe été.odt")
+        );
+
+        // RFC 2047 encoded words are used by some mail user agents instead.
+        let disposition = parse(" attachment; filename=\"=?utf-8?B?Q2Fmw6kudHh0?=\"\r\n");
+        pretty_assertions::assert_eq!(disposition.filename.as_deref(), Some("Café.txt"));
+    }
+}
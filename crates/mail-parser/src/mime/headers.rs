@@ -63,6 +63,146 @@ impl Header {
             .iter()
             .find(|arg| arg.name().eq_ignore_ascii_case(needle))
     }
+
+    /// Set the parameter `name` to `value`, replacing its previous value if any.
+    pub fn set_arg(&mut self, name: &str, value: &str) {
+        // the line break terminating the header is stored in its last element.
+        let last = self.last_mut();
+        let terminator = last.split_off(last.trim_end().len());
+
+        let arg = format!(" {name}={value}")
+            .parse::<Arg>()
+            .expect("the argument has a name and a value");
+        match self
+            .args
+            .iter_mut()
+            .find(|arg| arg.name().eq_ignore_ascii_case(name))
+        {
+            Some(previous) => *previous = arg,
+            None => self.args.push(arg),
+        }
+
+        self.last_mut().push_str(&terminator);
+    }
+
+    fn last_mut(&mut self) -> &mut String {
+        match self.args.last_mut() {
+            Some(arg) => arg.mut_value(),
+            None => &mut self.body,
+        }
+    }
+
+    /// Get the decoded value of the parameter `needle`.
+    ///
+    /// The continuations (`name*0=`, `name*1=`, ...) are joined and the extended
+    /// values (`name*=utf-8''caf%C3%A9`) decoded following the RFC 2231, the
+    /// encoded words of a plain value are decoded following the RFC 2047.
+    #[must_use]
+    pub fn param(&self, needle: &str) -> Option<String> {
+        let mut extended = None;
+        let mut sections = vec![];
+
+        for arg in &self.args {
+            let name = arg.name();
+            let Some(suffix) = name
+                .get(..needle.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(needle))
+                .map(|_| &name[needle.len()..])
+            else {
+                continue;
+            };
+
+            match suffix {
+                "*" => extended = Some(arg.value()),
+                _ => {
+                    let Some(section) = suffix.strip_prefix('*') else {
+                        continue;
+                    };
+                    let (index, encoded) = section
+                        .strip_suffix('*')
+                        .map_or((section, false), |index| (index, true));
+                    if let Ok(index) = index.parse::<usize>() {
+                        sections.push((index, encoded, arg.value()));
+                    }
+                }
+            }
+        }
+
+        if let Some(value) = extended {
+            return Some(decode_extended_value([(true, value)]));
+        }
+
+        if !sections.is_empty() {
+            sections.sort_by_key(|(index, ..)| *index);
+            // the sections after a missing one are ignored.
+            let contiguous = sections
+                .iter()
+                .enumerate()
+                .take_while(|(expected, (index, ..))| expected == index)
+                .map(|(_, (_, encoded, value))| (*encoded, *value));
+
+            return Some(decode_extended_value(contiguous));
+        }
+
+        self.arg(needle).map(|arg| {
+            if arg.value().contains("=?") {
+                crate::mail::encoded_words::decode(arg.value())
+            } else {
+                arg.value().to_string()
+            }
+        })
+    }
+}
+
+/// Join the `(encoded, value)` sections of a RFC 2231 parameter, the first encoded
+/// section starting with the `charset'language'` prefix.
+fn decode_extended_value<'a>(sections: impl IntoIterator<Item = (bool, &'a str)>) -> String {
+    let mut charset = None;
+    let mut bytes = vec![];
+
+    for (position, (encoded, value)) in sections.into_iter().enumerate() {
+        if !encoded {
+            bytes.extend_from_slice(value.as_bytes());
+            continue;
+        }
+
+        let value = match value.splitn(3, '\'').collect::<Vec<_>>().as_slice() {
+            [prefix_charset, _language, value] if position == 0 => {
+                charset = Some((*prefix_charset).to_string());
+                *value
+            }
+            _ => value,
+        };
+        bytes.extend(percent_decode(value));
+    }
+
+    charset
+        .filter(|charset| !charset.is_empty())
+        .and_then(|charset| crate::charset::decode(&charset, &bytes))
+        .unwrap_or_else(|| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Decode the `%XX` sequences of an extended value, the malformed ones are kept as is.
+fn percent_decode(value: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut input = value.as_bytes().iter();
+
+    while let Some(byte) = input.next() {
+        if *byte == b'%' {
+            let hex = input
+                .as_slice()
+                .get(..2)
+                .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+            if let Some(decoded) = hex {
+                bytes.push(decoded);
+                input.nth(1);
+                continue;
+            }
+        }
+        bytes.push(*byte);
+    }
+
+    bytes
 }
 
 // TODO: handle folding here
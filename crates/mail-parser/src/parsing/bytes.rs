@@ -30,9 +30,22 @@ enum BoundaryType {
 #[derive(Default)]
 pub struct Parser {
     boundary_stack: Vec<String>,
+    /// Maximum number of MIME parts of the message, nested parts included.
+    max_parts: Option<usize>,
+    part_count: usize,
 }
 
 impl Parser {
+    /// Create a parser failing with [`ParserError::TooManyParts`] once more than
+    /// `max_parts` MIME parts have been read, nested parts included.
+    #[must_use]
+    pub fn with_max_parts(max_parts: usize) -> Self {
+        Self {
+            max_parts: Some(max_parts),
+            ..Self::default()
+        }
+    }
+
     // PERF: use u8 slices instead of vec.
     /// Parse the header section of an email from lines of bytes.
     /// The body is stored as is.
@@ -129,6 +142,27 @@ impl Parser {
         }
     }
 
+    /// Parse the raw body of an email without keeping the result,
+    /// to check it against the limits of the parser.
+    /// The emails without MIME structure or already parsed are not checked.
+    pub fn check_body(&mut self, mail: &Mail) -> ParserResult<()> {
+        match &mail.body {
+            Body::Raw(raw) if Self::has_mime_version(&mail.headers.0) => {
+                let mime_headers = mail
+                    .headers
+                    .0
+                    .iter()
+                    .filter(|header| is_mime_header(&header.name))
+                    .map(mime::Header::from)
+                    .collect();
+
+                self.as_mime_body(&mut &raw[..], mime_headers, None)
+                    .map(|_| ())
+            }
+            _ => Ok(()),
+        }
+    }
+
     // PERF: use u8 slices instead of vec.
     /// Parse the entire content an email from lines of bytes.
     /// To only parse the header section, see [`Parser::parse_headers`].
@@ -203,21 +237,17 @@ impl Parser {
     }
 
     fn check_boundary(&self, line: &str) -> Option<BoundaryType> {
-        // we start by checking if the stack as any boundary.
-        self.boundary_stack.last().and_then(|b| {
-            get_boundary_type(line, b).map_or_else(
-                || {
-                    if self.boundary_stack[..self.boundary_stack.len() - 1]
-                        .iter()
-                        .any(|b| get_boundary_type(line, b).is_some())
-                    {
-                        Some(BoundaryType::OutOfScope)
-                    } else {
-                        None
-                    }
-                },
-                Some,
-            )
+        // fast path: every boundary line starts with "--", no need to scan the stack otherwise.
+        if !line.starts_with("--") {
+            return None;
+        }
+
+        let (current, parents) = self.boundary_stack.split_last()?;
+        get_boundary_type(line, current).or_else(|| {
+            parents
+                .iter()
+                .any(|b| get_boundary_type(line, b).is_some())
+                .then_some(BoundaryType::OutOfScope)
         })
     }
 
@@ -301,10 +331,17 @@ impl Parser {
                 headers,
                 part: mime::Part::Html(self.parse_regular_mime_body(content)?),
             }),
-            ("message", _) => Ok(Mime {
-                headers,
-                part: mime::Part::Embedded(self.parse_inner(content)?),
-            }),
+            // the other subtypes (delivery-status, disposition-notification, ...)
+            // are not emails, and are handled as binary parts.
+            ("message", subtype)
+                if subtype.eq_ignore_ascii_case("rfc822")
+                    || subtype.eq_ignore_ascii_case("global") =>
+            {
+                Ok(Mime {
+                    headers,
+                    part: mime::Part::Embedded(self.parse_inner(content)?),
+                })
+            }
             ("multipart", _) => Ok(Mime {
                 headers: headers.clone(),
                 part: mime::Part::Multipart(self.parse_multipart(&headers, content)?),
@@ -409,7 +446,8 @@ impl Parser {
             .unwrap();
 
         match content_type.arg("boundary") {
-            Some(arg) => self.boundary_stack.push(arg.value().to_string()),
+            // a boundary cannot end with a whitespace (rfc2046 section 5.1.1).
+            Some(arg) => self.boundary_stack.push(arg.value().trim_end().to_string()),
             None => {
                 return Err(ParserError::BoundaryNotFound(
                     "boundary parameter not found in Content-Type header for a multipart."
@@ -433,6 +471,11 @@ impl Parser {
                 Some(BoundaryType::Delimiter) => {
                     *content = &content[1..];
 
+                    self.part_count += 1;
+                    if let Some(max_parts) = self.max_parts.filter(|max| self.part_count > *max) {
+                        return Err(ParserError::TooManyParts(max_parts));
+                    }
+
                     multi_parts
                         .parts
                         .push(self.parse_mime(content, Some(headers))?);
@@ -526,18 +569,150 @@ pub(crate) fn is_mime_header(name: &str) -> bool {
         .map_or(false, |name| name.eq_ignore_ascii_case(MIME_HEADER_START))
 }
 
-// is used to deduce the boundary type.
-// ! this method is called too many times, causing slow downs.
+/// Deduce the boundary type of a line, following rfc2046 section 5.1.1:
+/// the boundary must start the line after "--", can be followed by "--" for
+/// the close delimiter, and the remaining of the line must be whitespaces.
 #[inline]
 fn get_boundary_type(line: &str, boundary: &str) -> Option<BoundaryType> {
-    match (
-        // TODO: can be optimized.
-        line.starts_with("--") && !line.starts_with(boundary),
-        (line.ends_with("--") || line.ends_with("--\r\n")) && !line.ends_with(boundary),
-        line.contains(boundary),
-    ) {
-        (true, false, true) => Some(BoundaryType::Delimiter),
-        (true, true, true) => Some(BoundaryType::End),
-        _ => None,
+    let line = line.strip_prefix("--")?.strip_prefix(boundary)?;
+
+    let (line, boundary_type) = line
+        .strip_prefix("--")
+        .map_or((line, BoundaryType::Delimiter), |line| {
+            (line, BoundaryType::End)
+        });
+
+    line.trim_end_matches([' ', '\t', '\r', '\n'])
+        .is_empty()
+        .then_some(boundary_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::mail::body::{Body, ParsedBody};
+    use crate::mime::{Mime, Part};
+    use crate::ParserError;
+
+    fn parse_parts(lines: &[&str]) -> Vec<Mime> {
+        let mail = Parser::default()
+            .parse(
+                [
+                    "From: john.doe@example.com",
+                    "Date: Tue, 1 Apr 1997 09:06:31 -0800 (PST)",
+                    "MIME-Version: 1.0",
+                    "Content-Type: multipart/mixed; boundary=\"frontier\"",
+                    "",
+                    "preamble",
+                ]
+                .iter()
+                .chain(lines)
+                .map(|line| format!("{line}\r\n").into_bytes())
+                .collect(),
+            )
+            .unwrap();
+
+        let Body::Parsed(ParsedBody::Mime(mime)) = mail.body else {
+            panic!("the body should be parsed as mime")
+        };
+        let Part::Multipart(multipart) = mime.part else {
+            panic!("the body should be a multipart")
+        };
+        multipart.parts
+    }
+
+    fn parts_with_limit(count: usize, max_parts: usize) -> Result<(), ParserError> {
+        let mut lines = vec![
+            "From: john.doe@example.com".to_string(),
+            "Date: Tue, 1 Apr 1997 09:06:31 -0800 (PST)".to_string(),
+            "MIME-Version: 1.0".to_string(),
+            "Content-Type: multipart/mixed; boundary=\"frontier\"".to_string(),
+            String::new(),
+        ];
+        for i in 0..count {
+            lines.extend([
+                "--frontier".to_string(),
+                "Content-Type: text/plain".to_string(),
+                String::new(),
+                format!("part {i}"),
+            ]);
+        }
+        lines.push("--frontier--".to_string());
+
+        Parser::with_max_parts(max_parts)
+            .parse(
+                lines
+                    .into_iter()
+                    .map(|line| format!("{line}\r\n").into_bytes())
+                    .collect(),
+            )
+            .map(|_| ())
+    }
+
+    fn text(part: &Mime) -> &[String] {
+        let Part::Text(text) = &part.part else {
+            panic!("the part should be a text part")
+        };
+        text
+    }
+
+    #[test]
+    fn boundary_as_substring() {
+        let parts = parse_parts(&[
+            "--frontier",
+            "Content-Type: text/plain",
+            "",
+            "first part",
+            "a line quoting --frontier in the middle",
+            "--not frontier at all",
+            "--frontier-is-not-a-delimiter",
+            "--frontier",
+            "Content-Type: text/plain",
+            "",
+            "second part",
+            "--frontier--",
+            "epilogue",
+        ]);
+
+        pretty_assertions::assert_eq!(parts.len(), 2);
+        pretty_assertions::assert_eq!(
+            text(&parts[0]),
+            [
+                "first part\r\n",
+                "a line quoting --frontier in the middle\r\n",
+                "--not frontier at all\r\n",
+                "--frontier-is-not-a-delimiter\r\n",
+            ]
+        );
+        pretty_assertions::assert_eq!(text(&parts[1]), ["second part\r\n"]);
+    }
+
+    #[test]
+    fn boundary_with_trailing_whitespaces() {
+        let parts = parse_parts(&[
+            "--frontier \t",
+            "Content-Type: text/plain",
+            "",
+            "first part",
+            "--frontier  ",
+            "Content-Type: text/plain",
+            "",
+            "second part",
+            "--frontier--\t ",
+            "epilogue",
+        ]);
+
+        pretty_assertions::assert_eq!(parts.len(), 2);
+        pretty_assertions::assert_eq!(text(&parts[0]), ["first part\r\n"]);
+        pretty_assertions::assert_eq!(text(&parts[1]), ["second part\r\n"]);
+    }
+
+    #[test]
+    fn too_many_parts() {
+        assert!(parts_with_limit(100, 100).is_ok());
+        assert!(matches!(
+            parts_with_limit(101, 100),
+            Err(ParserError::TooManyParts(100))
+        ));
     }
 }
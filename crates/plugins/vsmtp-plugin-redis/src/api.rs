@@ -245,6 +245,31 @@ impl RedisConnector {
             }
         }
     }
+
+    pub fn seen(
+        &self,
+        key: &str,
+        window: std::time::Duration,
+    ) -> Result<bool, Box<rhai::EvalAltResult>> {
+        let mut client = self.pool.get();
+        match client {
+            Ok(ref mut client) => {
+                // `SET NX` does not overwrite the key, the expiration is only set when recording it.
+                let result: Option<String> = redis::cmd("SET")
+                    .arg(key)
+                    .arg(1)
+                    .arg("NX")
+                    .arg("EX")
+                    .arg(window.as_secs().max(1))
+                    .query(client)
+                    .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+                Ok(result.is_none())
+            }
+            Err(e) => {
+                Err(e).map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?
+            }
+        }
+    }
 }
 
 /// This plugin exposes methods to open a pool of connections to a redis database using
@@ -629,4 +654,52 @@ pub mod redis_api {
     ) -> Result<rhai::INT, Box<rhai::EvalAltResult>> {
         con.decrement(key, delta)
     }
+
+    /// Check if a key has already been seen recently, recording it otherwise.
+    /// Used with `ctx.fingerprint` to detect duplicate messages.
+    ///
+    /// # Args
+    ///
+    /// * `key` - The key to look for.
+    /// * `window` - How long the key is remembered once recorded (e.g. `"1h"`).
+    ///
+    /// # Return
+    ///
+    /// `true` if the key was recorded less than `window` ago, `false` otherwise.
+    ///
+    /// # Example
+    ///
+    /// Build a service in `services/redis.rhai`;
+    ///
+    /// ```text
+    /// // Import the plugin stored in the `plugins` directory.
+    /// import "plugins/libvsmtp_plugin_redis" as redis;
+    ///
+    /// export const client = redis::connect(#{
+    ///     url: "redis://localhost:6379",
+    ///     connections: 1,
+    /// });
+    /// ```
+    ///
+    /// Quarantine duplicated messages during filtering.
+    ///
+    /// ```js
+    /// import "services/redis" as srv;
+    ///
+    /// fn on_pre_queue(ctx) {
+    ///     if srv::client.seen(ctx.fingerprint, "1h") {
+    ///         status::quarantine("duplicates")
+    ///     } else {
+    ///         status::next()
+    ///     }
+    /// }
+    /// ```
+    /// # rhai-autodocs:index:9
+    #[rhai_fn(global, return_raw, pure)]
+    pub fn seen(con: &mut Red, key: &str, window: &str) -> Result<bool, Box<rhai::EvalAltResult>> {
+        let window =
+            humantime_serde::re::humantime::parse_duration(window)
+                .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+        con.seen(key, window)
+    }
 }
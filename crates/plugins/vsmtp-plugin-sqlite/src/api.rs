@@ -140,6 +140,44 @@ impl SQLiteConnector {
         }
         Ok(returned_rows)
     }
+
+    pub fn seen(
+        &self,
+        key: &str,
+        window: std::time::Duration,
+    ) -> Result<bool, Box<rhai::EvalAltResult>> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+        let now = i64::try_from(now.as_secs())
+            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+        let window = i64::try_from(window.as_secs())
+            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+
+        let client = self
+            .pool
+            .get()
+            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+        client
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS vsmtp_seen (key TEXT PRIMARY KEY, at INTEGER NOT NULL);",
+            )
+            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+        client
+            .execute(
+                "DELETE FROM vsmtp_seen WHERE at <= ?1;",
+                (now.saturating_sub(window),),
+            )
+            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+        let inserted = client
+            .execute(
+                "INSERT OR IGNORE INTO vsmtp_seen (key, at) VALUES (?1, ?2);",
+                (key, now),
+            )
+            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+
+        Ok(inserted == 0)
+    }
 }
 
 /// This plugin exposes methods to open a pool of connections to a sqlite database using
@@ -256,4 +294,44 @@ pub mod sqlite_api {
             .query(query)
             .map(|record| record.into_iter().map(rhai::Dynamic::from).collect())
     }
+
+    /// Check if a key has already been seen recently, recording it otherwise.
+    /// Used with `ctx.fingerprint` to detect duplicate messages.
+    ///
+    /// Keys are stored in the `vsmtp_seen` table, created if missing.
+    ///
+    /// # Args
+    ///
+    /// * `key` - The key to look for.
+    /// * `window` - How long the key is remembered once recorded (e.g. `"1h"`).
+    ///
+    /// # Return
+    ///
+    /// `true` if the key was recorded less than `window` ago, `false` otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// import "services/database" as srv;
+    ///
+    /// fn on_pre_queue(ctx) {
+    ///     if srv::database.seen(ctx.fingerprint, "1h") {
+    ///         status::quarantine("duplicates")
+    ///     } else {
+    ///         status::next()
+    ///     }
+    /// }
+    /// ```
+    /// # rhai-autodocs:index:3
+    #[rhai_fn(global, return_raw, pure)]
+    pub fn seen(
+        database: &mut SQLite,
+        key: &str,
+        window: &str,
+    ) -> Result<bool, Box<rhai::EvalAltResult>> {
+        let window =
+            humantime_serde::re::humantime::parse_duration(window)
+                .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?;
+        database.seen(key, window)
+    }
 }
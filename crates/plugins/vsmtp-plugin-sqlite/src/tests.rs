@@ -44,3 +44,27 @@ fn test_query() {
     sqlite_api::query(&mut server, "SELECT * FROM sharks;").unwrap();
     dbg!(sqlite_api::query(&mut server, "SELECT * FROM sharks;")).unwrap();
 }
+
+#[test]
+fn test_seen() {
+    let engine = Engine::new();
+    let map = engine.parse_json(
+        r#"
+            {
+                "path": "seen.db",
+                "connections": 1,
+                "timeout": "1s"
+            }"#,
+        true,
+    );
+    let mut server = sqlite_api::connect(map.unwrap()).unwrap();
+    sqlite_api::query(&mut server, "DROP TABLE IF EXISTS vsmtp_seen;").unwrap();
+
+    assert!(!sqlite_api::seen(&mut server, "fingerprint", "1h").unwrap());
+    assert!(sqlite_api::seen(&mut server, "fingerprint", "1h").unwrap());
+    assert!(!sqlite_api::seen(&mut server, "other", "1h").unwrap());
+
+    // an empty window forgets the keys immediately.
+    assert!(!sqlite_api::seen(&mut server, "fingerprint", "0s").unwrap());
+    assert!(sqlite_api::seen(&mut server, "invalid", "forever").is_err());
+}
@@ -0,0 +1,134 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Error;
+use hmac::Mac;
+
+/// The challenge of a `CRAM-MD5` exchange, see <https://datatracker.ietf.org/doc/html/rfc2195>.
+///
+/// This mechanism is not provided by the SASL backend, the [`Receiver`](crate::Receiver)
+/// runs the exchange itself and the [`ReceiverHandler`](crate::ReceiverHandler) verifies the response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CramMd5Challenge(String);
+
+impl CramMd5Challenge {
+    /// Generate a new challenge, unique to this exchange: `<random.timestamp@hostname>`.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let hostname = hostname::get().map_or_else(
+            |_| "localhost".to_owned(),
+            |hostname| hostname.to_string_lossy().into_owned(),
+        );
+
+        Self(format!(
+            "<{}.{timestamp}@{hostname}>",
+            rand::random::<u32>()
+        ))
+    }
+
+    /// Split the response of the client into the user name and the hexadecimal digest.
+    ///
+    /// # Errors
+    ///
+    /// * the response is not valid utf8
+    /// * the response is not `<user> <digest>`
+    #[inline]
+    pub fn parse_response(response: &[u8]) -> Result<(String, String), Error> {
+        let response = std::str::from_utf8(response).map_err(Error::Utf8)?;
+        match response.rsplit_once(' ') {
+            Some((authid, digest)) if !authid.is_empty() && !digest.is_empty() => {
+                Ok((authid.to_owned(), digest.to_owned()))
+            }
+            _ => Err(Error::MissingField),
+        }
+    }
+
+    /// Check the digest sent by the client, which is the HMAC-MD5 of the challenge
+    /// keyed by the secret of the user.
+    #[inline]
+    #[must_use]
+    pub fn verify(&self, secret: &[u8], digest: &str) -> bool {
+        let Some(digest) = decode_hex(digest) else {
+            return false;
+        };
+        let Ok(mut mac) = hmac::Hmac::<md5::Md5>::new_from_slice(secret) else {
+            return false;
+        };
+        mac.update(self.0.as_bytes());
+        mac.verify_slice(&digest).is_ok()
+    }
+}
+
+impl Default for CramMd5Challenge {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsRef<str> for CramMd5Challenge {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if input.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|i| {
+            input
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // <https://datatracker.ietf.org/doc/html/rfc2195#section-2>
+    fn rfc2195() -> CramMd5Challenge {
+        CramMd5Challenge("<1896.697170952@postoffice.reston.mci.net>".to_owned())
+    }
+
+    #[test]
+    fn verify() {
+        let (authid, digest) =
+            CramMd5Challenge::parse_response(b"tim b913a602c7eda7a495b4e6e7334d3890").unwrap();
+
+        assert_eq!(authid, "tim");
+        assert!(rfc2195().verify(b"tanstaaftanstaaf", &digest));
+        assert!(!rfc2195().verify(b"tanstaaf", &digest));
+        assert!(!rfc2195().verify(b"tanstaaftanstaaf", "b913a602"));
+        assert!(!rfc2195().verify(b"tanstaaftanstaaf", "not hexadecimal"));
+    }
+
+    #[test]
+    fn invalid_response() {
+        CramMd5Challenge::parse_response(b"b913a602c7eda7a495b4e6e7334d3890").unwrap_err();
+        CramMd5Challenge::parse_response(b" b913a602c7eda7a495b4e6e7334d3890").unwrap_err();
+    }
+
+    #[test]
+    fn unique() {
+        assert_ne!(CramMd5Challenge::new(), CramMd5Challenge::new());
+    }
+}
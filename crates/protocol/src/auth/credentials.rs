@@ -28,6 +28,12 @@ pub enum Credentials {
         /// [ email / 1*255TCHAR ]
         token: String,
     },
+    /// the client proved it knows the secret of `authid` during a challenge-response
+    /// exchange (`CRAM-MD5`, `SCRAM-SHA-256`), the password is never sent
+    ChallengeResponse {
+        ///
+        authid: String,
+    },
 }
 
 #[cfg(not(debug_assertions))]
@@ -43,6 +49,10 @@ impl std::fmt::Debug for Credentials {
                 .debug_struct("Credentials::AnonymousToken")
                 .field("token", &"***")
                 .finish(),
+            Credentials::ChallengeResponse { authid } => f
+                .debug_struct("Credentials::ChallengeResponse")
+                .field("authid", authid)
+                .finish(),
         }
     }
 }
@@ -68,6 +78,16 @@ impl serde::Serialize for Credentials {
                 s.serialize_field("token", "***")?;
                 s.end()
             }
+            Credentials::ChallengeResponse { .. } => {
+                let mut s = serializer.serialize_struct_variant(
+                    "Credentials",
+                    2,
+                    "ChallengeResponse",
+                    1,
+                )?;
+                s.serialize_field("authid", "***")?;
+                s.end()
+            }
         }
     }
 }
@@ -114,7 +134,13 @@ impl TryFrom<(&rsasl::callback::SessionData, &rsasl::callback::Context<'_>)> for
                     .ok_or(Error::MissingField)?
                     .to_owned(),
             }),
-            // mech if mech == Mechanism::CramMd5.as_ref() => todo!(),
+            mech if mech == Mechanism::ScramSha256.as_ref() => Ok(Self::ChallengeResponse {
+                authid: context
+                    .get_ref::<rsasl::property::AuthId>()
+                    .ok_or(Error::MissingField)?
+                    .to_owned(),
+            }),
+            // CRAM-MD5 is not handled by the SASL backend, see `ReceiverHandler::on_cram_md5`.
             _ => Err(Error::Unimplemented),
         }
     }
@@ -55,13 +55,16 @@ pub enum Mechanism {
     /// Common
     /// See <https://datatracker.ietf.org/doc/html/rfc4505>
     Anonymous,
+    /// Common
+    /// See <https://datatracker.ietf.org/doc/html/rfc7677>
+    #[strum(serialize = "SCRAM-SHA-256")]
+    ScramSha256,
     /*
     - EXTERNAL
     - SECURID
     - DIGEST-MD5
     - SCRAM-SHA-1
     - SCRAM-SHA-1-PLUS
-    - SCRAM-SHA-256
     - SCRAM-SHA-256-PLUS
     - SAML20
     - OPENID20
@@ -77,7 +80,7 @@ impl Mechanism {
     #[must_use]
     pub const fn client_first(self) -> bool {
         match self {
-            Self::Plain | Self::Anonymous => true,
+            Self::Plain | Self::Anonymous | Self::ScramSha256 => true,
             Self::Login | Self::CramMd5 => false,
         }
     }
@@ -88,6 +91,8 @@ impl Mechanism {
     pub const fn must_be_under_tls(self) -> bool {
         match self {
             Self::Plain | Self::Login | Self::CramMd5 | Self::Anonymous => true,
+            // the password is never sent, and the server proves it knows the verifier.
+            Self::ScramSha256 => false,
         }
     }
 }
@@ -103,6 +108,19 @@ mod tests {
         assert_eq!(Mechanism::Login.to_string(), "LOGIN");
         assert_eq!(Mechanism::CramMd5.to_string(), "CRAM-MD5");
         assert_eq!(Mechanism::Anonymous.to_string(), "ANONYMOUS");
+        assert_eq!(Mechanism::ScramSha256.to_string(), "SCRAM-SHA-256");
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!(
+            <Mechanism as std::str::FromStr>::from_str("SCRAM-SHA-256").unwrap(),
+            Mechanism::ScramSha256
+        );
+        assert_eq!(
+            <Mechanism as std::str::FromStr>::from_str("CRAM-MD5").unwrap(),
+            Mechanism::CramMd5
+        );
     }
 
     #[test]
@@ -0,0 +1,102 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// The verifier of a user for the `SCRAM-SHA-256` mechanism, stored by the server
+/// instead of the password.
+///
+/// Parsed from the format of <https://datatracker.ietf.org/doc/html/rfc5803>, also used by
+/// PostgreSQL and Dovecot: `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>`,
+/// with the salt and the keys [`base64`] encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScramVerifier {
+    /// Number of iterations used to derive the salted password.
+    pub iterations: u32,
+    /// Salt of the password.
+    pub salt: Vec<u8>,
+    /// `H(HMAC(SaltedPassword, "Client Key"))`
+    pub stored_key: Vec<u8>,
+    /// `HMAC(SaltedPassword, "Server Key")`
+    pub server_key: Vec<u8>,
+}
+
+/// Error while parsing a [`ScramVerifier`].
+#[derive(Debug, thiserror::Error)]
+pub enum ScramVerifierError {
+    /// The verifier is not `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>`.
+    #[error(
+        "invalid format, expected 'SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>'"
+    )]
+    Format,
+    /// The number of iterations is not a positive integer.
+    #[error("invalid iteration count: {0}")]
+    Iterations(std::num::ParseIntError),
+    /// The salt or a key is not [`base64`] encoded.
+    #[error("base64 decoding fail: {0}")]
+    Base64(base64::DecodeError),
+}
+
+impl std::str::FromStr for ScramVerifier {
+    type Err = ScramVerifierError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('$');
+        let (Some("SCRAM-SHA-256"), Some(parameters), Some(keys), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ScramVerifierError::Format);
+        };
+
+        let (iterations, salt) = parameters
+            .split_once(':')
+            .ok_or(ScramVerifierError::Format)?;
+        let (stored_key, server_key) = keys.split_once(':').ok_or(ScramVerifierError::Format)?;
+
+        let decode = |input: &str| STANDARD.decode(input).map_err(ScramVerifierError::Base64);
+
+        Ok(Self {
+            iterations: iterations.parse().map_err(ScramVerifierError::Iterations)?,
+            salt: decode(salt)?,
+            stored_key: decode(stored_key)?,
+            server_key: decode(server_key)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let verifier = "SCRAM-SHA-256$4096:W22ZaJ0SNY7soEsUEjb6gQ==$WG5d8oPm3OtcPnkdi4Uo7BkeZkBFzpcXkuLmtbsT4qY=:wfPLwcE6nTWhTAmQ7tl2KeoiWGPlZqQxSrmfPwDl2dU="
+            .parse::<ScramVerifier>()
+            .unwrap();
+
+        assert_eq!(verifier.iterations, 4096);
+        assert_eq!(verifier.salt.len(), 16);
+        assert_eq!(verifier.stored_key.len(), 32);
+        assert_eq!(verifier.server_key.len(), 32);
+    }
+
+    #[rstest::rstest]
+    #[case("")]
+    #[case("SCRAM-SHA-1$4096:W22ZaJ0SNY7soEsUEjb6gQ==$AAAA:AAAA")]
+    #[case("SCRAM-SHA-256$W22ZaJ0SNY7soEsUEjb6gQ==$AAAA:AAAA")]
+    #[case("SCRAM-SHA-256$-1:W22ZaJ0SNY7soEsUEjb6gQ==$AAAA:AAAA")]
+    #[case("SCRAM-SHA-256$4096:not base64$AAAA:AAAA")]
+    #[case("SCRAM-SHA-256$4096:W22ZaJ0SNY7soEsUEjb6gQ==$AAAA:AAAA$")]
+    fn invalid(#[case] verifier: &str) {
+        verifier.parse::<ScramVerifier>().unwrap_err();
+    }
+}
@@ -85,13 +85,29 @@ pub struct EhloArgs {
 
 /// See "SMTP Service Extension for 8-bit MIME Transport"
 /// <https://datatracker.ietf.org/doc/html/rfc6152>
-#[derive(strum::EnumVariantNames, strum::EnumString)]
+#[allow(clippy::exhaustive_enums)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum::EnumVariantNames,
+    strum::EnumString,
+    strum::Display,
+    serde::Serialize,
+    serde::Deserialize,
+    fake::Dummy,
+)]
+#[strum(ascii_case_insensitive)]
 pub enum MimeBodyType {
-    ///
+    /// The message only contains 7-bit US-ASCII data.
     #[strum(serialize = "7BIT")]
+    #[serde(rename = "7BIT")]
     SevenBit,
-    ///
+    /// The message may contain octets outside the US-ASCII range.
     #[strum(serialize = "8BITMIME")]
+    #[serde(rename = "8BITMIME")]
     EightBitMime,
     // TODO: https://datatracker.ietf.org/doc/html/rfc3030
     // Binary,
@@ -129,6 +145,10 @@ pub struct MailFromArgs {
     pub envelop_id: Option<String>,
     /// `RET` argument of the `MAIL FROM` command
     pub ret: Option<DsnReturn>,
+    /// Parameters not supported by vSMTP, as received (`KEYWORD` or `KEYWORD=value`).
+    pub unknown_parameters: Vec<String>,
+    /// All the parameters following the address, as received.
+    pub raw_parameters: String,
 }
 
 /// <https://www.rfc-editor.org/rfc/rfc3461>
@@ -159,6 +179,101 @@ pub struct OriginalRecipient {
     pub addr_type: String,
     /// The original recipient address.
     pub mailbox: Address,
+    /// The `xtext` encoded address as received from the client, relayed as is
+    /// so that the DSN report the exact `ORCPT` value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xtext: Option<String>,
+}
+
+/// Format the value of the `ORCPT` argument.
+impl std::fmt::Display for OriginalRecipient {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.xtext {
+            Some(xtext) => write!(f, "{};{xtext}", self.addr_type),
+            None => write!(
+                f,
+                "{};{}",
+                self.addr_type,
+                encode_xtext(&self.mailbox.to_string())
+            ),
+        }
+    }
+}
+
+/// Decode an `xtext` value, as defined in <https://www.rfc-editor.org/rfc/rfc3461#section-4>.
+///
+/// # Errors
+///
+/// * a character is not allowed in `xtext`
+/// * a `+` is not followed by two upper case hexadecimal digits
+/// * the decoded value is not valid utf8
+#[inline]
+pub fn decode_xtext(input: &[u8]) -> Result<String, ParseArgsError> {
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut iter = input.iter();
+
+    while let Some(c) = iter.next() {
+        match *c {
+            b'+' => {
+                let mut hex_digit = || match iter.next() {
+                    Some(digit @ b'0'..=b'9') => Ok(digit - b'0'),
+                    Some(digit @ b'A'..=b'F') => Ok(digit - b'A' + 10),
+                    _ => Err(ParseArgsError::InvalidArgs),
+                };
+                decoded.push((hex_digit()? << 4) | hex_digit()?);
+            }
+            b'=' => return Err(ParseArgsError::InvalidArgs),
+            c @ b'!'..=b'~' => decoded.push(c),
+            _ => return Err(ParseArgsError::InvalidArgs),
+        }
+    }
+
+    Ok(String::from_utf8(decoded)?)
+}
+
+/// Decode a malformed `xtext` value, keeping as is the characters which are not valid
+/// escapes. The `=XX` escapes of quoted-printable, used by some clients, are decoded.
+fn decode_xtext_lossy(input: &[u8]) -> String {
+    let hex_digit = |digit: u8| match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    };
+
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let escaped = match input.get(i..i + 3) {
+            Some(&[b'+' | b'=', high, low]) => hex_digit(high).zip(hex_digit(low)),
+            _ => None,
+        };
+        if let Some((high, low)) = escaped {
+            decoded.push((high << 4) | low);
+            i += 3;
+        } else {
+            decoded.push(input[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Encode a value as `xtext`, as defined in <https://www.rfc-editor.org/rfc/rfc3461#section-4>.
+#[inline]
+#[must_use]
+pub fn encode_xtext(input: &str) -> String {
+    input
+        .bytes()
+        .fold(String::with_capacity(input.len()), |mut out, c| {
+            if matches!(c, b'!'..=b'~') && c != b'+' && c != b'=' {
+                out.push(char::from(c));
+            } else {
+                out.push_str(&format!("+{c:02X}"));
+            }
+            out
+        })
 }
 
 /// Information received from the client at the RCPT TO command.
@@ -170,6 +285,10 @@ pub struct RcptToArgs {
     pub original_forward_path: Option<OriginalRecipient>,
     /// `NOTIFY` argument of the `RCPT TO` command
     pub notify_on: NotifyOn,
+    /// Parameters not supported by vSMTP, as received (`KEYWORD` or `KEYWORD=value`).
+    pub unknown_parameters: Vec<String>,
+    /// All the parameters following the address, as received.
+    pub raw_parameters: String,
 }
 
 /// Information received from the client at the AUTH command.
@@ -182,6 +301,87 @@ pub struct AuthArgs {
     pub initial_response: Option<Vec<u8>>,
 }
 
+/// Information received from the client at the VRFY command.
+#[non_exhaustive]
+pub struct VrfyArgs {
+    /// User name or mailbox to verify, as received.
+    pub query: String,
+}
+
+/// Information received from the client at the EXPN command.
+#[non_exhaustive]
+pub struct ExpnArgs {
+    /// Mailing list to expand, as received.
+    pub query: String,
+}
+
+/// Read the single string argument of the VRFY and EXPN commands.
+/// <https://www.rfc-editor.org/rfc/rfc5321#section-4.1.1.6>
+fn parse_query(value: UnparsedArgs) -> Result<String, ParseArgsError> {
+    let value = strip_suffix_crlf!(value);
+    let query = std::str::from_utf8(
+        value
+            .strip_prefix(b" ")
+            .ok_or(ParseArgsError::InvalidArgs)?,
+    )?
+    .trim();
+
+    if query.is_empty() {
+        return Err(ParseArgsError::InvalidArgs);
+    }
+
+    Ok(query.to_owned())
+}
+
+/// Read an esmtp parameter not supported by vSMTP, which must still be a valid
+/// `esmtp-keyword["=" esmtp-value]`.
+/// <https://www.rfc-editor.org/rfc/rfc5321#section-4.1.2>
+fn unknown_parameter(raw_args: &[u8]) -> Result<String, ParseArgsError> {
+    let (keyword, value) = match split_args(raw_args) {
+        Some((_, b"")) => return Err(ParseArgsError::InvalidArgs),
+        Some((keyword, value)) => (keyword, value),
+        None => (raw_args, &b""[..]),
+    };
+
+    let is_keyword = keyword.first().map_or(false, u8::is_ascii_alphanumeric)
+        && keyword
+            .iter()
+            .all(|c| c.is_ascii_alphanumeric() || *c == b'-');
+    let is_value = value.iter().all(|c| (33..=126).contains(c) && *c != b'=');
+
+    if !is_keyword || !is_value {
+        return Err(ParseArgsError::InvalidArgs);
+    }
+
+    Ok(std::str::from_utf8(raw_args)?.to_owned())
+}
+
+/// Read the parameters following the path of the MAIL FROM and RCPT TO commands.
+///
+/// The path ends at its closing `>`, a quoted local part may contain spaces or a `>`.
+fn raw_parameters(value: &[u8]) -> Result<String, ParseArgsError> {
+    let value = std::str::from_utf8(value)?.trim_start();
+
+    let end = if value.starts_with('<') {
+        let mut quoted = false;
+        let mut escaped = false;
+        value.char_indices().find_map(|(i, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                '>' if !quoted => return Some(i + 1),
+                _ => {}
+            }
+            None
+        })
+    } else {
+        value.find(|c: char| c.is_ascii_whitespace())
+    };
+
+    Ok(end.map_or("", |end| value[end..].trim()).to_owned())
+}
+
 fn split_args(slice: &[u8]) -> Option<(&[u8], &[u8])> {
     slice.iter().position(|c| *c == b'=').map(|pos| {
         let (k, v) = slice.split_at(pos);
@@ -283,29 +483,45 @@ impl TryFrom<UnparsedArgs> for AuthArgs {
     }
 }
 
+impl TryFrom<UnparsedArgs> for VrfyArgs {
+    type Error = ParseArgsError;
+
+    #[inline]
+    fn try_from(value: UnparsedArgs) -> Result<Self, Self::Error> {
+        Ok(Self {
+            query: parse_query(value)?,
+        })
+    }
+}
+
+impl TryFrom<UnparsedArgs> for ExpnArgs {
+    type Error = ParseArgsError;
+
+    #[inline]
+    fn try_from(value: UnparsedArgs) -> Result<Self, Self::Error> {
+        Ok(Self {
+            query: parse_query(value)?,
+        })
+    }
+}
+
 impl MailFromArgs {
     fn parse_arguments(&mut self, raw_args: &[u8]) -> Result<(), ParseArgsError> {
         match split_args(raw_args) {
-            #[allow(clippy::expect_used)]
             Some((key, value)) if key.eq_ignore_ascii_case(b"BODY") => {
                 if self.mime_body_type.is_some() {
                     Err(ParseArgsError::InvalidArgs)
                 } else {
-                    self.mime_body_type = <MimeBodyType as strum::VariantNames>::VARIANTS
-                        .iter()
-                        .find(|i| {
-                            value.len() >= i.len()
-                                && value
-                                    .get(..i.len())
-                                    .expect("range checked above")
-                                    .eq_ignore_ascii_case(i.as_bytes())
-                        })
-                        .map(|body| body.parse().expect("body found above"));
+                    self.mime_body_type = Some(
+                        std::str::from_utf8(value)?
+                            .parse()
+                            .map_err(|_e| ParseArgsError::InvalidArgs)?,
+                    );
                     Ok(())
                 }
             }
             Some((key, value)) if key.eq_ignore_ascii_case(b"SIZE") => {
-                if self.mime_body_type.is_some() {
+                if self.size.is_some() {
                     Err(ParseArgsError::InvalidArgs)
                 } else {
                     self.size = Some(
@@ -340,7 +556,10 @@ impl MailFromArgs {
                     Ok(())
                 }
             }
-            _ => Err(ParseArgsError::InvalidArgs),
+            _ => {
+                self.unknown_parameters.push(unknown_parameter(raw_args)?);
+                Ok(())
+            }
         }
     }
 
@@ -350,7 +569,10 @@ impl MailFromArgs {
                 self.use_smtputf8 = true;
                 Ok(())
             }
-            _ => Err(ParseArgsError::InvalidArgs),
+            _ => {
+                self.unknown_parameters.push(unknown_parameter(raw_args)?);
+                Ok(())
+            }
         }
     }
 }
@@ -380,6 +602,8 @@ impl TryFrom<UnparsedArgs> for MailFromArgs {
             use_smtputf8: false,
             envelop_id: None,
             ret: None,
+            unknown_parameters: vec![],
+            raw_parameters: raw_parameters(value)?,
         };
 
         for arg in args {
@@ -418,17 +642,17 @@ impl RcptToArgs {
                         None => return Err(ParseArgsError::InvalidArgs),
                     };
 
-                    let value = std::str::from_utf8(addr)?;
+                    // the raw value is still relayed as is, see `OriginalRecipient::xtext`.
+                    let value = decode_xtext(addr).unwrap_or_else(|_| decode_xtext_lossy(addr));
                     self.original_forward_path =
-                        match <Address as std::str::FromStr>::from_str(value) {
+                        match <Address as std::str::FromStr>::from_str(&value) {
                             Ok(mailbox) => Some(OriginalRecipient {
                                 addr_type: std::str::from_utf8(addr_type)?.to_owned(),
                                 mailbox,
+                                xtext: Some(std::str::from_utf8(addr)?.to_owned()),
                             }),
                             Err(_error) => {
-                                return Err(ParseArgsError::InvalidMailAddress {
-                                    mail: value.to_owned(),
-                                })
+                                return Err(ParseArgsError::InvalidMailAddress { mail: value })
                             }
                         };
                     Ok(())
@@ -496,7 +720,10 @@ impl RcptToArgs {
 
                 Ok(())
             }
-            _ => Err(ParseArgsError::InvalidArgs),
+            _ => {
+                self.unknown_parameters.push(unknown_parameter(raw_args)?);
+                Ok(())
+            }
         }
     }
 }
@@ -528,13 +755,15 @@ impl TryFrom<UnparsedArgs> for RcptToArgs {
                 failure: true,
                 delay: false,
             },
+            unknown_parameters: vec![],
+            raw_parameters: raw_parameters(value)?,
         };
 
         for arg in args {
             if arg.contains(&b'=') {
                 result.parse_arguments(arg)?;
             } else {
-                return Err(ParseArgsError::InvalidArgs);
+                result.unknown_parameters.push(unknown_parameter(arg)?);
             }
         }
 
@@ -595,6 +824,14 @@ pub enum Verb {
     /// <https://datatracker.ietf.org/doc/html/rfc4954>
     #[strum(serialize = "AUTH ")]
     Auth,
+    /// This command asks the receiver to confirm that the argument identifies
+    /// a user or mailbox.
+    #[strum(serialize = "VRFY")]
+    Vrfy,
+    /// This command asks the receiver to confirm that the argument identifies
+    /// a mailing list, and if so, to return the membership of that list.
+    #[strum(serialize = "EXPN")]
+    Expn,
     /// Any other buffer received while expecting a command is considered an
     /// unknown.
     Unknown,
@@ -602,12 +839,184 @@ pub enum Verb {
 
 impl Verb {
     /// check if the answer of the verb is bufferable (cf. pipelining)
-    // Note: missing VRFY, EXPN, TURN
+    // Note: missing TURN
     #[inline]
     #[must_use]
     pub const fn is_bufferable(self) -> bool {
-        !matches!(self, Self::Ehlo | Self::Data | Self::Quit | Self::Noop)
+        !matches!(
+            self,
+            Self::Ehlo | Self::Data | Self::Quit | Self::Noop | Self::Vrfy | Self::Expn
+        )
     }
 }
 
 pub type Batch = Vec<Result<Command<Verb, UnparsedArgs>, Error>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rcpt_to(args: &str) -> Result<RcptToArgs, ParseArgsError> {
+        RcptToArgs::try_from(UnparsedArgs(format!("{args}\r\n").into_bytes()))
+    }
+
+    fn mail_from(args: &str) -> Result<MailFromArgs, ParseArgsError> {
+        MailFromArgs::try_from(UnparsedArgs(format!("{args}\r\n").into_bytes()))
+    }
+
+    #[rstest::rstest]
+    #[case("user+2Bext@example.com", "user+ext@example.com")]
+    #[case("user+3Dext@example.com", "user=ext@example.com")]
+    #[case("user+2Bext+40example.com", "user+ext@example.com")]
+    #[case("j+C3+A9r+C3+B4me@example.com", "jérôme@example.com")]
+    #[case("", "")]
+    fn decode(#[case] xtext: &str, #[case] expected: &str) {
+        assert_eq!(decode_xtext(xtext.as_bytes()).unwrap(), expected);
+    }
+
+    #[rstest::rstest]
+    #[case("user+ext=40example.com")]
+    #[case("user+2bext@example.com")]
+    #[case("user+4")]
+    #[case("user name@example.com")]
+    #[case("user+C3@example.com")]
+    fn decode_invalid(#[case] xtext: &str) {
+        decode_xtext(xtext.as_bytes()).unwrap_err();
+    }
+
+    #[rstest::rstest]
+    #[case("user+ext@example.com", "user+2Bext@example.com")]
+    #[case("user=ext@example.com", "user+3Dext@example.com")]
+    #[case("jérôme@example.com", "j+C3+A9r+C3+B4me@example.com")]
+    fn encode(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(encode_xtext(value), expected);
+        assert_eq!(decode_xtext(expected.as_bytes()).unwrap(), value);
+    }
+
+    #[rstest::rstest]
+    #[case("rfc822;user+2Bext@example.com", "user+ext@example.com")]
+    #[case("rfc822;user+2Bext+40example.com", "user+ext@example.com")]
+    #[case("rfc822;user+3Dext@example.com", "user=ext@example.com")]
+    fn orcpt_round_trip(#[case] orcpt: &str, #[case] mailbox: &str) {
+        let args = rcpt_to(&format!("<jenny@example.com> ORCPT={orcpt} NOTIFY=NEVER")).unwrap();
+        let original = args.original_forward_path.unwrap();
+
+        assert_eq!(original.addr_type, "rfc822");
+        assert_eq!(original.mailbox.to_string(), mailbox);
+        assert_eq!(original.to_string(), orcpt);
+        assert_eq!(args.notify_on, NotifyOn::Never);
+    }
+
+    #[test]
+    fn orcpt_without_xtext() {
+        let original = OriginalRecipient {
+            addr_type: "rfc822".to_owned(),
+            mailbox: "user+ext@example.com".parse().unwrap(),
+            xtext: None,
+        };
+        assert_eq!(original.to_string(), "rfc822;user+2Bext@example.com");
+    }
+
+    #[rstest::rstest]
+    #[case(" jenny@example.com\r\n", Some("jenny@example.com"))]
+    #[case(" Jenny Doe  \r\n", Some("Jenny Doe"))]
+    #[case("\r\n", None)]
+    #[case("  \r\n", None)]
+    #[case("X jenny\r\n", None)]
+    fn vrfy_query(#[case] args: &str, #[case] expected: Option<&str>) {
+        let args = VrfyArgs::try_from(UnparsedArgs(args.as_bytes().to_vec()));
+        assert_eq!(args.ok().map(|args| args.query).as_deref(), expected);
+    }
+
+    #[rstest::rstest]
+    #[case("rfc822;user+ext=40example.com", "user+ext@example.com")]
+    #[case("rfc822;user+2bext@example.com", "user+2bext@example.com")]
+    fn orcpt_malformed_xtext(#[case] orcpt: &str, #[case] mailbox: &str) {
+        let args = rcpt_to(&format!("<jenny@example.com> ORCPT={orcpt}")).unwrap();
+        let original = args.original_forward_path.unwrap();
+
+        assert_eq!(original.mailbox.to_string(), mailbox);
+        assert_eq!(original.to_string(), orcpt);
+    }
+
+    #[rstest::rstest]
+    #[case("<jenny@example.com> ORCPT=user+2Bext@example.com")]
+    #[case("<jenny@example.com> ORCPT=rfc822;user+2Bext")]
+    fn orcpt_invalid(#[case] args: &str) {
+        assert!(rcpt_to(args).is_err());
+    }
+
+    #[rstest::rstest]
+    #[case("<john@example.com>", None)]
+    #[case("<john@example.com> BODY=7BIT", Some(MimeBodyType::SevenBit))]
+    #[case("<john@example.com> BODY=8BITMIME", Some(MimeBodyType::EightBitMime))]
+    #[case("<john@example.com> body=8bitmime", Some(MimeBodyType::EightBitMime))]
+    #[case(
+        "<john@example.com> BODY=8BITMIME SIZE=1000",
+        Some(MimeBodyType::EightBitMime)
+    )]
+    fn mail_from_body(#[case] args: &str, #[case] expected: Option<MimeBodyType>) {
+        assert_eq!(mail_from(args).unwrap().mime_body_type, expected);
+    }
+
+    #[rstest::rstest]
+    #[case("<john@example.com> BODY=BINARYMIME")]
+    #[case("<john@example.com> BODY=8BITMIMEX")]
+    #[case("<john@example.com> BODY=7BIT BODY=8BITMIME")]
+    fn mail_from_body_invalid(#[case] args: &str) {
+        assert!(mail_from(args).is_err());
+    }
+
+    #[rstest::rstest]
+    #[case("<john@example.com> XFOO=bar", &["XFOO=bar"])]
+    #[case("<john@example.com> SIZE=1000 X-OPTION", &["X-OPTION"])]
+    #[case("<john@example.com> XFOO=bar SMTPUTF8 XBAZ", &["XFOO=bar", "XBAZ"])]
+    fn mail_from_unknown_parameters(#[case] args: &str, #[case] expected: &[&str]) {
+        assert_eq!(mail_from(args).unwrap().unknown_parameters, expected);
+    }
+
+    #[rstest::rstest]
+    #[case("<jenny@example.com> XFOO=bar", &["XFOO=bar"])]
+    #[case("<jenny@example.com> NOTIFY=NEVER X-OPTION", &["X-OPTION"])]
+    fn rcpt_to_unknown_parameters(#[case] args: &str, #[case] expected: &[&str]) {
+        assert_eq!(rcpt_to(args).unwrap().unknown_parameters, expected);
+    }
+
+    #[rstest::rstest]
+    #[case("<john@example.com>", "")]
+    #[case("<john@example.com> SIZE=1000  XFOO=bar ", "SIZE=1000  XFOO=bar")]
+    #[case("<> BODY=8BITMIME", "BODY=8BITMIME")]
+    fn mail_from_raw_parameters(#[case] args: &str, #[case] expected: &str) {
+        assert_eq!(mail_from(args).unwrap().raw_parameters, expected);
+    }
+
+    #[rstest::rstest]
+    #[case("<jenny@example.com>", "")]
+    #[case(
+        "<jenny@example.com> NOTIFY=NEVER ORCPT=rfc822;jenny@example.com",
+        "NOTIFY=NEVER ORCPT=rfc822;jenny@example.com"
+    )]
+    fn rcpt_to_raw_parameters(#[case] args: &str, #[case] expected: &str) {
+        assert_eq!(rcpt_to(args).unwrap().raw_parameters, expected);
+    }
+
+    #[rstest::rstest]
+    #[case(r#"<"john doe"@example.com> SIZE=1000"#, "SIZE=1000")]
+    #[case(r#"<"john>\"doe"@example.com> XFOO=bar"#, "XFOO=bar")]
+    #[case("<john@example.com>SIZE=1000", "SIZE=1000")]
+    #[case("john@example.com SIZE=1000", "SIZE=1000")]
+    #[case("<john@example.com", "")]
+    fn raw_parameters_after_the_path(#[case] args: &str, #[case] expected: &str) {
+        assert_eq!(raw_parameters(args.as_bytes()).unwrap(), expected);
+    }
+
+    #[rstest::rstest]
+    #[case("<jenny@example.com> XFOO=")]
+    #[case("<jenny@example.com> XFOO=a=b")]
+    #[case("<jenny@example.com> -XFOO")]
+    #[case("<jenny@example.com> X_FOO=bar")]
+    fn unknown_parameters_invalid(#[case] args: &str) {
+        assert!(rcpt_to(args).is_err());
+        assert!(mail_from(args).is_err());
+    }
+}
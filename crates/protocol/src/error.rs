@@ -188,6 +188,22 @@ impl Error {
         .into()
     }
 
+    pub(crate) fn header_too_long(expected: usize, got: usize) -> Self {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            ParseArgsError::HeaderTooLong { expected, got },
+        )
+        .into()
+    }
+
+    pub(crate) fn line_too_long(expected: usize, got: usize) -> Self {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            ParseArgsError::LineTooLong { expected, got },
+        )
+        .into()
+    }
+
     pub(crate) fn no_crlf() -> Self {
         std::io::Error::new(std::io::ErrorKind::InvalidData, "No CRLF found".to_owned()).into()
     }
@@ -262,6 +278,22 @@ pub enum ParseArgsError {
         /// actual size of the buffer we got
         got: usize,
     },
+    /// The header section of a message is longer than the size limit of the message.
+    #[error("header section is not supposed to be longer than {expected} bytes but got {got}")]
+    HeaderTooLong {
+        /// message size limit
+        expected: usize,
+        /// size of the header section received before the limit was reached
+        got: usize,
+    },
+    /// The line is longer than the limit, including the "\r\n".
+    #[error("line is not supposed to be longer than {expected} bytes but got {got}")]
+    LineTooLong {
+        /// line length limit
+        expected: usize,
+        /// actual length of the line we got
+        got: usize,
+    },
     /// mail address is invalid (for rcpt, mail from ...)
     #[error("")]
     InvalidMailAddress {
@@ -25,15 +25,20 @@ mod stage;
 pub use stage::Stage;
 
 pub mod auth {
+    mod cram_md5;
     mod credentials;
     mod mechanism;
+    mod scram;
 
+    pub use cram_md5::CramMd5Challenge;
     pub use credentials::{Credentials, Error};
     pub use mechanism::Mechanism;
+    pub use scram::{ScramVerifier, ScramVerifierError};
 }
 
 mod types {
     pub mod address;
+    pub mod canonical;
     pub mod client_name;
     pub mod domain;
     pub mod reply;
@@ -41,19 +46,25 @@ mod types {
 }
 
 pub use command::{
-    AcceptArgs, AuthArgs, DsnReturn, EhloArgs, HeloArgs, MailFromArgs, NotifyOn, OriginalRecipient,
-    RcptToArgs, UnparsedArgs, Verb,
+    decode_xtext, encode_xtext, AcceptArgs, AuthArgs, DsnReturn, EhloArgs, ExpnArgs, HeloArgs,
+    MailFromArgs, MimeBodyType, NotifyOn, OriginalRecipient, RcptToArgs, UnparsedArgs, Verb,
+    VrfyArgs,
 };
 pub use connection_kind::ConnectionKind;
 pub use error::{Error, ErrorKind, ParseArgsError};
-pub use reader::Reader;
-pub use receiver::{Receiver, ReceiverContext};
+pub use reader::{LineLengthLimit, Reader};
+pub use receiver::{HandshakePermit, Receiver, ReceiverContext};
 pub use receiver_handler::ReceiverHandler;
 pub use rsasl;
 pub use smtp_sasl::{AuthError, CallbackWrap};
 pub use tokio_rustls;
 pub use tokio_rustls::rustls;
 pub use types::{
-    address::Address, client_name::ClientName, domain::Domain, reply::Reply, reply_code::ReplyCode,
+    address::Address,
+    canonical::{Canonicalization, LocalPartRules},
+    client_name::ClientName,
+    domain::Domain,
+    reply::Reply,
+    reply_code::ReplyCode,
 };
 pub use writer::Writer;
@@ -11,7 +11,7 @@
 
 use crate::{
     command::{Batch, Command},
-    Error, Reply, UnparsedArgs, Verb,
+    Error, ParseArgsError, Reply, UnparsedArgs, Verb,
 };
 use tokio::io::AsyncReadExt;
 use tokio_stream::StreamExt;
@@ -28,13 +28,40 @@ fn find(bytes: &[u8], search: &[u8]) -> Option<usize> {
         .position(|window| window == search)
 }
 
-#[allow(clippy::expect_used)]
-fn parse_command_line(line: &Vec<u8>) -> Result<Command<Verb, UnparsedArgs>, Error> {
-    // TODO: put max len as a parameter
+/// Drop the first `n` bytes of the buffer, which do not contain any "\r\n",
+/// except a trailing '\r' that could be the beginning of the delimiter.
+/// Return the number of bytes dropped.
+fn discard_partial_line(buffer: &mut bytes::BytesMut, n: usize) -> usize {
+    let to_discard = if buffer[..n].last() == Some(&b'\r') {
+        n - 1
+    } else {
+        n
+    };
+    let _ = buffer.split_to(to_discard);
+    to_discard
+}
 
-    if line.len() >= MAX_LINE_SIZE {
-        return Err(Error::buffer_too_long(MAX_LINE_SIZE, line.len()));
+/// Maximum length of the lines received from the client, including the "\r\n".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineLengthLimit {
+    /// Maximum length of a command line. (RFC 5321 4.5.3.1.4)
+    pub command: usize,
+    /// Maximum length of a text line of the message. (RFC 5321 4.5.3.1.6)
+    pub text: usize,
+}
+
+impl Default for LineLengthLimit {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            command: 512,
+            text: 1000,
+        }
     }
+}
+
+#[allow(clippy::expect_used)]
+fn parse_command_line(line: &Vec<u8>) -> Result<Command<Verb, UnparsedArgs>, Error> {
     if find(line, b"\r\n").is_none() {
         return Err(Error::no_crlf());
     }
@@ -58,6 +85,7 @@ struct ReaderWindow<'win, R: tokio::io::AsyncRead + Unpin + Send> {
     inner: &'win mut R,
     buffer: &'win mut bytes::BytesMut,
     additional_reserve: usize,
+    max_line_length: usize,
     n: usize,
 }
 
@@ -66,6 +94,9 @@ where
     R: tokio::io::AsyncRead + Unpin + Send,
 {
     /// return the full read tcp window (~= buffer)
+    ///
+    /// A line longer than `max_line_length` is discarded while being read,
+    /// and the stream stops with a [`ParseArgsError::LineTooLong`] error.
     fn flush_window(
         &'win mut self,
     ) -> impl tokio_stream::Stream<Item = std::io::Result<Vec<u8>>> + 'win {
@@ -73,15 +104,30 @@ where
             if !self.buffer.is_empty() {
                 self.n = self.buffer.len();
             }
+            let mut discarded = 0;
             loop {
                 if let Some(pos) = find(&self.buffer[..self.n], b"\r\n") {
                     let out = self.buffer.split_to(pos + 2);
                     self.n -= out.len();
+                    let line_length = discarded + out.len();
+                    if line_length > self.max_line_length {
+                        Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            ParseArgsError::LineTooLong {
+                                expected: self.max_line_length,
+                                got: line_length,
+                            },
+                        ))?;
+                    }
                     yield Vec::<u8>::from(out);
                     if self.buffer.is_empty() {
                         return;
                     }
                 } else {
+                    if self.n > self.max_line_length {
+                        discarded += discard_partial_line(self.buffer, self.n);
+                        self.n = self.buffer.len();
+                    }
                     self.buffer.reserve(self.additional_reserve);
                     let read_size = self.inner.read_buf(self.buffer).await?;
                     if read_size == 0 {
@@ -100,6 +146,7 @@ pub struct Reader<R: tokio::io::AsyncRead + Unpin + Send> {
     additional_reserve: usize,
     buffer: bytes::BytesMut,
     pipelining_enabled: bool,
+    line_length_limit: LineLengthLimit,
 }
 
 impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
@@ -112,9 +159,18 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
             additional_reserve: 100,
             buffer: bytes::BytesMut::with_capacity(80),
             pipelining_enabled: enable_pipelining,
+            line_length_limit: LineLengthLimit::default(),
         }
     }
 
+    /// Set the maximum length of the lines received, instead of the RFC 5321 defaults.
+    #[must_use]
+    #[inline]
+    pub const fn with_line_length_limit(mut self, line_length_limit: LineLengthLimit) -> Self {
+        self.line_length_limit = line_length_limit;
+        self
+    }
+
     /// Consume the instance and return the underlying reader.
     #[must_use]
     #[inline]
@@ -130,6 +186,7 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
             inner: &mut self.inner,
             buffer: &mut self.buffer,
             additional_reserve: self.additional_reserve,
+            max_line_length: self.line_length_limit.command,
             n: 0,
         }
     }
@@ -148,8 +205,12 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
 
                 let window_content = window_reader.flush_window();
                 tokio::pin!(window_content);
-                while let Some(cmd) = window_content.next().await {
-                    batch.push(parse_command_line(&cmd?));
+                while let Some(line) = window_content.next().await {
+                    batch.push(match line {
+                        // the overlong line has been discarded, the client can send the next one
+                        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => Err(e.into()),
+                        line => parse_command_line(&line?),
+                    });
                     if !pipelined {
                         break;
                     }
@@ -190,31 +251,109 @@ impl<R: tokio::io::AsyncRead + Unpin + Send> Reader<R> {
         }
     }
 
+    /// Produce a stream of "\r\n" terminated lines, without buffering more than
+    /// `max_line_length` bytes.
+    /// A line longer than the limit is discarded, and its length is produced instead.
+    fn as_bounded_line_stream(
+        &mut self,
+        max_line_length: usize,
+    ) -> impl tokio_stream::Stream<Item = std::io::Result<Result<Vec<u8>, usize>>> + '_ {
+        async_stream::try_stream! {
+            let mut n = self.buffer.len();
+            let mut discarded = 0;
+
+            loop {
+                if let Some(pos) = find(&self.buffer[..n], b"\r\n") {
+                    let out = self.buffer.split_to(pos + 2);
+                    n -= out.len();
+
+                    let line_length = discarded + out.len();
+                    discarded = 0;
+                    if line_length > max_line_length {
+                        yield Err(line_length);
+                    } else {
+                        yield Ok(Vec::<u8>::from(out));
+                    }
+                } else {
+                    if n > max_line_length {
+                        discarded += discard_partial_line(&mut self.buffer, n);
+                        n = self.buffer.len();
+                    }
+                    self.buffer.reserve(self.additional_reserve);
+                    let read_size = self.inner.read_buf(&mut self.buffer).await?;
+                    if read_size == 0 {
+                        return;
+                    }
+                    n += read_size;
+                }
+            }
+        }
+    }
+
     /// Produce a stream of lines to generate IMF compliant messages.
+    ///
+    /// A line longer than the text line limit is discarded without being buffered,
+    /// and a [`ParseArgsError::LineTooLong`] error is produced once the end of
+    /// the message has been received.
+    ///
+    /// A message larger than `size_limit` produces a [`ParseArgsError::HeaderTooLong`]
+    /// error if the limit is reached in the header section, and a
+    /// [`ParseArgsError::BufferTooLong`] error if it is reached in the body.
+    /// The rest of the message is discarded and the error produced once its end has been
+    /// received, so that the session can go on with the next command.
     #[inline]
     pub fn as_message_stream(
         &mut self,
         size_limit: usize,
     ) -> impl tokio_stream::Stream<Item = Result<Vec<u8>, Error>> + '_ {
+        let max_line_length = self.line_length_limit.text;
         async_stream::stream! {
-            let mut size = 0;
-
-            for await line in self.as_line_stream() {
-                let mut line = line?;
+            let mut header_size = 0;
+            let mut body_size = 0;
+            let mut in_header = true;
+            let mut line_too_long = None;
+            let mut too_long = None;
+
+            for await line in self.as_bounded_line_stream(max_line_length) {
+                let mut line = match line? {
+                    Ok(line) => line,
+                    Err(line_length) => {
+                        tracing::trace!("<< line of {line_length} bytes discarded");
+                        line_too_long.get_or_insert(line_length);
+                        continue;
+                    }
+                };
                 tracing::trace!("<< {:?}", std::str::from_utf8(&line));
 
                 if line == b".\r\n" {
+                    if let Some(error) = too_long {
+                        yield Err(error);
+                    } else if let Some(line_length) = line_too_long {
+                        yield Err(Error::line_too_long(max_line_length, line_length));
+                    }
                     return;
                 }
+                if line_too_long.is_some() || too_long.is_some() {
+                    continue;
+                }
                 if line.first() == Some(&b'.') {
                     line = line[1..].to_vec();
                 }
 
-                // TODO: handle line length max ?
-                size += line.len();
-                if size >= size_limit {
-                    yield Err(Error::buffer_too_long(size_limit, size));
-                    return;
+                if in_header {
+                    header_size += line.len();
+                    // the empty line separating the header section from the body.
+                    in_header = line != b"\r\n";
+                } else {
+                    body_size += line.len();
+                }
+                if header_size + body_size >= size_limit {
+                    too_long = Some(if in_header {
+                        Error::header_too_long(size_limit, header_size)
+                    } else {
+                        Error::buffer_too_long(size_limit, header_size + body_size)
+                    });
+                    continue;
                 }
 
                 yield Ok(line);
@@ -420,6 +559,165 @@ mod tests {
         assert_cmd_batch(&output, &expected);
     }
 
+    fn is_line_too_long(error: &Error, expected_limit: usize, expected_length: usize) -> bool {
+        matches!(
+            error
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<crate::ParseArgsError>()),
+            Some(crate::ParseArgsError::LineTooLong { expected, got })
+                if *expected == expected_limit && *got == expected_length
+        )
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn window_stream_command_line_too_long() {
+        let input = [
+            &format!("MAIL FROM:<{}@example.com>\r\n", "a".repeat(600)),
+            "RCPT TO:<ned@innosoft.com>\r\n",
+        ]
+        .concat();
+
+        let cursor = std::io::Cursor::new(input);
+        let mut reader = super::Reader::new(cursor, true);
+        let stream = reader
+            .as_window_stream()
+            .timeout(std::time::Duration::from_secs(30));
+        tokio::pin!(stream);
+
+        let output = stream.try_next().await.unwrap().unwrap().unwrap();
+        assert_eq!(output.len(), 1);
+        assert!(is_line_too_long(
+            output[0].as_ref().unwrap_err(),
+            512,
+            "MAIL FROM:<@example.com>\r\n".len() + 600
+        ));
+
+        let output = stream.try_next().await.unwrap().unwrap().unwrap();
+        let expected = vec![std::result::Result::<
+            (command::Verb, command::UnparsedArgs),
+            Error,
+        >::Ok((
+            command::Verb::RcptTo,
+            command::UnparsedArgs(b"<ned@innosoft.com>\r\n".to_vec()),
+        ))];
+        assert_cmd_batch(&output, &expected);
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn message_stream_body_line_too_long() {
+        let input = [
+            "Subject: test\r\n",
+            "\r\n",
+            &"a".repeat(1200),
+            "\r\n",
+            "end of the body\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat();
+
+        let cursor = std::io::Cursor::new(input);
+        let mut reader = super::Reader::new(cursor, true);
+
+        let output = reader
+            .as_message_stream(1_000_000)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(output.len(), 3);
+        assert_eq!(output[0].as_ref().unwrap(), b"Subject: test\r\n");
+        assert_eq!(output[1].as_ref().unwrap(), b"\r\n");
+        assert!(is_line_too_long(
+            output[2].as_ref().unwrap_err(),
+            1000,
+            1202
+        ));
+
+        // the rest of the message has been consumed, the next command can be read.
+        let stream = reader
+            .as_window_stream()
+            .timeout(std::time::Duration::from_secs(30));
+        tokio::pin!(stream);
+        let output = stream.try_next().await.unwrap().unwrap().unwrap();
+        let expected = vec![std::result::Result::<
+            (command::Verb, command::UnparsedArgs),
+            Error,
+        >::Ok((
+            command::Verb::Quit,
+            command::UnparsedArgs(b"\r\n".to_vec()),
+        ))];
+        assert_cmd_batch(&output, &expected);
+    }
+
+    fn is_too_long(error: &Error) -> Option<&crate::ParseArgsError> {
+        error
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<crate::ParseArgsError>())
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn message_stream_header_too_long() {
+        let input = [
+            "Subject: test\r\n",
+            &"X-Filler: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n".repeat(10),
+            "\r\n",
+            "body\r\n",
+            ".\r\n",
+        ]
+        .concat();
+
+        let cursor = std::io::Cursor::new(input);
+        let mut reader = super::Reader::new(cursor, true);
+
+        let output = reader.as_message_stream(200).collect::<Vec<_>>().await;
+        assert!(matches!(
+            is_too_long(output.last().unwrap().as_ref().unwrap_err()),
+            Some(crate::ParseArgsError::HeaderTooLong { expected: 200, got })
+                if *got >= 200
+        ));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn message_stream_body_too_long() {
+        let input = [
+            "Subject: test\r\n",
+            "\r\n",
+            &"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n".repeat(10),
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat();
+
+        let cursor = std::io::Cursor::new(input);
+        let mut reader = super::Reader::new(cursor, true);
+
+        let output = reader.as_message_stream(200).collect::<Vec<_>>().await;
+        assert_eq!(output[0].as_ref().unwrap(), b"Subject: test\r\n");
+        assert!(matches!(
+            is_too_long(output.last().unwrap().as_ref().unwrap_err()),
+            Some(crate::ParseArgsError::BufferTooLong { expected: 200, got })
+                if *got >= 200
+        ));
+
+        // the rest of the message has been consumed, the next command can be read.
+        let stream = reader
+            .as_window_stream()
+            .timeout(std::time::Duration::from_secs(30));
+        tokio::pin!(stream);
+        let output = stream.try_next().await.unwrap().unwrap().unwrap();
+        let expected = vec![std::result::Result::<
+            (command::Verb, command::UnparsedArgs),
+            Error,
+        >::Ok((
+            command::Verb::Quit,
+            command::UnparsedArgs(b"\r\n".to_vec()),
+        ))];
+        assert_cmd_batch(&output, &expected);
+    }
+
     #[allow(clippy::unwrap_used)]
     #[tokio::test]
     async fn window_stream_no_lines() {
@@ -10,8 +10,11 @@
  */
 
 use crate::{
-    auth::Mechanism, reader::Reader, writer::WindowWriter, AcceptArgs, AuthArgs, ConnectionKind,
-    EhloArgs, Error, HeloArgs, MailFromArgs, RcptToArgs, ReceiverHandler, Reply, Stage, Verb,
+    auth::Mechanism,
+    reader::{LineLengthLimit, Reader},
+    writer::WindowWriter,
+    AcceptArgs, AuthArgs, ConnectionKind, EhloArgs, Error, ExpnArgs, HeloArgs, MailFromArgs,
+    RcptToArgs, ReceiverHandler, Reply, Stage, Verb, VrfyArgs,
 };
 use tokio_rustls::rustls;
 use tokio_stream::StreamExt;
@@ -21,6 +24,7 @@ enum HandshakeOutcome {
     UpgradeTLS {
         config: alloc::sync::Arc<rustls::ServerConfig>,
         handshake_timeout: std::time::Duration,
+        permit: Option<HandshakePermit>,
     },
     Authenticate {
         mechanism: Mechanism,
@@ -35,6 +39,15 @@ pub struct ErrorCounter {
     pub threshold_hard_error: i64,
 }
 
+/// Permit of a TLS handshake, held until the handshake is over
+/// to limit the handshakes running at the same time.
+pub enum HandshakePermit {
+    /// A permit acquired by the handler.
+    Acquired(tokio::sync::OwnedSemaphorePermit),
+    /// A permit to wait for, within the timeout of the handshake.
+    Wait(alloc::sync::Arc<tokio::sync::Semaphore>),
+}
+
 /// An handle to send event from the [`ReceiverHandler`] to the [`Receiver`].
 #[allow(clippy::module_name_repetitions)]
 #[derive(Default)]
@@ -49,16 +62,27 @@ impl ReceiverContext {
         self.outcome = Some(HandshakeOutcome::Quit);
     }
 
+    /// Has the connection been denied by the handler ?
+    #[inline]
+    #[must_use]
+    pub const fn is_denied(&self) -> bool {
+        matches!(self.outcome, Some(HandshakeOutcome::Quit))
+    }
+
     /// Make the [`Receiver`] initialize a TLS handshake.
+    ///
+    /// If `permit` is set, it is held until the handshake is over.
     #[inline]
     pub fn upgrade_tls(
         &mut self,
         config: alloc::sync::Arc<rustls::ServerConfig>,
         handshake_timeout: std::time::Duration,
+        permit: Option<HandshakePermit>,
     ) {
         self.outcome = Some(HandshakeOutcome::UpgradeTLS {
             config,
             handshake_timeout,
+            permit,
         });
     }
 
@@ -84,33 +108,42 @@ pub struct Receiver<
     pub(crate) sink: WindowWriter<W>,
     pub(crate) stream: Reader<R>,
     error_counter: ErrorCounter,
+    // number of commands received during the session, the connection is closed
+    // once `max_commands` is exceeded (-1 to disable).
+    command_count: i64,
+    max_commands: i64,
     context: ReceiverContext,
     kind: ConnectionKind,
     message_size_max: usize,
     support_pipelining: bool,
+    line_length_limit: LineLengthLimit,
     v: std::marker::PhantomData<V>,
     h: std::marker::PhantomData<H>,
 }
 
-impl<H, V> Receiver<H, V, tokio::net::tcp::OwnedWriteHalf, tokio::net::tcp::OwnedReadHalf>
+impl<H, V, S> Receiver<H, V, tokio::io::WriteHalf<S>, tokio::io::ReadHalf<S>>
 where
     H: ReceiverHandler + Send,
     V: rsasl::validate::Validation + Send,
     V::Value: Send + Sync,
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
 {
-    /// Create a new [`Receiver`] from a TCP/IP stream.
+    /// Create a new [`Receiver`] from a stream, usually a TCP/IP connection.
     #[inline]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        tcp_stream: tokio::net::TcpStream,
+        stream: S,
         kind: ConnectionKind,
         threshold_soft_error: i64,
         threshold_hard_error: i64,
+        max_commands: i64,
         message_size_max: usize,
         support_pipelining: bool,
+        line_length_limit: LineLengthLimit,
     ) -> Self {
-        let (read, write) = tcp_stream.into_split();
+        let (read, write) = tokio::io::split(stream);
         let (stream, sink) = (
-            Reader::new(read, support_pipelining),
+            Reader::new(read, support_pipelining).with_line_length_limit(line_length_limit),
             WindowWriter::new(write),
         );
         Self {
@@ -121,10 +154,13 @@ where
                 threshold_soft_error,
                 threshold_hard_error,
             },
+            command_count: 0,
+            max_commands,
             context: ReceiverContext { outcome: None },
             kind,
             message_size_max,
             support_pipelining,
+            line_length_limit,
             v: std::marker::PhantomData,
             h: std::marker::PhantomData,
         }
@@ -135,21 +171,30 @@ where
         handler: H,
         config: alloc::sync::Arc<rustls::ServerConfig>,
         handshake_timeout: std::time::Duration,
+        permit: Option<HandshakePermit>,
     ) -> impl tokio_stream::Stream<Item = Result<(), Error>> {
         async_stream::stream! {
-            #[allow(clippy::expect_used)]
-            let tcp_stream = self
-                .sink
-                .into_inner()
-                .reunite(self.stream.into_inner())
-                .expect("valid stream/sink pair");
+            let stream = self.stream.into_inner().unsplit(self.sink.into_inner());
 
             let acceptor = tokio_rustls::TlsAcceptor::from(config);
 
-            let tls_tcp_stream = match tokio::time::timeout(
-                handshake_timeout,
-                acceptor.accept(tcp_stream),
-            ).await {
+            // the time waiting for a permit counts in the timeout of the handshake.
+            let handshake = async {
+                // the semaphore is never closed.
+                #[allow(clippy::expect_used)]
+                let permit = match permit {
+                    Some(HandshakePermit::Acquired(permit)) => Some(permit),
+                    Some(HandshakePermit::Wait(handshakes)) => Some(
+                        handshakes.acquire_owned().await.expect("handshake semaphore closed"),
+                    ),
+                    None => None,
+                };
+                let tls_tcp_stream = acceptor.accept(stream).await;
+                drop(permit);
+                tls_tcp_stream
+            };
+
+            let tls_tcp_stream = match tokio::time::timeout(handshake_timeout, handshake).await {
                 Ok(Ok(tls_tcp_stream)) => tls_tcp_stream,
                 Ok(Err(e)) => {
                     Err(e)?;
@@ -178,16 +223,22 @@ where
             // FIXME: see https://github.com/tokio-rs/tls/issues/40
             let (read, write) = tokio::io::split(tls_tcp_stream);
 
-            let (stream, sink) = (Reader::new(read, self.support_pipelining), WindowWriter::new(write));
+            let (stream, sink) = (
+                Reader::new(read, self.support_pipelining).with_line_length_limit(self.line_length_limit),
+                WindowWriter::new(write)
+            );
 
             let secured_receiver = Receiver {
                 sink,
                 stream,
                 context: ReceiverContext { outcome: None },
                 error_counter: self.error_counter,
+                command_count: self.command_count,
+                max_commands: self.max_commands,
                 kind: self.kind,
                 message_size_max: self.message_size_max,
                 support_pipelining: self.support_pipelining,
+                line_length_limit: self.line_length_limit,
                 v: self.v,
                 h: self.h,
             }.into_secured_stream(
@@ -267,10 +318,11 @@ where
                 (handler, ReceiverContext{
                     outcome: Some(HandshakeOutcome::UpgradeTLS {
                         config,
-                        handshake_timeout
+                        handshake_timeout,
+                        permit,
                     }),
                 }, None) => {
-                    for await i in self.upgrade_tls(handler, config, handshake_timeout) {
+                    for await i in self.upgrade_tls(handler, config, handshake_timeout, permit) {
                         yield i?;
                     }
                     return;
@@ -307,8 +359,8 @@ where
 
                         yield ();
                     },
-                    HandshakeOutcome::UpgradeTLS { config, handshake_timeout } => {
-                        for await i in self.upgrade_tls(handler, config, handshake_timeout) {
+                    HandshakeOutcome::UpgradeTLS { config, handshake_timeout, permit } => {
+                        for await i in self.upgrade_tls(handler, config, handshake_timeout, permit) {
                             yield i?;
                         }
                         return;
@@ -354,6 +406,7 @@ where
     ) -> impl tokio_stream::Stream<Item = Result<(), Error>> {
         async_stream::try_stream! {
             let reply_post_tls_handshake = handler.on_post_tls_handshake(
+                &mut self.context,
                 sni,
                 protocol_version,
                 negotiated_cipher_suite.suite(),
@@ -361,7 +414,8 @@ where
                 alpn_protocol
             ).await;
 
-            if self.kind == ConnectionKind::Tunneled {
+            let denied = self.context.is_denied();
+            if self.kind == ConnectionKind::Tunneled || denied {
                 self.sink.direct_send_reply(
                     &mut self.context,
                     &mut self.error_counter,
@@ -369,6 +423,9 @@ where
                     reply_post_tls_handshake
                 ).await?;
             }
+            if denied {
+                return;
+            }
 
             loop {
                 match self.smtp_handshake(&mut handler).await? {
@@ -462,6 +519,23 @@ where
                 _ => return Ok(HandshakeOutcome::Quit),
             };
             for command in commands_batch {
+                self.command_count += 1;
+                if self.max_commands != -1 && self.command_count > self.max_commands {
+                    tracing::warn!(
+                        max_commands = self.max_commands,
+                        "Too many commands received, closing connection"
+                    );
+                    if !self.sink.is_empty() {
+                        self.sink.flush().await?;
+                    }
+                    self.sink
+                        .write_all("421 4.7.0 Too many commands - closing connection\r\n")
+                        .await?;
+
+                    return Ok(HandshakeOutcome::Quit);
+                }
+                handler.on_command();
+
                 let (verb, args) = match command {
                     Ok(command) => command,
                     Err(e) => {
@@ -514,6 +588,8 @@ where
                         Some(handler.on_quit().await)
                     }
                     (Verb::Help, _) => Some(handler.on_help(args).await),
+                    (Verb::Vrfy, _) => Some(handle_args!(VrfyArgs, args, on_vrfy)),
+                    (Verb::Expn, _) => Some(handle_args!(ExpnArgs, args, on_expn)),
                     (Verb::Unknown, _) => Some(handler.on_unknown(args.0).await),
                     otherwise => Some(handler.on_bad_sequence(otherwise).await),
                 };
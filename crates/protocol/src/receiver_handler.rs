@@ -10,8 +10,9 @@
  */
 
 use crate::{
-    receiver::ReceiverContext, smtp_sasl::CallbackWrap, AuthArgs, AuthError, EhloArgs, Error,
-    HeloArgs, MailFromArgs, ParseArgsError, RcptToArgs, Reply, Stage, UnparsedArgs, Verb,
+    auth::CramMd5Challenge, receiver::ReceiverContext, smtp_sasl::CallbackWrap, AuthArgs,
+    AuthError, EhloArgs, Error, ExpnArgs, HeloArgs, MailFromArgs, ParseArgsError, RcptToArgs,
+    Reply, Stage, UnparsedArgs, Verb, VrfyArgs,
 };
 use tokio_rustls::rustls;
 
@@ -35,12 +36,18 @@ pub trait ReceiverHandler {
     /// Create an instance capable to handle the SASL handshake.
     fn generate_sasl_callback(&self) -> CallbackWrap;
 
+    /// Called for each command received, before it is handled.
+    fn on_command(&mut self) {}
+
     /// Called after receiving a [`Verb::StartTls`] command.
     async fn on_starttls(&mut self, ctx: &mut ReceiverContext) -> Reply;
 
     /// Called after a successful TLS handshake.
+    ///
+    /// If the handler denies the connection, the reply is sent and the connection closed.
     async fn on_post_tls_handshake(
         &mut self,
+        ctx: &mut ReceiverContext,
         sni: Option<String>,
         protocol_version: rustls::ProtocolVersion,
         cipher_suite: rustls::CipherSuite,
@@ -51,6 +58,16 @@ pub trait ReceiverHandler {
     /// Called after receiving a [`Verb::Auth`] command.
     async fn on_auth(&mut self, ctx: &mut ReceiverContext, args: AuthArgs) -> Option<Reply>;
 
+    /// Called to verify the response of a `CRAM-MD5` exchange, which is not handled by
+    /// the SASL backend. The handler should check the `digest` of `authid` using
+    /// [`CramMd5Challenge::verify`] with the secret of the user.
+    fn on_cram_md5(
+        &self,
+        challenge: &CramMd5Challenge,
+        authid: &str,
+        digest: &str,
+    ) -> Result<(), AuthError>;
+
     /// Called after a successful SASL handshake.
     async fn on_post_auth(
         &mut self,
@@ -118,10 +135,26 @@ pub trait ReceiverHandler {
         reply("214 https://viridit.com/support")
     }
 
+    /// Called after receiving a [`Verb::Vrfy`] command.
+    ///
+    /// By default the address is neither confirmed nor denied.
+    #[inline]
+    async fn on_vrfy(&mut self, _: &mut ReceiverContext, _: VrfyArgs) -> Reply {
+        reply("252 2.5.0 Cannot VRFY user, but will accept message and attempt delivery\r\n")
+    }
+
+    /// Called after receiving a [`Verb::Expn`] command.
+    ///
+    /// By default the command is disabled.
+    #[inline]
+    async fn on_expn(&mut self, _: &mut ReceiverContext, _: ExpnArgs) -> Reply {
+        reply("502 5.5.1 EXPN command disabled\r\n")
+    }
+
     /// Called after receiving an unknown command (unrecognized or unimplemented).
     #[inline]
     async fn on_unknown(&mut self, buffer: Vec<u8>) -> Reply {
-        let unimplemented_command = [b"VRFY".as_slice(), b"EXPN".as_slice(), b"TURN".as_slice()];
+        let unimplemented_command = [b"TURN".as_slice()];
 
         #[allow(clippy::expect_used)]
         if unimplemented_command.iter().any(|c| {
@@ -154,6 +187,7 @@ pub trait ReceiverHandler {
                 "553 5.1.7 The address <{mail}> is not a valid RFC-5321 address\r\n"
             )),
             ParseArgsError::EmailUnavailable => reply("550 mailbox unavailable\r\n"),
+            ParseArgsError::LineTooLong { .. } => reply("500 5.5.6 Line too long\r\n"),
             _other => reply("501 Syntax error in parameters or arguments\r\n"),
         }
     }
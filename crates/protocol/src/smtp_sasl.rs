@@ -9,7 +9,10 @@
  *
  */
 
-use crate::{auth::Mechanism, Receiver, ReceiverHandler};
+use crate::{
+    auth::{CramMd5Challenge, Mechanism},
+    Receiver, ReceiverHandler,
+};
 use base64::{engine::general_purpose::STANDARD, Engine};
 use tokio::io::AsyncWriteExt;
 use tokio_stream::StreamExt;
@@ -118,20 +121,6 @@ where
                 block_on! { tokio::io::AsyncWriteExt::flush(&mut self.0) }
             }
         }
-        let callback = handler.generate_sasl_callback();
-
-        let rsasl_config = rsasl::config::SASLConfig::builder()
-            .with_default_mechanisms()
-            .with_callback(callback)?;
-
-        let sasl_server = rsasl::prelude::SASLServer::<V>::new(rsasl_config);
-
-        let temp = mechanism.to_string();
-        #[allow(clippy::expect_used)]
-        let selected =
-            rsasl::prelude::Mechname::parse(temp.as_bytes()).expect("mechanism is valid");
-        let mut session = sasl_server.start_suggested(selected)?;
-
         let mut adapter = AdapterSMTPandSASL(self.sink.as_mut());
         let challenge_stream = self.stream.as_line_stream().map(|line| {
             let l = line.map(|buffer| {
@@ -167,6 +156,34 @@ where
             };
         }
 
+        if mechanism == Mechanism::CramMd5 {
+            if initial_response.is_some() {
+                return Err(AuthError::ClientMustNotStart);
+            }
+
+            let challenge = CramMd5Challenge::new();
+            std::io::Write::write(&mut adapter, challenge.as_ref().as_bytes())?;
+            let response = next_challenge_line!(challenge_stream).unwrap_or_default();
+            let (authid, digest) = CramMd5Challenge::parse_response(&response)
+                .map_err(|e| AuthError::ValidationError(Box::new(e)))?;
+
+            return handler.on_cram_md5(&challenge, &authid, &digest);
+        }
+
+        let callback = handler.generate_sasl_callback();
+
+        let rsasl_config = rsasl::config::SASLConfig::builder()
+            .with_default_mechanisms()
+            .with_callback(callback)?;
+
+        let sasl_server = rsasl::prelude::SASLServer::<V>::new(rsasl_config);
+
+        let temp = mechanism.to_string();
+        #[allow(clippy::expect_used)]
+        let selected =
+            rsasl::prelude::Mechname::parse(temp.as_bytes()).expect("mechanism is valid");
+        let mut session = sasl_server.start_suggested(selected)?;
+
         let mut data = match (initial_response, session.are_we_first()) {
             (None, true) => None,
             (None, false) => {
@@ -177,23 +194,40 @@ where
             (Some(data), false) => Some(STANDARD.decode(data)?),
         };
 
-        #[allow(clippy::wildcard_enum_match_arm)]
-        while session
-            .step(data.as_deref(), &mut adapter)
-            .map_err(|e| match e {
-                rsasl::prelude::SessionError::ValidationError(
-                    rsasl::validate::ValidationError::Boxed(e),
-                ) => AuthError::ValidationError(e),
-                otherwise => AuthError::SessionError(otherwise),
-            })?
-            .is_running()
-        {
-            data = next_challenge_line!(challenge_stream);
+        loop {
+            #[allow(clippy::wildcard_enum_match_arm)]
+            let state = session
+                .step(data.as_deref(), &mut adapter)
+                .map_err(|e| match e {
+                    rsasl::prelude::SessionError::ValidationError(
+                        rsasl::validate::ValidationError::Boxed(e),
+                    ) => AuthError::ValidationError(e),
+                    otherwise => AuthError::SessionError(otherwise),
+                })?;
+
+            match state {
+                rsasl::prelude::State::Running => {
+                    data = next_challenge_line!(challenge_stream);
+                }
+                // The `235` reply cannot carry data, the last message of the server (like the
+                // signature of SCRAM) is sent as a challenge, acknowledged with an empty response.
+                // See <https://datatracker.ietf.org/doc/html/rfc4954#section-4>
+                rsasl::prelude::State::Finished(rsasl::prelude::MessageSent::Yes) => {
+                    next_challenge_line!(challenge_stream);
+                    break;
+                }
+                rsasl::prelude::State::Finished(rsasl::prelude::MessageSent::No) => break,
+            }
         }
 
-        #[allow(clippy::todo)]
+        // The exchange can end without validation, for example when the proof
+        // of a challenge-response mechanism does not match the stored secret.
         session.validation().map_or_else(
-            || todo!("what happen when the validator return nothing ?"),
+            || {
+                Err(AuthError::ValidationError(
+                    "the exchange ended without validation".into(),
+                ))
+            },
             |_v| Ok(()),
         )
     }
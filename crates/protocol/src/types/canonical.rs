@@ -0,0 +1,224 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::Address;
+
+/// Rules applied to the local part of an address by [`Address::canonicalize`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(clippy::exhaustive_structs)]
+pub struct LocalPartRules {
+    /// Put the local part in lowercase.
+    #[serde(default)]
+    pub fold_case: bool,
+    /// Remove the sub-address starting at this separator, ex: `user+tag@` becomes `user@`.
+    #[serde(default)]
+    pub subaddress_separator: Option<char>,
+    /// Remove the dots of the local part, ex: `john.doe@` becomes `johndoe@`.
+    #[serde(default)]
+    pub remove_dots: bool,
+}
+
+impl LocalPartRules {
+    /// Rules of the Gmail addresses, where the case, the dots and the `+tag` are ignored.
+    #[inline]
+    #[must_use]
+    pub const fn gmail() -> Self {
+        Self {
+            fold_case: true,
+            subaddress_separator: Some('+'),
+            remove_dots: true,
+        }
+    }
+}
+
+/// Options of [`Address::canonicalize`], to compare the addresses of the same mailbox
+/// written differently (rate limits, deduplication, allow lists, ...).
+///
+/// The domain is always put in lowercase.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(clippy::exhaustive_structs)]
+pub struct Canonicalization {
+    /// Rules of the domains without a provider.
+    #[serde(default)]
+    pub default: LocalPartRules,
+    /// Rules of a provider, by domain, ex: `gmail.com`. The domains are put in lowercase.
+    #[serde(default, deserialize_with = "lowercase_keys")]
+    pub providers: std::collections::BTreeMap<String, LocalPartRules>,
+}
+
+fn lowercase_keys<'de, D>(
+    deserializer: D,
+) -> Result<std::collections::BTreeMap<String, LocalPartRules>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let providers =
+        <std::collections::BTreeMap<String, LocalPartRules> as serde::Deserialize>::deserialize(
+            deserializer,
+        )?;
+
+    Ok(providers
+        .into_iter()
+        .map(|(domain, rules)| (domain.to_lowercase(), rules))
+        .collect())
+}
+
+impl Canonicalization {
+    /// Add the rules of a provider for `domain`.
+    #[inline]
+    #[must_use]
+    pub fn with_provider(mut self, domain: &str, rules: LocalPartRules) -> Self {
+        self.providers.insert(domain.to_lowercase(), rules);
+        self
+    }
+}
+
+impl Address {
+    /// Get the canonical form of the address, following the rules of its domain.
+    ///
+    /// The sub-address and the dots are kept if removing them would leave an empty local part.
+    #[inline]
+    #[must_use]
+    pub fn canonicalize(&self, options: &Canonicalization) -> Self {
+        let local_part = self.local_part();
+        #[allow(clippy::indexing_slicing, clippy::string_slice)]
+        let domain = self.full()[local_part.len() + 1..].to_lowercase();
+        let rules = options.providers.get(&domain).unwrap_or(&options.default);
+
+        let mut local_part = rules
+            .subaddress_separator
+            .and_then(|separator| local_part.split_once(separator))
+            .filter(|(user, _)| !user.is_empty())
+            .map_or(local_part, |(user, _)| user)
+            .to_owned();
+        if rules.remove_dots {
+            let without_dots = local_part.replace('.', "");
+            // a quoted local part made only of dots, ex: `".."@`.
+            if !without_dots.trim_matches('"').is_empty() {
+                local_part = without_dots;
+            }
+        }
+        if rules.fold_case {
+            local_part = local_part.to_lowercase();
+        }
+
+        Self::new_unchecked(format!("{local_part}@{domain}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Canonicalization, LocalPartRules};
+    use crate::Address;
+
+    fn canonical(address: &str, options: &Canonicalization) -> String {
+        address
+            .parse::<Address>()
+            .unwrap()
+            .canonicalize(options)
+            .to_string()
+    }
+
+    #[test]
+    fn case_folding() {
+        let options = Canonicalization::default();
+        assert_eq!(
+            canonical("John.Doe@Example.COM", &options),
+            "John.Doe@example.com"
+        );
+
+        let options = Canonicalization {
+            default: LocalPartRules {
+                fold_case: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            canonical("John.Doe@Example.COM", &options),
+            "john.doe@example.com"
+        );
+    }
+
+    #[test]
+    fn plus_tag_stripping() {
+        let options = Canonicalization {
+            default: LocalPartRules {
+                subaddress_separator: Some('+'),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .with_provider("example.org", LocalPartRules::default());
+
+        assert_eq!(
+            canonical("user+tag@example.com", &options),
+            "user@example.com"
+        );
+        assert_eq!(
+            canonical("user+tag+more@example.com", &options),
+            "user@example.com"
+        );
+        assert_eq!(canonical("+tag@example.com", &options), "+tag@example.com");
+        assert_eq!(
+            canonical("user+tag@example.org", &options),
+            "user+tag@example.org"
+        );
+    }
+
+    #[test]
+    fn gmail_dot_removal() {
+        let options =
+            Canonicalization::default().with_provider("Gmail.com", LocalPartRules::gmail());
+
+        assert_eq!(
+            canonical("John.Doe+news@GMAIL.com", &options),
+            "johndoe@gmail.com"
+        );
+        assert_eq!(
+            canonical("john.doe+news@example.com", &options),
+            "john.doe+news@example.com"
+        );
+    }
+
+    #[test]
+    fn only_dots() {
+        let options = Canonicalization {
+            default: LocalPartRules::gmail(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            canonical("\"..\"@example.com", &options),
+            "\"..\"@example.com"
+        );
+        assert_eq!(
+            canonical("\"j..d\"@example.com", &options),
+            "\"jd\"@example.com"
+        );
+    }
+
+    #[test]
+    fn deserialize_providers_in_lowercase() {
+        let options = serde_json::from_str::<Canonicalization>(
+            r#"{ "providers": { "GMail.com": { "remove_dots": true } } }"#,
+        )
+        .unwrap();
+
+        assert_eq!(options.providers.keys().collect::<Vec<_>>(), ["gmail.com"]);
+        assert_eq!(
+            canonical("john.doe@gmail.com", &options),
+            "johndoe@gmail.com"
+        );
+    }
+}
@@ -10,8 +10,16 @@
  */
 
 pub mod smtp {
+    /// Deferral of the recipients whose delivery queue is lagging.
+    pub mod backpressure;
     /// SMTP receiver service configuration.
     pub mod config;
+    /// Addresses and mailing lists answered by VRFY and EXPN.
+    pub mod directory;
+    /// Replies to the errors of a session.
+    pub mod error_reply;
+    /// Verification of the HELO/EHLO name.
+    pub mod helo;
     /// SMTP receiver rules settings and rhai apis.
     pub mod rules;
     pub mod server;
@@ -10,25 +10,18 @@
  */
 
 use futures_util::TryFutureExt;
-use vsmtp_common::{
-    broker::{Exchange, Queue},
-    ctx::Ctx,
-    stateful_ctx_received::StatefulCtxReceived,
-};
+use vsmtp_common::broker::{BackendError, Consumer, Exchange, Queue, QueueBackend};
 use vsmtp_config::Config;
 use vsmtp_protocol::ConnectionKind;
 use vsmtp_receiver::smtp::{
-    config::SMTPReceiverConfig,
-    rules::{api, stages::ReceiverStage, status::ReceiverStatus},
-    server::Server,
-    session::Handler,
-};
-use vsmtp_rule_engine::{
-    api::{msa_modules, net_modules, server_auth, utils_modules},
-    rhai, RuleEngineConfigBuilder,
+    backpressure::QueueDepths, config::SMTPReceiverConfig,
+    rules::engine::ListenersRuleEngineConfig, server::Server, session::Handler,
 };
 
-async fn init(channel: &lapin::Channel) -> lapin::Result<(lapin::Queue, lapin::Queue)> {
+async fn init(
+    channel: &lapin::Channel,
+    config: &SMTPReceiverConfig,
+) -> lapin::Result<(lapin::Queue, lapin::Queue)> {
     let to_working_queue = channel
         .queue_declare(
             Queue::ToWorking.as_ref(),
@@ -73,9 +66,73 @@ async fn init(channel: &lapin::Channel) -> lapin::Result<(lapin::Queue, lapin::Q
         )
         .await?;
 
+    for name in &config.quarantine.queues {
+        let queue = format!("{}-{name}", Queue::Quarantine.as_ref());
+        channel
+            .queue_declare(
+                &queue,
+                lapin::options::QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
+
+        channel
+            .queue_bind(
+                &queue,
+                Exchange::Quarantine.as_ref(),
+                &format!("rule.{name}"),
+                lapin::options::QueueBindOptions::default(),
+                lapin::types::FieldTable::default(),
+            )
+            .await?;
+    }
+
     Ok((to_working_queue, all_quarantine))
 }
 
+/// Consume the [`vsmtp_common::broker::Backlog`] published by the delivery services,
+/// on a queue of this receiver bound to the [`Exchange::Backlog`] exchange.
+async fn watch_backlogs(channel: &lapin::Channel) -> Result<Consumer, BackendError> {
+    channel
+        .exchange_declare(
+            Exchange::Backlog.as_ref(),
+            lapin::ExchangeKind::Fanout,
+            lapin::options::ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            lapin::types::FieldTable::default(),
+        )
+        .await?;
+
+    let queue = channel
+        .queue_declare(
+            "",
+            lapin::options::QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..Default::default()
+            },
+            lapin::types::FieldTable::default(),
+        )
+        .await?;
+
+    channel
+        .queue_bind(
+            queue.name().as_str(),
+            Exchange::Backlog.as_ref(),
+            "",
+            lapin::options::QueueBindOptions::default(),
+            lapin::types::FieldTable::default(),
+        )
+        .await?;
+
+    channel.consume(queue.name().as_str()).await
+}
+
 type SocketsConfig = std::collections::HashMap<ConnectionKind, Vec<std::net::SocketAddr>>;
 type SocketsBound = std::collections::HashMap<ConnectionKind, Vec<tokio::net::TcpListener>>;
 
@@ -112,13 +169,7 @@ struct Receiver {
     conn: std::sync::Arc<lapin::Connection>,
     #[allow(dead_code)]
     channel: lapin::Channel,
-    rule_engine_config: std::sync::Arc<
-        vsmtp_rule_engine::RuleEngineConfig<
-            Ctx<StatefulCtxReceived>,
-            ReceiverStatus,
-            ReceiverStage,
-        >,
-    >,
+    rule_engine_config: std::sync::Arc<ListenersRuleEngineConfig>,
 }
 
 #[derive(clap::Parser)]
@@ -144,48 +195,11 @@ impl Receiver {
         channel
             .confirm_select(lapin::options::ConfirmSelectOptions::default())
             .await?;
-        channel
-            .basic_qos(1, lapin::options::BasicQosOptions::default())
-            .await?;
-        let _ = init(&channel).await?;
-
-        let rule_engine_config = std::sync::Arc::new(
-            RuleEngineConfigBuilder::default()
-                .with_configuration(&config)?
-                .with_default_module_resolvers(config.scripts.path.parent().ok_or_else(|| {
-                    format!("Invalid script path: {}", config.scripts.path.display())
-                })?)
-                .with_standard_global_modules()
-                .with_global_modules([rhai::packages::Package::as_shared_module(
-                    &rhai_rand::RandomPackage::new(),
-                )])
-                .with_smtp_modules()
-                .with_static_modules(
-                    [
-                        ("code".to_string(), rhai::exported_module!(api::code).into()),
-                        (
-                            "status".to_string(),
-                            rhai::exported_module!(api::status).into(),
-                        ),
-                    ]
-                    .into_iter()
-                    .chain(msa_modules())
-                    .chain(server_auth())
-                    .chain(net_modules())
-                    .chain(utils_modules())
-                    .chain([
-                        vsmtp_rhai_utils::time(),
-                        vsmtp_rhai_utils::env(),
-                        vsmtp_rhai_utils::process(),
-                        vsmtp_rhai_utils::crypto(),
-                    ]),
-                )
-                .with_script_at(
-                    &config.scripts.path,
-                    include_str!("smtp/rules/defaults/filter.rhai"),
-                )?
-                .build(),
-        );
+        channel.set_prefetch(config.broker().prefetch_count).await?;
+        let _ = init(&channel, &config).await?;
+
+        let rule_engine_config =
+            std::sync::Arc::new(ListenersRuleEngineConfig::from_config(&config)?);
 
         Ok(Self {
             config,
@@ -218,35 +232,65 @@ impl Receiver {
         .await?;
 
         let config = std::sync::Arc::new(config);
-        let rustls_config = if let Some(tls) = &config.tls {
-            Some(std::sync::Arc::new(vsmtp_common::tls::get_rustls_config(
-                &tls.protocol_version,
-                &tls.cipher_suite,
-                tls.preempt_cipherlist,
-                &config.name,
-                tls.root.as_ref(),
-                &tls.r#virtual,
-            )?))
-        } else {
-            None
-        };
+        let rustls_config = config.rustls_config()?.map(std::sync::Arc::new);
+
+        let queue_depths = QueueDepths::default();
+        let routing_keys = config
+            .backpressure
+            .routing_keys
+            .values()
+            .cloned()
+            .collect::<std::collections::BTreeSet<_>>();
+        if !routing_keys.is_empty() {
+            let channel = conn.create_channel().await?;
+            let backlogs = watch_backlogs(&channel).await?;
+            tokio::spawn(queue_depths.clone().watch_deferred(backlogs));
+
+            let conn = conn.clone();
+            let open_probe = move || {
+                let conn = conn.clone();
+                async move {
+                    Ok::<_, BackendError>(std::sync::Arc::new(conn.create_channel().await?)
+                        as std::sync::Arc<dyn QueueBackend>)
+                }
+            };
+            tokio::spawn(queue_depths.clone().watch_waiting(
+                open_probe,
+                routing_keys.into_iter().collect(),
+                config.backpressure.interval,
+            ));
+        }
 
         let server = Server {
             socket: sockets,
             config: config.clone(),
         };
 
-        let on_accept = move |args| async move {
+        let on_accept = move |args: vsmtp_protocol::AcceptArgs| async move {
             let channel = conn.create_channel().await.unwrap();
             channel
                 .confirm_select(lapin::options::ConfirmSelectOptions::default())
                 .await
                 .unwrap();
             channel
-                .basic_qos(1, lapin::options::BasicQosOptions::default())
+                .set_prefetch(config.broker().prefetch_count)
                 .await
                 .unwrap();
-            Handler::on_accept(args, rule_engine_config, channel, config, rustls_config)
+            let listener_rule_engine_config = rule_engine_config.get(args.kind);
+            let (handler, ctx, reply) = Handler::on_accept(
+                args,
+                listener_rule_engine_config,
+                std::sync::Arc::new(channel),
+                config,
+                rustls_config,
+            );
+            (
+                handler
+                    .with_tenants(rule_engine_config)
+                    .with_queue_depths(queue_depths),
+                ctx,
+                reply,
+            )
         };
         tracing::info!("SMTP server is listening");
         server.listen(on_accept).await;
@@ -0,0 +1,222 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use tokio_stream::StreamExt;
+use vsmtp_common::broker::{BackendError, Backlog, Consumer, Exchange, QueueBackend};
+
+/// Messages not delivered yet by a delivery service.
+#[derive(Debug, Default, Clone, Copy)]
+struct Depth {
+    /// Messages ready in the `delivery-{routing_key}` queue.
+    waiting: u32,
+    /// Messages deferred by the service, see [`Backlog`].
+    deferred: u32,
+}
+
+/// Backlog of the delivery services watched by the `backpressure` configuration, by routing key,
+/// shared by the sessions and refreshed by [`QueueDepths::watch_waiting`]
+/// and [`QueueDepths::watch_deferred`].
+#[derive(Debug, Default, Clone)]
+pub struct QueueDepths(std::sync::Arc<std::sync::RwLock<std::collections::HashMap<String, Depth>>>);
+
+impl QueueDepths {
+    /// The number of messages not delivered yet by the service bound to `routing_key`,
+    /// `None` if nothing has been read for it yet.
+    #[must_use]
+    pub fn get(&self, routing_key: &str) -> Option<u32> {
+        self.0
+            .read()
+            .expect("queue depths poisoned")
+            .get(routing_key)
+            .map(|depth| depth.waiting.saturating_add(depth.deferred))
+    }
+
+    /// Update the number of messages ready in the delivery queue of `routing_key`.
+    pub fn set_waiting(&self, routing_key: &str, waiting: u32) {
+        self.0
+            .write()
+            .expect("queue depths poisoned")
+            .entry(routing_key.to_string())
+            .or_default()
+            .waiting = waiting;
+    }
+
+    /// Update the number of messages deferred by the service bound to `routing_key`.
+    pub fn set_deferred(&self, routing_key: &str, deferred: u32) {
+        self.0
+            .write()
+            .expect("queue depths poisoned")
+            .entry(routing_key.to_string())
+            .or_default()
+            .deferred = deferred;
+    }
+
+    /// Read once the depth of the delivery queue of the `routing_keys` on the `probe` backend,
+    /// opened with `open_probe` if `None`.
+    ///
+    /// The broker closes the channel of a read on an unknown queue, the probe is then
+    /// dropped and the next queue is read on a new one. The queues which cannot be read
+    /// keep their last depth.
+    pub async fn refresh<F, Fut>(
+        &self,
+        probe: &mut Option<std::sync::Arc<dyn QueueBackend>>,
+        open_probe: &F,
+        routing_keys: &[String],
+    ) where
+        F: Fn() -> Fut + Send + Sync,
+        Fut: std::future::Future<Output = Result<std::sync::Arc<dyn QueueBackend>, BackendError>>
+            + Send,
+    {
+        for routing_key in routing_keys {
+            let backend = match probe.take() {
+                Some(backend) => backend,
+                None => match open_probe().await {
+                    Ok(backend) => backend,
+                    Err(error) => {
+                        tracing::warn!(%error, "Failed to open the queue depth probe");
+                        return;
+                    }
+                },
+            };
+
+            let queue = format!("{}-{routing_key}", Exchange::Delivery.as_ref());
+            match backend.queue_depth(&queue).await {
+                Ok(waiting) => {
+                    self.set_waiting(routing_key, waiting);
+                    *probe = Some(backend);
+                }
+                Err(error) => tracing::warn!(%error, queue, "Failed to read the queue depth"),
+            }
+        }
+    }
+
+    /// Read the depth of the delivery queue of the `routing_keys` every `interval`, forever,
+    /// see [`QueueDepths::refresh`].
+    pub async fn watch_waiting<F, Fut>(
+        self,
+        open_probe: F,
+        routing_keys: Vec<String>,
+        interval: std::time::Duration,
+    ) where
+        F: Fn() -> Fut + Send + Sync,
+        Fut: std::future::Future<Output = Result<std::sync::Arc<dyn QueueBackend>, BackendError>>
+            + Send,
+    {
+        let mut probe = None;
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            self.refresh(&mut probe, &open_probe, &routing_keys).await;
+        }
+    }
+
+    /// Update the number of deferred messages from the [`Backlog`] published by the
+    /// delivery services, until the consumer ends.
+    pub async fn watch_deferred(self, mut backlogs: Consumer) {
+        while let Some(message) = backlogs.next().await {
+            let message = match message {
+                Ok(message) => message,
+                Err(error) => {
+                    tracing::error!(%error, "Failed to consume the backlogs");
+                    return;
+                }
+            };
+
+            match serde_json::from_slice::<Backlog>(&message.data) {
+                Ok(Backlog {
+                    routing_key,
+                    deferred,
+                }) => self.set_deferred(&routing_key, deferred),
+                Err(error) => tracing::warn!(%error, "Invalid backlog, ignoring it"),
+            }
+
+            if let Err(error) = message.ack().await {
+                tracing::error!(%error, "Failed to acknowledge the backlog");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueueDepths;
+    use vsmtp_common::broker::{in_memory::InMemory, Backlog, QueueBackend};
+
+    #[tokio::test]
+    async fn refresh() {
+        let depths = QueueDepths::default();
+        depths.set_waiting("unknown", 42);
+
+        let opened = std::sync::atomic::AtomicUsize::new(0);
+        let open_probe = || {
+            opened.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let backend = InMemory::default();
+            for _ in 0..120 {
+                backend.push("delivery-basic", vec![]);
+            }
+            for _ in 0..3 {
+                backend.push("delivery-relay", vec![]);
+            }
+            async { Ok(std::sync::Arc::new(backend) as std::sync::Arc<dyn QueueBackend>) }
+        };
+
+        let mut probe = None;
+        depths
+            .refresh(
+                &mut probe,
+                &open_probe,
+                &[
+                    "basic".to_string(),
+                    "unknown".to_string(),
+                    "relay".to_string(),
+                ],
+            )
+            .await;
+
+        assert_eq!(depths.get("basic"), Some(120));
+        assert_eq!(depths.get("relay"), Some(3));
+        assert_eq!(depths.get("unknown"), Some(42));
+        assert_eq!(depths.get("maildir"), None);
+        // a new probe after the unknown queue, kept for the next refresh.
+        assert_eq!(opened.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert!(probe.is_some());
+    }
+
+    #[tokio::test]
+    async fn deferred_are_added_to_waiting() {
+        let depths = QueueDepths::default();
+        depths.set_waiting("basic", 100);
+
+        let backend = InMemory::default();
+        for backlog in [
+            serde_json::to_vec(&Backlog {
+                routing_key: "basic".to_string(),
+                deferred: 9_000,
+            })
+            .unwrap(),
+            b"not a backlog".to_vec(),
+            serde_json::to_vec(&Backlog {
+                routing_key: "relay".to_string(),
+                deferred: 7,
+            })
+            .unwrap(),
+        ] {
+            backend.push("backlog", backlog);
+        }
+        depths
+            .clone()
+            .watch_deferred(backend.consume("backlog").await.unwrap())
+            .await;
+
+        assert_eq!(depths.get("basic"), Some(9_100));
+        assert_eq!(depths.get("relay"), Some(7));
+    }
+}
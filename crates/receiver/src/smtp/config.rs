@@ -9,9 +9,13 @@
  *
  */
 
-use vsmtp_common::tls::{secret::Secret, CipherSuite, ProtocolVersion};
+use super::directory::Directory;
+use vsmtp_common::dns_resolver::DnsResolver;
+use vsmtp_common::domain_map::DomainMap;
+use vsmtp_common::tls::{secret::Secret, CipherSuite, ClientAuth, ProtocolVersion};
 use vsmtp_config::{logs, semver, Broker, Config, Logs};
-use vsmtp_protocol::{auth::Mechanism, rustls, Domain};
+use vsmtp_protocol::{auth::Mechanism, rustls, ConnectionKind, Domain, Reply};
+use vsmtp_rule_engine::MissingScript;
 
 /// Configuration for the SMTP receiver.
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -30,18 +34,49 @@ pub struct SMTPReceiverConfig {
     /// Error counts handling.
     #[serde(default)]
     pub errors: Errors,
-    /// Maximum number of clients that can connect at the same time.
+    /// Verification of the name given by the clients on HELO/EHLO.
+    #[serde(default)]
+    pub helo: Helo,
+    /// Handling of the VRFY and EXPN commands.
+    #[serde(default)]
+    pub verify: Verify,
+    /// Temporary rejection of the recipients whose delivery queue is lagging.
+    #[serde(default)]
+    pub backpressure: Backpressure,
+    /// Clients and domains allowed to relay, see `status::relay_control`.
+    #[serde(default)]
+    pub relay: Relay,
+    /// Maximum number of clients that can connect at the same time,
+    /// the connections above are closed with a `421` reply. -1 to disable.
     #[serde(default = "SMTPReceiverConfig::default_max_client")]
     pub max_clients: i64,
+    /// Maximum number of clients connected at the same time by TLS SNI, applied
+    /// after the handshake of the tunneled and STARTTLS connections. Unlimited for the absent domains.
+    #[serde(default)]
+    pub max_clients_per_sni: std::collections::BTreeMap<Domain, usize>,
+    /// Maximum number of commands a client can issue during a session,
+    /// the connection is closed with a `421` reply once exceeded. -1 to disable.
+    #[serde(default = "SMTPReceiverConfig::default_max_commands")]
+    pub max_commands: i64,
     /// Maximum size of the message in bytes.
     #[serde(default = "SMTPReceiverConfig::default_message_size_limit")]
     pub message_size_limit: usize,
+    /// Maximum number of MIME parts of a message, nested parts included,
+    /// the messages above are rejected with a `554` reply. -1 to disable.
+    #[serde(default = "SMTPReceiverConfig::default_max_mime_parts")]
+    pub max_mime_parts: i64,
+    /// Maximum length of the lines sent by the clients.
+    #[serde(default)]
+    pub line_length_limit: LineLengthLimit,
     /// TLS parameters.
     #[serde(default)]
     pub tls: Option<Tls>,
     /// Filters configuration.
     #[serde(default)]
     pub scripts: Scripts,
+    /// Quarantine queues the rules can send messages to.
+    #[serde(default)]
+    pub quarantine: Quarantine,
     /// Application data location on disk. (quarantine, email write, context dump, etc.)
     #[serde(default = "SMTPReceiverConfig::default_storage")]
     pub storage: std::path::PathBuf,
@@ -66,13 +101,47 @@ impl SMTPReceiverConfig {
         -1
     }
 
+    /// Unlimited commands by default.
+    const fn default_max_commands() -> i64 {
+        -1
+    }
+
     const fn default_message_size_limit() -> usize {
         20_000_000
     }
 
+    const fn default_max_mime_parts() -> i64 {
+        1_000
+    }
+
     fn default_storage() -> std::path::PathBuf {
         "/var/vsmtp/storage".into()
     }
+
+    /// Build the configuration of the TLS handshakes, once at startup.
+    ///
+    /// `None` if TLS is not configured or no certificate is, in which case STARTTLS
+    /// is not advertised and the tunneled connections are closed.
+    ///
+    /// # Errors
+    ///
+    /// * the protocol versions, cipher suites or certificates are invalid
+    pub fn rustls_config(
+        &self,
+    ) -> Result<Option<rustls::ServerConfig>, vsmtp_common::tls::error::Error> {
+        let Some(tls) = &self.tls else {
+            if self.esmtp.starttls {
+                tracing::warn!("STARTTLS is enabled but TLS is not configured");
+            }
+            return Ok(None);
+        };
+        if !tls.has_certificates() {
+            tracing::warn!("No TLS certificate is configured, TLS is not available");
+            return Ok(None);
+        }
+
+        tls.rustls_config(&self.name).map(Some)
+    }
 }
 
 impl Default for SMTPReceiverConfig {
@@ -83,10 +152,19 @@ impl Default for SMTPReceiverConfig {
             interfaces: Interfaces::default(),
             esmtp: Esmtp::default(),
             errors: Errors::default(),
+            helo: Helo::default(),
+            verify: Verify::default(),
+            backpressure: Backpressure::default(),
+            relay: Relay::default(),
             max_clients: Self::default_max_client(),
+            max_clients_per_sni: std::collections::BTreeMap::default(),
+            max_commands: Self::default_max_commands(),
             message_size_limit: Self::default_message_size_limit(),
+            max_mime_parts: Self::default_max_mime_parts(),
+            line_length_limit: LineLengthLimit::default(),
             tls: None,
             scripts: Scripts::default(),
+            quarantine: Quarantine::default(),
             storage: Self::default_storage(),
             broker: Broker::default(),
             logs: Logs::default(),
@@ -120,9 +198,13 @@ pub struct Errors {
     /// hard error count before dropping the email. -1 to disable.
     #[serde(default = "Errors::default_hard_count")]
     pub hard_count: i64,
-    /// Delay between errors.
+    /// Delay applied on the first soft error, doubled on each following soft error:
+    /// 1s, 2s, 4s... by default.
     #[serde(default = "Errors::default_delay", with = "humantime_serde")]
     pub delay: std::time::Duration,
+    /// Maximum delay applied on a soft error.
+    #[serde(default = "Errors::default_max_delay", with = "humantime_serde")]
+    pub max_delay: std::time::Duration,
 }
 
 impl Errors {
@@ -136,7 +218,21 @@ impl Errors {
     }
 
     const fn default_delay() -> std::time::Duration {
-        std::time::Duration::from_secs(2)
+        std::time::Duration::from_secs(1)
+    }
+
+    const fn default_max_delay() -> std::time::Duration {
+        std::time::Duration::from_secs(30)
+    }
+
+    /// Delay to apply on the `count`-th soft error of a session (starting at 1),
+    /// doubling on each error up to `max_delay`.
+    #[must_use]
+    pub fn soft_error_delay(&self, count: u32) -> std::time::Duration {
+        2_u32
+            .checked_pow(count.saturating_sub(1))
+            .and_then(|factor| self.delay.checked_mul(factor))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
     }
 }
 
@@ -146,6 +242,201 @@ impl Default for Errors {
             soft_count: Self::default_soft_count(),
             hard_count: Self::default_hard_count(),
             delay: Self::default_delay(),
+            max_delay: Self::default_max_delay(),
+        }
+    }
+}
+
+/// Level of verification of the HELO/EHLO name.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeloCheck {
+    /// Any name is accepted.
+    #[default]
+    Off,
+    /// The name must be a fully qualified domain name or an address literal.
+    Syntax,
+    /// The name must also resolve to the client's address,
+    /// or be one of the names of the client's address. (forward/reverse DNS)
+    Confirm,
+}
+
+/// Verification of the name given by the clients on HELO/EHLO.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Helo {
+    #[serde(default)]
+    pub check: HeloCheck,
+    /// Reply sent to the clients failing the verification.
+    #[serde(default = "Helo::default_reply")]
+    pub reply: Reply,
+    /// Resolver used to confirm the name.
+    #[serde(default = "DnsResolver::google")]
+    pub dns: DnsResolver,
+}
+
+impl Helo {
+    fn default_reply() -> Reply {
+        "550 5.7.1 Invalid HELO/EHLO name\r\n"
+            .parse()
+            .expect("valid reply")
+    }
+}
+
+impl Default for Helo {
+    fn default() -> Self {
+        Self {
+            check: HeloCheck::default(),
+            reply: Self::default_reply(),
+            dns: DnsResolver::google(),
+        }
+    }
+}
+
+/// Answer to the VRFY or EXPN command.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyMode {
+    /// VRFY is answered with `252`, the address is neither confirmed nor denied.
+    /// EXPN is answered with `502`.
+    #[default]
+    Disabled,
+    /// The command is answered with `502`, as not implemented.
+    Rejected,
+    /// The argument is looked up in the [`Verify::directory`],
+    /// the command is disabled if there is no directory.
+    Enabled,
+}
+
+/// Handling of the VRFY and EXPN commands, disabled by default as they
+/// disclose the existing addresses. <https://www.rfc-editor.org/rfc/rfc5321#section-3.5>
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Verify {
+    #[serde(default)]
+    pub vrfy: VerifyMode,
+    #[serde(default)]
+    pub expn: VerifyMode,
+    /// Path of the file listing the addresses and mailing lists, see [`Directory`].
+    #[serde(default)]
+    pub directory: Option<Directory>,
+}
+
+/// Temporary rejection (`451`) at RCPT TO of the recipients of a delivery service whose backlog
+/// has grown above `max_depth` messages. Disabled if no routing key is configured.
+///
+/// The backlog of a service is the number of messages ready in its `delivery-{routing_key}` queue,
+/// read from the broker every `interval`, plus the number of messages it has deferred,
+/// published by the service itself.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backpressure {
+    /// Routing key of the delivery service of the recipients, by domain pattern,
+    /// ex: `"*.example.com": "slow"`.
+    #[serde(default)]
+    pub routing_keys: DomainMap<String>,
+    /// Number of messages not delivered yet above which the recipients of a service are deferred.
+    #[serde(default = "Backpressure::default_max_depth")]
+    pub max_depth: u32,
+    /// Delay between two reads of the depth of the delivery queues.
+    #[serde(default = "Backpressure::default_interval", with = "humantime_serde")]
+    pub interval: std::time::Duration,
+}
+
+impl Backpressure {
+    const fn default_max_depth() -> u32 {
+        10_000
+    }
+
+    const fn default_interval() -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Self {
+            routing_keys: DomainMap::default(),
+            max_depth: Self::default_max_depth(),
+            interval: Self::default_interval(),
+        }
+    }
+}
+
+/// Relay authorization: the recipients outside of the local domains are only accepted
+/// from the trusted networks or from authenticated clients.
+#[serde_with::serde_as]
+#[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Relay {
+    /// Networks of the clients allowed to relay without authentication, ex: `192.168.0.0/16`.
+    #[serde(default)]
+    #[serde_as(as = "Vec<serde_with::DisplayFromStr>")]
+    pub trusted_networks: Vec<ipnet::IpNet>,
+    /// Domains this server receives mail for, the recipients of the other domains are relayed.
+    ///
+    /// The domains are matched exactly: the subdomains must be listed as well, ex: `mail.example.com`
+    /// is relayed if only `example.com` is listed.
+    #[serde(default)]
+    pub local_domains: std::collections::BTreeSet<Domain>,
+}
+
+impl Relay {
+    /// Is the client at `ip` in one of the trusted networks.
+    ///
+    /// An IPv4-mapped IPv6 address (ex: `::ffff:192.168.1.1`, from a dual-stack listener)
+    /// is matched as its IPv4 address.
+    #[must_use]
+    pub fn is_trusted(&self, ip: &std::net::IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted_networks
+            .iter()
+            .any(|network| network.contains(&ip))
+    }
+
+    /// Is `domain` one of the local domains, matched exactly.
+    #[must_use]
+    pub fn is_local(&self, domain: &Domain) -> bool {
+        self.local_domains.contains(domain)
+    }
+}
+
+/// Maximum length of the lines sent by the clients, including the "\r\n".
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LineLengthLimit {
+    /// Command lines, 512 bytes by default. (RFC 5321 4.5.3.1.4)
+    #[serde(default = "LineLengthLimit::default_command")]
+    pub command: usize,
+    /// Text lines of the message, 1000 bytes by default. (RFC 5321 4.5.3.1.6)
+    #[serde(default = "LineLengthLimit::default_text")]
+    pub text: usize,
+}
+
+impl LineLengthLimit {
+    const fn default_command() -> usize {
+        512
+    }
+
+    const fn default_text() -> usize {
+        1000
+    }
+}
+
+impl Default for LineLengthLimit {
+    fn default() -> Self {
+        Self {
+            command: Self::default_command(),
+            text: Self::default_text(),
+        }
+    }
+}
+
+impl From<&LineLengthLimit> for vsmtp_protocol::LineLengthLimit {
+    fn from(value: &LineLengthLimit) -> Self {
+        Self {
+            command: value.command,
+            text: value.text,
         }
     }
 }
@@ -161,6 +452,13 @@ pub struct Tls {
     /// Timeout for the TLS handshake. Sending a timeout reply to the client.
     #[serde(default = "Tls::default_handshake_timeout", with = "humantime_serde")]
     pub handshake_timeout: std::time::Duration,
+    /// Maximum number of TLS handshakes running at the same time, STARTTLS and
+    /// tunneled connections included. -1 to disable.
+    #[serde(default = "Tls::default_max_handshakes")]
+    pub max_handshakes: MaxHandshakes,
+    /// What to do with the handshakes above `max_handshakes`.
+    #[serde(default)]
+    pub handshake_overflow: HandshakeOverflow,
     /// TLS protocol supported.
     #[serde(default = "Tls::default_protocol_version")]
     pub protocol_version: Vec<ProtocolVersion>,
@@ -173,6 +471,17 @@ pub struct Tls {
     /// Virtual domain used by the server for Server Name Identification (SNI).
     #[serde(default)]
     pub r#virtual: std::collections::BTreeMap<Domain, Secret>,
+    /// Protocols offered with ALPN, by order of preference. The handshakes of the clients
+    /// offering only other protocols fail. Empty to not negotiate any protocol.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    /// Close the connections where no protocol has been negotiated with ALPN.
+    #[serde(default)]
+    pub require_alpn: bool,
+    /// Verify the certificates of the clients, exposed to the rules with `ctx.client_certificate`.
+    /// The clients are not asked for a certificate if `None`.
+    #[serde(default)]
+    pub client_auth: Option<ClientAuth>,
 }
 
 impl Default for Tls {
@@ -180,10 +489,15 @@ impl Default for Tls {
         Self {
             preempt_cipherlist: Default::default(),
             handshake_timeout: Self::default_handshake_timeout(),
+            max_handshakes: Self::default_max_handshakes(),
+            handshake_overflow: HandshakeOverflow::default(),
             protocol_version: Self::default_protocol_version(),
             cipher_suite: Self::default_cipher_suite(),
             root: Option::default(),
             r#virtual: std::collections::BTreeMap::default(),
+            alpn: Vec::default(),
+            require_alpn: false,
+            client_auth: None,
         }
     }
 }
@@ -218,6 +532,100 @@ impl Tls {
     pub(crate) const fn default_handshake_timeout() -> std::time::Duration {
         std::time::Duration::from_secs(1)
     }
+
+    /// Unlimited handshakes by default.
+    fn default_max_handshakes() -> MaxHandshakes {
+        MaxHandshakes::from(-1)
+    }
+
+    /// Build the configuration of the TLS handshakes of the server named `hostname`.
+    ///
+    /// # Errors
+    ///
+    /// * the protocol versions, cipher suites or certificates are invalid
+    pub fn rustls_config(
+        &self,
+        hostname: &str,
+    ) -> Result<rustls::ServerConfig, vsmtp_common::tls::error::Error> {
+        let mut rustls_config = vsmtp_common::tls::get_rustls_config(
+            &self.protocol_version,
+            &self.cipher_suite,
+            self.preempt_cipherlist,
+            hostname,
+            self.root.as_ref(),
+            &self.r#virtual,
+            self.client_auth.as_ref(),
+        )?;
+        rustls_config.alpn_protocols = self
+            .alpn
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect();
+        Ok(rustls_config)
+    }
+
+    /// Is the protocol negotiated with ALPN during the handshake accepted ?
+    #[must_use]
+    pub fn accepts_alpn(&self, protocol: Option<&[u8]>) -> bool {
+        protocol.map_or(!self.require_alpn, |protocol| {
+            self.alpn
+                .iter()
+                .any(|allowed| allowed.as_bytes() == protocol)
+        })
+    }
+
+    /// Is there a certificate for the default name or a virtual one ?
+    /// The certificates are checked when building [`Tls::rustls_config`].
+    #[must_use]
+    pub fn has_certificates(&self) -> bool {
+        self.root.is_some() || !self.r#virtual.is_empty()
+    }
+}
+
+/// Limit of the TLS handshakes running at the same time, -1 to disable.
+///
+/// The permits are shared by all the sessions using the configuration.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(from = "i64", into = "i64")]
+pub struct MaxHandshakes {
+    max: i64,
+    semaphore: Option<std::sync::Arc<tokio::sync::Semaphore>>,
+}
+
+impl From<i64> for MaxHandshakes {
+    fn from(max: i64) -> Self {
+        Self {
+            max,
+            semaphore: usize::try_from(max)
+                .ok()
+                .map(|max| std::sync::Arc::new(tokio::sync::Semaphore::new(max))),
+        }
+    }
+}
+
+impl From<MaxHandshakes> for i64 {
+    fn from(value: MaxHandshakes) -> Self {
+        value.max
+    }
+}
+
+impl MaxHandshakes {
+    /// The semaphore a handshake must hold a permit of, `None` if unlimited.
+    #[must_use]
+    pub fn semaphore(&self) -> Option<std::sync::Arc<tokio::sync::Semaphore>> {
+        self.semaphore.clone()
+    }
+}
+
+/// Handling of the TLS handshakes above the `max_handshakes` limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HandshakeOverflow {
+    /// The handshake waits for a running one to finish.
+    #[default]
+    Queue,
+    /// The connection is closed with a `421` reply.
+    Reject,
 }
 
 /// Scripts location and parameters.
@@ -226,12 +634,33 @@ impl Tls {
 pub struct Scripts {
     #[serde(default = "Scripts::default_script_path")]
     pub path: std::path::PathBuf,
+    /// Use the default script with a warning (`warn`) or fail to start (`fail`)
+    /// if the script at `path` does not exist.
+    #[serde(default)]
+    pub on_missing: MissingScript,
+    /// Rules and actions enabled (`true`) or disabled (`false`) by name,
+    /// to turn them off without editing the scripts. Enabled if not listed.
+    #[serde(default)]
+    pub rules: std::collections::HashMap<String, bool>,
+    /// Scripts to run instead of `path` for the connections accepted
+    /// on the listeners of a given kind.
+    #[serde(default)]
+    pub listeners: std::collections::HashMap<ConnectionKind, std::path::PathBuf>,
+    /// Scripts to run for the rest of the session once the client presented
+    /// one of these names (SNI) during the TLS handshake. The clients presenting
+    /// another name keep the script of their listener.
+    #[serde(default)]
+    pub tenants: std::collections::HashMap<Domain, std::path::PathBuf>,
 }
 
 impl Default for Scripts {
     fn default() -> Self {
         Self {
             path: Self::default_script_path(),
+            on_missing: MissingScript::default(),
+            rules: std::collections::HashMap::default(),
+            listeners: std::collections::HashMap::default(),
+            tenants: std::collections::HashMap::default(),
         }
     }
 }
@@ -243,6 +672,16 @@ impl Scripts {
     }
 }
 
+/// Quarantine queues the rules can send messages to.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quarantine {
+    /// Names accepted by `status::quarantine`. A queue is declared for each name on startup.
+    /// If empty, any name is accepted and the messages only reach the catch-all queue.
+    #[serde(default)]
+    pub queues: std::collections::BTreeSet<String>,
+}
+
 /// Extended Simple Mail Transfer Protocol (ESMTP) options.
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(deny_unknown_fields)]
@@ -250,10 +689,10 @@ pub struct Esmtp {
     /// Authentication policy.
     #[serde(default = "Esmtp::default_auth")]
     pub auth: Option<Auth>,
+    /// Enable 8BITMIME.
+    #[serde(default = "Esmtp::default_eightbitmime")]
+    pub eightbitmime: bool,
     // TODO:
-    // /// Enable 8BITMIME.
-    // #[serde(default = "Esmtp::default_eightbitmime")]
-    // pub eightbitmime: bool,
     // /// Enable SMTPUTF8.
     // #[serde(default = "Esmtp::default_smtputf8")]
     // pub smtputf8: bool,
@@ -273,6 +712,21 @@ pub struct Esmtp {
     /// DSN
     #[serde(default = "Esmtp::default_dsn")]
     pub dsn: bool,
+    /// Policy for the MAIL FROM and RCPT TO parameters not supported by vSMTP.
+    #[serde(default)]
+    pub unknown_parameters: UnknownParameters,
+}
+
+/// Handling of the MAIL FROM and RCPT TO parameters not supported by vSMTP.
+/// <https://www.rfc-editor.org/rfc/rfc5321#section-4.1.1.11>
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownParameters {
+    /// The command is rejected with a `555 5.5.4` reply.
+    #[default]
+    Reject,
+    /// The parameters are logged and the command is handled without them.
+    Ignore,
 }
 
 impl Esmtp {
@@ -280,9 +734,9 @@ impl Esmtp {
         None
     }
 
-    // pub(crate) const fn default_eightbitmime() -> bool {
-    //     true
-    // }
+    pub(crate) const fn default_eightbitmime() -> bool {
+        true
+    }
 
     // pub(crate) const fn default_smtputf8() -> bool {
     //     true
@@ -313,10 +767,12 @@ impl Default for Esmtp {
     fn default() -> Self {
         Self {
             auth: Self::default_auth(),
+            eightbitmime: Self::default_eightbitmime(),
             starttls: Self::default_starttls(),
             pipelining: Self::default_pipelining(),
             size: Self::default_size(),
             dsn: Self::default_dsn(),
+            unknown_parameters: UnknownParameters::default(),
         }
     }
 }
@@ -332,12 +788,35 @@ pub struct Auth {
     #[serde(default = "Auth::default_enable_dangerous_mechanism_in_clair")]
     pub enable_dangerous_mechanism_in_clair: bool,
     /// List of mechanisms supported by the server.
+    ///
+    /// `CRAM-MD5` and `SCRAM-SHA-256` require the script to define an `auth_secret(ctx, mechanism, authid)`
+    /// function, returning the password (`CRAM-MD5`) or the verifier (`SCRAM-SHA-256`,
+    /// `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>`) of the user, or `()` if the user is unknown.
     #[serde(default = "Auth::default_mechanisms")]
     pub mechanisms: Vec<Mechanism>,
     /// If the AUTH exchange is canceled, the server will not consider the connection as closing,
     /// increasing the number of attempt failed, until `attempt_count_max`, producing an error.
     #[serde(default = "Auth::default_attempt_count_max")]
     pub attempt_count_max: i64,
+    /// Number of failed AUTH exchanges allowed during a session, -1 to disable.
+    #[serde(default = "Auth::default_max_failures")]
+    pub max_failures: i64,
+    /// What to do once `max_failures` is reached.
+    #[serde(default)]
+    pub on_max_failures: AuthLockout,
+}
+
+/// What to do when a client reaches the maximum number of failed AUTH exchanges.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthLockout {
+    /// The last failure is answered with `535` and the connection is closed.
+    #[default]
+    Close,
+    /// The last failure is answered with `421` and the connection is closed.
+    Disconnect,
+    /// The session goes on, the next AUTH commands are refused with `503`.
+    Refuse,
 }
 
 impl Auth {
@@ -354,6 +833,11 @@ impl Auth {
     pub(crate) const fn default_attempt_count_max() -> i64 {
         -1
     }
+
+    /// The connection is closed on the first failure by default.
+    pub(crate) const fn default_max_failures() -> i64 {
+        1
+    }
 }
 
 impl Config for SMTPReceiverConfig {
@@ -377,3 +861,40 @@ impl Config for SMTPReceiverConfig {
         &self.path
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Errors, Relay};
+
+    #[test]
+    fn soft_error_delay_escalation() {
+        let errors = Errors {
+            max_delay: std::time::Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            (1..=6)
+                .map(|count| errors.soft_error_delay(count).as_secs())
+                .collect::<Vec<_>>(),
+            [1, 2, 4, 8, 10, 10]
+        );
+        // does not overflow on long sessions.
+        assert_eq!(
+            errors.soft_error_delay(u32::MAX),
+            std::time::Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn trusted_mapped_ipv4() {
+        let relay = Relay {
+            trusted_networks: vec!["192.168.0.0/16".parse().unwrap()],
+            ..Default::default()
+        };
+
+        assert!(relay.is_trusted(&"192.168.1.1".parse().unwrap()));
+        assert!(relay.is_trusted(&"::ffff:192.168.1.1".parse().unwrap()));
+        assert!(!relay.is_trusted(&"::ffff:10.0.0.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+/// Addresses and mailing lists known by the `VRFY` and `EXPN` commands, read from a file
+/// when the configuration is loaded.
+///
+/// The file lists one address per line, or a mailing list followed by its members:
+///
+/// ```text
+/// # comments and empty lines are ignored
+/// jenny@example.com
+/// staff@example.com: jenny@example.com, john@example.com
+/// ```
+///
+/// Addresses are case insensitive.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "std::path::PathBuf", into = "std::path::PathBuf")]
+pub struct Directory {
+    path: std::path::PathBuf,
+    addresses: std::sync::Arc<std::collections::BTreeSet<String>>,
+    lists: std::sync::Arc<std::collections::BTreeMap<String, Vec<String>>>,
+}
+
+impl TryFrom<std::path::PathBuf> for Directory {
+    type Error = String;
+
+    fn try_from(path: std::path::PathBuf) -> Result<Self, Self::Error> {
+        let content = std::fs::read_to_string(&path).map_err(|error| {
+            format!("failed to read directory at '{}': {error}", path.display())
+        })?;
+
+        Ok(Self::parse(path, &content))
+    }
+}
+
+impl From<Directory> for std::path::PathBuf {
+    fn from(value: Directory) -> Self {
+        value.path
+    }
+}
+
+/// Remove the optional angle brackets around an address, and put it in lowercase.
+fn normalize(address: &str) -> String {
+    let address = address.trim();
+    address
+        .strip_prefix('<')
+        .and_then(|address| address.strip_suffix('>'))
+        .unwrap_or(address)
+        .to_lowercase()
+}
+
+impl Directory {
+    fn parse(path: std::path::PathBuf, content: &str) -> Self {
+        let mut addresses = std::collections::BTreeSet::new();
+        let mut lists = std::collections::BTreeMap::new();
+
+        for line in content
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(line, _)| line).trim())
+            .filter(|line| !line.is_empty())
+        {
+            match line.split_once(':') {
+                Some((list, members)) => {
+                    lists.insert(
+                        normalize(list),
+                        members
+                            .split(',')
+                            .map(normalize)
+                            .filter(|member| !member.is_empty())
+                            .collect(),
+                    );
+                }
+                None => {
+                    addresses.insert(normalize(line));
+                }
+            }
+        }
+
+        Self {
+            path,
+            addresses: std::sync::Arc::new(addresses),
+            lists: std::sync::Arc::new(lists),
+        }
+    }
+
+    /// The mailbox matching the argument of `VRFY`, `None` if unknown.
+    #[must_use]
+    pub fn verify(&self, query: &str) -> Option<&str> {
+        self.addresses.get(&normalize(query)).map(String::as_str)
+    }
+
+    /// The members of the mailing list matching the argument of `EXPN`, `None` if unknown.
+    #[must_use]
+    pub fn expand(&self, query: &str) -> Option<&[String]> {
+        self.lists.get(&normalize(query)).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Directory;
+
+    #[test]
+    fn parse() {
+        let directory = Directory::parse(
+            "directory.txt".into(),
+            concat!(
+                "# users\n",
+                "Jenny@example.com\n",
+                "\n",
+                "staff@example.com: jenny@example.com, <John@example.com> # team\n",
+                "empty@example.com:\n",
+            ),
+        );
+
+        assert_eq!(
+            directory.verify("<jenny@EXAMPLE.com>"),
+            Some("jenny@example.com")
+        );
+        assert_eq!(directory.verify("john@example.com"), None);
+        assert_eq!(directory.verify("staff@example.com"), None);
+
+        assert_eq!(
+            directory.expand("Staff@example.com"),
+            Some(
+                [
+                    "jenny@example.com".to_string(),
+                    "john@example.com".to_string()
+                ]
+                .as_slice()
+            )
+        );
+        assert_eq!(directory.expand("empty@example.com"), Some([].as_slice()));
+        assert_eq!(directory.expand("jenny@example.com"), None);
+    }
+}
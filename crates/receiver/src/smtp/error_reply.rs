@@ -0,0 +1,129 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_mail_parser::ParserError;
+use vsmtp_protocol::{rsasl, AuthError, Reply};
+
+/// Reply sent to the client when a step of the session fails,
+/// so that every error ends the command with a standard reply.
+pub trait ErrorReply {
+    /// The reply to send to the client.
+    fn reply(&self) -> Reply;
+
+    /// Is the connection closed once the reply is sent.
+    fn closes_connection(&self) -> bool {
+        false
+    }
+}
+
+fn reply(message: &str) -> Reply {
+    message.parse().expect("valid reply")
+}
+
+impl ErrorReply for ParserError {
+    fn reply(&self) -> Reply {
+        match self {
+            Self::Io(_) => reply("451 4.3.0 Failed to receive the message\r\n"),
+            Self::BufferTooLong { .. } => {
+                reply("552 5.3.4 Message size exceeds fixed maximum message size\r\n")
+            }
+            Self::HeaderTooLong { .. } => reply("500 5.5.6 Header section too long\r\n"),
+            Self::LineTooLong { .. } => reply("500 5.5.6 Line too long\r\n"),
+            Self::TooManyParts(_) => reply("554 5.6.0 Message has too many MIME parts\r\n"),
+            Self::MandatoryHeadersNotFound(header) => reply(&format!(
+                "554 5.6.0 Mandatory header '{header}' not found\r\n"
+            )),
+            Self::InvalidMail(_) | Self::BoundaryNotFound(_) | Self::MisplacedBoundary(_) => {
+                reply("554 5.6.0 Message is not valid\r\n")
+            }
+        }
+    }
+}
+
+impl ErrorReply for AuthError {
+    fn reply(&self) -> Reply {
+        match self {
+            Self::ClientMustNotStart => {
+                reply("501 5.7.0 Client must not start with this mechanism\r\n")
+            }
+            Self::ValidationError(_) => reply("535 5.7.8 Authentication credentials invalid\r\n"),
+            Self::Canceled => reply("501 Authentication canceled by client\r\n"),
+            Self::Base64 { .. } => reply("501 5.5.2 Invalid, not base64\r\n"),
+            Self::ConfigError(rsasl::prelude::SASLError::NoSharedMechanism) => {
+                reply("504 5.5.4 Mechanism is not supported\r\n")
+            }
+            Self::SessionError(_) | Self::IO(_) | Self::ConfigError(_) => {
+                reply("454 4.7.0 Temporary authentication failure\r\n")
+            }
+        }
+    }
+
+    fn closes_connection(&self) -> bool {
+        matches!(
+            self,
+            Self::SessionError(_) | Self::IO(_) | Self::ConfigError(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorReply;
+    use vsmtp_mail_parser::ParserError;
+    use vsmtp_protocol::{rsasl, AuthError};
+
+    fn code(error: &impl ErrorReply) -> String {
+        error.reply().code().to_string()
+    }
+
+    #[test]
+    fn parser_errors() {
+        let io = ParserError::Io(std::io::ErrorKind::UnexpectedEof.into());
+        assert_eq!(code(&io), "451 4.3.0");
+
+        let missing = ParserError::MandatoryHeadersNotFound("From".to_string());
+        assert_eq!(code(&missing), "554 5.6.0");
+        assert_eq!(
+            missing.reply().lines().next().unwrap(),
+            "Mandatory header 'From' not found"
+        );
+
+        for invalid in [
+            ParserError::InvalidMail("invalid".to_string()),
+            ParserError::BoundaryNotFound("no boundary".to_string()),
+            ParserError::MisplacedBoundary("misplaced".to_string()),
+        ] {
+            assert_eq!(code(&invalid), "554 5.6.0");
+            assert!(!invalid.closes_connection());
+        }
+    }
+
+    #[test]
+    fn auth_errors() {
+        let io = AuthError::IO(std::io::ErrorKind::BrokenPipe.into());
+        assert_eq!(code(&io), "454 4.7.0");
+        assert!(io.closes_connection());
+
+        let config = AuthError::ConfigError(rsasl::prelude::SASLError::NoSharedMechanism);
+        assert_eq!(code(&config), "504 5.5.4");
+        assert!(config.closes_connection());
+
+        let config = AuthError::ConfigError(
+            rsasl::prelude::Mechname::parse(b"")
+                .expect_err("empty mechanism name")
+                .into(),
+        );
+        assert_eq!(code(&config), "454 4.7.0");
+        assert!(config.closes_connection());
+
+        assert!(!AuthError::Canceled.closes_connection());
+    }
+}
@@ -0,0 +1,186 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::config::{Helo, HeloCheck};
+use vsmtp_protocol::{ClientName, Domain};
+
+/// Reason of the rejection of a HELO/EHLO name.
+#[derive(Debug, thiserror::Error)]
+pub enum HeloError {
+    #[error("'{0}' is not a fully qualified domain name")]
+    NotFullyQualified(Domain),
+    #[error("'{name}' does not match the client address {client_ip}")]
+    Unconfirmed {
+        name: ClientName,
+        client_ip: std::net::IpAddr,
+    },
+}
+
+/// A fully qualified domain name has at least two labels made of letters, digits and
+/// hyphens (not at the edges of the label), and a top level domain which is not numeric.
+fn is_fully_qualified(domain: &Domain) -> bool {
+    let domain = domain.to_string();
+    let labels = domain
+        .strip_suffix('.')
+        .unwrap_or(&domain)
+        .split('.')
+        .collect::<Vec<_>>();
+
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-')
+        })
+        && !labels
+            .last()
+            .is_some_and(|tld| tld.bytes().all(|c| c.is_ascii_digit()))
+}
+
+/// Does `domain` resolve to `client_ip`, or is `domain` one of the names of `client_ip`?
+async fn is_confirmed(config: &Helo, domain: &Domain, client_ip: std::net::IpAddr) -> bool {
+    let name = domain.to_string();
+
+    match config.dns.resolver.lookup_ip(name.as_str()).await {
+        Ok(ips) if ips.iter().any(|ip| ip == client_ip) => return true,
+        Ok(_) => {}
+        Err(error) => tracing::debug!(%name, ?error, "Forward lookup of the HELO name failed"),
+    }
+
+    match config.dns.resolver.reverse_lookup(client_ip).await {
+        Ok(names) => names.iter().any(|ptr| {
+            ptr.to_string()
+                .trim_end_matches('.')
+                .eq_ignore_ascii_case(name.trim_end_matches('.'))
+        }),
+        Err(error) => {
+            tracing::debug!(%client_ip, ?error, "Reverse lookup of the client address failed");
+            false
+        }
+    }
+}
+
+/// Verify the name given by the client on HELO/EHLO, following the configured policy.
+pub async fn verify(
+    config: &Helo,
+    client_name: &ClientName,
+    client_ip: std::net::IpAddr,
+) -> Result<(), HeloError> {
+    if config.check == HeloCheck::Off {
+        return Ok(());
+    }
+
+    let confirmed = match client_name {
+        ClientName::Domain(domain) if !is_fully_qualified(domain) => {
+            return Err(HeloError::NotFullyQualified(domain.clone()));
+        }
+        _ if config.check == HeloCheck::Syntax => true,
+        ClientName::Domain(domain) => is_confirmed(config, domain, client_ip).await,
+        ClientName::Ip4(ip) => client_ip == std::net::IpAddr::V4(*ip),
+        ClientName::Ip6(ip) => client_ip == std::net::IpAddr::V6(*ip),
+    };
+
+    if confirmed {
+        Ok(())
+    } else {
+        Err(HeloError::Unconfirmed {
+            name: client_name.clone(),
+            client_ip,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, HeloError};
+    use crate::smtp::config::{Helo, HeloCheck};
+    use vsmtp_protocol::ClientName;
+
+    fn policy(check: HeloCheck) -> Helo {
+        Helo {
+            check,
+            ..Default::default()
+        }
+    }
+
+    fn client_ip() -> std::net::IpAddr {
+        "192.0.2.10".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn bare_hostname() {
+        let name = ClientName::Domain("mailserver".parse().unwrap());
+
+        assert!(verify(&policy(HeloCheck::Off), &name, client_ip())
+            .await
+            .is_ok());
+        assert!(matches!(
+            verify(&policy(HeloCheck::Syntax), &name, client_ip()).await,
+            Err(HeloError::NotFullyQualified(_))
+        ));
+        assert!(matches!(
+            verify(&policy(HeloCheck::Confirm), &name, client_ip()).await,
+            Err(HeloError::NotFullyQualified(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn valid_fqdn() {
+        for name in ["mail.example.com", "mail.example.com.", "mx-1.example.org"] {
+            let name = ClientName::Domain(name.parse().unwrap());
+            assert!(verify(&policy(HeloCheck::Syntax), &name, client_ip())
+                .await
+                .is_ok());
+        }
+
+        let name = ClientName::Domain("10.0.0.1".parse().unwrap());
+        assert!(verify(&policy(HeloCheck::Syntax), &name, client_ip())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn confirmed_address_literal() {
+        let name = ClientName::Ip4("192.0.2.10".parse().unwrap());
+
+        assert!(verify(&policy(HeloCheck::Syntax), &name, client_ip())
+            .await
+            .is_ok());
+        assert!(verify(&policy(HeloCheck::Confirm), &name, client_ip())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_address_literal() {
+        let name = ClientName::Ip4("198.51.100.20".parse().unwrap());
+
+        assert!(verify(&policy(HeloCheck::Syntax), &name, client_ip())
+            .await
+            .is_ok());
+        assert!(matches!(
+            verify(&policy(HeloCheck::Confirm), &name, client_ip()).await,
+            Err(HeloError::Unconfirmed { .. })
+        ));
+        assert!(matches!(
+            verify(
+                &policy(HeloCheck::Confirm),
+                &ClientName::Ip6("2001:db8::1".parse().unwrap()),
+                client_ip()
+            )
+            .await,
+            Err(HeloError::Unconfirmed { .. })
+        ));
+    }
+}
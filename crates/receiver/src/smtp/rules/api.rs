@@ -14,8 +14,12 @@ use rhai::plugin::{
     mem, Dynamic, EvalAltResult, FnAccess, FnNamespace, ImmutableString, Module, NativeCallContext,
     PluginFunction, RhaiResult, TypeId,
 };
+use vsmtp_common::Mailbox;
 use vsmtp_protocol::Reply;
-use vsmtp_rule_engine::rhai;
+use vsmtp_rule_engine::{
+    api::{docs::Ctx, is_plain_data, DmarcEnforcement, EnvelopeRecipient, RecipientsBackend},
+    rhai,
+};
 
 type Result<T> = std::result::Result<T, Box<rhai::EvalAltResult>>;
 
@@ -190,6 +194,10 @@ pub mod status {
     ///             This path will be concatenated to the `config.app.dirpath` field in
     ///             your root configuration.
     ///
+    /// # Errors
+    ///
+    /// * `config.quarantine.queues` is not empty and does not contain `queue`.
+    ///
     /// # SMTP stages
     ///
     /// all of them.
@@ -219,6 +227,59 @@ pub mod status {
         ReceiverStatus::Quarantine(queue.to_string(), None)
     }
 
+    /// Place the email in a quarantine queue like `status::quarantine(queue)`, storing
+    /// the reason of the quarantine in the `quarantine_reason` variable of the context,
+    /// so the tools reading the queue know why the email was quarantined.
+    ///
+    /// # Args
+    ///
+    /// * `queue` - the name of the quarantine queue.
+    /// * `reason` - a map describing the reason, for example the rule name, a score or the matched pattern.
+    ///
+    /// # Errors
+    ///
+    /// * `config.quarantine.queues` is not empty and does not contain `queue`.
+    /// * `reason` contains a value which cannot be carried with the message (see `ctx.set_var`).
+    ///
+    /// # SMTP stages
+    ///
+    /// all of them.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// fn on_pre_queue(ctx) {
+    ///     ctx.run([
+    ///         rule "spam score" |ctx| {
+    ///             if ctx.get_var("spam_score") > 5 {
+    ///                 status::quarantine(ctx, "spam", #{ rule: "spam score", score: ctx.get_var("spam_score") })
+    ///             } else {
+    ///                 status::next()
+    ///             }
+    ///         },
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:12
+    #[rhai_fn(name = "quarantine", pure, return_raw)]
+    pub fn quarantine_with_reason(
+        ctx: &mut Ctx,
+        queue: &str,
+        reason: rhai::Map,
+    ) -> Result<ReceiverStatus> {
+        let reason = Dynamic::from_map(reason);
+        if !is_plain_data(&reason) {
+            return Err("the quarantine reason must only contain plain data".into());
+        }
+
+        ctx.write(|ctx| {
+            ctx.variables
+                .insert("quarantine_reason".to_string(), reason);
+        });
+        Ok(quarantine_str(queue))
+    }
+
     /// Check if two statuses are equal.
     ///
     /// # SMTP stages
@@ -323,6 +384,195 @@ pub mod status {
     pub fn to_debug(status: &mut ReceiverStatus) -> String {
         format!("{status:?}")
     }
+
+    /// Deny the transaction if a spam score is strictly above a threshold,
+    /// go to the next rule otherwise.
+    ///
+    /// # Args
+    ///
+    /// * `score` - the score computed by a scanner (Rspamd, SpamAssassin, ...), as an int or a float.
+    /// * `threshold` - the maximum accepted score, as an int or a float.
+    /// * `code` - the reply to send when denying, as a string or a code object.
+    ///
+    /// # Errors
+    ///
+    /// * `score` or `threshold` is not a number.
+    /// * `code` is not a valid reply.
+    ///
+    /// # SMTP stages
+    ///
+    /// all of them.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// #{
+    ///     preq: [
+    ///         rule "spam score" || {
+    ///             let result = rspamd.check(ctx);
+    ///             reject_if_score(result.score, 15, "554 5.7.1 Message rejected as spam")
+    ///         }
+    ///     ],
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:9
+    #[rhai_fn(name = "reject_if_score", return_raw)]
+    pub fn reject_if_score_with_string(
+        score: Dynamic,
+        threshold: Dynamic,
+        code: &str,
+    ) -> Result<ReceiverStatus> {
+        reject_if_score(score, threshold, reply_from_string(code)?)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(return_raw)]
+    pub fn reject_if_score(
+        score: Dynamic,
+        threshold: Dynamic,
+        code: Reply,
+    ) -> Result<ReceiverStatus> {
+        if as_score(&score, "score")? > as_score(&threshold, "threshold")? {
+            Ok(ReceiverStatus::Deny(Some(code)))
+        } else {
+            Ok(ReceiverStatus::Next)
+        }
+    }
+
+    /// Run a DMARC check using the SPF and DKIM results stored in the context and the
+    /// policy published by the domain of the `From` header, then compute the disposition
+    /// of the message (`none`, `quarantine` or `reject`), following the `pct` tag of the record.
+    /// The result is stored in the context and used to produce the `Authentication-Results` header.
+    ///
+    /// # Args
+    ///
+    /// a map composed of the following parameters:
+    /// * `dns_resolver` - The DNS resolver to use when performing DMARC record lookup. (see the `dns` module)
+    /// * `enforce` - Quarantine or deny the message following the disposition. (default: false)
+    /// * `quarantine` - The queue used when the message is quarantined. (default: "dmarc")
+    ///
+    /// # Return
+    ///
+    /// * `deny` if the disposition is `reject` and the policy is enforced.
+    /// * `quarantine` if the disposition is `quarantine` and the policy is enforced.
+    /// * `next` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// * The parameters are invalid.
+    /// * `spf::check` and `dkim::verify` have not been called before.
+    ///
+    /// # SMTP stages
+    ///
+    /// `preq` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// #{
+    ///     preq: [
+    ///         rule "dmarc" || dmarc_evaluate(ctx, #{ dns_resolver: global::dns_resolver, enforce: true }),
+    ///     ],
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:10
+    #[rhai_fn(global, pure, return_raw)]
+    pub fn dmarc_evaluate(ctx: &mut Ctx, params: Dynamic) -> Result<ReceiverStatus> {
+        vsmtp_rule_engine::api::dmarc_evaluate(ctx, &params).map(dmarc_status)
+    }
+
+    /// Deny a recipient which does not exist in a backend with `code::c550_1_1`.
+    ///
+    /// # Args
+    ///
+    /// * `backend` - The recipients backend, see the `recipients` module.
+    /// * `rcpt` - The recipient to look up, as a string or a recipient object.
+    ///
+    /// # Return
+    ///
+    /// * `deny` if the recipient does not exist.
+    /// * `next` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// * The recipient is not a valid address.
+    /// * The lookup of the backend failed.
+    ///
+    /// # SMTP stages
+    ///
+    /// `rcpt` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// fn on_rcpt_to(ctx) {
+    ///     ctx.run([
+    ///         rule "unknown recipients" |ctx| status::deny_unknown_recipient(global::recipients::backend, ctx.recipients[-1]),
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:11
+    #[rhai_fn(name = "deny_unknown_recipient", return_raw)]
+    pub fn deny_unknown_recipient_with_string(
+        ncc: NativeCallContext,
+        backend: RecipientsBackend,
+        rcpt: &str,
+    ) -> Result<ReceiverStatus> {
+        let rcpt = Mailbox(
+            rcpt.parse()
+                .map_err(|error| format!("invalid recipient '{rcpt}': {error}"))?,
+        );
+
+        backend.exists(&ncc, &rcpt).map(recipient_status)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(name = "deny_unknown_recipient", return_raw)]
+    pub fn deny_unknown_recipient(
+        ncc: NativeCallContext,
+        backend: RecipientsBackend,
+        rcpt: rhai::Shared<EnvelopeRecipient>,
+    ) -> Result<ReceiverStatus> {
+        backend
+            .exists(&ncc, &rcpt.recipient.forward_path)
+            .map(recipient_status)
+    }
+}
+
+/// Convert the existence of a recipient to a status.
+fn recipient_status(exists: bool) -> ReceiverStatus {
+    if exists {
+        ReceiverStatus::Next
+    } else {
+        ReceiverStatus::Deny(Some(code::unknown_account()))
+    }
+}
+
+/// Convert the enforcement of a DMARC evaluation to a status.
+fn dmarc_status(enforcement: DmarcEnforcement) -> ReceiverStatus {
+    match enforcement {
+        DmarcEnforcement::None => ReceiverStatus::Next,
+        DmarcEnforcement::Quarantine(queue) => ReceiverStatus::Quarantine(queue, None),
+        DmarcEnforcement::Reject => ReceiverStatus::Deny(Some(
+            "550 5.7.1 Message rejected due to the DMARC policy of the sender"
+                .parse()
+                .expect("valid code"),
+        )),
+    }
+}
+
+/// Convert a rhai number to a score.
+fn as_score(value: &rhai::Dynamic, name: &str) -> Result<rhai::FLOAT> {
+    #[allow(clippy::cast_precision_loss)]
+    value
+        .as_float()
+        .or_else(|_| value.as_int().map(|value| value as rhai::FLOAT))
+        .map_err::<Box<EvalAltResult>, _>(|_| {
+            format!("{name} must be a number, not a {}", value.type_name()).into()
+        })
 }
 
 /// Predefined codes for SMTP responses.
@@ -343,6 +593,67 @@ pub mod code {
             .map_err(|error| error.to_string().into())
     }
 
+    /// A SMTP code with the code and message as parameter, without enhanced code.
+    ///
+    /// # rhai-autodocs:index:18
+    #[rhai_fn(global, name = "code", return_raw)]
+    pub fn code_simple(code: rhai::INT, text: &str) -> Result<Code> {
+        format!("{code} {text}")
+            .parse::<Code>()
+            .map_err(|error| error.to_string().into())
+    }
+
+    /// Append a line to the code, making it a multiline reply.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// let reply = code::code(451, "4.7.1", "Greylisted");
+    /// reply.append("Please try again later");
+    /// // "451-4.7.1 Greylisted\r\n451 4.7.1 Please try again later\r\n"
+    /// ```
+    ///
+    /// # rhai-autodocs:index:19
+    #[rhai_fn(global, return_raw)]
+    pub fn append(reply: &mut Code, line: &str) -> Result<()> {
+        if line.contains(['\r', '\n']) {
+            return Err(format!("a line of a code cannot contain a line break: {line:?}").into());
+        }
+
+        let line = format!("{} {line}", reply.code())
+            .parse::<Code>()
+            .map_err::<Box<EvalAltResult>, _>(|error| error.to_string().into())?;
+        *reply = reply.clone().extended(&line);
+        Ok(())
+    }
+
+    /// Get the numeric value of the code, for example `451`.
+    ///
+    /// # rhai-autodocs:index:20
+    #[rhai_fn(global, get = "value", pure)]
+    pub fn value(reply: &mut Code) -> rhai::INT {
+        rhai::INT::from(reply.code().value())
+    }
+
+    /// Get the enhanced value of the code, for example `"4.7.1"`, or `()` if the code is not enhanced.
+    ///
+    /// # rhai-autodocs:index:21
+    #[rhai_fn(global, get = "enhanced", pure)]
+    pub fn enhanced(reply: &mut Code) -> Dynamic {
+        reply
+            .code()
+            .details()
+            .map_or(Dynamic::UNIT, |enhanced| enhanced.to_string().into())
+    }
+
+    /// Get the lines of text of the code, without the codes.
+    ///
+    /// # rhai-autodocs:index:22
+    #[rhai_fn(global, get = "text", pure)]
+    pub fn text(reply: &mut Code) -> rhai::Array {
+        reply.lines().cloned().map(Dynamic::from).collect()
+    }
+
     /// Return a relay access denied code.
     ///
     /// # Example
@@ -738,7 +1049,40 @@ pub mod code {
         .expect("valid code")
     }
 
+    /// Sender address is not owned by the authenticated user code.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let states = vsmtp_test::rhai::run(
+    /// # |builder| Ok(builder.add_root_filter_rules(r#"
+    /// #{
+    ///     mail: [
+    ///         // Will send "553 5.7.1 Sender address rejected: not owned by the authenticated user" to the client.
+    ///         rule "deny with code" || { state::deny(code::c553_7_1()) }
+    ///     ]
+    /// }
+    /// # "#)?.build()));
+    /// # use vsmtp_common::{status::ReceiverStatus, Reply, ReplyCode::Enhanced};
+    /// # assert_eq!(states[&vsmtp_rule_engine::ExecutionStage::MailFrom].2,
+    /// #   ReceiverStatus::Deny(
+    /// #     "553 5.7.1 Sender address rejected: not owned by the authenticated user\r\n".parse().expect("valid code"),
+    /// #   )
+    /// # );
+    /// ```
+    ///
     /// # rhai-autodocs:index:16
+    #[rhai_fn(name = "c553_7_1")]
+    pub fn sender_not_owned() -> Code {
+        code_enhanced(
+            553,
+            "5.7.1",
+            "Sender address rejected: not owned by the authenticated user",
+        )
+        .expect("valid code")
+    }
+
+    /// # rhai-autodocs:index:17
     #[cfg(debug_assertion)]
     pub const fn panic() {
         panic!()
@@ -749,6 +1093,65 @@ pub mod code {
 mod tests {
     use super::*;
 
+    #[rstest::rstest]
+    #[case(Dynamic::from_int(5), Dynamic::from_int(10), ReceiverStatus::Next)]
+    #[case(Dynamic::from_float(9.99), Dynamic::from_int(10), ReceiverStatus::Next)]
+    #[case(Dynamic::from_int(10), Dynamic::from_int(10), ReceiverStatus::Next)]
+    #[case(
+        Dynamic::from_float(10.0),
+        Dynamic::from_float(10.0),
+        ReceiverStatus::Next
+    )]
+    #[case(
+        Dynamic::from_float(10.5),
+        Dynamic::from_int(10),
+        ReceiverStatus::Deny(Some("554 5.7.1 spam".parse().unwrap()))
+    )]
+    #[case(
+        Dynamic::from_int(11),
+        Dynamic::from_float(10.0),
+        ReceiverStatus::Deny(Some("554 5.7.1 spam".parse().unwrap()))
+    )]
+    fn reject_if_score(
+        #[case] score: Dynamic,
+        #[case] threshold: Dynamic,
+        #[case] expected: ReceiverStatus,
+    ) {
+        assert_eq!(
+            status::reject_if_score_with_string(score.clone(), threshold.clone(), "554 5.7.1 spam")
+                .unwrap(),
+            expected
+        );
+        assert_eq!(
+            status::reject_if_score(score, threshold, "554 5.7.1 spam".parse().unwrap()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn reject_if_score_invalid() {
+        status::reject_if_score_with_string("5".into(), Dynamic::from_int(10), "554 spam")
+            .unwrap_err();
+        status::reject_if_score_with_string(Dynamic::from_int(5), Dynamic::UNIT, "554 spam")
+            .unwrap_err();
+        status::reject_if_score_with_string(Dynamic::from_int(11), Dynamic::from_int(10), "spam")
+            .unwrap_err();
+    }
+
+    #[rstest::rstest]
+    #[case(DmarcEnforcement::None, ReceiverStatus::Next)]
+    #[case(
+        DmarcEnforcement::Quarantine("dmarc".to_string()),
+        ReceiverStatus::Quarantine("dmarc".to_string(), None)
+    )]
+    #[case(
+        DmarcEnforcement::Reject,
+        ReceiverStatus::Deny(Some("550 5.7.1 Message rejected due to the DMARC policy of the sender".parse().unwrap()))
+    )]
+    fn dmarc_enforcement(#[case] enforcement: DmarcEnforcement, #[case] expected: ReceiverStatus) {
+        assert_eq!(dmarc_status(enforcement), expected);
+    }
+
     #[test]
     fn codes() {
         assert_eq!(
@@ -803,5 +1206,65 @@ mod tests {
             code::unknown_account().to_string(),
             "550 5.1.1 The email account that you tried to reach does not exist. Please try again.\r\n".to_string()
         );
+        assert_eq!(
+            code::sender_not_owned().to_string(),
+            "553 5.7.1 Sender address rejected: not owned by the authenticated user\r\n"
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn multiline_code() {
+        let mut reply = code::code_enhanced(451, "4.7.1", "Greylisted").unwrap();
+        code::append(&mut reply, "Please try again later").unwrap();
+
+        assert_eq!(
+            reply.to_string(),
+            "451-4.7.1 Greylisted\r\n451 4.7.1 Please try again later\r\n"
+        );
+        assert_eq!(code::value(&mut reply), 451);
+        assert_eq!(
+            code::enhanced(&mut reply).into_string().unwrap(),
+            "4.7.1".to_string()
+        );
+        assert_eq!(
+            code::text(&mut reply)
+                .into_iter()
+                .map(|line| line.into_string().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["Greylisted", "Please try again later"]
+        );
+
+        code::append(&mut reply, "two\r\nlines").unwrap_err();
+    }
+
+    #[test]
+    fn multiline_code_in_rhai() {
+        let mut engine = rhai::Engine::new();
+        engine.register_static_module("code", rhai::exported_module!(code).into());
+
+        let parts = engine
+            .eval::<rhai::Array>(
+                r#"
+                let reply = code::code(550, "No such user");
+                reply.append("Check the address");
+                reply.append("And try again");
+                [reply.value, reply.enhanced, reply.text]
+            "#,
+            )
+            .unwrap();
+
+        assert_eq!(parts[0].as_int().unwrap(), 550);
+        assert!(parts[1].is_unit());
+        assert_eq!(
+            parts[2]
+                .clone()
+                .into_array()
+                .unwrap()
+                .into_iter()
+                .map(|line| line.into_string().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["No such user", "Check the address", "And try again"]
+        );
     }
 }
@@ -0,0 +1,208 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::{api, stages::ReceiverStage, status::ReceiverStatus};
+use crate::smtp::config::{Relay, SMTPReceiverConfig};
+use vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use vsmtp_protocol::{ConnectionKind, Domain};
+use vsmtp_rule_engine::{
+    api::{msa_modules, net_modules, server_auth, utils_modules},
+    rhai, RuleEngineConfig, RuleEngineConfigBuilder,
+};
+
+/// Rule engine configuration used by the receiver.
+pub type ReceiverRuleEngineConfig =
+    RuleEngineConfig<Ctx<StatefulCtxReceived>, ReceiverStatus, ReceiverStage>;
+
+/// Relay control of the `relay` configuration: `Deny` with `code::c554_7_1` if a recipient
+/// is outside of the local domains and the client is neither trusted nor authenticated.
+fn relay_status(relay: &Relay, ctx: &Ctx<StatefulCtxReceived>) -> ReceiverStatus {
+    let connect = ctx.metadata.get_connect();
+    if relay.is_trusted(&connect.client_addr.ip())
+        || connect
+            .sasl
+            .as_ref()
+            .is_some_and(|sasl| sasl.is_authenticated)
+    {
+        return ReceiverStatus::Next;
+    }
+
+    let relayed = ctx.metadata.get_rcpt_to().is_ok_and(|rcpt_to| {
+        rcpt_to
+            .recipient_values()
+            .any(|rcpt| !relay.is_local(&rcpt.forward_path.0.domain()))
+    });
+    if relayed {
+        ReceiverStatus::Deny(Some(api::code::c554_7_1()))
+    } else {
+        ReceiverStatus::Next
+    }
+}
+
+/// Register `ctx.is_trusted_client()` and `status::relay_control(ctx)`,
+/// which read the `relay` configuration.
+fn register_relay_control(module: &mut rhai::Module, relay: &Relay) {
+    let relay = std::sync::Arc::new(relay.clone());
+
+    let trusted = relay.clone();
+    let hash = module.set_native_fn(
+        "is_trusted_client",
+        move |ctx: &mut vsmtp_rule_engine::api::docs::Ctx| -> Result<bool, Box<rhai::EvalAltResult>> {
+            Ok(ctx.read(|ctx| trusted.is_trusted(&ctx.metadata.get_connect().client_addr.ip())))
+        },
+    );
+    module.update_fn_namespace(hash, rhai::FnNamespace::Global);
+
+    module.set_native_fn(
+        "relay_control",
+        move |ctx: &mut vsmtp_rule_engine::api::docs::Ctx| -> Result<ReceiverStatus, Box<rhai::EvalAltResult>> {
+            Ok(ctx.read(|ctx| relay_status(&relay, ctx)))
+        },
+    );
+}
+
+/// Build the status module. If quarantine queues are configured, `status::quarantine`
+/// fails for any other name, instead of sending the message to an unbound queue.
+fn status_module(config: &SMTPReceiverConfig) -> rhai::Module {
+    let mut module = rhai::exported_module!(api::status);
+    register_relay_control(&mut module, &config.relay);
+
+    if !config.quarantine.queues.is_empty() {
+        let queues = std::sync::Arc::new(config.quarantine.queues.clone());
+        let check = move |queue: &str| -> Result<(), Box<rhai::EvalAltResult>> {
+            if queues.contains(queue) {
+                Ok(())
+            } else {
+                Err(format!("'{queue}' is not a configured quarantine queue").into())
+            }
+        };
+
+        let check_queue = check.clone();
+        module.set_native_fn(
+            "quarantine",
+            move |queue: rhai::ImmutableString| -> Result<ReceiverStatus, Box<rhai::EvalAltResult>> {
+                check_queue(&queue).map(|()| api::status::quarantine_str(&queue))
+            },
+        );
+        module.set_native_fn(
+            "quarantine",
+            move |ctx: &mut vsmtp_rule_engine::api::docs::Ctx,
+                  queue: rhai::ImmutableString,
+                  reason: rhai::Map|
+                  -> Result<ReceiverStatus, Box<rhai::EvalAltResult>> {
+                check(&queue)?;
+                api::status::quarantine_with_reason(ctx, &queue, reason)
+            },
+        );
+    }
+
+    module
+}
+
+/// Build the rule engine configuration running the script at `script_path`.
+///
+/// # Errors
+///
+/// * the script path has no parent directory.
+/// * the configuration or the script failed to be loaded.
+pub fn build_rule_engine_config(
+    config: &SMTPReceiverConfig,
+    script_path: &std::path::Path,
+) -> Result<ReceiverRuleEngineConfig, Box<dyn std::error::Error>> {
+    Ok(RuleEngineConfigBuilder::default()
+        .with_configuration(config)?
+        .with_default_module_resolvers(
+            script_path
+                .parent()
+                .ok_or_else(|| format!("Invalid script path: {}", script_path.display()))?,
+        )
+        .with_standard_global_modules()
+        .with_global_modules([rhai::packages::Package::as_shared_module(
+            &rhai_rand::RandomPackage::new(),
+        )])
+        .with_smtp_modules()
+        .with_static_modules(
+            [
+                ("code".to_string(), rhai::exported_module!(api::code).into()),
+                ("status".to_string(), status_module(config).into()),
+            ]
+            .into_iter()
+            .chain(msa_modules())
+            .chain(server_auth())
+            .chain(net_modules())
+            .chain(utils_modules())
+            .chain([
+                vsmtp_rhai_utils::time(),
+                vsmtp_rhai_utils::env(),
+                vsmtp_rhai_utils::process(),
+                vsmtp_rhai_utils::crypto(),
+            ]),
+        )
+        .with_missing_script(config.scripts.on_missing)
+        .with_directive_toggles(config.scripts.rules.clone())
+        .with_script_at(script_path, include_str!("defaults/filter.rhai"))?
+        .build())
+}
+
+/// Rule engine configurations of the receiver, selected by the kind
+/// of the listener that accepted the connection, then by the SNI of the TLS handshake.
+pub struct ListenersRuleEngineConfig {
+    default: std::sync::Arc<ReceiverRuleEngineConfig>,
+    listeners: std::collections::HashMap<ConnectionKind, std::sync::Arc<ReceiverRuleEngineConfig>>,
+    tenants: std::collections::HashMap<Domain, std::sync::Arc<ReceiverRuleEngineConfig>>,
+}
+
+impl ListenersRuleEngineConfig {
+    /// Build a rule engine configuration for the default script and one
+    /// for each script specific to a listener kind or to a tenant.
+    ///
+    /// # Errors
+    ///
+    /// * one of the rule engine configuration failed to be built.
+    pub fn from_config(config: &SMTPReceiverConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            default: std::sync::Arc::new(build_rule_engine_config(config, &config.scripts.path)?),
+            listeners: config
+                .scripts
+                .listeners
+                .iter()
+                .map(|(kind, path)| {
+                    build_rule_engine_config(config, path)
+                        .map(|rule_engine_config| (*kind, std::sync::Arc::new(rule_engine_config)))
+                })
+                .collect::<Result<_, _>>()?,
+            tenants: config
+                .scripts
+                .tenants
+                .iter()
+                .map(|(sni, path)| {
+                    build_rule_engine_config(config, path).map(|rule_engine_config| {
+                        (sni.clone(), std::sync::Arc::new(rule_engine_config))
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Get the rule engine configuration to use for a connection accepted
+    /// on a listener of the given kind.
+    #[must_use]
+    pub fn get(&self, kind: ConnectionKind) -> std::sync::Arc<ReceiverRuleEngineConfig> {
+        self.listeners.get(&kind).unwrap_or(&self.default).clone()
+    }
+
+    /// Get the rule engine configuration of the tenant selected by the SNI
+    /// of a TLS handshake, if any.
+    #[must_use]
+    pub fn get_tenant(&self, sni: &Domain) -> Option<std::sync::Arc<ReceiverRuleEngineConfig>> {
+        self.tenants.get(sni).cloned()
+    }
+}
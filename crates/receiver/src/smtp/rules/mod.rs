@@ -10,5 +10,6 @@
  */
 
 pub mod api;
+pub mod engine;
 pub mod stages;
 pub mod status;
@@ -73,6 +73,10 @@ impl Status for ReceiverStatus {
     fn is_next(&self) -> bool {
         matches!(self, Self::Next)
     }
+
+    fn is_deny(&self) -> bool {
+        matches!(self, Self::Deny(_))
+    }
 }
 
 impl std::fmt::Display for ReceiverStatus {
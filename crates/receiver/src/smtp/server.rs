@@ -11,11 +11,111 @@
 
 use crate::smtp::session::{Handler, SaslValidation};
 use futures_lite::StreamExt;
+use tokio::io::AsyncWriteExt;
 use vsmtp_common::uuid;
-use vsmtp_protocol::{AcceptArgs, ConnectionKind, ReceiverContext, Reply};
+use vsmtp_protocol::{AcceptArgs, ConnectionKind, Domain, ReceiverContext, Reply};
 
 use super::config::SMTPReceiverConfig;
 
+/// Number of connections being served, in total and by TLS SNI.
+#[derive(Default)]
+struct Connections {
+    total: usize,
+    by_sni: std::collections::HashMap<Domain, usize>,
+}
+
+/// Admission of the incoming connections, following the `max_clients`
+/// and `max_clients_per_sni` parameters.
+#[derive(Clone)]
+struct ConnectionLimiter {
+    config: std::sync::Arc<SMTPReceiverConfig>,
+    connections: std::sync::Arc<std::sync::Mutex<Connections>>,
+}
+
+/// A slot reserved for a connection, released when dropped.
+pub struct ConnectionPermit {
+    config: std::sync::Arc<SMTPReceiverConfig>,
+    connections: std::sync::Arc<std::sync::Mutex<Connections>>,
+    sni: Option<Domain>,
+}
+
+impl ConnectionLimiter {
+    fn new(config: std::sync::Arc<SMTPReceiverConfig>) -> Self {
+        Self {
+            config,
+            connections: std::sync::Arc::default(),
+        }
+    }
+
+    /// Reserve a slot for a connection, `None` if the global cap is reached.
+    ///
+    /// The SNI is not known yet, it is bound once the TLS handshake is over,
+    /// see [`ConnectionPermit::bind_sni`].
+    fn try_acquire(&self) -> Option<ConnectionPermit> {
+        let mut connections = self.connections.lock().expect("connections poisoned");
+
+        if usize::try_from(self.config.max_clients).map_or(false, |max| connections.total >= max) {
+            return None;
+        }
+        connections.total += 1;
+
+        Some(ConnectionPermit {
+            config: self.config.clone(),
+            connections: self.connections.clone(),
+            sni: None,
+        })
+    }
+}
+
+impl ConnectionPermit {
+    /// Count the connection in the cap of its `sni`, on the tunneled
+    /// and the STARTTLS connections alike.
+    ///
+    /// Returns `false` if the cap of `sni` is reached, the connection must then be closed.
+    #[must_use]
+    pub fn bind_sni(&mut self, sni: &Domain) -> bool {
+        let Some(max) = self.config.max_clients_per_sni.get(sni) else {
+            return true;
+        };
+        if self.sni.as_ref() == Some(sni) {
+            return true;
+        }
+
+        let mut connections = self.connections.lock().expect("connections poisoned");
+        let count = connections.by_sni.entry(sni.clone()).or_default();
+        if *count >= *max {
+            return false;
+        }
+        *count += 1;
+        drop(connections);
+
+        self.release_sni();
+        self.sni = Some(sni.clone());
+        true
+    }
+
+    fn release_sni(&mut self) {
+        let Some(sni) = self.sni.take() else {
+            return;
+        };
+
+        let mut connections = self.connections.lock().expect("connections poisoned");
+        if let Some(count) = connections.by_sni.get_mut(&sni) {
+            *count -= 1;
+            if *count == 0 {
+                connections.by_sni.remove(&sni);
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.release_sni();
+        self.connections.lock().expect("connections poisoned").total -= 1;
+    }
+}
+
 pub struct Server {
     pub socket: std::collections::HashMap<ConnectionKind, Vec<tokio::net::TcpListener>>,
     pub config: std::sync::Arc<SMTPReceiverConfig>,
@@ -48,29 +148,33 @@ impl Server {
             .collect::<tokio_stream::StreamMap<_, _>>()
     }
 
-    async fn serve<Fun, Future>(
+    /// Run a SMTP session on `stream`, a connection accepted on a listener of `kind`.
+    pub async fn serve<Fun, Future, S>(
         on_accept: Fun,
-        (kind, server_addr, client_addr, tcp_stream): (
+        (kind, server_addr, client_addr, stream): (
             ConnectionKind,
             std::net::SocketAddr,
             std::net::SocketAddr,
-            tokio::net::TcpStream,
+            S,
         ),
         config: std::sync::Arc<SMTPReceiverConfig>,
     ) where
         Fun: FnOnce(AcceptArgs) -> Future + Send,
         Future: std::future::Future<Output = (Handler, ReceiverContext, Option<Reply>)> + Send,
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
     {
         let timestamp = time::OffsetDateTime::now_utc();
         let uuid = uuid::Uuid::new_v4();
 
         let message_stream = vsmtp_protocol::Receiver::<_, SaslValidation, _, _>::new(
-            tcp_stream,
+            stream,
             kind,
             config.errors.soft_count,
             config.errors.hard_count,
+            config.max_commands,
             config.message_size_limit,
             config.esmtp.pipelining,
+            (&config.line_length_limit).into(),
         )
         .into_stream(on_accept, client_addr, server_addr, timestamp, uuid);
         tokio::pin!(message_stream);
@@ -87,6 +191,46 @@ impl Server {
         tracing::info!("Connection closed cleanly.");
     }
 
+    async fn serve_limited<Fun, Future>(
+        on_accept: Fun,
+        (kind, server_addr, client_addr, mut tcp_stream): (
+            ConnectionKind,
+            std::net::SocketAddr,
+            std::net::SocketAddr,
+            tokio::net::TcpStream,
+        ),
+        config: std::sync::Arc<SMTPReceiverConfig>,
+        limiter: ConnectionLimiter,
+    ) where
+        Fun: FnOnce(AcceptArgs) -> Future + Send,
+        Future: std::future::Future<Output = (Handler, ReceiverContext, Option<Reply>)> + Send,
+    {
+        // taken before anything is read from the client.
+        let Some(permit) = limiter.try_acquire() else {
+            tracing::warn!("Too many connections, closing the connection from '{client_addr}'");
+            if let Err(e) = tcp_stream
+                .write_all(b"421 4.7.0 Too many connections, try again later\r\n")
+                .await
+            {
+                tracing::debug!("Failed to reply to '{client_addr}': {e:?}");
+            }
+            return;
+        };
+
+        // the SNI cap is applied by the handler after the TLS handshake.
+        let on_accept = move |args| async move {
+            let (handler, ctx, reply) = on_accept(args).await;
+            (handler.with_connection_permit(permit), ctx, reply)
+        };
+
+        Self::serve(
+            on_accept,
+            (kind, server_addr, client_addr, tcp_stream),
+            config,
+        )
+        .await;
+    }
+
     pub async fn listen<Fun, Future>(&self, on_accept: Fun)
     where
         Fun: FnOnce(AcceptArgs) -> Future + Send + Clone + 'static,
@@ -111,11 +255,302 @@ impl Server {
 
         tokio::pin!(incoming_connection);
 
-        // TODO: add max concurrent connections
+        let limiter = ConnectionLimiter::new(self.config.clone());
 
         while let Some(session) = incoming_connection.next().await {
             tracing::debug!("Serving a new connection");
-            tokio::spawn(Self::serve(on_accept.clone(), session, self.config.clone()));
+            tokio::spawn(Self::serve_limited(
+                on_accept.clone(),
+                session,
+                self.config.clone(),
+                limiter.clone(),
+            ));
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smtp::{
+        config::{HandshakeOverflow, Tls},
+        rules::engine::build_rule_engine_config,
+    };
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn too_many_commands() {
+        let config = std::sync::Arc::new(SMTPReceiverConfig {
+            max_commands: 3,
+            ..Default::default()
+        });
+        let rule_engine_config = std::sync::Arc::new(
+            build_rule_engine_config(
+                &config,
+                &std::path::PathBuf::from_iter([
+                    env!("CARGO_MANIFEST_DIR"),
+                    "tests/scripts",
+                    "replay_accept.rhai",
+                ]),
+            )
+            .unwrap(),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let client = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+        let (tcp_stream, client_addr) = listener.accept().await.unwrap();
+
+        let handler_config = config.clone();
+        let server = tokio::spawn(Server::serve(
+            move |args| async move {
+                Handler::accept(args, rule_engine_config, None, handler_config, None)
+            },
+            (ConnectionKind::Relay, server_addr, client_addr, tcp_stream),
+            config,
+        ));
+
+        let (read, mut write) = client.into_split();
+        let mut lines = tokio::io::BufReader::new(read).lines();
+        assert!(lines.next_line().await.unwrap().unwrap().starts_with("220"));
+
+        for _ in 0..3 {
+            write.write_all(b"NOOP\r\n").await.unwrap();
+            assert!(lines.next_line().await.unwrap().unwrap().starts_with("250"));
+        }
+
+        write.write_all(b"NOOP\r\n").await.unwrap();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "421 4.7.0 Too many commands - closing connection"
+        );
+        assert!(lines.next_line().await.unwrap().is_none());
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn global_connection_cap() {
+        let config = std::sync::Arc::new(SMTPReceiverConfig {
+            max_clients: 1,
+            ..Default::default()
+        });
+        let rule_engine_config = std::sync::Arc::new(
+            build_rule_engine_config(
+                &config,
+                &std::path::PathBuf::from_iter([
+                    env!("CARGO_MANIFEST_DIR"),
+                    "tests/scripts",
+                    "replay_accept.rhai",
+                ]),
+            )
+            .unwrap(),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let server = Server {
+            socket: std::collections::HashMap::from([(ConnectionKind::Relay, vec![listener])]),
+            config: config.clone(),
+        };
+
+        let handler_config = config.clone();
+        let server = tokio::spawn(async move {
+            server
+                .listen(move |args| async move {
+                    Handler::accept(args, rule_engine_config, None, handler_config, None)
+                })
+                .await;
+        });
+
+        let first = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+        let mut first = tokio::io::BufReader::new(first).lines();
+        assert!(first.next_line().await.unwrap().unwrap().starts_with("220"));
+
+        let second = tokio::net::TcpStream::connect(server_addr).await.unwrap();
+        let mut second = tokio::io::BufReader::new(second).lines();
+        assert_eq!(
+            second.next_line().await.unwrap().unwrap(),
+            "421 4.7.0 Too many connections, try again later"
+        );
+        assert!(second.next_line().await.unwrap().is_none());
+
+        server.abort();
+    }
+
+    /// Serve the tunneled and relay connections with one TLS handshake at a time,
+    /// returning their addresses and the semaphore of the handshakes.
+    async fn one_handshake_at_a_time(
+        handshake_overflow: HandshakeOverflow,
+        handshake_timeout: std::time::Duration,
+    ) -> (
+        std::net::SocketAddr,
+        std::net::SocketAddr,
+        std::sync::Arc<tokio::sync::Semaphore>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let config = std::sync::Arc::new(SMTPReceiverConfig {
+            tls: Some(Tls {
+                handshake_timeout,
+                max_handshakes: 1.into(),
+                handshake_overflow,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let tls = config.tls.as_ref().unwrap();
+        let rustls_config = std::sync::Arc::new(
+            vsmtp_common::tls::get_rustls_config(
+                &tls.protocol_version,
+                &tls.cipher_suite,
+                tls.preempt_cipherlist,
+                "testserver.com",
+                None,
+                &std::collections::BTreeMap::default(),
+                None,
+            )
+            .unwrap(),
+        );
+        let handshakes = tls.max_handshakes.semaphore().unwrap();
+        let rule_engine_config = std::sync::Arc::new(
+            build_rule_engine_config(
+                &config,
+                &std::path::PathBuf::from_iter([
+                    env!("CARGO_MANIFEST_DIR"),
+                    "tests/scripts",
+                    "replay_accept.rhai",
+                ]),
+            )
+            .unwrap(),
+        );
+
+        let tunneled = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tunneled_addr = tunneled.local_addr().unwrap();
+        let relay = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let server = Server {
+            socket: std::collections::HashMap::from([
+                (ConnectionKind::Tunneled, vec![tunneled]),
+                (ConnectionKind::Relay, vec![relay]),
+            ]),
+            config: config.clone(),
+        };
+
+        let server = tokio::spawn(async move {
+            server
+                .listen(move |args| async move {
+                    Handler::accept(args, rule_engine_config, None, config, Some(rustls_config))
+                })
+                .await;
+        });
+
+        (tunneled_addr, relay_addr, handshakes, server)
+    }
+
+    #[tokio::test]
+    async fn tls_handshakes_are_throttled() {
+        let (tunneled_addr, relay_addr, handshakes, server) =
+            one_handshake_at_a_time(HandshakeOverflow::Reject, std::time::Duration::from_secs(5))
+                .await;
+
+        let wait_for_permits = |expected| {
+            let handshakes = handshakes.clone();
+            async move {
+                while handshakes.available_permits() != expected {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+            }
+        };
+
+        // the client never sends its `ClientHello`, holding the only permit.
+        let first = tokio::net::TcpStream::connect(tunneled_addr).await.unwrap();
+        wait_for_permits(0).await;
+
+        let second = tokio::net::TcpStream::connect(tunneled_addr).await.unwrap();
+        let mut second = tokio::io::BufReader::new(second).lines();
+        assert_eq!(
+            second.next_line().await.unwrap().unwrap(),
+            "421 4.7.0 Too many TLS handshakes, try again later"
+        );
+        assert!(second.next_line().await.unwrap().is_none());
+
+        let starttls = |expected: &'static str| async move {
+            let (read, mut write) = tokio::net::TcpStream::connect(relay_addr)
+                .await
+                .unwrap()
+                .into_split();
+            let mut lines = tokio::io::BufReader::new(read).lines();
+            assert!(lines.next_line().await.unwrap().unwrap().starts_with("220"));
+
+            write.write_all(b"STARTTLS\r\n").await.unwrap();
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), expected);
+        };
+        starttls("421 4.7.0 Too many TLS handshakes, try again later").await;
+
+        // the permit is released once the handshake is over.
+        drop(first);
+        wait_for_permits(1).await;
+        starttls("220 Ready to start TLS").await;
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn queued_tls_handshakes_time_out() {
+        let (tunneled_addr, _, handshakes, server) = one_handshake_at_a_time(
+            HandshakeOverflow::Queue,
+            std::time::Duration::from_millis(500),
+        )
+        .await;
+
+        // a handshake holding the only permit longer than the timeout.
+        let held = handshakes.try_acquire_owned().unwrap();
+
+        // the time waiting for the permit counts in the timeout of the handshake.
+        let client = tokio::net::TcpStream::connect(tunneled_addr).await.unwrap();
+        let mut client = tokio::io::BufReader::new(client).lines();
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_secs(2), client.next_line())
+                .await
+                .expect("the queued handshake should time out")
+                .map_or(true, |line| line.is_none())
+        );
+
+        drop(held);
+        server.abort();
+    }
+
+    #[test]
+    fn sni_caps_are_independent() {
+        let example: Domain = "example.com".parse().unwrap();
+        let other: Domain = "other.example.com".parse().unwrap();
+        let limiter = ConnectionLimiter::new(std::sync::Arc::new(SMTPReceiverConfig {
+            max_clients: 6,
+            max_clients_per_sni: std::collections::BTreeMap::from([
+                (example.clone(), 1),
+                (other.clone(), 2),
+            ]),
+            ..Default::default()
+        }));
+        let bound = |sni: &Domain| {
+            let mut permit = limiter.try_acquire().unwrap();
+            permit.bind_sni(sni).then_some(permit)
+        };
+
+        let mut first = bound(&example).unwrap();
+        assert!(bound(&example).is_none());
+        // binding the same SNI again is not counted twice.
+        assert!(first.bind_sni(&example));
+
+        let _other = [bound(&other).unwrap(), bound(&other).unwrap()];
+        assert!(bound(&other).is_none());
+
+        // the connections without a capped SNI are only bound to `max_clients`.
+        let _unknown = bound(&"unknown.example.com".parse().unwrap()).unwrap();
+        let _no_sni = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+
+        drop(first);
+        assert!(bound(&example).is_some());
+    }
+}
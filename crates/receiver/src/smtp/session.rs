@@ -10,32 +10,53 @@
  */
 
 use super::{
-    config::{Esmtp, SMTPReceiverConfig},
-    rules::{stages::ReceiverStage, status::ReceiverStatus},
+    backpressure::QueueDepths,
+    config::{
+        Auth, AuthLockout, Backpressure, Esmtp, HandshakeOverflow, SMTPReceiverConfig, Tls,
+        UnknownParameters, VerifyMode,
+    },
+    error_reply::ErrorReply,
+    helo,
+    rules::{engine::ListenersRuleEngineConfig, stages::ReceiverStage, status::ReceiverStatus},
+    server::ConnectionPermit,
 };
 use futures_util::stream::TryStreamExt;
+use tracing::Instrument;
 use vsmtp_common::{
-    api::{write_to_quarantine, write_to_working},
+    broker::QueueBackend,
     ctx::Ctx,
     delivery_route::DeliveryRoute,
     extensions::Extension,
-    stateful_ctx_received::{ConnectProps, SaslAuthProps, StatefulCtxReceived},
+    stateful_ctx_received::{ConnectProps, SaslAuthProps, StateError, StatefulCtxReceived},
+    telemetry::message_span,
     Mailbox, Recipient,
 };
 use vsmtp_mail_parser::ParserError;
 use vsmtp_protocol::{
-    rsasl, rustls, AcceptArgs, AuthArgs, AuthError, ClientName, ConnectionKind, Domain, EhloArgs,
-    Error, HeloArgs, MailFromArgs, ParseArgsError, RcptToArgs, ReceiverContext, Reply, Stage,
+    auth::{CramMd5Challenge, Credentials, Mechanism, ScramVerifier},
+    rsasl::{self, mechanisms::scram::properties::ScramStoredPassword},
+    rustls, AcceptArgs, Address, AuthArgs, AuthError, ClientName, ConnectionKind, Domain, EhloArgs,
+    Error, ExpnArgs, HandshakePermit, HeloArgs, MailFromArgs, MimeBodyType, ParseArgsError,
+    RcptToArgs, ReceiverContext, Reply, Stage, VrfyArgs,
 };
-use vsmtp_rule_engine::{RuleEngine, RuleEngineConfig};
+use vsmtp_rule_engine::{rhai, RuleEngine, RuleEngineConfig};
 
 pub struct Handler {
     rule_engine:
         std::sync::Arc<RuleEngine<Ctx<StatefulCtxReceived>, ReceiverStatus, ReceiverStage>>,
     going_to_quarantine: Option<String>,
-    channel: lapin::Channel,
+    /// `None` if the handler is not connected to a broker.
+    backend: Option<std::sync::Arc<dyn QueueBackend>>,
     config: std::sync::Arc<SMTPReceiverConfig>,
     rustls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+    /// Number of soft errors during the session, used to escalate the delay.
+    soft_error_count: u32,
+    /// Rules of the tenants, selected by the SNI of the TLS handshake.
+    tenants: Option<std::sync::Arc<ListenersRuleEngineConfig>>,
+    /// Backlog of the delivery services watched by the `backpressure` configuration.
+    queue_depths: Option<QueueDepths>,
+    /// Slot of the connection in the `max_clients` and `max_clients_per_sni` limits.
+    connection_permit: Option<ConnectionPermit>,
 }
 
 fn reply(message: impl AsRef<str>) -> Reply {
@@ -50,6 +71,48 @@ pub(crate) fn default_accept() -> Reply {
     reply("250 Ok\r\n")
 }
 
+/// The command is not allowed at this stage of the transaction, the state is left untouched.
+fn bad_sequence(error: &StateError) -> Reply {
+    tracing::debug!(?error, "Client sent a command out of sequence");
+    reply("503 Bad sequence of commands\r\n")
+}
+
+/// Verify the HELO/EHLO name, returning the reply to send if it is rejected.
+async fn verify_helo(
+    config: &SMTPReceiverConfig,
+    client_name: &ClientName,
+    client_ip: std::net::IpAddr,
+) -> Option<Reply> {
+    match helo::verify(&config.helo, client_name, client_ip).await {
+        Ok(()) => None,
+        Err(error) => {
+            tracing::info!(%error, "HELO/EHLO name rejected");
+            Some(config.helo.reply.clone())
+        }
+    }
+}
+
+/// The permit of the TLS handshake, or the reply closing the connection
+/// if the handshake is rejected by the `max_handshakes` limit.
+fn handshake_permit(tls_config: &Tls) -> Result<Option<HandshakePermit>, Reply> {
+    let Some(semaphore) = tls_config.max_handshakes.semaphore() else {
+        return Ok(None);
+    };
+
+    match tls_config.handshake_overflow {
+        HandshakeOverflow::Queue => Ok(Some(HandshakePermit::Wait(semaphore))),
+        HandshakeOverflow::Reject => match semaphore.try_acquire_owned() {
+            Ok(permit) => Ok(Some(HandshakePermit::Acquired(permit))),
+            Err(_) => {
+                tracing::warn!("Too many TLS handshakes, closing the connection");
+                Err(reply(
+                    "421 4.7.0 Too many TLS handshakes, try again later\r\n",
+                ))
+            }
+        },
+    }
+}
+
 fn convert_error(e: Error) -> ParserError {
     if e.get_ref().is_some() {
         match e.into_inner().unwrap().downcast::<std::io::Error>() {
@@ -58,6 +121,12 @@ fn convert_error(e: Error) -> ParserError {
                 Ok(ParseArgsError::BufferTooLong { expected, got }) => {
                     ParserError::BufferTooLong { expected, got }
                 }
+                Ok(ParseArgsError::HeaderTooLong { expected, got }) => {
+                    ParserError::HeaderTooLong { expected, got }
+                }
+                Ok(ParseArgsError::LineTooLong { expected, got }) => {
+                    ParserError::LineTooLong { expected, got }
+                }
                 Ok(otherwise) => ParserError::InvalidMail(otherwise.to_string()),
                 Err(otherwise) => ParserError::InvalidMail(otherwise.to_string()),
             },
@@ -69,6 +138,25 @@ fn convert_error(e: Error) -> ParserError {
 
 impl Handler {
     pub fn on_accept(
+        args: AcceptArgs,
+        rule_engine_config: std::sync::Arc<
+            RuleEngineConfig<Ctx<StatefulCtxReceived>, ReceiverStatus, ReceiverStage>,
+        >,
+        backend: std::sync::Arc<dyn QueueBackend>,
+        config: std::sync::Arc<SMTPReceiverConfig>,
+        rustls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+    ) -> (Self, ReceiverContext, Option<Reply>) {
+        Self::accept(
+            args,
+            rule_engine_config,
+            Some(backend),
+            config,
+            rustls_config,
+        )
+    }
+
+    /// Accept a connection, the messages received being sent to the broker through `backend` if any.
+    pub(crate) fn accept(
         AcceptArgs {
             client_addr,
             server_addr,
@@ -80,7 +168,7 @@ impl Handler {
         rule_engine_config: std::sync::Arc<
             RuleEngineConfig<Ctx<StatefulCtxReceived>, ReceiverStatus, ReceiverStage>,
         >,
-        channel: lapin::Channel,
+        backend: Option<std::sync::Arc<dyn QueueBackend>>,
         config: std::sync::Arc<SMTPReceiverConfig>,
         rustls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
     ) -> (Self, ReceiverContext, Option<Reply>) {
@@ -101,6 +189,7 @@ impl Handler {
                     client_addr,
                     server_addr,
                     server_name: server_name.clone(),
+                    kind,
                     connect_timestamp: timestamp,
                     connect_uuid: uuid,
                     sasl: None,
@@ -119,22 +208,36 @@ impl Handler {
         let make = |going_to_quarantine| Self {
             rule_engine: rule_engine.into(),
             going_to_quarantine,
-            channel,
+            backend,
             config: config_clone,
             rustls_config: rustls_config_clone,
+            soft_error_count: 0,
+            tenants: None,
+            queue_depths: None,
+            connection_permit: None,
         };
 
         // NOTE: The rule engine result is ignored in this case ...
         if kind == ConnectionKind::Tunneled {
-            match (rustls_config, config.tls.as_ref()) {
-                (Some(rustls_config), Some(tls_config)) => {
-                    ctx.upgrade_tls(rustls_config, tls_config.handshake_timeout);
-                }
+            let reply = match (rustls_config, config.tls.as_ref()) {
+                (Some(rustls_config), Some(tls_config)) => match handshake_permit(tls_config) {
+                    Ok(permit) => {
+                        ctx.upgrade_tls(rustls_config, tls_config.handshake_timeout, permit);
+                        None
+                    }
+                    Err(reply) => {
+                        ctx.deny();
+                        Some(reply)
+                    }
+                },
                 // Tunneled connection without TLS config is not allowed.
-                _ => ctx.deny(),
+                _ => {
+                    ctx.deny();
+                    None
+                }
             };
 
-            return (make(None), ctx, None);
+            return (make(None), ctx, reply);
         }
 
         // NOTE: do we want to allow the user to override the reply on accept?
@@ -168,19 +271,131 @@ pub enum ValidationError {
         ReceiverStage::Authenticate
     )]
     NonAcceptCode,
+    #[error("no valid secret returned by 'auth_secret' for '{0}'")]
+    UnknownUser(String),
+    #[error("the response of the client does not match the secret")]
+    InvalidResponse,
 }
 
 impl rsasl::validate::Validation for SaslValidation {
     type Value = ();
 }
 
+/// Function of the script returning the secret of a user, for the mechanisms where the
+/// server runs a challenge-response exchange (`CRAM-MD5` and `SCRAM-SHA-256`).
+const AUTH_SECRET_FN: &str = "auth_secret";
+
+/// Get the secret of `authid` from the script, `None` if the user is unknown.
+fn auth_secret(
+    rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, ReceiverStatus, ReceiverStage>,
+    mechanism: Mechanism,
+    authid: &str,
+) -> Option<String> {
+    match rule_engine.call::<rhai::Dynamic>(
+        AUTH_SECRET_FN,
+        [mechanism.to_string().into(), authid.into()],
+    ) {
+        Ok(Some(secret)) => secret.into_string().ok(),
+        Ok(None) => {
+            tracing::warn!(
+                %mechanism,
+                "'{AUTH_SECRET_FN}' must be defined in the script to use this mechanism"
+            );
+            None
+        }
+        Err(error) => {
+            tracing::warn!(%error, %mechanism, "'{AUTH_SECRET_FN}' failed");
+            None
+        }
+    }
+}
+
+/// Store the credentials of the current AUTH exchange in the context,
+/// keeping the counters of the previous exchanges.
+fn record_credentials(
+    rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, ReceiverStatus, ReceiverStage>,
+    mechanism: Mechanism,
+    credentials: Credentials,
+) {
+    rule_engine.write_state(|state| {
+        let sasl = &mut state.metadata.mut_connect().sasl;
+        let (cancel_count, failure_count) = sasl
+            .as_ref()
+            .map_or((0, 0), |props| (props.cancel_count, props.failure_count));
+
+        *sasl = Some(SaslAuthProps {
+            mechanism,
+            cancel_count,
+            failure_count,
+            is_authenticated: false,
+            credentials,
+        });
+    });
+}
+
+/// Store the credentials in the context and run the rules of the `auth` stage,
+/// the client is authenticated only if they return `accept`.
+fn run_auth_stage(
+    rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, ReceiverStatus, ReceiverStage>,
+    mechanism: Mechanism,
+    credentials: Credentials,
+) -> Result<(), ValidationError> {
+    record_credentials(rule_engine, mechanism, credentials);
+
+    if matches!(
+        rule_engine.run(&ReceiverStage::Authenticate),
+        ReceiverStatus::Accept(_)
+    ) {
+        Ok(())
+    } else {
+        Err(ValidationError::NonAcceptCode)
+    }
+}
+
 impl rsasl::callback::SessionCallback for RsaslSessionCallback {
     fn callback(
         &self,
         _session_data: &rsasl::callback::SessionData,
-        _context: &rsasl::callback::Context<'_>,
-        _request: &mut rsasl::callback::Request<'_>,
+        context: &rsasl::callback::Context<'_>,
+        request: &mut rsasl::callback::Request<'_>,
     ) -> Result<(), rsasl::prelude::SessionError> {
+        if request.is::<ScramStoredPassword>() {
+            let authid = context
+                .get_ref::<rsasl::property::AuthId>()
+                .unwrap_or_default();
+
+            record_credentials(
+                &self.rule_engine,
+                Mechanism::ScramSha256,
+                Credentials::ChallengeResponse {
+                    authid: authid.to_string(),
+                },
+            );
+
+            let Some(verifier) = auth_secret(&self.rule_engine, Mechanism::ScramSha256, authid)
+                .and_then(|secret| match secret.parse::<ScramVerifier>() {
+                    Ok(verifier) => Some(verifier),
+                    Err(error) => {
+                        tracing::warn!(%error, "invalid SCRAM-SHA-256 verifier");
+                        None
+                    }
+                })
+            else {
+                return Err(rsasl::prelude::SessionError::ValidationError(
+                    rsasl::validate::ValidationError::Boxed(Box::new(
+                        ValidationError::UnknownUser(authid.to_string()),
+                    )),
+                ));
+            };
+
+            request.satisfy::<ScramStoredPassword>(&ScramStoredPassword::new(
+                verifier.iterations,
+                &verifier.salt,
+                &verifier.stored_key,
+                &verifier.server_key,
+            ))?;
+        }
+
         Ok(())
     }
 
@@ -199,23 +414,12 @@ impl rsasl::callback::SessionCallback for RsaslSessionCallback {
             })?;
 
         validate.with::<SaslValidation, _>(|| {
-            self.rule_engine.write_state(|state| {
-                state.metadata.mut_connect().sasl = Some(SaslAuthProps {
-                    mechanism: session_data.mechanism().to_string().parse().unwrap(),
-                    cancel_count: 0,
-                    is_authenticated: false,
-                    credentials,
-                });
-            });
-            let result = self.rule_engine.run(&ReceiverStage::Authenticate);
-
-            if matches!(result, ReceiverStatus::Accept(_)) {
-                Ok(())
-            } else {
-                Err(rsasl::validate::ValidationError::Boxed(Box::new(
-                    ValidationError::NonAcceptCode,
-                )))
-            }
+            run_auth_stage(
+                &self.rule_engine,
+                session_data.mechanism().to_string().parse().unwrap(),
+                credentials,
+            )
+            .map_err(|e| rsasl::validate::ValidationError::Boxed(Box::new(e)))
         })?;
 
         Ok(())
@@ -237,14 +441,30 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
         }))
     }
 
+    fn on_command(&mut self) {
+        self.rule_engine.write_state(Ctx::count_command);
+    }
+
     async fn on_post_tls_handshake(
         &mut self,
+        ctx: &mut ReceiverContext,
         sni: Option<String>,
         protocol_version: rustls::ProtocolVersion,
         cipher_suite: rustls::CipherSuite,
         peer_certificates: Option<Vec<rustls::Certificate>>,
         alpn_protocol: Option<Vec<u8>>,
     ) -> Reply {
+        if let Some(tls_config) = &self.config.tls {
+            if !tls_config.accepts_alpn(alpn_protocol.as_deref()) {
+                tracing::warn!(
+                    alpn_protocol = ?alpn_protocol.as_deref().map(String::from_utf8_lossy),
+                    "ALPN protocol not accepted, closing the connection"
+                );
+                ctx.deny();
+                return reply("554 5.7.0 ALPN protocol not accepted\r\n");
+            }
+        }
+
         let Ok(sni) = sni
             .map(|sni| <Domain as std::str::FromStr>::from_str(&sni))
             .transpose()
@@ -253,6 +473,17 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
             return reply("501 5.5.4 Syntax error in parameters or arguments\r\n");
         };
 
+        if let Some(sni) = &sni {
+            if let Some(permit) = &mut self.connection_permit {
+                if !permit.bind_sni(sni) {
+                    tracing::warn!(%sni, "Too many connections for the SNI, closing the connection");
+                    ctx.deny();
+                    return reply("421 4.7.0 Too many connections, try again later\r\n");
+                }
+            }
+            self.route_to_tenant(sni);
+        }
+
         match self.rule_engine.write_state(|state| {
             state
                 .metadata
@@ -284,10 +515,20 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
             reply("554 5.5.1 Error: TLS already active\r\n")
         } else {
             match (self.rustls_config.as_ref(), self.config.tls.as_ref()) {
-                (Some(rustls_config), Some(tls_config)) => {
-                    ctx.upgrade_tls(rustls_config.clone(), tls_config.handshake_timeout);
-                    reply("220 Ready to start TLS\r\n")
-                }
+                (Some(rustls_config), Some(tls_config)) => match handshake_permit(tls_config) {
+                    Ok(permit) => {
+                        ctx.upgrade_tls(
+                            rustls_config.clone(),
+                            tls_config.handshake_timeout,
+                            permit,
+                        );
+                        reply("220 Ready to start TLS\r\n")
+                    }
+                    Err(reply) => {
+                        ctx.deny();
+                        reply
+                    }
+                },
                 _ => reply("454 TLS not available due to temporary reason\r\n"),
             }
         }
@@ -303,10 +544,52 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
             ..
         }: AuthArgs,
     ) -> Option<Reply> {
+        if self.auth_lockout() == AuthLockout::Refuse && self.auth_failures_exceeded() {
+            return Some(reply(
+                "503 5.7.0 Too many failed authentication attempts\r\n",
+            ));
+        }
+
         ctx.authenticate(mechanism, initial_response);
         None
     }
 
+    fn on_cram_md5(
+        &self,
+        challenge: &CramMd5Challenge,
+        authid: &str,
+        digest: &str,
+    ) -> Result<(), AuthError> {
+        record_credentials(
+            &self.rule_engine,
+            Mechanism::CramMd5,
+            Credentials::ChallengeResponse {
+                authid: authid.to_string(),
+            },
+        );
+
+        let Some(secret) = auth_secret(&self.rule_engine, Mechanism::CramMd5, authid) else {
+            return Err(AuthError::ValidationError(Box::new(
+                ValidationError::UnknownUser(authid.to_string()),
+            )));
+        };
+
+        if !challenge.verify(secret.as_bytes(), digest) {
+            return Err(AuthError::ValidationError(Box::new(
+                ValidationError::InvalidResponse,
+            )));
+        }
+
+        run_auth_stage(
+            &self.rule_engine,
+            Mechanism::CramMd5,
+            Credentials::ChallengeResponse {
+                authid: authid.to_string(),
+            },
+        )
+        .map_err(|e| AuthError::ValidationError(Box::new(e)))
+    }
+
     async fn on_post_auth(
         &mut self,
         ctx: &mut ReceiverContext,
@@ -325,14 +608,30 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
 
                 reply("235 2.7.0 Authentication succeeded\r\n")
             }
-            Err(AuthError::ClientMustNotStart) => {
-                reply("501 5.7.0 Client must not start with this mechanism\r\n")
-            }
-            Err(AuthError::ValidationError(..)) => {
-                ctx.deny();
-                reply("535 5.7.8 Authentication credentials invalid\r\n")
+            Err(error @ AuthError::ValidationError(..)) => {
+                self.rule_engine.write_state(|i| {
+                    if let Some(auth_props) = i.metadata.mut_connect().sasl.as_mut() {
+                        auth_props.failure_count += 1;
+                    }
+                });
+
+                if !self.auth_failures_exceeded() {
+                    return error.reply();
+                }
+
+                match self.auth_lockout() {
+                    AuthLockout::Close => {
+                        ctx.deny();
+                        error.reply()
+                    }
+                    AuthLockout::Disconnect => {
+                        ctx.deny();
+                        reply("421 4.7.0 Too many failed authentication attempts, closing connection\r\n")
+                    }
+                    AuthLockout::Refuse => error.reply(),
+                }
             }
-            Err(AuthError::Canceled) => self.rule_engine.write_state(|i| {
+            Err(error @ AuthError::Canceled) => self.rule_engine.write_state(|i| {
                 let auth_props = i
                     .metadata
                     .mut_connect()
@@ -359,20 +658,15 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
                     ctx.deny();
                 }
 
-                reply("501 Authentication canceled by client\r\n")
+                error.reply()
             }),
-            Err(AuthError::Base64 { .. }) => reply("501 5.5.2 Invalid, not base64\r\n"),
-            Err(AuthError::SessionError(e)) => {
-                tracing::warn!(%e, "auth error");
-                ctx.deny();
-                reply("454 4.7.0 Temporary authentication failure\r\n")
-            }
-            Err(AuthError::IO(e)) => todo!("io auth error {e}"),
-            Err(AuthError::ConfigError(rsasl::prelude::SASLError::NoSharedMechanism)) => {
-                ctx.deny();
-                reply("504 5.5.4 Mechanism is not supported\r\n")
+            Err(error) => {
+                if error.closes_connection() {
+                    tracing::warn!(%error, "auth error");
+                    ctx.deny();
+                }
+                error.reply()
             }
-            Err(AuthError::ConfigError(e)) => todo!("handle non_exhaustive pattern: {e}"),
         }
     }
 
@@ -390,14 +684,16 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
             move || reply(format!("250 {server_name} Greetings {client_name}\r\n"))
         };
 
-        if let Err(error) = self.rule_engine.write_state(|state| {
-            state
-                .metadata
-                .set_helo(ClientName::Domain(client_name), true)
-                .map(|_| ())
-        }) {
-            tracing::debug!(?error, "Client sent bad HELO/EHLO command");
-            return reply("503 Bad sequence of commands\r\n");
+        let client_name = ClientName::Domain(client_name);
+        if let Some(reply) = verify_helo(&self.config, &client_name, self.client_ip()).await {
+            return reply;
+        }
+
+        if let Err(error) = self
+            .rule_engine
+            .write_state(|state| state.metadata.set_helo(client_name, true).map(|_| ()))
+        {
+            return bad_sequence(&error);
         }
 
         // NOTE: do we want to allow the user to override the reply on helo?
@@ -420,6 +716,19 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
         ctx: &mut ReceiverContext,
         EhloArgs { client_name, .. }: EhloArgs,
     ) -> Reply {
+        if let Some(reply) = verify_helo(&self.config, &client_name, self.client_ip()).await {
+            return reply;
+        }
+
+        if let Err(error) = self.rule_engine.write_state(|state| {
+            state
+                .metadata
+                .set_helo(client_name.clone(), false)
+                .map(|_| ())
+        }) {
+            return bad_sequence(&error);
+        }
+
         let default = self.build_ehlo_reply(&client_name);
         // NOTE: do we want to allow the user to override the reply on ehlo?
         match self.rule_engine.run(&ReceiverStage::Helo) {
@@ -443,21 +752,35 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
             reverse_path,
             envelop_id,
             ret,
+            mime_body_type,
+            unknown_parameters,
+            raw_parameters,
             ..
         }: MailFromArgs,
     ) -> Reply {
+        if let Some(reply) = self.on_unknown_parameters("MAIL FROM", &unknown_parameters) {
+            return reply;
+        }
+
+        if mime_body_type == Some(MimeBodyType::EightBitMime) && !self.config.esmtp.eightbitmime {
+            return reply("555 5.5.4 BODY=8BITMIME is not supported\r\n");
+        }
+
         let reverse_path = reverse_path.map(Mailbox);
         let default = reply(reverse_path.as_ref().map_or_else(
             || "250 sender <> Ok".to_string(),
             |reverse_path| format!("250 sender <{reverse_path}> Ok"),
         ));
 
-        self.rule_engine.write_state(|state| {
+        if let Err(error) = self.rule_engine.write_state(|state| {
             state
                 .metadata
-                .set_mail_from(reverse_path, envelop_id, ret)
-                .unwrap();
-        });
+                .set_mail_from(reverse_path, envelop_id, ret, mime_body_type)
+                .map(|_| ())
+                .map(|()| state.set_mail_from_parameters(raw_parameters))
+        }) {
+            return bad_sequence(&error);
+        }
 
         match self.rule_engine.run(&ReceiverStage::MailFrom) {
             ReceiverStatus::Next => default,
@@ -480,15 +803,26 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
             forward_path,
             original_forward_path,
             notify_on,
+            unknown_parameters,
+            raw_parameters,
             ..
         }: RcptToArgs,
     ) -> Reply {
+        if let Some(reply) = self.on_unknown_parameters("RCPT TO", &unknown_parameters) {
+            return reply;
+        }
+
+        if let Some(reply) = self.on_queue_lag(&forward_path) {
+            return reply;
+        }
+
         // TODO: add too much rcpt
 
         let default = reply(format!("250 recipient <{forward_path}> Ok"));
 
         let route = DeliveryRoute::Basic;
-        self.rule_engine.write_state(|state| {
+        let rcpt = forward_path.to_string();
+        if let Err(error) = self.rule_engine.write_state(|state| {
             state
                 .metadata
                 .set_rcpt_to(
@@ -499,15 +833,27 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
                         notify_on,
                     },
                 )
-                .unwrap();
-        });
+                .map(|_| ())
+                .map(|()| state.set_rcpt_to_parameters(raw_parameters))
+        }) {
+            return bad_sequence(&error);
+        }
 
-        match self.rule_engine.run(&ReceiverStage::RcptTo) {
+        let (status, rule) = self.rule_engine.run_with_directive(&ReceiverStage::RcptTo);
+        match status {
             ReceiverStatus::Next => default,
             ReceiverStatus::Accept(reply) => reply.unwrap_or_else(default_accept),
             ReceiverStatus::Deny(reply) => {
                 ctx.deny();
-                reply.unwrap_or_else(default_deny)
+                let reply = reply.unwrap_or_else(default_deny);
+                tracing::warn!(
+                    rcpt = rcpt.as_str(),
+                    code = reply.code().value(),
+                    enhanced_code = reply.code().details(),
+                    rule = rule.as_deref().unwrap_or("unknown"),
+                    "Recipient rejected"
+                );
+                reply
             }
             ReceiverStatus::Quarantine(name, reply) => {
                 self.going_to_quarantine = Some(name);
@@ -516,6 +862,51 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
         }
     }
 
+    async fn on_vrfy(
+        &mut self,
+        _: &mut ReceiverContext,
+        VrfyArgs { query, .. }: VrfyArgs,
+    ) -> Reply {
+        let verify = &self.config.verify;
+        match (verify.vrfy, &verify.directory) {
+            (VerifyMode::Enabled, Some(directory)) => {
+                tracing::debug!(%query, "VRFY");
+                directory.verify(&query).map_or_else(
+                    || reply("550 5.1.1 Mailbox unknown\r\n"),
+                    |address| reply(format!("250 2.1.5 <{address}>\r\n")),
+                )
+            }
+            (VerifyMode::Rejected, _) => reply("502 5.5.1 VRFY command disabled\r\n"),
+            _ => reply(
+                "252 2.5.0 Cannot VRFY user, but will accept message and attempt delivery\r\n",
+            ),
+        }
+    }
+
+    async fn on_expn(
+        &mut self,
+        _: &mut ReceiverContext,
+        ExpnArgs { query, .. }: ExpnArgs,
+    ) -> Reply {
+        let verify = &self.config.verify;
+        match (verify.expn, &verify.directory) {
+            (VerifyMode::Enabled, Some(directory)) => {
+                tracing::debug!(%query, "EXPN");
+                match directory.expand(&query) {
+                    None => reply("550 5.1.1 Mailing list unknown\r\n"),
+                    Some([]) => reply("250 2.1.5 Mailing list is empty\r\n"),
+                    Some(members) => reply(
+                        members
+                            .iter()
+                            .map(|member| format!("250 2.1.5 <{member}>\r\n"))
+                            .collect::<String>(),
+                    ),
+                }
+            }
+            _ => reply("502 5.5.1 EXPN command disabled\r\n"),
+        }
+    }
+
     async fn on_rset(&mut self) -> Reply {
         self.rule_engine.write_state(|state| state.metadata.reset());
         self.going_to_quarantine = None;
@@ -529,33 +920,60 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
         stream: impl tokio_stream::Stream<Item = Result<Vec<u8>, vsmtp_protocol::Error>> + Send + Unpin,
         // FIXME: output should be just one Self::Item and not a vec
     ) -> (Reply, Option<Vec<Self::Item>>) {
+        // Size of the message as received, the parsed message being reserialized
+        // with normalized line endings.
+        let received_size = std::sync::atomic::AtomicUsize::new(0);
         let mail = {
             tracing::debug!("SMTP handshake completed");
-            let stream = stream.map_err(convert_error);
+            let stream = stream.map_err(convert_error).inspect_ok(|line| {
+                received_size.fetch_add(line.len(), std::sync::atomic::Ordering::Relaxed);
+            });
 
             // FIXME: the message_size max is already defined when instantiating the `proto::Receiver`
-            let mail = match vsmtp_mail_parser::Mail::parse_stream(stream).await {
+            let max_parts = usize::try_from(self.config.max_mime_parts).ok();
+            match vsmtp_mail_parser::Mail::parse_stream(stream, max_parts).await {
                 Ok(mail) => mail,
-                Err(ParserError::BufferTooLong { .. }) => {
-                    return (
-                        reply("552 4.3.1 Message size exceeds fixed maximum message size\r\n"),
-                        None,
-                    );
+                Err(error) => {
+                    tracing::info!(%error, "Message rejected");
+                    self.rule_engine.write_state(|state| state.metadata.reset());
+                    self.going_to_quarantine = None;
+                    return (error.reply(), None);
                 }
-                Err(otherwise) => todo!("handle error cleanly {:?}", otherwise),
-            };
-            tracing::debug!("Message body fully received");
-            mail
+            }
         };
+        let received_size = received_size.into_inner();
+        tracing::debug!(received_size, "Message body fully received");
 
         // TODO: add headers from preq rules
 
-        let message_size = mail.to_string().len();
+        let raw = mail.to_string();
+        let declared_7bit = self.rule_engine.read_state(|state| {
+            state
+                .metadata
+                .get_mail_from()
+                .is_ok_and(|mail_from| mail_from.mime_body_type == Some(MimeBodyType::SevenBit))
+        });
+        if declared_7bit && !raw.is_ascii() {
+            self.rule_engine.write_state(|state| state.metadata.reset());
+            self.going_to_quarantine = None;
+            return (
+                reply("554 5.6.0 Message contains 8-bit data but was declared as 7BIT\r\n"),
+                None,
+            );
+        }
+
+        if raw.len() != received_size {
+            tracing::debug!(
+                received_size,
+                reserialized_size = raw.len(),
+                "Message size changed by the parsing"
+            );
+        }
         self.rule_engine.write_state(|state| {
             state.metadata.set_complete(mail).unwrap();
         });
 
-        let default = || reply(format!("250 message of {message_size} bytes Ok"));
+        let default = || reply(format!("250 message of {received_size} bytes Ok"));
 
         let (reply, should_return) = match self.rule_engine.run(&ReceiverStage::PreQueue) {
             ReceiverStatus::Next => (default(), true),
@@ -573,13 +991,16 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
         let Self {
             rule_engine,
             going_to_quarantine,
-            channel: _,
+            backend: _,
             config: _,
             rustls_config: _,
+            soft_error_count: _,
+            tenants: _,
+            queue_depths: _,
+            connection_permit: _,
         } = self;
 
-        let ctx: Ctx<StatefulCtxReceived> =
-            rule_engine.write_state(|i| std::mem::replace(i, i.produce_new()));
+        let ctx: Ctx<StatefulCtxReceived> = rule_engine.write_state(Ctx::take_message);
         let going_to_quarantine = std::mem::take(going_to_quarantine);
 
         (
@@ -592,16 +1013,32 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
         // TODO: handle timeout and all the amqp errors
         // let timeout_duration = std::time::Duration::from_secs(5);
 
-        let (ctx, going_to_quarantine) = item;
+        let Some(backend) = &self.backend else {
+            tracing::warn!("No broker to send the message to, dropping it");
+            return None;
+        };
+
+        let (mut ctx, going_to_quarantine) = item;
+        let span = match ctx.metadata.get_mail_from() {
+            Ok(mail_from) => {
+                let uuid = mail_from.message_uuid;
+                message_span(&mut ctx, &uuid)
+            }
+            Err(_) => tracing::Span::none(),
+        };
         let payload = ctx.to_json().unwrap();
 
-        if let Some(quarantine) = going_to_quarantine {
-            tracing::debug!(queue = quarantine, "sending to quarantine");
-            write_to_quarantine(&self.channel, &quarantine, payload).await;
-        } else {
-            tracing::debug!("sending to working");
-            write_to_working(&self.channel, payload).await;
+        async move {
+            if let Some(quarantine) = going_to_quarantine {
+                tracing::debug!(queue = quarantine, "sending to quarantine");
+                backend.write_to_quarantine(&quarantine, payload).await;
+            } else {
+                tracing::debug!("sending to working");
+                backend.write_to_working(payload).await;
+            }
         }
+        .instrument(span)
+        .await;
 
         None
     }
@@ -612,27 +1049,143 @@ impl vsmtp_protocol::ReceiverHandler for Handler {
     }
 
     async fn on_soft_error(&mut self, _: &mut ReceiverContext, reply: Reply) -> Reply {
-        // TODO: configurable
-        // self.config.server.smtp.error.delay
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        self.soft_error_count = self.soft_error_count.saturating_add(1);
+        let delay = self.config.errors.soft_error_delay(self.soft_error_count);
+        tracing::debug!(
+            count = self.soft_error_count,
+            ?delay,
+            "Delaying a soft error"
+        );
+
+        tokio::time::sleep(delay).await;
         reply
     }
 }
 
 impl Handler {
-    fn build_ehlo_reply(&mut self, client_name: &ClientName) -> Reply {
-        self.rule_engine.write_state(|state| {
-            if let Err(error) = state.metadata.set_helo(client_name.clone(), false) {
-                tracing::debug!(?error, "Client sent bad HELO/EHLO command");
-                return reply("503 Bad sequence of commands\r\n");
+    /// Select the rules of the tenants by the SNI of the TLS handshake,
+    /// see [`ListenersRuleEngineConfig::get_tenant`].
+    #[must_use]
+    pub fn with_tenants(mut self, tenants: std::sync::Arc<ListenersRuleEngineConfig>) -> Self {
+        self.tenants = Some(tenants);
+        self
+    }
+
+    /// Defer the recipients whose queue is lagging, see [`Backpressure`].
+    #[must_use]
+    pub fn with_queue_depths(mut self, queue_depths: QueueDepths) -> Self {
+        self.queue_depths = Some(queue_depths);
+        self
+    }
+
+    /// Hold the slot of the connection for the session, the cap of its SNI
+    /// being applied after the TLS handshake.
+    #[must_use]
+    pub fn with_connection_permit(mut self, connection_permit: ConnectionPermit) -> Self {
+        self.connection_permit = Some(connection_permit);
+        self
+    }
+
+    /// The reply deferring `forward_path` if the backlog of the delivery service
+    /// of its domain has grown above the `backpressure.max_depth` limit.
+    fn on_queue_lag(&self, forward_path: &Address) -> Option<Reply> {
+        let Backpressure {
+            routing_keys,
+            max_depth,
+            ..
+        } = &self.config.backpressure;
+        let routing_key = routing_keys.get(&forward_path.domain().to_string())?;
+        let depth = self.queue_depths.as_ref()?.get(routing_key)?;
+
+        (depth > *max_depth).then(|| {
+            tracing::warn!(
+                rcpt = %forward_path,
+                routing_key,
+                depth,
+                "Delivery is lagging, deferring the recipient"
+            );
+            reply("451 4.3.1 Queue is lagging for this domain, try again later\r\n")
+        })
+    }
+
+    /// Run the rules of the tenant matching `sni` for the rest of the session, if any.
+    /// The state of the session is kept.
+    fn route_to_tenant(&mut self, sni: &Domain) {
+        let Some(rule_engine_config) = self
+            .tenants
+            .as_ref()
+            .and_then(|tenants| tenants.get_tenant(sni))
+        else {
+            return;
+        };
+
+        tracing::debug!(%sni, "Routing the session to the rules of the tenant");
+        let state = self.rule_engine.read_state(Clone::clone);
+        self.rule_engine = std::sync::Arc::new(RuleEngine::from_config_with_state(
+            rule_engine_config,
+            state,
+        ));
+    }
+
+    fn auth_lockout(&self) -> AuthLockout {
+        self.config
+            .esmtp
+            .auth
+            .as_ref()
+            .map_or_else(AuthLockout::default, |auth| auth.on_max_failures)
+    }
+
+    /// Has the client reached the maximum number of failed AUTH exchanges?
+    fn auth_failures_exceeded(&self) -> bool {
+        let max_failures = self
+            .config
+            .esmtp
+            .auth
+            .as_ref()
+            .map_or_else(Auth::default_max_failures, |auth| auth.max_failures);
+        let failure_count = self.rule_engine.read_state(|state| {
+            state
+                .metadata
+                .get_connect()
+                .sasl
+                .as_ref()
+                .map_or(0, |auth_props| auth_props.failure_count)
+        });
+
+        usize::try_from(max_failures).is_ok_and(|max_failures| failure_count >= max_failures)
+    }
+
+    fn client_ip(&self) -> std::net::IpAddr {
+        self.rule_engine
+            .read_state(|state| state.metadata.get_connect().client_addr.ip())
+    }
+
+    /// Apply the policy of the parameters not supported by vSMTP,
+    /// returning the reply if the command is rejected.
+    fn on_unknown_parameters(&self, command: &str, parameters: &[String]) -> Option<Reply> {
+        let first = parameters.first()?;
+
+        match self.config.esmtp.unknown_parameters {
+            UnknownParameters::Reject => Some(reply(format!(
+                "555 5.5.4 {command} parameter {first} is not supported\r\n"
+            ))),
+            UnknownParameters::Ignore => {
+                tracing::info!(command, ?parameters, "Ignoring unknown parameters");
+                None
             }
+        }
+    }
 
+    fn build_ehlo_reply(&self, client_name: &ClientName) -> Reply {
+        self.rule_engine.read_state(|state| {
             let Esmtp {
                 auth,
+                eightbitmime,
                 starttls,
                 pipelining,
                 size: _,
                 dsn,
+                unknown_parameters: _,
             } = &self.config.esmtp;
 
             let helo_reply = [
@@ -643,17 +1196,11 @@ impl Handler {
                 )),
                 Some(format!("250-{}\r\n", Extension::EnhancedStatusCodes)),
                 pipelining.then(|| format!("250-{}\r\n", Extension::Pipelining)),
+                eightbitmime.then(|| format!("250-{}\r\n", Extension::BitMime8)),
                 dsn.then(|| format!("250-{}\r\n", Extension::DeliveryStatusNotification)),
-                if *starttls {
-                    if self.config.tls.is_some() {
-                        Some(format!("250-{}\r\n", Extension::StartTls))
-                    } else {
-                        tracing::warn!("STARTTLS is enabled but TLS is not configured");
-                        None
-                    }
-                } else {
-                    None
-                },
+                // https://www.rfc-editor.org/rfc/rfc3207#section-4.2
+                (*starttls && self.rustls_config.is_some() && !state.metadata.is_secured())
+                    .then(|| format!("250-{}\r\n", Extension::StartTls)),
                 auth.as_ref().map(|auth| {
                     format!(
                         "250-{} {}\r\n",
@@ -0,0 +1,119 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use common::{config, Client, HEADERS};
+use vsmtp_protocol::auth::{Credentials, Mechanism};
+use vsmtp_receiver::smtp::config::{Auth, AuthLockout, SMTPReceiverConfig};
+
+// NOTE: the tests run on a multi-threaded runtime, the SASL exchange blocking a worker thread.
+async fn connect() -> Client {
+    Client::relay(config("replay_auth.rhai")).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn plain_credentials_accepted() {
+    let mut client = connect().await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    // "\0john.doe\0s3cr3t"
+    client
+        .expect("AUTH PLAIN AGpvaG4uZG9lAHMzY3IzdA==\r\n", 235)
+        .await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(format!("{HEADERS}Subject: auth\r\n\r\nHello\r\n.\r\n"), 250)
+        .await;
+
+    assert_eq!(client.received().len(), 1);
+
+    let received = client.received();
+    let (ctx, _) = &received[0];
+    let sasl = ctx.metadata.get_connect().sasl.as_ref().unwrap();
+    assert!(sasl.is_authenticated);
+    assert_eq!(sasl.mechanism, Mechanism::Plain);
+    assert_eq!(
+        sasl.credentials,
+        Credentials::Verify {
+            authid: "john.doe".to_string(),
+            authpass: "s3cr3t".to_string(),
+        }
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn plain_credentials_denied() {
+    let mut client = connect().await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    // "\0john.doe\0wrong"
+    client
+        .expect("AUTH PLAIN AGpvaG4uZG9lAHdyb25n\r\n", 535)
+        .await;
+
+    client.assert_closed().await;
+}
+
+fn lockout_config(on_max_failures: AuthLockout) -> SMTPReceiverConfig {
+    let mut config = config("replay_auth.rhai");
+    config.esmtp.auth = Some(Auth {
+        enable_dangerous_mechanism_in_clair: true,
+        mechanisms: Auth::default_mechanisms(),
+        attempt_count_max: -1,
+        max_failures: 3,
+        on_max_failures,
+    });
+    config
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_failures_disconnect() {
+    let mut client = Client::relay(lockout_config(AuthLockout::Disconnect)).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    for _ in 0..2 {
+        client
+            .expect("AUTH PLAIN AGpvaG4uZG9lAHdyb25n\r\n", 535)
+            .await;
+        client.expect("NOOP\r\n", 250).await;
+    }
+    client
+        .expect("AUTH PLAIN AGpvaG4uZG9lAHdyb25n\r\n", 421)
+        .await;
+
+    client.assert_closed().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_failures_refuse() {
+    let mut client = Client::relay(lockout_config(AuthLockout::Refuse)).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    for _ in 0..3 {
+        client
+            .expect("AUTH PLAIN AGpvaG4uZG9lAHdyb25n\r\n", 535)
+            .await;
+    }
+    // "\0john.doe\0s3cr3t", refused even with valid credentials.
+    client
+        .expect("AUTH PLAIN AGpvaG4uZG9lAHMzY3IzdA==\r\n", 503)
+        .await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 530)
+        .await;
+
+    client.expect("NOOP\r\n", 250).await;
+}
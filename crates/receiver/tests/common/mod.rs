@@ -0,0 +1,334 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+// each test file uses a part of the helpers.
+#![allow(dead_code)]
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use vsmtp_common::{
+    broker::{in_memory::InMemory, Queue, QueueBackend},
+    ctx::Ctx,
+    stateful_ctx_received::StatefulCtxReceived,
+    tls::secret::Secret,
+};
+use vsmtp_protocol::{rustls, tokio_rustls, ConnectionKind, Reply};
+use vsmtp_receiver::smtp::{
+    backpressure::QueueDepths, config::SMTPReceiverConfig,
+    rules::engine::ListenersRuleEngineConfig, server::Server, session::Handler,
+};
+
+/// Mandatory header fields prepended to the messages sent by the tests.
+pub const HEADERS: &str = "From: john.doe@example.com\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\n";
+
+/// Path of a file in the `tests/scripts` directory.
+pub fn script(name: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from_iter([env!("CARGO_MANIFEST_DIR"), "tests/scripts", name])
+}
+
+/// The default configuration, running the rules of `script`.
+pub fn config(script_name: &str) -> SMTPReceiverConfig {
+    let mut config = SMTPReceiverConfig::default();
+    config.scripts.path = script(script_name);
+    config
+}
+
+/// Certificate of `mx.example.com`, `*.example.com` and `mail.example.org`,
+/// signed by the `certs/rootCA.crt` authority.
+pub fn secret() -> Secret {
+    Secret {
+        certificate: include_str!("../certs/mx.example.com.crt").parse().unwrap(),
+        private_key: include_str!("../certs/mx.example.com.key").parse().unwrap(),
+    }
+}
+
+/// Certificate chain and private key of a client.
+pub struct Identity(Vec<rustls::Certificate>, rustls::PrivateKey);
+
+impl Identity {
+    /// Certificate of `john.doe`, signed by the `certs/clientCA.crt` authority.
+    pub fn john_doe() -> Self {
+        Self(
+            rustls_pemfile::certs(&mut include_bytes!("../certs/john.doe.crt").as_slice())
+                .unwrap()
+                .into_iter()
+                .map(rustls::Certificate)
+                .collect(),
+            rustls::PrivateKey(
+                rustls_pemfile::pkcs8_private_keys(
+                    &mut include_bytes!("../certs/john.doe.key").as_slice(),
+                )
+                .unwrap()
+                .remove(0),
+            ),
+        )
+    }
+
+    /// Certificate of `mx.example.com`, not signed by the `certs/clientCA.crt` authority.
+    pub fn untrusted() -> Self {
+        Self(
+            rustls_pemfile::certs(&mut include_bytes!("../certs/mx.example.com.crt").as_slice())
+                .unwrap()
+                .into_iter()
+                .map(rustls::Certificate)
+                .collect(),
+            rustls::PrivateKey(
+                rustls_pemfile::pkcs8_private_keys(
+                    &mut include_bytes!("../certs/mx.example.com.key").as_slice(),
+                )
+                .unwrap()
+                .remove(0),
+            ),
+        )
+    }
+}
+
+/// A stream between the client and the receiver, in clear or over TLS.
+pub trait Stream: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {}
+
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> Stream for T {}
+
+/// The client of a session with the receiver, served as a real connection
+/// over an in-memory stream.
+pub struct Client {
+    stream: tokio::io::BufReader<Box<dyn Stream>>,
+    queues: std::sync::Arc<InMemory>,
+}
+
+impl Client {
+    /// Connect to a listener of `kind`, configured with `config`, without reading the greeting.
+    pub fn connect(
+        config: SMTPReceiverConfig,
+        kind: ConnectionKind,
+        queue_depths: Option<QueueDepths>,
+    ) -> Self {
+        let config = std::sync::Arc::new(config);
+        let rule_engines =
+            std::sync::Arc::new(ListenersRuleEngineConfig::from_config(&config).unwrap());
+        let rustls_config = config.rustls_config().unwrap().map(std::sync::Arc::new);
+        let queues = std::sync::Arc::new(InMemory::default());
+
+        let backend = queues.clone() as std::sync::Arc<dyn QueueBackend>;
+        let handler_config = config.clone();
+        let on_accept = move |args: vsmtp_protocol::AcceptArgs| async move {
+            let rule_engine_config = rule_engines.get(args.kind);
+            let (handler, ctx, reply) = Handler::on_accept(
+                args,
+                rule_engine_config,
+                backend,
+                handler_config,
+                rustls_config,
+            );
+            let handler = handler.with_tenants(rule_engines);
+            let handler = match queue_depths {
+                Some(queue_depths) => handler.with_queue_depths(queue_depths),
+                None => handler,
+            };
+            (handler, ctx, reply)
+        };
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(Server::serve(
+            on_accept,
+            (
+                kind,
+                "127.0.0.1:25".parse().unwrap(),
+                "127.0.0.1:49152".parse().unwrap(),
+                server,
+            ),
+            config,
+        ));
+
+        Self {
+            stream: tokio::io::BufReader::new(Box::new(client)),
+            queues,
+        }
+    }
+
+    /// Connect to the relay listener, expecting a `220` greeting.
+    pub async fn relay(config: SMTPReceiverConfig) -> Self {
+        Self::relay_with_depths(config, None).await
+    }
+
+    /// Same as [`Client::relay`], deferring the recipients whose queue is lagging.
+    pub async fn relay_with_depths(
+        config: SMTPReceiverConfig,
+        queue_depths: Option<QueueDepths>,
+    ) -> Self {
+        let mut client = Self::connect(config, ConnectionKind::Relay, queue_depths);
+        let greeting = client.read_reply().await.unwrap();
+        assert_eq!(greeting.code().value(), 220, "{greeting:?}");
+        client
+    }
+
+    /// Connect to the tunneled listener and run the TLS handshake, see [`Client::handshake`].
+    pub async fn tunneled(
+        config: SMTPReceiverConfig,
+        sni: Option<&str>,
+        alpn: &[&str],
+    ) -> std::io::Result<Self> {
+        Self::connect(config, ConnectionKind::Tunneled, None)
+            .handshake(sni, alpn)
+            .await
+    }
+
+    /// Same as [`Client::tunneled`], authenticating with the certificate of `identity`.
+    pub async fn tunneled_as(
+        config: SMTPReceiverConfig,
+        identity: Identity,
+    ) -> std::io::Result<Self> {
+        Self::connect(config, ConnectionKind::Tunneled, None)
+            .handshake_as(None, &[], Some(identity))
+            .await
+    }
+
+    /// Read a reply of the receiver, `None` if the connection is closed.
+    pub async fn read_reply(&mut self) -> Option<Reply> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            match self.stream.read_line(&mut line).await {
+                Ok(0) => break,
+                Ok(_) => {}
+                // a TLS connection dropped without `close_notify`.
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => panic!("failed to read the reply: {e}"),
+            }
+            reply.push_str(&line);
+            // the last line of a reply has a space after its code.
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Some(reply.parse().unwrap());
+            }
+        }
+
+        assert!(reply.is_empty(), "incomplete reply: {reply:?}");
+        None
+    }
+
+    /// Send `input` to the receiver and get its reply.
+    ///
+    /// The input is a command line, the content of a message terminated by `.\r\n`,
+    /// or the response of the client during an `AUTH` exchange.
+    pub async fn send(&mut self, input: impl AsRef<[u8]>) -> Reply {
+        let input = input.as_ref();
+        self.stream.get_mut().write_all(input).await.unwrap();
+        self.stream.get_mut().flush().await.unwrap();
+
+        self.read_reply().await.unwrap_or_else(|| {
+            panic!(
+                "connection closed after {:?}",
+                String::from_utf8_lossy(input)
+            )
+        })
+    }
+
+    /// Send `input` to the receiver and assert the code of the reply.
+    pub async fn expect(&mut self, input: impl AsRef<[u8]>, code: u16) -> Reply {
+        let input = input.as_ref();
+        let reply = self.send(input).await;
+        assert_eq!(
+            reply.code().value(),
+            code,
+            "unexpected reply to {:?}: {reply:?}",
+            String::from_utf8_lossy(input)
+        );
+        reply
+    }
+
+    /// Assert the receiver has closed the connection.
+    pub async fn assert_closed(&mut self) {
+        let mut rest = vec![];
+        match self.stream.read_to_end(&mut rest).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {}
+            Err(e) => panic!("failed to read the connection: {e}"),
+        }
+        assert!(
+            rest.is_empty(),
+            "unexpected data: {:?}",
+            String::from_utf8_lossy(&rest)
+        );
+    }
+
+    /// Send `STARTTLS` and run the TLS handshake, see [`Client::handshake`].
+    pub async fn starttls(mut self, sni: Option<&str>, alpn: &[&str]) -> std::io::Result<Self> {
+        self.expect("STARTTLS\r\n", 220).await;
+        self.handshake(sni, alpn).await
+    }
+
+    /// Run the TLS handshake, presenting `sni` and offering the `alpn` protocols.
+    ///
+    /// The certificate of the receiver must be signed by `certs/rootCA.crt`,
+    /// and valid for `sni`, or `mx.example.com` if `None`.
+    pub async fn handshake(self, sni: Option<&str>, alpn: &[&str]) -> std::io::Result<Self> {
+        self.handshake_as(sni, alpn, None).await
+    }
+
+    /// Same as [`Client::handshake`], authenticating with the certificate of `identity` if any.
+    pub async fn handshake_as(
+        self,
+        sni: Option<&str>,
+        alpn: &[&str],
+        identity: Option<Identity>,
+    ) -> std::io::Result<Self> {
+        let mut roots = rustls::RootCertStore::empty();
+        for certificate in
+            rustls_pemfile::certs(&mut include_bytes!("../certs/rootCA.crt").as_slice()).unwrap()
+        {
+            roots.add(&rustls::Certificate(certificate)).unwrap();
+        }
+
+        let tls_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let mut tls_config = match identity {
+            Some(Identity(certificates, private_key)) => tls_config
+                .with_client_auth_cert(certificates, private_key)
+                .unwrap(),
+            None => tls_config.with_no_client_auth(),
+        };
+        tls_config.alpn_protocols = alpn
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect();
+        tls_config.enable_sni = sni.is_some();
+
+        let server_name = rustls::ServerName::try_from(sni.unwrap_or("mx.example.com")).unwrap();
+        let stream = tokio_rustls::TlsConnector::from(std::sync::Arc::new(tls_config))
+            .connect(server_name, self.stream.into_inner())
+            .await?;
+
+        Ok(Self {
+            stream: tokio::io::BufReader::new(Box::new(stream)),
+            queues: self.queues,
+        })
+    }
+
+    /// Messages accepted by the receiver, with the quarantine they are going to.
+    pub fn received(&self) -> Vec<(Ctx<StatefulCtxReceived>, Option<String>)> {
+        self.queues
+            .queues()
+            .into_iter()
+            .flat_map(|queue| {
+                let quarantine = queue.strip_prefix("rule.").map(ToString::to_string);
+                assert!(quarantine.is_some() || queue == Queue::ToWorking.as_ref());
+                self.queues
+                    .messages(&queue)
+                    .into_iter()
+                    .map(move |payload| {
+                        (
+                            Ctx::<StatefulCtxReceived>::from_json(&payload).unwrap(),
+                            quarantine.clone(),
+                        )
+                    })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use common::{config, script, secret, Client};
+use vsmtp_common::{
+    ctx::Ctx,
+    stateful_ctx_received::{ConnectProps, StatefulCtxReceived},
+};
+use vsmtp_protocol::{ConnectionKind, Reply};
+use vsmtp_receiver::smtp::{
+    config::{SMTPReceiverConfig, Scripts, Tls},
+    rules::{engine::ListenersRuleEngineConfig, stages::ReceiverStage, status::ReceiverStatus},
+};
+use vsmtp_rule_engine::RuleEngine;
+
+fn run_connect(rule_engines: &ListenersRuleEngineConfig, kind: ConnectionKind) -> ReceiverStatus {
+    let rule_engine = RuleEngine::from_config_with_state(
+        rule_engines.get(kind),
+        Ctx {
+            variables: std::collections::HashMap::default(),
+            internal: std::collections::HashMap::default(),
+            metadata: StatefulCtxReceived::new(ConnectProps {
+                connect_timestamp: vsmtp_common::time::OffsetDateTime::now_utc(),
+                connect_uuid: vsmtp_common::uuid::Uuid::new_v4(),
+                client_addr: "127.0.0.1:49152".parse().unwrap(),
+                server_addr: "127.0.0.1:25".parse().unwrap(),
+                server_name: "testserver.com".parse().unwrap(),
+                kind,
+                sasl: None,
+                iprev: None,
+                tls: None,
+            }),
+        },
+    );
+
+    rule_engine.run(&ReceiverStage::Connect)
+}
+
+#[test]
+fn rules_selected_by_listener_kind() {
+    let config = SMTPReceiverConfig {
+        scripts: Scripts {
+            path: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scripts/relay.rhai").into(),
+            listeners: [(
+                ConnectionKind::Submission,
+                concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scripts/submission.rhai").into(),
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let rule_engines = ListenersRuleEngineConfig::from_config(&config).unwrap();
+
+    assert_eq!(
+        run_connect(&rule_engines, ConnectionKind::Relay),
+        ReceiverStatus::Deny(Some("554 5.7.1 relay listener".parse().unwrap()))
+    );
+    assert_eq!(
+        run_connect(&rule_engines, ConnectionKind::Submission),
+        ReceiverStatus::Accept(Some("220 submission listener".parse().unwrap()))
+    );
+    // Listeners without a dedicated script use the default one.
+    assert_eq!(
+        run_connect(&rule_engines, ConnectionKind::Tunneled),
+        ReceiverStatus::Deny(Some("554 5.7.1 relay listener".parse().unwrap()))
+    );
+}
+
+#[test]
+fn connection_kind() {
+    let config = SMTPReceiverConfig {
+        scripts: Scripts {
+            path: concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/scripts/connection_kind.rhai"
+            )
+            .into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let rule_engines = ListenersRuleEngineConfig::from_config(&config).unwrap();
+
+    for (kind, expected) in [
+        (ConnectionKind::Relay, "220 relay 127.0.0.1:25"),
+        (ConnectionKind::Submission, "220 submission 127.0.0.1:25"),
+        (ConnectionKind::Tunneled, "220 tunneled 127.0.0.1:25"),
+    ] {
+        assert_eq!(
+            run_connect(&rule_engines, kind),
+            ReceiverStatus::Accept(Some(expected.parse().unwrap()))
+        );
+    }
+}
+
+/// Reply to HELO after a TLS handshake where the client presented `sni`.
+async fn helo_after_handshake(sni: Option<&str>) -> Reply {
+    let mut config = config("replay_accept.rhai");
+    config.scripts.tenants = [
+        ("a.example.com".parse().unwrap(), script("tenant_a.rhai")),
+        ("b.example.com".parse().unwrap(), script("tenant_b.rhai")),
+    ]
+    .into_iter()
+    .collect();
+    config.tls = Some(Tls {
+        root: Some(secret()),
+        r#virtual: ["a.example.com", "b.example.com", "c.example.com"]
+            .into_iter()
+            .map(|name| (name.parse().unwrap(), secret()))
+            .collect(),
+        ..Default::default()
+    });
+
+    let mut client = Client::relay(config)
+        .await
+        .starttls(sni, &[])
+        .await
+        .unwrap();
+    client.expect("HELO client.example.com\r\n", 250).await
+}
+
+#[tokio::test]
+async fn rules_selected_by_sni() {
+    for (sni, expected) in [
+        ("a.example.com", "250 tenant a a.example.com"),
+        ("b.example.com", "250 tenant b b.example.com"),
+        // the names are case insensitive.
+        ("B.Example.com", "250 tenant b"),
+    ] {
+        let reply = helo_after_handshake(Some(sni)).await;
+        assert!(reply.as_ref().starts_with(expected), "{reply:?}");
+    }
+}
+
+#[tokio::test]
+async fn unknown_sni_uses_the_listener_rules() {
+    for sni in [Some("c.example.com"), None] {
+        let reply = helo_after_handshake(sni).await;
+        assert!(!reply.as_ref().contains("tenant"), "{reply:?}");
+    }
+}
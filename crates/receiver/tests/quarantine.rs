@@ -0,0 +1,85 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use common::{config, Client, HEADERS};
+
+async fn send_to(recipient: &str, code: u16) -> Client {
+    let mut config = config("replay_quarantine.rhai");
+    config.quarantine.queues = ["virus".to_string(), "spam".to_string()]
+        .into_iter()
+        .collect();
+
+    let mut client = Client::relay(config).await;
+
+    client.expect("HELO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client
+        .expect(format!("RCPT TO:<{recipient}@example.net>\r\n"), 250)
+        .await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            format!("{HEADERS}Subject: quarantine\r\n\r\nHello\r\n.\r\n"),
+            code,
+        )
+        .await;
+
+    client
+}
+
+#[tokio::test]
+async fn allowed_queue() {
+    let client = send_to("virus", 250).await;
+
+    assert_eq!(client.received().len(), 1);
+    assert_eq!(client.received()[0].1.as_deref(), Some("virus"));
+}
+
+#[tokio::test]
+async fn unknown_queue() {
+    let mut client = send_to("virsu", 554).await;
+
+    client.assert_closed().await;
+    assert!(client.received().is_empty());
+}
+
+#[tokio::test]
+async fn reason_persisted() {
+    let client = send_to("spam", 250).await;
+
+    assert_eq!(client.received().len(), 1);
+    let received = client.received();
+    let (ctx, queue) = &received[0];
+    assert_eq!(queue.as_deref(), Some("spam"));
+
+    // the reason is carried with the context sent to the quarantine queue.
+    let reason = ctx.variables["quarantine_reason"]
+        .clone()
+        .try_cast::<vsmtp_rule_engine::rhai::Map>()
+        .unwrap();
+    assert_eq!(reason["rule"].clone().into_string().unwrap(), "quarantine");
+    assert_eq!(reason["score"].as_int().unwrap(), 7);
+    assert_eq!(reason["pattern"].clone().into_string().unwrap(), "^spam$");
+}
+
+#[tokio::test]
+async fn no_reason() {
+    let client = send_to("virus", 250).await;
+
+    assert!(!client.received()[0]
+        .0
+        .variables
+        .contains_key("quarantine_reason"));
+}
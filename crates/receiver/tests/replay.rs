@@ -0,0 +1,876 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use common::{config, secret, Client, Identity, HEADERS};
+use vsmtp_common::{domain_map::DomainMap, tls::ClientAuth};
+use vsmtp_protocol::{MimeBodyType, Reply};
+use vsmtp_receiver::smtp::{
+    backpressure::QueueDepths,
+    config::{Backpressure, Relay, SMTPReceiverConfig, Tls, UnknownParameters, Verify, VerifyMode},
+    directory::Directory,
+};
+
+#[tokio::test]
+async fn accept_path() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            [
+                HEADERS,
+                "Subject: replay\r\n",
+                "\r\n",
+                "..leading dot\r\n",
+                ".\r\n",
+            ]
+            .concat(),
+            250,
+        )
+        .await;
+    client.expect("QUIT\r\n", 221).await;
+
+    client.assert_closed().await;
+    assert_eq!(client.received().len(), 1);
+
+    let received = client.received();
+    let (ctx, quarantine) = &received[0];
+    assert!(quarantine.is_none());
+    let mail = ctx.metadata.get_mail(ToString::to_string).unwrap();
+    assert!(mail.contains("\r\n.leading dot\r\n"));
+}
+
+#[tokio::test]
+async fn deny_at_rcpt() {
+    let mut client = Client::relay(config("replay_deny_rcpt.rhai")).await;
+
+    client.expect("HELO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("NOOP\r\n", 250).await;
+
+    let reply = client.expect("RCPT TO:<jenny@example.net>\r\n", 554).await;
+    assert_eq!(reply.code().details(), Some("5.7.1"));
+    client.assert_closed().await;
+    assert!(client.received().is_empty());
+}
+
+/// Collect the fields of the "Recipient rejected" events.
+#[derive(Clone, Default)]
+struct RejectedRecipients(
+    std::sync::Arc<std::sync::Mutex<Vec<std::collections::HashMap<String, String>>>>,
+);
+
+#[derive(Default)]
+struct Fields(std::collections::HashMap<String, String>);
+
+impl tracing::field::Visit for Fields {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for RejectedRecipients {
+    fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        if fields.0.get("message").map(String::as_str) == Some("Recipient rejected") {
+            self.0.lock().unwrap().push(fields.0);
+        }
+    }
+}
+
+#[tokio::test]
+async fn deny_at_rcpt_is_logged() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let rejected = RejectedRecipients::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(rejected.clone()));
+
+    let mut client = Client::relay(config("replay_deny_rcpt.rhai")).await;
+
+    client.expect("HELO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 554).await;
+
+    let rejected = rejected.0.lock().unwrap();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0]["rcpt"], "jenny@example.net");
+    assert_eq!(rejected[0]["code"], "554");
+    assert_eq!(rejected[0]["enhanced_code"], "5.7.1");
+    assert_eq!(rejected[0]["rule"], "relaying denied");
+}
+
+/// Send a message with a valid sequence of commands, the session being greeted.
+async fn send_message(client: &mut Client) {
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            format!("{HEADERS}Subject: replay\r\n\r\nHello world!\r\n.\r\n"),
+            250,
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn message_size_on_the_wire() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+
+    // bare LF line endings are kept in the received bytes.
+    let message =
+        &format!("{HEADERS}Subject: size\r\n\r\nfirst line\nsecond line\r\n..leading dot\r\n");
+    let reply = client.expect(format!("{message}.\r\n"), 250).await;
+
+    // the dot added by the transparency procedure is not part of the message.
+    assert_eq!(
+        reply.lines().next().unwrap(),
+        &format!("message of {} bytes Ok", message.len() - 1)
+    );
+}
+
+/// Send `message` to a receiver accepting messages up to `message_size_limit` bytes.
+/// A rejected message ends the transaction, the session going on with the next command.
+async fn data_with_limit(message_size_limit: usize, message: &str, code: u16) -> Reply {
+    let mut client = Client::relay(SMTPReceiverConfig {
+        message_size_limit,
+        ..config("replay_accept.rhai")
+    })
+    .await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    let reply = client.expect(format!("{message}.\r\n"), code).await;
+    if code != 250 {
+        client.expect("RCPT TO:<jenny@example.net>\r\n", 503).await;
+    }
+    reply
+}
+
+#[tokio::test]
+async fn header_section_too_long() {
+    let message = [
+        "Subject: enormous header section\r\n",
+        &"X-Filler: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n".repeat(100),
+        "\r\n",
+        "Hello world!\r\n",
+    ]
+    .concat();
+
+    let reply = data_with_limit(1024, &message, 500).await;
+    assert_eq!(reply.code().details(), Some("5.5.6"));
+}
+
+#[tokio::test]
+async fn body_too_long() {
+    let message = [
+        HEADERS,
+        "Subject: enormous body\r\n",
+        "\r\n",
+        &"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n".repeat(100),
+    ]
+    .concat();
+
+    let reply = data_with_limit(1024, &message, 552).await;
+    assert_eq!(reply.code().details(), Some("5.3.4"));
+
+    // the same message is accepted with a larger limit.
+    data_with_limit(message.len() + 1, &message, 250).await;
+}
+
+#[tokio::test]
+async fn rcpt_before_mail() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 503).await;
+    client.expect("NOOP\r\n", 250).await;
+
+    send_message(&mut client).await;
+    client.expect("QUIT\r\n", 221).await;
+
+    assert_eq!(client.received().len(), 1);
+    let received = client.received();
+    let (ctx, _) = &received[0];
+    let rcpt = &ctx.metadata.get_rcpt_to().unwrap().recipient;
+    assert_eq!(rcpt.values().map(Vec::len).sum::<usize>(), 1);
+}
+
+#[tokio::test]
+async fn data_before_rcpt() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client.expect("DATA\r\n", 503).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("DATA\r\n", 503).await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            format!("{HEADERS}Subject: replay\r\n\r\nHello world!\r\n.\r\n"),
+            250,
+        )
+        .await;
+
+    assert_eq!(client.received().len(), 1);
+}
+
+#[tokio::test]
+async fn auth_after_mail() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("AUTH PLAIN AGpvaG4AcGFzcw==\r\n", 503).await;
+    // a nested transaction is refused too, keeping the current one.
+    client
+        .expect("MAIL FROM:<jenny@example.net>\r\n", 503)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            format!("{HEADERS}Subject: replay\r\n\r\nHello world!\r\n.\r\n"),
+            250,
+        )
+        .await;
+
+    assert_eq!(client.received().len(), 1);
+    let received = client.received();
+    let (ctx, _) = &received[0];
+    assert_eq!(
+        ctx.metadata
+            .get_mail_from()
+            .unwrap()
+            .reverse_path
+            .as_ref()
+            .map(ToString::to_string),
+        Some("john.doe@example.com".to_string())
+    );
+
+    // the next transaction starts from a clean state.
+    send_message(&mut client).await;
+    assert_eq!(client.received().len(), 2);
+}
+
+#[tokio::test]
+async fn eightbitmime_advertised() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    let reply = client.expect("EHLO client.example.com\r\n", 250).await;
+    assert!(reply.as_ref().contains("250-8BITMIME\r\n"));
+
+    let mut config = config("replay_accept.rhai");
+    config.esmtp.eightbitmime = false;
+    let mut client = Client::relay(config).await;
+
+    let reply = client.expect("EHLO client.example.com\r\n", 250).await;
+    assert!(!reply.as_ref().contains("8BITMIME"));
+    client
+        .expect("MAIL FROM:<john.doe@example.com> BODY=8BITMIME\r\n", 555)
+        .await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com> BODY=7BIT\r\n", 250)
+        .await;
+}
+
+#[tokio::test]
+async fn eightbitmime_body() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com> BODY=8BITMIME\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            format!("{HEADERS}Subject: caf\u{e9}\r\n\r\nd\u{e9}j\u{e0} vu\r\n.\r\n"),
+            250,
+        )
+        .await;
+
+    assert_eq!(client.received().len(), 1);
+    let received = client.received();
+    let (ctx, _) = &received[0];
+    assert_eq!(
+        ctx.metadata.get_mail_from().unwrap().mime_body_type,
+        Some(MimeBodyType::EightBitMime)
+    );
+}
+
+#[tokio::test]
+async fn eight_bit_body_declared_7bit() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com> BODY=7BIT\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    let reply = client
+        .expect(
+            format!("{HEADERS}Subject: caf\u{e9}\r\n\r\nd\u{e9}j\u{e0} vu\r\n.\r\n"),
+            554,
+        )
+        .await;
+    assert_eq!(reply.code().details(), Some("5.6.0"));
+    assert!(client.received().is_empty());
+
+    // the transaction is aborted, a 7-bit message can be sent.
+    client
+        .expect("MAIL FROM:<john.doe@example.com> BODY=7BIT\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            format!("{HEADERS}Subject: replay\r\n\r\nHello world!\r\n.\r\n"),
+            250,
+        )
+        .await;
+    assert_eq!(client.received().len(), 1);
+}
+
+/// Send a recipient, expecting `code`.
+async fn rcpt_to(script: &str, rcpt: &str, code: u16) -> Reply {
+    let mut client = Client::relay(config(script)).await;
+
+    client.expect("HELO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect(format!("RCPT TO:<{rcpt}>\r\n"), code).await
+}
+
+#[tokio::test]
+async fn unknown_recipient_lookup() {
+    rcpt_to("replay_recipients_lookup.rhai", "jenny@example.net", 250).await;
+    let reply = rcpt_to("replay_recipients_lookup.rhai", "unknown@example.net", 550).await;
+    assert_eq!(reply.code().details(), Some("5.1.1"));
+}
+
+#[tokio::test]
+async fn unknown_recipient_file() {
+    rcpt_to("replay_recipients_file.rhai", "jenny@example.net", 250).await;
+    rcpt_to("replay_recipients_file.rhai", "anyone@example.org", 250).await;
+    rcpt_to("replay_recipients_file.rhai", "unknown@example.net", 550).await;
+}
+
+/// Send a parameter not supported by vSMTP under the `policy` for the unknown parameters.
+async fn unknown_parameter(policy: UnknownParameters, code: u16) -> Client {
+    let mut config = config("replay_accept.rhai");
+    config.esmtp.unknown_parameters = policy;
+    let mut client = Client::relay(config).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com> XFOO=bar\r\n", code)
+        .await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client
+        .expect("RCPT TO:<jenny@example.net> X-OPTION\r\n", code)
+        .await;
+
+    client
+}
+
+#[tokio::test]
+async fn unknown_parameter_rejected() {
+    let mut client = unknown_parameter(UnknownParameters::Reject, 555).await;
+
+    let reply = client
+        .expect("RCPT TO:<jenny@example.net> NOTIFY=NEVER XFOO=bar\r\n", 555)
+        .await;
+    assert_eq!(reply.code().details(), Some("5.5.4"));
+    assert!(reply.as_ref().contains("XFOO=bar"));
+
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("NOOP\r\n", 250).await;
+}
+
+#[tokio::test]
+async fn unknown_parameter_ignored() {
+    let mut client = unknown_parameter(UnknownParameters::Ignore, 250).await;
+
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            format!("{HEADERS}Subject: replay\r\n\r\nHello world!\r\n.\r\n"),
+            250,
+        )
+        .await;
+    assert_eq!(client.received().len(), 1);
+}
+
+#[tokio::test]
+async fn connection_age_and_command_count() {
+    let mut client = Client::relay(config("replay_session.rhai")).await;
+
+    client.expect("HELO client.example.com\r\n", 250).await;
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    client.expect("NOOP\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client
+        .expect(
+            format!("{HEADERS}Subject: replay\r\n\r\nHello world!\r\n.\r\n"),
+            250,
+        )
+        .await;
+
+    let received = client.received();
+    let variables = &received[0].0.variables;
+    let age = |name: &str| variables[name].as_float().unwrap();
+    let commands = |name: &str| variables[name].as_int().unwrap();
+
+    assert!(age("connect_age") <= age("helo_age"));
+    assert!(age("pre_queue_age") - age("helo_age") >= 0.02);
+
+    assert_eq!(commands("connect_commands"), 0);
+    assert_eq!(commands("helo_commands"), 1);
+    // HELO, NOOP, MAIL FROM, RCPT TO and DATA.
+    assert_eq!(commands("pre_queue_commands"), 5);
+    // the count of the session is not sent with the message.
+    assert!(!received[0].0.internal.contains_key("command_count"));
+}
+
+/// A tunneled listener offering the `smtp` protocol with ALPN.
+fn alpn_config(require_alpn: bool) -> SMTPReceiverConfig {
+    SMTPReceiverConfig {
+        tls: Some(Tls {
+            root: Some(secret()),
+            alpn: vec!["smtp".to_string()],
+            require_alpn,
+            ..Default::default()
+        }),
+        ..config("replay_alpn.rhai")
+    }
+}
+
+#[tokio::test]
+async fn alpn_protocol() {
+    let mut client = Client::tunneled(alpn_config(false), None, &["smtp"])
+        .await
+        .unwrap();
+
+    assert_eq!(client.read_reply().await.unwrap().code().value(), 220);
+    let reply = client.expect("HELO client.example.com\r\n", 250).await;
+    assert!(reply.as_ref().starts_with("250 alpn smtp"), "{reply:?}");
+}
+
+#[tokio::test]
+async fn alpn_restricted() {
+    // a protocol not offered by the server fails the handshake.
+    assert!(Client::tunneled(alpn_config(false), None, &["h2"])
+        .await
+        .is_err());
+
+    // no protocol negotiated.
+    let mut client = Client::tunneled(alpn_config(true), None, &[])
+        .await
+        .unwrap();
+    assert_eq!(client.read_reply().await.unwrap().code().value(), 554);
+    client.assert_closed().await;
+
+    let mut client = Client::tunneled(alpn_config(false), None, &[])
+        .await
+        .unwrap();
+    assert_eq!(client.read_reply().await.unwrap().code().value(), 220);
+    client.expect("NOOP\r\n", 250).await;
+}
+
+/// A tunneled listener verifying the client certificates with the `certs/clientCA.crt` authority.
+fn client_auth_config(required: bool) -> SMTPReceiverConfig {
+    SMTPReceiverConfig {
+        tls: Some(Tls {
+            root: Some(secret()),
+            // the client certificates are verified before the handshake completes on the client.
+            protocol_version: vec!["TLSv1.2".parse().unwrap()],
+            client_auth: Some(ClientAuth {
+                authorities: include_str!("certs/clientCA.crt").parse().unwrap(),
+                required,
+            }),
+            ..Default::default()
+        }),
+        ..config("replay_client_certificate.rhai")
+    }
+}
+
+#[tokio::test]
+async fn client_certificate_verified() {
+    let mut client = Client::tunneled_as(client_auth_config(false), Identity::john_doe())
+        .await
+        .unwrap();
+
+    assert_eq!(client.read_reply().await.unwrap().code().value(), 220);
+    let reply = client.expect("HELO client.example.com\r\n", 250).await;
+    assert!(
+        reply
+            .as_ref()
+            .starts_with("250 john.doe john.doe@example.com"),
+        "{reply:?}"
+    );
+}
+
+#[tokio::test]
+async fn client_certificate_untrusted() {
+    assert!(
+        Client::tunneled_as(client_auth_config(false), Identity::untrusted())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn client_certificate_missing() {
+    let mut client = Client::tunneled(client_auth_config(false), None, &[])
+        .await
+        .unwrap();
+    assert_eq!(client.read_reply().await.unwrap().code().value(), 220);
+    let reply = client.expect("HELO client.example.com\r\n", 250).await;
+    assert!(reply.as_ref().starts_with("250 anonymous"), "{reply:?}");
+
+    assert!(Client::tunneled(client_auth_config(true), None, &[])
+        .await
+        .is_err());
+}
+
+async fn with_certificates(tls: Tls) -> Client {
+    Client::relay(SMTPReceiverConfig {
+        tls: Some(tls),
+        ..config("replay_accept.rhai")
+    })
+    .await
+}
+
+fn advertises_starttls(reply: &Reply) -> bool {
+    reply.lines().any(|line| line.starts_with("STARTTLS"))
+}
+
+#[tokio::test]
+async fn starttls_with_certificate() {
+    let mut client = with_certificates(Tls {
+        root: Some(secret()),
+        ..Default::default()
+    })
+    .await;
+    let reply = client.expect("EHLO client.example.com\r\n", 250).await;
+    assert!(advertises_starttls(&reply), "{reply:?}");
+
+    // a virtual certificate only, for the clients requesting its name.
+    let mut client = with_certificates(Tls {
+        r#virtual: [("mail.example.org".parse().unwrap(), secret())].into(),
+        ..Default::default()
+    })
+    .await;
+    let reply = client.expect("EHLO client.example.com\r\n", 250).await;
+    assert!(advertises_starttls(&reply), "{reply:?}");
+
+    // not advertised once TLS is active.
+    let mut client = client
+        .starttls(Some("mail.example.org"), &[])
+        .await
+        .unwrap();
+    let reply = client.expect("EHLO client.example.com\r\n", 250).await;
+    assert!(!advertises_starttls(&reply), "{reply:?}");
+    client.expect("STARTTLS\r\n", 554).await;
+}
+
+#[tokio::test]
+async fn starttls_without_certificate() {
+    let mut client = with_certificates(Tls::default()).await;
+    let reply = client.expect("EHLO client.example.com\r\n", 250).await;
+    assert!(!advertises_starttls(&reply), "{reply:?}");
+    client.expect("STARTTLS\r\n", 454).await;
+
+    // no certificate for the name requested.
+    let client = with_certificates(Tls {
+        r#virtual: [("mail.example.org".parse().unwrap(), secret())].into(),
+        ..Default::default()
+    })
+    .await;
+    assert!(client
+        .starttls(Some("mail.example.net"), &[])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn vrfy_expn_disabled() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client.expect("VRFY jenny@example.net\r\n", 252).await;
+    client.expect("EXPN staff@example.net\r\n", 502).await;
+    client.expect("VRFY\r\n", 501).await;
+}
+
+#[tokio::test]
+async fn vrfy_enabled() {
+    let directory = Directory::try_from(common::script("directory.txt")).unwrap();
+    let mut client = Client::relay(SMTPReceiverConfig {
+        verify: Verify {
+            vrfy: VerifyMode::Enabled,
+            expn: VerifyMode::Rejected,
+            directory: Some(directory),
+        },
+        ..config("replay_accept.rhai")
+    })
+    .await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    let reply = client.expect("VRFY <Jenny@example.net>\r\n", 250).await;
+    assert_eq!(reply.as_ref(), "250 2.1.5 <jenny@example.net>\r\n");
+    let reply = client.expect("VRFY unknown@example.net\r\n", 550).await;
+    assert_eq!(reply.code().details(), Some("5.1.1"));
+    client.expect("EXPN staff@example.net\r\n", 502).await;
+}
+
+#[tokio::test]
+async fn lagging_queue_defers_recipients() {
+    let queue_depths = QueueDepths::default();
+    queue_depths.set_waiting("slow", 20_000);
+    queue_depths.set_deferred("slow", 5_000);
+    queue_depths.set_waiting("basic", 10);
+
+    let mut client = Client::relay_with_depths(
+        SMTPReceiverConfig {
+            backpressure: Backpressure {
+                routing_keys: [
+                    ("*.slow.example".to_string(), "slow".to_string()),
+                    ("*".to_string(), "basic".to_string()),
+                ]
+                .into_iter()
+                .collect::<DomainMap<_>>(),
+                ..Default::default()
+            },
+            ..config("replay_accept.rhai")
+        },
+        Some(queue_depths.clone()),
+    )
+    .await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    let reply = client
+        .expect("RCPT TO:<jenny@mx.slow.example>\r\n", 451)
+        .await;
+    assert_eq!(reply.code().details(), Some("4.3.1"));
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+
+    // the recipients are accepted again once the backlog has drained.
+    queue_depths.set_waiting("slow", 100);
+    queue_depths.set_deferred("slow", 900);
+    client
+        .expect("RCPT TO:<jenny@mx.slow.example>\r\n", 250)
+        .await;
+    client.expect("NOOP\r\n", 250).await;
+}
+
+/// A receiver trusting `trusted_network`, with `example.com` as local domain.
+async fn relay(trusted_network: &str) -> Client {
+    Client::relay(SMTPReceiverConfig {
+        relay: Relay {
+            trusted_networks: vec![trusted_network.parse().unwrap()],
+            local_domains: ["example.com".parse().unwrap()].into(),
+        },
+        ..config("replay_relay.rhai")
+    })
+    .await
+}
+
+#[tokio::test]
+async fn relay_from_trusted_network() {
+    let mut client = relay("127.0.0.0/8").await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    let reply = client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    assert_eq!(reply.lines().next().unwrap(), "Trusted client");
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("NOOP\r\n", 250).await;
+}
+
+// the SASL exchange blocks a worker thread.
+#[tokio::test(flavor = "multi_thread")]
+async fn relay_from_authenticated_client() {
+    let mut client = relay("192.168.0.0/16").await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    // "\0john.doe\0s3cr3t"
+    client
+        .expect("AUTH PLAIN AGpvaG4uZG9lAHMzY3IzdA==\r\n", 235)
+        .await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("NOOP\r\n", 250).await;
+}
+
+#[tokio::test]
+async fn relay_from_untrusted_client_denied() {
+    let mut client = relay("192.168.0.0/16").await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.net>\r\n", 250)
+        .await;
+    // the local domains are not relayed.
+    client.expect("RCPT TO:<jenny@example.com>\r\n", 250).await;
+    let reply = client.expect("RCPT TO:<jenny@example.net>\r\n", 554).await;
+    assert_eq!(reply.code().details(), Some("5.7.1"));
+}
+
+/// A multipart message with `count` text parts.
+fn multipart(count: usize) -> String {
+    let parts = (0..count)
+        .map(|i| format!("--frontier\r\nContent-Type: text/plain\r\n\r\npart {i}\r\n"))
+        .collect::<String>();
+
+    [
+        HEADERS,
+        "Subject: parts\r\n",
+        "MIME-Version: 1.0\r\n",
+        "Content-Type: multipart/mixed; boundary=\"frontier\"\r\n",
+        "\r\n",
+        &parts,
+        "--frontier--\r\n",
+        ".\r\n",
+    ]
+    .concat()
+}
+
+#[tokio::test]
+async fn too_many_mime_parts() {
+    let mut client = Client::relay(SMTPReceiverConfig {
+        max_mime_parts: 3,
+        ..config("replay_accept.rhai")
+    })
+    .await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    let reply = client.expect(multipart(4), 554).await;
+    assert_eq!(reply.code().details(), Some("5.6.0"));
+
+    // the transaction is reset, a message at the limit is accepted.
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    client.expect(multipart(3), 250).await;
+
+    assert_eq!(client.received().len(), 1);
+}
+
+#[tokio::test]
+async fn missing_mandatory_header() {
+    let mut client = Client::relay(config("replay_accept.rhai")).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    client
+        .expect("MAIL FROM:<john.doe@example.com>\r\n", 250)
+        .await;
+    client.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    client.expect("DATA\r\n", 354).await;
+    let reply = client
+        .expect("Subject: no sender\r\n\r\nbody\r\n.\r\n", 554)
+        .await;
+    assert_eq!(reply.code().details(), Some("5.6.0"));
+
+    // the session goes on.
+    client.expect("NOOP\r\n", 250).await;
+    client.expect("QUIT\r\n", 221).await;
+    assert!(client.received().is_empty());
+}
+
+#[tokio::test]
+async fn raw_parameters() {
+    let mut config = config("replay_parameters.rhai");
+    config.esmtp.unknown_parameters = UnknownParameters::Ignore;
+    let mut client = Client::relay(config).await;
+
+    client.expect("EHLO client.example.com\r\n", 250).await;
+    let reply = client
+        .expect(
+            "MAIL FROM:<john.doe@example.com> SIZE=1000 XFOO=bar\r\n",
+            250,
+        )
+        .await;
+    assert_eq!(
+        reply.lines().collect::<Vec<_>>(),
+        ["parameters: [SIZE=1000 XFOO=bar]"]
+    );
+
+    let reply = client
+        .expect("RCPT TO:<jenny@example.net> NOTIFY=NEVER X-OPTION\r\n", 250)
+        .await;
+    assert_eq!(
+        reply.lines().collect::<Vec<_>>(),
+        ["parameters: [NOTIFY=NEVER X-OPTION]"]
+    );
+
+    let reply = client.expect("RCPT TO:<jenny@example.org>\r\n", 250).await;
+    assert_eq!(reply.lines().collect::<Vec<_>>(), ["parameters: []"]);
+}
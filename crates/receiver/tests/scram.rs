@@ -0,0 +1,155 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::{config, Client, HEADERS};
+use vsmtp_protocol::{
+    auth::{Credentials, Mechanism},
+    rsasl::{
+        config::SASLConfig,
+        prelude::{Mechname, SASLClient, Session},
+    },
+    Reply,
+};
+use vsmtp_receiver::smtp::config::{Auth, AuthLockout};
+
+// NOTE: the tests run on a multi-threaded runtime, the SASL exchange blocking a worker thread.
+async fn connect() -> Client {
+    let mut config = config("replay_scram.rhai");
+    config.esmtp.auth = Some(Auth {
+        enable_dangerous_mechanism_in_clair: false,
+        mechanisms: vec![Mechanism::ScramSha256, Mechanism::CramMd5],
+        attempt_count_max: -1,
+        max_failures: 1,
+        on_max_failures: AuthLockout::Close,
+    });
+
+    let mut smtp = Client::relay(config).await;
+
+    let ehlo = smtp.expect("EHLO client.example.com\r\n", 250).await;
+    assert!(ehlo
+        .lines()
+        .any(|line| line == "AUTH SCRAM-SHA-256 CRAM-MD5"));
+
+    smtp
+}
+
+fn client(authid: &str, password: &str) -> Session {
+    SASLClient::new(
+        SASLConfig::with_credentials(None, authid.to_string(), password.to_string()).unwrap(),
+    )
+    .start_suggested(&[Mechname::parse(b"SCRAM-SHA-256").unwrap()])
+    .unwrap()
+}
+
+/// Run a step of the client with the challenge of the smtp, returning the encoded response.
+fn respond(client: &mut Session, challenge: Option<&Reply>) -> String {
+    let challenge = challenge.map(|reply| {
+        assert_eq!(reply.code().value(), 334);
+        STANDARD
+            .decode(reply.lines().next().unwrap().trim())
+            .unwrap()
+    });
+
+    let mut response = vec![];
+    client.step(challenge.as_deref(), &mut response).unwrap();
+    STANDARD.encode(response)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scram_sha_256_handshake() {
+    let mut smtp = connect().await;
+    let mut client = client("user", "pencil");
+
+    let client_first = respond(&mut client, None);
+    let server_first = smtp
+        .send(format!("AUTH SCRAM-SHA-256 {client_first}\r\n"))
+        .await;
+
+    let client_final = respond(&mut client, Some(&server_first));
+    let server_final = smtp.send(format!("{client_final}\r\n")).await;
+
+    // the client checks the signature of the server.
+    respond(&mut client, Some(&server_final));
+    assert!(!client.is_running());
+    smtp.expect("\r\n", 235).await;
+
+    smtp.expect("MAIL FROM:<user@example.com>\r\n", 250).await;
+    smtp.expect("RCPT TO:<jenny@example.net>\r\n", 250).await;
+    smtp.expect("DATA\r\n", 354).await;
+    smtp.expect(
+        format!("{HEADERS}Subject: scram\r\n\r\nHello\r\n.\r\n"),
+        250,
+    )
+    .await;
+
+    let received = smtp.received();
+    let (ctx, _) = &received[0];
+    let sasl = ctx.metadata.get_connect().sasl.as_ref().unwrap();
+    assert!(sasl.is_authenticated);
+    assert_eq!(sasl.mechanism, Mechanism::ScramSha256);
+    assert_eq!(
+        sasl.credentials,
+        Credentials::ChallengeResponse {
+            authid: "user".to_string()
+        }
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scram_sha_256_wrong_password() {
+    let mut smtp = connect().await;
+    let mut client = client("user", "pencils");
+
+    let client_first = respond(&mut client, None);
+    let server_first = smtp
+        .send(format!("AUTH SCRAM-SHA-256 {client_first}\r\n"))
+        .await;
+
+    let client_final = respond(&mut client, Some(&server_first));
+    let mut reply = smtp.send(format!("{client_final}\r\n")).await;
+    // the server may send the error to the client before replying.
+    if reply.code().value() == 334 {
+        reply = smtp.send("\r\n").await;
+    }
+
+    assert_eq!(reply.code().value(), 535);
+    smtp.assert_closed().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scram_sha_256_unknown_user() {
+    let mut smtp = connect().await;
+    let mut client = client("john.doe", "pencil");
+
+    let client_first = respond(&mut client, None);
+    smtp.expect(format!("AUTH SCRAM-SHA-256 {client_first}\r\n"), 535)
+        .await;
+    smtp.assert_closed().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cram_md5_wrong_digest() {
+    let mut smtp = connect().await;
+
+    smtp.expect("AUTH CRAM-MD5\r\n", 334).await;
+    smtp.expect(
+        format!(
+            "{}\r\n",
+            STANDARD.encode("tim 00000000000000000000000000000000")
+        ),
+        535,
+    )
+    .await;
+    smtp.assert_closed().await;
+}
@@ -0,0 +1,151 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_common::{
+    ctx::Ctx,
+    delivery_route::DeliveryRoute,
+    stateful_ctx_received::{ConnectProps, StatefulCtxReceived},
+    Mailbox, Recipient,
+};
+use vsmtp_mail_parser::Mail;
+use vsmtp_protocol::{Address, ClientName, ConnectionKind, NotifyOn};
+use vsmtp_receiver::smtp::{
+    config::SMTPReceiverConfig,
+    rules::{engine::build_rule_engine_config, stages::ReceiverStage, status::ReceiverStatus},
+};
+use vsmtp_rule_engine::RuleEngine;
+
+const STAGES: [ReceiverStage; 5] = [
+    ReceiverStage::Connect,
+    ReceiverStage::Helo,
+    ReceiverStage::MailFrom,
+    ReceiverStage::RcptTo,
+    ReceiverStage::PreQueue,
+];
+
+fn simulate(
+    script: &str,
+    fixture: &str,
+) -> (
+    Vec<(ReceiverStage, ReceiverStatus)>,
+    Ctx<StatefulCtxReceived>,
+) {
+    let rule_engine_config = build_rule_engine_config(
+        &SMTPReceiverConfig::default(),
+        &std::path::PathBuf::from_iter([env!("CARGO_MANIFEST_DIR"), "tests/scripts", script]),
+    )
+    .unwrap();
+
+    let message = std::fs::read_to_string(std::path::PathBuf::from_iter([
+        env!("CARGO_MANIFEST_DIR"),
+        "tests/mail",
+        fixture,
+    ]))
+    .unwrap();
+
+    let mut metadata = StatefulCtxReceived::new(ConnectProps {
+        connect_timestamp: vsmtp_common::time::OffsetDateTime::now_utc(),
+        connect_uuid: vsmtp_common::uuid::Uuid::new_v4(),
+        client_addr: "127.0.0.1:49152".parse().unwrap(),
+        server_addr: "127.0.0.1:25".parse().unwrap(),
+        server_name: "testserver.com".parse().unwrap(),
+        kind: ConnectionKind::Relay,
+        sasl: None,
+        iprev: None,
+        tls: None,
+    });
+    metadata
+        .set_helo(ClientName::Domain("client.com".parse().unwrap()), false)
+        .unwrap()
+        .set_mail_from(
+            Some(Mailbox(Address::new_unchecked(
+                "john.doe@example.com".to_string(),
+            ))),
+            None,
+            None,
+            None,
+        )
+        .unwrap()
+        .set_rcpt_to(
+            DeliveryRoute::Basic,
+            Recipient {
+                forward_path: Mailbox(Address::new_unchecked("jenny@example.net".to_string())),
+                original_forward_path: None,
+                notify_on: NotifyOn::Never,
+            },
+        )
+        .unwrap()
+        .set_complete(Mail::try_from(message.as_str()).unwrap())
+        .unwrap();
+
+    RuleEngine::simulate(
+        rule_engine_config.into(),
+        Ctx {
+            variables: std::collections::HashMap::default(),
+            internal: std::collections::HashMap::default(),
+            metadata,
+        },
+        STAGES,
+    )
+}
+
+#[test]
+fn simulate_spam() {
+    let (statuses, ctx) = simulate("simulate.rhai", "spam.eml");
+
+    assert_eq!(
+        statuses,
+        [
+            (ReceiverStage::Connect, ReceiverStatus::Next),
+            (ReceiverStage::Helo, ReceiverStatus::Next),
+            (ReceiverStage::MailFrom, ReceiverStatus::Next),
+            (ReceiverStage::RcptTo, ReceiverStatus::Next),
+            (
+                ReceiverStage::PreQueue,
+                ReceiverStatus::Deny(Some("554 5.7.1 Message looks like spam".parse().unwrap()))
+            ),
+        ]
+    );
+    assert!(ctx
+        .metadata
+        .get_mail(|mail| mail.get_header("X-Simulated").is_some())
+        .unwrap());
+}
+
+#[test]
+fn simulate_ham() {
+    let (statuses, _) = simulate("simulate.rhai", "ham.eml");
+
+    assert_eq!(
+        statuses.iter().map(|(stage, _)| *stage).collect::<Vec<_>>(),
+        STAGES
+    );
+    assert_eq!(statuses[4].1, ReceiverStatus::Accept(None));
+}
+
+#[test]
+fn simulate_stops_at_deny() {
+    let (statuses, ctx) = simulate("simulate_deny.rhai", "ham.eml");
+
+    assert_eq!(
+        statuses,
+        [
+            (ReceiverStage::Connect, ReceiverStatus::Next),
+            (ReceiverStage::Helo, ReceiverStatus::Next),
+            (ReceiverStage::MailFrom, ReceiverStatus::Deny(None)),
+        ]
+    );
+    // the stages after the denial are not run.
+    assert!(ctx
+        .metadata
+        .get_mail(|mail| mail.get_header("X-Simulated").is_none())
+        .unwrap());
+}
@@ -0,0 +1,230 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::{mime::root, Result};
+use crate::api::docs::Ctx;
+use rhai::plugin::{
+    Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult, TypeId,
+};
+use vsmtp_mail_parser::{
+    mail::addresses::Address,
+    mime::{Mime, Part},
+};
+
+pub use anomaly::*;
+
+const EXECUTABLE: &[&str] = &[
+    "application/x-msdownload",
+    "application/x-msdos-program",
+    "application/x-dosexec",
+    "application/x-executable",
+];
+
+/// Content types expected for the file extensions commonly found in attachments.
+const EXTENSIONS: &[(&str, &[&str])] = &[
+    ("pdf", &["application/pdf"]),
+    ("jpg", &["image/jpeg"]),
+    ("jpeg", &["image/jpeg"]),
+    ("png", &["image/png"]),
+    ("gif", &["image/gif"]),
+    ("txt", &["text/plain"]),
+    ("htm", &["text/html"]),
+    ("html", &["text/html"]),
+    ("zip", &["application/zip", "application/x-zip-compressed"]),
+    ("doc", &["application/msword"]),
+    (
+        "docx",
+        &["application/vnd.openxmlformats-officedocument.wordprocessingml.document"],
+    ),
+    ("xls", &["application/vnd.ms-excel"]),
+    (
+        "xlsx",
+        &["application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"],
+    ),
+    ("exe", EXECUTABLE),
+    ("scr", EXECUTABLE),
+    ("com", EXECUTABLE),
+    ("bat", EXECUTABLE),
+];
+
+/// Is the value of a `Message-ID` header a valid `<id-left@id-right>` identifier ?
+/// <https://www.rfc-editor.org/rfc/rfc5322#section-3.6.4>
+fn is_msg_id(value: &str) -> bool {
+    value
+        .trim()
+        .strip_prefix('<')
+        .and_then(|id| id.strip_suffix('>'))
+        .and_then(|id| id.split_once('@'))
+        .map_or(false, |(left, right)| !left.is_empty() && !right.is_empty())
+}
+
+/// Does the display name of `address` contain an address of another domain ?
+fn is_display_name_spoofed(address: &Address) -> bool {
+    let Some(display_name) = &address.display_name else {
+        return false;
+    };
+    let domain = address.domain().trim_end_matches('.');
+
+    display_name
+        .split(|c: char| c.is_whitespace() || "<>()\"',;".contains(c))
+        .filter_map(|word| word.rsplit_once('@'))
+        .map(|(_, other)| other.trim_end_matches('.'))
+        .any(|other| !other.is_empty() && !other.eq_ignore_ascii_case(domain))
+}
+
+/// Is the `filename` of an attachment declared with a `content_type` which does not match
+/// its extension ? The generic `application/octet-stream` matches every extension.
+fn is_type_mismatch(content_type: &str, filename: &str) -> bool {
+    let Some((_, extension)) = filename.rsplit_once('.') else {
+        return false;
+    };
+    if content_type == "application/octet-stream" {
+        return false;
+    }
+
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension.trim()))
+        .map_or(false, |(_, expected)| !expected.contains(&content_type))
+}
+
+/// Does any leaf part of `mime` have a file name which does not match its content type ?
+fn has_type_mismatch(mime: &Mime) -> bool {
+    match &mime.part {
+        Part::Multipart(multipart) => multipart.parts.iter().any(has_type_mismatch),
+        _ => mime.filename().map_or(false, |filename| {
+            is_type_mismatch(&mime.content_type(), &filename)
+        }),
+    }
+}
+
+/// Detect the anomalies commonly found in the headers of unsolicited emails.
+/// Each function returns a boolean, so the rules can compose a score.
+#[rhai::plugin::export_module]
+mod anomaly {
+
+    /// Is the `Message-ID` header missing or malformed ?
+    /// Legitimate mail user agents always add a `<id-left@id-right>` identifier.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     let score = 0;
+    ///     if ctx.missing_message_id() { score += 2; }
+    ///     ctx.set_var("spam_score", score);
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:1
+    #[rhai_fn(global, return_raw)]
+    pub fn missing_message_id(ctx: &mut Ctx) -> Result<bool> {
+        Ok(ctx.read(|ctx| {
+            ctx.metadata.get_mail(|mail| {
+                mail.get_header("Message-ID")
+                    .map_or(true, |header| !is_msg_id(&header.body))
+            })
+        })?)
+    }
+
+    /// Does the display name of the `From` header contain an address of another
+    /// domain than the address of the author ?
+    /// (e.g. `From: "service@bank.example" <phishing@example.com>`)
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     if ctx.from_display_name_mismatch() {
+    ///         return status::quarantine("phishing");
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:2
+    #[rhai_fn(global, return_raw)]
+    pub fn from_display_name_mismatch(ctx: &mut Ctx) -> Result<bool> {
+        Ok(ctx.read(|ctx| {
+            ctx.metadata.get_mail(|mail| {
+                mail.header_addresses("From")
+                    .first()
+                    .map_or(false, is_display_name_spoofed)
+            })
+        })?)
+    }
+
+    /// Has the message gone through more than `max` hops, counted with the `Received` headers ?
+    ///
+    /// # Args
+    ///
+    /// * `max` - The number of `Received` headers above which the message is suspicious.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     if ctx.too_many_received(15) {
+    ///         return status::deny();
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:3
+    #[rhai_fn(global, return_raw)]
+    pub fn too_many_received(ctx: &mut Ctx, max: rhai::INT) -> Result<bool> {
+        Ok(ctx.read(|ctx| {
+            ctx.metadata.get_mail(|mail| {
+                rhai::INT::try_from(mail.get_headers("Received").count())
+                    .map_or(true, |count| count > max)
+            })
+        })?)
+    }
+
+    /// Is an attachment named with an extension which does not match its content type ?
+    /// (e.g. `invoice.pdf.exe` declared as `application/pdf`)
+    /// The parts declared as `application/octet-stream` are not reported.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     if ctx.attachment_type_mismatch() {
+    ///         return status::quarantine("suspicious-attachment");
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:4
+    #[rhai_fn(global, return_raw)]
+    pub fn attachment_type_mismatch(ctx: &mut Ctx) -> Result<bool> {
+        Ok(root(ctx)?.map_or(false, |mime| has_type_mismatch(&mime)))
+    }
+}
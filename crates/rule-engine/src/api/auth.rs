@@ -125,8 +125,13 @@ impl AuthMechanism {
 
             let dmarc = dmarc.map(|dmarc| {
                 format!(
-                    "\tdmarc={} header.from={};",
-                    dmarc.value, dmarc.rfc5322_from_domain
+                    "\tdmarc={} header.from={}{};",
+                    dmarc.value,
+                    dmarc.rfc5322_from_domain,
+                    dmarc
+                        .disposition
+                        .map(|disposition| format!(" policy.dmarc={disposition}"))
+                        .unwrap_or_default()
                 )
             });
 
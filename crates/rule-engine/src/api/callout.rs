@@ -0,0 +1,223 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Result;
+use crate::block_on;
+use rhai::plugin::{
+    mem, Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult,
+    TypeId,
+};
+use vsmtp_common::{
+    callout::Verdict,
+    dns_resolver::DnsResolver,
+    hickory_resolver::{self, error::ResolveErrorKind},
+    timeouts::Timeouts,
+    Mailbox,
+};
+use vsmtp_protocol::{ClientName, Domain};
+
+pub use callout::*;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Params {
+    #[serde(deserialize_with = "super::deserialize_dns_resolver")]
+    dns_resolver: std::sync::Arc<DnsResolver>,
+    client_name: String,
+    #[serde(default = "default_ttl", with = "humantime_serde")]
+    ttl: std::time::Duration,
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(default)]
+    timeouts: Timeouts,
+}
+
+const fn default_ttl() -> std::time::Duration {
+    std::time::Duration::from_secs(60 * 60)
+}
+
+const fn default_port() -> u16 {
+    25
+}
+
+/// Verify recipients with the callouts, see `callout::verifier`.
+pub struct Verifier {
+    callout: vsmtp_common::callout::Callout,
+    dns_resolver: std::sync::Arc<DnsResolver>,
+    port: u16,
+}
+
+impl Verifier {
+    /// Get the address of the mail exchanger of `domain` with the highest priority,
+    /// or of the domain itself if it has no MX record (see RFC 5321 section 5.1).
+    async fn mail_exchanger(
+        &self,
+        domain: &Domain,
+    ) -> std::result::Result<std::net::IpAddr, String> {
+        let host = match self
+            .dns_resolver
+            .resolver
+            .mx_lookup::<hickory_resolver::Name>(domain.clone().into())
+            .await
+        {
+            Ok(records) => records
+                .into_iter()
+                .min_by_key(hickory_resolver::proto::rr::rdata::MX::preference)
+                .map(|mx| mx.exchange().clone())
+                .ok_or_else(|| format!("no MX record found for '{domain}'"))?,
+            Err(error) if matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                domain.clone().into()
+            }
+            Err(error) => return Err(error.to_string()),
+        };
+
+        self.dns_resolver
+            .resolver
+            .lookup_ip(host.clone())
+            .await
+            .map_err(|error| error.to_string())?
+            .iter()
+            .next()
+            .ok_or_else(|| format!("no address found for '{host}'"))
+    }
+
+    async fn verify(&self, rcpt: &Mailbox) -> Verdict {
+        if let Some(verdict) = self.callout.cached(rcpt) {
+            return verdict;
+        }
+
+        let domain = rcpt.domain();
+        match self.mail_exchanger(&domain).await {
+            Ok(ip) => {
+                self.callout
+                    .verify(std::net::SocketAddr::new(ip, self.port), rcpt)
+                    .await
+            }
+            Err(error) => Verdict::Unknown(error),
+        }
+    }
+}
+
+/// Verification of the recipients by their mail exchanger (callout).
+#[rhai::plugin::export_module]
+mod callout {
+
+    /// A recipient verifier created with `callout::verifier`.
+    ///
+    /// # rhai-autodocs:index:1
+    pub type Callout = std::sync::Arc<Verifier>;
+
+    /// Create a recipient verifier.
+    ///
+    /// To verify a recipient, a session is opened to the mail exchanger of its domain,
+    /// `MAIL FROM:<>` and `RCPT TO:<recipient>` are issued and the session is closed
+    /// without sending any message. The verdicts are cached.
+    ///
+    /// # Args
+    ///
+    /// a map composed of the following parameters:
+    /// * `dns_resolver` - The DNS resolver used to find the mail exchangers. (see the `dns` module)
+    /// * `client_name` - The name sent in the `EHLO` command.
+    /// * `ttl` - How long the accepted and rejected recipients are cached. (default: "1h")
+    /// * `port` - The port of the mail exchangers. (default: 25)
+    /// * `timeouts` - The timeouts of the session, a map of `connect`, `greeting` and `command` durations.
+    ///
+    /// # Errors
+    ///
+    /// * The parameters are invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// // in `global/callout.rhai`
+    /// export const verifier = callout::verifier(#{
+    ///     dns_resolver: global::dns_resolver,
+    ///     client_name: "mx.example.com",
+    ///     ttl: "2h",
+    ///     timeouts: #{ connect: "10s", command: "30s" },
+    /// });
+    /// ```
+    ///
+    /// # rhai-autodocs:index:2
+    #[rhai_fn(return_raw)]
+    pub fn verifier(params: Dynamic) -> Result<Callout> {
+        let Params {
+            dns_resolver,
+            client_name,
+            ttl,
+            port,
+            timeouts,
+        } = rhai::serde::from_dynamic(&params)?;
+
+        let client_name = ClientName::Domain(
+            client_name
+                .parse()
+                .map_err(|error| format!("invalid client name '{client_name}': {error}"))?,
+        );
+
+        Ok(std::sync::Arc::new(Verifier {
+            callout: vsmtp_common::callout::Callout::new(client_name, timeouts, ttl),
+            dns_resolver,
+            port,
+        }))
+    }
+
+    /// Verify that a recipient is accepted by the mail exchanger of its domain.
+    ///
+    /// # Args
+    ///
+    /// * `rcpt` - The recipient to verify, as a string or a recipient object.
+    ///
+    /// # Return
+    ///
+    /// * `"accepted"` - the recipient has been accepted.
+    /// * `"rejected"` - the recipient has been rejected with a permanent error.
+    /// * `"unknown"` - the recipient could not be verified (temporary error, unreachable server, ...).
+    ///
+    /// # Errors
+    ///
+    /// * The recipient is not a valid address.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_rcpt_to(ctx) {
+    ///     if global::callout::verifier.verify(ctx.recipients[-1]) == "rejected" {
+    ///         return status::deny(code::c550_1_1());
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:3
+    #[rhai_fn(global, name = "verify", return_raw, pure)]
+    pub fn verify(verifier: &mut Callout, rcpt: &str) -> Result<String> {
+        let rcpt = Mailbox(
+            rcpt.parse()
+                .map_err(|error| format!("invalid recipient '{rcpt}': {error}"))?,
+        );
+
+        Ok(block_on(verifier.verify(&rcpt)).to_string())
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(global, name = "verify", pure)]
+    pub fn verify_recipient(
+        verifier: &mut Callout,
+        rcpt: crate::api::mailbox::Recipient,
+    ) -> String {
+        block_on(verifier.verify(&rcpt.recipient.forward_path)).to_string()
+    }
+}
@@ -11,8 +11,8 @@
 
 use crate::api::docs::{Ctx, Mail};
 use rhai::plugin::{
-    mem, Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult,
-    TypeId,
+    mem, Dynamic, FnAccess, FnNamespace, ImmutableString, Module, NativeCallContext,
+    PluginFunction, RhaiResult, TypeId,
 };
 use vsmtp_auth::{
     dkim::{self as backend, DkimVerificationResult, Value},
@@ -35,6 +35,22 @@ struct SignParams {
     canonicalization: Option<backend::Canonicalization>,
 }
 
+/// Keys of the signing domains configured for the service, used by
+/// `dkim::sign(ctx, domain, selector)` and `dkim::sign_by_domain(ctx)`.
+pub use backend::SigningKeys as DkimKeys;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignByDomainParams {
+    domains: std::collections::HashMap<String, backend::DomainKey>,
+    #[serde(default)]
+    double_signing: Option<String>,
+    #[serde(default)]
+    headers_field: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_canonicalization")]
+    canonicalization: Option<backend::Canonicalization>,
+}
+
 #[allow(non_camel_case_types)]
 struct const_usize<const U: usize>;
 
@@ -97,6 +113,8 @@ struct VerifyParams {
     expiration_epsilon: u64,
     #[serde(deserialize_with = "super::deserialize_dns_resolver")]
     dns_resolver: rhai::Shared<DnsResolver>,
+    #[serde(default)]
+    policy: backend::VerifyPolicy,
 }
 
 fn deserialize_canonicalization<'de, D>(
@@ -184,7 +202,7 @@ mod dkim {
     ///
     ///```js
     /// fn on_pre_queue(ctx) {
-    ///   dkim::sign(ctx.mail, #{
+    ///   dkim::sign(ctx, #{
     ///     sdid: "mydomain.tld",
     ///     selector: "myselector",
     ///     private_key: crypto::load_pem_rsa_pkcs8("/etc/vsmtp/keys/my_key.pem"),
@@ -195,6 +213,25 @@ mod dkim {
     /// }
     /// ```
     ///
+    /// Signing `ctx` records the signature as added by this server, so that
+    /// [append_footer](http://vsmtp.rs/docs/global/message#fn-append_footer)
+    /// refuses to invalidate it. `dkim::sign(ctx.mail, params)` signs the message
+    /// without recording the signature.
+    ///
+    /// The services configuring DKIM keys also provide `dkim::sign(ctx, domain, selector)`,
+    /// signing the message with the configured key of the domain and the selector,
+    /// to sign some messages only (ex: the submissions). The headers signed and the
+    /// canonicalization are the ones configured for the key.
+    ///
+    ///```js
+    /// fn on_post_queue(ctx) {
+    ///   if ctx.connection_kind() == "submission" {
+    ///     dkim::sign(ctx, "mydomain.tld", "myselector");
+    ///   }
+    ///   status::next();
+    /// }
+    /// ```
+    ///
     /// # rhai-autodocs:index:1
     #[rhai_fn(return_raw)]
     pub fn sign(mail: &mut Mail, params: rhai::Dynamic) -> Result<()> {
@@ -205,6 +242,32 @@ mod dkim {
         Ok(())
     }
 
+    /// See the documentation of [sign](http://vsmtp.rs/docs/global/dkim#fn-sign).
+    #[rhai_fn(name = "sign", return_raw)]
+    pub fn sign_message(ctx: &mut Ctx, params: rhai::Dynamic) -> Result<()> {
+        let SignParams {
+            sdid,
+            selector,
+            private_key,
+            headers_field,
+            canonicalization,
+        } = rhai::serde::from_dynamic::<SignParams>(&params)?;
+
+        let signature = ctx.read(|ctx| {
+            ctx.metadata.get_mail(|mail| {
+                signature_value(
+                    mail,
+                    &private_key,
+                    sdid,
+                    selector,
+                    canonicalization,
+                    headers_field,
+                )
+            })
+        })??;
+        add_signatures(ctx, vec![signature])
+    }
+
     /// Produce a DKIM signature with the given parameters.
     ///
     /// This method will produce a new signature for the message, with the given parameters **and
@@ -252,38 +315,14 @@ mod dkim {
             canonicalization,
         } = rhai::serde::from_dynamic::<SignParams>(&params)?;
 
-        let signature = {
-            let mail = mail.read().unwrap();
-            backend::sign(
-                &DkimMail { mail: &mail },
-                private_key.private_key(),
-                sdid,
-                selector,
-                canonicalization
-                    .unwrap_or_else(|| "simple/relaxed".parse().expect("default values are valid")),
-                headers_field.unwrap_or_else(|| {
-                    ["From", "To", "Date", "Subject", "From"]
-                        .into_iter()
-                        .map(str::to_string)
-                        .collect()
-                }),
-            )
-        };
-
-        match signature {
-            Ok(signature) => {
-                let mut value = signature.get_signature_value();
-                // FIXME: enhance whitespace handling
-                let removed_char = value.remove(0);
-                debug_assert_eq!(removed_char, ' ');
-                tracing::trace!("Signature: {:?}: with value '{:?}'", signature, value);
-                Ok(value)
-            }
-            Err(e) => {
-                tracing::error!("An error ocurred while signing mail: {:?}", e);
-                Err(format!("{e:?}").into())
-            }
-        }
+        signature_value(
+            &mail.read().unwrap(),
+            &private_key,
+            sdid,
+            selector,
+            canonicalization,
+            headers_field,
+        )
     }
 
     /// Verify all the DKIM signature of the message. This method will return a list of
@@ -299,6 +338,9 @@ mod dkim {
     ///   * `header_limit_count` - The maximum number of `DKIM-Signature` header to verify, optional `5` by default.
     ///   * `expiration_epsilon` - The number of seconds of tolerance for the signature expiration, optional `100` by default.
     ///   * `dns_resolver` - The DNS resolver to use for the verification, loaded with the [dns] module.
+    ///   * `policy` - The keys and algorithms accepted, optional. Signatures not meeting it have the `policy` value.
+    ///     * `min_rsa_key_size` - The minimum size of the RSA keys in bits, optional `1024` by default.
+    ///     * `allowed_hash_algorithms` - The hash algorithms accepted, optional `["sha256"]` by default.
     ///
     /// [dns]: http://vsmtp.rs/docs/global/dns
     ///
@@ -325,6 +367,7 @@ mod dkim {
             header_limit_count,
             expiration_epsilon,
             dns_resolver,
+            policy,
         } = rhai::serde::from_dynamic::<VerifyParams>(&params)?;
 
         let mail = mail.read().unwrap();
@@ -332,7 +375,7 @@ mod dkim {
         let verifications = mail
             .get_headers_raw_without_crlf("DKIM-Signature")
             .take(header_limit_count)
-            .map(|header| verify_one(header, expiration_epsilon, &mail, &dns_resolver))
+            .map(|header| verify_one(header, expiration_epsilon, &mail, &dns_resolver, &policy))
             .collect::<Vec<_>>();
 
         if verifications.is_empty() {
@@ -376,6 +419,285 @@ mod dkim {
     pub fn to_debug(v: &mut VerificationResult) -> String {
         format!("{v:?}")
     }
+
+    /// Sign the message with the key of the domain of its `From` header, and add
+    /// the `DKIM-Signature` headers to the message.
+    ///
+    /// The key of the `From` domain is used, or the one of its closest parent domain
+    /// (`news.brand.tld` is signed with the key of `brand.tld`). The message is not signed
+    /// if no key matches.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - A map containing the parameters for the signatures.
+    ///   * `domains` - A map of the signing domains to their `selector` and `private_key`, loaded with the [crypto] module.
+    ///   * `double_signing` - A domain of `domains` (the organizational domain for example) adding a second signature, optional.
+    ///   * `headers_field` - The list of headers to sign, optional `["From", "To", "Date", "Subject", "From"]` by default.
+    ///   * `canonicalization` - The canonicalization algorithm to use, optional `"simple/relaxed"` by default.
+    ///
+    /// [crypto]: http://vsmtp.rs/docs/global/crypto
+    ///
+    /// # Return
+    ///
+    /// * `array` - the signing domains used.
+    ///
+    /// # Example
+    ///
+    ///```js
+    /// fn on_pre_queue(ctx) {
+    ///   dkim::sign_by_domain(ctx, #{
+    ///     domains: #{
+    ///       "brand-a.tld": #{
+    ///         selector: "brand-a",
+    ///         private_key: crypto::load_pem_rsa_pkcs8("/etc/vsmtp/keys/brand-a.pem"),
+    ///       },
+    ///       "brand-b.tld": #{
+    ///         selector: "brand-b",
+    ///         private_key: crypto::load_pem_rsa_pkcs8("/etc/vsmtp/keys/brand-b.pem"),
+    ///       },
+    ///       "mydomain.tld": #{
+    ///         selector: "myselector",
+    ///         private_key: crypto::load_pem_rsa_pkcs8("/etc/vsmtp/keys/my_key.pem"),
+    ///       },
+    ///     },
+    ///     double_signing: "mydomain.tld",
+    ///   });
+    ///   status::next();
+    /// }
+    /// ```
+    ///
+    /// The services configuring DKIM keys also provide `dkim::sign_by_domain(ctx)`,
+    /// signing the message with the configured keys.
+    ///
+    /// # rhai-autodocs:index:8
+    #[rhai_fn(return_raw)]
+    pub fn sign_by_domain(mail: &mut Mail, params: rhai::Dynamic) -> Result<rhai::Array> {
+        let (domains, signatures) = signatures_by_domain(&mail.read().unwrap(), &params, &[])?;
+
+        let mut mail = mail.write().unwrap();
+        // the first signature is prepended last, to be on top of the headers
+        for signature in signatures.iter().rev() {
+            mail.prepend_headers([Header::new("DKIM-Signature", signature)]);
+        }
+
+        Ok(domains)
+    }
+
+    /// See the documentation of [sign_by_domain](http://vsmtp.rs/docs/global/dkim#fn-sign_by_domain),
+    /// the signatures being recorded as added by this server like with
+    /// [sign](http://vsmtp.rs/docs/global/dkim#fn-sign). The domains already signing
+    /// the message from the rules are skipped.
+    #[rhai_fn(name = "sign_by_domain", return_raw)]
+    pub fn sign_message_by_domain(ctx: &mut Ctx, params: rhai::Dynamic) -> Result<rhai::Array> {
+        let (domains, signatures) = ctx.read(|ctx| {
+            let signed = signed_domains(ctx.dkim_signatures());
+            ctx.metadata
+                .get_mail(|mail| signatures_by_domain(mail, &params, &signed))
+        })??;
+        add_signatures(ctx, signatures)?;
+
+        Ok(domains)
+    }
+}
+
+/// Sign `mail` with the key of the domain of its `From` header, see `dkim::sign_by_domain`,
+/// returning the signing domains and the values of the `DKIM-Signature` headers.
+fn signatures_by_domain(
+    mail: &vsmtp_mail_parser::Mail,
+    params: &rhai::Dynamic,
+    signed: &[String],
+) -> crate::api::Result<(rhai::Array, Vec<String>)> {
+    let SignByDomainParams {
+        domains,
+        double_signing,
+        headers_field,
+        canonicalization,
+    } = rhai::serde::from_dynamic::<SignByDomainParams>(params)?;
+
+    signatures_with_keys(
+        mail,
+        &DkimKeys {
+            domains,
+            double_signing,
+        },
+        signed,
+        canonicalization,
+        headers_field,
+    )
+}
+
+/// The canonicalization of the signatures when none is specified.
+fn default_canonicalization() -> backend::Canonicalization {
+    "simple/relaxed".parse().expect("default values are valid")
+}
+
+/// The headers signed when none are specified.
+fn default_headers_field() -> Vec<String> {
+    ["From", "To", "Date", "Subject", "From"]
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+}
+
+/// The value of the `DKIM-Signature` header of `signature`.
+fn signature_header_value(signature: &backend::Signature) -> String {
+    let mut value = signature.get_signature_value();
+    // FIXME: enhance whitespace handling
+    let removed_char = value.remove(0);
+    debug_assert_eq!(removed_char, ' ');
+    value
+}
+
+/// Sign `mail` with the key of `keys` selected by the domain of its `From` header,
+/// skipping the domains of `signed`.
+fn signatures_with_keys(
+    mail: &vsmtp_mail_parser::Mail,
+    keys: &DkimKeys,
+    signed: &[String],
+    canonicalization: Option<backend::Canonicalization>,
+    headers_field: Option<Vec<String>>,
+) -> crate::api::Result<(rhai::Array, Vec<String>)> {
+    let Some(from_domain) = mail
+        .header_addresses("From")
+        .first()
+        .map(|from| from.domain().to_lowercase())
+    else {
+        tracing::debug!("No `From` domain found, the message is not signed");
+        return Ok((rhai::Array::new(), vec![]));
+    };
+
+    let signatures = backend::sign_for_from_domain(
+        &DkimMail { mail },
+        &from_domain,
+        keys,
+        signed,
+        canonicalization.unwrap_or_else(default_canonicalization),
+        &headers_field.unwrap_or_else(default_headers_field),
+    )
+    .map_err::<Box<rhai::EvalAltResult>, _>(|e| {
+        tracing::error!("An error ocurred while signing mail: {:?}", e);
+        format!("{e:?}").into()
+    })?;
+
+    Ok(signatures
+        .into_iter()
+        .map(|signature| {
+            let value = signature_header_value(&signature);
+            (rhai::Dynamic::from(signature.sdid), value)
+        })
+        .unzip())
+}
+
+/// The signing domains of the `DKIM-Signature` headers added by this server.
+fn signed_domains(signatures: Vec<String>) -> Vec<String> {
+    signatures
+        .into_iter()
+        .filter_map(|value| {
+            format!("DKIM-Signature: {value}")
+                .parse::<backend::Signature>()
+                .ok()
+        })
+        .map(|signature| signature.sdid)
+        .collect()
+}
+
+/// Add the `DKIM-Signature` headers to the message, the first one on top of the headers,
+/// and record them as added by this server.
+fn add_signatures(ctx: &mut Ctx, signatures: Vec<String>) -> crate::api::Result<()> {
+    ctx.write(|ctx| {
+        ctx.metadata.mut_mail(|mail| {
+            for signature in signatures.iter().rev() {
+                mail.prepend_headers([Header::new("DKIM-Signature", signature)]);
+            }
+        })?;
+        for signature in signatures {
+            ctx.add_dkim_signature(signature);
+        }
+        Ok(())
+    })
+}
+
+/// Sign `mail`, returning the value of its `DKIM-Signature` header.
+fn signature_value(
+    mail: &vsmtp_mail_parser::Mail,
+    private_key: &TlsPrivateKey,
+    sdid: String,
+    selector: String,
+    canonicalization: Option<backend::Canonicalization>,
+    headers_field: Option<Vec<String>>,
+) -> crate::api::Result<String> {
+    let signature = backend::sign(
+        &DkimMail { mail },
+        private_key.private_key(),
+        sdid,
+        selector,
+        canonicalization.unwrap_or_else(default_canonicalization),
+        headers_field.unwrap_or_else(default_headers_field),
+    );
+
+    match signature {
+        Ok(signature) => {
+            let value = signature_header_value(&signature);
+            tracing::trace!("Signature: {:?}: with value '{:?}'", signature, value);
+            Ok(value)
+        }
+        Err(e) => {
+            tracing::error!("An error ocurred while signing mail: {:?}", e);
+            Err(format!("{e:?}").into())
+        }
+    }
+}
+
+/// Build the `dkim` module, where `dkim::sign(ctx, domain, selector)` signs the
+/// message with the key of `keys` matching the domain and the selector, and
+/// `dkim::sign_by_domain(ctx)` with the keys selected by its `From` domain.
+#[must_use]
+pub fn module_with_keys(keys: DkimKeys) -> rhai::Module {
+    let mut module = rhai::exported_module!(dkim);
+    let keys = std::sync::Arc::new(keys);
+
+    let by_selector = keys.clone();
+    module.set_native_fn(
+        "sign",
+        move |ctx: &mut Ctx,
+              domain: ImmutableString,
+              selector: ImmutableString|
+              -> crate::api::Result<()> {
+            let key = by_selector.get(&domain, &selector).ok_or_else(|| {
+                format!("no DKIM key configured for the domain '{domain}' and the selector '{selector}'")
+            })?;
+
+            let signature = ctx.read(|ctx| {
+                ctx.metadata.get_mail(|mail| {
+                    signature_value(
+                        mail,
+                        &key.private_key,
+                        domain.to_lowercase(),
+                        key.selector.clone(),
+                        key.canonicalization,
+                        key.headers_field.clone(),
+                    )
+                })
+            })??;
+            add_signatures(ctx, vec![signature])
+        },
+    );
+
+    module.set_native_fn(
+        "sign_by_domain",
+        move |ctx: &mut Ctx| -> crate::api::Result<rhai::Array> {
+            let (domains, signatures) = ctx.read(|ctx| {
+                let signed = signed_domains(ctx.dkim_signatures());
+                ctx.metadata
+                    .get_mail(|mail| signatures_with_keys(mail, &keys, &signed, None, None))
+            })??;
+            add_signatures(ctx, signatures)?;
+
+            Ok(domains)
+        },
+    );
+
+    module
 }
 
 async fn verify_one(
@@ -383,6 +705,7 @@ async fn verify_one(
     expiration_epsilon: u64,
     mail: &vsmtp_mail_parser::Mail,
     dns_resolver: &DnsResolver,
+    policy: &backend::VerifyPolicy,
 ) -> DkimVerificationResult {
     tracing::trace!(?header, "Verifying DKIM signature ...");
 
@@ -416,12 +739,25 @@ async fn verify_one(
         }
     };
 
-    if let Err(e) = backend::verify(&signature, &DkimMail { mail }, &public_key) {
-        tracing::debug!("Failed to verify the DKIM signature: {:?}", e);
-        return DkimVerificationResult {
-            value: Value::PermFail,
-            signature: Some(signature),
-        };
+    match backend::verify(&signature, &DkimMail { mail }, &public_key, policy) {
+        Ok(()) => {}
+        Err(
+            e @ (backend::VerifierError::HashAlgorithmNotAllowed { .. }
+            | backend::VerifierError::KeyTooSmall { .. }),
+        ) => {
+            tracing::debug!("The DKIM signature does not meet the policy: {}", e);
+            return DkimVerificationResult {
+                value: Value::Policy,
+                signature: Some(signature),
+            };
+        }
+        Err(e) => {
+            tracing::debug!("Failed to verify the DKIM signature: {:?}", e);
+            return DkimVerificationResult {
+                value: Value::PermFail,
+                signature: Some(signature),
+            };
+        }
     }
 
     tracing::debug!("DKIM signature successfully verified.");
@@ -10,6 +10,7 @@
  */
 
 use crate::api::docs::Ctx;
+use rand::Rng;
 use rhai::plugin::{
     mem, Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult,
     TypeId,
@@ -104,12 +105,14 @@ async fn get_dmarc_record(
                         domain: organizational_domain.parse().unwrap(),
                         rfc5322_from_domain: rfc5322_from_domain.parse().unwrap(),
                         record: Some(record),
+                        disposition: None,
                     }),
                     Err(_otherwise) => Err(backend::Result {
                         value: backend::Value::None,
                         domain: organizational_domain.parse().unwrap(),
                         rfc5322_from_domain: rfc5322_from_domain.parse().unwrap(),
                         record: None,
+                        disposition: None,
                     }),
                 }
             } else {
@@ -118,6 +121,7 @@ async fn get_dmarc_record(
                     domain: rfc5322_from_domain.parse().unwrap(),
                     rfc5322_from_domain: rfc5322_from_domain.parse().unwrap(),
                     record: None,
+                    disposition: None,
                 })
             }
         }
@@ -126,16 +130,152 @@ async fn get_dmarc_record(
             domain: rfc5322_from_domain.parse().unwrap(),
             rfc5322_from_domain: rfc5322_from_domain.parse().unwrap(),
             record: Some(record),
+            disposition: None,
         }),
         Err(_otherwise) => Err(backend::Result {
             value: backend::Value::None,
             domain: rfc5322_from_domain.parse().unwrap(),
             rfc5322_from_domain: rfc5322_from_domain.parse().unwrap(),
             record: None,
+            disposition: None,
         }),
     }
 }
 
+/// Run the DMARC alignment of the SPF and DKIM results stored in the context
+/// with the domain of the `From` header.
+fn check_alignment(
+    ctx: &Ctx,
+    dns_resolver: std::sync::Arc<DnsResolver>,
+) -> Result<backend::Result, Box<rhai::EvalAltResult>> {
+    let (rfc5322_from_domain, spf, dkim) =
+        ctx.read(|ctx| match ctx.metadata.get_mail(get_rfc5322_from_domain) {
+            Err(e) => Err(e.to_string()),
+            Ok(Err(e)) => Err(e),
+            Ok(Ok(rfc5322_from_domain)) => Ok((
+                rfc5322_from_domain,
+                ctx.metadata
+                    .get_mail_from()
+                    .map_err(|e| e.to_string())?
+                    .spf_mail_from_identity
+                    .clone()
+                    .ok_or("SPF on MAIL FROM identity must be called first")?,
+                ctx.metadata
+                    .get_complete()
+                    .map_err(|e| e.to_string())?
+                    .dkim
+                    .clone()
+                    .ok_or("DKIM must be called first")?,
+            )),
+        })?;
+
+    let mut result = match crate::block_on(get_dmarc_record(dns_resolver, &rfc5322_from_domain)) {
+        Ok(record) => record,
+        Err(value) => return Ok(value),
+    };
+
+    let Some(record) = &result.record else {
+        return Ok(result);
+    };
+
+    if spf.value == vsmtp_auth::spf::Value::Pass
+        && spf
+            .domain
+            .as_deref()
+            .is_some_and(|spf_domain| record.spf_is_aligned(&rfc5322_from_domain, spf_domain))
+    {
+        tracing::debug!("Dmarc spf pass");
+        result.value = backend::Value::Pass;
+        return Ok(result);
+    }
+
+    for i in &*dkim {
+        if i.value == vsmtp_auth::dkim::Value::Pass
+            && i.signature.as_ref().is_some_and(|signature| {
+                record.dkim_is_aligned(&rfc5322_from_domain, &signature.sdid)
+            })
+        {
+            tracing::debug!("Dmarc dkim pass");
+            result.value = backend::Value::Pass;
+            return Ok(result);
+        }
+    }
+
+    result.value = backend::Value::Fail;
+    Ok(result)
+}
+
+/// Parameters of the `dmarc_evaluate` function.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EvaluateParams {
+    #[serde(deserialize_with = "super::deserialize_dns_resolver")]
+    dns_resolver: std::sync::Arc<DnsResolver>,
+    #[serde(default)]
+    enforce: bool,
+    #[serde(default = "default_quarantine_queue")]
+    quarantine: String,
+}
+
+fn default_quarantine_queue() -> String {
+    "dmarc".to_string()
+}
+
+/// Action to take on a message after a DMARC evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Enforcement {
+    /// The message is accepted, because it passed the DMARC check, the disposition
+    /// is `none` or the policy is not enforced.
+    None,
+    /// The message must be placed in the given quarantine queue.
+    Quarantine(String),
+    /// The message must be rejected.
+    Reject,
+}
+
+impl Enforcement {
+    fn new(disposition: backend::ReceiverPolicy, enforce: bool, quarantine: String) -> Self {
+        match disposition {
+            backend::ReceiverPolicy::Quarantine if enforce => Self::Quarantine(quarantine),
+            backend::ReceiverPolicy::Reject if enforce => Self::Reject,
+            _ => Self::None,
+        }
+    }
+}
+
+/// Run a DMARC check, compute the disposition of the message following the policy
+/// and the `pct` tag of the record, then store the result in the context.
+///
+/// # Errors
+///
+/// * the parameters are invalid.
+/// * the SPF and DKIM results are not stored in the context.
+pub fn evaluate(
+    ctx: &mut Ctx,
+    params: &rhai::Dynamic,
+) -> Result<Enforcement, Box<rhai::EvalAltResult>> {
+    let EvaluateParams {
+        dns_resolver,
+        enforce,
+        quarantine,
+    } = rhai::serde::from_dynamic(params)?;
+
+    let mut result = check_alignment(ctx, dns_resolver)?;
+    let disposition = result.disposition(rand::thread_rng().gen_range(0..100));
+    result.disposition = Some(disposition);
+
+    tracing::info!(
+        value = %result.value,
+        policy = %result.policy(),
+        %disposition,
+        enforce,
+        "DMARC evaluated"
+    );
+
+    store(ctx, result.into())?;
+    Ok(Enforcement::new(disposition, enforce, quarantine))
+}
+
 /// Domain-based message authentication, reporting and conformance implementation
 /// specified by RFC 7489. (<https://www.rfc-editor.org/rfc/rfc7489>)
 #[rhai::plugin::export_module]
@@ -182,62 +322,7 @@ mod rhai_dmarc {
     ) -> Result<DmarcResult, Box<rhai::EvalAltResult>> {
         let Params { dns_resolver } = rhai::serde::from_dynamic(&params)?;
 
-        let (rfc5322_from_domain, spf, dkim) =
-            ctx.read(|ctx| match ctx.metadata.get_mail(get_rfc5322_from_domain) {
-                Err(e) => Err(e.to_string()),
-                Ok(Err(e)) => Err(e),
-                Ok(Ok(rfc5322_from_domain)) => Ok((
-                    rfc5322_from_domain,
-                    ctx.metadata
-                        .get_mail_from()
-                        .map_err(|e| e.to_string())?
-                        .spf_mail_from_identity
-                        .clone()
-                        .ok_or("SPF on MAIL FROM identity must be called first")?,
-                    ctx.metadata
-                        .get_complete()
-                        .map_err(|e| e.to_string())?
-                        .dkim
-                        .clone()
-                        .ok_or("DKIM must be called first")?,
-                )),
-            })?;
-
-        let mut result = match crate::block_on(get_dmarc_record(dns_resolver, &rfc5322_from_domain))
-        {
-            Ok(record) => record,
-            Err(value) => return Ok(value.into()),
-        };
-
-        let Some(record) = &result.record else {
-            return Ok(result.into());
-        };
-
-        if spf.value == vsmtp_auth::spf::Value::Pass
-            && spf
-                .domain
-                .as_deref()
-                .is_some_and(|spf_domain| record.spf_is_aligned(&rfc5322_from_domain, spf_domain))
-        {
-            tracing::debug!("Dmarc spf pass");
-            result.value = backend::Value::Pass;
-            return Ok(result.into());
-        }
-
-        for i in &*dkim {
-            if i.value == vsmtp_auth::dkim::Value::Pass
-                && i.signature.as_ref().is_some_and(|signature| {
-                    record.dkim_is_aligned(&rfc5322_from_domain, &signature.sdid)
-                })
-            {
-                tracing::debug!("Dmarc dkim pass");
-                result.value = backend::Value::Pass;
-                return Ok(result.into());
-            }
-        }
-
-        result.value = backend::Value::Fail;
-        Ok(result.into())
+        check_alignment(ctx, dns_resolver).map(Into::into)
     }
 
     /// Cache DMARC result from the `dmarc::check` function.
@@ -267,22 +352,16 @@ mod rhai_dmarc {
     /// # rhai-autodocs:index:5
     #[rhai_fn(global, get = "policy", pure)]
     pub fn get_policy(res: &mut DmarcResult) -> String {
-        let is_subdomain =
-            res.domain != res.rfc5322_from_domain && res.domain.zone_of(&res.rfc5322_from_domain);
-
-        res.record
-            .as_ref()
-            .map_or_else(
-                || backend::ReceiverPolicy::None,
-                |record| {
-                    record
-                        .receiver_policy_subdomain
-                        .as_ref()
-                        .and_then(|sub| is_subdomain.then_some(sub))
-                        .unwrap_or(&record.receiver_policy)
-                        .clone()
-                },
-            )
-            .to_string()
+        res.policy().to_string()
+    }
+
+    /// Get the disposition computed by `dmarc_evaluate`, following the policy and
+    /// the `pct` tag of the DMARC record. Returns `()` if the result was produced by `dmarc::check`.
+    ///
+    /// # rhai-autodocs:index:6
+    #[rhai_fn(global, get = "disposition", pure)]
+    pub fn get_disposition(res: &mut DmarcResult) -> Dynamic {
+        res.disposition
+            .map_or(Dynamic::UNIT, |disposition| disposition.to_string().into())
     }
 }
@@ -14,10 +14,100 @@ use rhai::plugin::{
     mem, Dynamic, FnAccess, FnNamespace, ImmutableString, Module, NativeCallContext,
     PluginFunction, RhaiResult, TypeId,
 };
-use vsmtp_common::hickory_resolver::proto::rr::RecordType;
+use vsmtp_common::hickory_resolver::{
+    self,
+    proto::rr::{RData, RecordType},
+};
 
 pub use dns::*;
 
+/// Lookup of DNS records, implemented by the [`DnsResolver`] and faked in tests.
+pub trait RecordLookup {
+    /// Get the records of type `record_type` for `name`.
+    ///
+    /// # Errors
+    ///
+    /// * Lookup failed.
+    fn lookup(
+        &self,
+        name: hickory_resolver::Name,
+        record_type: RecordType,
+    ) -> std::result::Result<Vec<RData>, hickory_resolver::error::ResolveError>;
+}
+
+impl RecordLookup for vsmtp_common::dns_resolver::DnsResolver {
+    fn lookup(
+        &self,
+        name: hickory_resolver::Name,
+        record_type: RecordType,
+    ) -> std::result::Result<Vec<RData>, hickory_resolver::error::ResolveError> {
+        // NOTE: the answers are cached by the resolver, following the ttl of the records.
+        crate::block_on(self.resolver.lookup(name, record_type))
+            .map(|lookup| lookup.iter().cloned().collect())
+    }
+}
+
+/// Convert a domain name to a string without the trailing dot.
+fn name_to_string(name: &hickory_resolver::Name) -> String {
+    name.to_utf8().trim_end_matches('.').to_string()
+}
+
+/// Convert a record to a rhai value.
+///
+/// `A` and `AAAA` records are IPs, `MX` records are `#{ priority, host }` maps,
+/// `TXT` records are strings and `PTR` records are host names. Other record types
+/// are formatted as in a zone file.
+fn record_to_dynamic(record: &RData) -> rhai::Dynamic {
+    match record {
+        RData::A(ip) => ip.to_string().into(),
+        RData::AAAA(ip) => ip.to_string().into(),
+        RData::MX(mx) => rhai::Map::from_iter([
+            ("priority".into(), rhai::INT::from(mx.preference()).into()),
+            ("host".into(), name_to_string(mx.exchange()).into()),
+        ])
+        .into(),
+        RData::TXT(txt) => txt
+            .txt_data()
+            .iter()
+            .map(|data| String::from_utf8_lossy(data))
+            .collect::<String>()
+            .into(),
+        RData::PTR(ptr) => name_to_string(&ptr.0).into(),
+        otherwise => otherwise.to_string().into(),
+    }
+}
+
+/// Lookup the records of a host, converted to rhai values.
+///
+/// For `PTR` records, the host can be an IP, converted to its reverse lookup name.
+///
+/// # Errors
+///
+/// * Invalid record type.
+/// * Invalid host.
+/// * Lookup failed.
+pub fn lookup_records(
+    lookup: &impl RecordLookup,
+    host: &str,
+    record: &str,
+) -> Result<rhai::Array> {
+    let record_type = <RecordType as std::str::FromStr>::from_str(record)
+        .map_err::<Box<rhai::EvalAltResult>, _>(|_| format!("Invalid record type {record}").into())?;
+
+    let name = match <std::net::IpAddr as std::str::FromStr>::from_str(host) {
+        Ok(ip) if record_type == RecordType::PTR => hickory_resolver::Name::from(ip),
+        _ => <hickory_resolver::Name as std::str::FromStr>::from_str(host)
+            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?,
+    };
+
+    Ok(lookup
+        .lookup(name, record_type)
+        .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?
+        .iter()
+        .map(record_to_dynamic)
+        .collect::<rhai::Array>())
+}
+
 /// Functions used to query the DNS.
 #[rhai::plugin::export_module]
 mod dns {
@@ -130,23 +220,60 @@ mod dns {
             .collect::<rhai::Array>())
     }
 
-    #[doc(hidden)]
+    /// Performs a DNS lookup of a given record type for the given hostname.
+    ///
+    /// # Args
+    ///
+    /// * `host`   - A valid hostname to search, or an IP for "PTR" records.
+    /// * `record` - A valid record type to search as a string, "A", "AAAA", "MX", "TXT", "PTR", "TLSA", etc.
+    ///
+    /// # Return
+    ///
+    /// * `array` - the records found, depending on the record type:
+    ///   * "A" / "AAAA" - IPs as strings.
+    ///   * "MX"         - maps with the `priority` and the `host` of the mail exchanger.
+    ///   * "TXT"        - the text of the records.
+    ///   * "PTR"        - host names.
+    ///   * other types  - the records formatted as in a zone file.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them.
+    ///
+    /// # Errors
+    ///
+    /// * Invalid record type.
+    /// * Lookup failed.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// const google_dns = dns::resolver(#{
+    ///    config: "google_tls",
+    /// });
+    ///
+    /// for mx in google_dns.lookup("google.com", "MX") {
+    ///     log("my_topic", "debug", `${mx.host} (${mx.priority})`);
+    /// }
+    ///
+    /// // DNSBL style lookup.
+    /// if google_dns.lookup("2.0.0.127.zen.spamhaus.org", "A").contains("127.0.0.2") {
+    ///     log("my_topic", "warn", "listed");
+    /// }
+    ///
+    /// for name in google_dns.lookup("8.8.8.8", "PTR") {
+    ///     log("my_topic", "debug", name);
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:4
     #[rhai_fn(global, name = "lookup", return_raw, pure)]
     pub fn lookup_record(
         dns_resolver: &mut DnsResolver,
         host: &str,
         record: &str,
     ) -> Result<rhai::Array> {
-        let record = <RecordType as std::str::FromStr>::from_str(record)
-            .map_err::<Box<rhai::EvalAltResult>, _>(|_| {
-                format!("Invalid record type {record}").into()
-            })?;
-
-        Ok(crate::block_on(dns_resolver.resolver.lookup(host, record))
-            .map_err::<Box<rhai::EvalAltResult>, _>(|err| err.to_string().into())?
-            .into_iter()
-            .map(|record| rhai::Dynamic::from(record.to_string()))
-            .collect::<rhai::Array>())
+        super::lookup_records(dns_resolver.as_ref(), host, record)
     }
 
     /// Performs a reverse lookup for the given IP.
@@ -181,7 +308,7 @@ mod dns {
     /// }
     /// ```
     ///
-    /// # rhai-autodocs:index:4
+    /// # rhai-autodocs:index:5
     #[rhai_fn(global, name = "rlookup", return_raw)]
     pub fn rlookup(dns_resolver: &mut DnsResolver, ip: &str) -> Result<rhai::Array> {
         let ip = <std::net::IpAddr as std::str::FromStr>::from_str(ip)
@@ -30,6 +30,134 @@ fn mailbox(address: &str) -> Result<vsmtp_common::Mailbox> {
     ))
 }
 
+/// Targets of the aliases, by address or `@domain` in lowercase.
+type Aliases = std::collections::HashMap<String, Vec<String>>;
+
+/// Read the aliases of a rhai map, whose values are an address or an array of addresses.
+fn aliases_from_map(map: rhai::Map) -> Result<Aliases> {
+    map.into_iter()
+        .map(|(alias, value)| {
+            let targets = if value.is_string() {
+                vec![value.to_string()]
+            } else if value.is_array() {
+                value
+                    .into_array()
+                    .expect("value is an array")
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            } else {
+                return Err(format!(
+                    "the alias of '{alias}' must be a string or an array of strings, got a {}",
+                    value.type_name()
+                )
+                .into());
+            };
+            Ok((alias.to_lowercase(), targets))
+        })
+        .collect()
+}
+
+/// Read the aliases of a file, one alias per line followed by its targets separated
+/// by commas, ex: `info@example.com: alice@example.com, bob@example.com`.
+/// The empty lines and the lines starting with `#` are ignored.
+fn aliases_from_file(path: &str) -> Result<Aliases> {
+    let content =
+        std::fs::read_to_string(path).map_err::<Box<rhai::EvalAltResult>, _>(|error| {
+            format!("failed to read the aliases '{path}': {error}").into()
+        })?;
+
+    let mut aliases = Aliases::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (alias, targets) = line.split_once(':').ok_or_else(|| {
+            format!(
+                "the line {} of the aliases '{path}' must be `<alias>: <target>, ...`",
+                number + 1
+            )
+        })?;
+        aliases
+            .entry(alias.trim().to_lowercase())
+            .or_default()
+            .extend(
+                targets
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|target| !target.is_empty())
+                    .map(str::to_string),
+            );
+    }
+
+    Ok(aliases)
+}
+
+/// Read the aliases of the rows of a table, ex: the result of a query to a database,
+/// each row being a map with an `alias` and a `target` column.
+fn aliases_from_table(table: rhai::Array) -> Result<Aliases> {
+    let column = |row: &rhai::Map, name: &str| -> Result<String> {
+        row.get(name)
+            .filter(|value| value.is_string())
+            .map(ToString::to_string)
+            .ok_or_else(|| format!("a row of the aliases has no `{name}` string").into())
+    };
+
+    let mut aliases = Aliases::new();
+    for row in table {
+        let row = row
+            .try_cast::<rhai::Map>()
+            .ok_or("the rows of the aliases must be maps")?;
+
+        aliases
+            .entry(column(&row, "alias")?.to_lowercase())
+            .or_default()
+            .push(column(&row, "target")?);
+    }
+
+    Ok(aliases)
+}
+
+/// Get the addresses an alias expands to, from its targets.
+/// A target starting with `@` maps the recipient to the same local part in another domain.
+fn alias_members(rcpt: &Mailbox, targets: &[String]) -> Result<Vec<Mailbox>> {
+    targets
+        .iter()
+        .map(|target| match target.strip_prefix('@') {
+            Some(domain) => mailbox(&format!("{}@{domain}", rcpt.local_part())),
+            None => mailbox(target),
+        })
+        .collect()
+}
+
+/// Replace the recipients found in `aliases` by their members.
+fn expand_aliases(ctx: &mut Ctx, aliases: &Aliases) -> Result<()> {
+    ctx.write(|ctx| {
+        let rcpt_to = ctx.metadata.mut_rcpt_to()?;
+
+        let mut expansions = vec![];
+        for rcpt in rcpt_to.recipient_values().map(|r| &r.forward_path) {
+            let address = rcpt.0.full().to_lowercase();
+            let domain = address.rsplit_once('@').map(|(_, domain)| domain);
+
+            if let Some(targets) = aliases
+                .get(&address)
+                .or_else(|| domain.and_then(|domain| aliases.get(&format!("@{domain}"))))
+            {
+                expansions.push((rcpt.clone(), alias_members(rcpt, targets)?));
+            }
+        }
+
+        for (alias, members) in expansions {
+            rcpt_to.expand_recipient(&alias, members);
+        }
+
+        Ok(())
+    })
+}
+
 /// Functions to inspect and mutate the SMTP envelop.
 #[rhai::plugin::export_module]
 mod envelop {
@@ -168,4 +296,119 @@ mod envelop {
             Ok(())
         })
     }
+
+    /// Expand the recipients of the envelop using an alias map, before delivery.
+    ///
+    /// The recipients found in the map are replaced by their aliases, on the same
+    /// delivery route and with the same notification settings. The address used
+    /// by the sender is kept as the original recipient (`ORCPT`) of the new recipients.
+    /// The expansion is not recursive.
+    ///
+    /// # Args
+    ///
+    /// * `aliases` - a map of the recipients to expand.
+    ///   * the keys are addresses (`"info@example.com"`), or domains (`"@example.org"`) to
+    ///     apply to all the recipients of the domain that are not found by their address.
+    ///   * the values are an address or an array of addresses, or a domain (`"@example.com"`)
+    ///     to keep the local part of the recipient in another domain (virtual domain).
+    ///
+    /// # Errors
+    ///
+    /// * An alias is not a valid address.
+    ///
+    /// # SMTP stages
+    ///
+    /// `rcpt_to` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     ctx.expand_aliases(#{
+    ///         "info@example.com": ["alice@example.com", "bob@example.com"],
+    ///         "@example.org": "@example.com",
+    ///     });
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:6
+    #[rhai_fn(name = "expand_aliases", return_raw, pure)]
+    pub fn expand_aliases_with_map(ctx: &mut Ctx, aliases: rhai::Map) -> Result<()> {
+        super::expand_aliases(ctx, &super::aliases_from_map(aliases)?)
+    }
+
+    /// Expand the recipients of the envelop using an alias file, before delivery,
+    /// see `expand_aliases` with a map.
+    ///
+    /// # Args
+    ///
+    /// * `path` - the path of the file, with one alias per line followed by its targets
+    ///   separated by commas. The empty lines and the lines starting with `#` are ignored.
+    ///
+    /// ```text
+    /// # aliases of the local recipients.
+    /// info@example.com: alice@example.com, bob@example.com
+    /// @example.org: @example.com
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * The file cannot be read, or a line is not an alias.
+    /// * An alias is not a valid address.
+    ///
+    /// # SMTP stages
+    ///
+    /// `rcpt_to` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     ctx.expand_aliases("/etc/vsmtp/aliases");
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:7
+    #[rhai_fn(name = "expand_aliases", return_raw, pure)]
+    pub fn expand_aliases_with_file(ctx: &mut Ctx, path: &str) -> Result<()> {
+        super::expand_aliases(ctx, &super::aliases_from_file(path)?)
+    }
+
+    /// Expand the recipients of the envelop using an alias table, before delivery,
+    /// see `expand_aliases` with a map.
+    ///
+    /// # Args
+    ///
+    /// * `aliases` - the rows of the table, for example the result of a query to a database,
+    ///   each row being a map with an `alias` and a `target` column. An alias with several
+    ///   targets spans several rows.
+    ///
+    /// # Errors
+    ///
+    /// * A row is not a map with an `alias` and a `target` string.
+    /// * An alias is not a valid address.
+    ///
+    /// # SMTP stages
+    ///
+    /// `rcpt_to` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     ctx.expand_aliases([
+    ///         #{ alias: "info@example.com", target: "alice@example.com" },
+    ///         #{ alias: "info@example.com", target: "bob@example.com" },
+    ///     ]);
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:8
+    #[rhai_fn(name = "expand_aliases", return_raw, pure)]
+    pub fn expand_aliases_with_table(ctx: &mut Ctx, aliases: rhai::Array) -> Result<()> {
+        super::expand_aliases(ctx, &super::aliases_from_table(aliases)?)
+    }
 }
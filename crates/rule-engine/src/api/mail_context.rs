@@ -11,15 +11,37 @@
 
 use super::Result;
 use crate::api::docs::Ctx;
-use crate::api::mailbox::{Mailbox, Recipient};
+use crate::api::mailbox::{EnvelopeRecipient, Mailbox, Recipient};
 use rhai::plugin::{
     mem, Dynamic, FnAccess, FnNamespace, ImmutableString, Module, NativeCallContext,
     PluginFunction, RhaiResult, TypeId,
 };
 use vsmtp_common::delivery_route::DeliveryRoute;
+use vsmtp_common::tls::{CipherSuite, ProtocolVersion};
 
 pub use mail_context::*;
 
+/// Can the value be serialized with the context, see `mail_context::set_var`.
+#[must_use]
+pub fn is_plain_data(value: &rhai::Dynamic) -> bool {
+    if value.is_array() {
+        value
+            .read_lock::<rhai::Array>()
+            .is_some_and(|array| array.iter().all(is_plain_data))
+    } else if value.is_map() {
+        value
+            .read_lock::<rhai::Map>()
+            .is_some_and(|map| map.values().all(is_plain_data))
+    } else {
+        value.is_unit()
+            || value.is_bool()
+            || value.is_int()
+            || value.is_float()
+            || value.is_char()
+            || value.is_string()
+    }
+}
+
 /// Inspect the transaction context.
 #[rhai::plugin::export_module]
 mod mail_context {
@@ -45,22 +67,23 @@ mod mail_context {
     ///
     /// # rhai-autodocs:index:1
     #[rhai_fn(global, return_raw, pure)]
-    #[tracing::instrument(skip(rcpt), fields(rcpt = %rcpt.forward_path))]
+    #[tracing::instrument(skip(rcpt), fields(rcpt = %rcpt.recipient.forward_path))]
     pub fn set_routing_path(ctx: &mut Ctx, rcpt: Recipient, path: &str) -> Result<()> {
         let path = path.parse::<DeliveryRoute>().map_err(|e| e.to_string())?;
 
         ctx.write(|ctx| {
             let map = &mut ctx.metadata.mut_rcpt_to()?.recipient;
 
-            for (previous_routing_key, r, idx) in map
-                .iter_mut()
-                .filter_map(|(k, r)| r.iter().position(|i| *i == *rcpt).map(|idx| (k, r, idx)))
-            {
+            for (previous_routing_key, r, idx) in map.iter_mut().filter_map(|(k, r)| {
+                r.iter()
+                    .position(|i| *i == rcpt.recipient)
+                    .map(|idx| (k, r, idx))
+            }) {
                 tracing::debug!(?previous_routing_key, "Recipient already exists, removing");
                 r.remove(idx);
             }
 
-            let rcpt = rcpt.as_ref().clone();
+            let rcpt = rcpt.recipient.clone();
             if let Some(values) = map.get_mut(&path) {
                 tracing::debug!("Found pre-existing routing key, appending");
                 values.push(rcpt);
@@ -153,6 +176,8 @@ mod mail_context {
     ///
     ///```js
     /// let server_address = ctx.server_address;
+    /// // or
+    /// let server_address = ctx.server_address();
     /// ```
     ///
     /// # rhai-autodocs:index:5
@@ -227,6 +252,64 @@ mod mail_context {
         ctx.read(|ctx| ctx.metadata.get_connect().connect_timestamp)
     }
 
+    /// Get the time elapsed since the client connected, in seconds.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them.
+    ///
+    /// # Return
+    ///
+    /// * `float` - the age of the connection in seconds.
+    ///
+    /// # Example
+    ///
+    ///```js
+    /// fn on_mail_from(ctx) {
+    ///     ctx.run([
+    ///         rule "too fast" |ctx| {
+    ///             // a legitimate client does not issue commands at this pace.
+    ///             if ctx.command_count > 10 && ctx.connection_age < 1.0 {
+    ///                 status::deny()
+    ///             } else {
+    ///                 status::next()
+    ///             }
+    ///         },
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:24
+    #[rhai_fn(global, get = "connection_age")]
+    pub fn connection_age(ctx: &mut Ctx) -> rhai::FLOAT {
+        let connect_timestamp = ctx.read(|ctx| ctx.metadata.get_connect().connect_timestamp);
+
+        (vsmtp_common::time::OffsetDateTime::now_utc() - connect_timestamp).as_seconds_f64()
+    }
+
+    /// Get the number of commands issued by the client so far in the session,
+    /// including the current one.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them, the count is `0` at the `connect` stage.
+    ///
+    /// # Return
+    ///
+    /// * `int` - the number of commands.
+    ///
+    /// # Example
+    ///
+    ///```js
+    /// let command_count = ctx.command_count;
+    /// ```
+    ///
+    /// # rhai-autodocs:index:25
+    #[rhai_fn(global, get = "command_count")]
+    pub fn command_count(ctx: &mut Ctx) -> rhai::INT {
+        ctx.read(vsmtp_common::ctx::Ctx::command_count)
+    }
+
     /// Get the name of the server.
     ///
     /// # SMTP stages
@@ -389,12 +472,17 @@ mod mail_context {
     ///
     /// # Return
     ///
-    /// * `Array of addresses` - the list containing all recipients.
+    /// * `Array of recipients` - the list containing all recipients, with their
+    ///   `address`, `route`, `notify_on` and `orcpt`.
     ///
     /// # Examples
     ///
     /// ```js
     /// log("my_queue", "info", `recipients: ${ctx.recipients}`);
+    ///
+    /// for rcpt in ctx.recipients {
+    ///     log("my_queue", "info", `${rcpt.address} is delivered by ${rcpt.route}`);
+    /// }
     /// ```
     ///
     /// # rhai-autodocs:index:16
@@ -404,15 +492,79 @@ mod mail_context {
             Ok(ctx
                 .metadata
                 .get_rcpt_to()?
-                .recipient
-                .values()
-                .flat_map(|i| i.iter().cloned())
+                .recipient_values_with_route()
+                .map(|(route, recipient)| EnvelopeRecipient {
+                    recipient: recipient.clone(),
+                    route: route.clone(),
+                })
                 .map(rhai::Shared::new)
                 .map(rhai::Dynamic::from)
                 .collect::<rhai::Array>())
         })
     }
 
+    /// Get the parameters of the `MAIL FROM` command following the sender address,
+    /// as received, to inspect the parameters vSMTP does not handle.
+    ///
+    /// # SMTP stages
+    ///
+    /// `mail` and onwards, the parameters are not sent with the message to the other services.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the parameters, ex: `"SIZE=1000 XFOO=bar"`, empty if there is none.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_mail_from(ctx) {
+    ///     ctx.run([
+    ///         rule "tracking" |ctx| {
+    ///             log("info", `MAIL FROM parameters: ${ctx.mail_from_parameters}`);
+    ///             status::next()
+    ///         },
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:28
+    #[rhai_fn(global, get = "mail_from_parameters", return_raw)]
+    pub fn mail_from_parameters(ctx: &mut Ctx) -> Result<String> {
+        ctx.read(vsmtp_common::ctx::Ctx::mail_from_parameters)
+            .ok_or_else(|| "the `MAIL FROM` command has not been received".into())
+    }
+
+    /// Get the parameters of the last `RCPT TO` command following the recipient address,
+    /// as received, to inspect the parameters vSMTP does not handle.
+    ///
+    /// # SMTP stages
+    ///
+    /// `rcpt` and onwards, the parameters are not sent with the message to the other services.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the parameters, ex: `"NOTIFY=NEVER XFOO=bar"`, empty if there is none.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_rcpt_to(ctx) {
+    ///     ctx.run([
+    ///         rule "tracking" |ctx| {
+    ///             log("info", `RCPT TO parameters: ${ctx.rcpt_to_parameters}`);
+    ///             status::next()
+    ///         },
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:29
+    #[rhai_fn(global, get = "rcpt_to_parameters", return_raw)]
+    pub fn rcpt_to_parameters(ctx: &mut Ctx) -> Result<String> {
+        ctx.read(vsmtp_common::ctx::Ctx::rcpt_to_parameters)
+            .ok_or_else(|| "the `RCPT TO` command has not been received".into())
+    }
+
     /// Store a custom variable into the context that can be
     /// fetched from any state in any service.
     ///
@@ -495,4 +647,310 @@ mod mail_context {
     pub fn get_variable(ctx: &mut Ctx, variable: &str) -> rhai::Dynamic {
         ctx.read(|ctx| ctx.variables.get(variable).cloned().unwrap_or_default())
     }
+
+    /// Get the whole transaction context as an object, to log it or to
+    /// access fields that do not have a dedicated getter.
+    ///
+    /// # SMTP stages
+    ///
+    /// Any stage, the object contains the `connect`, `helo`, `mail_from`, `rcpt_to`
+    /// and `complete` sections received so far. The message body and the
+    /// SASL credentials are not included.
+    ///
+    /// # Return
+    ///
+    /// * `map` - the context.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_rcpt_to(ctx) {
+    ///     let object = ctx.to_object();
+    ///     log("my_queue", "debug", `context: ${object}`);
+    ///
+    ///     if object.helo.using_deprecated {
+    ///         // ...
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:19
+    #[rhai_fn(global, return_raw, pure)]
+    pub fn to_object(ctx: &mut Ctx) -> Result<rhai::Map> {
+        ctx.read(|ctx| ctx.metadata.to_object())
+    }
+
+    /// Get the kind of the listener which accepted the connection.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them.
+    ///
+    /// # Return
+    ///
+    /// * `string` - `relay` (port 25), `submission` (port 587) or `tunneled` (port 465).
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_connect(ctx) {
+    ///     if ctx.connection_kind() == "relay" && ctx.server_address() != "192.168.1.254:25" {
+    ///         return status::deny();
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:20
+    #[rhai_fn(global, pure)]
+    pub fn connection_kind(ctx: &mut Ctx) -> String {
+        ctx.read(|ctx| ctx.metadata.get_connect().kind.to_string())
+    }
+
+    /// Check that the connection is secured with a minimum version of TLS,
+    /// and optionally with one of the allowed cipher suites.
+    ///
+    /// # Args
+    ///
+    /// * `min_version` - the minimum version of TLS, `"TLSv1.2"` or `"TLSv1.3"`.
+    /// * `allowed_ciphers` - (optional) an array of the allowed cipher suites,
+    ///   named as in the `tls.cipher_suite` field of the configuration. Any cipher suite is
+    ///   allowed if the array is empty or omitted.
+    ///
+    /// # Return
+    ///
+    /// * `bool` - `true` if the connection is secured and meets the policy, `false` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// * The version or one of the cipher suites is not valid.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_mail_from(ctx) {
+    ///     if ctx.connection_kind() == "submission" && !ctx.tls_meets_policy("TLSv1.3") {
+    ///         return status::deny();
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:21
+    #[rhai_fn(global, name = "tls_meets_policy", return_raw, pure)]
+    pub fn tls_meets_policy(
+        ctx: &mut Ctx,
+        min_version: &str,
+        allowed_ciphers: rhai::Array,
+    ) -> Result<bool> {
+        let min_version = min_version
+            .parse::<ProtocolVersion>()
+            .map_err(|_| format!("invalid TLS version '{min_version}'"))?;
+        let allowed_ciphers = allowed_ciphers
+            .into_iter()
+            .map(|cipher| cipher.to_string().parse::<CipherSuite>())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(ctx.read(|ctx| {
+            ctx.metadata
+                .get_connect()
+                .tls
+                .as_ref()
+                .is_some_and(|tls| tls.meets_policy(&min_version, &allowed_ciphers))
+        }))
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(global, name = "tls_meets_policy", return_raw, pure)]
+    pub fn tls_meets_min_version(ctx: &mut Ctx, min_version: &str) -> Result<bool> {
+        tls_meets_policy(ctx, min_version, rhai::Array::new())
+    }
+
+    /// Get the protocol negotiated with ALPN during the TLS handshake.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the protocol (e.g. `"smtp"`), or `()` if the connection is not secured
+    ///   or no protocol has been negotiated.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_helo(ctx) {
+    ///     log("my_queue", "debug", `ALPN protocol: ${ctx.alpn_protocol}`);
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:26
+    #[rhai_fn(global, get = "alpn_protocol")]
+    pub fn alpn_protocol(ctx: &mut Ctx) -> rhai::Dynamic {
+        ctx.read(|ctx| {
+            ctx.metadata
+                .get_connect()
+                .tls
+                .as_ref()
+                .and_then(|tls| tls.alpn_protocol.as_deref())
+                .map_or_else(rhai::Dynamic::default, |protocol| {
+                    String::from_utf8_lossy(protocol).into_owned().into()
+                })
+        })
+    }
+
+    /// Get the identity of the client certificate sent during the TLS handshake.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them.
+    ///
+    /// # Return
+    ///
+    /// * `map` - the identity of the certificate, or `()` if the connection is not secured
+    ///   or the client did not send a certificate, with the fields:
+    ///   * `subject` - the distinguished name of the subject (e.g. `"O=Example, CN=john.doe"`).
+    ///   * `common_name` - the common name of the subject, or `()`.
+    ///   * `issuer` - the distinguished name of the issuer.
+    ///   * `emails` - an array of the email addresses of the subject alternative names.
+    ///   * `dns_names` - an array of the domain names of the subject alternative names.
+    ///
+    /// The clients are asked for a certificate only if `tls.client_auth` is configured
+    /// on the receiver, the certificate being verified against its authorities during
+    /// the handshake.
+    ///
+    /// # Errors
+    ///
+    /// * The certificate cannot be parsed.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_mail_from(ctx) {
+    ///     let certificate = ctx.client_certificate;
+    ///
+    ///     if certificate == () || !("john.doe@example.com" in certificate.emails) {
+    ///         return status::deny();
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:27
+    #[rhai_fn(global, get = "client_certificate", return_raw)]
+    pub fn client_certificate(ctx: &mut Ctx) -> Result<rhai::Dynamic> {
+        let Some(identity) = ctx.read(|ctx| {
+            ctx.metadata
+                .get_connect()
+                .tls
+                .as_ref()
+                .and_then(vsmtp_common::tls::TlsProps::peer_identity)
+        }) else {
+            return Ok(rhai::Dynamic::UNIT);
+        };
+        let identity = identity.map_err(|e| e.to_string())?;
+
+        Ok(rhai::Map::from_iter([
+            ("subject".into(), identity.subject.into()),
+            (
+                "common_name".into(),
+                identity.common_name.map_or(rhai::Dynamic::UNIT, Into::into),
+            ),
+            ("issuer".into(), identity.issuer.into()),
+            ("emails".into(), identity.emails.into()),
+            ("dns_names".into(), identity.dns_names.into()),
+        ])
+        .into())
+    }
+
+    /// Tag the message with a metadata, carried with the message through all the services
+    /// (receiver, working and delivery), a spam score or a customer id for example.
+    ///
+    /// Unlike `ctx.set_variable`, only plain data can be stored, so that the value
+    /// survives the serialization of the context between the services.
+    ///
+    /// # Args
+    ///
+    /// * `key` - The name of the metadata.
+    /// * `value` - The value of the metadata, a boolean, a number, a string,
+    ///   or an array or a map of those.
+    ///
+    /// # Return
+    ///
+    /// The previous value of the metadata, a `()` rhai unit if it was not set.
+    ///
+    /// # Errors
+    ///
+    /// * The value is not plain data.
+    ///
+    /// # SMTP stages
+    ///
+    /// Any stage.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// // Called on the smtp receiver service.
+    /// fn on_pre_queue(ctx) {
+    ///     ctx.set_var("customer_id", 42);
+    ///     status::next()
+    /// }
+    ///
+    /// // Called on the working service.
+    /// fn on_post_queue(ctx) {
+    ///     log("my_queue", "info", `message of customer ${ctx.get_var("customer_id")}`);
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:22
+    #[rhai_fn(global, return_raw)]
+    pub fn set_var(ctx: &mut Ctx, key: &str, value: rhai::Dynamic) -> Result<rhai::Dynamic> {
+        if !is_plain_data(&value) {
+            return Err(format!(
+                "cannot set '{key}', a value of type '{}' is not carried with the message",
+                value.type_name()
+            )
+            .into());
+        }
+
+        Ok(set_variable(ctx, key, value))
+    }
+
+    /// Get a metadata of the message set by `ctx.set_var` in any stage or service.
+    ///
+    /// # Args
+    ///
+    /// * `key` - The name of the metadata.
+    ///
+    /// # Return
+    ///
+    /// The value of the metadata if it exists, a `()` rhai unit otherwise.
+    ///
+    /// # SMTP stages
+    ///
+    /// Any stage.
+    ///
+    /// # rhai-autodocs:index:23
+    #[rhai_fn(global)]
+    pub fn get_var(ctx: &mut Ctx, key: &str) -> rhai::Dynamic {
+        get_variable(ctx, key)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(global, name = "server_address", pure)]
+    pub fn server_address_fn(ctx: &mut Ctx) -> String {
+        server_address(ctx)
+    }
 }
@@ -13,6 +13,16 @@ pub use mailbox_rhai::*;
 use rhai::plugin::{
     Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult, TypeId,
 };
+use vsmtp_common::delivery_route::DeliveryRoute;
+use vsmtp_protocol::NotifyOn;
+
+/// A recipient of the envelope, with the route of its delivery,
+/// exposed to the rules as a [`Recipient`].
+#[derive(Debug, Clone)]
+pub struct EnvelopeRecipient {
+    pub recipient: vsmtp_common::Recipient,
+    pub route: DeliveryRoute,
+}
 
 /// Rhai wrapper for the Mailbox type, instead of using dynamic
 #[derive(Debug, Clone)]
@@ -59,7 +69,7 @@ mod mailbox_rhai {
     /// Use `ctx.recipients` to get a list of this object.
     ///
     /// # rhai-autodocs:index:1
-    pub type Recipient = rhai::Shared<vsmtp_common::Recipient>;
+    pub type Recipient = rhai::Shared<EnvelopeRecipient>;
 
     /// Get a recipient's domain.
     ///
@@ -77,7 +87,7 @@ mod mailbox_rhai {
     /// # rhai-autodocs:index:2
     #[rhai_fn(global, pure, get = "domain")]
     pub fn recipient_domain(rcpt: &mut Recipient) -> String {
-        rcpt.forward_path.domain().to_string()
+        rcpt.recipient.forward_path.domain().to_string()
     }
 
     /// Get a recipient's address local part.
@@ -96,7 +106,7 @@ mod mailbox_rhai {
     /// # rhai-autodocs:index:3
     #[rhai_fn(global, pure, get = "local_part")]
     pub fn recipient_local_part(rcpt: &mut Recipient) -> String {
-        rcpt.forward_path.local_part().to_string()
+        rcpt.recipient.forward_path.local_part().to_string()
     }
 
     /// Get a recipient's address.
@@ -115,7 +125,80 @@ mod mailbox_rhai {
     /// # rhai-autodocs:index:4
     #[rhai_fn(global, pure, get = "address")]
     pub fn recipient_address(rcpt: &mut Recipient) -> String {
-        rcpt.forward_path.to_string()
+        rcpt.recipient.forward_path.to_string()
+    }
+
+    /// Get the route of a recipient's delivery, see `ctx.set_routing_path`.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     for rcpt in ctx.recipients {
+    ///         if rcpt.route == "maildir" {
+    ///             // ...
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    /// # rhai-autodocs:index:10
+    #[rhai_fn(global, pure, get = "route")]
+    pub fn recipient_route(rcpt: &mut Recipient) -> String {
+        rcpt.route.to_string()
+    }
+
+    /// Get the events for which a recipient requested a DSN with the `NOTIFY` parameter,
+    /// among `"success"`, `"failure"` and `"delay"`. Empty if no DSN must be produced.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     for rcpt in ctx.recipients {
+    ///         if "success" in rcpt.notify_on {
+    ///             // ...
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    /// # rhai-autodocs:index:11
+    #[rhai_fn(global, pure, get = "notify_on")]
+    pub fn recipient_notify_on(rcpt: &mut Recipient) -> rhai::Array {
+        match rcpt.recipient.notify_on {
+            NotifyOn::Never => rhai::Array::new(),
+            NotifyOn::Some {
+                success,
+                failure,
+                delay,
+            } => [(success, "success"), (failure, "failure"), (delay, "delay")]
+                .into_iter()
+                .filter(|(requested, _)| *requested)
+                .map(|(_, event)| event.into())
+                .collect(),
+        }
+    }
+
+    /// Get the original address of a recipient, sent by the client with the `ORCPT`
+    /// parameter or kept when the recipient was rewritten. `()` if there is none.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     for rcpt in ctx.recipients {
+    ///         if rcpt.orcpt != () {
+    ///             log("info", `${rcpt.orcpt} rewritten to ${rcpt.address}`);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    /// # rhai-autodocs:index:12
+    #[rhai_fn(global, pure, get = "orcpt")]
+    pub fn recipient_orcpt(rcpt: &mut Recipient) -> Dynamic {
+        rcpt.recipient
+            .original_forward_path
+            .as_ref()
+            .map_or(Dynamic::UNIT, |orcpt| orcpt.mailbox.to_string().into())
     }
 
     #[doc(hidden)]
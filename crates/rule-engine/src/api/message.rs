@@ -19,6 +19,37 @@ use vsmtp_mail_parser::mail::headers::Header;
 
 pub use message::*;
 
+/// Build a header from a rhai value, checking that the name is a valid field name
+/// and that the value can be written on a single line.
+/// <https://www.rfc-editor.org/rfc/rfc5322#section-2.2>
+fn header_from_dynamic(name: &str, value: rhai::Dynamic) -> Result<Header> {
+    if name.is_empty() || !name.bytes().all(|c| (33..=126).contains(&c) && c != b':') {
+        return Err(format!("invalid header name '{name}'").into());
+    }
+
+    let value = value
+        .into_immutable_string()
+        .map_err::<Box<rhai::EvalAltResult>, _>(|ty| {
+            format!("value of header '{name}' must be a string, got {ty}").into()
+        })?;
+    if value.contains(['\r', '\n']) {
+        return Err(format!("value of header '{name}' must not contain line breaks").into());
+    }
+
+    Ok(Header::new(name, value))
+}
+
+/// Get the domain of the first mailbox of the `name` header.
+fn first_domain(ctx: &mut Ctx, name: &str) -> Result<rhai::Dynamic> {
+    Ok(ctx.read(|ctx| {
+        ctx.metadata.get_mail(|mail| {
+            mail.header_addresses(name)
+                .first()
+                .map_or_else(|| ().into(), |address| address.domain().into())
+        })
+    })?)
+}
+
 /// Inspect incoming messages.
 #[rhai::plugin::export_module]
 mod message {
@@ -269,6 +300,73 @@ mod message {
         })?)
     }
 
+    /// Add multiple headers **at the end** of the header list in the message, at once.
+    ///
+    /// Every header is validated before the message is modified: if one of them
+    /// is malformed, an error is returned and none of them are added.
+    ///
+    /// # Args
+    ///
+    /// * `headers` - a map of header names and values, added in the alphabetical order of the names,
+    ///   or an array of `[name, value]` pairs, added in the order of the array.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them. Even though the email is not received at the current stage,
+    /// vsmtp stores new headers and will add them on top of the ones received once
+    /// the `pre_queue` stage is reached.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_post_queue(ctx) {
+    ///     ctx.add_headers(#{ "X-My-Header": "foo", "X-My-Header-2": "bar" });
+    ///     ctx.add_headers([
+    ///         ["X-My-Header-3", "baz"],
+    ///         ["X-My-Header-4", "qux"],
+    ///     ]);
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:9
+    #[rhai_fn(global, name = "add_headers", return_raw)]
+    pub fn add_headers_map(ctx: &mut Ctx, headers: rhai::Map) -> Result<()> {
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| super::header_from_dynamic(name.as_str(), value))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ctx.write(|ctx| ctx.metadata.mut_mail(|mail| mail.append_headers(headers)))?)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(global, name = "add_headers", return_raw)]
+    pub fn add_headers_array(ctx: &mut Ctx, headers: rhai::Array) -> Result<()> {
+        let headers = headers
+            .into_iter()
+            .map(|pair| {
+                let pair = pair
+                    .try_cast::<rhai::Array>()
+                    .filter(|pair| pair.len() == 2)
+                    .ok_or_else::<Box<rhai::EvalAltResult>, _>(|| {
+                        "headers must be given as `[name, value]` pairs".into()
+                    })?;
+                let [name, value]: [rhai::Dynamic; 2] =
+                    pair.try_into().expect("length checked above");
+                let name = name
+                    .into_immutable_string()
+                    .map_err::<Box<rhai::EvalAltResult>, _>(|ty| {
+                        format!("header name must be a string, got {ty}").into()
+                    })?;
+
+                super::header_from_dynamic(&name, value)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ctx.write(|ctx| ctx.metadata.mut_mail(|mail| mail.append_headers(headers)))?)
+    }
+
     /// Add a new header on top all other headers in the message.
     ///
     /// # Args
@@ -536,4 +634,358 @@ mod message {
     pub fn body_string(ctx: &mut Ctx) -> Result<String> {
         Ok(ctx.write(|ctx| ctx.metadata.get_mail(|mail| mail.body.to_string()))?)
     }
+
+    /// Get the text of the body, to scan its content regardless of its encoding.
+    ///
+    /// For MIME messages, the text and html parts that are not attachments are decoded
+    /// following their `Content-Transfer-Encoding`, converted from their charset to UTF-8
+    /// and concatenated.
+    ///
+    /// # Errors
+    ///
+    /// * The body of the message could not be parsed or decoded.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     if ctx.body_text().contains("verify your account") {
+    ///         return status::quarantine("phishing");
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:22
+    #[rhai_fn(global, return_raw)]
+    pub fn body_text(ctx: &mut Ctx) -> Result<String> {
+        ctx.write(|ctx| {
+            ctx.metadata.mut_mail(|mail| {
+                mail.body_text()
+                    .map_err(|error| format!("failed to decode the body: {error}").into())
+            })?
+        })
+    }
+
+    /// Get the URLs of the body, to check them against a blocklist.
+    ///
+    /// The URLs (`http`, `https` and `ftp`) are extracted from the decoded text and html
+    /// parts (see `body_text`), both from the `href` attributes and the bare links,
+    /// without duplicates and in order of appearance.
+    ///
+    /// # Errors
+    ///
+    /// * The body of the message could not be parsed or decoded.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     for url in ctx.urls() {
+    ///         if url.contains("login.example.com") {
+    ///             return status::deny("554 5.7.1 Phishing URL detected");
+    ///         }
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:23
+    #[rhai_fn(global, return_raw)]
+    pub fn urls(ctx: &mut Ctx) -> Result<rhai::Array> {
+        ctx.write(|ctx| {
+            ctx.metadata.mut_mail(|mail| {
+                mail.urls()
+                    .map(|urls| urls.into_iter().map(Into::into).collect())
+                    .map_err(|error| format!("failed to decode the body: {error}").into())
+            })?
+        })
+    }
+
+    /// Get a stable fingerprint of the email, as an hexadecimal SHA-256 digest
+    /// of its identifying headers (`From`, `To`, `Cc`, `Subject`, `Date` and `Message-ID`)
+    /// and its body. Trace headers and trailing whitespaces are ignored, so a message
+    /// sent again by a retrying client has the same fingerprint.
+    ///
+    /// Record the fingerprint in a store (see the `seen` function of the
+    /// redis and sqlite plugins) to detect duplicates.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     if global::dedup.seen(ctx.fingerprint, "1h") {
+    ///         return status::deny("554 5.7.0 Duplicate message");
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:21
+    #[rhai_fn(global, get = "fingerprint", return_raw)]
+    pub fn fingerprint(ctx: &mut Ctx) -> Result<String> {
+        Ok(ctx.read(|ctx| ctx.metadata.get_mail(vsmtp_mail_parser::Mail::fingerprint))?)
+    }
+
+    /// Append a footer (like a legal disclaimer) to the body of the email.
+    ///
+    /// For MIME messages, the footers are appended to the text and html parts that are
+    /// not attachments, the html footer being inserted before the closing `</body>` tag.
+    /// Other messages only get the text footer. An empty footer is derived from the other
+    /// one, so that all the versions of a `multipart/alternative` message get the footer.
+    ///
+    /// The footer is encoded in the charset of each part, the parts whose charset cannot
+    /// represent it being converted to UTF-8.
+    ///
+    /// The footer must be appended before signing the message with
+    /// [dkim::sign](http://vsmtp.rs/docs/global/dkim#fn-sign), as modifying the body
+    /// invalidates the signature. The signatures of the previous hops are not checked.
+    ///
+    /// # Args
+    ///
+    /// * `text_plain` - the footer of the text parts.
+    /// * `text_html` - the footer of the html parts.
+    ///
+    /// # Errors
+    ///
+    /// * The message has already been signed by this server.
+    /// * The charset of a part is unknown, or its content is not valid in this charset.
+    /// * The body of the message could not be parsed.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_post_queue(ctx) {
+    ///     ctx.append_footer(
+    ///         "\r\n-- \r\nThis email is confidential.\r\n",
+    ///         "<p>This email is confidential.</p>",
+    ///     );
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:20
+    #[rhai_fn(global, return_raw)]
+    pub fn append_footer(ctx: &mut Ctx, text_plain: &str, text_html: &str) -> Result<()> {
+        ctx.write(|ctx| {
+            let signatures = ctx.dkim_signatures();
+            ctx.metadata.mut_mail(|mail| {
+                if mail.get_headers("DKIM-Signature").any(|header| {
+                    signatures
+                        .iter()
+                        .any(|signature| header.body.trim() == signature.as_str())
+                }) {
+                    return Err("the footer must be appended before signing the message".into());
+                }
+
+                mail.append_footer(text_plain, text_html)
+                    .map_err(|error| format!("failed to append the footer: {error}").into())
+            })?
+        })
+    }
+
+    /// Get the domain of the `From` header, parsed as a list of mailboxes.
+    ///
+    /// The display names, comments and groups are ignored. If the header contains
+    /// multiple mailboxes, the domain of the first one is returned.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the domain of the author of the message.
+    /// * `()` - the header is missing or does not contain a valid mailbox.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     // `From: "Doe, John" <john.doe@example.com>` => "example.com"
+    ///     if ctx.header_from_domain() != ctx.sender.domain {
+    ///         log("my_queue", "warn", "the author is not aligned with the sender");
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:24
+    #[rhai_fn(global, return_raw)]
+    pub fn header_from_domain(ctx: &mut Ctx) -> Result<rhai::Dynamic> {
+        first_domain(ctx, "From")
+    }
+
+    /// Get the domain of the `Sender` header, see [`header_from_domain`].
+    ///
+    /// # Return
+    ///
+    /// * `string` - the domain of the agent which submitted the message.
+    /// * `()` - the header is missing or does not contain a valid mailbox.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     let sender = ctx.header_sender_domain();
+    ///     if sender != () && sender != ctx.header_from_domain() {
+    ///         log("my_queue", "info", `sent on behalf of the author by ${sender}`);
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:25
+    #[rhai_fn(global, return_raw)]
+    pub fn header_sender_domain(ctx: &mut Ctx) -> Result<rhai::Dynamic> {
+        first_domain(ctx, "Sender")
+    }
+
+    /// Get the addresses of the `Reply-To` headers, without the display names.
+    /// The members of the groups are included, and the malformed mailboxes are skipped.
+    ///
+    /// # Return
+    ///
+    /// * `array` - the `local-part@domain` addresses, empty if the header is missing.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     // `Reply-To: Support <help@example.com>, billing@example.org`
+    ///     // => ["help@example.com", "billing@example.org"]
+    ///     for addr in ctx.header_reply_to() {
+    ///         if !addr.ends_with("@example.com") {
+    ///             log("my_queue", "warn", `replies are redirected to ${addr}`);
+    ///         }
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:26
+    #[rhai_fn(global, return_raw)]
+    pub fn header_reply_to(ctx: &mut Ctx) -> Result<rhai::Array> {
+        Ok(ctx.read(|ctx| {
+            ctx.metadata.get_mail(|mail| {
+                mail.header_addresses("Reply-To")
+                    .into_iter()
+                    .map(|address| address.address.into())
+                    .collect()
+            })
+        })?)
+    }
+
+    /// Get the value of the `Subject` header, with its RFC 2047 encoded words
+    /// (ex: `=?utf-8?B?Q2Fmw6k=?=`) decoded.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the decoded subject.
+    /// * `()` - the header is missing.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     if ctx.subject.contains("invoice") {
+    ///         log("my_queue", "info", `invoice received: ${ctx.subject}`);
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:27
+    #[rhai_fn(global, get = "subject", return_raw)]
+    pub fn subject(ctx: &mut Ctx) -> Result<rhai::Dynamic> {
+        Ok(ctx.read(|ctx| {
+            ctx.metadata.get_mail(|mail| {
+                mail.subject()
+                    .map_or_else(rhai::Dynamic::default, Into::into)
+            })
+        })?)
+    }
+
+    /// Set the value of the `Subject` header, or add it if it is missing.
+    /// The value is encoded with RFC 2047 encoded words if it is not plain ASCII.
+    ///
+    /// # Args
+    ///
+    /// * `subject` - the new subject, decoded.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     ctx.set_subject(`Réf. ${ctx.get_var("ticket")} - ${ctx.subject}`);
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:28
+    #[rhai_fn(global, return_raw)]
+    pub fn set_subject(ctx: &mut Ctx, subject: &str) -> Result<()> {
+        Ok(ctx.write(|ctx| ctx.metadata.mut_mail(|mail| mail.set_subject(subject)))?)
+    }
+
+    /// Prefix the `Subject` header (ex: `[SPAM] `), see [`set_subject`].
+    /// Nothing is done if the subject already starts with the prefix, so a message
+    /// scanned twice is not tagged twice. A subject in an unknown charset is prefixed as is.
+    ///
+    /// # Args
+    ///
+    /// * `prefix` - the text to insert at the start of the subject, including the separator.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     if ctx.get_var("spam_score") > 5.0 {
+    ///         ctx.prefix_subject("[SPAM] ");
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:29
+    #[rhai_fn(global, return_raw)]
+    pub fn prefix_subject(ctx: &mut Ctx, prefix: &str) -> Result<()> {
+        Ok(ctx.write(|ctx| ctx.metadata.mut_mail(|mail| mail.prefix_subject(prefix)))?)
+    }
 }
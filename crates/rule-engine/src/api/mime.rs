@@ -0,0 +1,322 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Result;
+use crate::api::docs::Ctx;
+use rhai::plugin::{
+    Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult, TypeId,
+};
+use vsmtp_mail_parser::{
+    mail::body::ParsedBody,
+    mime::{Mime, Part},
+};
+
+pub use mime_rhai::*;
+
+/// Parse the body of the email and get a copy of its root mime part, if any.
+pub(super) fn root(ctx: &Ctx) -> Result<Option<Mime>> {
+    ctx.write(|ctx| {
+        ctx.metadata.mut_mail(|mail| match mail.parse_body() {
+            Ok(ParsedBody::Mime(mime)) => Ok(Some(mime.as_ref().clone())),
+            Ok(_) => Ok(None),
+            Err(error) => Err(format!("failed to parse the body of the email: {error}").into()),
+        })?
+    })
+}
+
+/// Parse the body of the email and run `f` on its root mime part.
+fn with_root_mut<O>(ctx: &Ctx, f: impl FnOnce(&mut Mime) -> Result<O>) -> Result<O> {
+    ctx.write(|ctx| {
+        ctx.metadata.mut_mail(|mail| match mail.parse_body() {
+            Ok(ParsedBody::Mime(mime)) => f(mime),
+            Ok(_) => Err("the email is not a MIME message".into()),
+            Err(error) => Err(format!("failed to parse the body of the email: {error}").into()),
+        })?
+    })
+}
+
+/// Convert the index of a part given by a script.
+fn part_index(index: rhai::INT) -> Result<usize> {
+    usize::try_from(index).map_err(|_| format!("invalid mime part index {index}").into())
+}
+
+/// Replace the content of the `index`-th part of `ctx.mime_parts`.
+fn set_part_body(ctx: &Ctx, index: rhai::INT, body: &[u8]) -> Result<()> {
+    let index = part_index(index)?;
+    with_root_mut(ctx, |mime| {
+        mime.leaf_mut(index)
+            .ok_or_else::<Box<rhai::EvalAltResult>, _>(|| {
+                format!("mime part {index} does not exist").into()
+            })?
+            .set_body(body)
+            .map_err(|error| error.to_string().into())
+    })
+}
+
+/// Push the leaf parts of `mime` in `parts`, depth first.
+fn flatten(mime: &Mime, parts: &mut rhai::Array) {
+    match &mime.part {
+        Part::Multipart(multipart) => {
+            for part in &multipart.parts {
+                flatten(part, parts);
+            }
+        }
+        _ => parts.push(rhai::Dynamic::from(rhai::Shared::new(mime.clone()))),
+    }
+}
+
+/// Inspect and rewrite the MIME structure of the email.
+#[rhai::plugin::export_module]
+mod mime_rhai {
+
+    /// A part of a MIME message, which can be a multipart section
+    /// containing other parts. Use `ctx.mime` or `ctx.mime_parts` to get this object.
+    ///
+    /// # rhai-autodocs:index:1
+    pub type MimePart = rhai::Shared<vsmtp_mail_parser::mime::Mime>;
+
+    /// Get the root part of the MIME structure of the email,
+    /// or `()` if the email is not a MIME message.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     let root = ctx.mime;
+    ///     if root != () && root.is_multipart {
+    ///         for part in root.parts {
+    ///             log("my_queue", "info", part.content_type);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:2
+    #[rhai_fn(global, get = "mime", return_raw)]
+    pub fn mime(ctx: &mut Ctx) -> Result<rhai::Dynamic> {
+        Ok(root(ctx)?.map_or_else(rhai::Dynamic::default, |mime| {
+            rhai::Dynamic::from(rhai::Shared::new(mime))
+        }))
+    }
+
+    /// Get all the parts of the email that are not multipart sections,
+    /// in the order they appear in the message.
+    /// The array is empty if the email is not a MIME message.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     for part in ctx.mime_parts {
+    ///         if part.is_attachment && part.content_type == "application/x-msdownload" {
+    ///             return status::deny();
+    ///         }
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:3
+    #[rhai_fn(global, get = "mime_parts", return_raw)]
+    pub fn mime_parts(ctx: &mut Ctx) -> Result<rhai::Array> {
+        let mut parts = rhai::Array::new();
+        if let Some(mime) = root(ctx)? {
+            flatten(&mime, &mut parts);
+        }
+
+        Ok(parts)
+    }
+
+    /// Get the direct children of a multipart section.
+    /// The array is empty for any other part.
+    ///
+    /// # rhai-autodocs:index:4
+    #[rhai_fn(global, pure, get = "parts")]
+    pub fn parts(part: &mut MimePart) -> rhai::Array {
+        match &part.part {
+            Part::Multipart(multipart) => multipart
+                .parts
+                .iter()
+                .cloned()
+                .map(rhai::Shared::new)
+                .map(rhai::Dynamic::from)
+                .collect(),
+            _ => rhai::Array::new(),
+        }
+    }
+
+    /// Get the mime type of a part, in lower case (e.g. `"text/plain"`).
+    ///
+    /// # rhai-autodocs:index:5
+    #[rhai_fn(global, pure, get = "content_type")]
+    pub fn content_type(part: &mut MimePart) -> String {
+        part.content_type()
+    }
+
+    /// Get the disposition of a part (e.g. `"inline"` or `"attachment"`),
+    /// or `()` if the part does not have a Content-Disposition header.
+    ///
+    /// # rhai-autodocs:index:6
+    #[rhai_fn(global, pure, get = "disposition")]
+    pub fn disposition(part: &mut MimePart) -> rhai::Dynamic {
+        part.disposition()
+            .map_or_else(rhai::Dynamic::default, |disposition| disposition.into())
+    }
+
+    /// Get the file name of a part, or `()` if the part does not have one.
+    /// Long and non-ASCII names split or encoded following the RFC 2231 are decoded.
+    ///
+    /// # rhai-autodocs:index:7
+    #[rhai_fn(global, pure, get = "filename")]
+    pub fn filename(part: &mut MimePart) -> rhai::Dynamic {
+        part.filename()
+            .map_or_else(rhai::Dynamic::default, |filename| filename.into())
+    }
+
+    /// Is the part an attachment ? Binary content, embedded emails and
+    /// parts with the `attachment` disposition are attachments.
+    ///
+    /// # rhai-autodocs:index:8
+    #[rhai_fn(global, pure, get = "is_attachment")]
+    pub fn is_attachment(part: &mut MimePart) -> bool {
+        part.is_attachment()
+    }
+
+    /// Is the part a multipart section ?
+    ///
+    /// # rhai-autodocs:index:9
+    #[rhai_fn(global, pure, get = "is_multipart")]
+    pub fn is_multipart(part: &mut MimePart) -> bool {
+        matches!(part.part, Part::Multipart(_))
+    }
+
+    /// Get the content of a part, decoded following its Content-Transfer-Encoding.
+    /// Bytes that are not valid UTF-8 are replaced.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_pre_queue(ctx) {
+    ///     for part in ctx.mime_parts {
+    ///         if part.content_type == "text/plain" && part.body.contains("lottery") {
+    ///             return status::quarantine("spam");
+    ///         }
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:10
+    #[rhai_fn(global, pure, get = "body", return_raw)]
+    pub fn body(part: &mut MimePart) -> Result<String> {
+        part.decoded_body()
+            .map(|body| String::from_utf8_lossy(&body).into_owned())
+            .map_err(|error| error.to_string().into())
+    }
+
+    /// Replace the content of a part of the email, encoding it following the
+    /// Content-Transfer-Encoding of the part. The part is designated by its
+    /// index in `ctx.mime_parts`.
+    ///
+    /// # Args
+    ///
+    /// * `index` - the index of the part in `ctx.mime_parts`.
+    /// * `body` - the new content of the part, as a string or a blob.
+    ///
+    /// # Errors
+    ///
+    /// * The email is not a MIME message.
+    /// * The part does not exist, or is an embedded email.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_post_queue(ctx) {
+    ///     let parts = ctx.mime_parts;
+    ///     for i in 0..parts.len() {
+    ///         if parts[i].content_type == "text/plain" {
+    ///             ctx.set_mime_part_body(i, parts[i].body + "-- \r\nSent with vSMTP\r\n");
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:11
+    #[rhai_fn(global, name = "set_mime_part_body", return_raw)]
+    pub fn set_mime_part_body_str(ctx: &mut Ctx, index: rhai::INT, body: &str) -> Result<()> {
+        set_part_body(ctx, index, body.as_bytes())
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(global, name = "set_mime_part_body", return_raw)]
+    pub fn set_mime_part_body_blob(
+        ctx: &mut Ctx,
+        index: rhai::INT,
+        body: rhai::Blob,
+    ) -> Result<()> {
+        set_part_body(ctx, index, &body)
+    }
+
+    /// Remove a part of the email, designated by its index in `ctx.mime_parts`.
+    /// Multipart sections left empty are removed too.
+    ///
+    /// # Args
+    ///
+    /// * `index` - the index of the part in `ctx.mime_parts`.
+    ///
+    /// # Errors
+    ///
+    /// * The email is not a MIME message.
+    /// * The part does not exist, or is the last part of the email.
+    ///
+    /// # SMTP stages
+    ///
+    /// `pre_queue` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_post_queue(ctx) {
+    ///     let parts = ctx.mime_parts;
+    ///     // iterate backwards, the index of the following parts changes on removal.
+    ///     for i in range(parts.len() - 1, -1, -1) {
+    ///         if parts[i].is_attachment {
+    ///             ctx.remove_mime_part(i);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:12
+    #[rhai_fn(global, return_raw)]
+    pub fn remove_mime_part(ctx: &mut Ctx, index: rhai::INT) -> Result<()> {
+        let index = part_index(index)?;
+        with_root_mut(ctx, |mime| {
+            mime.remove_leaf(index)
+                .map(|_| ())
+                .map_err(|error| error.to_string().into())
+        })
+    }
+}
@@ -11,7 +11,9 @@
 
 use vsmtp_common::dns_resolver::DnsResolver;
 
+mod anomaly;
 mod auth;
+mod callout;
 mod dkim;
 mod dmarc;
 mod dns;
@@ -22,10 +24,22 @@ mod logging;
 mod mail_context;
 mod mailbox;
 mod message;
+mod mime;
 mod net;
+mod recipients;
 mod sasl;
 mod spf;
 
+pub use dkim::DkimKeys;
+pub use dmarc::{evaluate as dmarc_evaluate, Enforcement as DmarcEnforcement};
+pub use dns::{lookup_records, RecordLookup};
+pub use mail_context::is_plain_data;
+pub use mailbox::EnvelopeRecipient;
+pub use recipients::Backend as RecipientsBackend;
+pub use spf::{
+    check_helo_identity as spf_check_helo, check_mail_from_identity as spf_check_mail_from,
+};
+
 /// Error produced by Rust API function calls.
 pub type Result<T> = std::result::Result<T, Box<rhai::EvalAltResult>>;
 
@@ -90,7 +104,7 @@ pub mod docs {
 
 /// Modules that enable access and mutation on the email and it's context.
 #[must_use]
-pub fn smtp_modules() -> [(String, rhai::Shared<rhai::Module>); 4] {
+pub fn smtp_modules() -> [(String, rhai::Shared<rhai::Module>); 6] {
     [
         (
             "message".to_string(),
@@ -108,6 +122,14 @@ pub fn smtp_modules() -> [(String, rhai::Shared<rhai::Module>); 4] {
             "mailbox".to_string(),
             rhai::Shared::new(rhai::exported_module!(mailbox)),
         ),
+        (
+            "mime".to_string(),
+            rhai::Shared::new(rhai::exported_module!(mime)),
+        ),
+        (
+            "anomaly".to_string(),
+            rhai::Shared::new(rhai::exported_module!(anomaly)),
+        ),
     ]
 }
 
@@ -121,7 +143,7 @@ pub fn msa_modules() -> [(String, rhai::Shared<rhai::Module>); 1] {
 
 /// Network related modules.
 #[must_use]
-pub fn net_modules() -> [(String, rhai::Shared<rhai::Module>); 2] {
+pub fn net_modules() -> [(String, rhai::Shared<rhai::Module>); 4] {
     [
         (
             "net".to_string(),
@@ -131,11 +153,28 @@ pub fn net_modules() -> [(String, rhai::Shared<rhai::Module>); 2] {
             "dns".to_string(),
             rhai::Shared::new(rhai::exported_module!(dns)),
         ),
+        (
+            "callout".to_string(),
+            rhai::Shared::new(rhai::exported_module!(callout)),
+        ),
+        (
+            "recipients".to_string(),
+            rhai::Shared::new(rhai::exported_module!(recipients)),
+        ),
     ]
 }
 
 #[must_use]
 pub fn server_auth() -> [(String, rhai::Shared<rhai::Module>); 5] {
+    server_auth_with_dkim_keys(DkimKeys::default())
+}
+
+/// Authentication modules, `dkim::sign(ctx, domain, selector)` and `dkim::sign_by_domain(ctx)`
+/// signing the messages with the `dkim_keys`.
+#[must_use]
+pub fn server_auth_with_dkim_keys(
+    dkim_keys: DkimKeys,
+) -> [(String, rhai::Shared<rhai::Module>); 5] {
     [
         (
             "auth".to_string(),
@@ -151,7 +190,7 @@ pub fn server_auth() -> [(String, rhai::Shared<rhai::Module>); 5] {
         ),
         (
             "dkim".to_string(),
-            rhai::Shared::new(rhai::exported_module!(dkim)),
+            rhai::Shared::new(dkim::module_with_keys(dkim_keys)),
         ),
         (
             "dmarc".to_string(),
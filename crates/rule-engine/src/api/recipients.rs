@@ -0,0 +1,185 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use super::Result;
+use rhai::plugin::{
+    mem, Dynamic, FnAccess, FnNamespace, Module, NativeCallContext, PluginFunction, RhaiResult,
+    TypeId,
+};
+use vsmtp_common::Mailbox;
+
+pub use recipients::*;
+
+/// Source of the existing recipients, see `recipients::file` and `recipients::lookup`.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// Addresses (or `@domain` catch-alls) listed in a file, in lowercase.
+    File(std::sync::Arc<std::collections::HashSet<String>>),
+    /// A function called with the address, returning true if the recipient exists.
+    Lookup(rhai::FnPtr),
+}
+
+impl Backend {
+    /// Read the addresses of a file, one per line, ignoring the empty lines and the `#` comments.
+    fn parse_file(content: &str) -> std::collections::HashSet<String> {
+        content
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(line, _)| line).trim())
+            .filter(|line| !line.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    /// Does `address` exist in the backend ?
+    ///
+    /// # Errors
+    ///
+    /// * The lookup function failed or did not return a boolean.
+    pub fn exists(&self, ncc: &NativeCallContext<'_>, address: &Mailbox) -> Result<bool> {
+        match self {
+            Self::File(addresses) => {
+                let address = address.to_string().to_lowercase();
+                let catch_all = address
+                    .rsplit_once('@')
+                    .map(|(_, domain)| format!("@{domain}"));
+
+                Ok(addresses.contains(&address)
+                    || catch_all.map_or(false, |catch_all| addresses.contains(&catch_all)))
+            }
+            Self::Lookup(lookup) => lookup
+                .call_within_context::<bool>(ncc, (address.to_string(),))
+                .map_err(|error| {
+                    format!("recipient lookup failed for '{address}': {error}").into()
+                }),
+        }
+    }
+}
+
+/// Check the existence of the recipients in a backend configured once,
+/// a file or a lookup function wrapping a database query (sql, ldap, ...).
+#[rhai::plugin::export_module]
+mod recipients {
+
+    /// A backend created with `recipients::file` or `recipients::lookup`.
+    ///
+    /// # rhai-autodocs:index:1
+    pub type Recipients = Backend;
+
+    /// Create a backend from a file listing the existing recipients, one address per line.
+    /// A line `@example.com` accepts every recipient of the domain,
+    /// empty lines and `#` comments are ignored. Addresses are case insensitive.
+    ///
+    /// # Args
+    ///
+    /// * `path` - The path of the file.
+    ///
+    /// # Errors
+    ///
+    /// * The file cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// // in `global/recipients.rhai`
+    /// export const backend = recipients::file("/etc/vsmtp/receiver-smtp/recipients.txt");
+    /// ```
+    ///
+    /// # rhai-autodocs:index:2
+    #[rhai_fn(return_raw)]
+    pub fn file(path: &str) -> Result<Recipients> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| format!("failed to read recipients at '{path}': {error}"))?;
+
+        Ok(Backend::File(std::sync::Arc::new(Backend::parse_file(
+            &content,
+        ))))
+    }
+
+    /// Create a backend from a function called with the address of the recipient,
+    /// returning true if the recipient exists. Use it to wrap the queries
+    /// of the sql or ldap plugins.
+    ///
+    /// # Args
+    ///
+    /// * `lookup` - The function called with the address.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// // in `global/recipients.rhai`
+    /// import "global/database" as db;
+    ///
+    /// export const backend = recipients::lookup(|address| {
+    ///     db::users.query(`SELECT * FROM users WHERE email_address = '${address}';`).len() != 0
+    /// });
+    /// ```
+    ///
+    /// # rhai-autodocs:index:3
+    pub fn lookup(lookup: rhai::FnPtr) -> Recipients {
+        Backend::Lookup(lookup)
+    }
+
+    /// Does the recipient exist in the backend ?
+    ///
+    /// # Args
+    ///
+    /// * `rcpt` - The recipient to look up, as a string or a recipient object.
+    ///
+    /// # Errors
+    ///
+    /// * The recipient is not a valid address.
+    /// * The lookup function failed.
+    ///
+    /// # SMTP stages
+    ///
+    /// All of them.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// fn on_rcpt_to(ctx) {
+    ///     ctx.run([
+    ///         rule "unknown recipients" |ctx| {
+    ///             if global::recipients::backend.recipient_exists(ctx.recipients[-1]) {
+    ///                 status::next()
+    ///             } else {
+    ///                 status::deny(code::c550_1_1())
+    ///             }
+    ///         },
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:4
+    #[rhai_fn(global, name = "recipient_exists", return_raw, pure)]
+    pub fn recipient_exists(
+        ncc: NativeCallContext,
+        backend: &mut Recipients,
+        rcpt: &str,
+    ) -> Result<bool> {
+        let rcpt = Mailbox(
+            rcpt.parse()
+                .map_err(|error| format!("invalid recipient '{rcpt}': {error}"))?,
+        );
+
+        backend.exists(&ncc, &rcpt)
+    }
+
+    #[doc(hidden)]
+    #[rhai_fn(global, name = "recipient_exists", return_raw, pure)]
+    pub fn recipient_exists_recipient(
+        ncc: NativeCallContext,
+        backend: &mut Recipients,
+        rcpt: crate::api::mailbox::Recipient,
+    ) -> Result<bool> {
+        backend.exists(&ncc, &rcpt.recipient.forward_path)
+    }
+}
@@ -49,27 +49,276 @@ mod sasl_rhai {
         sasl.mechanism.to_string()
     }
 
+    /// Get the identity sent by the client, or `()` for the `ANONYMOUS` mechanism.
+    ///
     /// # rhai-autodocs:index:4
     #[rhai_fn(global, get = "authid")]
-    pub fn get_authid(sasl: &mut SaslAuthProps) -> String {
+    pub fn get_authid(sasl: &mut SaslAuthProps) -> rhai::Dynamic {
         match &sasl.credentials {
-            Credentials::Verify {
-                authid,
-                authpass: _,
-            } => authid.clone(),
-            Credentials::AnonymousToken { token: _ } => todo!(),
+            Credentials::Verify { authid, .. } | Credentials::ChallengeResponse { authid } => {
+                authid.clone().into()
+            }
+            Credentials::AnonymousToken { .. } => rhai::Dynamic::UNIT,
         }
     }
 
+    /// Get the password sent by the client with the `PLAIN` or `LOGIN` mechanism,
+    /// or `()` for the other mechanisms.
+    ///
     /// # rhai-autodocs:index:5
     #[rhai_fn(global, get = "password")]
-    pub fn get_authpass(sasl: &mut SaslAuthProps) -> String {
+    pub fn get_authpass(sasl: &mut SaslAuthProps) -> rhai::Dynamic {
         match &sasl.credentials {
-            Credentials::Verify {
-                authid: _,
-                authpass: password,
-            } => password.clone(),
-            Credentials::AnonymousToken { token: _ } => todo!(),
+            Credentials::Verify { authpass, .. } => authpass.clone().into(),
+            Credentials::AnonymousToken { .. } | Credentials::ChallengeResponse { .. } => {
+                rhai::Dynamic::UNIT
+            }
         }
     }
+
+    /// Get the trace token sent by the client with the `ANONYMOUS` mechanism,
+    /// or `()` for the other mechanisms.
+    ///
+    /// # rhai-autodocs:index:6
+    #[rhai_fn(global, get = "token")]
+    pub fn get_token(sasl: &mut SaslAuthProps) -> rhai::Dynamic {
+        match &sasl.credentials {
+            Credentials::Verify { .. } | Credentials::ChallengeResponse { .. } => {
+                rhai::Dynamic::UNIT
+            }
+            Credentials::AnonymousToken { token } => token.clone().into(),
+        }
+    }
+
+    /// Get the credentials decoded from the SASL exchange, to verify them against
+    /// your own store in the `auth` stage. The client is authenticated only if the
+    /// rules of the stage return `status::accept()`.
+    ///
+    /// # SMTP stages
+    ///
+    /// `auth` and onwards, on submission listeners.
+    ///
+    /// # Return
+    ///
+    /// * `map` - `#{ mechanism, authid, password }` for the `PLAIN` and `LOGIN` mechanisms,
+    ///   `#{ mechanism, token }` for the `ANONYMOUS` mechanism, and `#{ mechanism, authid }`
+    ///   for the `CRAM-MD5` and `SCRAM-SHA-256` mechanisms, where the secret of the user has
+    ///   already been verified using the `auth_secret` function of the script.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// import "services/redis" as srv;
+    ///
+    /// fn on_auth(ctx) {
+    ///     ctx.run([
+    ///         rule "verify credentials" |ctx| {
+    ///             let credentials = ctx.sasl.credentials;
+    ///             let password = srv::client.get(`password:${credentials.authid}`);
+    ///
+    ///             if password != () && password == credentials.password {
+    ///                 status::accept()
+    ///             } else {
+    ///                 status::deny()
+    ///             }
+    ///         },
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:7
+    #[rhai_fn(global, get = "credentials")]
+    pub fn get_credentials(sasl: &mut SaslAuthProps) -> rhai::Map {
+        let mut credentials = rhai::Map::new();
+        credentials.insert("mechanism".into(), sasl.mechanism.to_string().into());
+        match &sasl.credentials {
+            Credentials::Verify { authid, authpass } => {
+                credentials.insert("authid".into(), authid.clone().into());
+                credentials.insert("password".into(), authpass.clone().into());
+            }
+            Credentials::AnonymousToken { token } => {
+                credentials.insert("token".into(), token.clone().into());
+            }
+            Credentials::ChallengeResponse { authid } => {
+                credentials.insert("authid".into(), authid.clone().into());
+            }
+        }
+        credentials
+    }
+
+    /// Check if the client successfully authenticated using the `AUTH` command.
+    ///
+    /// # SMTP stages
+    ///
+    /// `auth` and onwards, on submission listeners.
+    ///
+    /// # Return
+    ///
+    /// * `bool` - `true` if the SASL handshake succeeded, `false` otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_mail_from(ctx) {
+    ///     if !ctx.is_authenticated() {
+    ///         return status::deny("530 5.7.0 Authentication required\r\n");
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:8
+    #[rhai_fn(global, name = "is_authenticated")]
+    pub fn is_authenticated_fn(ctx: &mut Ctx) -> bool {
+        is_authenticated(ctx)
+    }
+
+    /// Get the identity used by the client to authenticate.
+    ///
+    /// # SMTP stages
+    ///
+    /// `auth` and onwards, on submission listeners.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the authentication identity, or the trace token for the `ANONYMOUS` mechanism.
+    /// * `()` - if the client is not authenticated.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_mail_from(ctx) {
+    ///     if ctx.auth_identity() == "john.doe" {
+    ///         log("my_queue", "info", "john is sending a message");
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:9
+    #[rhai_fn(global)]
+    pub fn auth_identity(ctx: &mut Ctx) -> rhai::Dynamic {
+        ctx.read(|ctx| {
+            ctx.metadata
+                .get_connect()
+                .sasl
+                .as_ref()
+                .filter(|sasl| sasl.is_authenticated)
+                .map_or(rhai::Dynamic::UNIT, |sasl| match &sasl.credentials {
+                    Credentials::Verify { authid, .. }
+                    | Credentials::ChallengeResponse { authid } => authid.clone().into(),
+                    Credentials::AnonymousToken { token } => token.clone().into(),
+                })
+        })
+    }
+
+    /// Get the name of the SASL mechanism used by the client to authenticate.
+    ///
+    /// # SMTP stages
+    ///
+    /// `auth` and onwards, on submission listeners.
+    ///
+    /// # Return
+    ///
+    /// * `string` - the mechanism name, for example `"PLAIN"` or `"LOGIN"`.
+    /// * `()` - if the client is not authenticated.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_mail_from(ctx) {
+    ///     if ctx.auth_mechanism() == "LOGIN" {
+    ///         log("my_queue", "warn", "client is using an obsolete mechanism");
+    ///     }
+    ///
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:10
+    #[rhai_fn(global)]
+    pub fn auth_mechanism(ctx: &mut Ctx) -> rhai::Dynamic {
+        ctx.read(|ctx| {
+            ctx.metadata
+                .get_connect()
+                .sasl
+                .as_ref()
+                .filter(|sasl| sasl.is_authenticated)
+                .map_or(rhai::Dynamic::UNIT, |sasl| {
+                    sasl.mechanism.to_string().into()
+                })
+        })
+    }
+
+    /// Check that the sender of the message is owned by the authenticated user,
+    /// preventing a client from spoofing the address of another user on submission.
+    ///
+    /// The envelope sender (`MAIL FROM`) and, once the message is received, the addresses
+    /// of the `From` header must match the authentication identity or one of the given aliases.
+    /// The comparison is case insensitive. A null reverse path (`<>`) is accepted.
+    ///
+    /// # Args
+    ///
+    /// * `aliases` - An array of addresses that the authenticated user is also allowed to send as.
+    ///
+    /// # SMTP stages
+    ///
+    /// `mail_from` and onwards, on submission listeners. The `From` header is checked from the `pre_queue` stage.
+    ///
+    /// # Return
+    ///
+    /// * `bool` - `true` if the client is authenticated and owns the sender addresses, `false` otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_mail_from(ctx) {
+    ///     ctx.run([
+    ///         rule "sender must be owned by the user" |ctx| {
+    ///             if ctx.is_sender_authorized(["sales@example.com"]) {
+    ///                 status::next()
+    ///             } else {
+    ///                 status::deny(code::c553_7_1())
+    ///             }
+    ///         }
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:11
+    #[rhai_fn(global, name = "is_sender_authorized")]
+    pub fn is_sender_authorized(ctx: &mut Ctx, aliases: rhai::Array) -> bool {
+        let Some(identity) = auth_identity(ctx).try_cast::<String>() else {
+            return false;
+        };
+
+        let allowed = std::iter::once(identity)
+            .chain(aliases.into_iter().map(|alias| alias.to_string()))
+            .map(|address| address.to_lowercase())
+            .collect::<Vec<_>>();
+
+        ctx.read(|ctx| {
+            let envelope_allowed = ctx.metadata.get_mail_from().map_or(true, |mail_from| {
+                mail_from
+                    .reverse_path
+                    .as_ref()
+                    .map_or(true, |reverse_path| {
+                        allowed.contains(&reverse_path.0.full().to_lowercase())
+                    })
+            });
+
+            let header_allowed = ctx
+                .metadata
+                .get_mail(|mail| mail.header_addresses("From"))
+                .map_or(true, |mailboxes| {
+                    mailboxes
+                        .iter()
+                        .all(|mailbox| allowed.contains(&mailbox.address.to_lowercase()))
+                });
+
+            envelope_allowed && header_allowed
+        })
+    }
 }
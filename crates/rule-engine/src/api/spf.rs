@@ -42,6 +42,13 @@ fn default_mailbox() -> Option<Mailbox> {
     None
 }
 
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IdentityParams {
+    #[serde(deserialize_with = "super::deserialize_dns_resolver")]
+    dns_resolver: std::sync::Arc<DnsResolver>,
+}
+
 struct Lookup(std::sync::Arc<DnsResolver>);
 
 fn to_lookup_error(error: hickory_resolver::error::ResolveError) -> viaspf::lookup::LookupError {
@@ -172,6 +179,127 @@ fn to_spf_result(
     }
 }
 
+/// Evaluate the SPF policy of `sender` for a client at `ip`.
+fn evaluate(
+    lookup: &impl viaspf::lookup::Lookup,
+    ip: std::net::IpAddr,
+    sender: &viaspf::Sender,
+    helo: Option<&viaspf::DomainName>,
+) -> SpfResult {
+    to_spf_result(
+        block_on(viaspf::evaluate_sender(
+            lookup,
+            &viaspf::Config::builder().build(),
+            ip,
+            sender,
+            helo,
+        )),
+        sender.domain().to_string(),
+    )
+    .into()
+}
+
+/// Result of the identities which cannot be verified, as an ip sent on HELO/EHLO.
+fn no_identity() -> SpfResult {
+    spf::Result {
+        value: spf::Value::None,
+        domain: None,
+    }
+    .into()
+}
+
+/// Evaluate the SPF policy of the HELO identity of the client, and store the result
+/// in the `spf_helo_identity` of the context.
+///
+/// # Errors
+///
+/// * The HELO/EHLO command has not been received yet.
+/// * The HELO identity is not a valid domain.
+pub fn check_helo_identity(ctx: &Ctx, lookup: &impl viaspf::lookup::Lookup) -> Result<SpfResult> {
+    let (ip, helo) = ctx.read(|ctx| {
+        ctx.metadata
+            .get_helo()
+            .map(|helo| {
+                (
+                    ctx.metadata.get_connect().client_addr.ip(),
+                    helo.client_name.clone(),
+                )
+            })
+            .map_err(|e| e.to_string())
+    })?;
+
+    let spf_result = match helo {
+        ClientName::Domain(helo) => {
+            let sender =
+                viaspf::Sender::from_domain(&helo.to_string()).map_err(|e| e.to_string())?;
+            evaluate(lookup, ip, &sender, Some(sender.domain()))
+        }
+        ClientName::Ip4(..) | ClientName::Ip6(..) => no_identity(),
+    };
+
+    ctx.write(|ctx| {
+        ctx.metadata
+            .mut_helo()
+            .map_err(|e| e.to_string())?
+            .spf_helo_identity = Some(spf_result.clone());
+        Ok(spf_result)
+    })
+}
+
+/// Evaluate the SPF policy of the MAIL FROM identity of the client, and store the result
+/// in the `spf_mail_from_identity` of the context.
+///
+/// If the reverse path is null, the HELO identity is checked instead. (RFC 7208 2.4)
+///
+/// # Errors
+///
+/// * The MAIL FROM command has not been received yet.
+/// * The MAIL FROM or HELO identity is not valid.
+pub fn check_mail_from_identity(
+    ctx: &Ctx,
+    lookup: &impl viaspf::lookup::Lookup,
+) -> Result<SpfResult> {
+    let (ip, helo, reverse_path) = ctx.read(|ctx| {
+        let helo = ctx.metadata.get_helo().map_err(|e| e.to_string())?;
+        let mail_from = ctx.metadata.get_mail_from().map_err(|e| e.to_string())?;
+        Ok::<_, String>((
+            ctx.metadata.get_connect().client_addr.ip(),
+            helo.client_name.clone(),
+            mail_from.reverse_path.clone(),
+        ))
+    })?;
+
+    let helo = match helo {
+        ClientName::Domain(helo) => Some(helo.to_string()),
+        ClientName::Ip4(..) | ClientName::Ip6(..) => None,
+    };
+
+    let spf_result = match (reverse_path, helo) {
+        (Some(reverse_path), helo) => {
+            let sender = viaspf::Sender::from_address(&reverse_path.0.to_string())
+                .map_err(|e| e.to_string())?;
+            let helo = helo
+                .map(|helo| helo.parse::<viaspf::DomainName>())
+                .transpose()
+                .map_err(|e| e.to_string())?;
+            evaluate(lookup, ip, &sender, helo.as_ref())
+        }
+        (None, Some(helo)) => {
+            let sender = viaspf::Sender::from_domain(&helo).map_err(|e| e.to_string())?;
+            evaluate(lookup, ip, &sender, Some(sender.domain()))
+        }
+        (None, None) => no_identity(),
+    };
+
+    ctx.write(|ctx| {
+        ctx.metadata
+            .mut_mail_from()
+            .map_err(|e| e.to_string())?
+            .spf_mail_from_identity = Some(spf_result.clone());
+        Ok(spf_result)
+    })
+}
+
 /// Implementation of the Sender Policy Framework (SPF), described by RFC 7208. (<https://datatracker.ietf.org/doc/html/rfc7208>)
 #[rhai::plugin::export_module]
 mod rhai_spf {
@@ -284,6 +412,93 @@ mod rhai_spf {
         }
     }
 
+    /// Evaluate the SPF policy of the HELO identity of the client, and store the result
+    /// in the context, as `spf::store(ctx, "helo", result)` would.
+    ///
+    /// # Args
+    ///
+    /// * `params` - A map containing the following parameters:
+    ///   * `dns_resolver` - The DNS resolver to use for the verification, loaded with the [dns] module.
+    ///
+    /// [dns]: http://vsmtp.rs/docs/global/dns
+    ///
+    /// # Return
+    ///
+    /// * The SPF result, `none` if the client sent an ip on HELO/EHLO.
+    ///
+    /// # Errors
+    ///
+    /// * The parameters are invalid.
+    /// * The HELO identity is not a valid domain.
+    ///
+    /// # SMTP stages
+    ///
+    /// `helo` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_helo(ctx) {
+    ///     ctx.run([
+    ///         rule "spf helo" |ctx| {
+    ///             let result = spf::check_helo(ctx, #{ dns_resolver: global::dns_resolver });
+    ///             if result.value == "fail" { status::deny("550 5.7.23 SPF validation failed") } else { status::next() }
+    ///         },
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:6
+    #[rhai_fn(pure, return_raw)]
+    pub fn check_helo(ctx: &mut Ctx, params: rhai::Dynamic) -> Result<SpfResult> {
+        let IdentityParams { dns_resolver } = rhai::serde::from_dynamic(&params)?;
+        check_helo_identity(ctx, &Lookup(dns_resolver))
+    }
+
+    /// Evaluate the SPF policy of the MAIL FROM identity of the client, and store the result
+    /// in the context, as `spf::store(ctx, "mail_from", result)` would.
+    /// If the reverse path is null, the HELO identity is checked instead.
+    ///
+    /// # Args
+    ///
+    /// * `params` - A map containing the following parameters:
+    ///   * `dns_resolver` - The DNS resolver to use for the verification, loaded with the [dns] module.
+    ///
+    /// [dns]: http://vsmtp.rs/docs/global/dns
+    ///
+    /// # Return
+    ///
+    /// * The SPF result.
+    ///
+    /// # Errors
+    ///
+    /// * The parameters are invalid.
+    /// * The MAIL FROM or HELO identity is not valid.
+    ///
+    /// # SMTP stages
+    ///
+    /// `mail` and onwards.
+    ///
+    /// # Example
+    ///
+    /// ```js
+    /// fn on_mail_from(ctx) {
+    ///     ctx.run([
+    ///         rule "spf mail from" |ctx| {
+    ///             let result = spf::check_mail_from(ctx, #{ dns_resolver: global::dns_resolver });
+    ///             if result.value == "fail" { status::deny("550 5.7.23 SPF validation failed") } else { status::next() }
+    ///         },
+    ///     ])
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:7
+    #[rhai_fn(pure, return_raw)]
+    pub fn check_mail_from(ctx: &mut Ctx, params: rhai::Dynamic) -> Result<SpfResult> {
+        let IdentityParams { dns_resolver } = rhai::serde::from_dynamic(&params)?;
+        check_mail_from_identity(ctx, &Lookup(dns_resolver))
+    }
+
     /// Store the result of a previous `spf::check_host` function execution.
     /// # rhai-autodocs:index:2
     #[rhai_fn(global, pure, return_raw)]
@@ -13,7 +13,7 @@ use crate::{
     api::State,
     config::RuleEngineConfig,
     module_resolver::{DomainFilterResolver, Domains},
-    Directive, DirectiveError, Directives, Flow, FlowType, Stage, Status,
+    Directive, DirectiveError, DirectiveToggles, Directives, Flow, FlowType, Stage, Status,
 };
 use rhai::{
     module_resolvers::{FileModuleResolver, ModuleResolversCollection},
@@ -45,11 +45,24 @@ pub struct RuleEngineConfigBuilder<
     global_modules: Vec<rhai::Shared<rhai::Module>>,
     static_modules: Vec<(String, rhai::Shared<rhai::Module>)>,
     ast: rhai::AST,
+    missing_script: MissingScript,
+    directive_toggles: DirectiveToggles,
     status: std::marker::PhantomData<STATUS>,
     stage: std::marker::PhantomData<STAGE>,
     state: std::marker::PhantomData<CONTEXT>,
 }
 
+/// Behavior of [`RuleEngineConfigBuilder::with_script_at`] when the script file does not exist.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingScript {
+    /// Log a warning and use the default sources instead.
+    #[default]
+    Warn,
+    /// Fail to build the configuration.
+    Fail,
+}
+
 /// Errors emitted by the rule engine configuration builder.
 #[derive(Debug, thiserror::Error)]
 pub enum RuleEngineConfigBuilderError {
@@ -68,6 +81,9 @@ pub enum RuleEngineConfigBuilderError {
     /// Failed to find the script at the given path.
     #[error("failed to load a rhai script at {0:?}")]
     LoadScript(String),
+    /// The script does not exist, and the builder was configured to fail with [`MissingScript::Fail`].
+    #[error("rhai script not found at {0:?}")]
+    ScriptNotFound(std::path::PathBuf),
     /// Failed to customize the engine.
     #[error("failed to customize the engine: {0}")]
     Engine(Box<rhai::EvalAltResult>),
@@ -99,6 +115,8 @@ impl<CONTEXT, STATUS: Status, STAGE: Stage> Default
             global_modules: Vec::default(),
             static_modules: Vec::default(),
             ast: rhai::AST::default(),
+            missing_script: MissingScript::default(),
+            directive_toggles: DirectiveToggles::default(),
             status: std::marker::PhantomData,
             stage: std::marker::PhantomData,
             state: std::marker::PhantomData,
@@ -351,16 +369,30 @@ impl<CONTEXT: 'static, STATUS: Status, STAGE: Stage + 'static>
             .map_or("unknown".into(), ToString::to_string);
 
         for directive in directives {
+            if !directive.is_enabled() {
+                tracing::debug!(
+                    rule = directive.name(),
+                    stage,
+                    "Directive disabled, skipping"
+                );
+                continue;
+            }
+
             tracing::trace!(rule = directive.name(), stage, "Executing directive");
             let status = match directive.execute::<STATUS, CONTEXT>(&ncc, ctx.clone()) {
                 Ok(status) => status,
                 Err(mut error) => {
+                    crate::dsl::directives::DECISIVE_DIRECTIVE.with(|decisive| {
+                        *decisive.borrow_mut() = Some(directive.name().to_string())
+                    });
                     error.stage = Some(stage);
                     return STATUS::error(error);
                 }
             };
 
             if !status.is_next() {
+                crate::dsl::directives::DECISIVE_DIRECTIVE
+                    .with(|decisive| *decisive.borrow_mut() = Some(directive.name().to_string()));
                 return status;
             }
         }
@@ -478,12 +510,36 @@ impl<CONTEXT: 'static, STATUS: Status, STAGE: Stage + 'static>
         self
     }
 
+    /// Set the behavior of [`Self::with_script_at`] when the script cannot be found,
+    /// the default sources being used with a warning by default.
+    #[must_use]
+    pub fn with_missing_script(mut self, missing_script: MissingScript) -> Self {
+        self.missing_script = missing_script;
+
+        self
+    }
+
+    /// Enable or disable directives by name, without editing the scripts.
+    /// A disabled directive is skipped when its stage is run, the directives
+    /// which are not listed are enabled.
+    #[must_use]
+    pub fn with_directive_toggles(
+        mut self,
+        toggles: impl IntoIterator<Item = (String, bool)>,
+    ) -> Self {
+        self.directive_toggles.extend(toggles);
+
+        self
+    }
+
     /// Compile and add directives from a script file to the engine configuration.
-    /// Additional default sources can be passed if the script cannot be found.
+    /// Additional default sources can be passed if the script cannot be found,
+    /// unless [`MissingScript::Fail`] has been set with [`Self::with_missing_script`].
     ///
     /// Prefer calling [`Self::with_module_resolver`] before this method.
     ///
     /// # Errors
+    /// * The script cannot be found and the builder is configured with [`MissingScript::Fail`].
     /// * Failed to build directives from script.
     pub fn with_script_at(
         mut self,
@@ -501,8 +557,12 @@ impl<CONTEXT: 'static, STATUS: Status, STAGE: Stage + 'static>
                     Ok(sources)
                 },
             )?
+        } else if self.missing_script == MissingScript::Fail {
+            tracing::error!(path = ?path_ptr, "Rhai script not found");
+
+            return Err(RuleEngineConfigBuilderError::ScriptNotFound(path));
         } else {
-            tracing::warn!("Rhai script not found, vsmtp will use default scripts instead");
+            tracing::warn!(path = ?path_ptr, "Rhai script not found, vsmtp will use default scripts instead");
 
             defaults.into()
         };
@@ -561,6 +621,7 @@ impl<CONTEXT: 'static, STATUS: Status, STAGE: Stage + 'static>
             global_modules: self.global_modules,
             static_modules: self.static_modules,
             ast: self.ast,
+            directive_toggles: rhai::Shared::new(self.directive_toggles),
             status: std::marker::PhantomData,
             stage: std::marker::PhantomData,
             state: std::marker::PhantomData,
@@ -587,19 +648,35 @@ fn new_rhai_engine() -> rhai::Engine {
         }
     });
 
+    // NOTE: the directives are only parsed by this engine, the toggles
+    //       are applied by the engines spawned from the configuration.
     engine
         .disable_symbol("eval")
         .register_custom_syntax_with_state_raw(
             "rule",
             Directive::parse_directive,
             true,
-            crate::dsl::directives::rule::parse,
+            |context, input, state| {
+                crate::dsl::directives::rule::parse(
+                    context,
+                    input,
+                    state,
+                    &DirectiveToggles::default(),
+                )
+            },
         )
         .register_custom_syntax_with_state_raw(
             "action",
             Directive::parse_directive,
             true,
-            crate::dsl::directives::action::parse,
+            |context, input, state| {
+                crate::dsl::directives::action::parse(
+                    context,
+                    input,
+                    state,
+                    &DirectiveToggles::default(),
+                )
+            },
         );
 
     // New operators can be registered into the engine, fast operators
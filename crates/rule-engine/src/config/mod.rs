@@ -25,6 +25,7 @@ pub struct RuleEngineConfig<STATE: 'static, STATUS: crate::Status, STAGE: crate:
     pub(super) global_modules: Vec<rhai::Shared<rhai::Module>>,
     pub(super) static_modules: Vec<(String, rhai::Shared<rhai::Module>)>,
     pub(super) ast: rhai::AST,
+    pub(super) directive_toggles: rhai::Shared<crate::DirectiveToggles>,
     status: std::marker::PhantomData<STATUS>,
     stage: std::marker::PhantomData<STAGE>,
     state: std::marker::PhantomData<STATE>,
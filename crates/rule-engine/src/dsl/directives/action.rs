@@ -9,12 +9,14 @@
  *
  */
 
+use super::DirectiveToggles;
 use crate::Directive;
 
 pub fn parse(
     context: &mut rhai::EvalContext<'_, '_, '_, '_, '_, '_>,
     input: &[rhai::Expression<'_>],
     _state: &rhai::Dynamic,
+    toggles: &DirectiveToggles,
 ) -> crate::api::Result<rhai::Dynamic> {
     let name = input[0]
         .get_literal_value::<rhai::ImmutableString>()
@@ -35,5 +37,6 @@ pub fn parse(
                 }
             }
         },
+        enabled: Directive::is_toggled_on(toggles, &name),
     }))
 }
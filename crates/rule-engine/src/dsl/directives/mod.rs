@@ -19,6 +19,19 @@ pub mod rule;
 
 pub type Result<T> = std::result::Result<T, DirectiveError>;
 
+thread_local! {
+    /// Name of the last directive which returned a status other than "next" on this thread.
+    ///
+    /// Directives are run synchronously by [`crate::RuleEngine::run`], so the thread
+    /// is used to bring the name back to the caller without changing the status types.
+    pub(crate) static DECISIVE_DIRECTIVE: std::cell::RefCell<Option<String>> =
+        std::cell::RefCell::new(None);
+}
+
+/// Directives enabled (`true`) or disabled (`false`) by name, from the configuration
+/// of the service. The directives which are not listed are enabled.
+pub type DirectiveToggles = std::collections::HashMap<String, bool>;
+
 /// The type of email flow for a given transaction.
 #[derive(Clone, PartialEq, Eq)]
 pub enum FlowType {
@@ -151,6 +164,8 @@ pub enum Directive {
         name: String,
         /// Function pointer used to execute the function's code.
         pointer: rhai::FnPtr,
+        /// Is the rule executed, see [`DirectiveToggles`].
+        enabled: bool,
     },
 
     /// execute code that does not need a return value.
@@ -160,6 +175,8 @@ pub enum Directive {
         name: String,
         /// Function pointer used to execute the function's code.
         pointer: rhai::FnPtr,
+        /// Is the action executed, see [`DirectiveToggles`].
+        enabled: bool,
     },
 }
 
@@ -214,6 +231,18 @@ impl Directive {
         }
     }
 
+    /// Is the directive executed when its stage is run ?
+    pub(crate) const fn is_enabled(&self) -> bool {
+        match self {
+            Self::Rule { enabled, .. } | Self::Action { enabled, .. } => *enabled,
+        }
+    }
+
+    /// Is the directive named `name` enabled by the `toggles` ?
+    pub(crate) fn is_toggled_on(toggles: &DirectiveToggles, name: &str) -> bool {
+        toggles.get(name).copied().unwrap_or(true)
+    }
+
     /// Parse a directive from a list of rhai symbols.
     /// This function is to be called by the rhai custom syntax parser.
     pub(crate) fn parse_directive(
@@ -252,6 +281,7 @@ impl std::fmt::Debug for Directive {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct(self.as_ref())
             .field("name", &self.name())
+            .field("enabled", &self.is_enabled())
             .finish_non_exhaustive()
     }
 }
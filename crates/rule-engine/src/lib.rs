@@ -25,14 +25,16 @@ mod stage;
 /// Values return by the rule engine when executing a script.
 mod status;
 
-pub use crate::config::builder::RuleEngineConfigBuilder;
+pub use crate::config::builder::{
+    MissingScript, RuleEngineConfigBuilder, RuleEngineConfigBuilderError,
+};
 pub use crate::config::RuleEngineConfig;
 pub use crate::stage::Stage;
 pub use crate::status::Status;
 use api::State;
 pub use dsl::directives::{
-    directives_try_from, error::DirectiveError, error::ParseError, Directive, Directives, Flow,
-    FlowType,
+    directives_try_from, error::DirectiveError, error::ParseError, Directive, DirectiveToggles,
+    Directives, Flow, FlowType,
 };
 pub use rhai;
 pub use rhai_dylib;
@@ -123,18 +125,26 @@ impl<CONTEXT: 'static + std::fmt::Debug, STATUS: Status, STAGE: Stage>
 
         // Setting up directive parsing.
         // FIXME: this should not be necessary because the AST as already been parsed, right ?
+        let (rule_toggles, action_toggles) = (
+            config.directive_toggles.clone(),
+            config.directive_toggles.clone(),
+        );
         rhai_engine
             .register_custom_syntax_with_state_raw(
                 "rule",
                 Directive::parse_directive,
                 true,
-                crate::dsl::directives::rule::parse,
+                move |context, input, state| {
+                    crate::dsl::directives::rule::parse(context, input, state, &rule_toggles)
+                },
             )
             .register_custom_syntax_with_state_raw(
                 "action",
                 Directive::parse_directive,
                 true,
-                crate::dsl::directives::action::parse,
+                move |context, input, state| {
+                    crate::dsl::directives::action::parse(context, input, state, &action_toggles)
+                },
             );
 
         rhai_engine.set_fast_operators(false);
@@ -148,10 +158,53 @@ impl<CONTEXT: 'static + std::fmt::Debug, STATUS: Status, STAGE: Stage>
         }
     }
 
+    /// Run a sequence of stages against a state, without a live SMTP session.
+    ///
+    /// The stages are run until one of them denies the transaction, as in a live session,
+    /// see [`Status::is_deny`]. The state, as modified by the scripts, is returned alongside
+    /// the statuses of the stages run.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The rule engine configuration containing the scripts to test.
+    /// * `state` -  The state to run the stages against, usually built from a message fixture.
+    /// * `stages` - The stages to run, in order.
+    #[must_use]
+    pub fn simulate(
+        config: rhai::Shared<RuleEngineConfig<CONTEXT, STATUS, STAGE>>,
+        state: CONTEXT,
+        stages: impl IntoIterator<Item = STAGE>,
+    ) -> (Vec<(STAGE, STATUS)>, CONTEXT) {
+        let rule_engine = Self::from_config_with_state(config, state);
+
+        let mut statuses = vec![];
+        for stage in stages {
+            let status = rule_engine.run(&stage);
+            let deny = status.is_deny();
+            statuses.push((stage, status));
+            if deny {
+                break;
+            }
+        }
+
+        (statuses, rule_engine.take_state())
+    }
+
     /// Run a stage hook function and returns a status.
     #[must_use]
-    #[tracing::instrument(level = "info", skip(self), fields(hook = %stage.hook()))]
     pub fn run(&self, stage: &STAGE) -> STATUS {
+        self.run_with_directive(stage).0
+    }
+
+    /// Run a stage hook function and returns a status, with the name of the directive
+    /// which produced it.
+    ///
+    /// The name is `None` if the status was not returned by a directive, for example
+    /// when all rules returned "next" or the stage hook is not defined.
+    #[must_use]
+    #[tracing::instrument(level = "info", skip(self), fields(hook = %stage.hook()))]
+    pub fn run_with_directive(&self, stage: &STAGE) -> (STATUS, Option<String>) {
+        crate::dsl::directives::DECISIVE_DIRECTIVE.with(|decisive| decisive.borrow_mut().take());
         let hook = stage.hook();
 
         match self
@@ -174,19 +227,57 @@ impl<CONTEXT: 'static + std::fmt::Debug, STATUS: Status, STAGE: Stage>
             }) {
             Ok(status) => {
                 tracing::info!(stage = hook, ?status, "Rule engine was successful");
-                status
+                let directive = crate::dsl::directives::DECISIVE_DIRECTIVE
+                    .with(|decisive| decisive.borrow_mut().take())
+                    .filter(|_| !status.is_next());
+                (status, directive)
             }
             Err(error) => {
                 tracing::error!(stage = hook, ?error, "Rule engine found an error");
-                STATUS::error(DirectiveError {
-                    kind: DirectiveErrorKind::Runtime(error),
-                    stage: Some(stage.to_string()),
-                    directive: None,
-                })
+                (
+                    STATUS::error(DirectiveError {
+                        kind: DirectiveErrorKind::Runtime(error),
+                        stage: Some(stage.to_string()),
+                        directive: None,
+                    }),
+                    None,
+                )
             }
         }
     }
 
+    /// Call a function defined in the script which is not a stage hook, the state being
+    /// passed as the first argument, followed by `args`.
+    ///
+    /// Returns `None` if the script does not define the function.
+    ///
+    /// # Errors
+    ///
+    /// * The function failed to run, or did not return a `T`.
+    #[tracing::instrument(level = "debug", skip(self, args))]
+    pub fn call<T: rhai::Variant + Clone>(
+        &self,
+        name: &str,
+        args: impl IntoIterator<Item = rhai::Dynamic>,
+    ) -> Result<Option<T>, Box<rhai::EvalAltResult>> {
+        let args = std::iter::once(rhai::Dynamic::from(self.state.clone()))
+            .chain(args)
+            .collect::<Vec<_>>();
+
+        match self.rhai_engine.call_fn::<T>(
+            &mut rhai::Scope::default(),
+            &self.config.ast,
+            name,
+            args,
+        ) {
+            Ok(value) => Ok(Some(value)),
+            Err(error) => match *error {
+                rhai::EvalAltResult::ErrorFunctionNotFound(ref func, _) if func == name => Ok(None),
+                _ => Err(error),
+            },
+        }
+    }
+
     /// Read the value of the state.
     pub fn read_state<O>(&self, f: impl FnOnce(&CONTEXT) -> O) -> O {
         self.state.read(f)
@@ -23,4 +23,9 @@ pub trait Status: std::fmt::Debug + Clone + Send + Sync + 'static {
     fn next() -> Self;
 
     fn is_next(&self) -> bool;
+
+    /// Does the status reject the transaction, the following stages not being run.
+    fn is_deny(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,147 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{
+    ctx::Ctx, delivery_route::DeliveryRoute, stateful_ctx_received::StatefulCtxReceived, Mailbox,
+    Recipient,
+};
+use common::{MyStages, MyStatus};
+use vsmtp_protocol::{Address, NotifyOn, OriginalRecipient};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+const MESSAGE: &str = concat!(
+    "From: john.doe@example.com\r\n",
+    "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+    "Subject: test\r\n",
+    "\r\n",
+    "Hello world!\r\n",
+);
+
+fn rule_engine(script: &str) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    let mut ctx = common::received(MESSAGE);
+    ctx.metadata.mut_rcpt_to().unwrap().recipient = [
+        (
+            DeliveryRoute::Maildir,
+            vec![recipient("info@example.com", None)],
+        ),
+        (
+            DeliveryRoute::Basic,
+            vec![
+                recipient("jenny@example.org", Some(orcpt("jenny@example.net"))),
+                recipient("someone@example.net", None),
+            ],
+        ),
+    ]
+    .into_iter()
+    .collect();
+
+    common::rule_engine(script, smtp_modules(), ctx)
+}
+
+fn orcpt(addr: &str) -> OriginalRecipient {
+    OriginalRecipient {
+        addr_type: "rfc822".to_string(),
+        mailbox: Address::new_unchecked(addr.to_string()),
+        xtext: None,
+    }
+}
+
+fn recipient(addr: &str, original_forward_path: Option<OriginalRecipient>) -> Recipient {
+    Recipient {
+        forward_path: Mailbox(Address::new_unchecked(addr.to_string())),
+        original_forward_path,
+        notify_on: NotifyOn::Some {
+            success: false,
+            failure: true,
+            delay: true,
+        },
+    }
+}
+
+fn recipients(
+    rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>,
+    route: &DeliveryRoute,
+) -> Vec<Recipient> {
+    rule_engine.read_state(|ctx| ctx.metadata.get_rcpt_to().unwrap().recipient[route].clone())
+}
+
+#[test]
+fn expand_one_to_many_alias() {
+    let rule_engine = rule_engine("aliases.rhai");
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    assert_eq!(
+        recipients(&rule_engine, &DeliveryRoute::Maildir),
+        [
+            recipient("alice@example.com", Some(orcpt("info@example.com"))),
+            recipient("bob@example.com", Some(orcpt("info@example.com"))),
+        ]
+    );
+}
+
+#[test]
+fn rewrite_virtual_domain() {
+    let rule_engine = rule_engine("aliases.rhai");
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    // the original recipient sent by the client is preserved.
+    assert_eq!(
+        recipients(&rule_engine, &DeliveryRoute::Basic),
+        [
+            recipient("jenny@example.com", Some(orcpt("jenny@example.net"))),
+            recipient("someone@example.net", None),
+        ]
+    );
+}
+
+#[test]
+fn invalid_alias() {
+    let rule_engine = rule_engine("aliases.rhai");
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Fail(None));
+
+    assert_eq!(
+        recipients(&rule_engine, &DeliveryRoute::Maildir),
+        [recipient("info@example.com", None)]
+    );
+}
+
+/// The same aliases as `aliases.rhai`, read from a file or from the rows of a table.
+fn expand_from(script: &str) {
+    let rule_engine = rule_engine(script);
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    assert_eq!(
+        recipients(&rule_engine, &DeliveryRoute::Maildir),
+        [
+            recipient("alice@example.com", Some(orcpt("info@example.com"))),
+            recipient("bob@example.com", Some(orcpt("info@example.com"))),
+        ]
+    );
+    assert_eq!(
+        recipients(&rule_engine, &DeliveryRoute::Basic),
+        [
+            recipient("jenny@example.com", Some(orcpt("jenny@example.net"))),
+            recipient("someone@example.net", None),
+        ]
+    );
+}
+
+#[test]
+fn expand_from_file() {
+    expand_from("aliases_file.rhai");
+}
+
+#[test]
+fn expand_from_table() {
+    expand_from("aliases_table.rhai");
+}
@@ -0,0 +1,127 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use common::{MyStages, MyStatus};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+fn rule_engine(mail: &str) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    common::rule_engine("anomalies.rhai", smtp_modules(), common::received(mail))
+}
+
+/// Run the script on `mail`, and get the anomaly `name` it has detected.
+fn detected(mail: &str, name: &str) -> bool {
+    let rule_engine = rule_engine(mail);
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    rule_engine.read_state(|ctx| ctx.variables[name].as_bool().unwrap())
+}
+
+const LEGITIMATE: &str = concat!(
+    "Received: from mta.example.com by mx.example.net\r\n",
+    "From: \"John Doe\" <john.doe@example.com>\r\n",
+    "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+    "Message-ID: <1234@mta.example.com>\r\n",
+    "Subject: test\r\n",
+    "\r\n",
+    "Hello world!\r\n",
+);
+
+#[test]
+fn legitimate() {
+    for name in [
+        "missing_message_id",
+        "display_name_mismatch",
+        "too_many_received",
+        "attachment_type_mismatch",
+    ] {
+        assert!(!detected(LEGITIMATE, name), "{name} detected");
+    }
+}
+
+#[test]
+fn missing_message_id() {
+    assert!(detected(
+        "From: john.doe@example.com\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\nSubject: test\r\n\r\nHello world!\r\n",
+        "missing_message_id"
+    ));
+    assert!(detected(
+        "From: john.doe@example.com\r\nMessage-ID: 1234.example.com\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\nSubject: test\r\n\r\nHello world!\r\n",
+        "missing_message_id"
+    ));
+}
+
+#[test]
+fn display_name_mismatch() {
+    assert!(detected(
+        "From: \"service@bank.example\" <phishing@example.com>\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\nSubject: test\r\n\r\nHello world!\r\n",
+        "display_name_mismatch"
+    ));
+    // an address of the same domain in the display name is not an anomaly.
+    assert!(!detected(
+        "From: \"John (john.doe@Example.com)\" <john.doe@example.com>\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\nSubject: test\r\n\r\nHello world!\r\n",
+        "display_name_mismatch"
+    ));
+}
+
+#[test]
+fn too_many_received() {
+    let mail = format!(
+        "{}From: john.doe@example.com\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\nSubject: test\r\n\r\nHello world!\r\n",
+        "Received: from relay.example.com by mx.example.net\r\n".repeat(6)
+    );
+    assert!(detected(&mail, "too_many_received"));
+}
+
+fn with_attachment(content_type: &str, filename: &str) -> String {
+    [
+        "From: john.doe@example.com\r\n",
+        "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+        "Message-ID: <1234@mta.example.com>\r\n",
+        "MIME-Version: 1.0\r\n",
+        "Content-Type: multipart/mixed; boundary=\"mixed\"\r\n",
+        "\r\n",
+        "--mixed\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "Please find the invoice attached.\r\n",
+        "--mixed\r\n",
+        &format!("Content-Type: {content_type}\r\n"),
+        &format!("Content-Disposition: attachment; filename=\"{filename}\"\r\n"),
+        "Content-Transfer-Encoding: base64\r\n",
+        "\r\n",
+        "SGVsbG8gd29ybGQhCg==\r\n",
+        "--mixed--\r\n",
+    ]
+    .concat()
+}
+
+#[test]
+fn attachment_type_mismatch() {
+    assert!(detected(
+        &with_attachment("application/pdf", "invoice.pdf.exe"),
+        "attachment_type_mismatch"
+    ));
+    assert!(detected(
+        &with_attachment("image/jpeg", "photo.pdf"),
+        "attachment_type_mismatch"
+    ));
+    assert!(!detected(
+        &with_attachment("application/pdf", "invoice.pdf"),
+        "attachment_type_mismatch"
+    ));
+    assert!(!detected(
+        &with_attachment("application/octet-stream", "invoice.pdf.exe"),
+        "attachment_type_mismatch"
+    ));
+}
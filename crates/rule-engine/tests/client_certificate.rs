@@ -0,0 +1,65 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::tls::TlsProps;
+use common::{MyStages, MyStatus};
+use vsmtp_protocol::{rustls, ConnectionKind};
+use vsmtp_rule_engine::api::msa_modules;
+
+fn run_with_certificate(peer_certificates: Option<Vec<rustls::Certificate>>) -> MyStatus {
+    let mut ctx = common::mail_from();
+    let connect = ctx.metadata.mut_connect();
+    connect.kind = ConnectionKind::Submission;
+    connect.tls = Some(TlsProps {
+        protocol_version: "TLSv1.3".parse().unwrap(),
+        cipher_suite: "TLS_AES_256_GCM_SHA384".parse().unwrap(),
+        peer_certificates,
+        alpn_protocol: None,
+    });
+
+    common::rule_engine("client_certificate.rhai", msa_modules(), ctx).run(&MyStages::MailFrom)
+}
+
+fn client_certificate() -> rustls::Certificate {
+    let (_, der) = pem_rfc7468::decode_vec(include_bytes!("certs/client.example.com.crt"))
+        .expect("valid PEM certificate");
+    rustls::Certificate(der)
+}
+
+#[test]
+fn no_client_certificate() {
+    assert_eq!(
+        run_with_certificate(None),
+        MyStatus::Fail(Some("no client certificate".to_string()))
+    );
+}
+
+#[test]
+fn authorize_on_san() {
+    assert_eq!(
+        run_with_certificate(Some(vec![client_certificate()])),
+        MyStatus::Ok(Some(
+            "john.doe issued by O=Example, CN=Example Client CA".to_string()
+        ))
+    );
+}
+
+#[test]
+fn invalid_client_certificate() {
+    assert_eq!(
+        run_with_certificate(Some(vec![rustls::Certificate(
+            b"not a certificate".to_vec()
+        )])),
+        MyStatus::Fail(None)
+    );
+}
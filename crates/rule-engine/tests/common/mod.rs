@@ -9,6 +9,24 @@
  *
  */
 
+// each test file uses a part of the helpers.
+#![allow(dead_code)]
+
+use vsmtp_common::{
+    ctx::Ctx,
+    ctx_received::CtxReceived,
+    delivery_route::DeliveryRoute,
+    stateful_ctx_received::{CompleteProps, HeloProps, StatefulCtxReceived},
+    Mailbox, Recipient,
+};
+use vsmtp_config::{broker, logs, semver, Config};
+use vsmtp_mail_parser::Mail;
+use vsmtp_protocol::{Address, ClientName, ConnectionKind, NotifyOn};
+use vsmtp_rule_engine::{
+    rhai::plugin::*, DirectiveError, RuleEngine, RuleEngineConfig, RuleEngineConfigBuilder, Stage,
+    Status,
+};
+
 /// Build a complete path from the current cargo manifest files using a relative path.
 #[macro_export]
 macro_rules! from_manifest_path {
@@ -16,3 +34,265 @@ macro_rules! from_manifest_path {
         std::path::PathBuf::from_iter([env!("CARGO_MANIFEST_DIR"), $path])
     };
 }
+
+/// Stages of the rule engine run by the tests.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MyStages {
+    Connect,
+    Helo,
+    MailFrom,
+    RcptTo,
+    PreQueue,
+    PostQueue,
+    Rewrite,
+    Strip,
+}
+
+impl Stage for MyStages {
+    fn hook(&self) -> &'static str {
+        match self {
+            Self::Connect => "on_connect",
+            Self::Helo => "on_helo",
+            Self::MailFrom => "on_mail_from",
+            Self::RcptTo => "on_rcpt_to",
+            Self::PreQueue => "on_pre_queue",
+            Self::PostQueue => "on_post_queue",
+            Self::Rewrite => "on_rewrite",
+            Self::Strip => "on_strip",
+        }
+    }
+
+    fn stages() -> &'static [&'static str] {
+        &[
+            "connect",
+            "helo",
+            "mail_from",
+            "rcpt_to",
+            "pre_queue",
+            "post_queue",
+            "rewrite",
+            "strip",
+        ]
+    }
+}
+
+impl std::str::FromStr for MyStages {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "connect" => Ok(Self::Connect),
+            "helo" => Ok(Self::Helo),
+            "mail_from" => Ok(Self::MailFrom),
+            "rcpt_to" => Ok(Self::RcptTo),
+            "pre_queue" => Ok(Self::PreQueue),
+            "post_queue" => Ok(Self::PostQueue),
+            "rewrite" => Ok(Self::Rewrite),
+            "strip" => Ok(Self::Strip),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for MyStages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Connect => "connect",
+                Self::Helo => "helo",
+                Self::MailFrom => "mail_from",
+                Self::RcptTo => "rcpt_to",
+                Self::PreQueue => "pre_queue",
+                Self::PostQueue => "post_queue",
+                Self::Rewrite => "rewrite",
+                Self::Strip => "strip",
+            }
+        )
+    }
+}
+
+/// Custom status for the rule engine of the tests.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MyStatus {
+    Ok(Option<String>),
+    Fail(Option<String>),
+}
+
+impl Status for MyStatus {
+    fn no_rules(_: impl Stage) -> Self {
+        Self::Fail(None)
+    }
+
+    fn error(error: DirectiveError) -> Self {
+        dbg!(error);
+        Self::Fail(None)
+    }
+
+    fn next() -> Self {
+        Self::Ok(None)
+    }
+
+    fn is_next(&self) -> bool {
+        matches!(self, Self::Ok(None))
+    }
+}
+
+// Enable the user to access our statuses.
+#[rhai::export_module]
+pub mod status {
+    use super::*;
+
+    #[rhai_fn(name = "ok")]
+    pub const fn ok() -> MyStatus {
+        MyStatus::Ok(None)
+    }
+
+    #[rhai_fn(name = "ok")]
+    pub fn ok_with_message(message: &str) -> MyStatus {
+        MyStatus::Ok(Some(message.into()))
+    }
+
+    #[rhai_fn(name = "fail")]
+    pub const fn fail() -> MyStatus {
+        MyStatus::Fail(None)
+    }
+
+    #[rhai_fn(name = "fail")]
+    pub fn fail_with_message(message: &str) -> MyStatus {
+        MyStatus::Fail(Some(message.into()))
+    }
+}
+
+/// Define a service configuration.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct MyConfig {
+    pub dummy: bool,
+}
+
+impl Config for MyConfig {
+    fn api_version(&self) -> &semver::VersionReq {
+        unimplemented!()
+    }
+
+    fn broker(&self) -> &broker::Broker {
+        unimplemented!()
+    }
+
+    fn logs(&self) -> &logs::Logs {
+        unimplemented!()
+    }
+
+    fn path(&self) -> &std::path::Path {
+        unimplemented!()
+    }
+}
+
+/// Build the configuration running the script `tests/scripts/{script}`, with the `status`
+/// module and `modules`, and the directives listed in `toggles` enabled or disabled.
+pub fn config(
+    script: &str,
+    modules: impl IntoIterator<Item = (String, rhai::Shared<rhai::Module>)>,
+    toggles: impl IntoIterator<Item = (String, bool)>,
+) -> std::sync::Arc<RuleEngineConfig<Ctx<StatefulCtxReceived>, MyStatus, MyStages>> {
+    std::sync::Arc::new(
+        RuleEngineConfigBuilder::<Ctx<StatefulCtxReceived>, MyStatus, MyStages>::default()
+            .with_configuration(&MyConfig::default())
+            .expect("failed to build the configuration")
+            .with_default_module_resolvers(from_manifest_path!("tests/scripts"))
+            .with_standard_global_modules()
+            .with_static_modules(
+                [("status".to_string(), rhai::exported_module!(status).into())]
+                    .into_iter()
+                    .chain(modules),
+            )
+            .with_directive_toggles(toggles)
+            .with_script_at(from_manifest_path!("tests/scripts").join(script), "")
+            .unwrap_or_else(|_| panic!("failed to build script {script}"))
+            .build(),
+    )
+}
+
+/// Build a rule engine running the script `tests/scripts/{script}` on `ctx`,
+/// with the `status` module and `modules`.
+pub fn rule_engine(
+    script: &str,
+    modules: impl IntoIterator<Item = (String, rhai::Shared<rhai::Module>)>,
+    ctx: Ctx<StatefulCtxReceived>,
+) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    RuleEngine::from_config_with_state(config(script, modules, []), ctx)
+}
+
+/// The recipient `addr`, without original recipient nor notification.
+pub fn recipient(addr: &str) -> Recipient {
+    Recipient {
+        forward_path: Mailbox(Address::new_unchecked(addr.to_string())),
+        original_forward_path: None,
+        notify_on: NotifyOn::Never,
+    }
+}
+
+/// A transaction of `client.com` on the relay of `testserver.com`,
+/// from `john.doe@example.com` to `someone@example.net`.
+fn transaction() -> CtxReceived {
+    let mut metadata = CtxReceived::fake();
+    metadata.connect.client_addr = "127.0.0.1:49152".parse().unwrap();
+    metadata.connect.server_addr = "127.0.0.1:25".parse().unwrap();
+    metadata.connect.server_name = "testserver.com".parse().unwrap();
+    metadata.connect.kind = ConnectionKind::Relay;
+    metadata.connect.sasl = None;
+    metadata.connect.iprev = None;
+    metadata.connect.tls = None;
+    metadata.helo = HeloProps {
+        client_name: ClientName::Domain("client.com".parse().unwrap()),
+        using_deprecated: false,
+        spf_helo_identity: None,
+    };
+    metadata.mail_from.reverse_path = Some(Mailbox(Address::new_unchecked(
+        "john.doe@example.com".to_string(),
+    )));
+    metadata.mail_from.envelop_id = None;
+    metadata.mail_from.spf_mail_from_identity = None;
+    metadata.mail_from.ret = None;
+    metadata.mail_from.mime_body_type = None;
+    metadata.rcpt_to.recipient =
+        std::iter::once((DeliveryRoute::Basic, vec![recipient("someone@example.net")])).collect();
+    metadata.complete = CompleteProps {
+        dkim: None,
+        dmarc: None,
+    };
+    metadata
+}
+
+/// The state of the [`transaction`] after the `MAIL FROM` command.
+pub fn mail_from() -> Ctx<StatefulCtxReceived> {
+    let CtxReceived {
+        connect,
+        helo,
+        mail_from,
+        ..
+    } = transaction();
+
+    Ctx {
+        variables: std::collections::HashMap::default(),
+        internal: std::collections::HashMap::default(),
+        metadata: StatefulCtxReceived::MailFrom {
+            connect,
+            helo,
+            mail_from,
+        },
+    }
+}
+
+/// The state of the [`transaction`] once `mail` has been received.
+pub fn received(mail: &str) -> Ctx<StatefulCtxReceived> {
+    let mut metadata = transaction();
+    metadata.mail = std::sync::Arc::new(std::sync::RwLock::new(Mail::try_from(mail).unwrap()));
+
+    Ctx {
+        variables: std::collections::HashMap::default(),
+        internal: std::collections::HashMap::default(),
+        metadata: StatefulCtxReceived::Complete(metadata),
+    }
+}
@@ -148,3 +148,58 @@ fn configuration() {
         MyStatus::Next("configuration successful".to_string())
     );
 }
+
+fn build_at(
+    path: std::path::PathBuf,
+    missing_script: MissingScript,
+) -> Result<RuleEngineConfig<StatefulCtxReceived, MyStatus, MyStages>, RuleEngineConfigBuilderError>
+{
+    Ok(
+        RuleEngineConfigBuilder::<StatefulCtxReceived, MyStatus, MyStages>::default()
+            .with_configuration(&MyConfig::default())?
+            .with_static_modules([("status".to_string(), rhai::exported_module!(status).into())])
+            .with_standard_global_modules()
+            .with_missing_script(missing_script)
+            .with_script_at(
+                path,
+                r#"fn on_config_check(ctx) { ctx.run([rule "default" |ctx| status::next("default script")]) }"#,
+            )?
+            .build(),
+    )
+}
+
+#[test]
+fn missing_script_fallback() {
+    let rule_engine_config = build_at(
+        from_manifest_path!("tests/scripts/not_found.rhai"),
+        MissingScript::Warn,
+    )
+    .expect("the default script should be used");
+
+    let rule_engine = RuleEngine::from_config_with_state(
+        std::sync::Arc::new(rule_engine_config),
+        StatefulCtxReceived::Complete(CtxReceived::fake()),
+    );
+
+    assert_eq!(
+        rule_engine.run(&MyStages::ConfigCheck),
+        MyStatus::Next("default script".to_string())
+    );
+}
+
+#[test]
+fn missing_script_strict() {
+    let path = from_manifest_path!("tests/scripts/not_found.rhai");
+
+    assert!(matches!(
+        build_at(path.clone(), MissingScript::Fail),
+        Err(RuleEngineConfigBuilderError::ScriptNotFound(not_found)) if not_found == path
+    ));
+
+    // an existing script is loaded whatever the behavior.
+    assert!(build_at(
+        from_manifest_path!("tests/scripts/config.rhai"),
+        MissingScript::Fail
+    )
+    .is_ok());
+}
@@ -0,0 +1,266 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use common::{MyStages, MyStatus};
+use vsmtp_auth::dkim;
+use vsmtp_mail_parser::Mail;
+use vsmtp_protocol::ConnectionKind;
+use vsmtp_rule_engine::{
+    api::{server_auth_with_dkim_keys, smtp_modules, DkimKeys},
+    RuleEngine,
+};
+
+const MESSAGE: &str = concat!(
+    "From: john.doe@example.com\r\n",
+    "To: someone@example.net\r\n",
+    "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+    "Subject: Signed on demand\r\n",
+    "\r\n",
+    "Hello world!\r\n",
+);
+
+fn rule_engine(
+    kind: ConnectionKind,
+    dkim_keys: DkimKeys,
+) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    rule_engine_with("dkim_sign.rhai", kind, dkim_keys)
+}
+
+fn rule_engine_with(
+    script: &str,
+    kind: ConnectionKind,
+    dkim_keys: DkimKeys,
+) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    let mut ctx = common::received(MESSAGE);
+    ctx.metadata.mut_connect().kind = kind;
+
+    common::rule_engine(
+        script,
+        smtp_modules()
+            .into_iter()
+            .chain(server_auth_with_dkim_keys(dkim_keys)),
+        ctx,
+    )
+}
+
+/// Generate a key of `selector`, returning its public key as published in the DNS.
+fn key_pair(selector: &str) -> (dkim::DomainKey, dkim::PublicKey) {
+    let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+
+    // the base64 of the DER encoding is the content of the PEM encoding.
+    let pem = rsa::pkcs8::EncodePublicKey::to_public_key_pem(
+        &key.to_public_key(),
+        rsa::pkcs8::LineEnding::LF,
+    )
+    .unwrap();
+    let der = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect::<String>();
+    let public_key = format!("v=DKIM1; k=rsa; p={der}").parse().unwrap();
+
+    let private_key = rsa::pkcs8::EncodePrivateKey::to_pkcs8_pem(&key, rsa::pkcs8::LineEnding::LF)
+        .unwrap()
+        .to_string();
+
+    (
+        dkim::DomainKey {
+            selector: selector.to_string(),
+            private_key: private_key.parse().unwrap(),
+            headers_field: None,
+            canonicalization: None,
+        },
+        public_key,
+    )
+}
+
+/// The keys of the signing domains.
+fn keys<const N: usize>(keys: [(&str, dkim::DomainKey); N]) -> DkimKeys {
+    DkimKeys {
+        domains: keys
+            .into_iter()
+            .map(|(domain, key)| (domain.to_string(), key))
+            .collect(),
+        double_signing: None,
+    }
+}
+
+struct DkimHeader<'a>(&'a vsmtp_mail_parser::mail::headers::Header);
+
+impl dkim::Header for DkimHeader<'_> {
+    fn field_name(&self) -> String {
+        self.0.name.clone()
+    }
+
+    fn get(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+struct DkimMail<'a>(&'a Mail);
+
+impl<'a> dkim::Mail for DkimMail<'a> {
+    type H = DkimHeader<'a>;
+
+    fn get_body(&self) -> String {
+        self.0.body.to_string()
+    }
+
+    fn get_headers(&self) -> Vec<Self::H> {
+        self.0.headers.iter().map(DkimHeader).collect()
+    }
+}
+
+/// The message as it is sent.
+fn sent(rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>) -> Mail {
+    let serialized =
+        rule_engine.read_state(|ctx| ctx.metadata.get_mail(ToString::to_string).unwrap());
+    Mail::try_from(serialized.as_str()).unwrap()
+}
+
+#[test]
+fn submission_is_signed() {
+    let (key, public_key) = key_pair("2030");
+    let rule_engine = rule_engine(ConnectionKind::Submission, keys([("example.com", key)]));
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Ok(None));
+
+    let mail = sent(&rule_engine);
+    assert_eq!(mail.count_header("DKIM-Signature"), 1);
+    assert_eq!(mail.headers[0].name, "DKIM-Signature");
+
+    let header = mail
+        .get_headers_raw_without_crlf("DKIM-Signature")
+        .next()
+        .unwrap();
+    let signature = header.parse::<dkim::Signature>().unwrap();
+    assert_eq!(signature.sdid, "example.com");
+    assert_eq!(signature.selector, "2030");
+
+    dkim::verify(
+        &signature,
+        &DkimMail(&mail),
+        &public_key,
+        &dkim::VerifyPolicy::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn relay_is_not_signed() {
+    let (key, _) = key_pair("2030");
+    let rule_engine = rule_engine(ConnectionKind::Relay, keys([("example.com", key)]));
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Ok(None));
+
+    assert_eq!(sent(&rule_engine), Mail::try_from(MESSAGE).unwrap());
+}
+
+#[test]
+fn key_not_configured() {
+    let (key, _) = key_pair("2029");
+    let rule_engine = rule_engine(ConnectionKind::Submission, keys([("example.com", key)]));
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Fail(None));
+
+    assert_eq!(sent(&rule_engine).count_header("DKIM-Signature"), 0);
+}
+
+/// The value of the tag `name` of a `DKIM-Signature` header, without its whitespaces.
+fn tag(header: &str, name: &str) -> String {
+    header
+        .split(';')
+        .find_map(|tag| tag.trim().strip_prefix(name)?.strip_prefix('='))
+        .unwrap()
+        .split_whitespace()
+        .collect()
+}
+
+#[test]
+fn headers_by_domain() {
+    let (com, com_public_key) = key_pair("2030");
+    let (org, org_public_key) = key_pair("2030");
+    let rule_engine = rule_engine_with(
+        "dkim_sign_domains.rhai",
+        ConnectionKind::Submission,
+        keys([
+            (
+                "example.com",
+                dkim::DomainKey {
+                    headers_field: Some(vec!["From".to_string(), "Subject".to_string()]),
+                    canonicalization: Some("relaxed/relaxed".parse().unwrap()),
+                    ..com
+                },
+            ),
+            (
+                "example.org",
+                dkim::DomainKey {
+                    headers_field: Some(vec![
+                        "From".to_string(),
+                        "To".to_string(),
+                        "Date".to_string(),
+                    ]),
+                    canonicalization: Some("simple/simple".parse().unwrap()),
+                    ..org
+                },
+            ),
+        ]),
+    );
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Ok(None));
+
+    let mail = sent(&rule_engine);
+    assert_eq!(mail.count_header("DKIM-Signature"), 2);
+
+    for header in mail.get_headers_raw_without_crlf("DKIM-Signature") {
+        let signature = header.parse::<dkim::Signature>().unwrap();
+        let (headers_field, canonicalization, public_key) = match signature.sdid.as_str() {
+            "example.com" => ("From:Subject", "relaxed/relaxed", &com_public_key),
+            "example.org" => ("From:To:Date", "simple/simple", &org_public_key),
+            sdid => panic!("unexpected signing domain {sdid}"),
+        };
+
+        assert_eq!(tag(&header, "h"), headers_field);
+        assert_eq!(tag(&header, "c"), canonicalization);
+        dkim::verify(
+            &signature,
+            &DkimMail(&mail),
+            public_key,
+            &dkim::VerifyPolicy::default(),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn signed_once_by_domain() {
+    let (key, public_key) = key_pair("2030");
+    let rule_engine = rule_engine_with(
+        "dkim_sign_once.rhai",
+        ConnectionKind::Submission,
+        keys([("example.com", key)]),
+    );
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Ok(None));
+
+    let mail = sent(&rule_engine);
+    assert_eq!(mail.count_header("DKIM-Signature"), 1);
+
+    let header = mail
+        .get_headers_raw_without_crlf("DKIM-Signature")
+        .next()
+        .unwrap();
+    dkim::verify(
+        &header.parse::<dkim::Signature>().unwrap(),
+        &DkimMail(&mail),
+        &public_key,
+        &dkim::VerifyPolicy::default(),
+    )
+    .unwrap();
+}
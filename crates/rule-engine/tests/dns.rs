@@ -0,0 +1,117 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_common::hickory_resolver::{
+    error::ResolveError,
+    proto::rr::{
+        rdata::{A, AAAA, MX, PTR, TXT},
+        RData, RecordType,
+    },
+    Name,
+};
+use vsmtp_rule_engine::api::{lookup_records, RecordLookup};
+
+/// Answer with fixed records for `example.com` and `127.0.0.2`.
+struct FakeResolver;
+
+impl RecordLookup for FakeResolver {
+    fn lookup(&self, name: Name, record_type: RecordType) -> Result<Vec<RData>, ResolveError> {
+        let name = name.to_utf8();
+        let example = name.trim_end_matches('.') == "example.com";
+        let reverse = name.trim_end_matches('.') == "2.0.0.127.in-addr.arpa";
+
+        match record_type {
+            RecordType::A if example => Ok(vec![RData::A(A::new(93, 184, 216, 34))]),
+            RecordType::AAAA if example => Ok(vec![RData::AAAA(AAAA::from(
+                "2606:2800:220:1:248:1893:25c8:1946"
+                    .parse::<std::net::Ipv6Addr>()
+                    .unwrap(),
+            ))]),
+            RecordType::MX if example => Ok(vec![
+                RData::MX(MX::new(10, "mx1.example.com.".parse().unwrap())),
+                RData::MX(MX::new(20, "mx2.example.com.".parse().unwrap())),
+            ]),
+            RecordType::TXT if example => Ok(vec![RData::TXT(TXT::new(vec![
+                "v=spf1 ".to_string(),
+                "-all".to_string(),
+            ]))]),
+            RecordType::PTR if reverse => Ok(vec![RData::PTR(PTR(
+                "listed.example.com.".parse().unwrap(),
+            ))]),
+            _ => Err(ResolveError::from("no records found")),
+        }
+    }
+}
+
+fn lookup(host: &str, record: &str) -> rhai::Array {
+    lookup_records(&FakeResolver, host, record).unwrap()
+}
+
+#[test]
+fn a() {
+    let records = lookup("example.com", "A");
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].to_string(), "93.184.216.34");
+}
+
+#[test]
+fn aaaa() {
+    let records = lookup("example.com", "AAAA");
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        records[0].to_string(),
+        "2606:2800:220:1:248:1893:25c8:1946"
+    );
+}
+
+#[test]
+fn mx() {
+    let records = lookup("example.com", "MX")
+        .into_iter()
+        .map(|record| {
+            let record = record.cast::<rhai::Map>();
+            (
+                record["priority"].as_int().unwrap(),
+                record["host"].to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        records,
+        [
+            (10, "mx1.example.com".to_string()),
+            (20, "mx2.example.com".to_string())
+        ]
+    );
+}
+
+#[test]
+fn txt() {
+    let records = lookup("example.com", "TXT");
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].to_string(), "v=spf1 -all");
+}
+
+#[test]
+fn ptr() {
+    for host in ["127.0.0.2", "2.0.0.127.in-addr.arpa"] {
+        let records = lookup(host, "PTR");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].to_string(), "listed.example.com");
+    }
+}
+
+#[test]
+fn errors() {
+    assert!(lookup_records(&FakeResolver, "example.com", "NOT_A_TYPE").is_err());
+    assert!(lookup_records(&FakeResolver, "unknown.example.com", "A").is_err());
+}
@@ -0,0 +1,127 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use common::{MyStages, MyStatus};
+use vsmtp_mail_parser::{mail::body::ParsedBody, mime::Mime, Mail};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+fn rule_engine(mail: &str) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    common::rule_engine("footer.rhai", smtp_modules(), common::received(mail))
+}
+
+fn body(rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>) -> String {
+    rule_engine.read_state(|ctx| ctx.metadata.get_mail(|mail| mail.body.to_string()).unwrap())
+}
+
+/// Serialize the email and parse it again, to check that the result is valid.
+fn reparsed(rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>) -> Mime {
+    let serialized =
+        rule_engine.read_state(|ctx| ctx.metadata.get_mail(ToString::to_string).unwrap());
+
+    match Mail::try_from(serialized.as_str())
+        .unwrap()
+        .parse_body()
+        .unwrap()
+    {
+        ParsedBody::Mime(mime) => mime.as_ref().clone(),
+        otherwise => panic!("not a mime message anymore: {otherwise:?}"),
+    }
+}
+
+const ALTERNATIVE: &str = concat!(
+    "From: john.doe@example.com\r\n",
+    "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+    "Subject: alternative\r\n",
+    "MIME-Version: 1.0\r\n",
+    "Content-Type: multipart/alternative; boundary=\"alternative\"\r\n",
+    "\r\n",
+    "--alternative\r\n",
+    "Content-Type: text/plain; charset=utf-8\r\n",
+    "\r\n",
+    "Hello\r\n",
+    "--alternative\r\n",
+    "Content-Type: text/html; charset=utf-8\r\n",
+    "\r\n",
+    "<html><body><p>Hello</p></body></html>\r\n",
+    "--alternative--\r\n",
+);
+
+#[test]
+fn plain_text_message() {
+    let rule_engine = rule_engine(concat!(
+        "From: john.doe@example.com\r\n",
+        "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+        "Subject: test\r\n",
+        "\r\n",
+        "Hello world!\r\n",
+    ));
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    assert_eq!(
+        body(&rule_engine),
+        "Hello world!\r\n-- \r\nConfidential.\r\n"
+    );
+}
+
+#[test]
+fn alternative_message() {
+    let rule_engine = rule_engine(ALTERNATIVE);
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    let mut mime = reparsed(&rule_engine);
+    assert_eq!(mime.leaf_count(), 2);
+    assert_eq!(
+        mime.leaf_mut(0).unwrap().decoded_body().unwrap(),
+        b"Hello\r\n-- \r\nConfidential.\r\n"
+    );
+    assert_eq!(
+        mime.leaf_mut(1).unwrap().decoded_body().unwrap(),
+        b"<html><body><p>Hello</p><p>Confidential.</p></body></html>\r\n"
+    );
+}
+
+const SIGNATURE: &str = "v=1; a=rsa-sha256; d=example.com; s=dkim; bh=aGVsbG8=; b=d29ybGQ=";
+
+fn signed() -> String {
+    format!(
+        "DKIM-Signature: {SIGNATURE}\r\n{}",
+        concat!(
+            "From: john.doe@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "Subject: test\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+        )
+    )
+}
+
+#[test]
+fn signed_by_previous_hop() {
+    let rule_engine = rule_engine(&signed());
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    assert_eq!(
+        body(&rule_engine),
+        "Hello world!\r\n-- \r\nConfidential.\r\n"
+    );
+}
+
+#[test]
+fn signed_by_this_server() {
+    let rule_engine = rule_engine(&signed());
+    rule_engine.write_state(|ctx| ctx.add_dkim_signature(SIGNATURE.to_string()));
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Fail(None));
+
+    assert_eq!(body(&rule_engine), "Hello world!\r\n");
+}
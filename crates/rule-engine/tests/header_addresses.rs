@@ -0,0 +1,88 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use common::{MyStages, MyStatus};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+fn rule_engine(mail: &str) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    common::rule_engine(
+        "header_addresses.rhai",
+        smtp_modules(),
+        common::received(mail),
+    )
+}
+
+/// Run the script on `headers`, and get the variable `name` it has set.
+fn parsed(headers: &str, name: &str) -> rhai::Dynamic {
+    let rule_engine = rule_engine(
+        &[
+            headers,
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "Subject: test\r\n\r\nHello world!\r\n",
+        ]
+        .concat(),
+    );
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    rule_engine.read_state(|ctx| ctx.variables[name].clone())
+}
+
+#[test]
+fn header_from_domain() {
+    assert_eq!(
+        parsed(
+            "From: \"Doe, John\" (the author) <john.doe@example.com>,\r\n jane@example.org\r\n",
+            "from_domain"
+        )
+        .into_string()
+        .unwrap(),
+        "example.com"
+    );
+    assert!(parsed("From: undisclosed:;\r\n", "from_domain").is_unit());
+}
+
+#[test]
+fn header_sender_domain() {
+    assert_eq!(
+        parsed(
+            "From: john.doe@example.com\r\nSender: \"Secretary <on behalf>\" <secretary@assistant.example>\r\n",
+            "sender_domain"
+        )
+        .into_string()
+        .unwrap(),
+        "assistant.example"
+    );
+    assert!(parsed("From: john.doe@example.com\r\n", "sender_domain").is_unit());
+}
+
+#[test]
+fn header_reply_to() {
+    assert_eq!(
+        parsed(
+            concat!(
+                "From: john.doe@example.com\r\n",
+                "Reply-To: Support: \"Help, Desk\" <help@example.com>, <@relay:billing@example.org>;,\r\n",
+                "\tnot an address, jane@example.net (Jane)\r\n",
+            ),
+            "reply_to"
+        )
+        .into_typed_array::<String>()
+        .unwrap(),
+        ["help@example.com", "billing@example.org", "jane@example.net"]
+    );
+    assert!(parsed("From: john.doe@example.com\r\n", "reply_to")
+        .into_typed_array::<String>()
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,76 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use common::{MyStages, MyStatus};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+const MESSAGE: &str = concat!(
+    "From: john.doe@example.com\r\n",
+    "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+    "Subject: test\r\n",
+    "\r\n",
+    "Hello world!\r\n",
+);
+
+fn rule_engine() -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    common::rule_engine("headers.rhai", smtp_modules(), common::received(MESSAGE))
+}
+
+fn headers(rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>) -> Vec<String> {
+    rule_engine.read_state(|ctx| {
+        ctx.metadata
+            .get_mail(|mail| {
+                mail.headers
+                    .iter()
+                    .map(|header| header.to_string_without_crlf())
+                    .collect()
+            })
+            .unwrap()
+    })
+}
+
+#[test]
+fn add_headers_in_order() {
+    let rule_engine = rule_engine();
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    assert_eq!(
+        headers(&rule_engine),
+        [
+            "From: john.doe@example.com",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000",
+            "Subject: test",
+            "X-Third: 3",
+            "X-First: 1",
+            "X-Second: 2",
+            "X-A: a",
+            "X-B: b",
+        ]
+    );
+}
+
+#[test]
+fn add_headers_is_atomic() {
+    let rule_engine = rule_engine();
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Fail(None));
+
+    assert_eq!(
+        headers(&rule_engine),
+        [
+            "From: john.doe@example.com",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000",
+            "Subject: test",
+        ]
+    );
+}
@@ -0,0 +1,141 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use common::{MyStages, MyStatus};
+use vsmtp_mail_parser::{mail::body::ParsedBody, mime::Mime, Mail};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+fn rule_engine(mail: &str) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    common::rule_engine("mime.rhai", smtp_modules(), common::received(mail))
+}
+
+/// Serialize the email and parse it again, to check that the result is valid.
+fn reparsed(rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>) -> Mime {
+    let serialized =
+        rule_engine.read_state(|ctx| ctx.metadata.get_mail(ToString::to_string).unwrap());
+
+    match Mail::try_from(serialized.as_str())
+        .unwrap()
+        .parse_body()
+        .unwrap()
+    {
+        ParsedBody::Mime(mime) => mime.as_ref().clone(),
+        otherwise => panic!("not a mime message anymore: {otherwise:?}"),
+    }
+}
+
+fn headers(rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>) -> Vec<String> {
+    rule_engine.read_state(|ctx| {
+        ctx.metadata
+            .get_mail(|mail| {
+                mail.headers
+                    .iter()
+                    .map(|header| header.to_string_without_crlf())
+                    .collect()
+            })
+            .unwrap()
+    })
+}
+
+const NESTED: &str = concat!(
+    "From: john.doe@example.com\r\n",
+    "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+    "Subject: nested\r\n",
+    "MIME-Version: 1.0\r\n",
+    "Content-Type: multipart/mixed; boundary=\"mixed\"\r\n",
+    "\r\n",
+    "--mixed\r\n",
+    "Content-Type: multipart/alternative; boundary=\"alternative\"\r\n",
+    "\r\n",
+    "--alternative\r\n",
+    "Content-Type: text/plain; charset=utf-8\r\n",
+    "Content-Transfer-Encoding: quoted-printable\r\n",
+    "\r\n",
+    "caf=C3=A9 =\r\n",
+    "au lait\r\n",
+    "--alternative\r\n",
+    "Content-Type: text/html; charset=utf-8\r\n",
+    "\r\n",
+    "<p>caf\u{e9} au lait</p>\r\n",
+    "--alternative--\r\n",
+    "--mixed\r\n",
+    "Content-Type: application/octet-stream; name=\"hello.bin\"\r\n",
+    "Content-Disposition: attachment; filename=\"hello.txt\"\r\n",
+    "Content-Transfer-Encoding: base64\r\n",
+    "\r\n",
+    "aGVsbG8gd29y\r\n",
+    "bGQ=\r\n",
+    "--mixed--\r\n",
+);
+
+#[test]
+fn nested_multipart() {
+    let rule_engine = rule_engine(NESTED);
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    assert_eq!(
+        &headers(&rule_engine)[3..],
+        [
+            "X-Tree: multipart/mixed(multipart/alternative(text/plain,text/html),application/octet-stream)",
+            "X-Part: text/plain|-|-|false|caf\u{e9} au lait",
+            "X-Part: text/html|-|-|false|<p>caf\u{e9} au lait</p>",
+            "X-Part: application/octet-stream|attachment|hello.txt|true|hello world",
+        ]
+    );
+}
+
+#[test]
+fn not_a_mime_message() {
+    let rule_engine = rule_engine(concat!(
+        "From: john.doe@example.com\r\n",
+        "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+        "Subject: test\r\n",
+        "\r\n",
+        "Hello world!\r\n",
+    ));
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Ok(None));
+}
+
+#[test]
+fn replace_text_part() {
+    let rule_engine = rule_engine(NESTED);
+    assert_eq!(rule_engine.run(&MyStages::Rewrite), MyStatus::Ok(None));
+
+    let mut mime = reparsed(&rule_engine);
+    assert_eq!(mime.leaf_count(), 3);
+
+    let text = mime.leaf_mut(0).unwrap();
+    assert_eq!(text.content_type(), "text/plain");
+    assert_eq!(
+        text.decoded_body().unwrap(),
+        "caf\u{e9} au lait\r\n-- \r\ncaf\u{e9} disclaimer\r\n".as_bytes()
+    );
+    assert_eq!(
+        text.raw_part(),
+        "caf=C3=A9 au lait\r\n--=20\r\ncaf=C3=A9 disclaimer\r\n"
+    );
+}
+
+#[test]
+fn remove_attachment() {
+    let rule_engine = rule_engine(NESTED);
+    assert_eq!(rule_engine.run(&MyStages::Strip), MyStatus::Ok(None));
+
+    let mut mime = reparsed(&rule_engine);
+    assert_eq!(mime.content_type(), "multipart/mixed");
+    assert_eq!(mime.leaf_count(), 2);
+    assert_eq!(mime.leaf_mut(0).unwrap().content_type(), "text/plain");
+    assert_eq!(mime.leaf_mut(1).unwrap().content_type(), "text/html");
+    assert!(!mime.to_string().contains("hello.txt"));
+}
@@ -15,142 +15,9 @@ use ::vsmtp_common::{
     ctx_received::CtxReceived, delivery_route::DeliveryRoute,
     stateful_ctx_received::StatefulCtxReceived, Mailbox,
 };
-use vsmtp_config::{broker, logs, semver, Config};
+use common::{MyConfig, MyStages, MyStatus};
 use vsmtp_protocol::Address;
-use vsmtp_rule_engine::{
-    rhai::plugin::*, DirectiveError, RuleEngine, RuleEngineConfigBuilder, Stage, Status,
-};
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum MyStages {
-    Connect,
-    Helo,
-    MailFrom,
-    RcptTo,
-}
-
-impl Stage for MyStages {
-    fn hook(&self) -> &'static str {
-        match self {
-            Self::Connect => "on_connect",
-            Self::Helo => "on_helo",
-            Self::MailFrom => "on_mail_from",
-            Self::RcptTo => "on_rcpt_to",
-        }
-    }
-
-    fn stages() -> &'static [&'static str] {
-        &["connect", "helo", "mail_from", "rcpt_to"]
-    }
-}
-
-impl std::str::FromStr for MyStages {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "connect" => Ok(Self::Connect),
-            "helo" => Ok(Self::Helo),
-            "mail_from" => Ok(Self::MailFrom),
-            "rcpt_to" => Ok(Self::RcptTo),
-            _ => Err(()),
-        }
-    }
-}
-
-impl std::fmt::Display for MyStages {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::Connect => "connect",
-                Self::Helo => "helo",
-                Self::MailFrom => "mail_from",
-                Self::RcptTo => "rcpt_to",
-            }
-        )
-    }
-}
-
-/// Custom status for this rule engine.
-#[allow(dead_code)]
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum MyStatus {
-    Ok(Option<String>),
-    Fail(Option<String>),
-}
-
-/// Implement the [`Status`] trait and defining our own rules
-/// for each status.
-impl Status for MyStatus {
-    fn no_rules(_: impl Stage) -> Self {
-        Self::Fail(None)
-    }
-
-    fn error(error: DirectiveError) -> Self {
-        dbg!(error);
-        Self::Fail(None)
-    }
-
-    fn next() -> Self {
-        Self::Ok(None)
-    }
-
-    fn is_next(&self) -> bool {
-        matches!(self, Self::Ok(None))
-    }
-}
-
-// Enable the user to access our statuses.
-#[rhai::export_module]
-mod status {
-    use super::*;
-
-    #[rhai_fn(name = "ok")]
-    pub const fn ok() -> MyStatus {
-        MyStatus::Ok(None)
-    }
-
-    #[rhai_fn(name = "ok")]
-    pub fn ok_with_message(message: &str) -> MyStatus {
-        MyStatus::Ok(Some(message.into()))
-    }
-
-    #[rhai_fn(name = "fail")]
-    pub const fn fail() -> MyStatus {
-        MyStatus::Fail(None)
-    }
-
-    #[rhai_fn(name = "fail")]
-    pub fn fail_with_message(message: &str) -> MyStatus {
-        MyStatus::Fail(Some(message.into()))
-    }
-}
-
-/// Define a service configuration.
-#[derive(Default, serde::Serialize, serde::Deserialize)]
-struct MyConfig {
-    dummy: bool,
-}
-
-impl Config for MyConfig {
-    fn api_version(&self) -> &semver::VersionReq {
-        unimplemented!()
-    }
-
-    fn broker(&self) -> &broker::Broker {
-        unimplemented!()
-    }
-
-    fn logs(&self) -> &logs::Logs {
-        unimplemented!()
-    }
-
-    fn path(&self) -> &std::path::Path {
-        unimplemented!()
-    }
-}
+use vsmtp_rule_engine::{RuleEngine, RuleEngineConfigBuilder};
 
 #[allow(clippy::too_many_lines)]
 #[test]
@@ -162,7 +29,10 @@ fn resolve_file() {
             .with_default_module_resolvers(from_manifest_path!("tests/scripts/module-resolver"))
             .with_standard_global_modules()
             .with_smtp_modules()
-            .with_static_modules([("status".to_string(), rhai::exported_module!(status).into())])
+            .with_static_modules([(
+                "status".to_string(),
+                rhai::exported_module!(common::status).into(),
+            )])
             .engine(|engine| {
                 engine.on_print(|message| {
                     dbg!(message);
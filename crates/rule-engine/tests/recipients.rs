@@ -0,0 +1,159 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{
+    ctx::Ctx, delivery_route::DeliveryRoute, stateful_ctx_received::StatefulCtxReceived, Mailbox,
+    Recipient,
+};
+use common::{MyStages, MyStatus};
+use vsmtp_protocol::{Address, NotifyOn, OriginalRecipient};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+const MESSAGE: &str = concat!(
+    "From: john.doe@example.com\r\n",
+    "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+    "Subject: test\r\n",
+    "\r\n",
+    "Hello world!\r\n",
+);
+
+fn rule_engine() -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    let mut ctx = common::received(MESSAGE);
+    ctx.metadata.mut_rcpt_to().unwrap().recipient = [
+        (
+            DeliveryRoute::Maildir,
+            vec![recipient("info@example.com", None, NotifyOn::Never)],
+        ),
+        (
+            DeliveryRoute::Basic,
+            vec![
+                recipient(
+                    "jenny@example.com",
+                    Some(orcpt("jenny@example.net")),
+                    NotifyOn::Some {
+                        success: true,
+                        failure: true,
+                        delay: false,
+                    },
+                ),
+                recipient(
+                    "someone@example.org",
+                    None,
+                    NotifyOn::Some {
+                        success: false,
+                        failure: true,
+                        delay: true,
+                    },
+                ),
+            ],
+        ),
+    ]
+    .into_iter()
+    .collect();
+
+    common::rule_engine("recipients.rhai", smtp_modules(), ctx)
+}
+
+fn orcpt(addr: &str) -> OriginalRecipient {
+    OriginalRecipient {
+        addr_type: "rfc822".to_string(),
+        mailbox: Address::new_unchecked(addr.to_string()),
+        xtext: None,
+    }
+}
+
+fn recipient(
+    addr: &str,
+    original_forward_path: Option<OriginalRecipient>,
+    notify_on: NotifyOn,
+) -> Recipient {
+    Recipient {
+        forward_path: Mailbox(Address::new_unchecked(addr.to_string())),
+        original_forward_path,
+        notify_on,
+    }
+}
+
+/// The recipients listed by the script, by address.
+fn listed(
+    rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>,
+) -> Vec<serde_json::Value> {
+    let mut listed = rule_engine.read_state(|ctx| {
+        serde_json::from_value::<Vec<serde_json::Value>>(
+            serde_json::to_value(&ctx.variables["recipients"]).unwrap(),
+        )
+        .unwrap()
+    });
+    listed.sort_by_key(|rcpt| rcpt["address"].as_str().unwrap().to_string());
+    listed
+}
+
+#[test]
+fn iterate_recipients() {
+    let rule_engine = rule_engine();
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    assert_eq!(
+        listed(&rule_engine),
+        [
+            serde_json::json!({
+                "address": "info@example.com",
+                "route": "maildir",
+                "notify_on": [],
+                "orcpt": null,
+            }),
+            serde_json::json!({
+                "address": "jenny@example.com",
+                "route": "basic",
+                "notify_on": ["success", "failure"],
+                "orcpt": "jenny@example.net",
+            }),
+            serde_json::json!({
+                "address": "someone@example.org",
+                "route": "basic",
+                "notify_on": ["failure", "delay"],
+                "orcpt": null,
+            }),
+        ]
+    );
+}
+
+#[test]
+fn route_recipients() {
+    let rule_engine = rule_engine();
+    assert_eq!(rule_engine.run(&MyStages::PostQueue), MyStatus::Ok(None));
+
+    let routes = listed(&rule_engine)
+        .iter()
+        .map(|rcpt| {
+            (
+                rcpt["address"].as_str().unwrap().to_string(),
+                rcpt["route"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        routes,
+        [
+            ("info@example.com".to_string(), "maildir".to_string()),
+            ("jenny@example.com".to_string(), "maildir".to_string()),
+            ("someone@example.org".to_string(), "basic".to_string()),
+        ]
+    );
+
+    rule_engine.read_state(|ctx| {
+        let rcpt_to = ctx.metadata.get_rcpt_to().unwrap();
+        assert_eq!(rcpt_to.recipient[&DeliveryRoute::Maildir].len(), 2);
+        assert_eq!(rcpt_to.recipient[&DeliveryRoute::Basic].len(), 1);
+    });
+}
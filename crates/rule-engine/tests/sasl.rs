@@ -0,0 +1,85 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::stateful_ctx_received::SaslAuthProps;
+use common::{MyStages, MyStatus};
+use vsmtp_protocol::{
+    auth::{Credentials, Mechanism},
+    ConnectionKind,
+};
+use vsmtp_rule_engine::api::msa_modules;
+
+fn run_with_sasl(sasl: Option<SaslAuthProps>) -> MyStatus {
+    let mut ctx = common::mail_from();
+    let connect = ctx.metadata.mut_connect();
+    connect.kind = ConnectionKind::Submission;
+    connect.sasl = sasl;
+
+    common::rule_engine("sasl.rhai", msa_modules(), ctx).run(&MyStages::MailFrom)
+}
+
+fn authenticated(mechanism: Mechanism, authid: &str) -> Option<SaslAuthProps> {
+    Some(SaslAuthProps {
+        cancel_count: 0,
+        failure_count: 0,
+        is_authenticated: true,
+        mechanism,
+        credentials: Credentials::Verify {
+            authid: authid.to_string(),
+            authpass: "password".to_string(),
+        },
+    })
+}
+
+#[test]
+fn not_authenticated() {
+    assert_eq!(
+        run_with_sasl(None),
+        MyStatus::Fail(Some("not authenticated".to_string()))
+    );
+}
+
+#[test]
+fn failed_authentication() {
+    let mut sasl = authenticated(Mechanism::Plain, "john.doe");
+    sasl.as_mut().unwrap().is_authenticated = false;
+
+    assert_eq!(
+        run_with_sasl(sasl),
+        MyStatus::Fail(Some("not authenticated".to_string()))
+    );
+}
+
+#[test]
+fn branch_on_identity() {
+    assert_eq!(
+        run_with_sasl(authenticated(Mechanism::Plain, "john.doe")),
+        MyStatus::Ok(Some("john.doe using PLAIN".to_string()))
+    );
+    assert_eq!(
+        run_with_sasl(authenticated(Mechanism::Plain, "jane.doe")),
+        MyStatus::Ok(None)
+    );
+}
+
+#[test]
+fn branch_on_mechanism() {
+    assert_eq!(
+        run_with_sasl(authenticated(Mechanism::Login, "john.doe")),
+        MyStatus::Ok(Some("john.doe using LOGIN".to_string()))
+    );
+    assert_eq!(
+        run_with_sasl(authenticated(Mechanism::Login, "jane.doe")),
+        MyStatus::Fail(Some("jane.doe is using an obsolete mechanism".to_string()))
+    );
+}
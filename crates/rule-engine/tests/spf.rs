@@ -0,0 +1,143 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_auth::spf::Value;
+use vsmtp_common::{
+    ctx::Ctx, ctx_received::CtxReceived, stateful_ctx_received::StatefulCtxReceived, Mailbox,
+};
+use vsmtp_protocol::ClientName;
+use vsmtp_rule_engine::api::{spf_check_helo, spf_check_mail_from, State};
+
+/// Answer with the SPF records of `mail.example.com`, which allows `192.0.2.1`,
+/// and of `example.com`, which allows no host.
+struct FakeLookup;
+
+#[async_trait::async_trait]
+impl viaspf::lookup::Lookup for FakeLookup {
+    async fn lookup_a<'lookup, 'a>(
+        &'lookup self,
+        _: &'a viaspf::lookup::Name,
+    ) -> viaspf::lookup::LookupResult<Vec<std::net::Ipv4Addr>> {
+        Err(viaspf::lookup::LookupError::NoRecords)
+    }
+
+    async fn lookup_aaaa<'lookup, 'a>(
+        &'lookup self,
+        _: &'a viaspf::lookup::Name,
+    ) -> viaspf::lookup::LookupResult<Vec<std::net::Ipv6Addr>> {
+        Err(viaspf::lookup::LookupError::NoRecords)
+    }
+
+    async fn lookup_mx<'lookup, 'a>(
+        &'lookup self,
+        _: &'a viaspf::lookup::Name,
+    ) -> viaspf::lookup::LookupResult<Vec<viaspf::lookup::Name>> {
+        Err(viaspf::lookup::LookupError::NoRecords)
+    }
+
+    async fn lookup_txt<'lookup, 'a>(
+        &'lookup self,
+        name: &'a viaspf::lookup::Name,
+    ) -> viaspf::lookup::LookupResult<Vec<String>> {
+        let name: &str = name.as_ref();
+        match name.trim_end_matches('.') {
+            "mail.example.com" => Ok(vec!["v=spf1 ip4:192.0.2.1 -all".to_string()]),
+            "example.com" => Ok(vec!["v=spf1 -all".to_string()]),
+            _ => Err(viaspf::lookup::LookupError::NoRecords),
+        }
+    }
+
+    async fn lookup_ptr<'lookup>(
+        &'lookup self,
+        _: std::net::IpAddr,
+    ) -> viaspf::lookup::LookupResult<Vec<viaspf::lookup::Name>> {
+        Err(viaspf::lookup::LookupError::NoRecords)
+    }
+}
+
+/// A transaction from `192.0.2.1`, with `helo` and `reverse_path` as identities.
+fn received(helo: ClientName, reverse_path: Option<&str>) -> State<Ctx<StatefulCtxReceived>> {
+    let mut metadata = CtxReceived::fake();
+    metadata.connect.client_addr = "192.0.2.1:49152".parse().unwrap();
+    metadata.helo.client_name = helo;
+    metadata.helo.spf_helo_identity = None;
+    metadata.mail_from.reverse_path = reverse_path.map(|address| Mailbox(address.parse().unwrap()));
+    metadata.mail_from.spf_mail_from_identity = None;
+
+    State::from(Ctx {
+        variables: std::collections::HashMap::default(),
+        internal: std::collections::HashMap::default(),
+        metadata: StatefulCtxReceived::Complete(metadata),
+    })
+}
+
+fn mail_example_com() -> ClientName {
+    ClientName::Domain("mail.example.com".parse().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_helo() {
+    let ctx = received(mail_example_com(), Some("john.doe@example.com"));
+
+    let result = spf_check_helo(&ctx, &FakeLookup).unwrap();
+    assert_eq!(result.value, Value::Pass);
+    assert_eq!(result.domain.as_deref(), Some("mail.example.com"));
+
+    ctx.read(|ctx| {
+        let stored = ctx.metadata.get_helo().unwrap().spf_helo_identity.clone();
+        assert_eq!(stored.unwrap().value, Value::Pass);
+        assert!(ctx
+            .metadata
+            .get_mail_from()
+            .unwrap()
+            .spf_mail_from_identity
+            .is_none());
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_helo_ip() {
+    let ctx = received(ClientName::Ip4("192.0.2.1".parse().unwrap()), None);
+
+    let result = spf_check_helo(&ctx, &FakeLookup).unwrap();
+    assert_eq!(result.value, Value::None);
+    assert_eq!(result.domain, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_mail_from() {
+    let ctx = received(mail_example_com(), Some("john.doe@example.com"));
+
+    let result = spf_check_mail_from(&ctx, &FakeLookup).unwrap();
+    assert_eq!(result.value, Value::Fail);
+    assert_eq!(result.domain.as_deref(), Some("example.com"));
+
+    ctx.read(|ctx| {
+        let stored = ctx
+            .metadata
+            .get_mail_from()
+            .unwrap()
+            .spf_mail_from_identity
+            .clone();
+        assert_eq!(stored.unwrap().value, Value::Fail);
+        assert!(ctx.metadata.get_helo().unwrap().spf_helo_identity.is_none());
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_mail_from_null_reverse_path() {
+    let ctx = received(mail_example_com(), None);
+
+    // the HELO identity is checked instead.
+    let result = spf_check_mail_from(&ctx, &FakeLookup).unwrap();
+    assert_eq!(result.value, Value::Pass);
+    assert_eq!(result.domain.as_deref(), Some("mail.example.com"));
+}
@@ -0,0 +1,81 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use common::{MyStages, MyStatus};
+use vsmtp_mail_parser::{mail::encoded_words, Mail};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+fn rule_engine(mail: &str) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    common::rule_engine("subject.rhai", smtp_modules(), common::received(mail))
+}
+
+/// Serialize the email and parse it again, returning the raw and decoded `Subject`.
+fn reparsed_subject(
+    rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>,
+) -> (String, String) {
+    let serialized =
+        rule_engine.read_state(|ctx| ctx.metadata.get_mail(ToString::to_string).unwrap());
+    let mail = Mail::try_from(serialized.as_str()).unwrap();
+
+    (
+        mail.get_header("Subject").unwrap().body.clone(),
+        mail.subject().unwrap(),
+    )
+}
+
+fn original_subject(
+    rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>,
+) -> String {
+    rule_engine.read_state(|ctx| ctx.variables["subject"].to_string())
+}
+
+fn message(subject: &str) -> String {
+    format!(
+        concat!(
+            "From: john.doe@example.com\r\n",
+            "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+            "Subject: {}\r\n",
+            "\r\n",
+            "Hello world!\r\n",
+        ),
+        subject
+    )
+}
+
+#[test]
+fn encoded_subject() {
+    let rule_engine = rule_engine(&message(
+        "=?ISO-8859-1?Q?Caf=E9?= =?utf-8?B?IMOgIGVtcG9ydGVy?=",
+    ));
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+    assert_eq!(original_subject(&rule_engine), "Café à emporter");
+
+    let (raw, decoded) = reparsed_subject(&rule_engine);
+    assert_eq!(decoded, "[SPAM] Café à emporter");
+    assert!(raw.is_ascii());
+    assert_eq!(encoded_words::decode(&raw), decoded);
+}
+
+#[test]
+fn ascii_subject() {
+    let rule_engine = rule_engine(&message("Hello"));
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+    assert_eq!(original_subject(&rule_engine), "Hello");
+
+    // plain ASCII is not encoded, and the prefix is added once.
+    assert_eq!(
+        reparsed_subject(&rule_engine),
+        (" [SPAM] Hello\r\n".to_string(), "[SPAM] Hello".to_string())
+    );
+}
@@ -0,0 +1,105 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{
+    ctx::Ctx,
+    delivery_route::DeliveryRoute,
+    stateful_ctx_received::{SaslAuthProps, StatefulCtxReceived},
+    Mailbox,
+};
+use common::{MyStages, MyStatus};
+use vsmtp_mail_parser::Mail;
+use vsmtp_protocol::{
+    auth::{Credentials, Mechanism},
+    Address, ConnectionKind,
+};
+use vsmtp_rule_engine::{api::msa_modules, RuleEngine};
+
+fn rule_engine(
+    authid: &str,
+    reverse_path: &str,
+) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    let mut ctx = common::mail_from();
+    let connect = ctx.metadata.mut_connect();
+    connect.kind = ConnectionKind::Submission;
+    connect.sasl = Some(SaslAuthProps {
+        cancel_count: 0,
+        failure_count: 0,
+        is_authenticated: true,
+        mechanism: Mechanism::Plain,
+        credentials: Credentials::Verify {
+            authid: authid.to_string(),
+            authpass: "password".to_string(),
+        },
+    });
+    ctx.metadata.mut_mail_from().unwrap().reverse_path =
+        Some(Mailbox(Address::new_unchecked(reverse_path.to_string())));
+
+    common::rule_engine("submission.rhai", msa_modules(), ctx)
+}
+
+fn receive(rule_engine: &RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages>, from: &str) {
+    let mail =
+        Mail::try_from(format!("From: {from}\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\nSubject: test\r\n\r\nHello world!\r\n").as_str())
+            .unwrap();
+
+    rule_engine.write_state(|ctx| {
+        ctx.metadata
+            .set_rcpt_to(
+                DeliveryRoute::Basic,
+                common::recipient("someone@example.net"),
+            )
+            .unwrap()
+            .set_complete(mail)
+            .unwrap();
+    });
+}
+
+#[test]
+fn matching_sender() {
+    let rule_engine = rule_engine("john.doe@example.com", "John.Doe@example.com");
+    assert_eq!(rule_engine.run(&MyStages::MailFrom), MyStatus::Ok(None));
+
+    receive(&rule_engine, "John Doe <john.doe@example.com>");
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+}
+
+#[test]
+fn permitted_alias() {
+    let rule_engine = rule_engine("john.doe@example.com", "sales@example.com");
+    assert_eq!(rule_engine.run(&MyStages::MailFrom), MyStatus::Ok(None));
+
+    receive(&rule_engine, "Sales <sales@example.com>");
+    assert_eq!(rule_engine.run(&MyStages::PreQueue), MyStatus::Ok(None));
+}
+
+#[test]
+fn spoofed_envelope_sender() {
+    let rule_engine = rule_engine("john.doe@example.com", "jane.doe@example.com");
+    assert_eq!(
+        rule_engine.run(&MyStages::MailFrom),
+        MyStatus::Fail(Some("553 5.7.1".to_string()))
+    );
+}
+
+#[test]
+fn spoofed_from_header() {
+    let rule_engine = rule_engine("john.doe@example.com", "john.doe@example.com");
+    assert_eq!(rule_engine.run(&MyStages::MailFrom), MyStatus::Ok(None));
+
+    receive(&rule_engine, "Jane Doe <jane.doe@example.com>");
+    assert_eq!(
+        rule_engine.run(&MyStages::PreQueue),
+        MyStatus::Fail(Some("553 5.7.1".to_string()))
+    );
+}
@@ -0,0 +1,64 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use common::{MyStages, MyStatus};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+/// Run the `connect` stage with the directives toggled by `toggles`,
+/// and return the status and the variables set by the directives executed.
+fn run(toggles: &[(&str, bool)]) -> (MyStatus, Vec<String>) {
+    let rule_engine_config = common::config(
+        "toggles.rhai",
+        smtp_modules(),
+        toggles
+            .iter()
+            .map(|(name, enabled)| ((*name).to_string(), *enabled)),
+    );
+
+    let (mut statuses, ctx) =
+        RuleEngine::simulate(rule_engine_config, common::mail_from(), [MyStages::Connect]);
+    let mut executed = ctx.variables.into_keys().collect::<Vec<_>>();
+    executed.sort();
+
+    (statuses.remove(0).1, executed)
+}
+
+#[test]
+fn all_enabled() {
+    assert_eq!(
+        run(&[("blocklist", true)]),
+        (
+            MyStatus::Fail(None),
+            vec!["blocklist".to_string(), "first".to_string()]
+        )
+    );
+}
+
+#[test]
+fn disabled_rule_skipped() {
+    assert_eq!(
+        run(&[("blocklist", false)]),
+        (
+            MyStatus::Ok(None),
+            vec!["first".to_string(), "last".to_string()]
+        )
+    );
+}
+
+#[test]
+fn disabled_action_skipped() {
+    assert_eq!(
+        run(&[("first", false), ("unknown", false)]),
+        (MyStatus::Fail(None), vec!["blocklist".to_string()])
+    );
+}
@@ -0,0 +1,59 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+mod common;
+
+use ::vsmtp_common::{ctx::Ctx, stateful_ctx_received::StatefulCtxReceived};
+use common::{MyStages, MyStatus};
+use vsmtp_rule_engine::{api::smtp_modules, RuleEngine};
+
+const MESSAGE: &str = concat!(
+    "From: john.doe@example.com\r\n",
+    "Date: Tue, 1 Jan 2030 00:00:00 +0000\r\n",
+    "Subject: test\r\n",
+    "\r\n",
+    "Hello world!\r\n",
+);
+
+fn rule_engine(
+    ctx: Option<Ctx<StatefulCtxReceived>>,
+) -> RuleEngine<Ctx<StatefulCtxReceived>, MyStatus, MyStages> {
+    common::rule_engine(
+        "variables.rhai",
+        smtp_modules(),
+        ctx.unwrap_or_else(|| common::received(MESSAGE)),
+    )
+}
+
+#[test]
+fn variables_carried_to_the_working_service() {
+    // the receiver service.
+    let receiver = rule_engine(None);
+    assert_eq!(receiver.run(&MyStages::PreQueue), MyStatus::Ok(None));
+
+    // the context is serialized to be sent to the working service.
+    let payload = receiver.take_state().to_json().unwrap();
+    let working = rule_engine(Some(
+        Ctx::<StatefulCtxReceived>::from_json(&payload).unwrap(),
+    ));
+    assert_eq!(working.run(&MyStages::PostQueue), MyStatus::Ok(None));
+
+    working.read_state(|ctx| {
+        assert_eq!(ctx.variables["spam_score"].to_string(), "2.5");
+        assert!(ctx.variables["checked"].as_bool().unwrap());
+    });
+}
+
+#[test]
+fn non_plain_data_is_rejected() {
+    let working = rule_engine(None);
+    assert_eq!(working.run(&MyStages::PostQueue), MyStatus::Fail(None));
+}
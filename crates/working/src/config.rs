@@ -9,7 +9,9 @@
  *
  */
 
+use vsmtp_common::{delivery_route::DeliveryRoute, domain_map::DomainMap};
 use vsmtp_config::{logs, semver, Broker, Config, Logs};
+use vsmtp_rule_engine::{api::DkimKeys, MissingScript};
 
 pub mod cli;
 
@@ -24,6 +26,28 @@ pub struct WorkingConfig {
     /// Filters configuration.
     #[serde(default)]
     pub scripts: Scripts,
+    /// Name of the delivery transport of the recipients, by domain pattern.
+    /// The delivery services apply the policy of the transport (TLS, retry, ...).
+    #[serde(default)]
+    pub transports: DomainMap<String>,
+    /// Header classifying the messages not classified by the rules, `high` or `urgent`
+    /// publishing them to the priority delivery queues. Not read if unset.
+    #[serde(default)]
+    pub priority_header: Option<String>,
+    /// What to do with the messages whose route has no delivery service bound to it.
+    /// Not applied to the deliveries delayed by the rules, the broker drops them if unroutable.
+    #[serde(default)]
+    pub no_route: NoRouteFallback,
+    /// What to do with the messages whose recipients have all been removed by the rules.
+    #[serde(default)]
+    pub no_recipient: NoRecipientFallback,
+    /// Skip of the messages redelivered by the broker once processed.
+    #[serde(default)]
+    pub idempotency: Idempotency,
+    /// Keys signing the messages with `dkim::sign(ctx, domain, selector)`
+    /// or `dkim::sign_by_domain(ctx)` in the rules.
+    #[serde(default)]
+    pub dkim_keys: DkimKeys,
     /// AMQP client configuration.
     #[serde(default)]
     pub broker: Broker,
@@ -41,18 +65,92 @@ impl WorkingConfig {
     }
 }
 
+/// Fallback of the messages published to a route without delivery service.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum NoRouteFallback {
+    /// Put the message in the `no-route` queue of the quarantine exchange.
+    #[default]
+    Quarantine,
+    /// Send a failure notification to the sender, for the recipients which requested one.
+    Bounce,
+    /// Hand the message over to the delivery service of `route` instead.
+    CatchAll {
+        /// Route of the catch-all delivery service, ex: `maildir` or `forward.relay`.
+        route: DeliveryRoute,
+    },
+}
+
+/// Fallback of the messages without recipient once the post-queue rules have been run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NoRecipientFallback {
+    /// Discard the message, only a log records it.
+    #[default]
+    Drop,
+    /// Send a failure notification to the sender, for the recipients received
+    /// which requested one.
+    Bounce,
+    /// Put the message in the `no-recipient` quarantine.
+    Quarantine,
+}
+
+/// Messages flagged as redelivered by the broker (the acknowledgement of a handled
+/// message was lost, ...) are skipped if this worker has handled their uuid less than `ttl` ago.
+///
+/// The uuids are kept by each worker: a message redelivered to another worker is handled again.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Idempotency {
+    /// Record the uuids of the processed messages.
+    #[serde(default = "Idempotency::default_enable")]
+    pub enable: bool,
+    /// How long the uuid of a processed message is kept.
+    #[serde(default = "Idempotency::default_ttl", with = "humantime_serde")]
+    pub ttl: std::time::Duration,
+}
+
+impl Idempotency {
+    const fn default_enable() -> bool {
+        true
+    }
+
+    const fn default_ttl() -> std::time::Duration {
+        std::time::Duration::from_secs(10 * 60)
+    }
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Self {
+            enable: Self::default_enable(),
+            ttl: Self::default_ttl(),
+        }
+    }
+}
+
 /// Scripts location and parameters.
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Scripts {
     #[serde(default = "Scripts::default_script_path")]
     pub path: std::path::PathBuf,
+    /// Use the default script with a warning (`warn`) or fail to start (`fail`)
+    /// if the script at `path` does not exist.
+    #[serde(default)]
+    pub on_missing: MissingScript,
+    /// Rules and actions enabled (`true`) or disabled (`false`) by name,
+    /// to turn them off without editing the scripts. Enabled if not listed.
+    #[serde(default)]
+    pub rules: std::collections::HashMap<String, bool>,
 }
 
 impl Default for Scripts {
     fn default() -> Self {
         Self {
             path: Self::default_script_path(),
+            on_missing: MissingScript::default(),
+            rules: std::collections::HashMap::default(),
         }
     }
 }
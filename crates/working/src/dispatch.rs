@@ -0,0 +1,1020 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use crate::{
+    config::{NoRecipientFallback, NoRouteFallback},
+    rules::status::WorkingStatus,
+};
+use vsmtp_common::{
+    broker::{Priority, Queue, QueueBackend},
+    ctx::Ctx,
+    ctx_delivery::CtxDelivery,
+    ctx_received::CtxReceived,
+    delivery_attempt::{DeliveryAttempt, LocalInformation, ShouldNotify},
+    delivery_route::DeliveryRoute,
+    domain_map::DomainMap,
+    stateful_ctx_received::StatefulCtxReceived,
+    Recipient,
+};
+use vsmtp_protocol::NotifyOn;
+use vsmtp_rule_engine::rhai;
+
+/// Key of the `internal` variables of the context holding the delay before the
+/// first delivery attempt, in milliseconds. The delay of a single route is stored
+/// under `delivery_delay.<route>`.
+const DELIVERY_DELAY: &str = "delivery_delay";
+
+fn delivery_delay_key(route: Option<&DeliveryRoute>) -> String {
+    route.map_or_else(
+        || DELIVERY_DELAY.to_string(),
+        |route| format!("{DELIVERY_DELAY}.{route}"),
+    )
+}
+
+/// Delay the first delivery attempt of the message, or only of the recipients of `route`.
+pub(crate) fn set_delivery_delay<T>(
+    ctx: &mut Ctx<T>,
+    route: Option<&DeliveryRoute>,
+    delay: std::time::Duration,
+) {
+    ctx.internal.insert(
+        delivery_delay_key(route),
+        rhai::INT::try_from(delay.as_millis())
+            .unwrap_or(rhai::INT::MAX)
+            .into(),
+    );
+}
+
+/// The delay set for `route`, or for the whole message, if any.
+fn delivery_delay(
+    internal: &std::collections::HashMap<String, rhai::Dynamic>,
+    route: &DeliveryRoute,
+) -> Option<std::time::Duration> {
+    internal
+        .get(&delivery_delay_key(Some(route)))
+        .or_else(|| internal.get(DELIVERY_DELAY))
+        .and_then(|delay| delay.as_int().ok())
+        .and_then(|delay| u64::try_from(delay).ok())
+        .map(std::time::Duration::from_millis)
+        .filter(|delay| !delay.is_zero())
+}
+
+/// Key of the `internal` variables of the context holding the priority class set by the rules.
+const PRIORITY: &str = "priority";
+
+/// Set the priority class of the message, taking precedence over the priority header.
+pub(crate) fn set_delivery_priority<T>(ctx: &mut Ctx<T>, priority: Priority) {
+    ctx.internal
+        .insert(PRIORITY.to_string(), priority.as_ref().to_string().into());
+}
+
+/// The class set by the rules, or else read from the `header` of the message if configured.
+fn priority(
+    internal: &std::collections::HashMap<String, rhai::Dynamic>,
+    mail: &vsmtp_mail_parser::Mail,
+    header: Option<&str>,
+) -> Priority {
+    let value = internal
+        .get(PRIORITY)
+        .and_then(|priority| priority.clone().into_string().ok())
+        .or_else(|| {
+            header
+                .and_then(|header| mail.get_header(header))
+                .map(|header| header.body.trim().to_string())
+        });
+
+    value.map_or_else(Priority::default, |value| {
+        value.parse().unwrap_or_else(|_| {
+            tracing::debug!(
+                priority = %value,
+                "Unknown priority, using the default class"
+            );
+            Priority::default()
+        })
+    })
+}
+
+/// Split the recipients of a route by the transport mapped to their domain.
+fn by_transport(
+    transports: &DomainMap<String>,
+    recipients: Vec<Recipient>,
+) -> Vec<(Option<String>, Vec<Recipient>)> {
+    let mut out = Vec::<(Option<String>, Vec<Recipient>)>::new();
+    for rcpt in recipients {
+        let transport = transports
+            .get(&rcpt.forward_path.domain().to_string())
+            .cloned();
+        match out.iter_mut().find(|(name, _)| *name == transport) {
+            Some((_, rcpt_to)) => rcpt_to.push(rcpt),
+            None => out.push((transport, vec![rcpt])),
+        }
+    }
+    out
+}
+
+/// Apply the `fallback` to a delivery whose route has no delivery service bound to it.
+///
+/// The message ends in the [`Queue::NoRoute`] queue if the catch-all route has
+/// no delivery service either.
+async fn on_no_route(
+    backend: &dyn QueueBackend,
+    fallback: &NoRouteFallback,
+    priority: Priority,
+    mut ctx: Ctx<CtxDelivery>,
+) {
+    tracing::warn!(
+        route = %ctx.metadata.routing_key,
+        ?fallback,
+        "No delivery service bound to the route"
+    );
+
+    match fallback {
+        NoRouteFallback::Quarantine => {}
+        NoRouteFallback::Bounce => {
+            let reason = format!(
+                "no delivery service for the route {}",
+                ctx.metadata.routing_key
+            );
+            ctx.metadata
+                .rcpt_to
+                .retain(|rcpt| matches!(rcpt.notify_on, NotifyOn::Some { failure: true, .. }));
+            ctx.metadata.last_deliveries = ctx
+                .metadata
+                .rcpt_to
+                .iter()
+                .map(|rcpt| {
+                    DeliveryAttempt::new_local(
+                        rcpt.forward_path.clone(),
+                        LocalInformation::OtherError(reason.clone()),
+                        ShouldNotify::Failure,
+                    )
+                })
+                .collect();
+
+            if ctx.metadata.rcpt_to.is_empty() {
+                tracing::debug!("No recipient requested a failure notification, dropping it");
+            } else {
+                tracing::info!(queue = Queue::DSN.as_ref(), "Bouncing to the sender");
+                backend.write_to_report_dsn(ctx.to_json().unwrap()).await;
+            }
+            return;
+        }
+        NoRouteFallback::CatchAll { route } if *route != ctx.metadata.routing_key => {
+            let original = std::mem::replace(&mut ctx.metadata.routing_key, route.clone());
+            let routing_key = priority.routing_key(&route.to_string());
+
+            tracing::info!(queue = routing_key, "Sending to the catch-all delivery");
+            if backend
+                .write_to_delivery(&routing_key, ctx.to_json().unwrap())
+                .await
+            {
+                return;
+            }
+            tracing::warn!(%route, "No delivery service bound to the catch-all route");
+            ctx.metadata.routing_key = original;
+        }
+        NoRouteFallback::CatchAll { .. } => {}
+    }
+
+    tracing::info!(queue = Queue::NoRoute.as_ref(), "Sending to quarantine");
+    backend.write_to_no_route(ctx.to_json().unwrap()).await;
+}
+
+/// Name of the quarantine of the messages without recipient, see [`NoRecipientFallback`].
+const NO_RECIPIENT_QUARANTINE: &str = "no-recipient";
+
+/// Apply the `fallback` to a message whose recipients have all been removed by the
+/// post-queue rules, `received` being the recipients before the rules were run.
+pub async fn on_no_recipient(
+    backend: &dyn QueueBackend,
+    fallback: NoRecipientFallback,
+    received: Vec<Recipient>,
+    ctx: Ctx<StatefulCtxReceived>,
+) {
+    tracing::warn!(
+        ?fallback,
+        received = received.len(),
+        "All the recipients have been removed by the rules"
+    );
+
+    match fallback {
+        NoRecipientFallback::Drop => {
+            tracing::info!("Dropping the message");
+        }
+        NoRecipientFallback::Bounce => {
+            let Ctx {
+                variables,
+                internal: _,
+                metadata:
+                    StatefulCtxReceived::Complete(CtxReceived {
+                        mail_from, mail, ..
+                    }),
+            } = ctx
+            else {
+                unreachable!("the working service always use a complete email")
+            };
+
+            let rcpt_to = received
+                .into_iter()
+                .filter(|rcpt| matches!(rcpt.notify_on, NotifyOn::Some { failure: true, .. }))
+                .collect::<Vec<_>>();
+            if rcpt_to.is_empty() {
+                tracing::debug!("No recipient requested a failure notification, dropping it");
+                return;
+            }
+
+            let mut ctx_delivery = CtxDelivery::new(DeliveryRoute::Basic, mail_from, rcpt_to, mail);
+            ctx_delivery.last_deliveries = ctx_delivery
+                .rcpt_to
+                .iter()
+                .map(|rcpt| {
+                    DeliveryAttempt::new_local(
+                        rcpt.forward_path.clone(),
+                        LocalInformation::OtherError("recipient removed by the rules".to_string()),
+                        ShouldNotify::Failure,
+                    )
+                })
+                .collect();
+
+            tracing::info!(queue = Queue::DSN.as_ref(), "Bouncing to the sender");
+            let ctx = Ctx::<CtxDelivery> {
+                variables,
+                internal: std::collections::HashMap::default(),
+                metadata: ctx_delivery,
+            };
+            backend.write_to_report_dsn(ctx.to_json().unwrap()).await;
+        }
+        NoRecipientFallback::Quarantine => {
+            tracing::info!(queue = NO_RECIPIENT_QUARANTINE, "Sending to quarantine");
+            backend
+                .write_to_quarantine(NO_RECIPIENT_QUARANTINE, ctx.to_json().unwrap())
+                .await;
+        }
+    }
+}
+
+/// Hand the message over once the post-queue rules have been run: one delivery
+/// per route and transport of the recipients, or the quarantine named by the rules.
+///
+/// The routes with a delivery delay are published to the delayed exchange, and
+/// are delivered once the delay has expired. The high priority messages are
+/// published to the priority queues of the delivery services, after their delay
+/// for the delayed ones. The deliveries to a route without delivery service are
+/// handed to the `no_route` fallback.
+///
+/// The route of the delayed deliveries is not checked: the delayed exchange does not
+/// report the messages it cannot route, which are dropped once their delay has expired.
+pub async fn dispatch(
+    backend: &dyn QueueBackend,
+    transports: &DomainMap<String>,
+    priority_header: Option<&str>,
+    no_route: &NoRouteFallback,
+    status: WorkingStatus,
+    ctx: Ctx<StatefulCtxReceived>,
+) {
+    match status {
+        WorkingStatus::Next | WorkingStatus::Success => {
+            let Ctx {
+                variables,
+                internal,
+                metadata:
+                    StatefulCtxReceived::Complete(CtxReceived {
+                        connect: _,
+                        helo: _,
+                        mail_from,
+                        rcpt_to,
+                        mail,
+                        complete: _,
+                    }),
+            } = ctx
+            else {
+                unreachable!("the working service always use a complete email")
+            };
+
+            let priority = priority(&internal, &mail.read().unwrap(), priority_header);
+
+            let deliveries = rcpt_to
+                .recipient
+                .into_iter()
+                .filter(|(_, v)| !v.is_empty())
+                .flat_map(|(route, recipient)| {
+                    by_transport(transports, recipient)
+                        .into_iter()
+                        .map(move |(transport, recipient)| (route.clone(), transport, recipient))
+                })
+                .map(|(route, transport, recipient)| CtxDelivery {
+                    transport,
+                    ..CtxDelivery::new(route, mail_from.clone(), recipient, mail.clone())
+                })
+                .collect::<Vec<_>>();
+
+            for ctx_delivery in deliveries {
+                let delay = delivery_delay(&internal, &ctx_delivery.routing_key);
+                let ctx_processed = Ctx::<CtxDelivery> {
+                    variables: variables.clone(),
+                    internal: internal
+                        .iter()
+                        .filter(|(key, _)| !key.starts_with(DELIVERY_DELAY) && *key != PRIORITY)
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect(),
+                    metadata: ctx_delivery,
+                };
+                let payload = ctx_processed.to_json().unwrap();
+                let routing_key =
+                    priority.routing_key(&ctx_processed.metadata.routing_key.to_string());
+
+                if let Some(delay) = delay {
+                    tracing::info!(
+                        queue = routing_key,
+                        ?delay,
+                        "Sending to delivery with a delay"
+                    );
+                    backend
+                        .write_to_deferred(&routing_key, delay, payload)
+                        .await;
+                } else {
+                    tracing::info!(queue = routing_key, "Sending to delivery");
+                    if !backend.write_to_delivery(&routing_key, payload).await {
+                        on_no_route(backend, no_route, priority, ctx_processed).await;
+                    }
+                }
+            }
+        }
+        WorkingStatus::Quarantine(name) => {
+            tracing::trace!(queue = name, "Sending to quarantine");
+
+            let payload = ctx.to_json().unwrap();
+            backend.write_to_quarantine(&name, payload).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dispatch, on_no_recipient, set_delivery_delay, set_delivery_priority};
+    use crate::{
+        config::{NoRecipientFallback, NoRouteFallback},
+        rules::status::WorkingStatus,
+    };
+    use futures_lite::StreamExt;
+    use vsmtp_common::{
+        broker::{in_memory::InMemory, Priority, QueueBackend},
+        ctx::Ctx,
+        ctx_delivery::CtxDelivery,
+        ctx_received::CtxReceived,
+        delivery_attempt::{Action, ShouldNotify},
+        delivery_route::DeliveryRoute,
+        domain_map::DomainMap,
+        stateful_ctx_received::StatefulCtxReceived,
+        Mailbox, Recipient,
+    };
+    use vsmtp_protocol::NotifyOn;
+
+    fn recipient(addr: &str) -> Recipient {
+        Recipient {
+            forward_path: Mailbox(addr.parse().unwrap()),
+            original_forward_path: None,
+            notify_on: NotifyOn::Never,
+        }
+    }
+
+    fn received() -> Ctx<StatefulCtxReceived> {
+        let mut metadata = CtxReceived::fake();
+        metadata.rcpt_to.recipient = [
+            (DeliveryRoute::Basic, vec![recipient("jenny@example.com")]),
+            (
+                DeliveryRoute::Maildir,
+                vec![recipient("a@localhost"), recipient("b@localhost")],
+            ),
+            (DeliveryRoute::Pipe, vec![]),
+        ]
+        .into_iter()
+        .collect();
+
+        Ctx {
+            variables: std::collections::HashMap::default(),
+            internal: std::collections::HashMap::default(),
+            metadata: StatefulCtxReceived::Complete(metadata),
+        }
+    }
+
+    async fn consume_all(backend: &InMemory, queue: &str) -> Vec<Ctx<CtxDelivery>> {
+        let mut consumer = backend.consume(queue).await.unwrap();
+        let mut out = vec![];
+        while let Some(message) = consumer.next().await {
+            let message = message.unwrap();
+            message.ack().await.unwrap();
+            out.push(Ctx::<CtxDelivery>::from_json(&message.data).unwrap());
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn working_to_delivery() {
+        let backend = InMemory::default();
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            received(),
+        )
+        .await;
+
+        let basic = consume_all(&backend, "delivery-basic").await;
+        assert_eq!(basic.len(), 1);
+        assert_eq!(basic[0].metadata.routing_key, DeliveryRoute::Basic);
+        assert_eq!(
+            basic[0].metadata.rcpt_to,
+            vec![recipient("jenny@example.com")]
+        );
+
+        let maildir = consume_all(&backend, "delivery-maildir").await;
+        assert_eq!(maildir.len(), 1);
+        assert_eq!(
+            maildir[0].metadata.rcpt_to,
+            vec![recipient("a@localhost"), recipient("b@localhost")]
+        );
+
+        // routes without recipients are not handed over.
+        assert!(consume_all(&backend, "delivery-pipe").await.is_empty());
+        assert!(backend.is_empty());
+    }
+
+    #[tokio::test]
+    async fn working_to_quarantine() {
+        let backend = InMemory::default();
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Quarantine("spam".to_string()),
+            received(),
+        )
+        .await;
+
+        let mut consumer = backend.consume("rule.spam").await.unwrap();
+        let message = consumer.next().await.unwrap().unwrap();
+        assert!(matches!(
+            Ctx::<StatefulCtxReceived>::from_json(&message.data)
+                .unwrap()
+                .metadata,
+            StatefulCtxReceived::Complete(_)
+        ));
+        assert!(consumer.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn working_to_delayed_delivery() {
+        let mut ctx = received();
+        set_delivery_delay(&mut ctx, None, std::time::Duration::from_secs(2 * 60 * 60));
+
+        let backend = InMemory::default();
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            ctx,
+        )
+        .await;
+
+        let mut delays = backend.delays();
+        delays.sort();
+        assert_eq!(
+            delays,
+            vec![
+                (
+                    "basic".to_string(),
+                    std::time::Duration::from_secs(2 * 60 * 60)
+                ),
+                (
+                    "maildir".to_string(),
+                    std::time::Duration::from_secs(2 * 60 * 60)
+                ),
+            ]
+        );
+
+        let basic = consume_all(&backend, "deferred-basic").await;
+        assert_eq!(basic.len(), 1);
+        assert!(basic[0].internal.is_empty());
+        assert_eq!(consume_all(&backend, "deferred-maildir").await.len(), 1);
+        assert!(backend.is_empty());
+    }
+
+    #[tokio::test]
+    async fn working_to_delayed_route() {
+        let mut ctx = received();
+        set_delivery_delay(
+            &mut ctx,
+            Some(&DeliveryRoute::Maildir),
+            std::time::Duration::from_secs(30),
+        );
+
+        let backend = InMemory::default();
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            ctx,
+        )
+        .await;
+
+        assert_eq!(
+            backend.delays(),
+            vec![("maildir".to_string(), std::time::Duration::from_secs(30))]
+        );
+        assert_eq!(consume_all(&backend, "delivery-basic").await.len(), 1);
+        assert_eq!(consume_all(&backend, "deferred-maildir").await.len(), 1);
+        assert!(backend.is_empty());
+    }
+
+    #[tokio::test]
+    async fn working_to_transports() {
+        let mut ctx = received();
+        let StatefulCtxReceived::Complete(metadata) = &mut ctx.metadata else {
+            unreachable!()
+        };
+        metadata.rcpt_to.recipient.insert(
+            DeliveryRoute::Basic,
+            vec![
+                recipient("jenny@partner.com"),
+                recipient("john@mail.partner.com"),
+                recipient("jenny@example.com"),
+                recipient("john@example.org"),
+            ],
+        );
+
+        let transports = [
+            ("partner.com", "partners"),
+            ("*.partner.com", "partners"),
+            ("*", "bulk"),
+        ]
+        .into_iter()
+        .map(|(pattern, transport)| (pattern.to_string(), transport.to_string()))
+        .collect::<DomainMap<_>>();
+
+        let backend = InMemory::default();
+        dispatch(
+            &backend,
+            &transports,
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            ctx,
+        )
+        .await;
+
+        let mut basic = consume_all(&backend, "delivery-basic")
+            .await
+            .into_iter()
+            .map(|ctx| (ctx.metadata.transport, ctx.metadata.rcpt_to))
+            .collect::<Vec<_>>();
+        basic.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            basic,
+            vec![
+                (
+                    Some("bulk".to_string()),
+                    vec![
+                        recipient("jenny@example.com"),
+                        recipient("john@example.org")
+                    ]
+                ),
+                (
+                    Some("partners".to_string()),
+                    vec![
+                        recipient("jenny@partner.com"),
+                        recipient("john@mail.partner.com")
+                    ]
+                ),
+            ]
+        );
+
+        // the catch-all transport also applies to the local recipients.
+        let maildir = consume_all(&backend, "delivery-maildir").await;
+        assert_eq!(maildir.len(), 1);
+        assert_eq!(maildir[0].metadata.transport, Some("bulk".to_string()));
+    }
+
+    fn with_header(header: &str) -> Ctx<StatefulCtxReceived> {
+        let mut ctx = received();
+        let StatefulCtxReceived::Complete(metadata) = &mut ctx.metadata else {
+            unreachable!()
+        };
+        metadata.mail = std::sync::Arc::new(std::sync::RwLock::new(
+            vsmtp_mail_parser::Mail::try_from(
+                format!("{header}\r\nFrom: john.doe@example.com\r\nDate: Tue, 1 Jan 2030 00:00:00 +0000\r\nSubject: priority\r\n\r\nHello world!\r\n").as_str(),
+            )
+            .unwrap(),
+        ));
+        ctx
+    }
+
+    #[tokio::test]
+    async fn working_to_priority_delivery() {
+        let mut high = received();
+        set_delivery_priority(&mut high, Priority::High);
+
+        let backend = InMemory::default();
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            high,
+        )
+        .await;
+
+        let basic = consume_all(&backend, "delivery-basic.priority").await;
+        assert_eq!(basic.len(), 1);
+        assert_eq!(basic[0].metadata.routing_key, DeliveryRoute::Basic);
+        assert!(basic[0].internal.is_empty());
+        assert_eq!(
+            consume_all(&backend, "delivery-maildir.priority")
+                .await
+                .len(),
+            1
+        );
+        assert!(backend.is_empty());
+
+        // a bulk message is published to the standard queues.
+        let mut bulk = received();
+        set_delivery_priority(&mut bulk, Priority::Normal);
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            bulk,
+        )
+        .await;
+
+        assert_eq!(consume_all(&backend, "delivery-basic").await.len(), 1);
+        assert_eq!(consume_all(&backend, "delivery-maildir").await.len(), 1);
+        assert!(backend.is_empty());
+    }
+
+    #[tokio::test]
+    async fn working_to_delayed_priority_delivery() {
+        let mut ctx = received();
+        set_delivery_priority(&mut ctx, Priority::High);
+        set_delivery_delay(
+            &mut ctx,
+            Some(&DeliveryRoute::Maildir),
+            std::time::Duration::from_secs(30),
+        );
+
+        let backend = InMemory::default();
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            ctx,
+        )
+        .await;
+
+        assert_eq!(
+            backend.delays(),
+            vec![(
+                "maildir.priority".to_string(),
+                std::time::Duration::from_secs(30)
+            )]
+        );
+        let maildir = consume_all(&backend, "delivery-maildir.priority").await;
+        assert_eq!(maildir.len(), 1);
+        assert!(maildir[0].internal.is_empty());
+        assert_eq!(
+            consume_all(&backend, "delivery-basic.priority").await.len(),
+            1
+        );
+        assert!(backend.is_empty());
+    }
+
+    #[tokio::test]
+    async fn priority_from_header() {
+        let backend = InMemory::default();
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            Some("Priority"),
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            with_header("Priority: urgent"),
+        )
+        .await;
+        assert_eq!(
+            consume_all(&backend, "delivery-basic.priority").await.len(),
+            1
+        );
+
+        // the header is ignored if not configured.
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            with_header("Priority: urgent"),
+        )
+        .await;
+        assert_eq!(consume_all(&backend, "delivery-basic").await.len(), 1);
+
+        // the class set by the rules takes precedence over the header.
+        let mut bulk = with_header("Priority: urgent");
+        set_delivery_priority(&mut bulk, Priority::Normal);
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            Some("Priority"),
+            &NoRouteFallback::default(),
+            WorkingStatus::Next,
+            bulk,
+        )
+        .await;
+        assert_eq!(consume_all(&backend, "delivery-basic").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn no_route_to_quarantine() {
+        let backend = InMemory::default().without_service(&["basic"]);
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::Quarantine,
+            WorkingStatus::Next,
+            received(),
+        )
+        .await;
+
+        let no_route = consume_all(&backend, "no-route").await;
+        assert_eq!(no_route.len(), 1);
+        assert_eq!(no_route[0].metadata.routing_key, DeliveryRoute::Basic);
+        assert_eq!(
+            no_route[0].metadata.rcpt_to,
+            vec![recipient("jenny@example.com")]
+        );
+
+        // the routes with a delivery service are not affected.
+        assert_eq!(consume_all(&backend, "delivery-maildir").await.len(), 1);
+        assert!(backend.is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_route_to_bounce() {
+        let notified = Recipient {
+            notify_on: NotifyOn::Some {
+                success: false,
+                failure: true,
+                delay: false,
+            },
+            ..recipient("jenny@example.com")
+        };
+        let mut ctx = received();
+        let StatefulCtxReceived::Complete(metadata) = &mut ctx.metadata else {
+            unreachable!()
+        };
+        metadata.rcpt_to.recipient.insert(
+            DeliveryRoute::Basic,
+            vec![notified.clone(), recipient("john@example.com")],
+        );
+
+        let backend = InMemory::default().without_service(&["basic"]);
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::Bounce,
+            WorkingStatus::Next,
+            ctx,
+        )
+        .await;
+
+        // only the recipients requesting a failure notification are reported.
+        let dsn = consume_all(&backend, "dsn").await;
+        assert_eq!(dsn.len(), 1);
+        assert_eq!(dsn[0].metadata.rcpt_to, vec![notified.clone()]);
+        let [attempt] = dsn[0].metadata.last_deliveries.as_slice() else {
+            panic!("one failed attempt per reported recipient")
+        };
+        assert!(matches!(
+            attempt.get_action(attempt.get_rcpt_index(&notified).unwrap()),
+            Action::Failed { .. }
+        ));
+        assert!(attempt.should_notify_on(ShouldNotify::Failure));
+
+        assert_eq!(consume_all(&backend, "delivery-maildir").await.len(), 1);
+        assert!(backend.is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_route_to_catch_all() {
+        let catch_all = NoRouteFallback::CatchAll {
+            route: "forward.relay".parse().unwrap(),
+        };
+
+        let backend = InMemory::default().without_service(&["basic"]);
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &catch_all,
+            WorkingStatus::Next,
+            received(),
+        )
+        .await;
+
+        let relay = consume_all(&backend, "delivery-forward.relay").await;
+        assert_eq!(relay.len(), 1);
+        assert_eq!(
+            relay[0].metadata.routing_key,
+            DeliveryRoute::Forward {
+                service: "relay".to_string()
+            }
+        );
+        assert_eq!(
+            relay[0].metadata.rcpt_to,
+            vec![recipient("jenny@example.com")]
+        );
+        assert_eq!(consume_all(&backend, "delivery-maildir").await.len(), 1);
+        assert!(backend.is_empty());
+
+        // quarantined if the catch-all route has no delivery service either.
+        let backend = InMemory::default().without_service(&["basic", "forward.relay"]);
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &catch_all,
+            WorkingStatus::Next,
+            received(),
+        )
+        .await;
+
+        let no_route = consume_all(&backend, "no-route").await;
+        assert_eq!(no_route.len(), 1);
+        assert_eq!(no_route[0].metadata.routing_key, DeliveryRoute::Basic);
+    }
+
+    #[tokio::test]
+    async fn no_route_delayed() {
+        let mut ctx = received();
+        set_delivery_delay(
+            &mut ctx,
+            Some(&DeliveryRoute::Basic),
+            std::time::Duration::from_secs(30),
+        );
+
+        let backend = InMemory::default().without_service(&["basic"]);
+        dispatch(
+            &backend,
+            &DomainMap::default(),
+            None,
+            &NoRouteFallback::Quarantine,
+            WorkingStatus::Next,
+            ctx,
+        )
+        .await;
+
+        // the fallback is not applied to the delayed deliveries.
+        assert_eq!(consume_all(&backend, "deferred-basic").await.len(), 1);
+        assert_eq!(consume_all(&backend, "delivery-maildir").await.len(), 1);
+        assert!(backend.is_empty());
+    }
+
+    /// The log events written while the guard returned by [`Logs::capture`] is alive.
+    #[derive(Clone, Default)]
+    struct Logs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Logs {
+        fn capture(&self) -> tracing::subscriber::DefaultGuard {
+            let logs = self.clone();
+            tracing::subscriber::set_default(
+                tracing_subscriber::fmt()
+                    .with_ansi(false)
+                    .with_writer(move || logs.clone())
+                    .finish(),
+            )
+        }
+
+        fn contains(&self, message: &str) -> bool {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).contains(message)
+        }
+    }
+
+    /// A message whose recipients have all been removed, with the recipients received.
+    fn without_recipient() -> (Vec<Recipient>, Ctx<StatefulCtxReceived>) {
+        let mut ctx = received();
+        let StatefulCtxReceived::Complete(metadata) = &mut ctx.metadata else {
+            unreachable!()
+        };
+        let received = metadata
+            .rcpt_to
+            .recipient
+            .drain()
+            .flat_map(|(_, recipients)| recipients)
+            .collect();
+        (received, ctx)
+    }
+
+    #[tokio::test]
+    async fn no_recipient_dropped() {
+        let logs = Logs::default();
+        let _guard = logs.capture();
+
+        let backend = InMemory::default();
+        let (received, ctx) = without_recipient();
+        on_no_recipient(&backend, NoRecipientFallback::Drop, received, ctx).await;
+
+        assert!(backend.is_empty());
+        assert!(logs.contains("All the recipients have been removed by the rules"));
+        assert!(logs.contains("Dropping the message"));
+    }
+
+    #[tokio::test]
+    async fn no_recipient_quarantined() {
+        let logs = Logs::default();
+        let _guard = logs.capture();
+
+        let backend = InMemory::default();
+        let (received, ctx) = without_recipient();
+        on_no_recipient(&backend, NoRecipientFallback::Quarantine, received, ctx).await;
+
+        let mut consumer = backend.consume("rule.no-recipient").await.unwrap();
+        let message = consumer.next().await.unwrap().unwrap();
+        let ctx = Ctx::<StatefulCtxReceived>::from_json(&message.data).unwrap();
+        assert!(ctx
+            .metadata
+            .get_rcpt_to()
+            .unwrap()
+            .recipient_values()
+            .next()
+            .is_none());
+        assert!(backend.is_empty());
+        assert!(logs.contains("All the recipients have been removed by the rules"));
+    }
+
+    #[tokio::test]
+    async fn no_recipient_bounced() {
+        let notified = Recipient {
+            notify_on: NotifyOn::Some {
+                success: false,
+                failure: true,
+                delay: false,
+            },
+            ..recipient("jenny@example.com")
+        };
+
+        let backend = InMemory::default();
+        let (_, ctx) = without_recipient();
+        on_no_recipient(
+            &backend,
+            NoRecipientFallback::Bounce,
+            vec![notified.clone(), recipient("john@example.com")],
+            ctx,
+        )
+        .await;
+
+        // only the recipients requesting a failure notification are reported.
+        let dsn = consume_all(&backend, "dsn").await;
+        assert_eq!(dsn.len(), 1);
+        assert_eq!(dsn[0].metadata.rcpt_to, vec![notified.clone()]);
+        let [attempt] = dsn[0].metadata.last_deliveries.as_slice() else {
+            panic!("one failed attempt per reported recipient")
+        };
+        assert!(matches!(
+            attempt.get_action(attempt.get_rcpt_index(&notified).unwrap()),
+            Action::Failed { .. }
+        ));
+        assert!(backend.is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+/*
+ * vSMTP mail transfer agent
+ *
+ * Copyright (C) 2003 - viridIT SAS
+ * Licensed under the Elastic License 2.0
+ *
+ * You should have received a copy of the Elastic License 2.0 along with
+ * this program. If not, see https://www.elastic.co/licensing/elastic-license.
+ *
+ */
+
+use vsmtp_common::uuid::Uuid;
+
+/// Uuids of the messages handled by this worker, kept for `ttl` to skip the messages
+/// the broker redelivers after they have been handled (the acknowledgement was lost, ...).
+#[derive(Debug)]
+pub struct ProcessedMessages {
+    ttl: std::time::Duration,
+    processed: std::sync::Mutex<std::collections::HashMap<Uuid, std::time::Instant>>,
+}
+
+impl ProcessedMessages {
+    /// Create an empty store, the uuids being forgotten `ttl` after their processing.
+    #[must_use]
+    pub fn new(ttl: std::time::Duration) -> Self {
+        Self {
+            ttl,
+            processed: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Has the message `uuid` been handled less than `ttl` ago ?
+    #[must_use]
+    pub fn contains(&self, uuid: &Uuid) -> bool {
+        let now = std::time::Instant::now();
+        let mut processed = self.processed.lock().expect("processed messages poisoned");
+        processed.retain(|_, at| now.duration_since(*at) < self.ttl);
+
+        processed.contains_key(uuid)
+    }
+
+    /// Record the message `uuid` once it has been handled.
+    pub fn insert(&self, uuid: Uuid) {
+        self.processed
+            .lock()
+            .expect("processed messages poisoned")
+            .insert(uuid, std::time::Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProcessedMessages;
+    use vsmtp_common::uuid::Uuid;
+
+    #[test]
+    fn recorded_once_handled() {
+        let processed = ProcessedMessages::new(std::time::Duration::from_secs(600));
+        let uuid = Uuid::new_v4();
+
+        assert!(!processed.contains(&uuid));
+        processed.insert(uuid);
+        assert!(processed.contains(&uuid));
+
+        // other messages are still processed.
+        assert!(!processed.contains(&Uuid::new_v4()));
+    }
+
+    #[test]
+    fn forgotten_after_ttl() {
+        let processed = ProcessedMessages::new(std::time::Duration::ZERO);
+        let uuid = Uuid::new_v4();
+
+        processed.insert(uuid);
+        assert!(!processed.contains(&uuid));
+    }
+}
@@ -10,4 +10,8 @@
  */
 
 pub mod config;
+mod dispatch;
+pub mod idempotency;
 pub mod rules;
+
+pub use dispatch::{dispatch, on_no_recipient};
@@ -11,25 +11,51 @@
 
 use futures_lite::stream::StreamExt;
 use rules::{stage::WorkingStage, status::WorkingStatus};
+use tracing::Instrument;
 use vsmtp_common::{
-    api::{write_to_delivery, write_to_quarantine},
-    broker::{Exchange, Queue},
+    broker::{Consumer, Exchange, Message, Queue, QueueBackend},
     ctx::Ctx,
-    ctx_delivery::CtxDelivery,
-    ctx_received::CtxReceived,
+    domain_map::DomainMap,
     stateful_ctx_received::StatefulCtxReceived,
+    telemetry::message_span,
+    Recipient,
 };
 use vsmtp_config::Config;
 use vsmtp_rule_engine::{
-    api::{server_auth, utils_modules},
+    api::{server_auth_with_dkim_keys, utils_modules},
     rhai, RuleEngine, RuleEngineConfig, RuleEngineConfigBuilder,
 };
 use vsmtp_working::{
     config::{self, cli::Args},
-    rules,
+    dispatch,
+    idempotency::ProcessedMessages,
+    on_no_recipient, rules,
 };
 
-async fn init(channel: &lapin::Channel) -> lapin::Result<lapin::Consumer> {
+/// The recipients of the message, on all the routes.
+fn recipients(ctx: &Ctx<StatefulCtxReceived>) -> Vec<Recipient> {
+    ctx.metadata
+        .get_rcpt_to()
+        .map(|rcpt_to| rcpt_to.recipient_values().cloned().collect())
+        .unwrap_or_default()
+}
+
+async fn init(channel: &lapin::Channel) -> Result<Consumer, Box<dyn std::error::Error>> {
+    channel
+        .exchange_declare(
+            Exchange::DelayedDeferred.as_ref(),
+            lapin::ExchangeKind::Custom("x-delayed-message".to_string()),
+            lapin::options::ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            lapin::types::FieldTable::from(
+                std::iter::once(("x-delayed-type".into(), lapin::types::LongString::from("topic").into()))
+                    .collect::<std::collections::BTreeMap<lapin::types::ShortString, lapin::types::AMQPValue>>(),
+            ),
+        )
+        .await?;
+
     let _to_working = channel
         .queue_declare(
             Queue::ToWorking.as_ref(),
@@ -86,16 +112,19 @@ async fn init(channel: &lapin::Channel) -> lapin::Result<lapin::Consumer> {
         )
         .await?;
 
-    let consumer = channel
-        .basic_consume(
-            Queue::ToWorking.as_ref(),
-            "",
-            lapin::options::BasicConsumeOptions::default(),
-            lapin::types::FieldTable::default(),
-        )
-        .await?;
+    Ok(channel.consume(Queue::ToWorking.as_ref()).await?)
+}
 
-    Ok(consumer)
+/// The state shared by the tasks processing the messages.
+struct Shared {
+    rule_engine_config:
+        std::sync::Arc<RuleEngineConfig<Ctx<StatefulCtxReceived>, WorkingStatus, WorkingStage>>,
+    channel: lapin::Channel,
+    transports: DomainMap<String>,
+    priority_header: Option<String>,
+    no_route: config::NoRouteFallback,
+    no_recipient: config::NoRecipientFallback,
+    processed: Option<ProcessedMessages>,
 }
 
 /// Builder to separate initialization from the main function.
@@ -104,17 +133,15 @@ struct Working {
     config: config::WorkingConfig,
     #[allow(dead_code)]
     conn: lapin::Connection,
-    channel: lapin::Channel,
-    from_receiver: lapin::Consumer,
-    rule_engine_config:
-        std::sync::Arc<RuleEngineConfig<Ctx<StatefulCtxReceived>, WorkingStatus, WorkingStage>>,
+    from_receiver: Consumer,
+    shared: std::sync::Arc<Shared>,
 }
 
 impl Working {
     /// Build the configuration, AMQP connections and rule engine for the service.
     async fn build() -> Result<Self, Box<dyn std::error::Error>> {
         let Args { config } = <Args as clap::Parser>::parse();
-        let config = config::WorkingConfig::from_rhai_file(&config).map_err(|error| {
+        let mut config = config::WorkingConfig::from_rhai_file(&config).map_err(|error| {
             eprintln!("Failed to boot Working service: {error}");
             error
         })?;
@@ -125,12 +152,12 @@ impl Working {
         channel
             .confirm_select(lapin::options::ConfirmSelectOptions::default())
             .await?;
-        channel
-            .basic_qos(1, lapin::options::BasicQosOptions::default())
-            .await?;
+        channel.set_prefetch(config.broker().prefetch_count).await?;
 
         let from_receiver = init(&channel).await?;
 
+        // the private keys are not exposed to the scripts with the configuration.
+        let dkim_keys = std::mem::take(&mut config.dkim_keys);
         let rule_engine_config = std::sync::Arc::new(
             RuleEngineConfigBuilder::default()
                 .with_configuration(&config)?
@@ -140,11 +167,18 @@ impl Working {
                 .with_standard_global_modules()
                 .with_smtp_modules()
                 .with_static_modules(
-                    std::iter::once((
-                        "status".to_string(),
-                        rhai::exported_module!(rules::api::status).into(),
-                    ))
-                    .chain(server_auth())
+                    [
+                        (
+                            "status".to_string(),
+                            rhai::exported_module!(rules::api::status).into(),
+                        ),
+                        (
+                            "schedule".to_string(),
+                            rhai::exported_module!(rules::api::schedule).into(),
+                        ),
+                    ]
+                    .into_iter()
+                    .chain(server_auth_with_dkim_keys(dkim_keys))
                     .chain(utils_modules())
                     .chain([
                         vsmtp_rhai_utils::time(),
@@ -153,6 +187,8 @@ impl Working {
                         vsmtp_rhai_utils::crypto(),
                     ]),
                 )
+                .with_missing_script(config.scripts.on_missing)
+                .with_directive_toggles(config.scripts.rules.clone())
                 .with_script_at(
                     &config.scripts.path,
                     "/etc/vsmtp/working/conf.d/config.rhai",
@@ -161,76 +197,78 @@ impl Working {
         );
 
         Ok(Self {
+            shared: std::sync::Arc::new(Shared {
+                rule_engine_config,
+                channel,
+                transports: config.transports.clone(),
+                priority_header: config.priority_header.clone(),
+                no_route: config.no_route.clone(),
+                no_recipient: config.no_recipient,
+                processed: config
+                    .idempotency
+                    .enable
+                    .then(|| ProcessedMessages::new(config.idempotency.ttl)),
+            }),
             config,
             conn,
-            channel,
             from_receiver,
-            rule_engine_config,
         })
     }
+}
 
-    /// Run the service.
+impl Shared {
+    /// Run the service on one message.
     #[tracing::instrument(name = "working_", skip_all)]
-    async fn run(&mut self, ctx: Ctx<StatefulCtxReceived>) {
-        let rule_engine = RuleEngine::from_config_with_state(self.rule_engine_config.clone(), ctx);
-
-        match rule_engine.run(&WorkingStage::PostQueue) {
-            WorkingStatus::Next | WorkingStatus::Success => {
-                let Ctx {
-                    variables,
-                    internal,
-                    metadata:
-                        StatefulCtxReceived::Complete(CtxReceived {
-                            connect: _,
-                            helo: _,
-                            mail_from,
-                            rcpt_to,
-                            mail,
-                            complete: _,
-                        }),
-                } = rule_engine.take_state()
-                else {
-                    unreachable!("the working service always use a complete email")
-                };
+    async fn run(self: std::sync::Arc<Self>, message: Message, ctx: Ctx<StatefulCtxReceived>) {
+        let uuid = ctx
+            .metadata
+            .get_mail_from()
+            .map(|mail_from| mail_from.message_uuid)
+            .ok();
 
-                let deliveries = rcpt_to
-                    .recipient
-                    .into_iter()
-                    .filter(|(_, v)| !v.is_empty())
-                    .map(|(route, recipient)| {
-                        vsmtp_common::ctx_delivery::CtxDelivery::new(
-                            route,
-                            mail_from.clone(),
-                            recipient,
-                            mail.clone(),
-                        )
-                    })
-                    .collect::<Vec<_>>();
+        match (&self.processed, uuid) {
+            (Some(processed), Some(uuid)) if message.redelivered && processed.contains(&uuid) => {
+                tracing::warn!("Message already processed, skipping the redelivery");
+            }
+            _ => {
+                self.handle(ctx).await;
 
-                for ctx_delivery in deliveries {
-                    let ctx_processed = Ctx::<CtxDelivery> {
-                        variables: variables.clone(),
-                        internal: internal.clone(),
-                        metadata: ctx_delivery,
-                    };
-                    let payload = ctx_processed.to_json().unwrap();
-                    tracing::info!(queue = %ctx_processed.metadata.routing_key, "Sending to delivery");
-                    write_to_delivery(
-                        &self.channel,
-                        &ctx_processed.metadata.routing_key.to_string(),
-                        payload,
-                    )
-                    .await;
+                if let (Some(processed), Some(uuid)) = (&self.processed, uuid) {
+                    processed.insert(uuid);
                 }
             }
-            WorkingStatus::Quarantine(name) => {
-                tracing::trace!(queue = name, "Sending to quarantine");
+        }
 
-                let ctx = rule_engine.take_state();
-                let payload = ctx.to_json().unwrap();
-                write_to_quarantine(&self.channel, &name, payload).await;
-            }
+        // the message is redelivered by the broker if the worker stops before this point.
+        if let Err(error) = message.ack().await {
+            tracing::error!(%error, "Failed to acknowledge the message");
+        }
+    }
+
+    /// Run the rules on the message and send it to its next queues.
+    async fn handle(&self, ctx: Ctx<StatefulCtxReceived>) {
+        let received = recipients(&ctx);
+        let rule_engine = RuleEngine::from_config_with_state(self.rule_engine_config.clone(), ctx);
+
+        let status = rule_engine.run(&WorkingStage::PostQueue);
+        let ctx = rule_engine.take_state();
+
+        if matches!(status, WorkingStatus::Next | WorkingStatus::Success)
+            && recipients(&ctx).is_empty()
+        {
+            on_no_recipient(&self.channel, self.no_recipient, received, ctx).await;
+            return;
         }
+
+        dispatch(
+            &self.channel,
+            &self.transports,
+            self.priority_header.as_deref(),
+            &self.no_route,
+            status,
+            ctx,
+        )
+        .await;
     }
 }
 
@@ -246,22 +284,23 @@ async fn main() {
 
     tracing::info!("Working service is starting");
 
-    while let Some(delivery) = working.from_receiver.next().await {
-        let delivery = delivery.expect("error in consumer");
+    while let Some(message) = working.from_receiver.next().await {
+        let message = message.expect("error in consumer");
 
-        let lapin::message::Delivery { data, .. } = &delivery;
-        let ctx = match Ctx::<StatefulCtxReceived>::from_json(data) {
+        let mut ctx = match Ctx::<StatefulCtxReceived>::from_json(&message.data) {
             Ok(ctx) => ctx,
             Err(e) => {
                 todo!("handle invaliding payload {e:?}");
             }
         };
 
-        delivery
-            .ack(lapin::options::BasicAckOptions::default())
-            .await
-            .expect("ack");
-
-        working.run(ctx).await;
+        let span = match ctx.metadata.get_mail_from() {
+            Ok(mail_from) => {
+                let uuid = mail_from.message_uuid;
+                message_span(&mut ctx, &uuid)
+            }
+            Err(_) => tracing::Span::none(),
+        };
+        tokio::spawn(working.shared.clone().run(message, ctx).instrument(span));
     }
 }
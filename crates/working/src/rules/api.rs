@@ -143,3 +143,129 @@ pub mod status {
         format!("{status:?}")
     }
 }
+
+fn parse_delay(delay: &str) -> Result<std::time::Duration, Box<rhai::EvalAltResult>> {
+    humantime::parse_duration(delay)
+        .map_err(|error| format!("invalid delay '{delay}': {error}").into())
+}
+
+/// Schedule the delivery of the message.
+#[rhai::plugin::export_module]
+pub mod schedule {
+    use super::parse_delay;
+    use crate::dispatch::{set_delivery_delay, set_delivery_priority};
+    use vsmtp_common::{broker::Priority, delivery_route::DeliveryRoute};
+    use vsmtp_rule_engine::api::docs::Ctx;
+
+    /// Delay the first delivery attempt of the message, to defer low-priority bulk
+    /// mail for example. The message is held in the delayed exchange of the broker
+    /// before being handed over to the delivery services.
+    ///
+    /// # Args
+    ///
+    /// * `delay` - The delay before the delivery, as a duration string. (e.g. "30m", "2h")
+    ///
+    /// # Errors
+    ///
+    /// * The delay is not a valid duration.
+    ///
+    /// # SMTP stages
+    ///
+    /// ```post_queue```
+    ///
+    /// # Example
+    ///
+    /// ```js title="/etc/vsmtp/working/script.rhai"
+    /// fn on_post_queue(ctx) {
+    ///     if ctx.get_variable("bulk") == true {
+    ///         ctx.delay_delivery("2h");
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:1
+    #[rhai_fn(global, return_raw, pure)]
+    pub fn delay_delivery(ctx: &mut Ctx, delay: &str) -> Result<(), Box<rhai::EvalAltResult>> {
+        let delay = parse_delay(delay)?;
+        ctx.write(|ctx| set_delivery_delay(ctx, None, delay));
+        Ok(())
+    }
+
+    /// Delay the first delivery attempt of the recipients of a route only.
+    /// The delay of a route takes precedence over the delay of the message.
+    ///
+    /// # Args
+    ///
+    /// * `route` - The routing path of the recipients. (see `ctx.set_routing_path`)
+    /// * `delay` - The delay before the delivery, as a duration string. (e.g. "30m", "2h")
+    ///
+    /// # Errors
+    ///
+    /// * The route or the delay is invalid.
+    ///
+    /// # SMTP stages
+    ///
+    /// ```post_queue```
+    ///
+    /// # Example
+    ///
+    /// ```js title="/etc/vsmtp/working/script.rhai"
+    /// fn on_post_queue(ctx) {
+    ///     ctx.delay_delivery("basic", "30m");
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:2
+    #[rhai_fn(global, name = "delay_delivery", return_raw, pure)]
+    pub fn delay_route_delivery(
+        ctx: &mut Ctx,
+        route: &str,
+        delay: &str,
+    ) -> Result<(), Box<rhai::EvalAltResult>> {
+        let route = route
+            .parse::<DeliveryRoute>()
+            .map_err(|error| format!("invalid route '{route}': {error}"))?;
+        let delay = parse_delay(delay)?;
+        ctx.write(|ctx| set_delivery_delay(ctx, Some(&route), delay));
+        Ok(())
+    }
+
+    /// Set the priority class of the message. The high priority messages are delivered
+    /// from their own queues, ahead of the bulk mail. The class set by the rules takes
+    /// precedence over the priority header of the configuration.
+    ///
+    /// # Args
+    ///
+    /// * `priority` - The class of the message, "high" or "normal".
+    ///
+    /// # Errors
+    ///
+    /// * The priority class is invalid.
+    ///
+    /// # SMTP stages
+    ///
+    /// ```post_queue```
+    ///
+    /// # Example
+    ///
+    /// ```js title="/etc/vsmtp/working/script.rhai"
+    /// fn on_post_queue(ctx) {
+    ///     if ctx.get_variable("transactional") == true {
+    ///         ctx.set_priority("high");
+    ///     }
+    ///     status::next()
+    /// }
+    /// ```
+    ///
+    /// # rhai-autodocs:index:3
+    #[rhai_fn(global, return_raw, pure)]
+    pub fn set_priority(ctx: &mut Ctx, priority: &str) -> Result<(), Box<rhai::EvalAltResult>> {
+        let priority = priority
+            .parse::<Priority>()
+            .map_err(|error| format!("invalid priority '{priority}': {error}"))?;
+        ctx.write(|ctx| set_delivery_priority(ctx, priority));
+        Ok(())
+    }
+}
@@ -38,7 +38,7 @@ EOF
 if [ "$#" -ne 0 ]; then
     bins=("$@")
 else
-    bins=(vsmtp-broker all_in_one vsmtp-receiver vsmtp-working vsmtp-log-dispatcher vsmtp-maildir vsmtp-mbox vsmtp-basic vsmtp-forward)
+    bins=(vsmtp-broker all_in_one vsmtp-receiver vsmtp-working vsmtp-log-dispatcher vsmtp-maildir vsmtp-mbox vsmtp-basic vsmtp-forward vsmtp-report)
 fi
 
 for i in "${bins[@]}"; do